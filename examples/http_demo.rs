@@ -0,0 +1,238 @@
+//! Serves the fragmented MP4 segments converted from a TS stream together with a minimal MSE player.
+//!
+//! The input is converted incrementally by `StreamConverter`, and each media segment is served as soon as
+//! it has been made, so the input can also be a live stream (e.g., `-` to read from the standard input).
+//! The packets before the first PAT and PMT are skipped, so the stream can be joined at any point.
+//!
+//! ```console
+//! $ cargo run --example http_demo -- input.ts
+//! $ open http://127.0.0.1:8080/
+//! ```
+extern crate clap;
+extern crate mpeg2ts;
+extern crate mse_fmp4;
+#[macro_use]
+extern crate trackable;
+
+use clap::{App, Arg};
+use mpeg2ts::ts::TsPacketReader;
+use mse_fmp4::io::WriteTo;
+use mse_fmp4::mpeg2_ts::{ConvertOptions, SegmentationPolicy, SkipToPmt, StreamConverter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use trackable::error::Failure;
+
+const PLAYER_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<body>
+<video id="video" controls autoplay muted></video>
+<script>
+const MIME_TYPE = '{{MIME_TYPE}}';
+const video = document.getElementById('video');
+const mediaSource = new MediaSource();
+video.src = URL.createObjectURL(mediaSource);
+mediaSource.addEventListener('sourceopen', async () => {
+  const sourceBuffer = mediaSource.addSourceBuffer(MIME_TYPE);
+  const append = (bytes) => new Promise((resolve) => {
+    sourceBuffer.addEventListener('updateend', resolve, { once: true });
+    sourceBuffer.appendBuffer(bytes);
+  });
+  await append(await (await fetch('/init.mp4')).arrayBuffer());
+  for (let i = 1; ; i++) {
+    const response = await fetch('/' + i + '.m4s');
+    if (!response.ok) {
+      break;
+    }
+    await append(await response.arrayBuffer());
+  }
+  mediaSource.endOfStream();
+});
+</script>
+</body>
+</html>
+"#;
+
+/// Segments made so far by the converter thread.
+#[derive(Default)]
+struct Contents {
+    html: Option<String>,
+    initialization_segment: Option<Vec<u8>>,
+    media_segments: Vec<Vec<u8>>,
+    finished: bool,
+}
+
+/// `Contents` shared between the converter thread and the request handlers.
+///
+/// The condition variable is notified whenever a segment is added or the conversion finishes.
+#[derive(Default)]
+struct SharedContents {
+    contents: Mutex<Contents>,
+    updated: Condvar,
+}
+impl SharedContents {
+    fn update<F: FnOnce(&mut Contents)>(&self, f: F) {
+        f(&mut self.contents.lock().unwrap());
+        self.updated.notify_all();
+    }
+
+    /// Waits until `f` returns `Some(_)` or the conversion finishes.
+    fn wait_for<T, F>(&self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&Contents) -> Option<T>,
+    {
+        let mut contents = self.contents.lock().unwrap();
+        loop {
+            if let Some(x) = f(&contents) {
+                return Some(x);
+            }
+            if contents.finished {
+                return None;
+            }
+            contents = self.updated.wait(contents).unwrap();
+        }
+    }
+}
+
+fn main() {
+    let matches = App::new("http_demo")
+        .arg(Arg::with_name("INPUT_FILE").index(1).required(true))
+        .arg(
+            Arg::with_name("PORT")
+                .long("port")
+                .takes_value(true)
+                .default_value("8080"),
+        )
+        .arg(
+            Arg::with_name("SEGMENT_DURATION")
+                .long("segment-duration")
+                .takes_value(true)
+                .default_value("2"),
+        )
+        .get_matches();
+    let input_file = matches.value_of("INPUT_FILE").unwrap();
+    let port: u16 = track_try_unwrap!(matches
+        .value_of("PORT")
+        .unwrap()
+        .parse()
+        .map_err(Failure::from_error));
+    let segment_duration: u64 = track_try_unwrap!(matches
+        .value_of("SEGMENT_DURATION")
+        .unwrap()
+        .parse()
+        .map_err(Failure::from_error));
+
+    let input: Box<dyn Read + Send> = if input_file == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(track_try_unwrap!(
+            File::open(input_file).map_err(Failure::from_error)
+        ))
+    };
+    let options = ConvertOptions {
+        segmentation: SegmentationPolicy {
+            target_duration: Duration::from_secs(segment_duration),
+            ..SegmentationPolicy::default()
+        },
+        ..ConvertOptions::default()
+    };
+    let shared = Arc::new(SharedContents::default());
+    let converter_shared = Arc::clone(&shared);
+    thread::spawn(move || {
+        if let Err(e) = track!(convert(BufReader::new(input), options, &converter_shared)) {
+            eprintln!("[WARN] {}", e);
+        }
+        converter_shared.update(|contents| contents.finished = true);
+    });
+
+    let listener =
+        track_try_unwrap!(TcpListener::bind(("127.0.0.1", port)).map_err(Failure::from_error));
+    println!("# Listening on http://127.0.0.1:{}/", port);
+    for stream in listener.incoming() {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let result = stream
+                .map_err(Failure::from_error)
+                .and_then(|stream| track!(handle_request(stream, &shared)));
+            if let Err(e) = result {
+                eprintln!("[WARN] {}", e);
+            }
+        });
+    }
+}
+
+fn convert<R: Read>(
+    input: R,
+    options: ConvertOptions,
+    shared: &SharedContents,
+) -> Result<(), Failure> {
+    let mut converter = StreamConverter::new(TsPacketReader::new(SkipToPmt::new(input)), options);
+    let initialization_segment = track!(converter
+        .initialization_segment()
+        .map_err(Failure::from_error))?;
+    let mime_type = initialization_segment.mime_type();
+    let bytes = track!(initialization_segment
+        .to_bytes()
+        .map_err(Failure::from_error))?;
+    println!("# MIME Type: {}", mime_type);
+    shared.update(|contents| {
+        contents.html = Some(PLAYER_HTML.replace("{{MIME_TYPE}}", &mime_type));
+        contents.initialization_segment = Some(bytes);
+    });
+
+    while let Some(bytes) = track!(converter
+        .next_media_segment_bytes()
+        .map_err(Failure::from_error))?
+    {
+        let bytes = bytes.into_inner();
+        shared.update(|contents| contents.media_segments.push(bytes));
+    }
+    Ok(())
+}
+
+fn handle_request(mut stream: TcpStream, shared: &SharedContents) -> Result<(), Failure> {
+    let mut request_line = String::new();
+    track!(BufReader::new(&stream)
+        .read_line(&mut request_line)
+        .map_err(Failure::from_error))?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    println!("# GET {}", path);
+
+    let body = if path == "/" {
+        shared
+            .wait_for(|c| c.html.clone())
+            .map(|html| ("text/html", html.into_bytes()))
+    } else if path == "/init.mp4" {
+        shared
+            .wait_for(|c| c.initialization_segment.clone())
+            .map(|bytes| ("video/mp4", bytes))
+    } else if path.ends_with(".m4s") {
+        path.trim_start_matches('/')
+            .trim_end_matches(".m4s")
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| shared.wait_for(|c| c.media_segments.get(i).cloned()))
+            .map(|bytes| ("video/iso.segment", bytes))
+    } else {
+        None
+    };
+    let (status, content_type, body) = match body {
+        Some((content_type, body)) => ("200 OK", content_type, body),
+        None => ("404 Not Found", "text/plain", b"Not Found".to_vec()),
+    };
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    track!(stream
+        .write_all(header.as_bytes())
+        .and_then(|()| stream.write_all(&body))
+        .map_err(Failure::from_error))?;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! Measures the throughput of `ByteStreamFormatNalUnits` against a byte-by-byte start code scan.
+//!
+//! ```console
+//! $ cargo run --release --example nal_scan_bench
+//! ```
+extern crate mse_fmp4;
+
+use mse_fmp4::avc::ByteStreamFormatNalUnits;
+use std::time::{Duration, Instant};
+
+const STREAM_SIZE: usize = 64 * 1024 * 1024;
+const NAL_UNIT_SIZE: usize = 256 * 1024; // a slice of a high-bitrate 4K picture
+const ROUNDS: usize = 5;
+
+fn main() {
+    let stream = make_stream();
+    let expected = ByteStreamFormatNalUnits::new(&stream).unwrap().count();
+    assert_eq!(count_naively(&stream), expected);
+
+    let optimized = measure(|| ByteStreamFormatNalUnits::new(&stream).unwrap().count());
+    let naive = measure(|| count_naively(&stream));
+    println!("stream: {} MiB, {} NAL units", STREAM_SIZE >> 20, expected);
+    println!("byte-by-byte: {:>8.1} MiB/s", throughput(naive));
+    println!("optimized:    {:>8.1} MiB/s", throughput(optimized));
+    println!(
+        "speedup:      {:>8.1}x",
+        naive.as_secs_f64() / optimized.as_secs_f64()
+    );
+}
+
+/// Makes an Annex B byte stream whose NAL units are filled with pseudo-random bytes
+/// (emulation prevention is applied, so no start codes appear inside them).
+fn make_stream() -> Vec<u8> {
+    let mut stream = Vec::with_capacity(STREAM_SIZE + NAL_UNIT_SIZE);
+    let mut state = 1u32;
+    while stream.len() < STREAM_SIZE {
+        stream.extend_from_slice(&[0, 0, 0, 1, 0x41]);
+        let mut zeros = 0;
+        for _ in 0..NAL_UNIT_SIZE {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let b = (state >> 16) as u8;
+            if zeros == 2 && b <= 3 {
+                stream.push(3);
+                zeros = 0;
+            }
+            zeros = if b == 0 { zeros + 1 } else { 0 };
+            stream.push(b);
+        }
+        stream.push(0x80);
+    }
+    stream
+}
+
+/// Counts the NAL units by the scan that `ByteStreamFormatNalUnits` used to do.
+fn count_naively(stream: &[u8]) -> usize {
+    let mut bytes = &stream[4..];
+    let mut count = 0;
+    while !bytes.is_empty() {
+        let mut next_start = bytes.len();
+        for i in 0..bytes.len() {
+            if bytes[i..].starts_with(&[0, 0, 0, 1]) {
+                next_start = i + 4;
+                break;
+            } else if bytes[i..].starts_with(&[0, 0, 1]) {
+                next_start = i + 3;
+                break;
+            }
+        }
+        bytes = &bytes[next_start..];
+        count += 1;
+    }
+    count
+}
+
+/// Returns the shortest elapsed time of `f` in `ROUNDS` runs.
+fn measure<F: FnMut() -> usize>(mut f: F) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn throughput(elapsed: Duration) -> f64 {
+    STREAM_SIZE as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+}
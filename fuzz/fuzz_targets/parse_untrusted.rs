@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mse_fmp4::fmp4::{split_into_segments, InitializationSegment};
+use mse_fmp4::isobmff::{self, ParseLimits};
+use std::time::Duration;
+
+fuzz_target!(|data: &[u8]| {
+    if isobmff::parse_untrusted(data, &ParseLimits::default()).is_ok() {
+        let _ = InitializationSegment::read_from(data);
+        let _ = split_into_segments(data, Duration::from_secs(2));
+    }
+});
@@ -0,0 +1,49 @@
+/*
+ * C API of mse_fmp4 (build the crate with the `ffi` feature).
+ *
+ * See the documentation of the `mse_fmp4::ffi` module for the details.
+ * This header is maintained by hand; keep it in sync with `src/ffi.rs`.
+ */
+#ifndef MSE_FMP4_H
+#define MSE_FMP4_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MSE_FMP4_OK 0
+#define MSE_FMP4_ERROR_INVALID_ARGUMENT (-1)
+#define MSE_FMP4_ERROR_INVALID_INPUT (-2)
+#define MSE_FMP4_ERROR_UNSUPPORTED (-3)
+#define MSE_FMP4_ERROR_OTHER (-4)
+#define MSE_FMP4_ERROR_SEGMENT_TOO_LARGE (-5)
+#define MSE_FMP4_ERROR_PANIC (-6)
+
+typedef enum MseFmp4SegmentKind {
+    MSE_FMP4_SEGMENT_KIND_INITIALIZATION = 0,
+    MSE_FMP4_SEGMENT_KIND_MEDIA = 1
+} MseFmp4SegmentKind;
+
+typedef struct MseFmp4Segment {
+    MseFmp4SegmentKind kind;
+    const uint8_t* data;
+    size_t len;
+} MseFmp4Segment;
+
+typedef struct MseFmp4Converter MseFmp4Converter;
+
+MseFmp4Converter* mse_fmp4_converter_new(void);
+void mse_fmp4_converter_free(MseFmp4Converter* converter);
+int mse_fmp4_converter_push(MseFmp4Converter* converter, const uint8_t* data, size_t len);
+int mse_fmp4_converter_finish(MseFmp4Converter* converter);
+int mse_fmp4_converter_pull(MseFmp4Converter* converter, MseFmp4Segment* segment);
+const char* mse_fmp4_converter_mime_type(const MseFmp4Converter* converter);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MSE_FMP4_H */
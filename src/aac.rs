@@ -1,28 +1,42 @@
 //! AAC related constituent elements.
 use crate::{ErrorKind, Result};
 use byteorder::{BigEndian, ReadBytesExt};
-use std::io::Read;
+use std::io::{Read, Write};
 
 pub(crate) const SAMPLES_IN_FRAME: usize = 1024;
 
+/// ADTS (Audio Data Transport Stream) header.
 #[derive(Debug, Clone)]
-pub(crate) struct AdtsHeader {
+pub struct AdtsHeader {
+    /// AAC profile.
     pub profile: AacProfile,
+
+    /// Sampling frequency.
     pub sampling_frequency: SamplingFrequency,
+
+    /// Private bit.
     pub private: bool,
+
+    /// Channel configuration.
     pub channel_configuration: ChannelConfiguration,
-    pub frame_len: u16,       // u13
-    pub buffer_fullness: u16, // u11
+
+    /// Length of the frame including the header (13 bits).
+    pub frame_len: u16,
+
+    /// Buffer fullness (11 bits).
+    pub buffer_fullness: u16,
 }
 impl AdtsHeader {
     const SYNC_WORD: u16 = 0b1111_1111_1111;
     const MPEG_VERSION_4: u8 = 0;
     const HEADER_LEN_WITHOUT_CRC: u16 = 7;
 
+    /// Returns the length of the raw data blocks (i.e., the payload) of the frame.
     pub fn raw_data_blocks_len(&self) -> u16 {
         self.frame_len - Self::HEADER_LEN_WITHOUT_CRC
     }
 
+    /// Reads an ADTS header from the given reader.
     pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
         let n = track_io!(reader.read_u16::<BigEndian>())?;
         track_assert_eq!(n >> 4, Self::SYNC_WORD, ErrorKind::InvalidInput);
@@ -60,10 +74,14 @@ impl AdtsHeader {
 
         let n = track_io!(reader.read_u16::<BigEndian>())?;
         let frame_len = (frame_len_msb_2bits << 11) | (n >> 5);
+        track_assert!(
+            frame_len >= Self::HEADER_LEN_WITHOUT_CRC,
+            ErrorKind::InvalidInput
+        );
         let buffer_fullness_msb_5bits = n & 0b1_1111;
 
         let n = track_io!(reader.read_u8())?;
-        let buffer_fullness = (buffer_fullness_msb_5bits << 5) | u16::from(n >> 2);
+        let buffer_fullness = (buffer_fullness_msb_5bits << 6) | u16::from(n >> 2);
         let raw_data_blocks_minus_1 = n & 0b11;
         track_assert_eq!(raw_data_blocks_minus_1, 0, ErrorKind::Unsupported);
         if !crc_protection_absent {
@@ -80,6 +98,171 @@ impl AdtsHeader {
             buffer_fullness,
         })
     }
+
+    /// Writes the ADTS header to the given writer.
+    ///
+    /// Note that the CRC field is always omitted.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(self.frame_len < 0x2000, ErrorKind::InvalidInput; self.frame_len);
+        track_assert!(
+            self.frame_len >= Self::HEADER_LEN_WITHOUT_CRC,
+            ErrorKind::InvalidInput
+        );
+        track_assert!(self.buffer_fullness < 0x800, ErrorKind::InvalidInput; self.buffer_fullness);
+
+        let crc_protection_absent = 1;
+        write_u16!(
+            writer,
+            (Self::SYNC_WORD << 4) | (u16::from(Self::MPEG_VERSION_4) << 3) | crc_protection_absent
+        );
+
+        let channel_configuration = self.channel_configuration as u8;
+        write_u8!(
+            writer,
+            ((self.profile as u8) << 6)
+                | (self.sampling_frequency.as_index() << 2)
+                | ((self.private as u8) << 1)
+                | (channel_configuration >> 2)
+        );
+        write_u8!(
+            writer,
+            ((channel_configuration & 0b11) << 6) | ((self.frame_len >> 11) as u8)
+        );
+        write_u16!(
+            writer,
+            ((self.frame_len & 0b111_1111_1111) << 5) | (self.buffer_fullness >> 6)
+        );
+        write_u8!(writer, ((self.buffer_fullness & 0b11_1111) as u8) << 2); // raw_data_blocks_minus_1=0
+        Ok(())
+    }
+}
+
+/// Audio specific config (ISO/IEC 14496-3).
+///
+/// Only the fields that are representable in ADTS headers are supported.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AudioSpecificConfig {
+    pub profile: AacProfile,
+    pub sampling_frequency: SamplingFrequency,
+    pub channel_configuration: ChannelConfiguration,
+}
+impl AudioSpecificConfig {
+    /// Reads an `AudioSpecificConfig` from the given reader.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let n = track_io!(reader.read_u16::<BigEndian>())?;
+        let profile = match n >> 11 {
+            1 => AacProfile::Main,
+            2 => AacProfile::Lc,
+            3 => AacProfile::Ssr,
+            4 => AacProfile::Ltp,
+            object_type => track_panic!(ErrorKind::Unsupported, "object_type={}", object_type),
+        };
+        let sampling_frequency = track!(SamplingFrequency::from_index(((n >> 7) & 0b1111) as u8))?;
+        let channel_configuration =
+            track!(ChannelConfiguration::from_u8(((n >> 3) & 0b1111) as u8))?;
+        Ok(AudioSpecificConfig {
+            profile,
+            sampling_frequency,
+            channel_configuration,
+        })
+    }
+
+    /// Writes the `AudioSpecificConfig` to the given writer.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u16!(
+            writer,
+            ((self.profile as u16 + 1) << 11)
+                | (u16::from(self.sampling_frequency.as_index()) << 7)
+                | ((self.channel_configuration as u16) << 3)
+        );
+        Ok(())
+    }
+
+    /// Makes an ADTS header for a raw AAC frame that has the given length.
+    pub fn to_adts_header(&self, raw_frame_len: usize) -> Result<AdtsHeader> {
+        let frame_len = raw_frame_len + AdtsHeader::HEADER_LEN_WITHOUT_CRC as usize;
+        track_assert!(frame_len < 0x2000, ErrorKind::InvalidInput; raw_frame_len);
+        Ok(AdtsHeader {
+            profile: self.profile,
+            sampling_frequency: self.sampling_frequency,
+            private: false,
+            channel_configuration: self.channel_configuration,
+            frame_len: frame_len as u16,
+            buffer_fullness: 0x7FF, // variable bitrate
+        })
+    }
+}
+impl<'a> From<&'a AdtsHeader> for AudioSpecificConfig {
+    fn from(f: &'a AdtsHeader) -> Self {
+        AudioSpecificConfig {
+            profile: f.profile,
+            sampling_frequency: f.sampling_frequency,
+            channel_configuration: f.channel_configuration,
+        }
+    }
+}
+
+/// Wraps the given raw AAC frame in an ADTS frame, and writes it to `writer`.
+pub fn write_adts_frame<W: Write>(
+    mut writer: W,
+    config: &AudioSpecificConfig,
+    raw_frame: &[u8],
+) -> Result<()> {
+    let header = track!(config.to_adts_header(raw_frame.len()))?;
+    track!(header.write_to(&mut writer))?;
+    write_all!(writer, raw_frame);
+    Ok(())
+}
+
+/// An iterator over the ADTS frames contained in a byte sequence.
+///
+/// If an invalid frame is detected, the iterator returns an error and stops.
+#[derive(Debug)]
+pub struct AdtsFrames<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> AdtsFrames<'a> {
+    /// Makes a new `AdtsFrames` instance.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        AdtsFrames { bytes }
+    }
+
+    fn read_frame(&mut self) -> Result<AdtsFrame<'a>> {
+        let mut bytes = self.bytes;
+        let header = track!(AdtsHeader::read_from(&mut bytes))?;
+        let payload_len = header.raw_data_blocks_len() as usize;
+        track_assert!(payload_len <= bytes.len(), ErrorKind::InvalidInput; payload_len, bytes.len());
+
+        let payload = &bytes[..payload_len];
+        self.bytes = &bytes[payload_len..];
+        Ok(AdtsFrame { header, payload })
+    }
+}
+impl<'a> Iterator for AdtsFrames<'a> {
+    type Item = Result<AdtsFrame<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            None
+        } else {
+            let result = track!(self.read_frame());
+            if result.is_err() {
+                self.bytes = &[];
+            }
+            Some(result)
+        }
+    }
+}
+
+/// ADTS frame.
+#[derive(Debug, Clone)]
+pub struct AdtsFrame<'a> {
+    /// Header of the frame.
+    pub header: AdtsHeader,
+
+    /// Payload (raw data blocks) of the frame.
+    pub payload: &'a [u8],
 }
 
 /// Profile.
@@ -98,6 +281,12 @@ pub enum AacProfile {
     /// AAC LTP (Long Term Prediction).
     Ltp = 3,
 }
+impl AacProfile {
+    /// Returns the codec string (e.g., `"mp4a.40.2"`) used in MIME types.
+    pub fn codec_string(self) -> String {
+        format!("mp4a.40.{}", self as u8 + 1)
+    }
+}
 
 /// Sampling frequency.
 #[allow(missing_docs)]
@@ -118,7 +307,8 @@ pub enum SamplingFrequency {
     Hz7350 = 12,
 }
 impl SamplingFrequency {
-    pub(crate) fn as_u32(&self) -> u32 {
+    /// Returns the frequency in Hz.
+    pub fn as_u32(&self) -> u32 {
         match *self {
             SamplingFrequency::Hz96000 => 96_000,
             SamplingFrequency::Hz88200 => 88_200,
@@ -191,6 +381,23 @@ pub enum ChannelConfiguration {
     EightChannels = 7,
 }
 impl ChannelConfiguration {
+    /// Returns the number of the channels.
+    ///
+    /// If the configuration is `SentViaInbandPce`, this returns `None`
+    /// because the actual number is only known from the program config element.
+    pub fn channel_count(&self) -> Option<u16> {
+        match *self {
+            ChannelConfiguration::SentViaInbandPce => None,
+            ChannelConfiguration::OneChannel => Some(1),
+            ChannelConfiguration::TwoChannels => Some(2),
+            ChannelConfiguration::ThreeChannels => Some(3),
+            ChannelConfiguration::FourChannels => Some(4),
+            ChannelConfiguration::FiveChannels => Some(5),
+            ChannelConfiguration::SixChannels => Some(6),
+            ChannelConfiguration::EightChannels => Some(8),
+        }
+    }
+
     fn from_u8(n: u8) -> Result<Self> {
         Ok(match n {
             0 => ChannelConfiguration::SentViaInbandPce,
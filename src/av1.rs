@@ -0,0 +1,602 @@
+//! AV1 related constituent elements.
+//!
+//! # References
+//!
+//! - [AV1 Bitstream & Decoding Process Specification][av1-spec]
+//! - [AV1 Codec ISO Media File Format Binding][av1-isobmff]
+//!
+//! [av1-spec]: https://aomediacodec.github.io/av1-spec/
+//! [av1-isobmff]: https://aomediacodec.github.io/av1-isobmff/
+use crate::{ErrorKind, Result};
+use byteorder::ReadBytesExt;
+use std::io::{Read, Write};
+
+/// AV1 codec configuration record.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Av1CodecConfigurationRecord {
+    pub seq_profile: u8,     // u3
+    pub seq_level_idx_0: u8, // u5
+    pub seq_tier_0: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,                       // u2
+    pub initial_presentation_delay_minus_one: Option<u8>, // u4
+
+    /// Configuration OBUs (typically, a sequence header OBU).
+    pub config_obus: Vec<u8>,
+}
+impl Av1CodecConfigurationRecord {
+    /// Makes a new `Av1CodecConfigurationRecord` instance from the given sequence header OBU.
+    ///
+    /// The OBU is also stored in `config_obus`.
+    pub fn from_sequence_header_obu(obu: &[u8]) -> Result<Self> {
+        let mut obus = Obus::new(obu);
+        let obu = track_assert_some!(obus.next(), ErrorKind::InvalidInput);
+        let obu = track!(obu)?;
+        track_assert_eq!(
+            obu.header.obu_type,
+            ObuType::SequenceHeader,
+            ErrorKind::InvalidInput
+        );
+
+        let sequence_header = track!(SequenceHeader::read_from(obu.payload))?;
+        let mut config_obus = Vec::new();
+        track!(obu.write_to(&mut config_obus))?;
+        Ok(Av1CodecConfigurationRecord {
+            seq_profile: sequence_header.seq_profile,
+            seq_level_idx_0: sequence_header.seq_level_idx_0,
+            seq_tier_0: sequence_header.seq_tier_0,
+            high_bitdepth: sequence_header.high_bitdepth,
+            twelve_bit: sequence_header.twelve_bit,
+            monochrome: sequence_header.mono_chrome,
+            chroma_subsampling_x: sequence_header.subsampling_x,
+            chroma_subsampling_y: sequence_header.subsampling_y,
+            chroma_sample_position: sequence_header.chroma_sample_position,
+            initial_presentation_delay_minus_one: None,
+            config_obus,
+        })
+    }
+
+    /// Reads an `Av1CodecConfigurationRecord` from the given reader.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let b = track_io!(reader.read_u8())?;
+        track_assert_eq!(
+            b,
+            0b1000_0001,
+            ErrorKind::InvalidInput,
+            "Unknown marker or version"
+        );
+
+        let b = track_io!(reader.read_u8())?;
+        let seq_profile = b >> 5;
+        let seq_level_idx_0 = b & 0b1_1111;
+
+        let b = track_io!(reader.read_u8())?;
+        let seq_tier_0 = (b & 0b1000_0000) != 0;
+        let high_bitdepth = (b & 0b0100_0000) != 0;
+        let twelve_bit = (b & 0b0010_0000) != 0;
+        let monochrome = (b & 0b0001_0000) != 0;
+        let chroma_subsampling_x = (b & 0b0000_1000) != 0;
+        let chroma_subsampling_y = (b & 0b0000_0100) != 0;
+        let chroma_sample_position = b & 0b11;
+
+        let b = track_io!(reader.read_u8())?;
+        let initial_presentation_delay_minus_one = if (b & 0b0001_0000) != 0 {
+            Some(b & 0b1111)
+        } else {
+            None
+        };
+
+        let mut config_obus = Vec::new();
+        track_io!(reader.read_to_end(&mut config_obus))?;
+        Ok(Av1CodecConfigurationRecord {
+            seq_profile,
+            seq_level_idx_0,
+            seq_tier_0,
+            high_bitdepth,
+            twelve_bit,
+            monochrome,
+            chroma_subsampling_x,
+            chroma_subsampling_y,
+            chroma_sample_position,
+            initial_presentation_delay_minus_one,
+            config_obus,
+        })
+    }
+
+    /// Returns the bit depth of the stream.
+    pub fn bit_depth(&self) -> u8 {
+        match (self.high_bitdepth, self.twelve_bit) {
+            (false, _) => 8,
+            (true, false) => 10,
+            (true, true) => 12,
+        }
+    }
+
+    /// Returns the codec string (e.g., `"av01.0.04M.08"`) used in MIME types.
+    pub fn codec_string(&self) -> String {
+        format!(
+            "av01.{}.{:02}{}.{:02}",
+            self.seq_profile,
+            self.seq_level_idx_0,
+            if self.seq_tier_0 { 'H' } else { 'M' },
+            self.bit_depth()
+        )
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(self.seq_profile <= 0b111, ErrorKind::InvalidInput; self.seq_profile);
+        track_assert!(self.seq_level_idx_0 <= 0b1_1111, ErrorKind::InvalidInput; self.seq_level_idx_0);
+        track_assert!(self.chroma_sample_position <= 0b11, ErrorKind::InvalidInput);
+
+        write_u8!(writer, 0b1000_0001); // marker=1, version=1
+        write_u8!(writer, (self.seq_profile << 5) | self.seq_level_idx_0);
+        write_u8!(
+            writer,
+            ((self.seq_tier_0 as u8) << 7)
+                | ((self.high_bitdepth as u8) << 6)
+                | ((self.twelve_bit as u8) << 5)
+                | ((self.monochrome as u8) << 4)
+                | ((self.chroma_subsampling_x as u8) << 3)
+                | ((self.chroma_subsampling_y as u8) << 2)
+                | self.chroma_sample_position
+        );
+        if let Some(delay) = self.initial_presentation_delay_minus_one {
+            track_assert!(delay <= 0b1111, ErrorKind::InvalidInput; delay);
+            write_u8!(writer, 0b0001_0000 | delay);
+        } else {
+            write_u8!(writer, 0);
+        }
+        write_all!(writer, &self.config_obus);
+        Ok(())
+    }
+}
+
+/// OBU type.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObuType {
+    SequenceHeader,
+    TemporalDelimiter,
+    FrameHeader,
+    TileGroup,
+    Metadata,
+    Frame,
+    RedundantFrameHeader,
+    TileList,
+    Padding,
+    Reserved(u8),
+}
+impl ObuType {
+    fn from_u8(n: u8) -> Self {
+        match n {
+            1 => ObuType::SequenceHeader,
+            2 => ObuType::TemporalDelimiter,
+            3 => ObuType::FrameHeader,
+            4 => ObuType::TileGroup,
+            5 => ObuType::Metadata,
+            6 => ObuType::Frame,
+            7 => ObuType::RedundantFrameHeader,
+            8 => ObuType::TileList,
+            15 => ObuType::Padding,
+            _ => ObuType::Reserved(n),
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            ObuType::SequenceHeader => 1,
+            ObuType::TemporalDelimiter => 2,
+            ObuType::FrameHeader => 3,
+            ObuType::TileGroup => 4,
+            ObuType::Metadata => 5,
+            ObuType::Frame => 6,
+            ObuType::RedundantFrameHeader => 7,
+            ObuType::TileList => 8,
+            ObuType::Padding => 15,
+            ObuType::Reserved(n) => n,
+        }
+    }
+}
+
+/// OBU header.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObuHeader {
+    pub obu_type: ObuType,
+    pub has_size_field: bool,
+
+    /// `temporal_id` and `spatial_id` fields (present only if the extension flag is set).
+    pub extension: Option<(u8, u8)>,
+}
+impl ObuHeader {
+    fn read_from(bytes: &[u8]) -> Result<(Self, usize)> {
+        track_assert!(!bytes.is_empty(), ErrorKind::InvalidInput);
+        let b = bytes[0];
+        track_assert_eq!(b >> 7, 0, ErrorKind::InvalidInput, "Forbidden bit");
+        let obu_type = ObuType::from_u8((b >> 3) & 0b1111);
+        let extension_flag = (b & 0b100) != 0;
+        let has_size_field = (b & 0b10) != 0;
+        if extension_flag {
+            track_assert!(bytes.len() >= 2, ErrorKind::InvalidInput);
+            let temporal_id = bytes[1] >> 5;
+            let spatial_id = (bytes[1] >> 3) & 0b11;
+            let header = ObuHeader {
+                obu_type,
+                has_size_field,
+                extension: Some((temporal_id, spatial_id)),
+            };
+            Ok((header, 2))
+        } else {
+            let header = ObuHeader {
+                obu_type,
+                has_size_field,
+                extension: None,
+            };
+            Ok((header, 1))
+        }
+    }
+
+    fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u8!(
+            writer,
+            (self.obu_type.as_u8() << 3)
+                | ((self.extension.is_some() as u8) << 2)
+                | ((self.has_size_field as u8) << 1)
+        );
+        if let Some((temporal_id, spatial_id)) = self.extension {
+            write_u8!(writer, (temporal_id << 5) | (spatial_id << 3));
+        }
+        Ok(())
+    }
+}
+
+/// OBU (Open Bitstream Unit).
+#[derive(Debug, Clone)]
+pub struct Obu<'a> {
+    /// Header of the OBU.
+    pub header: ObuHeader,
+
+    /// Payload of the OBU.
+    pub payload: &'a [u8],
+}
+impl<'a> Obu<'a> {
+    /// Writes the OBU to the given writer.
+    ///
+    /// The written OBU always has the size field.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let header = ObuHeader {
+            has_size_field: true,
+            ..self.header
+        };
+        track!(header.write_to(&mut writer))?;
+        track!(write_leb128(&mut writer, self.payload.len() as u64))?;
+        write_all!(writer, self.payload);
+        Ok(())
+    }
+}
+
+/// An iterator over the OBUs contained in a byte sequence (low overhead bitstream format).
+///
+/// Only the last OBU in the sequence is allowed to omit the size field.
+#[derive(Debug)]
+pub struct Obus<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> Obus<'a> {
+    /// Makes a new `Obus` instance.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Obus { bytes }
+    }
+
+    fn read_obu(&mut self) -> Result<Obu<'a>> {
+        let (header, header_len) = track!(ObuHeader::read_from(self.bytes))?;
+        let mut bytes = &self.bytes[header_len..];
+        let payload_len = if header.has_size_field {
+            let size = track!(read_leb128(&mut bytes))?;
+            track_assert!(size <= bytes.len() as u64, ErrorKind::InvalidInput; size, bytes.len());
+            size as usize
+        } else {
+            bytes.len()
+        };
+        let payload = &bytes[..payload_len];
+        self.bytes = &bytes[payload_len..];
+        Ok(Obu { header, payload })
+    }
+}
+impl<'a> Iterator for Obus<'a> {
+    type Item = Result<Obu<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            None
+        } else {
+            let result = track!(self.read_obu());
+            if result.is_err() {
+                self.bytes = &[];
+            }
+            Some(result)
+        }
+    }
+}
+
+/// Converts a temporal unit into the payload of an ISO BMFF sample.
+///
+/// Temporal delimiter, tile list and padding OBUs are removed,
+/// and the size field is added to every OBU.
+pub fn temporal_unit_to_sample_data(temporal_unit: &[u8]) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(temporal_unit.len());
+    for obu in Obus::new(temporal_unit) {
+        let obu = track!(obu)?;
+        match obu.header.obu_type {
+            ObuType::TemporalDelimiter | ObuType::TileList | ObuType::Padding => {}
+            _ => track!(obu.write_to(&mut data))?,
+        }
+    }
+    Ok(data)
+}
+
+fn read_leb128(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0;
+    for i in 0..8 {
+        track_assert!(!bytes.is_empty(), ErrorKind::InvalidInput);
+        let b = bytes[0];
+        *bytes = &bytes[1..];
+        value |= u64::from(b & 0x7F) << (i * 7);
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    track_panic!(ErrorKind::InvalidInput, "Too long leb128 value")
+}
+
+fn write_leb128<W: Write>(mut writer: W, mut value: u64) -> Result<()> {
+    loop {
+        let b = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            write_u8!(writer, b);
+            return Ok(());
+        }
+        write_u8!(writer, b | 0x80);
+    }
+}
+
+#[derive(Debug)]
+struct SequenceHeader {
+    seq_profile: u8,
+    seq_level_idx_0: u8,
+    seq_tier_0: bool,
+    high_bitdepth: bool,
+    twelve_bit: bool,
+    mono_chrome: bool,
+    subsampling_x: bool,
+    subsampling_y: bool,
+    chroma_sample_position: u8,
+}
+impl SequenceHeader {
+    fn read_from(payload: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(payload);
+        let seq_profile = track!(reader.read_bits(3))? as u8;
+        let _still_picture = track!(reader.read_flag())?;
+        let reduced_still_picture_header = track!(reader.read_flag())?;
+
+        let seq_level_idx_0;
+        let mut seq_tier_0 = false;
+        if reduced_still_picture_header {
+            seq_level_idx_0 = track!(reader.read_bits(5))? as u8;
+        } else {
+            let timing_info_present_flag = track!(reader.read_flag())?;
+            let mut decoder_model_info_present_flag = false;
+            let mut buffer_delay_length_minus_1 = 0;
+            if timing_info_present_flag {
+                let _num_units_in_display_tick = track!(reader.read_bits(32))?;
+                let _time_scale = track!(reader.read_bits(32))?;
+                let equal_picture_interval = track!(reader.read_flag())?;
+                if equal_picture_interval {
+                    let _num_ticks_per_picture_minus_1 = track!(reader.read_uvlc())?;
+                }
+                decoder_model_info_present_flag = track!(reader.read_flag())?;
+                if decoder_model_info_present_flag {
+                    buffer_delay_length_minus_1 = track!(reader.read_bits(5))?;
+                    let _num_units_in_decoding_tick = track!(reader.read_bits(32))?;
+                    let _buffer_removal_time_length_minus_1 = track!(reader.read_bits(5))?;
+                    let _frame_presentation_time_length_minus_1 = track!(reader.read_bits(5))?;
+                }
+            }
+            let initial_display_delay_present_flag = track!(reader.read_flag())?;
+            let operating_points_cnt_minus_1 = track!(reader.read_bits(5))?;
+            let mut first_op = None;
+            for _ in 0..=operating_points_cnt_minus_1 {
+                let _operating_point_idc = track!(reader.read_bits(12))?;
+                let seq_level_idx = track!(reader.read_bits(5))? as u8;
+                let seq_tier = if seq_level_idx > 7 {
+                    track!(reader.read_flag())?
+                } else {
+                    false
+                };
+                if decoder_model_info_present_flag {
+                    let decoder_model_present_for_this_op = track!(reader.read_flag())?;
+                    if decoder_model_present_for_this_op {
+                        let n = buffer_delay_length_minus_1 as usize + 1;
+                        let _decoder_buffer_delay = track!(reader.read_bits(n))?;
+                        let _encoder_buffer_delay = track!(reader.read_bits(n))?;
+                        let _low_delay_mode_flag = track!(reader.read_flag())?;
+                    }
+                }
+                if initial_display_delay_present_flag {
+                    let initial_display_delay_present_for_this_op = track!(reader.read_flag())?;
+                    if initial_display_delay_present_for_this_op {
+                        let _initial_display_delay_minus_1 = track!(reader.read_bits(4))?;
+                    }
+                }
+                if first_op.is_none() {
+                    first_op = Some((seq_level_idx, seq_tier));
+                }
+            }
+            let (level, tier) = first_op.expect("Never fails");
+            seq_level_idx_0 = level;
+            seq_tier_0 = tier;
+        }
+
+        let frame_width_bits_minus_1 = track!(reader.read_bits(4))? as usize;
+        let frame_height_bits_minus_1 = track!(reader.read_bits(4))? as usize;
+        let _max_frame_width_minus_1 = track!(reader.read_bits(frame_width_bits_minus_1 + 1))?;
+        let _max_frame_height_minus_1 = track!(reader.read_bits(frame_height_bits_minus_1 + 1))?;
+        let frame_id_numbers_present_flag = if reduced_still_picture_header {
+            false
+        } else {
+            track!(reader.read_flag())?
+        };
+        if frame_id_numbers_present_flag {
+            let _delta_frame_id_length_minus_2 = track!(reader.read_bits(4))?;
+            let _additional_frame_id_length_minus_1 = track!(reader.read_bits(3))?;
+        }
+        let _use_128x128_superblock = track!(reader.read_flag())?;
+        let _enable_filter_intra = track!(reader.read_flag())?;
+        let _enable_intra_edge_filter = track!(reader.read_flag())?;
+        if !reduced_still_picture_header {
+            let _enable_interintra_compound = track!(reader.read_flag())?;
+            let _enable_masked_compound = track!(reader.read_flag())?;
+            let _enable_warped_motion = track!(reader.read_flag())?;
+            let _enable_dual_filter = track!(reader.read_flag())?;
+            let enable_order_hint = track!(reader.read_flag())?;
+            if enable_order_hint {
+                let _enable_jnt_comp = track!(reader.read_flag())?;
+                let _enable_ref_frame_mvs = track!(reader.read_flag())?;
+            }
+            let seq_choose_screen_content_tools = track!(reader.read_flag())?;
+            let seq_force_screen_content_tools = if seq_choose_screen_content_tools {
+                2 // SELECT_SCREEN_CONTENT_TOOLS
+            } else {
+                track!(reader.read_bits(1))?
+            };
+            if seq_force_screen_content_tools > 0 {
+                let seq_choose_integer_mv = track!(reader.read_flag())?;
+                if !seq_choose_integer_mv {
+                    let _seq_force_integer_mv = track!(reader.read_flag())?;
+                }
+            }
+            if enable_order_hint {
+                let _order_hint_bits_minus_1 = track!(reader.read_bits(3))?;
+            }
+        }
+        let _enable_superres = track!(reader.read_flag())?;
+        let _enable_cdef = track!(reader.read_flag())?;
+        let _enable_restoration = track!(reader.read_flag())?;
+
+        // color_config()
+        let high_bitdepth = track!(reader.read_flag())?;
+        let twelve_bit = if seq_profile == 2 && high_bitdepth {
+            track!(reader.read_flag())?
+        } else {
+            false
+        };
+        let mono_chrome = if seq_profile == 1 {
+            false
+        } else {
+            track!(reader.read_flag())?
+        };
+        let color_description_present_flag = track!(reader.read_flag())?;
+        let (color_primaries, transfer_characteristics, matrix_coefficients) =
+            if color_description_present_flag {
+                (
+                    track!(reader.read_bits(8))?,
+                    track!(reader.read_bits(8))?,
+                    track!(reader.read_bits(8))?,
+                )
+            } else {
+                (2, 2, 2) // unspecified
+            };
+
+        let mut chroma_sample_position = 0;
+        let (subsampling_x, subsampling_y) = if mono_chrome {
+            (true, true)
+        } else if color_primaries == 1 && transfer_characteristics == 13 && matrix_coefficients == 0
+        {
+            // sRGB
+            (false, false)
+        } else {
+            let _color_range = track!(reader.read_flag())?;
+            let subsampling = match seq_profile {
+                0 => (true, true),
+                1 => (false, false),
+                _ if twelve_bit => {
+                    let x = track!(reader.read_flag())?;
+                    let y = if x {
+                        track!(reader.read_flag())?
+                    } else {
+                        false
+                    };
+                    (x, y)
+                }
+                _ => (true, false),
+            };
+            if subsampling == (true, true) {
+                chroma_sample_position = track!(reader.read_bits(2))? as u8;
+            }
+            subsampling
+        };
+
+        Ok(SequenceHeader {
+            seq_profile,
+            seq_level_idx_0,
+            seq_tier_0,
+            high_bitdepth,
+            twelve_bit,
+            mono_chrome,
+            subsampling_x,
+            subsampling_y,
+            chroma_sample_position,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_offset: usize,
+}
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader {
+            bytes,
+            bit_offset: 0,
+        }
+    }
+
+    fn read_flag(&mut self) -> Result<bool> {
+        Ok(track!(self.read_bits(1))? == 1)
+    }
+
+    fn read_bits(&mut self, n: usize) -> Result<u64> {
+        track_assert!(n <= 64, ErrorKind::InvalidInput);
+        track_assert!(
+            self.bit_offset + n <= self.bytes.len() * 8,
+            ErrorKind::InvalidInput
+        );
+        let mut value = 0;
+        for _ in 0..n {
+            let bit = (self.bytes[self.bit_offset / 8] >> (7 - self.bit_offset % 8)) & 1;
+            value = (value << 1) | u64::from(bit);
+            self.bit_offset += 1;
+        }
+        Ok(value)
+    }
+
+    fn read_uvlc(&mut self) -> Result<u64> {
+        let mut leading_zeros = 0;
+        while !track!(self.read_flag())? {
+            leading_zeros += 1;
+        }
+        if leading_zeros >= 32 {
+            return Ok((1 << 32) - 1);
+        }
+        let value = track!(self.read_bits(leading_zeros))?;
+        Ok(value + (1 << leading_zeros) - 1)
+    }
+}
@@ -1,12 +1,12 @@
 //! AVC (H.264) related constituent elements.
 use crate::io::AvcBitReader;
 use crate::{ErrorKind, Result};
-use byteorder::ReadBytesExt;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{Read, Write};
 
 /// AVC decoder configuration record.
 #[allow(missing_docs)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AvcDecoderConfigurationRecord {
     pub profile_idc: u8,
     pub constraint_set_flag: u8,
@@ -15,6 +15,48 @@ pub struct AvcDecoderConfigurationRecord {
     pub picture_parameter_set: Vec<u8>,
 }
 impl AvcDecoderConfigurationRecord {
+    /// Returns the codec string (e.g., `"avc1.640029"`) used in MIME types.
+    pub fn codec_string(&self) -> String {
+        format!(
+            "avc1.{:02x}{:02x}{:02x}",
+            self.profile_idc, self.constraint_set_flag, self.level_idc
+        )
+    }
+
+    /// Reads an `AvcDecoderConfigurationRecord` from the given reader.
+    ///
+    /// Only records that contain exactly one SPS and one PPS are supported.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let configuration_version = track_io!(reader.read_u8())?;
+        track_assert_eq!(configuration_version, 1, ErrorKind::InvalidInput);
+
+        let profile_idc = track_io!(reader.read_u8())?;
+        let constraint_set_flag = track_io!(reader.read_u8())?;
+        let level_idc = track_io!(reader.read_u8())?;
+        let length_size_minus_one = track_io!(reader.read_u8())? & 0b11;
+        track_assert_eq!(length_size_minus_one, 3, ErrorKind::Unsupported);
+
+        let num_of_sequence_parameter_sets = track_io!(reader.read_u8())? & 0b1_1111;
+        track_assert_eq!(num_of_sequence_parameter_sets, 1, ErrorKind::Unsupported);
+        let mut sequence_parameter_set =
+            vec![0; track_io!(reader.read_u16::<BigEndian>())? as usize];
+        track_io!(reader.read_exact(&mut sequence_parameter_set))?;
+
+        let num_of_picture_parameter_sets = track_io!(reader.read_u8())?;
+        track_assert_eq!(num_of_picture_parameter_sets, 1, ErrorKind::Unsupported);
+        let mut picture_parameter_set =
+            vec![0; track_io!(reader.read_u16::<BigEndian>())? as usize];
+        track_io!(reader.read_exact(&mut picture_parameter_set))?;
+
+        Ok(AvcDecoderConfigurationRecord {
+            profile_idc,
+            constraint_set_flag,
+            level_idc,
+            sequence_parameter_set,
+            picture_parameter_set,
+        })
+    }
+
     pub(crate) fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
         write_u8!(writer, 1); // configuration_version
 
@@ -36,13 +78,203 @@ impl AvcDecoderConfigurationRecord {
         write_all!(writer, &self.picture_parameter_set);
         Ok(())
     }
+
+    /// Raises the level of the record (and the contained SPS) to `level_idc`.
+    ///
+    /// Level 1b is specified by `9` regardless of the profile (see [`LevelCheck`](struct.LevelCheck.html)).
+    ///
+    /// If the current level is higher than or equal to `level_idc`, this method does nothing and returns `false`.
+    pub fn raise_level(&mut self, level_idc: u8) -> Result<bool> {
+        if level_order(self.indicated_level_idc()) >= level_order(level_idc) {
+            return Ok(false);
+        }
+        track!(raise_sps_level(&mut self.sequence_parameter_set, level_idc))?;
+        set_level(
+            self.profile_idc,
+            &mut self.constraint_set_flag,
+            &mut self.level_idc,
+            level_idc,
+        );
+        Ok(true)
+    }
+
+    /// Returns the level of the record (level 1b is `9` regardless of the profile).
+    pub(crate) fn indicated_level_idc(&self) -> u8 {
+        indicated_level_idc(self.profile_idc, self.constraint_set_flag, self.level_idc)
+    }
 }
 
-#[derive(Debug)]
-pub(crate) struct SpsSummary {
+/// Raises the level of the given SPS NAL unit (including the NAL unit header) to `level_idc`.
+///
+/// Level 1b is specified by `9` regardless of the profile.
+/// If the current level is higher than or equal to `level_idc`, the NAL unit is left as it is and `false` is returned.
+pub(crate) fn raise_sps_level(nal_unit: &mut [u8], level_idc: u8) -> Result<bool> {
+    // nal_unit_header(8) + profile_idc(8) + constraint_set_flag(8) + level_idc(8)
+    track_assert!(nal_unit.len() >= 4, ErrorKind::InvalidInput);
+    let (profile_idc, mut constraint_set_flag, mut level) = (nal_unit[1], nal_unit[2], nal_unit[3]);
+    let current = indicated_level_idc(profile_idc, constraint_set_flag, level);
+    if level_order(current) >= level_order(level_idc) {
+        return Ok(false);
+    }
+    set_level(profile_idc, &mut constraint_set_flag, &mut level, level_idc);
+    nal_unit[2] = constraint_set_flag;
+    nal_unit[3] = level;
+    Ok(true)
+}
+
+/// `level_idc` of level 1b in the profiles other than Baseline, Main and Extended.
+const LEVEL_1B: u8 = 9;
+
+/// `constraint_set3_flag` of the `constraint_set_flag` field.
+const CONSTRAINT_SET3_FLAG: u8 = 0b0001_0000;
+
+/// Returns `true` if the profile signals level 1b by `level_idc=11` and `constraint_set3_flag` (A.3.1 and A.3.2).
+fn signals_level_1b_by_constraint_set3(profile_idc: u8) -> bool {
+    matches!(profile_idc, 66 | 77 | 88)
+}
+
+/// Returns the level indicated by the given fields (level 1b is `LEVEL_1B` regardless of the profile).
+fn indicated_level_idc(profile_idc: u8, constraint_set_flag: u8, level_idc: u8) -> u8 {
+    if level_idc == 11
+        && constraint_set_flag & CONSTRAINT_SET3_FLAG != 0
+        && signals_level_1b_by_constraint_set3(profile_idc)
+    {
+        LEVEL_1B
+    } else {
+        level_idc
+    }
+}
+
+/// Returns a key to order levels (level 1b is between levels 1 and 1.1).
+fn level_order(level_idc: u8) -> u16 {
+    if level_idc == LEVEL_1B {
+        21
+    } else {
+        u16::from(level_idc) * 2
+    }
+}
+
+/// Sets the fields of the given profile to `level_idc` (level 1b is `LEVEL_1B`).
+fn set_level(profile_idc: u8, constraint_set_flag: &mut u8, level: &mut u8, level_idc: u8) {
+    if !signals_level_1b_by_constraint_set3(profile_idc) {
+        *level = level_idc;
+    } else if level_idc == LEVEL_1B {
+        *constraint_set_flag |= CONSTRAINT_SET3_FLAG;
+        *level = 11;
+    } else {
+        // `constraint_set3_flag` is reserved at the other levels of these profiles
+        *constraint_set_flag &= !CONSTRAINT_SET3_FLAG;
+        *level = level_idc;
+    }
+}
+
+/// Result of checking the declared level of an AVC stream against its actual properties.
+///
+/// Level 1b is represented by `9` (as in the High profiles) even if the stream signals it
+/// by `level_idc=11` and `constraint_set3_flag` (as in the Baseline, Main and Extended profiles).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LevelCheck {
+    /// The level declared in the stream.
+    pub declared_level_idc: u8,
+
+    /// The lowest level that satisfies the properties of the stream.
+    pub required_level_idc: u8,
+}
+impl LevelCheck {
+    // level_idc, MaxMBPS, MaxFS, MaxBR (Table A-1 of ISO/IEC 14496-10), in ascending order of the levels
+    const LIMITS: [(u8, u64, u64, u64); 20] = [
+        (10, 1_485, 99, 64),
+        (LEVEL_1B, 1_485, 99, 128),
+        (11, 3_000, 396, 192),
+        (12, 6_000, 396, 384),
+        (13, 11_880, 396, 768),
+        (20, 11_880, 396, 2_000),
+        (21, 19_800, 792, 4_000),
+        (22, 20_250, 1_620, 4_000),
+        (30, 40_500, 1_620, 10_000),
+        (31, 108_000, 3_600, 14_000),
+        (32, 216_000, 5_120, 20_000),
+        (40, 245_760, 8_192, 20_000),
+        (41, 245_760, 8_192, 50_000),
+        (42, 522_240, 8_704, 50_000),
+        (50, 589_824, 22_080, 135_000),
+        (51, 983_040, 36_864, 240_000),
+        (52, 2_073_600, 36_864, 240_000),
+        (60, 4_177_920, 139_264, 240_000),
+        (61, 8_355_840, 139_264, 480_000),
+        (62, 16_711_680, 139_264, 800_000),
+    ];
+
+    /// Checks the level of a stream which has the given properties.
+    ///
+    /// `frame_rate` is in frames per second and `bitrate` is in bits per second.
+    ///
+    /// If no level can satisfy the properties, an `ErrorKind::Unsupported` error is returned.
+    pub fn new(sps: &SpsSummary, frame_rate: Option<f64>, bitrate: Option<u64>) -> Result<Self> {
+        let width_in_mbs = sps.pic_width_in_mbs_minus_1 + 1;
+        let height_in_mbs =
+            (2 - u64::from(sps.frame_mbs_only_flag)) * (sps.pic_height_in_map_units_minus_1 + 1);
+        let frame_size = width_in_mbs * height_in_mbs;
+        let mbs_per_sec = frame_rate.map(|r| (frame_size as f64 * r).ceil() as u64);
+        let cpb_br_vcl_factor = match sps.profile_idc {
+            100 => 1_250,
+            110 => 3_000,
+            122 | 244 => 4_000,
+            _ => 1_000,
+        };
+
+        for &(level_idc, max_mbps, max_fs, max_br) in &Self::LIMITS[..] {
+            // The width and height must not exceed `Sqrt(MaxFS * 8)` (A.3.1 and A.3.2)
+            let max_dimension = ((max_fs * 8) as f64).sqrt() as u64;
+            if frame_size > max_fs || width_in_mbs > max_dimension || height_in_mbs > max_dimension
+            {
+                continue;
+            }
+            if mbs_per_sec.filter(|&x| x > max_mbps).is_some() {
+                continue;
+            }
+            if bitrate
+                .filter(|&x| x > max_br * cpb_br_vcl_factor)
+                .is_some()
+            {
+                continue;
+            }
+            return Ok(LevelCheck {
+                declared_level_idc: indicated_level_idc(
+                    sps.profile_idc,
+                    sps.constraint_set_flag,
+                    sps.level_idc,
+                ),
+                required_level_idc: level_idc,
+            });
+        }
+        track_panic!(
+            ErrorKind::Unsupported,
+            "No AVC level satisfies the stream properties"
+        )
+    }
+
+    /// Returns `true` if the declared level is high enough, otherwise `false`.
+    pub fn is_sufficient(&self) -> bool {
+        level_order(self.declared_level_idc) >= level_order(self.required_level_idc)
+    }
+}
+
+/// Summary of a sequence parameter set (SPS).
+#[derive(Debug, Clone)]
+pub struct SpsSummary {
+    /// `profile_idc` field.
     pub profile_idc: u8,
+
+    /// `constraint_set[0-5]_flag` and `reserved_zero_2bits` fields.
     pub constraint_set_flag: u8,
+
+    /// `level_idc` field.
     pub level_idc: u8,
+
+    /// `seq_parameter_set_id` field.
+    pub seq_parameter_set_id: u64,
+
     pic_width_in_mbs_minus_1: u64,
     pic_height_in_map_units_minus_1: u64,
     frame_mbs_only_flag: u8,
@@ -52,12 +284,14 @@ pub(crate) struct SpsSummary {
     frame_crop_bottom_offset: u64,
 }
 impl SpsSummary {
+    /// Returns the width of the pictures in pixels.
     pub fn width(&self) -> usize {
         (self.pic_width_in_mbs_minus_1 as usize + 1) * 16
             - (self.frame_crop_right_offset as usize * 2)
             - (self.frame_crop_left_offset as usize * 2)
     }
 
+    /// Returns the height of the pictures in pixels.
     pub fn height(&self) -> usize {
         (2 - self.frame_mbs_only_flag as usize)
             * ((self.pic_height_in_map_units_minus_1 as usize + 1) * 16)
@@ -65,13 +299,15 @@ impl SpsSummary {
             - (self.frame_crop_top_offset as usize * 2)
     }
 
-    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
-        let profile_idc = track_io!(reader.read_u8())?;
-        let constraint_set_flag = track_io!(reader.read_u8())?;
-        let level_idc = track_io!(reader.read_u8())?;
-
+    /// Reads a `SpsSummary` from the given reader.
+    ///
+    /// Note that `reader` must not contain the NAL unit header.
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
         let mut reader = AvcBitReader::new(reader);
-        let _seq_parameter_set_id = track!(reader.read_ue())?;
+        let profile_idc = track!(reader.read_u8())?;
+        let constraint_set_flag = track!(reader.read_u8())?;
+        let level_idc = track!(reader.read_u8())?;
+        let seq_parameter_set_id = track!(reader.read_ue())?;
 
         match profile_idc {
             100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 => {
@@ -88,11 +324,11 @@ impl SpsSummary {
             }
             1 => {
                 let _delta_pic_order_always_zero_flag = track!(reader.read_bit())?;
-                let _offset_for_non_ref_pic = track!(reader.read_ue())?;
-                let _ffset_for_top_to_bottom_field = track!(reader.read_ue())?;
+                let _offset_for_non_ref_pic = track!(reader.read_se())?;
+                let _offset_for_top_to_bottom_field = track!(reader.read_se())?;
                 let num_ref_frames_in_pic_order_cnt_cycle = track!(reader.read_ue())?;
                 for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
-                    let _offset_for_ref_frame = track!(reader.read_ue())?;
+                    let _offset_for_ref_frame = track!(reader.read_se())?;
                 }
             }
             2 => {}
@@ -124,10 +360,39 @@ impl SpsSummary {
             (0, 0, 0, 0)
         };
 
+        // Rejects dimensions that do not fit in the 16-bit width and height fields of the sample entry,
+        // and cropping that exceeds the decoded picture (both would make `width()` or `height()` overflow)
+        let decoded_width = pic_width_in_mbs_minus_1
+            .saturating_add(1)
+            .saturating_mul(16);
+        let decoded_height = pic_height_in_map_units_minus_1
+            .saturating_add(1)
+            .saturating_mul(16)
+            .saturating_mul(2 - u64::from(frame_mbs_only_flag));
+        track_assert!(
+            decoded_width <= 0xFFFF && decoded_height <= 0xFFFF,
+            ErrorKind::InvalidInput,
+            "Too large picture: {}x{}",
+            decoded_width,
+            decoded_height
+        );
+        let crop_x = frame_crop_left_offset.saturating_add(frame_crop_right_offset);
+        let crop_y = frame_crop_top_offset.saturating_add(frame_crop_bottom_offset);
+        track_assert!(
+            crop_x.saturating_mul(2) < decoded_width && crop_y.saturating_mul(2) < decoded_height,
+            ErrorKind::InvalidInput,
+            "Cropping exceeds the picture: picture={}x{}, crop=({}, {})",
+            decoded_width,
+            decoded_height,
+            crop_x,
+            crop_y
+        );
+
         Ok(SpsSummary {
             profile_idc,
             constraint_set_flag,
             level_idc,
+            seq_parameter_set_id,
             pic_width_in_mbs_minus_1,
             pic_height_in_map_units_minus_1,
             frame_mbs_only_flag,
@@ -139,12 +404,106 @@ impl SpsSummary {
     }
 }
 
+/// Summary of a picture parameter set (PPS).
 #[derive(Debug)]
-pub(crate) struct NalUnit {
+pub struct PpsSummary {
+    /// `pic_parameter_set_id` field.
+    pub pic_parameter_set_id: u64,
+
+    /// `seq_parameter_set_id` field.
+    pub seq_parameter_set_id: u64,
+
+    /// `entropy_coding_mode_flag` field.
+    pub entropy_coding_mode_flag: bool,
+
+    /// `bottom_field_pic_order_in_frame_present_flag` field.
+    pub bottom_field_pic_order_in_frame_present_flag: bool,
+
+    /// `num_slice_groups_minus1` field.
+    pub num_slice_groups_minus1: u64,
+
+    /// `slice_group_map_type` field (present only if `num_slice_groups_minus1 > 0`).
+    pub slice_group_map_type: Option<u64>,
+}
+impl PpsSummary {
+    /// Returns `true` if the entropy coding mode is CABAC, otherwise (i.e., CAVLC) `false`.
+    pub fn is_cabac(&self) -> bool {
+        self.entropy_coding_mode_flag
+    }
+
+    /// Returns `true` if the picture parameter set uses slice groups (FMO).
+    pub fn has_slice_groups(&self) -> bool {
+        self.num_slice_groups_minus1 > 0
+    }
+
+    /// Checks whether this picture parameter set can be paired with the given sequence parameter set.
+    pub fn check_pairing(&self, sps: &SpsSummary) -> Result<()> {
+        track_assert_eq!(
+            self.seq_parameter_set_id,
+            sps.seq_parameter_set_id,
+            ErrorKind::InvalidInput,
+            "PPS refers to unknown SPS"
+        );
+        match sps.profile_idc {
+            66 => {
+                track_assert!(
+                    !self.is_cabac(),
+                    ErrorKind::InvalidInput,
+                    "CABAC is not allowed in the baseline profile"
+                );
+            }
+            88 => {}
+            _ => {
+                track_assert!(
+                    !self.has_slice_groups(),
+                    ErrorKind::InvalidInput,
+                    "Slice groups are not allowed in the profile"; sps.profile_idc
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a `PpsSummary` from the given reader.
+    ///
+    /// Note that `reader` must not contain the NAL unit header.
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = AvcBitReader::new(reader);
+        let pic_parameter_set_id = track!(reader.read_ue())?;
+        let seq_parameter_set_id = track!(reader.read_ue())?;
+        let entropy_coding_mode_flag = track!(reader.read_flag())?;
+        let bottom_field_pic_order_in_frame_present_flag = track!(reader.read_flag())?;
+        let num_slice_groups_minus1 = track!(reader.read_ue())?;
+        let slice_group_map_type = if num_slice_groups_minus1 > 0 {
+            let slice_group_map_type = track!(reader.read_ue())?;
+            track_assert!(slice_group_map_type <= 6, ErrorKind::InvalidInput; slice_group_map_type);
+            Some(slice_group_map_type)
+        } else {
+            None
+        };
+        Ok(PpsSummary {
+            pic_parameter_set_id,
+            seq_parameter_set_id,
+            entropy_coding_mode_flag,
+            bottom_field_pic_order_in_frame_present_flag,
+            num_slice_groups_minus1,
+            slice_group_map_type,
+        })
+    }
+}
+
+/// NAL unit header.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct NalUnit {
+    /// `nal_ref_idc` field.
     pub nal_ref_idc: u8,
+
+    /// `nal_unit_type` field.
     pub nal_unit_type: NalUnitType,
 }
 impl NalUnit {
+    /// Reads the header of a NAL unit from the given reader.
     pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
         let b = track_io!(reader.read_u8())?;
 
@@ -157,8 +516,11 @@ impl NalUnit {
     }
 }
 
+/// NAL unit type.
+#[allow(missing_docs)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub(crate) enum NalUnitType {
+#[non_exhaustive]
+pub enum NalUnitType {
     CodedSliceOfANonIdrPicture = 1,
     CodedSliceDataPartitionA = 2,
     CodedSliceDataPartitionB = 3,
@@ -202,11 +564,15 @@ impl NalUnitType {
     }
 }
 
+/// An iterator over the NAL units contained in a byte stream (Annex B) formatted sequence.
 #[derive(Debug)]
-pub(crate) struct ByteStreamFormatNalUnits<'a> {
+pub struct ByteStreamFormatNalUnits<'a> {
     bytes: &'a [u8],
 }
 impl<'a> ByteStreamFormatNalUnits<'a> {
+    /// Makes a new `ByteStreamFormatNalUnits` instance.
+    ///
+    /// `bytes` must start with a start code (`0x000001` or `0x00000001`).
     pub fn new(bytes: &'a [u8]) -> Result<Self> {
         let bytes = if bytes.starts_with(&[0, 0, 1][..]) {
             &bytes[3..]
@@ -224,22 +590,200 @@ impl<'a> Iterator for ByteStreamFormatNalUnits<'a> {
         if self.bytes.is_empty() {
             None
         } else {
-            let mut nal_unit_end = self.bytes.len();
-            let mut next_start = self.bytes.len();
-            for i in 0..self.bytes.len() {
-                if (&self.bytes[i..]).starts_with(&[0, 0, 0, 1][..]) {
-                    nal_unit_end = i;
-                    next_start = i + 4;
-                    break;
-                } else if (&self.bytes[i..]).starts_with(&[0, 0, 1][..]) {
-                    nal_unit_end = i;
-                    next_start = i + 3;
-                    break;
-                }
-            }
+            let (nal_unit_end, next_start) = match find_start_code(self.bytes) {
+                // A zero byte preceding a three-byte start code belongs to a four-byte one
+                Some(i) if i > 0 && self.bytes[i - 1] == 0 => (i - 1, i + 3),
+                Some(i) => (i, i + 3),
+                None => (self.bytes.len(), self.bytes.len()),
+            };
             let nal_unit = &self.bytes[..nal_unit_end];
             self.bytes = &self.bytes[next_start..];
             Some(nal_unit)
         }
     }
 }
+
+/// Returns the position of the first three-byte start code (`0x000001`) in `bytes`.
+///
+/// Instead of testing every position, this looks at the third byte of each candidate:
+/// if it is greater than one, no start code can begin at any of the three positions up to it,
+/// so three bytes are skipped at once (which is the common case in slice data).
+fn find_start_code(bytes: &[u8]) -> Option<usize> {
+    let mut i = 2;
+    while i < bytes.len() {
+        match bytes[i] {
+            0 => i += 1,
+            1 => {
+                if bytes[i - 1] == 0 && bytes[i - 2] == 0 {
+                    return Some(i - 2);
+                }
+                i += 3;
+            }
+            _ => i += 3,
+        }
+    }
+    None
+}
+
+/// SEI (supplemental enhancement information) message.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SeiMessage {
+    /// `payloadType` of the message (e.g., `SeiMessage::PIC_TIMING`).
+    pub payload_type: u32,
+
+    /// Payload of the message (without emulation prevention bytes).
+    pub payload: Vec<u8>,
+}
+impl SeiMessage {
+    /// Payload type of the buffering period SEI message.
+    pub const BUFFERING_PERIOD: u32 = 0;
+
+    /// Payload type of the picture timing SEI message.
+    pub const PIC_TIMING: u32 = 1;
+
+    /// Payload type of the user data registered by Rec. ITU-T T.35
+    /// (e.g., AFD and closed captions of ATSC A/53).
+    pub const USER_DATA_REGISTERED_ITU_T_T35: u32 = 4;
+
+    /// Payload type of the unregistered user data.
+    pub const USER_DATA_UNREGISTERED: u32 = 5;
+
+    /// Payload type of the mastering display colour volume SEI message (HDR metadata).
+    pub const MASTERING_DISPLAY_COLOUR_VOLUME: u32 = 137;
+
+    /// Payload type of the content light level information SEI message (HDR metadata).
+    pub const CONTENT_LIGHT_LEVEL_INFO: u32 = 144;
+
+    /// Reads the messages in an SEI NAL unit (including its header).
+    pub fn read_all(nal_unit: &[u8]) -> Result<Vec<Self>> {
+        let header = track!(NalUnit::read_from(nal_unit))?;
+        track_assert_eq!(
+            header.nal_unit_type,
+            NalUnitType::SupplementalEnhancementInformation,
+            ErrorKind::InvalidInput
+        );
+        let rbsp = remove_emulation_prevention_bytes(&nal_unit[1..]);
+        let mut rest = &rbsp[..];
+        let mut messages = Vec::new();
+        while !rest.is_empty() && rest != [0x80] {
+            let payload_type = track!(read_sei_value(&mut rest))?;
+            let payload_size = track!(read_sei_value(&mut rest))? as usize;
+            track_assert!(payload_size <= rest.len(), ErrorKind::InvalidInput; payload_size, rest.len());
+            messages.push(SeiMessage {
+                payload_type,
+                payload: rest[..payload_size].to_owned(),
+            });
+            rest = &rest[payload_size..];
+        }
+        Ok(messages)
+    }
+
+    /// Makes an SEI NAL unit (including its header) that contains `messages`.
+    pub fn to_nal_unit(messages: &[Self]) -> Vec<u8> {
+        let mut rbsp = Vec::new();
+        for m in messages {
+            write_sei_value(&mut rbsp, m.payload_type);
+            write_sei_value(&mut rbsp, m.payload.len() as u32);
+            rbsp.extend_from_slice(&m.payload);
+        }
+        rbsp.push(0x80); // rbsp_trailing_bits
+
+        let mut nal_unit = vec![NalUnitType::SupplementalEnhancementInformation as u8];
+        let mut zeros = 0;
+        for b in rbsp {
+            if zeros == 2 && b <= 3 {
+                nal_unit.push(3); // emulation_prevention_three_byte
+                zeros = 0;
+            }
+            zeros = if b == 0 { zeros + 1 } else { 0 };
+            nal_unit.push(b);
+        }
+        nal_unit
+    }
+}
+
+/// Reads the SEI messages in a sample of an AVC track (i.e., NAL units prefixed by their 4-byte lengths).
+pub fn read_sei_messages(sample: &[u8]) -> Result<Vec<SeiMessage>> {
+    let mut messages = Vec::new();
+    for nal_unit in track!(length_prefixed_nal_units(sample))? {
+        let nal_unit_type = track!(NalUnit::read_from(nal_unit))?.nal_unit_type;
+        if nal_unit_type == NalUnitType::SupplementalEnhancementInformation {
+            messages.extend(track!(SeiMessage::read_all(nal_unit))?);
+        }
+    }
+    Ok(messages)
+}
+
+/// Inserts an SEI NAL unit that contains `messages` into a sample of an AVC track
+/// (i.e., NAL units prefixed by their 4-byte lengths), and returns the resulting sample.
+///
+/// The NAL unit is placed before the first coded slice, as required by the specification.
+/// If `messages` is empty, the sample is returned as it is.
+pub fn insert_sei_messages(sample: &[u8], messages: &[SeiMessage]) -> Result<Vec<u8>> {
+    if messages.is_empty() {
+        return Ok(sample.to_owned());
+    }
+    let sei = SeiMessage::to_nal_unit(messages);
+    let mut inserted = false;
+    let mut data = Vec::with_capacity(sample.len() + 4 + sei.len());
+    for nal_unit in track!(length_prefixed_nal_units(sample))? {
+        let nal_unit_type = track!(NalUnit::read_from(nal_unit))?.nal_unit_type as u8;
+        if !inserted && (1..=5).contains(&nal_unit_type) {
+            track_io!(data.write_u32::<BigEndian>(sei.len() as u32))?;
+            data.extend_from_slice(&sei);
+            inserted = true;
+        }
+        track_io!(data.write_u32::<BigEndian>(nal_unit.len() as u32))?;
+        data.extend_from_slice(nal_unit);
+    }
+    track_assert!(inserted, ErrorKind::InvalidInput, "No coded slices");
+    Ok(data)
+}
+
+/// Splits a sample of an AVC track into its NAL units.
+fn length_prefixed_nal_units(mut sample: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut nal_units = Vec::new();
+    while !sample.is_empty() {
+        let size = track_io!(sample.read_u32::<BigEndian>())? as usize;
+        track_assert!(size > 0 && size <= sample.len(), ErrorKind::InvalidInput; size, sample.len());
+        nal_units.push(&sample[..size]);
+        sample = &sample[size..];
+    }
+    Ok(nal_units)
+}
+
+/// Reads a `payloadType` or `payloadSize` value of an SEI message.
+fn read_sei_value(bytes: &mut &[u8]) -> Result<u32> {
+    let mut value = 0u32;
+    loop {
+        let b = track_io!(bytes.read_u8())?;
+        value = track_assert_some!(value.checked_add(u32::from(b)), ErrorKind::InvalidInput);
+        if b != 0xFF {
+            return Ok(value);
+        }
+    }
+}
+
+/// Writes a `payloadType` or `payloadSize` value of an SEI message.
+fn write_sei_value(bytes: &mut Vec<u8>, mut value: u32) {
+    while value >= 0xFF {
+        bytes.push(0xFF);
+        value -= 0xFF;
+    }
+    bytes.push(value as u8);
+}
+
+/// Converts the payload of a NAL unit into its RBSP (raw byte sequence payload).
+fn remove_emulation_prevention_bytes(payload: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(payload.len());
+    let mut zeros = 0;
+    for &b in payload {
+        if zeros == 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        rbsp.push(b);
+    }
+    rbsp
+}
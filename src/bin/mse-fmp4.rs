@@ -0,0 +1,229 @@
+extern crate clap;
+extern crate mpeg2ts;
+extern crate mse_fmp4;
+#[macro_use]
+extern crate trackable;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use mpeg2ts::ts::TsPacketReader;
+use mse_fmp4::fmp4::{self, InitializationSegment};
+use mse_fmp4::fourcc;
+use mse_fmp4::io::WriteTo;
+use mse_fmp4::isobmff::{self, Boxes, ParseLimits, RawBox};
+use mse_fmp4::mpeg2_ts::{self, ConvertOptions};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+use trackable::error::{Failed, Failure};
+
+type Result<T> = std::result::Result<T, Failure>;
+
+fn main() {
+    let input = Arg::with_name("INPUT")
+        .help("Input file (`-` means the standard input)")
+        .default_value("-");
+    let output_dir = Arg::with_name("OUTPUT_DIR")
+        .long("output-dir")
+        .short("o")
+        .takes_value(true)
+        .default_value(".");
+    let prefix = Arg::with_name("PREFIX")
+        .long("prefix")
+        .takes_value(true)
+        .default_value("movie");
+    let segment_duration = Arg::with_name("SEGMENT_DURATION")
+        .long("segment-duration")
+        .help("Target duration of each media segment in seconds")
+        .takes_value(true);
+
+    let matches = App::new("mse-fmp4")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("ts2fmp4")
+                .about("Converts MPEG-2 TS into fragmented MP4 segments")
+                .arg(input.clone())
+                .arg(output_dir.clone())
+                .arg(prefix.clone())
+                .arg(segment_duration.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Prints the box structure of an MP4 file")
+                .arg(input.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Checks that an MP4 file can be parsed by this crate")
+                .arg(input.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("split")
+                .about("Re-groups the fragments of a fragmented MP4 file into segments")
+                .arg(input)
+                .arg(output_dir)
+                .arg(prefix)
+                .arg(segment_duration.default_value("2")),
+        )
+        .get_matches();
+
+    let result = match matches.subcommand() {
+        ("ts2fmp4", Some(m)) => ts2fmp4(m),
+        ("inspect", Some(m)) => inspect(m),
+        ("validate", Some(m)) => validate(m),
+        ("split", Some(m)) => split(m),
+        _ => unreachable!(),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn ts2fmp4(matches: &ArgMatches) -> Result<()> {
+    let input = track!(read_input(matches))?;
+    let reader = TsPacketReader::new(&input[..]);
+    let (initialization_segment, media_segment) = track!(mpeg2_ts::to_fmp4_with_options(
+        reader,
+        &ConvertOptions::default()
+    )
+    .map_err(Failure::from_error))?;
+    println!("# MIME Type: {}", initialization_segment.mime_type());
+
+    let mut file = track!(initialization_segment
+        .to_bytes()
+        .map_err(Failure::from_error))?;
+    if let Some(duration) = track!(segment_duration(matches))? {
+        track!(media_segment
+            .write_to(&mut file)
+            .map_err(Failure::from_error))?;
+        track!(write_split_segments(matches, &file, duration))
+    } else {
+        track!(write_output(matches, "-init.mp4", &file))?;
+        let bytes = track!(media_segment.to_bytes().map_err(Failure::from_error))?;
+        track!(write_output(matches, ".m4s", &bytes))
+    }
+}
+
+fn inspect(matches: &ArgMatches) -> Result<()> {
+    let input = track!(read_input(matches))?;
+    track!(isobmff::parse_untrusted(&input, &ParseLimits::default()).map_err(Failure::from_error))?;
+    track!(print_boxes(&input, 0, 0))?;
+    if track!(isobmff::find_box(&input, fourcc::MOOV).map_err(Failure::from_error))?.is_some() {
+        let segment =
+            track!(InitializationSegment::read_from(&input).map_err(Failure::from_error))?;
+        println!("# MIME Type: {}", segment.mime_type());
+    }
+    Ok(())
+}
+
+fn validate(matches: &ArgMatches) -> Result<()> {
+    let input = track!(read_input(matches))?;
+    let boxes = track!(
+        isobmff::parse_untrusted(&input, &ParseLimits::default()).map_err(Failure::from_error)
+    )?;
+    if boxes.iter().any(|b| b.box_type == fourcc::MOOV) {
+        track!(InitializationSegment::read_from(&input).map_err(Failure::from_error))?;
+    }
+    if boxes.iter().any(|b| b.box_type == fourcc::MOOF) {
+        track!(
+            fmp4::split_into_segments(&input, Duration::from_secs(0)).map_err(Failure::from_error)
+        )?;
+    }
+    println!("OK ({} top-level boxes)", boxes.len());
+    Ok(())
+}
+
+fn split(matches: &ArgMatches) -> Result<()> {
+    let input = track!(read_input(matches))?;
+    let duration = track!(segment_duration(matches))?.expect("Never fails");
+    track!(write_split_segments(matches, &input, duration))
+}
+
+fn write_split_segments(matches: &ArgMatches, file: &[u8], duration: Duration) -> Result<()> {
+    let segments = track!(fmp4::split_into_segments(file, duration).map_err(Failure::from_error))?;
+    track!(write_output(
+        matches,
+        "-init.mp4",
+        &segments.initialization_segment
+    ))?;
+    for (i, segment) in segments.media_segments.iter().enumerate() {
+        track!(write_output(matches, &format!("-{}.m4s", i + 1), segment))?;
+    }
+    Ok(())
+}
+
+fn print_boxes(bytes: &[u8], base_offset: usize, depth: usize) -> Result<()> {
+    for b in Boxes::new(bytes) {
+        let b: RawBox = track!(b.map_err(Failure::from_error))?;
+        println!(
+            "{:indent$}{} (offset={}, size={})",
+            "",
+            String::from_utf8_lossy(&b.box_type),
+            base_offset + b.offset,
+            b.size(),
+            indent = depth * 2
+        );
+        match b.box_type {
+            fourcc::MOOV
+            | fourcc::TRAK
+            | fourcc::EDTS
+            | fourcc::MDIA
+            | fourcc::MINF
+            | fourcc::DINF
+            | fourcc::STBL
+            | fourcc::MVEX
+            | fourcc::MOOF
+            | fourcc::TRAF
+            | fourcc::MFRA => {
+                let payload_offset = base_offset + b.offset + b.header_size;
+                track!(print_boxes(b.payload, payload_offset, depth + 1))?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_input(matches: &ArgMatches) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match matches.value_of("INPUT").expect("Never fails") {
+        "-" => track!(io::stdin()
+            .read_to_end(&mut buf)
+            .map_err(Failure::from_error))?,
+        path => track!(File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut buf))
+            .map_err(Failure::from_error))?,
+    };
+    Ok(buf)
+}
+
+fn segment_duration(matches: &ArgMatches) -> Result<Option<Duration>> {
+    if let Some(s) = matches.value_of("SEGMENT_DURATION") {
+        let secs: f64 = track!(s.parse().map_err(Failure::from_error))?;
+        track_assert!(
+            secs.is_finite() && secs >= 0.0,
+            Failed;
+            secs
+        );
+        Ok(Some(Duration::from_millis((secs * 1000.0) as u64)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn write_output(matches: &ArgMatches, suffix: &str, bytes: &[u8]) -> Result<()> {
+    let dir = Path::new(matches.value_of("OUTPUT_DIR").expect("Never fails"));
+    track!(fs::create_dir_all(dir).map_err(Failure::from_error))?;
+    let path = dir.join(format!(
+        "{}{}",
+        matches.value_of("PREFIX").expect("Never fails"),
+        suffix
+    ));
+    track!(File::create(&path)
+        .and_then(|mut f| f.write_all(bytes))
+        .map_err(Failure::from_error))?;
+    println!("# Wrote {:?}", path);
+    Ok(())
+}
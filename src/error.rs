@@ -21,6 +21,29 @@ impl From<mpeg2ts::Error> for Error {
 pub enum ErrorKind {
     InvalidInput,
     Unsupported,
+
+    /// A box (or a segment) is too large to be represented with 32-bit box sizes.
+    ///
+    /// Media segments written for MSE (e.g., by `WriteTo`) do not use the 64-bit `largesize` field.
+    /// `MediaSegment::write_large_to` can be used for files instead.
+    SegmentTooLarge,
     Other,
 }
 impl TrackableErrorKind for ErrorKind {}
+
+/// Converts `n` into another integer type, failing with an `ErrorKind::InvalidInput` error
+/// that names the converted quantity (`what`) if the value does not fit.
+pub(crate) fn try_cast<T, U>(n: T, what: &str) -> crate::Result<U>
+where
+    T: Copy + std::fmt::Display,
+    U: std::convert::TryFrom<T>,
+{
+    let m = U::try_from(n).ok();
+    Ok(track_assert_some!(
+        m,
+        ErrorKind::InvalidInput,
+        "{} is out of range: {}",
+        what,
+        n
+    ))
+}
@@ -0,0 +1,239 @@
+//! C API of the MPEG-2 TS to fragmented MP4 converter (requires the `ffi` feature).
+//!
+//! A typical usage is as follows:
+//!
+//! ```c
+//! MseFmp4Converter* c = mse_fmp4_converter_new();
+//! mse_fmp4_converter_push(c, ts, ts_len);  // may be called repeatedly
+//! mse_fmp4_converter_finish(c);
+//!
+//! MseFmp4Segment segment;
+//! while (mse_fmp4_converter_pull(c, &segment) == 1) {
+//!     // `segment.data` is valid until the next call of `pull` or `free`.
+//! }
+//! mse_fmp4_converter_free(c);
+//! ```
+//!
+//! Functions that return `c_int` return `MSE_FMP4_OK` (`0`) on success and one of the negative
+//! `MSE_FMP4_ERROR_*` codes on failure.
+//! No panic unwinds across the API: a panic is reported as `MSE_FMP4_ERROR_PANIC`
+//! (or a null pointer), after which the converter may only be released.
+//!
+//! The C declarations are in `include/mse_fmp4.h`.
+use crate::mpeg2_ts::{self, ConvertOptions, Fmp4Bytes};
+use crate::ErrorKind;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// The operation succeeded.
+pub const MSE_FMP4_OK: c_int = 0;
+
+/// An argument is invalid (e.g., a null pointer) or the function was called in a wrong state.
+pub const MSE_FMP4_ERROR_INVALID_ARGUMENT: c_int = -1;
+
+/// The TS stream is malformed.
+pub const MSE_FMP4_ERROR_INVALID_INPUT: c_int = -2;
+
+/// The TS stream contains unsupported elements.
+pub const MSE_FMP4_ERROR_UNSUPPORTED: c_int = -3;
+
+/// Other errors.
+pub const MSE_FMP4_ERROR_OTHER: c_int = -4;
+
+/// A segment is too large to be written with 32-bit box sizes.
+pub const MSE_FMP4_ERROR_SEGMENT_TOO_LARGE: c_int = -5;
+
+/// The library panicked (i.e., there is a bug in it).
+///
+/// The converter is left in an unspecified state and must only be released.
+pub const MSE_FMP4_ERROR_PANIC: c_int = -6;
+
+// Runs `f` so that a panic in it does not unwind into the C caller.
+fn catch_panic<T, F: FnOnce() -> T>(on_panic: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// Kind of a segment.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MseFmp4SegmentKind {
+    /// Initialization segment.
+    Initialization = 0,
+
+    /// Media segment.
+    Media = 1,
+}
+
+/// A segment pulled from a converter.
+#[repr(C)]
+#[derive(Debug)]
+pub struct MseFmp4Segment {
+    /// Kind of the segment.
+    pub kind: MseFmp4SegmentKind,
+
+    /// Pointer to the serialized segment (owned by the converter).
+    pub data: *const u8,
+
+    /// Length of the serialized segment in bytes.
+    pub len: usize,
+}
+
+/// Converter state (opaque to C).
+#[derive(Debug, Default)]
+pub struct MseFmp4Converter {
+    input: Vec<u8>,
+    output: Option<Fmp4Bytes>,
+    mime_type: Option<CString>,
+    pulled: usize,
+    current: Vec<u8>,
+}
+
+/// Makes a new converter.
+///
+/// The returned converter must be released by `mse_fmp4_converter_free`.
+/// Null is returned if the converter cannot be made.
+#[no_mangle]
+pub extern "C" fn mse_fmp4_converter_new() -> *mut MseFmp4Converter {
+    catch_panic(ptr::null_mut(), || {
+        Box::into_raw(Box::new(MseFmp4Converter::default()))
+    })
+}
+
+/// Releases a converter made by `mse_fmp4_converter_new`.
+///
+/// # Safety
+///
+/// `converter` must be null or a pointer returned by `mse_fmp4_converter_new` that has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn mse_fmp4_converter_free(converter: *mut MseFmp4Converter) {
+    if !converter.is_null() {
+        // Nothing can be reported here, so a panic while dropping (if any) only leaks the rest of the converter
+        catch_panic((), || drop(Box::from_raw(converter)));
+    }
+}
+
+/// Appends TS bytes to the converter.
+///
+/// # Safety
+///
+/// `converter` must be a valid converter and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mse_fmp4_converter_push(
+    converter: *mut MseFmp4Converter,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let converter = match converter.as_mut() {
+        Some(c) if c.output.is_none() && (!data.is_null() || len == 0) => c,
+        _ => return MSE_FMP4_ERROR_INVALID_ARGUMENT,
+    };
+    catch_panic(MSE_FMP4_ERROR_PANIC, || {
+        if len != 0 {
+            converter
+                .input
+                .extend_from_slice(slice::from_raw_parts(data, len));
+        }
+        MSE_FMP4_OK
+    })
+}
+
+/// Converts the TS bytes pushed so far into fragmented MP4 segments.
+///
+/// After this function succeeds, the segments can be retrieved by `mse_fmp4_converter_pull`.
+///
+/// # Safety
+///
+/// `converter` must be a valid converter.
+#[no_mangle]
+pub unsafe extern "C" fn mse_fmp4_converter_finish(converter: *mut MseFmp4Converter) -> c_int {
+    let converter = match converter.as_mut() {
+        Some(c) if c.output.is_none() => c,
+        _ => return MSE_FMP4_ERROR_INVALID_ARGUMENT,
+    };
+    catch_panic(MSE_FMP4_ERROR_PANIC, || {
+        let input = std::mem::take(&mut converter.input);
+        let output = match mpeg2_ts::ts_bytes_to_fmp4(&input, &ConvertOptions::default()) {
+            Ok(output) => output,
+            Err(e) => {
+                return match *e.kind() {
+                    ErrorKind::InvalidInput => MSE_FMP4_ERROR_INVALID_INPUT,
+                    ErrorKind::Unsupported => MSE_FMP4_ERROR_UNSUPPORTED,
+                    ErrorKind::SegmentTooLarge => MSE_FMP4_ERROR_SEGMENT_TOO_LARGE,
+                    ErrorKind::Other => MSE_FMP4_ERROR_OTHER,
+                }
+            }
+        };
+        match CString::new(output.mime_type.clone()) {
+            Ok(mime_type) => {
+                converter.mime_type = Some(mime_type);
+                converter.output = Some(output);
+                MSE_FMP4_OK
+            }
+            Err(_) => MSE_FMP4_ERROR_OTHER,
+        }
+    })
+}
+
+/// Pulls the next segment (the initialization segment first, followed by the media segment).
+///
+/// Returns `1` if a segment is stored in `*segment`, or `0` if all segments have been pulled.
+/// The memory pointed by `segment->data` is valid until the next call of this function or `mse_fmp4_converter_free`.
+///
+/// # Safety
+///
+/// `converter` must be a valid converter and `segment` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn mse_fmp4_converter_pull(
+    converter: *mut MseFmp4Converter,
+    segment: *mut MseFmp4Segment,
+) -> c_int {
+    let (converter, segment) = match (converter.as_mut(), segment.as_mut()) {
+        (Some(c), Some(s)) if c.output.is_some() => (c, s),
+        _ => return MSE_FMP4_ERROR_INVALID_ARGUMENT,
+    };
+    catch_panic(MSE_FMP4_ERROR_PANIC, || {
+        let output = converter.output.as_mut().expect("Never fails");
+        let (kind, bytes) = match converter.pulled {
+            0 => (
+                MseFmp4SegmentKind::Initialization,
+                std::mem::take(&mut output.initialization_segment),
+            ),
+            1 => (
+                MseFmp4SegmentKind::Media,
+                std::mem::take(&mut output.media_segment),
+            ),
+            _ => return 0,
+        };
+        converter.pulled += 1;
+        converter.current = bytes;
+        *segment = MseFmp4Segment {
+            kind,
+            data: converter.current.as_ptr(),
+            len: converter.current.len(),
+        };
+        1
+    })
+}
+
+/// Returns the MIME type (e.g., `video/mp4; codecs="avc1.640029, mp4a.40.2"`) of the converted segments.
+///
+/// Returns null if `mse_fmp4_converter_finish` has not succeeded yet.
+/// The returned string is owned by the converter.
+///
+/// # Safety
+///
+/// `converter` must be a valid converter.
+#[no_mangle]
+pub unsafe extern "C" fn mse_fmp4_converter_mime_type(
+    converter: *const MseFmp4Converter,
+) -> *const c_char {
+    catch_panic(ptr::null(), || {
+        match converter.as_ref().and_then(|c| c.mime_type.as_ref()) {
+            Some(mime_type) => mime_type.as_ptr(),
+            None => ptr::null(),
+        }
+    })
+}
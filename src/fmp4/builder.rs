@@ -0,0 +1,247 @@
+use crate::error::try_cast;
+use crate::fmp4::{
+    MediaDataBox, MediaSegment, MovieFragmentBox, MovieFragmentHeaderBox, Sample, SampleFlags,
+    TrackFragmentBox, TrackRunBox,
+};
+use crate::{ErrorKind, Result};
+
+/// Builder of a `MediaSegment` whose data offsets are filled in when the segment is built.
+///
+/// The `data_offset` fields of the `trun` boxes depend on the size of the `moof` box,
+/// which is not known until all the track fragments and runs are added.
+/// Instead of writing dummy offsets and patching them afterwards, this builder records where
+/// the sample data of each run is stored and sets the offsets in `finish`,
+/// so it never returns a segment whose offsets are left unset.
+///
+/// # Examples
+///
+/// ```
+/// use mse_fmp4::fmp4::{MediaSegmentBuilder, Sample, TrackFragmentBox};
+///
+/// let mut builder = MediaSegmentBuilder::new(1);
+/// let video = builder.add_track_fragment(TrackFragmentBox::new(true)).unwrap();
+/// let audio = builder.add_track_fragment(TrackFragmentBox::new(false)).unwrap();
+/// let sample = Sample { duration: Some(1), size: Some(2), ..Sample::default() };
+/// builder.add_run(video, None, vec![sample.clone()], vec![0, 1]).unwrap();
+/// builder.start_mdat();
+/// builder.add_run(audio, None, vec![sample], vec![2, 3]).unwrap();
+/// let segment = builder.finish().unwrap();
+///
+/// assert_eq!(segment.mdat_boxes.len(), 2);
+/// let data = segment.samples().unwrap().map(|s| s.unwrap().data.to_vec()).collect::<Vec<_>>();
+/// assert_eq!(data, [[0, 1], [2, 3]]);
+/// ```
+#[derive(Debug)]
+pub struct MediaSegmentBuilder {
+    sequence_number: u32,
+    traf_boxes: Vec<TrackFragmentBox>,
+    mdat_boxes: Vec<MediaDataBox>,
+
+    // `(traf_index, trun_index, mdat_index, position)`
+    runs: Vec<(usize, usize, usize, usize)>,
+}
+impl MediaSegmentBuilder {
+    /// Makes a new `MediaSegmentBuilder` instance for the segment of `sequence_number`.
+    pub fn new(sequence_number: u32) -> Self {
+        MediaSegmentBuilder {
+            sequence_number,
+            traf_boxes: Vec::new(),
+            mdat_boxes: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Makes a builder of the segment that has `traf_boxes` and `mdat_boxes`, in which the sample data of
+    /// the `trun` boxes are at `runs` (`(traf_index, trun_index, mdat_index, position)` of every `trun` box).
+    ///
+    /// As in `add_track_fragment`, the data offsets of the fragments are made relative to the `moof` box.
+    pub(crate) fn from_parts(
+        sequence_number: u32,
+        mut traf_boxes: Vec<TrackFragmentBox>,
+        mdat_boxes: Vec<MediaDataBox>,
+        runs: Vec<(usize, usize, usize, usize)>,
+    ) -> Self {
+        for traf in &mut traf_boxes {
+            traf.tfhd_box.base_data_offset = None;
+            traf.tfhd_box.default_base_is_moof = true;
+        }
+        MediaSegmentBuilder {
+            sequence_number,
+            traf_boxes,
+            mdat_boxes,
+            runs,
+        }
+    }
+
+    /// Makes a builder of `segment`, keeping its sample data where they are in the `mdat` boxes.
+    pub(crate) fn from_segment(segment: MediaSegment) -> Result<Self> {
+        let data_ranges = track!(segment.trun_data_ranges())?;
+        let payload_ranges = track!(segment.mdat_payload_ranges())?;
+        let mut runs = Vec::new();
+        for (traf_index, ranges) in data_ranges.into_iter().enumerate() {
+            for (trun_index, range) in ranges.into_iter().enumerate() {
+                let (mdat_index, payload) = track_assert_some!(
+                    payload_ranges
+                        .iter()
+                        .enumerate()
+                        .find(|(_, p)| p.start <= range.start && range.end <= p.end),
+                    ErrorKind::InvalidInput,
+                    "Samples out of `mdat` boxes: track_id={}, range={:?}",
+                    segment.moof_box.traf_boxes[traf_index].tfhd_box.track_id(),
+                    range
+                );
+                let position = (range.start - payload.start) as usize;
+                runs.push((traf_index, trun_index, mdat_index, position));
+            }
+        }
+        Ok(Self::from_parts(
+            segment.moof_box.mfhd_box.sequence_number,
+            segment.moof_box.traf_boxes,
+            segment.mdat_boxes,
+            runs,
+        ))
+    }
+
+    /// Adds a track fragment and returns its index, which is passed to `add_run`.
+    ///
+    /// `traf` must not have `trun` boxes (they are added by `add_run`).
+    /// The data offsets of the fragment are made relative to the `moof` box.
+    pub fn add_track_fragment(&mut self, mut traf: TrackFragmentBox) -> Result<usize> {
+        track_assert!(
+            traf.trun_boxes.is_empty(),
+            ErrorKind::InvalidInput,
+            "`trun` boxes need to be added by `add_run`: track_id={}",
+            traf.tfhd_box.track_id()
+        );
+        traf.tfhd_box.base_data_offset = None;
+        traf.tfhd_box.default_base_is_moof = true;
+        self.traf_boxes.push(traf);
+        Ok(self.traf_boxes.len() - 1)
+    }
+
+    /// Starts a new `mdat` box, to which the sample data of the following runs are appended.
+    ///
+    /// If this is never called, all the sample data are stored in a single `mdat` box.
+    pub fn start_mdat(&mut self) {
+        self.mdat_boxes.push(MediaDataBox { data: Vec::new() });
+    }
+
+    /// Adds a `trun` box that has `samples` to the `traf_index`-th track fragment
+    /// and appends their sample data `data` to the current `mdat` box.
+    ///
+    /// The size of `data` must equal the sum of the sample sizes
+    /// (the default sample size of the `tfhd` box is used for samples without sizes).
+    pub fn add_run(
+        &mut self,
+        traf_index: usize,
+        first_sample_flags: Option<SampleFlags>,
+        samples: Vec<Sample>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let traf = track_assert_some!(
+            self.traf_boxes.get_mut(traf_index),
+            ErrorKind::InvalidInput,
+            "No such track fragment: {}",
+            traf_index
+        );
+        let size = track!(run_size(traf, &samples))?;
+        track_assert_eq!(size, data.len() as u64, ErrorKind::InvalidInput);
+
+        let trun_index = traf.trun_boxes.len();
+        traf.trun_boxes.push(TrackRunBox {
+            data_offset: Some(0), // set by `finish`
+            first_sample_flags,
+            samples,
+        });
+        let (mdat_index, position) = self.append_data(data);
+        self.runs
+            .push((traf_index, trun_index, mdat_index, position));
+        Ok(())
+    }
+
+    /// Adds a track fragment that already has `trun` boxes and appends their sample data `data`
+    /// (in which the samples of the runs are laid out in order) to the current `mdat` box.
+    pub(crate) fn add_track_fragment_with_data(
+        &mut self,
+        mut traf: TrackFragmentBox,
+        data: Vec<u8>,
+    ) -> Result<usize> {
+        let trun_boxes = std::mem::take(&mut traf.trun_boxes);
+        let traf_index = track!(self.add_track_fragment(traf))?;
+        let data_size = data.len();
+        let (mdat_index, start) = self.append_data(data);
+        let mut position = start;
+        let traf = &mut self.traf_boxes[traf_index];
+        for mut trun in trun_boxes {
+            let size: usize = track!(try_cast(
+                track!(run_size(traf, &trun.samples))?,
+                "Sample data size"
+            ))?;
+            self.runs
+                .push((traf_index, traf.trun_boxes.len(), mdat_index, position));
+            trun.data_offset = Some(0); // set by `finish`
+            traf.trun_boxes.push(trun);
+            position += size;
+        }
+        track_assert!(
+            position - start <= data_size,
+            ErrorKind::InvalidInput,
+            "Too short sample data: track_id={}, size={}, expected={}",
+            traf.tfhd_box.track_id(),
+            data_size,
+            position - start
+        );
+        Ok(traf_index)
+    }
+
+    /// Builds the segment, setting the data offsets of all the `trun` boxes.
+    pub fn finish(self) -> Result<MediaSegment> {
+        let mut segment = MediaSegment {
+            moof_box: MovieFragmentBox {
+                mfhd_box: MovieFragmentHeaderBox {
+                    sequence_number: self.sequence_number,
+                },
+                traf_boxes: self.traf_boxes,
+            },
+            mdat_boxes: self.mdat_boxes,
+        };
+        let payload_ranges = track!(segment.mdat_payload_ranges())?;
+        for (traf_index, trun_index, mdat_index, position) in self.runs {
+            let offset = payload_ranges[mdat_index].start + position as u64;
+            segment.moof_box.traf_boxes[traf_index].trun_boxes[trun_index].data_offset =
+                Some(track!(try_cast(offset, "Data offset"))?);
+        }
+        Ok(segment)
+    }
+
+    /// Appends `data` to the current `mdat` box, and returns the index of the box and the position of `data` in it.
+    fn append_data(&mut self, data: Vec<u8>) -> (usize, usize) {
+        if self.mdat_boxes.is_empty() {
+            self.mdat_boxes.push(MediaDataBox { data: Vec::new() });
+        }
+        let mdat_index = self.mdat_boxes.len() - 1;
+        let mdat = &mut self.mdat_boxes[mdat_index];
+        let position = mdat.data.len();
+        if mdat.data.is_empty() {
+            mdat.data = data;
+        } else {
+            mdat.data.extend_from_slice(&data);
+        }
+        (mdat_index, position)
+    }
+}
+
+/// Returns the size of the sample data of `samples` in `traf`.
+fn run_size(traf: &TrackFragmentBox, samples: &[Sample]) -> Result<u64> {
+    let mut size = 0;
+    for sample in samples {
+        let sample_size = track_assert_some!(
+            sample.size.or(traf.tfhd_box.default_sample_size),
+            ErrorKind::InvalidInput,
+            "No sample size: track_id={}",
+            traf.tfhd_box.track_id()
+        );
+        size += u64::from(sample_size);
+    }
+    Ok(size)
+}
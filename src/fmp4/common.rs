@@ -1,4 +1,4 @@
-use crate::Result;
+use crate::{ErrorKind, Result};
 use std::io::Write;
 
 /// MP4 (ISO BMFF) box.
@@ -12,7 +12,14 @@ pub trait Mp4Box {
         if self.box_version().is_some() | self.box_flags().is_some() {
             size += 4;
         }
-        size += track!(self.box_payload_size())?;
+        let payload_size = track!(self.box_payload_size())?;
+        let size = track_assert_some!(
+            u32::checked_add(size, payload_size),
+            ErrorKind::SegmentTooLarge,
+            "box_type={:?}, payload_size={}",
+            String::from_utf8_lossy(&Self::BOX_TYPE),
+            payload_size
+        );
         Ok(size)
     }
 
@@ -52,3 +59,32 @@ pub trait Mp4Box {
     /// Writes the payload of the box to the given writer.
     fn write_box_payload<W: Write>(&self, writer: W) -> Result<()>;
 }
+
+/// Object-safe counterpart of `Mp4Box`.
+///
+/// `Mp4Box` cannot be made into a trait object because of its associated constant and generic methods.
+/// This trait is implemented for all `Mp4Box` types, so that boxes of different types
+/// can be held together (e.g., in a `Vec<Box<dyn DynMp4Box>>`).
+pub trait DynMp4Box {
+    /// Box type.
+    fn box_type(&self) -> [u8; 4];
+
+    /// Box size.
+    fn dyn_box_size(&self) -> Result<u32>;
+
+    /// Writes the box to the given writer.
+    fn write_dyn_box(&self, writer: &mut dyn Write) -> Result<()>;
+}
+impl<T: Mp4Box> DynMp4Box for T {
+    fn box_type(&self) -> [u8; 4] {
+        T::BOX_TYPE
+    }
+
+    fn dyn_box_size(&self) -> Result<u32> {
+        track!(self.box_size())
+    }
+
+    fn write_dyn_box(&self, writer: &mut dyn Write) -> Result<()> {
+        track!(self.write_box(writer))
+    }
+}
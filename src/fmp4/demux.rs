@@ -0,0 +1,104 @@
+use crate::aac::{self, AudioSpecificConfig};
+use crate::fmp4::{InitializationSegment, MediaSegment, SampleEntry};
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ByteOrder};
+
+/// Extracts the samples of the AVC track identified by `track_id` from `segment`
+/// as an H.264 byte stream (Annex B).
+///
+/// The length prefixes of the NAL units are replaced with start codes, and the SPS and PPS of
+/// the `avcC` box are inserted into the sync samples that lack them, so the output can be fed to decoders directly.
+pub fn demux_to_annexb(
+    initialization_segment: &InitializationSegment,
+    segment: &MediaSegment,
+    track_id: u32,
+) -> Result<Vec<u8>> {
+    let configuration = match *track!(sample_entry(initialization_segment, track_id))? {
+        SampleEntry::Avc(ref x) => &x.avcc_box.configuration,
+        _ => track_panic!(ErrorKind::Unsupported, "Not an AVC track: {}", track_id),
+    };
+
+    let mut stream = Vec::new();
+    for sample in track!(segment.samples())? {
+        let sample = track!(sample)?;
+        if sample.track_id != track_id {
+            continue;
+        }
+        let mut nal_units = Vec::new();
+        let mut bytes = sample.data;
+        while !bytes.is_empty() {
+            track_assert!(bytes.len() >= 4, ErrorKind::InvalidInput);
+            let size = BigEndian::read_u32(bytes) as usize;
+            track_assert!(bytes.len() - 4 >= size, ErrorKind::InvalidInput; size);
+            nal_units.push(&bytes[4..4 + size]);
+            bytes = &bytes[4 + size..];
+        }
+
+        let has_sps = nal_units
+            .iter()
+            .any(|nal| nal.first().is_some_and(|b| b & 0b1_1111 == 7));
+        if sample.is_sync && !has_sps {
+            write_nal_unit(&mut stream, &configuration.sequence_parameter_set);
+            write_nal_unit(&mut stream, &configuration.picture_parameter_set);
+        }
+        for nal in nal_units {
+            write_nal_unit(&mut stream, nal);
+        }
+    }
+    Ok(stream)
+}
+
+/// Extracts the samples of the AAC track identified by `track_id` from `segment` as ADTS frames.
+pub fn demux_to_adts(
+    initialization_segment: &InitializationSegment,
+    segment: &MediaSegment,
+    track_id: u32,
+) -> Result<Vec<u8>> {
+    let config = match *track!(sample_entry(initialization_segment, track_id))? {
+        SampleEntry::Aac(ref x) => AudioSpecificConfig {
+            profile: x.esds_box.profile,
+            sampling_frequency: x.esds_box.frequency,
+            channel_configuration: x.esds_box.channel_configuration,
+        },
+        _ => track_panic!(ErrorKind::Unsupported, "Not an AAC track: {}", track_id),
+    };
+
+    let mut stream = Vec::new();
+    for sample in track!(segment.samples())? {
+        let sample = track!(sample)?;
+        if sample.track_id == track_id {
+            track!(aac::write_adts_frame(&mut stream, &config, sample.data))?;
+        }
+    }
+    Ok(stream)
+}
+
+fn sample_entry(
+    initialization_segment: &InitializationSegment,
+    track_id: u32,
+) -> Result<&SampleEntry> {
+    let trak = initialization_segment
+        .moov_box
+        .trak_boxes
+        .iter()
+        .find(|t| t.tkhd_box.track_id() == track_id);
+    let trak = track_assert_some!(trak, ErrorKind::InvalidInput, "Unknown track: {}", track_id);
+    let entry = trak
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries
+        .first();
+    Ok(track_assert_some!(
+        entry,
+        ErrorKind::InvalidInput,
+        "No sample entry: track_id={}",
+        track_id
+    ))
+}
+
+fn write_nal_unit(stream: &mut Vec<u8>, nal_unit: &[u8]) {
+    stream.extend_from_slice(&[0, 0, 0, 1]);
+    stream.extend_from_slice(nal_unit);
+}
@@ -0,0 +1,245 @@
+use crate::io::ByteCounter;
+use crate::isobmff;
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::Write;
+
+/// MPEG-4 descriptor (ISO/IEC 14496-1).
+pub trait Descriptor {
+    /// Descriptor tag.
+    const DESCRIPTOR_TAG: u8;
+
+    /// Descriptor size (including the tag and the size field).
+    fn descriptor_size(&self) -> Result<u32> {
+        let payload_size = track!(self.descriptor_payload_size())?;
+        let size_field_len = track!(size_field_len(payload_size))?;
+        Ok(1 + size_field_len + payload_size)
+    }
+
+    /// Payload size of the descriptor.
+    fn descriptor_payload_size(&self) -> Result<u32> {
+        let size = track!(ByteCounter::calculate(|w| self.write_descriptor_payload(w)))?;
+        Ok(size as u32)
+    }
+
+    /// Writes the descriptor to the given writer.
+    ///
+    /// The size field is encoded in the minimal number of bytes.
+    fn write_descriptor<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u8!(writer, Self::DESCRIPTOR_TAG);
+
+        let payload_size = track!(self.descriptor_payload_size())?;
+        let size_field_len = track!(size_field_len(payload_size))?;
+        for i in (0..size_field_len).rev() {
+            let more = if i == 0 { 0 } else { 0x80 };
+            write_u8!(writer, more | ((payload_size >> (i * 7)) & 0x7F) as u8);
+        }
+
+        track!(self.write_descriptor_payload(writer))?;
+        Ok(())
+    }
+
+    /// Writes the payload of the descriptor to the given writer.
+    fn write_descriptor_payload<W: Write>(&self, writer: W) -> Result<()>;
+}
+
+/// Reads a descriptor header from `bytes` and returns the tag and the payload of the descriptor.
+fn read_descriptor<'a>(bytes: &mut &'a [u8]) -> Result<(u8, &'a [u8])> {
+    let tag = track_io!(bytes.read_u8())?;
+    let mut size = 0;
+    for _ in 0..4 {
+        let b = track_io!(bytes.read_u8())?;
+        size = (size << 7) | u32::from(b & 0x7F);
+        if b & 0x80 == 0 {
+            let size = size as usize;
+            track_assert!(size <= bytes.len(), ErrorKind::InvalidInput; tag, size, bytes.len());
+            let payload = &bytes[..size];
+            *bytes = &bytes[size..];
+            return Ok((tag, payload));
+        }
+    }
+    track_panic!(ErrorKind::InvalidInput, "Too long size field: tag={}", tag)
+}
+
+fn size_field_len(payload_size: u32) -> Result<u32> {
+    track_assert!(payload_size < (1 << 28), ErrorKind::InvalidInput; payload_size);
+    let mut len = 1;
+    while payload_size >> (len * 7) != 0 {
+        len += 1;
+    }
+    Ok(len)
+}
+
+/// 7.2.6.5 ES Descriptor (ISO/IEC 14496-1).
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct EsDescriptor {
+    pub es_id: u16,
+    pub stream_priority: u8, // u5
+    pub dec_config_descr: DecoderConfigDescriptor,
+    pub sl_config_descr: SlConfigDescriptor,
+}
+impl EsDescriptor {
+    /// Reads an `EsDescriptor` (including the tag and the size field) from the given bytes.
+    ///
+    /// Unknown child descriptors are ignored.
+    pub fn read_from(mut bytes: &[u8]) -> Result<Self> {
+        let (tag, mut payload) = track!(read_descriptor(&mut bytes))?;
+        track_assert_eq!(tag, Self::DESCRIPTOR_TAG, ErrorKind::InvalidInput);
+
+        let es_id = track_io!(payload.read_u16::<BigEndian>())?;
+        let flags = track_io!(payload.read_u8())?;
+        if flags & 0x80 != 0 {
+            track!(isobmff::skip_bytes(&mut payload, 2))?; // depends_on_es_id
+        }
+        if flags & 0x40 != 0 {
+            let url_len = track_io!(payload.read_u8())?;
+            track!(isobmff::skip_bytes(&mut payload, url_len as usize))?;
+        }
+        if flags & 0x20 != 0 {
+            track!(isobmff::skip_bytes(&mut payload, 2))?; // ocr_es_id
+        }
+
+        let mut dec_config_descr = None;
+        let mut sl_config_descr = SlConfigDescriptor::mp4();
+        while !payload.is_empty() {
+            let (tag, child) = track!(read_descriptor(&mut payload))?;
+            match tag {
+                DecoderConfigDescriptor::DESCRIPTOR_TAG => {
+                    dec_config_descr = Some(track!(DecoderConfigDescriptor::read_payload(child))?);
+                }
+                SlConfigDescriptor::DESCRIPTOR_TAG => {
+                    let predefined = track_io!({ child }.read_u8())?;
+                    sl_config_descr = SlConfigDescriptor { predefined };
+                }
+                _ => {}
+            }
+        }
+        let dec_config_descr = track_assert_some!(
+            dec_config_descr,
+            ErrorKind::InvalidInput,
+            "No decoder config descriptor"
+        );
+        Ok(EsDescriptor {
+            es_id,
+            stream_priority: flags & 0x1F,
+            dec_config_descr,
+            sl_config_descr,
+        })
+    }
+}
+impl Descriptor for EsDescriptor {
+    const DESCRIPTOR_TAG: u8 = 0x03;
+
+    fn write_descriptor_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(self.stream_priority <= 0x1F, ErrorKind::InvalidInput; self.stream_priority);
+        write_u16!(writer, self.es_id);
+        write_u8!(writer, self.stream_priority); // stream_dependence_flag=0, url_flag=0, ocr_stream_flag=0
+        track!(self.dec_config_descr.write_descriptor(&mut writer))?;
+        track!(self.sl_config_descr.write_descriptor(&mut writer))?;
+        Ok(())
+    }
+}
+
+/// 7.2.6.6 Decoder Config Descriptor (ISO/IEC 14496-1).
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct DecoderConfigDescriptor {
+    pub object_type_indication: u8,
+    pub stream_type: u8, // u6
+    pub up_stream: bool,
+    pub buffer_size_db: u32, // u24
+    pub max_bitrate: u32,
+    pub avg_bitrate: u32,
+    pub dec_specific_info: Option<DecoderSpecificInfo>,
+}
+impl DecoderConfigDescriptor {
+    fn read_payload(mut payload: &[u8]) -> Result<Self> {
+        let object_type_indication = track_io!(payload.read_u8())?;
+        let b = track_io!(payload.read_u8())?;
+        let buffer_size_db = track_io!(payload.read_u24::<BigEndian>())?;
+        let max_bitrate = track_io!(payload.read_u32::<BigEndian>())?;
+        let avg_bitrate = track_io!(payload.read_u32::<BigEndian>())?;
+
+        let mut dec_specific_info = None;
+        while !payload.is_empty() {
+            let (tag, child) = track!(read_descriptor(&mut payload))?;
+            if tag == DecoderSpecificInfo::DESCRIPTOR_TAG {
+                dec_specific_info = Some(DecoderSpecificInfo {
+                    data: child.to_owned(),
+                });
+            }
+        }
+        Ok(DecoderConfigDescriptor {
+            object_type_indication,
+            stream_type: b >> 2,
+            up_stream: (b & 0b10) != 0,
+            buffer_size_db,
+            max_bitrate,
+            avg_bitrate,
+            dec_specific_info,
+        })
+    }
+}
+impl Descriptor for DecoderConfigDescriptor {
+    const DESCRIPTOR_TAG: u8 = 0x04;
+
+    fn write_descriptor_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(self.stream_type <= 0x3F, ErrorKind::InvalidInput; self.stream_type);
+        track_assert!(self.buffer_size_db <= 0xFF_FFFF, ErrorKind::InvalidInput; self.buffer_size_db);
+        write_u8!(writer, self.object_type_indication);
+        write_u8!(
+            writer,
+            (self.stream_type << 2) | ((self.up_stream as u8) << 1) | 1 // reserved=1
+        );
+        write_u24!(writer, self.buffer_size_db);
+        write_u32!(writer, self.max_bitrate);
+        write_u32!(writer, self.avg_bitrate);
+        if let Some(ref x) = self.dec_specific_info {
+            track!(x.write_descriptor(&mut writer))?;
+        }
+        Ok(())
+    }
+}
+
+/// 7.2.6.7 Decoder Specific Info (ISO/IEC 14496-1).
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct DecoderSpecificInfo {
+    pub data: Vec<u8>,
+}
+impl Descriptor for DecoderSpecificInfo {
+    const DESCRIPTOR_TAG: u8 = 0x05;
+
+    fn descriptor_payload_size(&self) -> Result<u32> {
+        Ok(self.data.len() as u32)
+    }
+    fn write_descriptor_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_all!(writer, &self.data);
+        Ok(())
+    }
+}
+
+/// 7.3.2.3 SL Config Descriptor (ISO/IEC 14496-1).
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct SlConfigDescriptor {
+    pub predefined: u8,
+}
+impl SlConfigDescriptor {
+    /// Makes a new `SlConfigDescriptor` instance with the value reserved for MP4 files.
+    pub fn mp4() -> Self {
+        SlConfigDescriptor { predefined: 2 }
+    }
+}
+impl Descriptor for SlConfigDescriptor {
+    const DESCRIPTOR_TAG: u8 = 0x06;
+
+    fn descriptor_payload_size(&self) -> Result<u32> {
+        Ok(1)
+    }
+    fn write_descriptor_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u8!(writer, self.predefined);
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use crate::error::try_cast;
+use crate::fourcc;
+use crate::isobmff::{Boxes, RawBox};
+use crate::time::{MediaTime, Rounding};
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+/// Rewrites the durations of the serialized initialization segment `bytes` in place.
+///
+/// The durations of the `mvhd`, `mehd` (if any), `tkhd` and `mdhd` boxes are set to `duration`
+/// converted into their timescales (see also `InitializationSegment::mark_live`).
+/// The size of the segment does not change, so this can be applied to an initialization segment
+/// already written at the head of a file or an archive.
+///
+/// An error is returned if a duration does not fit in a version 0 box.
+pub fn patch_duration(bytes: &mut [u8], duration: Duration) -> Result<()> {
+    let mut patches = Vec::new();
+    let mut found = false;
+    for b in Boxes::new(bytes) {
+        let b = track!(b)?;
+        if b.box_type == fourcc::MOOV {
+            track!(collect_moov_patches(&b, duration, &mut patches))?;
+            found = true;
+        }
+    }
+    track_assert!(found, ErrorKind::InvalidInput, "No `moov` box");
+
+    for patch in patches {
+        if patch.is_64bit {
+            BigEndian::write_u64(&mut bytes[patch.position..], patch.value);
+        } else {
+            let value: u32 = track!(try_cast(patch.value, "Duration of a version 0 box"))?;
+            BigEndian::write_u32(&mut bytes[patch.position..], value);
+        }
+    }
+    Ok(())
+}
+
+/// Same as `patch_duration` except that the initialization segment is read from and written to `stream`.
+///
+/// The segment must start at the current position of `stream`.
+/// Only the `moov` box is read into memory and rewritten (the boxes before it are skipped),
+/// and the position after the call is unspecified.
+pub fn patch_duration_in<S: Read + Write + Seek>(mut stream: S, duration: Duration) -> Result<()> {
+    loop {
+        let start = track_io!(stream.stream_position())?;
+        let size = track_io!(stream.read_u32::<BigEndian>())?;
+        let mut box_type = [0; 4];
+        track_io!(stream.read_exact(&mut box_type))?;
+        let size = match size {
+            0 => track_panic!(
+                ErrorKind::InvalidInput,
+                "Unbounded box before the `moov` box"
+            ),
+            1 => track_io!(stream.read_u64::<BigEndian>())?,
+            _ => u64::from(size),
+        };
+        if box_type != fourcc::MOOV {
+            let next = track_assert_some!(start.checked_add(size), ErrorKind::InvalidInput);
+            track_io!(stream.seek(SeekFrom::Start(next)))?;
+            continue;
+        }
+
+        track_io!(stream.seek(SeekFrom::Start(start)))?;
+        let size: usize = track!(try_cast(size, "Size of the `moov` box"))?;
+        let mut moov = vec![0; size];
+        track_io!(stream.read_exact(&mut moov))?;
+        track!(patch_duration(&mut moov, duration))?;
+        track_io!(stream.seek(SeekFrom::Start(start)))?;
+        track_io!(stream.write_all(&moov))?;
+        return Ok(());
+    }
+}
+
+/// Duration field to be rewritten by `patch_duration`.
+#[derive(Debug)]
+struct Patch {
+    position: usize,
+    is_64bit: bool,
+    value: u64,
+}
+
+fn collect_moov_patches(moov: &RawBox, duration: Duration, patches: &mut Vec<Patch>) -> Result<()> {
+    let moov_start = payload_start(0, moov);
+    let mut movie_timescale = None;
+    let mut movie_patches = Vec::new(); // patches in the movie timescale
+    for child in moov.children() {
+        let child = track!(child)?;
+        let start = payload_start(moov_start, &child);
+        match child.box_type {
+            fourcc::MVHD => {
+                // creation_time, modification_time, timescale, duration
+                let (timescale_offset, duration_offset) = if track!(is_version_1(&child))? {
+                    (20, 24)
+                } else {
+                    (12, 16)
+                };
+                movie_timescale = Some(track!(read_u32_at(&child, timescale_offset))?);
+                movie_patches.push(track!(field(&child, start, duration_offset))?);
+            }
+            fourcc::MVEX => {
+                for mehd in child.children() {
+                    let mehd = track!(mehd)?;
+                    if mehd.box_type == fourcc::MEHD {
+                        let start = payload_start(start, &mehd);
+                        movie_patches.push(track!(field(&mehd, start, 4))?);
+                    }
+                }
+            }
+            fourcc::TRAK => {
+                for trak_child in child.children() {
+                    let trak_child = track!(trak_child)?;
+                    let trak_child_start = payload_start(start, &trak_child);
+                    match trak_child.box_type {
+                        fourcc::TKHD => {
+                            // creation_time, modification_time, track_ID, reserved, duration
+                            let offset = if track!(is_version_1(&trak_child))? {
+                                28
+                            } else {
+                                20
+                            };
+                            movie_patches.push(track!(field(
+                                &trak_child,
+                                trak_child_start,
+                                offset
+                            ))?);
+                        }
+                        fourcc::MDIA => {
+                            track!(collect_mdia_patches(
+                                &trak_child,
+                                trak_child_start,
+                                duration,
+                                patches
+                            ))?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let movie_timescale =
+        track_assert_some!(movie_timescale, ErrorKind::InvalidInput, "No `mvhd` box");
+    let value = MediaTime::from_duration(duration, movie_timescale, Rounding::Nearest).value;
+    for mut patch in movie_patches {
+        patch.value = value;
+        patches.push(patch);
+    }
+    Ok(())
+}
+
+fn collect_mdia_patches(
+    mdia: &RawBox,
+    mdia_start: usize,
+    duration: Duration,
+    patches: &mut Vec<Patch>,
+) -> Result<()> {
+    for mdhd in mdia.children() {
+        let mdhd = track!(mdhd)?;
+        if mdhd.box_type != fourcc::MDHD {
+            continue;
+        }
+        // creation_time, modification_time, timescale, duration
+        let (timescale_offset, duration_offset) = if track!(is_version_1(&mdhd))? {
+            (20, 24)
+        } else {
+            (12, 16)
+        };
+        let timescale = track!(read_u32_at(&mdhd, timescale_offset))?;
+        let mut patch = track!(field(
+            &mdhd,
+            payload_start(mdia_start, &mdhd),
+            duration_offset
+        ))?;
+        patch.value = MediaTime::from_duration(duration, timescale, Rounding::Nearest).value;
+        patches.push(patch);
+    }
+    Ok(())
+}
+
+/// Returns the absolute position of the payload of `b` whose parent payload starts at `parent_start`.
+fn payload_start(parent_start: usize, b: &RawBox) -> usize {
+    parent_start + b.offset + b.header_size
+}
+
+fn is_version_1(b: &RawBox) -> Result<bool> {
+    Ok(track!(b.full_box())?.version == 1)
+}
+
+fn read_u32_at(b: &RawBox, offset: usize) -> Result<u32> {
+    track_assert!(b.payload.len() >= offset + 4, ErrorKind::InvalidInput);
+    Ok(BigEndian::read_u32(&b.payload[offset..]))
+}
+
+/// Returns the duration field at `offset` in the payload of the full box `b` (the value is set later).
+fn field(b: &RawBox, payload_start: usize, offset: usize) -> Result<Patch> {
+    let is_64bit = track!(is_version_1(b))?;
+    let size = if is_64bit { 8 } else { 4 };
+    track_assert!(b.payload.len() >= offset + size, ErrorKind::InvalidInput);
+    Ok(Patch {
+        position: payload_start + offset,
+        is_64bit,
+        value: 0,
+    })
+}
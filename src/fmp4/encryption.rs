@@ -0,0 +1,487 @@
+use crate::fmp4::{
+    InitializationSegment, MediaSegment, Mp4Box, SampleEncryptionEntry, SampleGroupDescriptionBox,
+    SampleToGroupBox, SampleToGroupEntry, TrackFragmentBox,
+};
+use crate::fourcc;
+use crate::isobmff::RawBox;
+use crate::time::{MediaTime, Rounding};
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::cmp;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::time::Duration;
+
+/// 8.1 Protection System Specific Header Box (ISO/IEC 23001-7).
+///
+/// The box is written in version 1 if it has key identifiers, otherwise in version 0.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtectionSystemSpecificHeaderBox {
+    pub system_id: [u8; 16],
+    pub key_ids: Vec<[u8; 16]>,
+
+    /// System specific data (passed to the CDM as is).
+    pub data: Vec<u8>,
+}
+impl ProtectionSystemSpecificHeaderBox {
+    /// System identifier of the W3C Common PSSH box format (`1077efec-c0b2-4d02-ace3-3c1e52e2fb4b`).
+    pub const COMMON_SYSTEM_ID: [u8; 16] = [
+        0x10, 0x77, 0xef, 0xec, 0xc0, 0xb2, 0x4d, 0x02, 0xac, 0xe3, 0x3c, 0x1e, 0x52, 0xe2, 0xfb,
+        0x4b,
+    ];
+
+    /// System identifier of Widevine (`edef8ba9-79d6-4ace-a3c8-27dcd51d21ed`).
+    pub const WIDEVINE_SYSTEM_ID: [u8; 16] = [
+        0xed, 0xef, 0x8b, 0xa9, 0x79, 0xd6, 0x4a, 0xce, 0xa3, 0xc8, 0x27, 0xdc, 0xd5, 0x1d, 0x21,
+        0xed,
+    ];
+
+    /// System identifier of PlayReady (`9a04f079-9840-4286-ab92-e65be0885f95`).
+    pub const PLAYREADY_SYSTEM_ID: [u8; 16] = [
+        0x9a, 0x04, 0xf0, 0x79, 0x98, 0x40, 0x42, 0x86, 0xab, 0x92, 0xe6, 0x5b, 0xe0, 0x88, 0x5f,
+        0x95,
+    ];
+
+    /// System identifier of FairPlay (`94ce86fb-07ff-4f43-adb8-93d2fa968ca2`).
+    pub const FAIRPLAY_SYSTEM_ID: [u8; 16] = [
+        0x94, 0xce, 0x86, 0xfb, 0x07, 0xff, 0x4f, 0x43, 0xad, 0xb8, 0x93, 0xd2, 0xfa, 0x96, 0x8c,
+        0xa2,
+    ];
+
+    /// Makes a box of the W3C Common PSSH box format, which only lists the key identifiers
+    /// (e.g., for Clear Key).
+    pub fn common(key_ids: &[[u8; 16]]) -> Self {
+        ProtectionSystemSpecificHeaderBox {
+            system_id: Self::COMMON_SYSTEM_ID,
+            key_ids: key_ids.to_vec(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Makes a Widevine box whose data is a `WidevinePsshData` message that has
+    /// `key_ids` and `content_id` (omitted if empty).
+    pub fn widevine(key_ids: &[[u8; 16]], content_id: &[u8]) -> Self {
+        // Fields of the protobuf message: `key_id` (2) and `content_id` (4)
+        let mut data = Vec::new();
+        for key_id in key_ids {
+            data.push(0x12);
+            write_varint(&mut data, key_id.len() as u64);
+            data.extend_from_slice(key_id);
+        }
+        if !content_id.is_empty() {
+            data.push(0x22);
+            write_varint(&mut data, content_id.len() as u64);
+            data.extend_from_slice(content_id);
+        }
+        ProtectionSystemSpecificHeaderBox {
+            system_id: Self::WIDEVINE_SYSTEM_ID,
+            key_ids: key_ids.to_vec(),
+            data,
+        }
+    }
+
+    /// Makes a PlayReady box whose data is `playready_object` (a PlayReady Header Object made by the license server).
+    pub fn playready(key_ids: &[[u8; 16]], playready_object: Vec<u8>) -> Self {
+        ProtectionSystemSpecificHeaderBox {
+            system_id: Self::PLAYREADY_SYSTEM_ID,
+            key_ids: key_ids.to_vec(),
+            data: playready_object,
+        }
+    }
+
+    /// Makes a FairPlay box that has `data` as is.
+    pub fn fairplay(key_ids: &[[u8; 16]], data: Vec<u8>) -> Self {
+        ProtectionSystemSpecificHeaderBox {
+            system_id: Self::FAIRPLAY_SYSTEM_ID,
+            key_ids: key_ids.to_vec(),
+            data,
+        }
+    }
+
+    pub(crate) fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let mut system_id = [0; 16];
+        track_io!(reader.read_exact(&mut system_id))?;
+        let mut key_ids = Vec::new();
+        if full_box.version > 0 {
+            let key_id_count = track_io!(reader.read_u32::<BigEndian>())?;
+            track_assert!(
+                u64::from(key_id_count) * 16 <= reader.len() as u64,
+                ErrorKind::InvalidInput;
+                key_id_count
+            );
+            for _ in 0..key_id_count {
+                let mut key_id = [0; 16];
+                track_io!(reader.read_exact(&mut key_id))?;
+                key_ids.push(key_id);
+            }
+        }
+        let data_size = track_io!(reader.read_u32::<BigEndian>())? as usize;
+        track_assert!(data_size <= reader.len(), ErrorKind::InvalidInput; data_size, reader.len());
+        Ok(ProtectionSystemSpecificHeaderBox {
+            system_id,
+            key_ids,
+            data: reader[..data_size].to_vec(),
+        })
+    }
+}
+impl Mp4Box for ProtectionSystemSpecificHeaderBox {
+    const BOX_TYPE: [u8; 4] = fourcc::PSSH;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(if self.key_ids.is_empty() { 0 } else { 1 })
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 16 + 4 + self.data.len() as u32;
+        if !self.key_ids.is_empty() {
+            size += 4 + 16 * self.key_ids.len() as u32;
+        }
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_all!(writer, &self.system_id);
+        if !self.key_ids.is_empty() {
+            write_u32!(writer, self.key_ids.len() as u32);
+            for key_id in &self.key_ids {
+                write_all!(writer, key_id);
+            }
+        }
+        write_u32!(writer, self.data.len() as u32);
+        write_all!(writer, &self.data);
+        Ok(())
+    }
+}
+
+/// 6 CENC Sample Encryption Information Sample Group Entry (ISO/IEC 23001-7).
+///
+/// An entry of a `seig` sample group, which overrides the default encryption parameters
+/// of the `tenc` box for the samples in the group (e.g., to rotate the keys).
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CencSampleEncryptionInformationGroupEntry {
+    /// Number of the encrypted blocks in each pattern (`0` if pattern encryption is not used).
+    pub crypt_byte_block: u8,
+
+    /// Number of the unencrypted blocks in each pattern (`0` if pattern encryption is not used).
+    pub skip_byte_block: u8,
+
+    /// If `false`, the samples in the group are not encrypted (e.g., for a clear lead).
+    pub is_protected: bool,
+
+    /// Size of the per-sample initialization vectors in the `senc` box (`0`, `8` or `16`).
+    pub per_sample_iv_size: u8,
+
+    pub key_id: [u8; 16],
+
+    /// Initialization vector shared by the samples
+    /// (written only if `is_protected` is `true` and `per_sample_iv_size` is `0`).
+    pub constant_iv: Vec<u8>,
+}
+impl CencSampleEncryptionInformationGroupEntry {
+    /// Makes an entry of the samples that are not encrypted.
+    pub fn unprotected() -> Self {
+        CencSampleEncryptionInformationGroupEntry {
+            crypt_byte_block: 0,
+            skip_byte_block: 0,
+            is_protected: false,
+            per_sample_iv_size: 0,
+            key_id: [0; 16],
+            constant_iv: Vec::new(),
+        }
+    }
+
+    /// Reads an entry from the payload of a `seig` sample group entry.
+    pub fn read_from(bytes: &[u8]) -> Result<Self> {
+        track_assert!(bytes.len() >= 20, ErrorKind::InvalidInput; bytes.len());
+        let mut key_id = [0; 16];
+        key_id.copy_from_slice(&bytes[4..20]);
+        let mut entry = CencSampleEncryptionInformationGroupEntry {
+            crypt_byte_block: bytes[1] >> 4,
+            skip_byte_block: bytes[1] & 0x0F,
+            is_protected: bytes[2] != 0,
+            per_sample_iv_size: bytes[3],
+            key_id,
+            constant_iv: Vec::new(),
+        };
+        if entry.has_constant_iv() {
+            let iv_size = usize::from(*track_assert_some!(bytes.get(20), ErrorKind::InvalidInput));
+            track_assert!(bytes.len() >= 21 + iv_size, ErrorKind::InvalidInput; bytes.len(), iv_size);
+            entry.constant_iv = bytes[21..21 + iv_size].to_vec();
+        }
+        Ok(entry)
+    }
+
+    /// Serializes this entry as the payload of a `seig` sample group entry.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        track_assert!(
+            self.crypt_byte_block < 0x10 && self.skip_byte_block < 0x10,
+            ErrorKind::InvalidInput;
+            self.crypt_byte_block, self.skip_byte_block
+        );
+        let mut bytes = vec![
+            0,
+            (self.crypt_byte_block << 4) | self.skip_byte_block,
+            self.is_protected as u8,
+            self.per_sample_iv_size,
+        ];
+        bytes.extend_from_slice(&self.key_id);
+        if self.has_constant_iv() {
+            track_assert!(
+                self.constant_iv.len() <= usize::from(u8::MAX),
+                ErrorKind::InvalidInput;
+                self.constant_iv.len()
+            );
+            bytes.push(self.constant_iv.len() as u8);
+            bytes.extend_from_slice(&self.constant_iv);
+        }
+        Ok(bytes)
+    }
+
+    fn has_constant_iv(&self) -> bool {
+        self.is_protected && self.per_sample_iv_size == 0
+    }
+}
+
+/// Period during which the samples of a track are encrypted with the same parameters (see `KeyProvider`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPeriod {
+    /// Decode times of the samples in the period (in the timescale of the track).
+    pub range: Range<u64>,
+
+    /// Encryption parameters (e.g., the key identifier) of the samples in the period.
+    pub entry: CencSampleEncryptionInformationGroupEntry,
+}
+
+/// Provider of the keys used by `apply_key_rotation`.
+///
+/// This is implemented for closures of the same signature as `key_period`.
+pub trait KeyProvider {
+    /// Returns the key period of the track `track_id` that contains `decode_time`
+    /// (in the timescale of the track).
+    fn key_period(&mut self, track_id: u32, decode_time: u64) -> Result<KeyPeriod>;
+}
+impl<F> KeyProvider for F
+where
+    F: FnMut(u32, u64) -> Result<KeyPeriod>,
+{
+    fn key_period(&mut self, track_id: u32, decode_time: u64) -> Result<KeyPeriod> {
+        self(track_id, decode_time)
+    }
+}
+
+/// Key provider that leaves the beginning of each track unencrypted (i.e., a clear lead),
+/// and takes the keys of the rest from another provider.
+///
+/// The samples decoded before `lead` (from the decode time zero of the track) are
+/// in an unprotected key period (see `CencSampleEncryptionInformationGroupEntry::unprotected`),
+/// and the key periods of the other provider are clipped so that they start at the end of the lead.
+#[derive(Debug)]
+pub struct ClearLead<P> {
+    lead_ends: Vec<(u32, u64)>, // (track_id, the end of the lead in the timescale of the track)
+    provider: P,
+}
+impl<P: KeyProvider> ClearLead<P> {
+    /// Makes a new `ClearLead` instance for the tracks of `initialization_segment`.
+    ///
+    /// The end of the lead is rounded up to the timescale of each track.
+    pub fn new(
+        initialization_segment: &InitializationSegment,
+        lead: Duration,
+        provider: P,
+    ) -> Self {
+        let lead_ends = initialization_segment
+            .moov_box
+            .trak_boxes
+            .iter()
+            .map(|t| {
+                let timescale = t.mdia_box.mdhd_box.timescale;
+                let end = MediaTime::from_duration(lead, timescale, Rounding::Up).value;
+                (t.tkhd_box.track_id(), end)
+            })
+            .collect();
+        ClearLead {
+            lead_ends,
+            provider,
+        }
+    }
+}
+impl<P: KeyProvider> KeyProvider for ClearLead<P> {
+    fn key_period(&mut self, track_id: u32, decode_time: u64) -> Result<KeyPeriod> {
+        let lead_end = self
+            .lead_ends
+            .iter()
+            .find(|t| t.0 == track_id)
+            .map_or(0, |t| t.1);
+        if decode_time < lead_end {
+            return Ok(KeyPeriod {
+                range: 0..lead_end,
+                entry: CencSampleEncryptionInformationGroupEntry::unprotected(),
+            });
+        }
+        let mut period = track!(self.provider.key_period(track_id, decode_time))?;
+        period.range.start = cmp::max(period.range.start, lead_end);
+        Ok(period)
+    }
+}
+
+/// Signals the keys of the samples of `track_ids` in `segment` by `seig` sample groups (i.e., key rotation).
+///
+/// The samples of each track fragment are grouped by the key periods returned by `provider`,
+/// and the fragment gets a `sgpd` box that has an entry for each distinct period and
+/// a `sbgp` box that maps the samples to the entries.
+/// The `seig` sample groups that the fragments already have are replaced.
+///
+/// Only the signaling is written: the sample data and the `senc` boxes must be made
+/// with the same parameters (e.g., by an external encryptor).
+/// If a `senc` box has entries only for the protected samples (e.g., after a clear lead),
+/// empty entries are inserted for the unprotected ones, so that the box covers all of the samples.
+pub fn apply_key_rotation<P: KeyProvider>(
+    segment: &mut MediaSegment,
+    track_ids: &[u32],
+    provider: &mut P,
+) -> Result<()> {
+    let old_moof_size = track!(segment.moof_box.box_size())?;
+    for traf in &mut segment.moof_box.traf_boxes {
+        if track_ids.contains(&traf.tfhd_box.track_id()) {
+            track!(rotate_keys(traf, provider))?;
+        }
+    }
+    track!(segment.shift_data_offsets(old_moof_size))
+}
+
+fn rotate_keys<P: KeyProvider>(traf: &mut TrackFragmentBox, provider: &mut P) -> Result<()> {
+    let track_id = traf.tfhd_box.track_id();
+    let mut entries = Vec::new();
+    let mut groups: Vec<SampleToGroupEntry> = Vec::new();
+    let mut period: Option<(Range<u64>, u32)> = None;
+    let mut protected = Vec::new();
+    let mut decode_time = traf.tfdt_box.base_media_decode_time;
+    for sample in traf.trun_boxes.iter().flat_map(|t| t.samples.iter()) {
+        let index = match period {
+            Some((ref range, index)) if range.contains(&decode_time) => index,
+            _ => {
+                let next = track!(provider.key_period(track_id, decode_time))?;
+                track_assert!(
+                    next.range.contains(&decode_time),
+                    ErrorKind::InvalidInput,
+                    "The key period {:?} does not contain {}: track_id={}",
+                    next.range,
+                    decode_time,
+                    track_id
+                );
+                let index = match entries.iter().position(|e| *e == next.entry) {
+                    Some(i) => i,
+                    None => {
+                        entries.push(next.entry);
+                        entries.len() - 1
+                    }
+                };
+                // Entries local to the fragment are indexed from 0x10001
+                let index = 0x1_0001 + index as u32;
+                period = Some((next.range, index));
+                index
+            }
+        };
+        protected.push(entries[(index - 0x1_0001) as usize].is_protected);
+        match groups.last_mut() {
+            Some(group) if group.group_description_index == index => group.sample_count += 1,
+            _ => groups.push(SampleToGroupEntry {
+                sample_count: 1,
+                group_description_index: index,
+            }),
+        }
+
+        let duration = track_assert_some!(
+            sample.duration.or(traf.tfhd_box.default_sample_duration),
+            ErrorKind::InvalidInput,
+            "No sample duration: track_id={}",
+            track_id
+        );
+        decode_time += u64::from(duration);
+    }
+
+    if let Some(senc) = traf.senc_box.as_mut() {
+        if senc.entries.len() != protected.len() {
+            let protected_count = protected.iter().filter(|&&p| p).count();
+            track_assert_eq!(senc.entries.len(), protected_count, ErrorKind::InvalidInput);
+            let mut protected_entries = std::mem::take(&mut senc.entries).into_iter();
+            senc.entries = protected
+                .iter()
+                .map(|&p| {
+                    if p {
+                        protected_entries.next().expect("Never fails")
+                    } else {
+                        SampleEncryptionEntry::default()
+                    }
+                })
+                .collect();
+        }
+    }
+
+    traf.sbgp_boxes.retain(|b| b.grouping_type != fourcc::SEIG);
+    traf.sgpd_boxes.retain(|b| b.grouping_type != fourcc::SEIG);
+    if groups.is_empty() {
+        return Ok(());
+    }
+    traf.sbgp_boxes.push(SampleToGroupBox {
+        grouping_type: fourcc::SEIG,
+        entries: groups,
+    });
+    traf.sgpd_boxes.push(SampleGroupDescriptionBox {
+        grouping_type: fourcc::SEIG,
+        entries: track!(entries
+            .iter()
+            .map(CencSampleEncryptionInformationGroupEntry::to_bytes)
+            .collect::<Result<Vec<_>>>())?,
+    });
+    Ok(())
+}
+
+/// Returns the per-sample IV sizes of the `sample_count` samples in a track fragment given by its `seig` sample group.
+///
+/// `None` is returned if the fragment has no such group, the group does not cover exactly the samples, or
+/// some samples are not in the entries of the fragment
+/// (i.e., their sizes are given by the `tenc` box or the sample groups of the `moov` box).
+pub(crate) fn seig_iv_sizes(
+    sbgp_boxes: &[SampleToGroupBox],
+    sgpd_boxes: &[SampleGroupDescriptionBox],
+    sample_count: usize,
+) -> Result<Option<Vec<usize>>> {
+    let sbgp = sbgp_boxes.iter().find(|b| b.grouping_type == fourcc::SEIG);
+    let sgpd = sgpd_boxes.iter().find(|b| b.grouping_type == fourcc::SEIG);
+    let (sbgp, sgpd) = match (sbgp, sgpd) {
+        (Some(sbgp), Some(sgpd)) => (sbgp, sgpd),
+        _ => return Ok(None),
+    };
+    let mut iv_sizes = Vec::new();
+    for group in &sbgp.entries {
+        let entry = match group
+            .group_description_index
+            .checked_sub(0x1_0001)
+            .and_then(|i| sgpd.entries.get(i as usize))
+        {
+            None => return Ok(None),
+            Some(entry) => track!(CencSampleEncryptionInformationGroupEntry::read_from(entry))?,
+        };
+        if group.sample_count as usize > sample_count - iv_sizes.len() {
+            return Ok(None);
+        }
+        let iv_size = usize::from(entry.per_sample_iv_size);
+        iv_sizes.extend((0..group.sample_count).map(|_| iv_size));
+    }
+    if iv_sizes.len() != sample_count {
+        return Ok(None);
+    }
+    Ok(Some(iv_sizes))
+}
+
+/// Writes `n` as a protobuf varint.
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
@@ -1,10 +1,20 @@
-use crate::aac::{AacProfile, ChannelConfiguration, SamplingFrequency};
+use crate::aac::{AacProfile, AudioSpecificConfig, ChannelConfiguration, SamplingFrequency};
+use crate::av1::Av1CodecConfigurationRecord;
 use crate::avc::AvcDecoderConfigurationRecord;
-use crate::fmp4::{Mp4Box, AUDIO_TRACK_ID, VIDEO_TRACK_ID};
+use crate::error::try_cast;
+use crate::fmp4::{
+    DecoderConfigDescriptor, DecoderSpecificInfo, Descriptor, DynMp4Box, EsDescriptor, Mp4Box,
+    ProtectionSystemSpecificHeaderBox, SlConfigDescriptor, AUDIO_TRACK_ID, TEXT_TRACK_ID,
+    VIDEO_TRACK_ID,
+};
+use crate::fourcc;
 use crate::io::{ByteCounter, WriteTo};
+use crate::isobmff::{self, RawBox};
+use crate::vp9::VpCodecConfigurationRecord;
 use crate::{ErrorKind, Result};
-use std::ffi::CString;
-use std::io::Write;
+use byteorder::{BigEndian, ReadBytesExt};
+use std::ffi::{CStr, CString};
+use std::io::{Read, Write};
 
 /// [3. Initialization Segments][init_segment] (ISO BMFF Byte Stream Format)
 ///
@@ -16,10 +26,198 @@ pub struct InitializationSegment {
     pub moov_box: MovieBox,
 }
 impl InitializationSegment {
-    /// Returns MIME type.
+    /// Returns MIME type (e.g., `video/mp4; codecs="avc1.640029, mp4a.40.2"`).
+    ///
+    /// The type is `audio/mp4` if the segment contains no video tracks.
     pub fn mime_type(&self) -> String {
-        // FIXME
-        r#"video/mp4; "avc1.640029, mp4a.40.2""#.to_string()
+        let has_video = self
+            .moov_box
+            .trak_boxes
+            .iter()
+            .any(|t| t.mdia_box.hdlr_box.handler_type == fourcc::VIDE);
+        format!(
+            r#"{}/mp4; codecs="{}""#,
+            if has_video { "video" } else { "audio" },
+            self.codecs().join(", ")
+        )
+    }
+
+    /// Returns the codec strings of the sample entries in the order of the tracks.
+    pub fn codecs(&self) -> Vec<String> {
+        self.sample_entries().map(|e| e.codec_string()).collect()
+    }
+
+    /// Returns the AVC decoder configuration of the first AVC track.
+    pub fn video_config(&self) -> Option<&AvcDecoderConfigurationRecord> {
+        self.sample_entries().find_map(|e| match *e {
+            SampleEntry::Avc(ref x) => Some(&x.avcc_box.configuration),
+            _ => None,
+        })
+    }
+
+    /// Returns the ES descriptor (AAC profile, sampling frequency and channel configuration) of the first AAC track.
+    pub fn audio_config(&self) -> Option<&Mpeg4EsDescriptorBox> {
+        self.sample_entries().find_map(|e| match *e {
+            SampleEntry::Aac(ref x) => Some(&x.esds_box),
+            _ => None,
+        })
+    }
+
+    /// Returns the width and height (in pixels) of the first video track.
+    pub fn resolution(&self) -> Option<(u16, u16)> {
+        self.sample_entries().find_map(|e| match *e {
+            SampleEntry::Avc(ref x) => Some((x.width, x.height)),
+            SampleEntry::Vp9(ref x) => Some((x.width, x.height)),
+            SampleEntry::Av1(ref x) => Some((x.width, x.height)),
+            SampleEntry::Aac(_) | SampleEntry::WebVtt(_) => None,
+            SampleEntry::Custom(ref x) => x.resolution(),
+        })
+    }
+
+    /// Returns the sampling rate (in Hz) of the first audio track.
+    pub fn audio_sample_rate(&self) -> Option<u32> {
+        self.sample_entries().find_map(|e| match *e {
+            SampleEntry::Aac(ref x) => Some(match x.srat_box {
+                Some(ref srat) => srat.sampling_rate,
+                None => x.esds_box.frequency.as_u32(),
+            }),
+            _ => None,
+        })
+    }
+
+    fn sample_entries(&self) -> impl Iterator<Item = &SampleEntry> {
+        self.moov_box
+            .trak_boxes
+            .iter()
+            .flat_map(|t| t.mdia_box.minf_box.stbl_box.stsd_box.sample_entries.iter())
+    }
+
+    /// Returns `true` if media segments made for `other` can be appended to a source buffer
+    /// initialized with `self` (i.e., neither `changeType()` nor a new initialization segment is needed).
+    ///
+    /// Track identifiers, timescales, handler types and sample descriptions
+    /// (including the codec configurations) are compared.
+    pub fn is_compatible_with(&self, other: &Self) -> Result<bool> {
+        let a = &self.moov_box;
+        let b = &other.moov_box;
+        if a.mvhd_box.timescale != b.mvhd_box.timescale || a.trak_boxes.len() != b.trak_boxes.len()
+        {
+            return Ok(false);
+        }
+        for (a, b) in a.trak_boxes.iter().zip(b.trak_boxes.iter()) {
+            if !track!(a.is_compatible_with(b))? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Splits this segment into the initialization segments each of which has only one of the tracks
+    /// (e.g., for players that feed each track to its own `SourceBuffer`).
+    ///
+    /// The segments are returned in the order of the tracks.
+    /// The movie header and the encoder identification are copied to every segment,
+    /// and the `meta` box (if any) is kept only in the first one.
+    pub fn into_track_segments(self) -> Result<Vec<Self>> {
+        let ftyp_box = self.ftyp_box;
+        let moov = self.moov_box;
+        let mut trex_boxes = moov.mvex_box.trex_boxes;
+        let mut meta_box = moov.meta_box;
+        let mut segments = Vec::with_capacity(moov.trak_boxes.len());
+        for trak in moov.trak_boxes {
+            let track_id = trak.tkhd_box.track_id();
+            let i = track_assert_some!(
+                trex_boxes.iter().position(|t| t.track_id == track_id),
+                ErrorKind::InvalidInput,
+                "No `trex` box: track_id={}",
+                track_id
+            );
+            let trex = trex_boxes.swap_remove(i);
+            segments.push(InitializationSegment {
+                ftyp_box: ftyp_box.clone(),
+                moov_box: MovieBox {
+                    mvhd_box: MovieHeaderBox {
+                        timescale: moov.mvhd_box.timescale,
+                        duration: moov.mvhd_box.duration,
+                    },
+                    trak_boxes: vec![trak],
+                    mvex_box: MovieExtendsBox {
+                        mehd_box: moov.mvex_box.mehd_box.as_ref().map(|mehd| {
+                            MovieExtendsHeaderBox {
+                                fragment_duration: mehd.fragment_duration,
+                            }
+                        }),
+                        trex_boxes: vec![trex],
+                    },
+                    pssh_boxes: moov.pssh_boxes.clone(),
+                    meta_box: meta_box.take(),
+                    free_box: moov.free_box.clone(),
+                },
+            });
+        }
+        Ok(segments)
+    }
+
+    /// Marks this segment as that of a live stream whose duration is not known yet.
+    ///
+    /// The durations of the `mvhd`, `tkhd` and `mdhd` boxes are set to zero, and the `mehd` box is removed.
+    /// The durations can be written into the serialized segment later by `patch_duration`
+    /// (e.g., when archiving the live session).
+    pub fn mark_live(&mut self) {
+        self.moov_box.mvhd_box.duration = 0;
+        self.moov_box.mvex_box.mehd_box = None;
+        for trak in &mut self.moov_box.trak_boxes {
+            trak.tkhd_box.duration = 0;
+            trak.mdia_box.mdhd_box.duration = 0;
+        }
+    }
+
+    /// Drops the tracks other than `track_ids` (and their `trex` boxes) from this segment.
+    ///
+    /// See also `MediaSegment::retain_tracks`.
+    pub fn retain_tracks(&mut self, track_ids: &[u32]) {
+        self.moov_box
+            .trak_boxes
+            .retain(|t| track_ids.contains(&t.tkhd_box.track_id()));
+        self.moov_box
+            .mvex_box
+            .trex_boxes
+            .retain(|t| track_ids.contains(&t.track_id));
+    }
+
+    /// Returns a compact tree of the boxes in this segment (type, size and number of children) for debugging.
+    ///
+    /// See also [`isobmff::summarize`](../isobmff/fn.summarize.html).
+    pub fn summary(&self) -> Result<String> {
+        let size = u64::from(track!(self.ftyp_box.box_size())?)
+            + u64::from(track!(self.moov_box.box_size())?);
+        let mut bytes = Vec::with_capacity(size as usize);
+        track!(self.write_to(&mut bytes))?;
+        track_assert_eq!(bytes.len() as u64, size, ErrorKind::Other);
+        track!(isobmff::summarize(&bytes))
+    }
+
+    /// Reads an initialization segment from the given bytes and reconstructs the writer-side structures.
+    ///
+    /// Fields that the structures do not hold (e.g., creation times) are discarded.
+    /// If the bytes have no `ftyp` box, the default one is used.
+    pub fn read_from(bytes: &[u8]) -> Result<Self> {
+        let moov = track!(isobmff::find_box(bytes, fourcc::MOOV))?;
+        if moov.is_none() {
+            if let Some(meta) = track!(MetaBox::read_from(bytes))? {
+                track_assert!(
+                    !meta.has_image_items(),
+                    ErrorKind::Unsupported,
+                    "Image items (e.g., HEIF) are not supported"
+                );
+            }
+        }
+        let moov = track_assert_some!(moov, ErrorKind::InvalidInput, "No `moov` box");
+        let moov_box = track!(MovieBox::from_raw_box(&moov))?;
+        Ok(InitializationSegment {
+            ftyp_box: track!(FileTypeBox::read_from(bytes))?.unwrap_or_default(),
+            moov_box,
+        })
     }
 }
 impl WriteTo for InitializationSegment {
@@ -32,17 +230,56 @@ impl WriteTo for InitializationSegment {
 
 /// 4.3 File Type Box (ISO/IEC 14496-12).
 #[allow(missing_docs)]
-#[derive(Debug, Default)]
-pub struct FileTypeBox;
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileTypeBox {
+    pub major_brand: [u8; 4],
+    pub minor_version: u32,
+    pub compatible_brands: Vec<[u8; 4]>,
+}
+impl Default for FileTypeBox {
+    /// Returns a `ftyp` box whose major brand is `isom` (with no compatible brands).
+    fn default() -> Self {
+        FileTypeBox {
+            major_brand: fourcc::ISOM,
+            minor_version: 512,
+            compatible_brands: Vec::new(),
+        }
+    }
+}
+impl FileTypeBox {
+    /// Reads the first `ftyp` box in the given bytes (not recursive).
+    ///
+    /// `Ok(None)` is returned if there is no such box.
+    pub fn read_from(bytes: &[u8]) -> Result<Option<Self>> {
+        let b = match track!(isobmff::find_box(bytes, Self::BOX_TYPE))? {
+            None => return Ok(None),
+            Some(b) => b,
+        };
+        let mut reader = b.payload;
+        let mut major_brand = [0; 4];
+        track_io!(reader.read_exact(&mut major_brand))?;
+        let minor_version = track_io!(reader.read_u32::<BigEndian>())?;
+        track_assert_eq!(reader.len() % 4, 0, ErrorKind::InvalidInput);
+        let compatible_brands = reader.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+        Ok(Some(FileTypeBox {
+            major_brand,
+            minor_version,
+            compatible_brands,
+        }))
+    }
+}
 impl Mp4Box for FileTypeBox {
-    const BOX_TYPE: [u8; 4] = *b"ftyp";
+    const BOX_TYPE: [u8; 4] = fourcc::FTYP;
 
     fn box_payload_size(&self) -> Result<u32> {
-        Ok(8)
+        Ok(4 + 4 + 4 * self.compatible_brands.len() as u32)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        write_all!(writer, b"isom"); // major_brand
-        write_u32!(writer, 512); // minor_version
+        write_all!(writer, &self.major_brand);
+        write_u32!(writer, self.minor_version);
+        for brand in &self.compatible_brands {
+            write_all!(writer, brand);
+        }
         Ok(())
     }
 }
@@ -54,15 +291,77 @@ pub struct MovieBox {
     pub mvhd_box: MovieHeaderBox,
     pub trak_boxes: Vec<TrackBox>,
     pub mvex_box: MovieExtendsBox,
+
+    /// DRM system specific data (e.g., `ProtectionSystemSpecificHeaderBox::widevine`).
+    pub pssh_boxes: Vec<ProtectionSystemSpecificHeaderBox>,
+
+    pub meta_box: Option<MetaBox>,
+    pub free_box: Option<FreeSpaceBox>,
+}
+impl MovieBox {
+    /// Sets an encoder identification string (e.g., `"my-packager 1.2.0"`) for provenance.
+    ///
+    /// The string is written to a `free` box at the end of this box, so players ignore it.
+    pub fn set_encoder_identification(&mut self, identification: &str) {
+        self.free_box = Some(FreeSpaceBox {
+            data: identification.as_bytes().to_owned(),
+        });
+    }
+
+    /// Returns the encoder identification string set by `set_encoder_identification`, if any.
+    pub fn encoder_identification(&self) -> Option<String> {
+        self.free_box
+            .as_ref()
+            .map(|b| String::from_utf8_lossy(&b.data).into_owned())
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut mvhd_box = None;
+        let mut trak_boxes = Vec::new();
+        let mut mvex_box = None;
+        let mut pssh_boxes = Vec::new();
+        let mut meta_box = None;
+        let mut free_box = None;
+        for child in b.children() {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::MVHD => mvhd_box = Some(track!(MovieHeaderBox::from_raw_box(&child))?),
+                fourcc::TRAK => trak_boxes.push(track!(TrackBox::from_raw_box(&child))?),
+                fourcc::MVEX => mvex_box = Some(track!(MovieExtendsBox::from_raw_box(&child))?),
+                fourcc::PSSH => pssh_boxes.push(track!(
+                    ProtectionSystemSpecificHeaderBox::from_raw_box(&child)
+                )?),
+                // `meta` boxes of unknown layouts are ignored rather than rejecting the whole segment
+                fourcc::META => meta_box = MetaBox::from_raw_box(&child).ok(),
+                fourcc::FREE => {
+                    free_box = Some(FreeSpaceBox {
+                        data: child.payload.to_owned(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        Ok(MovieBox {
+            mvhd_box: track_assert_some!(mvhd_box, ErrorKind::InvalidInput, "No `mvhd` box"),
+            trak_boxes,
+            mvex_box: track_assert_some!(mvex_box, ErrorKind::InvalidInput, "No `mvex` box"),
+            pssh_boxes,
+            meta_box,
+            free_box,
+        })
+    }
 }
 impl Mp4Box for MovieBox {
-    const BOX_TYPE: [u8; 4] = *b"moov";
+    const BOX_TYPE: [u8; 4] = fourcc::MOOV;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
         size += box_size!(self.mvhd_box);
         size += boxes_size!(self.trak_boxes);
         size += box_size!(self.mvex_box);
+        size += boxes_size!(self.pssh_boxes);
+        size += optional_box_size!(self.meta_box);
+        size += optional_box_size!(self.free_box);
         Ok(size)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
@@ -70,10 +369,429 @@ impl Mp4Box for MovieBox {
         write_box!(writer, self.mvhd_box);
         write_boxes!(writer, &self.trak_boxes);
         write_box!(writer, &self.mvex_box);
+        write_boxes!(writer, &self.pssh_boxes);
+        if let Some(meta_box) = &self.meta_box {
+            write_box!(writer, meta_box);
+        }
+        if let Some(free_box) = &self.free_box {
+            write_box!(writer, free_box);
+        }
+        Ok(())
+    }
+}
+
+/// 8.1.2 Free Space Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default)]
+pub struct FreeSpaceBox {
+    pub data: Vec<u8>,
+}
+impl Mp4Box for FreeSpaceBox {
+    const BOX_TYPE: [u8; 4] = fourcc::FREE;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        track!(try_cast(self.data.len(), "Free space size"))
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_all!(writer, &self.data);
+        Ok(())
+    }
+}
+
+/// 8.11.1 The Meta box (ISO/IEC 14496-12).
+///
+/// Only the handler, item information and item location boxes are handled
+/// (the other child boxes are ignored by the reader).
+/// QuickTime-style boxes (that lack the full box header) are also read, but always written in the standard form.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct MetaBox {
+    pub hdlr_box: HandlerReferenceBox,
+    pub iinf_box: Option<ItemInfoBox>,
+    pub iloc_box: Option<ItemLocationBox>,
+}
+impl MetaBox {
+    /// Makes a new `MetaBox` instance that has the handler of `handler_type` (e.g., `fourcc::MDIR`) and no items.
+    pub fn new(handler_type: [u8; 4]) -> Self {
+        MetaBox {
+            hdlr_box: HandlerReferenceBox {
+                handler_type,
+                name: CString::default(),
+            },
+            iinf_box: None,
+            iloc_box: None,
+        }
+    }
+
+    /// Returns the handler type (e.g., `fourcc::PICT` for HEIF images).
+    pub fn handler_type(&self) -> [u8; 4] {
+        self.hdlr_box.handler_type
+    }
+
+    /// Returns `true` if the box describes image items (i.e., the handler type is `pict`).
+    ///
+    /// Image items (e.g., HEIF images) are not supported by this crate.
+    pub fn has_image_items(&self) -> bool {
+        self.hdlr_box.handler_type == fourcc::PICT
+    }
+
+    /// Reads the first top-level `meta` box in `bytes`, if any.
+    pub fn read_from(bytes: &[u8]) -> Result<Option<Self>> {
+        if let Some(b) = track!(isobmff::find_box(bytes, fourcc::META))? {
+            Ok(Some(track!(Self::from_raw_box(&b))?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        // QuickTime-style `meta` boxes have no full box header and start with the `hdlr` box
+        let payload = if b.payload.get(4..8) == Some(&fourcc::HDLR[..]) {
+            b.payload
+        } else {
+            track!(b.full_box())?.payload
+        };
+        let mut hdlr_box = None;
+        let mut iinf_box = None;
+        let mut iloc_box = None;
+        for child in isobmff::Boxes::new(payload) {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::HDLR => hdlr_box = Some(track!(HandlerReferenceBox::from_raw_box(&child))?),
+                fourcc::IINF => iinf_box = Some(track!(ItemInfoBox::from_raw_box(&child))?),
+                fourcc::ILOC => iloc_box = Some(track!(ItemLocationBox::from_raw_box(&child))?),
+                _ => {}
+            }
+        }
+        Ok(MetaBox {
+            hdlr_box: track_assert_some!(hdlr_box, ErrorKind::InvalidInput, "No `hdlr` box"),
+            iinf_box,
+            iloc_box,
+        })
+    }
+}
+impl Mp4Box for MetaBox {
+    const BOX_TYPE: [u8; 4] = fourcc::META;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 0;
+        size += box_size!(self.hdlr_box);
+        size += optional_box_size!(self.iinf_box);
+        size += optional_box_size!(self.iloc_box);
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_box!(writer, self.hdlr_box);
+        if let Some(iinf_box) = &self.iinf_box {
+            write_box!(writer, iinf_box);
+        }
+        if let Some(iloc_box) = &self.iloc_box {
+            write_box!(writer, iloc_box);
+        }
+        Ok(())
+    }
+}
+
+/// 8.11.6 Item Information Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Default)]
+pub struct ItemInfoBox {
+    pub entries: Vec<ItemInfoEntry>,
+}
+impl ItemInfoBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let entry_count = if full_box.version == 0 {
+            u32::from(track_io!(reader.read_u16::<BigEndian>())?)
+        } else {
+            track_io!(reader.read_u32::<BigEndian>())?
+        };
+        let mut entries = Vec::new();
+        for child in isobmff::Boxes::new(reader) {
+            let child = track!(child)?;
+            if child.box_type == fourcc::INFE {
+                entries.push(track!(ItemInfoEntry::from_raw_box(&child))?);
+            }
+        }
+        track_assert_eq!(entries.len() as u32, entry_count, ErrorKind::InvalidInput);
+        Ok(ItemInfoBox { entries })
+    }
+}
+impl Mp4Box for ItemInfoBox {
+    const BOX_TYPE: [u8; 4] = fourcc::IINF;
+
+    fn box_version(&self) -> Option<u8> {
+        if self.entries.len() > 0xFFFF {
+            Some(1)
+        } else {
+            Some(0)
+        }
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = if self.entries.len() > 0xFFFF { 4 } else { 2 };
+        size += boxes_size!(self.entries);
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        if self.entries.len() > 0xFFFF {
+            write_u32!(writer, self.entries.len() as u32);
+        } else {
+            write_u16!(writer, self.entries.len() as u16);
+        }
+        write_boxes!(writer, &self.entries);
+        Ok(())
+    }
+}
+
+/// 8.11.6 Item Info Entry (ISO/IEC 14496-12).
+///
+/// Entries of version 0 and 1 are converted into the version 2 (or 3) form when read;
+/// their item type is regarded as `mime`.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct ItemInfoEntry {
+    pub item_id: u32,
+    pub item_protection_index: u16,
+    pub item_type: [u8; 4],
+    pub item_name: String,
+
+    /// Content type of the item (only used if the item type is `mime`).
+    pub content_type: Option<String>,
+}
+impl ItemInfoEntry {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let item_id = if full_box.version == 3 {
+            track_io!(reader.read_u32::<BigEndian>())?
+        } else {
+            u32::from(track_io!(reader.read_u16::<BigEndian>())?)
+        };
+        let item_protection_index = track_io!(reader.read_u16::<BigEndian>())?;
+        let mut item_type = fourcc::MIME;
+        if full_box.version >= 2 {
+            track_io!(reader.read_exact(&mut item_type))?;
+        }
+        let item_name = read_null_terminated_string(&mut reader);
+        let content_type = if item_type == fourcc::MIME {
+            Some(read_null_terminated_string(&mut reader))
+        } else {
+            None
+        };
+        Ok(ItemInfoEntry {
+            item_id,
+            item_protection_index,
+            item_type,
+            item_name,
+            content_type,
+        })
+    }
+}
+impl Mp4Box for ItemInfoEntry {
+    const BOX_TYPE: [u8; 4] = fourcc::INFE;
+
+    fn box_version(&self) -> Option<u8> {
+        if self.item_id > 0xFFFF {
+            Some(3)
+        } else {
+            Some(2)
+        }
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        let size = track!(ByteCounter::calculate(|w| self.write_box_payload(w)))?;
+        Ok(size as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        if self.item_id > 0xFFFF {
+            write_u32!(writer, self.item_id);
+        } else {
+            write_u16!(writer, self.item_id as u16);
+        }
+        write_u16!(writer, self.item_protection_index);
+        write_all!(writer, &self.item_type);
+        write_all!(writer, self.item_name.as_bytes());
+        write_u8!(writer, 0);
+        if self.item_type == fourcc::MIME {
+            let content_type = self.content_type.as_ref().map_or("", |s| s.as_str());
+            write_all!(writer, content_type.as_bytes());
+            write_u8!(writer, 0);
+        }
+        Ok(())
+    }
+}
+
+/// 8.11.3 Item Location Box (ISO/IEC 14496-12).
+///
+/// The box is written in version 1 (or 2 if an item identifier does not fit in 16 bits)
+/// with 4-byte (or 8-byte if necessary) offset and length fields.
+#[allow(missing_docs)]
+#[derive(Debug, Default)]
+pub struct ItemLocationBox {
+    pub items: Vec<ItemLocation>,
+}
+impl ItemLocationBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        track_assert!(full_box.version <= 2, ErrorKind::Unsupported; full_box.version);
+        let mut reader = full_box.payload;
+        let sizes = track_io!(reader.read_u16::<BigEndian>())?;
+        let offset_size = (sizes >> 12) as usize;
+        let length_size = ((sizes >> 8) & 0xF) as usize;
+        let base_offset_size = ((sizes >> 4) & 0xF) as usize;
+        let index_size = if full_box.version == 0 {
+            0
+        } else {
+            (sizes & 0xF) as usize
+        };
+        let item_count = if full_box.version < 2 {
+            u32::from(track_io!(reader.read_u16::<BigEndian>())?)
+        } else {
+            track_io!(reader.read_u32::<BigEndian>())?
+        };
+
+        let mut items = Vec::new();
+        for _ in 0..item_count {
+            let item_id = if full_box.version < 2 {
+                u32::from(track_io!(reader.read_u16::<BigEndian>())?)
+            } else {
+                track_io!(reader.read_u32::<BigEndian>())?
+            };
+            let construction_method = if full_box.version == 0 {
+                0
+            } else {
+                (track_io!(reader.read_u16::<BigEndian>())? & 0xF) as u8
+            };
+            let data_reference_index = track_io!(reader.read_u16::<BigEndian>())?;
+            let base_offset = track!(read_sized_uint(&mut reader, base_offset_size))?;
+            let extent_count = track_io!(reader.read_u16::<BigEndian>())?;
+            let mut extents = Vec::new();
+            for _ in 0..extent_count {
+                track!(read_sized_uint(&mut reader, index_size))?;
+                let offset = track!(read_sized_uint(&mut reader, offset_size))?;
+                let length = track!(read_sized_uint(&mut reader, length_size))?;
+                extents.push(ItemExtent { offset, length });
+            }
+            items.push(ItemLocation {
+                item_id,
+                construction_method,
+                data_reference_index,
+                base_offset,
+                extents,
+            });
+        }
+        Ok(ItemLocationBox { items })
+    }
+
+    fn field_size(&self) -> usize {
+        let needs_u64 = self.items.iter().any(|item| {
+            item.base_offset > u64::from(u32::MAX)
+                || item
+                    .extents
+                    .iter()
+                    .any(|e| e.offset > u64::from(u32::MAX) || e.length > u64::from(u32::MAX))
+        });
+        if needs_u64 {
+            8
+        } else {
+            4
+        }
+    }
+}
+impl Mp4Box for ItemLocationBox {
+    const BOX_TYPE: [u8; 4] = fourcc::ILOC;
+
+    fn box_version(&self) -> Option<u8> {
+        if self.items.iter().any(|item| item.item_id > 0xFFFF) {
+            Some(2)
+        } else {
+            Some(1)
+        }
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        let size = track!(ByteCounter::calculate(|w| self.write_box_payload(w)))?;
+        Ok(size as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        let is_v2 = self.box_version() == Some(2);
+        let field_size = self.field_size();
+        let n = field_size as u16;
+        write_u16!(writer, (n << 12) | (n << 8) | (n << 4));
+        if is_v2 {
+            write_u32!(writer, self.items.len() as u32);
+        } else {
+            track_assert!(self.items.len() <= 0xFFFF, ErrorKind::InvalidInput);
+            write_u16!(writer, self.items.len() as u16);
+        }
+        for item in &self.items {
+            if is_v2 {
+                write_u32!(writer, item.item_id);
+            } else {
+                write_u16!(writer, item.item_id as u16);
+            }
+            write_u16!(writer, u16::from(item.construction_method & 0xF));
+            write_u16!(writer, item.data_reference_index);
+            track!(write_sized_uint(&mut writer, item.base_offset, field_size))?;
+            track_assert!(item.extents.len() <= 0xFFFF, ErrorKind::InvalidInput);
+            write_u16!(writer, item.extents.len() as u16);
+            for extent in &item.extents {
+                track!(write_sized_uint(&mut writer, extent.offset, field_size))?;
+                track!(write_sized_uint(&mut writer, extent.length, field_size))?;
+            }
+        }
         Ok(())
     }
 }
 
+/// Location of an item in `ItemLocationBox`.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct ItemLocation {
+    pub item_id: u32,
+
+    /// `0`: file offset, `1`: `idat` offset, `2`: item offset.
+    pub construction_method: u8,
+    pub data_reference_index: u16,
+    pub base_offset: u64,
+    pub extents: Vec<ItemExtent>,
+}
+
+/// Extent of an item in `ItemLocationBox`.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct ItemExtent {
+    pub offset: u64,
+    pub length: u64,
+}
+
+fn read_null_terminated_string(reader: &mut &[u8]) -> String {
+    let len = reader.iter().position(|&b| b == 0).unwrap_or(reader.len());
+    let s = String::from_utf8_lossy(&reader[..len]).into_owned();
+    *reader = &reader[(len + 1).min(reader.len())..];
+    s
+}
+
+fn read_sized_uint(reader: &mut &[u8], size: usize) -> Result<u64> {
+    match size {
+        0 => Ok(0),
+        4 => Ok(u64::from(track_io!(reader.read_u32::<BigEndian>())?)),
+        8 => Ok(track_io!(reader.read_u64::<BigEndian>())?),
+        _ => track_panic!(ErrorKind::InvalidInput, "Invalid field size: {}", size),
+    }
+}
+
+fn write_sized_uint<W: Write>(mut writer: W, n: u64, size: usize) -> Result<()> {
+    if size == 8 {
+        write_u64!(writer, n);
+    } else {
+        write_u32!(writer, n as u32);
+    }
+    Ok(())
+}
+
 /// 8.8.1 Movie Extends Box (ISO/IEC 14496-12).
 #[allow(missing_docs)]
 #[derive(Debug, Default)]
@@ -81,8 +799,28 @@ pub struct MovieExtendsBox {
     pub mehd_box: Option<MovieExtendsHeaderBox>,
     pub trex_boxes: Vec<TrackExtendsBox>,
 }
+impl MovieExtendsBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut mvex_box = MovieExtendsBox::default();
+        for child in b.children() {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::MEHD => {
+                    let mehd_box = track!(MovieExtendsHeaderBox::from_raw_box(&child))?;
+                    mvex_box.mehd_box = Some(mehd_box);
+                }
+                fourcc::TREX => {
+                    let trex_box = track!(TrackExtendsBox::from_raw_box(&child))?;
+                    mvex_box.trex_boxes.push(trex_box);
+                }
+                _ => {}
+            }
+        }
+        Ok(mvex_box)
+    }
+}
 impl Mp4Box for MovieExtendsBox {
-    const BOX_TYPE: [u8; 4] = *b"mvex";
+    const BOX_TYPE: [u8; 4] = fourcc::MVEX;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
@@ -106,8 +844,20 @@ impl Mp4Box for MovieExtendsBox {
 pub struct MovieExtendsHeaderBox {
     pub fragment_duration: u32,
 }
+impl MovieExtendsHeaderBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let fragment_duration = if full_box.version == 1 {
+            track!(duration_to_u32(track_io!(reader.read_u64::<BigEndian>())?))?
+        } else {
+            track_io!(reader.read_u32::<BigEndian>())?
+        };
+        Ok(MovieExtendsHeaderBox { fragment_duration })
+    }
+}
 impl Mp4Box for MovieExtendsHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"mehd";
+    const BOX_TYPE: [u8; 4] = fourcc::MEHD;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -146,9 +896,40 @@ impl TrackExtendsBox {
             default_sample_flags: 0,
         }
     }
+
+    /// Makes a new `TrackExtendsBox` instance for the track identified by `track_id`.
+    pub fn for_track(track_id: u32) -> Self {
+        TrackExtendsBox {
+            track_id,
+            ..Self::new(true)
+        }
+    }
+
+    /// Makes a new `TrackExtendsBox` instance of the text track made by `TrackBox::new_text`.
+    pub fn new_text() -> Self {
+        TrackExtendsBox {
+            track_id: TEXT_TRACK_ID,
+            ..Self::new(true)
+        }
+    }
+
+    pub(crate) fn track_id(&self) -> u32 {
+        self.track_id
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut reader = track!(b.full_box())?.payload;
+        Ok(TrackExtendsBox {
+            track_id: track_io!(reader.read_u32::<BigEndian>())?,
+            default_sample_description_index: track_io!(reader.read_u32::<BigEndian>())?,
+            default_sample_duration: track_io!(reader.read_u32::<BigEndian>())?,
+            default_sample_size: track_io!(reader.read_u32::<BigEndian>())?,
+            default_sample_flags: track_io!(reader.read_u32::<BigEndian>())?,
+        })
+    }
 }
 impl Mp4Box for TrackExtendsBox {
-    const BOX_TYPE: [u8; 4] = *b"trex";
+    const BOX_TYPE: [u8; 4] = fourcc::TREX;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -181,8 +962,29 @@ impl Default for MovieHeaderBox {
         }
     }
 }
+impl MovieHeaderBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let (timescale, duration) = if full_box.version == 1 {
+            track!(isobmff::skip_bytes(&mut reader, 8 + 8))?; // creation_time, modification_time
+            let timescale = track_io!(reader.read_u32::<BigEndian>())?;
+            let duration = track_io!(reader.read_u64::<BigEndian>())?;
+            (timescale, track!(duration_to_u32(duration))?)
+        } else {
+            track!(isobmff::skip_bytes(&mut reader, 4 + 4))?;
+            let timescale = track_io!(reader.read_u32::<BigEndian>())?;
+            let duration = track_io!(reader.read_u32::<BigEndian>())?;
+            (timescale, duration)
+        };
+        Ok(MovieHeaderBox {
+            timescale,
+            duration,
+        })
+    }
+}
 impl Mp4Box for MovieHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"mvhd";
+    const BOX_TYPE: [u8; 4] = fourcc::MVHD;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -216,6 +1018,7 @@ pub struct TrackBox {
     pub tkhd_box: TrackHeaderBox,
     pub edts_box: EditBox,
     pub mdia_box: MediaBox,
+    pub udta_box: Option<UserDataBox>,
 }
 impl TrackBox {
     /// Makes a new `TrackBox` instance.
@@ -224,23 +1027,168 @@ impl TrackBox {
             tkhd_box: TrackHeaderBox::new(is_video),
             edts_box: EditBox::default(),
             mdia_box: MediaBox::new(is_video),
+            udta_box: None,
+        }
+    }
+
+    /// Makes a new `TrackBox` instance of a text (WebVTT) track.
+    ///
+    /// The sample entry (see `WebVttSampleEntry`) and the timescale need to be set by the caller.
+    pub fn new_text() -> Self {
+        let mut tkhd_box = TrackHeaderBox::new(true);
+        tkhd_box.track_id = TEXT_TRACK_ID;
+        let mut minf_box = MediaInformationBox::new(true);
+        minf_box.vmhd_box = None;
+        minf_box.nmhd_box = Some(NullMediaHeaderBox);
+        TrackBox {
+            tkhd_box,
+            edts_box: EditBox::default(),
+            mdia_box: MediaBox {
+                mdhd_box: MediaHeaderBox::default(),
+                hdlr_box: HandlerReferenceBox {
+                    handler_type: fourcc::TEXT,
+                    name: CString::new("Text Handler").expect("Never fails"),
+                },
+                minf_box,
+            },
+            udta_box: None,
+        }
+    }
+
+    /// Adds a `kind` box (e.g., `urn:mpeg:dash:role:2011` and `commentary`) to the `udta` box of this track.
+    ///
+    /// Together with `TrackHeaderBox::alternate_group`, this describes which of the alternative tracks
+    /// (e.g., main audio and commentary) a player should select.
+    pub fn add_kind(&mut self, scheme_uri: &str, value: &str) {
+        self.udta_box
+            .get_or_insert_with(UserDataBox::default)
+            .kind_boxes
+            .push(KindBox {
+                scheme_uri: scheme_uri.to_owned(),
+                value: value.to_owned(),
+            });
+    }
+    fn is_compatible_with(&self, other: &Self) -> Result<bool> {
+        if self.tkhd_box.track_id != other.tkhd_box.track_id
+            || self.mdia_box.mdhd_box.timescale != other.mdia_box.mdhd_box.timescale
+            || self.mdia_box.hdlr_box.handler_type != other.mdia_box.hdlr_box.handler_type
+        {
+            return Ok(false);
+        }
+
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        write_box!(a, self.mdia_box.minf_box.stbl_box.stsd_box);
+        write_box!(b, other.mdia_box.minf_box.stbl_box.stsd_box);
+        Ok(a == b)
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut tkhd_box = None;
+        let mut edts_box = EditBox::default();
+        let mut mdia_box = None;
+        let mut udta_box = None;
+        for child in b.children() {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::TKHD => tkhd_box = Some(track!(TrackHeaderBox::from_raw_box(&child))?),
+                fourcc::EDTS => edts_box = track!(EditBox::from_raw_box(&child))?,
+                fourcc::MDIA => mdia_box = Some(track!(MediaBox::from_raw_box(&child))?),
+                fourcc::UDTA => udta_box = Some(track!(UserDataBox::from_raw_box(&child))?),
+                _ => {}
+            }
         }
+        Ok(TrackBox {
+            tkhd_box: track_assert_some!(tkhd_box, ErrorKind::InvalidInput, "No `tkhd` box"),
+            edts_box,
+            mdia_box: track_assert_some!(mdia_box, ErrorKind::InvalidInput, "No `mdia` box"),
+            udta_box,
+        })
     }
 }
 impl Mp4Box for TrackBox {
-    const BOX_TYPE: [u8; 4] = *b"trak";
+    const BOX_TYPE: [u8; 4] = fourcc::TRAK;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
         size += box_size!(self.tkhd_box);
         size += box_size!(self.edts_box);
         size += box_size!(self.mdia_box);
+        size += optional_box_size!(self.udta_box);
         Ok(size)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
         write_box!(writer, self.tkhd_box);
         write_box!(writer, self.edts_box);
         write_box!(writer, self.mdia_box);
+        if let Some(udta_box) = &self.udta_box {
+            write_box!(writer, udta_box);
+        }
+        Ok(())
+    }
+}
+
+/// 8.10.1 User Data Box (ISO/IEC 14496-12).
+///
+/// Only `kind` boxes are handled (the other child boxes are ignored by the reader).
+#[allow(missing_docs)]
+#[derive(Debug, Default)]
+pub struct UserDataBox {
+    pub kind_boxes: Vec<KindBox>,
+}
+impl UserDataBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut kind_boxes = Vec::new();
+        for child in b.children() {
+            let child = track!(child)?;
+            if child.box_type == fourcc::KIND {
+                kind_boxes.push(track!(KindBox::from_raw_box(&child))?);
+            }
+        }
+        Ok(UserDataBox { kind_boxes })
+    }
+}
+impl Mp4Box for UserDataBox {
+    const BOX_TYPE: [u8; 4] = fourcc::UDTA;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(boxes_size!(self.kind_boxes))
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_boxes!(writer, &self.kind_boxes);
+        Ok(())
+    }
+}
+
+/// 8.10.4 Track Kind Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct KindBox {
+    pub scheme_uri: String,
+    pub value: String,
+}
+impl KindBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut reader = track!(b.full_box())?.payload;
+        let scheme_uri = read_null_terminated_string(&mut reader);
+        let value = read_null_terminated_string(&mut reader);
+        Ok(KindBox { scheme_uri, value })
+    }
+}
+impl Mp4Box for KindBox {
+    const BOX_TYPE: [u8; 4] = fourcc::KIND;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok((self.scheme_uri.len() + 1 + self.value.len() + 1) as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_all!(writer, self.scheme_uri.as_bytes());
+        write_u8!(writer, 0);
+        write_all!(writer, self.value.as_bytes());
+        write_u8!(writer, 0);
         Ok(())
     }
 }
@@ -254,6 +1202,11 @@ pub struct TrackHeaderBox {
     volume: i16,     // fixed point 8.8
     pub width: u32,  // fixed point 16.16
     pub height: u32, // fixed point 16.16
+
+    /// Group of the alternative tracks (e.g., the main and commentary audio tracks) this track belongs to.
+    ///
+    /// `0` means that this track has no alternatives.
+    pub alternate_group: i16,
 }
 impl TrackHeaderBox {
     fn new(is_video: bool) -> Self {
@@ -267,11 +1220,48 @@ impl TrackHeaderBox {
             volume: if is_video { 0 } else { 256 },
             width: 0,
             height: 0,
+            alternate_group: 0,
         }
     }
+
+    pub(crate) fn track_id(&self) -> u32 {
+        self.track_id
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let (track_id, duration) = if full_box.version == 1 {
+            track!(isobmff::skip_bytes(&mut reader, 8 + 8))?; // creation_time, modification_time
+            let track_id = track_io!(reader.read_u32::<BigEndian>())?;
+            track!(isobmff::skip_bytes(&mut reader, 4))?;
+            let duration = track_io!(reader.read_u64::<BigEndian>())?;
+            (track_id, track!(duration_to_u32(duration))?)
+        } else {
+            track!(isobmff::skip_bytes(&mut reader, 4 + 4))?;
+            let track_id = track_io!(reader.read_u32::<BigEndian>())?;
+            track!(isobmff::skip_bytes(&mut reader, 4))?;
+            let duration = track_io!(reader.read_u32::<BigEndian>())?;
+            (track_id, duration)
+        };
+        track!(isobmff::skip_bytes(&mut reader, 4 * 2 + 2))?; // reserved, layer
+        let alternate_group = track_io!(reader.read_i16::<BigEndian>())?;
+        let volume = track_io!(reader.read_i16::<BigEndian>())?;
+        track!(isobmff::skip_bytes(&mut reader, 2 + 4 * 9))?; // reserved, matrix
+        let width = track_io!(reader.read_u32::<BigEndian>())?;
+        let height = track_io!(reader.read_u32::<BigEndian>())?;
+        Ok(TrackHeaderBox {
+            track_id,
+            duration,
+            volume,
+            width,
+            height,
+            alternate_group,
+        })
+    }
 }
 impl Mp4Box for TrackHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"tkhd";
+    const BOX_TYPE: [u8; 4] = fourcc::TKHD;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -293,7 +1283,7 @@ impl Mp4Box for TrackHeaderBox {
         write_u32!(writer, self.duration);
         write_zeroes!(writer, 4 * 2);
         write_i16!(writer, 0); // layer
-        write_i16!(writer, 0); // alternate_group
+        write_i16!(writer, self.alternate_group);
         write_i16!(writer, self.volume);
         write_zeroes!(writer, 2);
         for &x in &[0x1_0000, 0, 0, 0, 0x1_0000, 0, 0, 0, 0x4000_0000] {
@@ -311,8 +1301,16 @@ impl Mp4Box for TrackHeaderBox {
 pub struct EditBox {
     pub elst_box: EditListBox,
 }
+impl EditBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let elst = track!(isobmff::find_box(b.payload, fourcc::ELST))?;
+        let elst = track_assert_some!(elst, ErrorKind::InvalidInput, "No `elst` box");
+        let elst_box = track!(EditListBox::from_raw_box(&elst))?;
+        Ok(EditBox { elst_box })
+    }
+}
 impl Mp4Box for EditBox {
-    const BOX_TYPE: [u8; 4] = *b"edts";
+    const BOX_TYPE: [u8; 4] = fourcc::EDTS;
 
     fn box_payload_size(&self) -> Result<u32> {
         Ok(box_size!(self.elst_box))
@@ -327,19 +1325,76 @@ impl Mp4Box for EditBox {
 #[allow(missing_docs)]
 #[derive(Debug, Default)]
 pub struct EditListBox {
+    /// Duration (in the movie timescale) of an empty edit inserted before the media.
+    ///
+    /// If `0`, no empty edit is inserted.
+    pub empty_duration: u32,
+
     pub media_time: i32,
 }
+impl EditListBox {
+    /// Only the leading empty edit and the `media_time` of the first non-empty edit are retained.
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let entry_count = track_io!(reader.read_u32::<BigEndian>())?;
+        let mut empty_duration = 0;
+        for _ in 0..entry_count {
+            let (segment_duration, media_time) = if full_box.version == 1 {
+                let segment_duration = track_io!(reader.read_u64::<BigEndian>())?;
+                let media_time = track_io!(reader.read_i64::<BigEndian>())?;
+                track_assert!(
+                    media_time >= i64::from(i32::MIN) && media_time <= i64::from(i32::MAX),
+                    ErrorKind::Unsupported;
+                    media_time
+                );
+                (segment_duration, media_time as i32)
+            } else {
+                let segment_duration = track_io!(reader.read_u32::<BigEndian>())?;
+                let media_time = track_io!(reader.read_i32::<BigEndian>())?;
+                (u64::from(segment_duration), media_time)
+            };
+            track!(isobmff::skip_bytes(&mut reader, 2 + 2))?; // media_rate
+            if media_time != -1 {
+                return Ok(EditListBox {
+                    empty_duration,
+                    media_time,
+                });
+            }
+            let total = segment_duration.checked_add(u64::from(empty_duration));
+            let total = track_assert_some!(
+                total.filter(|&d| d <= u64::from(u32::MAX)),
+                ErrorKind::Unsupported;
+                segment_duration
+            );
+            empty_duration = total as u32;
+        }
+        Ok(EditListBox {
+            empty_duration,
+            media_time: 0,
+        })
+    }
+}
 impl Mp4Box for EditListBox {
-    const BOX_TYPE: [u8; 4] = *b"elst";
+    const BOX_TYPE: [u8; 4] = fourcc::ELST;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
     }
     fn box_payload_size(&self) -> Result<u32> {
-        Ok(4 + 4 + 4 + 2 + 2)
+        let entry_count = if self.empty_duration == 0 { 1 } else { 2 };
+        Ok(4 + (4 + 4 + 2 + 2) * entry_count)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        write_u32!(writer, 1); // entry_count
+        if self.empty_duration == 0 {
+            write_u32!(writer, 1); // entry_count
+        } else {
+            write_u32!(writer, 2); // entry_count
+            write_u32!(writer, self.empty_duration); // segment_duration
+            write_i32!(writer, -1); // media_time (empty edit)
+            write_i16!(writer, 1); // media_rate_integer
+            write_i16!(writer, 0); // media_rate_fraction
+        }
         write_u32!(writer, 0); // segment_duration ("0" indicating that it spans all subsequent media)
         write_i32!(writer, self.media_time);
         write_i16!(writer, 1); // media_rate_integer
@@ -364,9 +1419,29 @@ impl MediaBox {
             minf_box: MediaInformationBox::new(is_video),
         }
     }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut mdhd_box = None;
+        let mut hdlr_box = None;
+        let mut minf_box = None;
+        for child in b.children() {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::MDHD => mdhd_box = Some(track!(MediaHeaderBox::from_raw_box(&child))?),
+                fourcc::HDLR => hdlr_box = Some(track!(HandlerReferenceBox::from_raw_box(&child))?),
+                fourcc::MINF => minf_box = Some(track!(MediaInformationBox::from_raw_box(&child))?),
+                _ => {}
+            }
+        }
+        Ok(MediaBox {
+            mdhd_box: track_assert_some!(mdhd_box, ErrorKind::InvalidInput, "No `mdhd` box"),
+            hdlr_box: track_assert_some!(hdlr_box, ErrorKind::InvalidInput, "No `hdlr` box"),
+            minf_box: track_assert_some!(minf_box, ErrorKind::InvalidInput, "No `minf` box"),
+        })
+    }
 }
 impl Mp4Box for MediaBox {
-    const BOX_TYPE: [u8; 4] = *b"mdia";
+    const BOX_TYPE: [u8; 4] = fourcc::MDIA;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
@@ -398,8 +1473,29 @@ impl Default for MediaHeaderBox {
         }
     }
 }
+impl MediaHeaderBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let (timescale, duration) = if full_box.version == 1 {
+            track!(isobmff::skip_bytes(&mut reader, 8 + 8))?; // creation_time, modification_time
+            let timescale = track_io!(reader.read_u32::<BigEndian>())?;
+            let duration = track_io!(reader.read_u64::<BigEndian>())?;
+            (timescale, track!(duration_to_u32(duration))?)
+        } else {
+            track!(isobmff::skip_bytes(&mut reader, 4 + 4))?;
+            let timescale = track_io!(reader.read_u32::<BigEndian>())?;
+            let duration = track_io!(reader.read_u32::<BigEndian>())?;
+            (timescale, duration)
+        };
+        Ok(MediaHeaderBox {
+            timescale,
+            duration,
+        })
+    }
+}
 impl Mp4Box for MediaHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"mdhd";
+    const BOX_TYPE: [u8; 4] = fourcc::MDHD;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -432,15 +1528,51 @@ impl HandlerReferenceBox {
             "Sound Handler"
         };
         HandlerReferenceBox {
-            handler_type: if is_video { *b"vide" } else { *b"soun" },
+            handler_type: if is_video { fourcc::VIDE } else { fourcc::SOUN },
             name: CString::new(name).expect("Never fails"),
         }
     }
-}
-impl Mp4Box for HandlerReferenceBox {
-    const BOX_TYPE: [u8; 4] = *b"hdlr";
 
-    fn box_version(&self) -> Option<u8> {
+    /// Returns the handler type (e.g., `fourcc::VIDE` and `fourcc::SOUN`).
+    pub fn handler_type(&self) -> [u8; 4] {
+        self.handler_type
+    }
+
+    /// Returns the human-readable name of the track type (e.g., `"Video Handler"`).
+    pub fn name(&self) -> &CStr {
+        &self.name
+    }
+
+    /// Sets the human-readable name of the track type.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if `name` contains a NUL character.
+    pub fn set_name(&mut self, name: &str) -> Result<()> {
+        self.name = track_assert_some!(
+            CString::new(name).ok(),
+            ErrorKind::InvalidInput,
+            "Handler name contains a NUL character: {:?}",
+            name
+        );
+        Ok(())
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut reader = track!(b.full_box())?.payload;
+        track!(isobmff::skip_bytes(&mut reader, 4))?; // pre_defined
+        let mut handler_type = [0; 4];
+        track_io!(reader.read_exact(&mut handler_type))?;
+        track!(isobmff::skip_bytes(&mut reader, 4 * 3))?;
+        let name = reader.split(|&b| b == 0).next().unwrap_or(&[]);
+        Ok(HandlerReferenceBox {
+            handler_type,
+            name: CString::new(name).expect("Never fails"),
+        })
+    }
+}
+impl Mp4Box for HandlerReferenceBox {
+    const BOX_TYPE: [u8; 4] = fourcc::HDLR;
+
+    fn box_version(&self) -> Option<u8> {
         Some(0)
     }
     fn box_payload_size(&self) -> Result<u32> {
@@ -462,6 +1594,7 @@ impl Mp4Box for HandlerReferenceBox {
 pub struct MediaInformationBox {
     pub vmhd_box: Option<VideoMediaHeaderBox>,
     pub smhd_box: Option<SoundMediaHeaderBox>,
+    pub nmhd_box: Option<NullMediaHeaderBox>,
     pub dinf_box: DataInformationBox,
     pub stbl_box: SampleTableBox,
 }
@@ -478,18 +1611,44 @@ impl MediaInformationBox {
             } else {
                 None
             },
+            nmhd_box: None,
             dinf_box: DataInformationBox::default(),
             stbl_box: SampleTableBox::default(),
         }
     }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut vmhd_box = None;
+        let mut smhd_box = None;
+        let mut nmhd_box = None;
+        let mut stbl_box = None;
+        for child in b.children() {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::VMHD => vmhd_box = Some(VideoMediaHeaderBox),
+                fourcc::SMHD => smhd_box = Some(SoundMediaHeaderBox),
+                fourcc::NMHD => nmhd_box = Some(NullMediaHeaderBox),
+                fourcc::STBL => stbl_box = Some(track!(SampleTableBox::from_raw_box(&child))?),
+                _ => {}
+            }
+        }
+        Ok(MediaInformationBox {
+            vmhd_box,
+            smhd_box,
+            nmhd_box,
+            dinf_box: DataInformationBox::default(),
+            stbl_box: track_assert_some!(stbl_box, ErrorKind::InvalidInput, "No `stbl` box"),
+        })
+    }
 }
 impl Mp4Box for MediaInformationBox {
-    const BOX_TYPE: [u8; 4] = *b"minf";
+    const BOX_TYPE: [u8; 4] = fourcc::MINF;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
         size += optional_box_size!(self.vmhd_box);
         size += optional_box_size!(self.smhd_box);
+        size += optional_box_size!(self.nmhd_box);
         size += box_size!(self.dinf_box);
         size += box_size!(self.stbl_box);
         Ok(size)
@@ -501,6 +1660,9 @@ impl Mp4Box for MediaInformationBox {
         if let Some(ref x) = self.smhd_box {
             write_box!(writer, x);
         }
+        if let Some(ref x) = self.nmhd_box {
+            write_box!(writer, x);
+        }
         write_box!(writer, self.dinf_box);
         write_box!(writer, self.stbl_box);
         Ok(())
@@ -511,7 +1673,7 @@ impl Mp4Box for MediaInformationBox {
 #[derive(Debug)]
 pub struct VideoMediaHeaderBox;
 impl Mp4Box for VideoMediaHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"vmhd";
+    const BOX_TYPE: [u8; 4] = fourcc::VMHD;
 
     fn box_flags(&self) -> Option<u32> {
         Some(1)
@@ -530,7 +1692,7 @@ impl Mp4Box for VideoMediaHeaderBox {
 #[derive(Debug)]
 pub struct SoundMediaHeaderBox;
 impl Mp4Box for SoundMediaHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"smhd";
+    const BOX_TYPE: [u8; 4] = fourcc::SMHD;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -545,6 +1707,23 @@ impl Mp4Box for SoundMediaHeaderBox {
     }
 }
 
+/// 8.4.5.2 Null Media Header Box (ISO/IEC 14496-12).
+#[derive(Debug)]
+pub struct NullMediaHeaderBox;
+impl Mp4Box for NullMediaHeaderBox {
+    const BOX_TYPE: [u8; 4] = fourcc::NMHD;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(0)
+    }
+    fn write_box_payload<W: Write>(&self, _writer: W) -> Result<()> {
+        Ok(())
+    }
+}
+
 /// 8.7.1 Data Information Box (ISO/IEC 14496-12).
 #[allow(missing_docs)]
 #[derive(Debug, Default)]
@@ -552,7 +1731,7 @@ pub struct DataInformationBox {
     pub dref_box: DataReferenceBox,
 }
 impl Mp4Box for DataInformationBox {
-    const BOX_TYPE: [u8; 4] = *b"dinf";
+    const BOX_TYPE: [u8; 4] = fourcc::DINF;
 
     fn box_payload_size(&self) -> Result<u32> {
         Ok(box_size!(self.dref_box))
@@ -570,7 +1749,7 @@ pub struct DataReferenceBox {
     pub url_box: DataEntryUrlBox,
 }
 impl Mp4Box for DataReferenceBox {
-    const BOX_TYPE: [u8; 4] = *b"dref";
+    const BOX_TYPE: [u8; 4] = fourcc::DREF;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -591,7 +1770,7 @@ impl Mp4Box for DataReferenceBox {
 #[derive(Debug, Default)]
 pub struct DataEntryUrlBox;
 impl Mp4Box for DataEntryUrlBox {
-    const BOX_TYPE: [u8; 4] = *b"url ";
+    const BOX_TYPE: [u8; 4] = fourcc::URL;
 
     fn box_flags(&self) -> Option<u32> {
         Some(0x00_0001)
@@ -615,8 +1794,18 @@ pub struct SampleTableBox {
     pub stsz_box: SampleSizeBox,
     pub stco_box: ChunkOffsetBox,
 }
+impl SampleTableBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let stsd = track!(isobmff::find_box(b.payload, fourcc::STSD))?;
+        let stsd = track_assert_some!(stsd, ErrorKind::InvalidInput, "No `stsd` box");
+        Ok(SampleTableBox {
+            stsd_box: track!(SampleDescriptionBox::from_raw_box(&stsd))?,
+            ..SampleTableBox::default()
+        })
+    }
+}
 impl Mp4Box for SampleTableBox {
-    const BOX_TYPE: [u8; 4] = *b"stbl";
+    const BOX_TYPE: [u8; 4] = fourcc::STBL;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
@@ -643,11 +1832,28 @@ impl Mp4Box for SampleTableBox {
 pub struct SampleDescriptionBox {
     pub sample_entries: Vec<SampleEntry>,
 }
+impl SampleDescriptionBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut reader = track!(b.full_box())?.payload;
+        let entry_count = track_io!(reader.read_u32::<BigEndian>())?;
+        let mut sample_entries = Vec::new();
+        for entry in isobmff::Boxes::new(reader).take(entry_count as usize) {
+            let entry = track!(entry)?;
+            sample_entries.push(track!(SampleEntry::from_raw_box(&entry))?);
+        }
+        Ok(SampleDescriptionBox { sample_entries })
+    }
+}
 impl Mp4Box for SampleDescriptionBox {
-    const BOX_TYPE: [u8; 4] = *b"stsd";
+    const BOX_TYPE: [u8; 4] = fourcc::STSD;
 
     fn box_version(&self) -> Option<u8> {
-        Some(0)
+        // Version 1 is required if any entry is an `AudioSampleEntryV1`
+        let has_v1_entry = self.sample_entries.iter().any(|entry| match *entry {
+            SampleEntry::Aac(ref x) => x.entry_version() == 1,
+            _ => false,
+        });
+        Some(if has_v1_entry { 1 } else { 0 })
     }
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 4;
@@ -666,7 +1872,7 @@ impl Mp4Box for SampleDescriptionBox {
 #[derive(Debug, Default)]
 pub struct SampleSizeBox;
 impl Mp4Box for SampleSizeBox {
-    const BOX_TYPE: [u8; 4] = *b"stsz";
+    const BOX_TYPE: [u8; 4] = fourcc::STSZ;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -685,7 +1891,7 @@ impl Mp4Box for SampleSizeBox {
 #[derive(Debug, Default)]
 pub struct TimeToSampleBox;
 impl Mp4Box for TimeToSampleBox {
-    const BOX_TYPE: [u8; 4] = *b"stts";
+    const BOX_TYPE: [u8; 4] = fourcc::STTS;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -703,7 +1909,7 @@ impl Mp4Box for TimeToSampleBox {
 #[derive(Debug, Default)]
 pub struct ChunkOffsetBox;
 impl Mp4Box for ChunkOffsetBox {
-    const BOX_TYPE: [u8; 4] = *b"stco";
+    const BOX_TYPE: [u8; 4] = fourcc::STCO;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -721,7 +1927,7 @@ impl Mp4Box for ChunkOffsetBox {
 #[derive(Debug, Default)]
 pub struct SampleToChunkBox;
 impl Mp4Box for SampleToChunkBox {
-    const BOX_TYPE: [u8; 4] = *b"stsc";
+    const BOX_TYPE: [u8; 4] = fourcc::STSC;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
@@ -741,22 +1947,112 @@ impl Mp4Box for SampleToChunkBox {
 pub enum SampleEntry {
     Avc(AvcSampleEntry),
     Aac(AacSampleEntry),
+    Vp9(Vp9SampleEntry),
+    Av1(Av1SampleEntry),
+    WebVtt(WebVttSampleEntry),
+
+    /// Sample entry of a codec that is not built into this crate.
+    Custom(Box<dyn CustomSampleEntry>),
 }
 impl SampleEntry {
+    /// Returns the codec string (e.g., `"mp4a.40.2"`) used in MIME types.
+    pub fn codec_string(&self) -> String {
+        match *self {
+            SampleEntry::Avc(ref x) => x.avcc_box.configuration.codec_string(),
+            SampleEntry::Aac(ref x) => x.esds_box.profile.codec_string(),
+            SampleEntry::Vp9(ref x) => x.vpcc_box.configuration.codec_string(),
+            SampleEntry::Av1(ref x) => x.av1c_box.configuration.codec_string(),
+            SampleEntry::WebVtt(_) => "wvtt".to_owned(),
+            SampleEntry::Custom(ref x) => x.codec_string(),
+        }
+    }
     fn box_size(&self) -> Result<u32> {
         match *self {
             SampleEntry::Avc(ref x) => track!(x.box_size()),
             SampleEntry::Aac(ref x) => track!(x.box_size()),
+            SampleEntry::Vp9(ref x) => track!(x.box_size()),
+            SampleEntry::Av1(ref x) => track!(x.box_size()),
+            SampleEntry::WebVtt(ref x) => track!(x.box_size()),
+            SampleEntry::Custom(ref x) => track!(x.dyn_box_size()),
         }
     }
-    fn write_box<W: Write>(&self, writer: W) -> Result<()> {
+    fn write_box<W: Write>(&self, mut writer: W) -> Result<()> {
         match *self {
             SampleEntry::Avc(ref x) => track!(x.write_box(writer)),
             SampleEntry::Aac(ref x) => track!(x.write_box(writer)),
+            SampleEntry::Vp9(ref x) => track!(x.write_box(writer)),
+            SampleEntry::Av1(ref x) => track!(x.write_box(writer)),
+            SampleEntry::WebVtt(ref x) => track!(x.write_box(writer)),
+            SampleEntry::Custom(ref x) => track!(x.write_dyn_box(&mut writer)),
+        }
+    }
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        match b.box_type {
+            fourcc::AVC1 => track!(AvcSampleEntry::from_raw_box(b)).map(SampleEntry::Avc),
+            fourcc::MP4A => track!(AacSampleEntry::from_raw_box(b)).map(SampleEntry::Aac),
+            fourcc::VP09 => track!(Vp9SampleEntry::from_raw_box(b)).map(SampleEntry::Vp9),
+            fourcc::AV01 => track!(Av1SampleEntry::from_raw_box(b)).map(SampleEntry::Av1),
+            fourcc::WVTT => track!(WebVttSampleEntry::from_raw_box(b)).map(SampleEntry::WebVtt),
+            _ => track_panic!(
+                ErrorKind::Unsupported,
+                "Unsupported sample entry: {:?}",
+                String::from_utf8_lossy(&b.box_type)
+            ),
         }
     }
 }
 
+/// Sample entry of a codec that is not built into this crate.
+///
+/// Downstream crates can implement this trait to add sample entries of other codecs
+/// (via `SampleEntry::Custom`) without modifying `SampleEntry`.
+/// Types implementing `Mp4Box` automatically satisfy the `DynMp4Box` bound.
+pub trait CustomSampleEntry: DynMp4Box + std::fmt::Debug {
+    /// Returns the codec string (e.g., `"hvc1.1.6.L93.B0"`) used in MIME types.
+    fn codec_string(&self) -> String;
+
+    /// Returns the width and height (in pixels) if this is a visual sample entry.
+    ///
+    /// The default implementation returns `None`.
+    fn resolution(&self) -> Option<(u16, u16)> {
+        None
+    }
+}
+
+/// Writes the fields of `VisualSampleEntry` (ISO/IEC 14496-12).
+fn write_visual_sample_entry_fields<W: Write>(
+    mut writer: W,
+    width: u16,
+    height: u16,
+) -> Result<()> {
+    write_zeroes!(writer, 6);
+    write_u16!(writer, 1); // data_reference_index
+
+    write_zeroes!(writer, 16);
+    write_u16!(writer, width);
+    write_u16!(writer, height);
+    write_u32!(writer, 0x0048_0000);
+    write_u32!(writer, 0x0048_0000);
+    write_zeroes!(writer, 4);
+    write_u16!(writer, 1);
+    write_zeroes!(writer, 32);
+    write_u16!(writer, 0x0018);
+    write_i16!(writer, -1);
+    Ok(())
+}
+
+/// Reads the fields of `VisualSampleEntry` and returns the width, the height and the remaining bytes.
+fn read_visual_sample_entry_fields(mut payload: &[u8]) -> Result<(u16, u16, &[u8])> {
+    track!(isobmff::skip_bytes(&mut payload, 6 + 2 + 16))?;
+    let width = track_io!(payload.read_u16::<BigEndian>())?;
+    let height = track_io!(payload.read_u16::<BigEndian>())?;
+    track!(isobmff::skip_bytes(
+        &mut payload,
+        4 + 4 + 4 + 2 + 32 + 2 + 2
+    ))?;
+    Ok((width, height, payload))
+}
+
 /// Sample Entry for AVC.
 #[allow(missing_docs)]
 #[derive(Debug)]
@@ -764,27 +2060,41 @@ pub struct AvcSampleEntry {
     pub width: u16,
     pub height: u16,
     pub avcc_box: AvcConfigurationBox,
+    pub hdr_boxes: HdrBoxes,
 }
 impl AvcSampleEntry {
-    fn write_box_payload_without_avcc<W: Write>(&self, mut writer: W) -> Result<()> {
-        write_zeroes!(writer, 6);
-        write_u16!(writer, 1); // data_reference_index
-
-        write_zeroes!(writer, 16);
-        write_u16!(writer, self.width);
-        write_u16!(writer, self.height);
-        write_u32!(writer, 0x0048_0000);
-        write_u32!(writer, 0x0048_0000);
-        write_zeroes!(writer, 4);
-        write_u16!(writer, 1);
-        write_zeroes!(writer, 32);
-        write_u16!(writer, 0x0018);
-        write_i16!(writer, -1);
-        Ok(())
+    fn write_box_payload_without_avcc<W: Write>(&self, writer: W) -> Result<()> {
+        track!(write_visual_sample_entry_fields(
+            writer,
+            self.width,
+            self.height
+        ))
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let (width, height, children) = track!(read_visual_sample_entry_fields(b.payload))?;
+        let mut avcc_box = None;
+        let mut hdr_boxes = HdrBoxes::default();
+        for child in isobmff::Boxes::new(children) {
+            let child = track!(child)?;
+            if child.box_type == fourcc::AVCC {
+                let configuration =
+                    track!(AvcDecoderConfigurationRecord::read_from(child.payload))?;
+                avcc_box = Some(AvcConfigurationBox { configuration });
+            } else {
+                track!(hdr_boxes.read_child(&child))?;
+            }
+        }
+        Ok(AvcSampleEntry {
+            width,
+            height,
+            avcc_box: track_assert_some!(avcc_box, ErrorKind::InvalidInput, "No `avcC` box"),
+            hdr_boxes,
+        })
     }
 }
 impl Mp4Box for AvcSampleEntry {
-    const BOX_TYPE: [u8; 4] = *b"avc1";
+    const BOX_TYPE: [u8; 4] = fourcc::AVC1;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
@@ -792,11 +2102,13 @@ impl Mp4Box for AvcSampleEntry {
             |w| self.write_box_payload_without_avcc(w)
         ))? as u32;
         size += box_size!(self.avcc_box);
+        size += track!(self.hdr_boxes.box_size())?;
         Ok(size)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
         track!(self.write_box_payload_without_avcc(&mut writer))?;
         write_box!(writer, self.avcc_box);
+        track!(self.hdr_boxes.write_boxes(&mut writer))?;
         Ok(())
     }
 }
@@ -808,7 +2120,7 @@ pub struct AvcConfigurationBox {
     pub configuration: AvcDecoderConfigurationRecord,
 }
 impl Mp4Box for AvcConfigurationBox {
-    const BOX_TYPE: [u8; 4] = *b"avcC";
+    const BOX_TYPE: [u8; 4] = fourcc::AVCC;
 
     fn box_payload_size(&self) -> Result<u32> {
         let size = track!(ByteCounter::calculate(|w| self.configuration.write_to(w)))?;
@@ -819,33 +2131,489 @@ impl Mp4Box for AvcConfigurationBox {
     }
 }
 
+/// Sample Entry for VP9.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct Vp9SampleEntry {
+    pub width: u16,
+    pub height: u16,
+    pub vpcc_box: VpCodecConfigurationBox,
+    pub hdr_boxes: HdrBoxes,
+}
+impl Vp9SampleEntry {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let (width, height, children) = track!(read_visual_sample_entry_fields(b.payload))?;
+        let mut vpcc_box = None;
+        let mut hdr_boxes = HdrBoxes::default();
+        for child in isobmff::Boxes::new(children) {
+            let child = track!(child)?;
+            if child.box_type == fourcc::VPCC {
+                let payload = track!(child.full_box())?.payload;
+                let configuration = track!(VpCodecConfigurationRecord::read_from(payload))?;
+                vpcc_box = Some(VpCodecConfigurationBox { configuration });
+            } else {
+                track!(hdr_boxes.read_child(&child))?;
+            }
+        }
+        Ok(Vp9SampleEntry {
+            width,
+            height,
+            vpcc_box: track_assert_some!(vpcc_box, ErrorKind::InvalidInput, "No `vpcC` box"),
+            hdr_boxes,
+        })
+    }
+}
+impl Mp4Box for Vp9SampleEntry {
+    const BOX_TYPE: [u8; 4] = fourcc::VP09;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 0;
+        size += track!(ByteCounter::calculate(|w| {
+            write_visual_sample_entry_fields(w, self.width, self.height)
+        }))? as u32;
+        size += box_size!(self.vpcc_box);
+        size += track!(self.hdr_boxes.box_size())?;
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        track!(write_visual_sample_entry_fields(
+            &mut writer,
+            self.width,
+            self.height
+        ))?;
+        write_box!(writer, self.vpcc_box);
+        track!(self.hdr_boxes.write_boxes(&mut writer))?;
+        Ok(())
+    }
+}
+
+/// Box that contains VP Codec Configuration Record.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct VpCodecConfigurationBox {
+    pub configuration: VpCodecConfigurationRecord,
+}
+impl Mp4Box for VpCodecConfigurationBox {
+    const BOX_TYPE: [u8; 4] = fourcc::VPCC;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(1)
+    }
+    fn box_flags(&self) -> Option<u32> {
+        Some(0)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        let size = track!(ByteCounter::calculate(|w| self.configuration.write_to(w)))?;
+        Ok(size as u32)
+    }
+    fn write_box_payload<W: Write>(&self, writer: W) -> Result<()> {
+        track!(self.configuration.write_to(writer))
+    }
+}
+
+/// Sample Entry for AV1.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct Av1SampleEntry {
+    pub width: u16,
+    pub height: u16,
+    pub av1c_box: Av1ConfigurationBox,
+    pub hdr_boxes: HdrBoxes,
+}
+impl Av1SampleEntry {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let (width, height, children) = track!(read_visual_sample_entry_fields(b.payload))?;
+        let mut av1c_box = None;
+        let mut hdr_boxes = HdrBoxes::default();
+        for child in isobmff::Boxes::new(children) {
+            let child = track!(child)?;
+            if child.box_type == fourcc::AV1C {
+                let configuration = track!(Av1CodecConfigurationRecord::read_from(child.payload))?;
+                av1c_box = Some(Av1ConfigurationBox { configuration });
+            } else {
+                track!(hdr_boxes.read_child(&child))?;
+            }
+        }
+        Ok(Av1SampleEntry {
+            width,
+            height,
+            av1c_box: track_assert_some!(av1c_box, ErrorKind::InvalidInput, "No `av1C` box"),
+            hdr_boxes,
+        })
+    }
+}
+impl Mp4Box for Av1SampleEntry {
+    const BOX_TYPE: [u8; 4] = fourcc::AV01;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 0;
+        size += track!(ByteCounter::calculate(|w| {
+            write_visual_sample_entry_fields(w, self.width, self.height)
+        }))? as u32;
+        size += box_size!(self.av1c_box);
+        size += track!(self.hdr_boxes.box_size())?;
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        track!(write_visual_sample_entry_fields(
+            &mut writer,
+            self.width,
+            self.height
+        ))?;
+        write_box!(writer, self.av1c_box);
+        track!(self.hdr_boxes.write_boxes(&mut writer))?;
+        Ok(())
+    }
+}
+
+/// Box that contains AV1 Codec Configuration Record.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct Av1ConfigurationBox {
+    pub configuration: Av1CodecConfigurationRecord,
+}
+impl Mp4Box for Av1ConfigurationBox {
+    const BOX_TYPE: [u8; 4] = fourcc::AV1C;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        let size = track!(ByteCounter::calculate(|w| self.configuration.write_to(w)))?;
+        Ok(size as u32)
+    }
+    fn write_box_payload<W: Write>(&self, writer: W) -> Result<()> {
+        track!(self.configuration.write_to(writer))
+    }
+}
+
+/// Optional HDR related children of a video sample entry.
+#[allow(missing_docs)]
+#[derive(Debug, Default)]
+pub struct HdrBoxes {
+    pub mdcv_box: Option<MasteringDisplayColourVolumeBox>,
+    pub clli_box: Option<ContentLightLevelBox>,
+    pub dvcc_box: Option<DolbyVisionConfigurationBox>,
+    pub dvvc_box: Option<DolbyVisionExtendedConfigurationBox>,
+}
+impl HdrBoxes {
+    fn box_size(&self) -> Result<u32> {
+        let mut size = 0;
+        size += optional_box_size!(self.mdcv_box);
+        size += optional_box_size!(self.clli_box);
+        size += optional_box_size!(self.dvcc_box);
+        size += optional_box_size!(self.dvvc_box);
+        Ok(size)
+    }
+    fn write_boxes<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(
+            self.dvcc_box.is_none() || self.dvvc_box.is_none(),
+            ErrorKind::InvalidInput,
+            "Both `dvcC` and `dvvC` are specified"
+        );
+        if let Some(ref x) = self.mdcv_box {
+            write_box!(writer, x);
+        }
+        if let Some(ref x) = self.clli_box {
+            write_box!(writer, x);
+        }
+        if let Some(ref x) = self.dvcc_box {
+            write_box!(writer, x);
+        }
+        if let Some(ref x) = self.dvvc_box {
+            write_box!(writer, x);
+        }
+        Ok(())
+    }
+
+    /// Sets the corresponding field if `b` is one of the HDR related boxes (otherwise `b` is ignored).
+    fn read_child(&mut self, b: &RawBox) -> Result<()> {
+        let mut reader = b.payload;
+        match b.box_type {
+            fourcc::MDCV => {
+                let mut read_xy = || -> Result<(u16, u16)> {
+                    let x = track_io!(reader.read_u16::<BigEndian>())?;
+                    let y = track_io!(reader.read_u16::<BigEndian>())?;
+                    Ok((x, y))
+                };
+                let display_primaries =
+                    [track!(read_xy())?, track!(read_xy())?, track!(read_xy())?];
+                let white_point = track!(read_xy())?;
+                self.mdcv_box = Some(MasteringDisplayColourVolumeBox {
+                    display_primaries,
+                    white_point,
+                    max_display_mastering_luminance: track_io!(reader.read_u32::<BigEndian>())?,
+                    min_display_mastering_luminance: track_io!(reader.read_u32::<BigEndian>())?,
+                });
+            }
+            fourcc::CLLI => {
+                self.clli_box = Some(ContentLightLevelBox {
+                    max_content_light_level: track_io!(reader.read_u16::<BigEndian>())?,
+                    max_pic_average_light_level: track_io!(reader.read_u16::<BigEndian>())?,
+                });
+            }
+            fourcc::DVCC => {
+                let configuration = track!(DoviDecoderConfigurationRecord::read_from(reader))?;
+                self.dvcc_box = Some(DolbyVisionConfigurationBox { configuration });
+            }
+            fourcc::DVVC => {
+                let configuration = track!(DoviDecoderConfigurationRecord::read_from(reader))?;
+                self.dvvc_box = Some(DolbyVisionExtendedConfigurationBox { configuration });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Mastering Display Colour Volume Box (ISO/IEC 14496-12, SMPTE ST 2086).
+///
+/// Chromaticity coordinates are in increments of 0.00002 and luminances are in increments of 0.0001 cd/m^2.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct MasteringDisplayColourVolumeBox {
+    /// `(x, y)` pairs of the display primaries (G, B, R order).
+    pub display_primaries: [(u16, u16); 3],
+    pub white_point: (u16, u16),
+    pub max_display_mastering_luminance: u32,
+    pub min_display_mastering_luminance: u32,
+}
+impl Mp4Box for MasteringDisplayColourVolumeBox {
+    const BOX_TYPE: [u8; 4] = fourcc::MDCV;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(24)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        for &(x, y) in &self.display_primaries {
+            write_u16!(writer, x);
+            write_u16!(writer, y);
+        }
+        write_u16!(writer, self.white_point.0);
+        write_u16!(writer, self.white_point.1);
+        write_u32!(writer, self.max_display_mastering_luminance);
+        write_u32!(writer, self.min_display_mastering_luminance);
+        Ok(())
+    }
+}
+
+/// Content Light Level Box (ISO/IEC 14496-12, CTA-861.3).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct ContentLightLevelBox {
+    pub max_content_light_level: u16,
+    pub max_pic_average_light_level: u16,
+}
+impl Mp4Box for ContentLightLevelBox {
+    const BOX_TYPE: [u8; 4] = fourcc::CLLI;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(4)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u16!(writer, self.max_content_light_level);
+        write_u16!(writer, self.max_pic_average_light_level);
+        Ok(())
+    }
+}
+
+/// Dolby Vision decoder configuration record.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DoviDecoderConfigurationRecord {
+    pub dv_version_major: u8,
+    pub dv_version_minor: u8,
+    pub dv_profile: u8, // u7
+    pub dv_level: u8,   // u6
+    pub rpu_present_flag: bool,
+    pub el_present_flag: bool,
+    pub bl_present_flag: bool,
+    pub dv_bl_signal_compatibility_id: u8, // u4
+}
+impl DoviDecoderConfigurationRecord {
+    fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(self.dv_profile <= 0x7F, ErrorKind::InvalidInput; self.dv_profile);
+        track_assert!(self.dv_level <= 0x3F, ErrorKind::InvalidInput; self.dv_level);
+        track_assert!(
+            self.dv_bl_signal_compatibility_id <= 0xF,
+            ErrorKind::InvalidInput;
+            self.dv_bl_signal_compatibility_id
+        );
+
+        write_u8!(writer, self.dv_version_major);
+        write_u8!(writer, self.dv_version_minor);
+        write_u16!(
+            writer,
+            (u16::from(self.dv_profile) << 9)
+                | (u16::from(self.dv_level) << 3)
+                | ((self.rpu_present_flag as u16) << 2)
+                | ((self.el_present_flag as u16) << 1)
+                | self.bl_present_flag as u16
+        );
+        write_u32!(writer, u32::from(self.dv_bl_signal_compatibility_id) << 28);
+        write_zeroes!(writer, 16);
+        Ok(())
+    }
+
+    fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let dv_version_major = track_io!(reader.read_u8())?;
+        let dv_version_minor = track_io!(reader.read_u8())?;
+        let n = track_io!(reader.read_u16::<BigEndian>())?;
+        let m = track_io!(reader.read_u32::<BigEndian>())?;
+        Ok(DoviDecoderConfigurationRecord {
+            dv_version_major,
+            dv_version_minor,
+            dv_profile: (n >> 9) as u8,
+            dv_level: ((n >> 3) & 0b11_1111) as u8,
+            rpu_present_flag: (n & 0b100) != 0,
+            el_present_flag: (n & 0b010) != 0,
+            bl_present_flag: (n & 0b001) != 0,
+            dv_bl_signal_compatibility_id: (m >> 28) as u8,
+        })
+    }
+}
+
+/// Dolby Vision Configuration Box (used for profiles up to 7).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct DolbyVisionConfigurationBox {
+    pub configuration: DoviDecoderConfigurationRecord,
+}
+impl Mp4Box for DolbyVisionConfigurationBox {
+    const BOX_TYPE: [u8; 4] = fourcc::DVCC;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(24)
+    }
+    fn write_box_payload<W: Write>(&self, writer: W) -> Result<()> {
+        track_assert!(self.configuration.dv_profile <= 7, ErrorKind::InvalidInput; self.configuration.dv_profile);
+        track!(self.configuration.write_to(writer))
+    }
+}
+
+/// Dolby Vision Extended Configuration Box (used for profiles 8 to 10).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct DolbyVisionExtendedConfigurationBox {
+    pub configuration: DoviDecoderConfigurationRecord,
+}
+impl Mp4Box for DolbyVisionExtendedConfigurationBox {
+    const BOX_TYPE: [u8; 4] = fourcc::DVVC;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(24)
+    }
+    fn write_box_payload<W: Write>(&self, writer: W) -> Result<()> {
+        track_assert!(
+            self.configuration.dv_profile > 7 && self.configuration.dv_profile <= 10,
+            ErrorKind::InvalidInput;
+            self.configuration.dv_profile
+        );
+        track!(self.configuration.write_to(writer))
+    }
+}
+
 /// Sample Entry for AAC.
 #[allow(missing_docs)]
 #[derive(Debug)]
 pub struct AacSampleEntry {
     pub esds_box: Mpeg4EsDescriptorBox,
+    pub srat_box: Option<SamplingRateBox>,
 }
 impl AacSampleEntry {
     fn write_box_payload_without_esds<W: Write>(&self, mut writer: W) -> Result<()> {
         write_zeroes!(writer, 6);
         write_u16!(writer, 1); // data_reference_index
 
-        let channels = self.esds_box.channel_configuration as u16;
-        let sample_rate = self.esds_box.frequency.as_u32();
-        write_zeroes!(writer, 8);
-        track_assert!(channels == 1 || channels == 2, ErrorKind::Unsupported);
-        track_assert!(sample_rate <= 0xFFFF, ErrorKind::InvalidInput);
+        let channels = track_assert_some!(
+            self.esds_box.channel_configuration.channel_count(),
+            ErrorKind::Unsupported,
+            "Channel configuration via an inband PCE is not supported"
+        );
+        write_u16!(writer, self.entry_version()); // entry_version (reserved in version 0)
+        write_zeroes!(writer, 6);
 
         write_u16!(writer, channels);
         write_u16!(writer, 16);
         write_zeroes!(writer, 4);
-        write_u16!(writer, sample_rate as u16);
+        write_u16!(writer, track!(self.sample_rate_field())?);
         write_zeroes!(writer, 2);
         Ok(())
     }
+
+    /// Returns `1` if this entry is written as an `AudioSampleEntryV1` (i.e., it has a `srat` box),
+    /// `0` otherwise.
+    fn entry_version(&self) -> u16 {
+        if self.srat_box.is_some() {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Returns the integer part of `samplerate` field.
+    ///
+    /// A version 0 entry carries the actual sampling rate, so it must fit in 16 bits.
+    /// In a version 1 entry the rate is divided by the smallest integer that makes it fit
+    /// (the actual rate is conveyed by the `srat` box).
+    fn sample_rate_field(&self) -> Result<u16> {
+        let sample_rate = self.esds_box.frequency.as_u32();
+        if self.srat_box.is_none() {
+            track_assert!(
+                sample_rate <= 0xFFFF,
+                ErrorKind::InvalidInput,
+                "Sampling rate {} needs a `srat` box",
+                sample_rate
+            );
+            return Ok(sample_rate as u16);
+        }
+
+        let mut divisor = 1;
+        while sample_rate / divisor > 0xFFFF {
+            divisor += 1;
+        }
+        Ok((sample_rate / divisor) as u16)
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        // reserved, data_reference_index, entry_version, reserved,
+        // channelcount, samplesize, pre_defined, reserved, samplerate
+        let mut children = b.payload;
+        track!(isobmff::skip_bytes(&mut children, 6 + 2))?;
+        let entry_version = track_io!(children.read_u16::<BigEndian>())?;
+        track_assert!(
+            entry_version <= 1,
+            ErrorKind::Unsupported,
+            "Unsupported audio sample entry version: {}",
+            entry_version
+        );
+        track!(isobmff::skip_bytes(&mut children, 6 + 2 + 2 + 2 + 2 + 4))?;
+
+        let mut esds_box = None;
+        let mut srat_box = None;
+        for child in isobmff::Boxes::new(children) {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::ESDS => {
+                    esds_box = Some(track!(Mpeg4EsDescriptorBox::from_raw_box(&child))?)
+                }
+                fourcc::SRAT => {
+                    let mut reader = track!(child.full_box())?.payload;
+                    let sampling_rate = track_io!(reader.read_u32::<BigEndian>())?;
+                    srat_box = Some(SamplingRateBox { sampling_rate });
+                }
+                _ => {}
+            }
+        }
+        if entry_version == 0 {
+            // `srat` is only defined for `AudioSampleEntryV1`
+            srat_box = None;
+        }
+        Ok(AacSampleEntry {
+            esds_box: track_assert_some!(esds_box, ErrorKind::InvalidInput, "No `esds` box"),
+            srat_box,
+        })
+    }
 }
 impl Mp4Box for AacSampleEntry {
-    const BOX_TYPE: [u8; 4] = *b"mp4a";
+    const BOX_TYPE: [u8; 4] = fourcc::MP4A;
 
     fn box_payload_size(&self) -> Result<u32> {
         let mut size = 0;
@@ -853,15 +2621,40 @@ impl Mp4Box for AacSampleEntry {
             |w| self.write_box_payload_without_esds(w)
         ))? as u32;
         size += box_size!(self.esds_box);
+        size += optional_box_size!(self.srat_box);
         Ok(size)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
         track!(self.write_box_payload_without_esds(&mut writer))?;
+        if let Some(ref x) = self.srat_box {
+            write_box!(writer, x);
+        }
         write_box!(writer, self.esds_box);
         Ok(())
     }
 }
 
+/// 12.2.3 Sampling Rate Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub struct SamplingRateBox {
+    pub sampling_rate: u32,
+}
+impl Mp4Box for SamplingRateBox {
+    const BOX_TYPE: [u8; 4] = fourcc::SRAT;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(4)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u32!(writer, self.sampling_rate);
+        Ok(())
+    }
+}
+
 /// MPEG-4 ES Description Box (ISO/IEC 14496-1).
 #[allow(missing_docs)]
 #[derive(Debug)]
@@ -869,49 +2662,147 @@ pub struct Mpeg4EsDescriptorBox {
     pub profile: AacProfile,
     pub frequency: SamplingFrequency,
     pub channel_configuration: ChannelConfiguration,
+
+    /// `ES_ID` field of the ES descriptor.
+    pub es_id: u16,
+
+    /// `streamPriority` field of the ES descriptor (5 bits).
+    pub stream_priority: u8,
+}
+impl Mpeg4EsDescriptorBox {
+    fn es_descriptor(&self) -> Result<EsDescriptor> {
+        let config = AudioSpecificConfig {
+            profile: self.profile,
+            sampling_frequency: self.frequency,
+            channel_configuration: self.channel_configuration,
+        };
+        let mut data = Vec::new();
+        track!(config.write_to(&mut data))?;
+
+        Ok(EsDescriptor {
+            es_id: self.es_id,
+            stream_priority: self.stream_priority,
+            dec_config_descr: DecoderConfigDescriptor {
+                object_type_indication: 0x40, // Audio ISO/IEC 14496-3
+                stream_type: 5,               // audio
+                up_stream: false,
+                buffer_size_db: 0,
+                max_bitrate: 0,
+                avg_bitrate: 0,
+                dec_specific_info: Some(DecoderSpecificInfo { data }),
+            },
+            sl_config_descr: SlConfigDescriptor::mp4(),
+        })
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let payload = track!(b.full_box())?.payload;
+        let es_descriptor = track!(EsDescriptor::read_from(payload))?;
+        let dec_config_descr = es_descriptor.dec_config_descr;
+        track_assert_eq!(
+            dec_config_descr.object_type_indication,
+            0x40,
+            ErrorKind::Unsupported
+        );
+        let dec_specific_info = track_assert_some!(
+            dec_config_descr.dec_specific_info,
+            ErrorKind::InvalidInput,
+            "No decoder specific info"
+        );
+        let config = track!(AudioSpecificConfig::read_from(&dec_specific_info.data[..]))?;
+        Ok(Mpeg4EsDescriptorBox {
+            profile: config.profile,
+            frequency: config.sampling_frequency,
+            channel_configuration: config.channel_configuration,
+            es_id: es_descriptor.es_id,
+            stream_priority: es_descriptor.stream_priority,
+        })
+    }
 }
 impl Mp4Box for Mpeg4EsDescriptorBox {
-    const BOX_TYPE: [u8; 4] = *b"esds";
+    const BOX_TYPE: [u8; 4] = fourcc::ESDS;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
     }
     fn box_payload_size(&self) -> Result<u32> {
-        let size = track!(ByteCounter::calculate(|w| self.write_box_payload(w)))?;
-        Ok(size as u32)
+        let es_descriptor = track!(self.es_descriptor())?;
+        track!(es_descriptor.descriptor_size())
     }
-    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        // es descriptor
-        write_u8!(writer, 0x03); // descriptor_tag=es
-        write_u8!(writer, 25); // descriptor_len
-        write_u16!(writer, 0); // es_id
-        write_u8!(writer, 0); // stream_priority and flags
+    fn write_box_payload<W: Write>(&self, writer: W) -> Result<()> {
+        let es_descriptor = track!(self.es_descriptor())?;
+        track!(es_descriptor.write_descriptor(writer))
+    }
+}
 
-        // decoder configuration descriptor
-        write_u8!(writer, 0x04); // descriptor_tag=decoder_configuration
-        write_u8!(writer, 17); // descriptor_len
+/// Converts a 64-bit duration into 32-bit (the all-ones value, indicating an unknown duration, is preserved).
+fn duration_to_u32(n: u64) -> Result<u32> {
+    if n == u64::MAX {
+        Ok(u32::MAX)
+    } else {
+        track_assert!(n <= u64::from(u32::MAX), ErrorKind::Unsupported; n);
+        Ok(n as u32)
+    }
+}
 
-        write_u8!(writer, 0x40); // object_type
-        write_u8!(writer, (5 << 2) | 1); // stream_type=audio=5, upstream=0, reserved=1
-        write_u24!(writer, 0); // buffer_size
-        write_u32!(writer, 0); // max_bitrate
-        write_u32!(writer, 0); // avg_bitrate
+/// 6.5 WebVTT Sample Entry (ISO/IEC 14496-30).
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default)]
+pub struct WebVttSampleEntry {
+    pub vttc_box: WebVttConfigurationBox,
+}
+impl WebVttSampleEntry {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut children = b.payload;
+        track!(isobmff::skip_bytes(&mut children, 6 + 2))?; // reserved, data_reference_index
+
+        let mut vttc_box = WebVttConfigurationBox::default();
+        for child in isobmff::Boxes::new(children) {
+            let child = track!(child)?;
+            if child.box_type == fourcc::VTTC_CONFIG {
+                vttc_box.config = String::from_utf8_lossy(child.payload).into_owned();
+            }
+        }
+        Ok(WebVttSampleEntry { vttc_box })
+    }
+}
+impl Mp4Box for WebVttSampleEntry {
+    const BOX_TYPE: [u8; 4] = fourcc::WVTT;
 
-        // decoder specific info
-        write_u8!(writer, 0x05); // descriptor_tag=decoder_specific_info
-        write_u8!(writer, 2); // descriptor_len
-        write_u16!(
-            writer,
-            ((self.profile as u16 + 1) << 11)
-                | (u16::from(self.frequency.as_index()) << 7)
-                | ((self.channel_configuration as u16) << 3)
-        );
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(6 + 2 + box_size!(self.vttc_box))
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_zeroes!(writer, 6);
+        write_u16!(writer, 1); // data_reference_index
+        write_box!(writer, self.vttc_box);
+        Ok(())
+    }
+}
 
-        // sl configuration descriptor
-        write_u8!(writer, 0x06); // descriptor_tag=es_configuration_descriptor
-        write_u8!(writer, 1); // descriptor_len
-        write_u8!(writer, 2); // MP4
+/// 6.5 WebVTT Configuration Box (ISO/IEC 14496-30).
+///
+/// `config` is the header of a WebVTT file (e.g., `"WEBVTT"`) without cues.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct WebVttConfigurationBox {
+    pub config: String,
+}
+impl Default for WebVttConfigurationBox {
+    fn default() -> Self {
+        WebVttConfigurationBox {
+            config: "WEBVTT".to_owned(),
+        }
+    }
+}
+impl Mp4Box for WebVttConfigurationBox {
+    const BOX_TYPE: [u8; 4] = fourcc::VTTC_CONFIG;
 
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(self.config.len() as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_all!(writer, self.config.as_bytes());
         Ok(())
     }
 }
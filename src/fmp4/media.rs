@@ -1,141 +1,1871 @@
-use crate::fmp4::{Mp4Box, AUDIO_TRACK_ID, VIDEO_TRACK_ID};
-use crate::io::{ByteCounter, WriteTo};
+use crate::error::try_cast;
+use crate::fmp4::encryption::seig_iv_sizes;
+use crate::fmp4::{
+    InitializationSegment, MediaSegmentBuilder, Mp4Box, AUDIO_TRACK_ID, TEXT_TRACK_ID,
+    VIDEO_TRACK_ID,
+};
+use crate::fourcc;
+use crate::io::WriteTo;
+use crate::isobmff::{self, RawBox};
+use crate::time::MediaTime;
 use crate::{ErrorKind, Result};
-use std::io::Write;
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::time::Duration;
 
 /// [ISO BMFF Byte Stream Format: 4. Media Segments][media_segment]
 ///
 /// [media_segment]: https://w3c.github.io/media-source/isobmff-byte-stream-format.html#iso-media-segments
 #[allow(missing_docs)]
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct MediaSegment {
     pub moof_box: MovieFragmentBox,
     pub mdat_boxes: Vec<MediaDataBox>,
 }
+impl MediaSegment {
+    /// Makes a media segment that represents an empty period (e.g., signal loss) for each of the given track fragments.
+    ///
+    /// The segment has no `mdat` box. See also `TrackFragmentBox::new_gap`.
+    pub fn new_gap(sequence_number: u32, traf_boxes: Vec<TrackFragmentBox>) -> Self {
+        MediaSegment {
+            moof_box: MovieFragmentBox {
+                mfhd_box: MovieFragmentHeaderBox { sequence_number },
+                traf_boxes,
+            },
+            mdat_boxes: Vec::new(),
+        }
+    }
+
+    /// Reads the first `moof` box in the given bytes and the `mdat` boxes following it (up to the next `moof` box).
+    ///
+    /// The other boxes (e.g., `styp`, `sidx` and `emsg`) are skipped, and
+    /// the base data offsets of the track fragments are made relative to the `moof` box.
+    pub fn read_from(bytes: &[u8]) -> Result<Self> {
+        track!(Self::read_with_optional_iv_size(bytes, None))
+    }
+
+    /// Reads a media segment like `read_from`, using `per_sample_iv_size` as the size of the
+    /// initialization vectors in the `senc` boxes that no `seig` sample group covers
+    /// (i.e., the `default_Per_Sample_IV_Size` of the `tenc` box in the initialization segment).
+    pub fn read_with_iv_size(bytes: &[u8], per_sample_iv_size: u8) -> Result<Self> {
+        track!(Self::read_with_optional_iv_size(
+            bytes,
+            Some(per_sample_iv_size)
+        ))
+    }
+
+    fn read_with_optional_iv_size(bytes: &[u8], per_sample_iv_size: Option<u8>) -> Result<Self> {
+        let mut segment: Option<MediaSegment> = None;
+        for b in isobmff::Boxes::new(bytes) {
+            let b = track!(b)?;
+            match (b.box_type, segment.as_mut()) {
+                (fourcc::MOOF, None) => {
+                    let mut moof_box =
+                        track!(MovieFragmentBox::from_raw_box(&b, per_sample_iv_size))?;
+                    for traf in &mut moof_box.traf_boxes {
+                        if let Some(offset) = traf.tfhd_box.base_data_offset.as_mut() {
+                            *offset = track_assert_some!(
+                                offset.checked_sub(b.offset as u64),
+                                ErrorKind::InvalidInput
+                            );
+                        }
+                    }
+                    segment = Some(MediaSegment {
+                        moof_box,
+                        mdat_boxes: Vec::new(),
+                    });
+                }
+                (fourcc::MOOF, Some(_)) => break,
+                (fourcc::MDAT, Some(segment)) => segment.mdat_boxes.push(MediaDataBox {
+                    data: b.payload.to_owned(),
+                }),
+                _ => {}
+            }
+        }
+        Ok(track_assert_some!(
+            segment,
+            ErrorKind::InvalidInput,
+            "No `moof` box"
+        ))
+    }
+
+    /// Returns `true` if none of the track fragments in this segment has samples.
+    pub fn is_gap(&self) -> bool {
+        self.moof_box.traf_boxes.iter().all(|t| t.is_empty())
+    }
+
+    /// Writes this segment to `writer` using vectored writes.
+    ///
+    /// The box headers are serialized into a small buffer and the payloads of `mdat` boxes are
+    /// passed to `Write::write_vectored` as is, instead of being copied.
+    pub fn write_vectored_to<W: Write>(&self, writer: W) -> Result<()> {
+        track_assert!(
+            !self.mdat_boxes.is_empty() || self.is_gap(),
+            ErrorKind::InvalidInput
+        );
+        let mut headers = Vec::new();
+        write_box!(headers, self.moof_box);
+        let moof_size = headers.len();
+        for mdat in &self.mdat_boxes {
+            write_u32!(headers, track!(mdat.box_size())?);
+            write_all!(headers, &MediaDataBox::BOX_TYPE);
+        }
+
+        let mut bufs = vec![&headers[..moof_size]];
+        for (i, mdat) in self.mdat_boxes.iter().enumerate() {
+            let header_start = moof_size + i * 8;
+            bufs.push(&headers[header_start..header_start + 8]);
+            bufs.push(&mdat.data[..]);
+        }
+        track!(crate::io::write_all_vectored(writer, &bufs))
+    }
+
+    /// Returns the presentation timing of each track fragment in the segment.
+    ///
+    /// The times are in the timescale of each track (i.e., the `timescale` of its `mdhd` box),
+    /// and edit lists are not taken into account.
+    /// Samples without durations must be covered by the `default_sample_duration` of the `tfhd` box.
+    pub fn track_timings(&self) -> Result<Vec<TrackTiming>> {
+        let mut timings = Vec::with_capacity(self.moof_box.traf_boxes.len());
+        for traf in &self.moof_box.traf_boxes {
+            timings.push(track!(traf.timing())?);
+        }
+        Ok(timings)
+    }
+
+    /// Returns the duration of each track fragment in the segment as pairs of the track identifier and the duration.
+    ///
+    /// The durations are in the timescales of the tracks in `initialization_segment`, so they can be used
+    /// both for `sidx` boxes (`MediaTime::value`) and for playlists (`MediaTime::to_duration`).
+    /// Unlike `TrackFragmentBox::duration`, samples without durations in the `trun` and `tfhd` boxes
+    /// are given the `default_sample_duration` of the `trex` box of the track.
+    pub fn duration_per_track(
+        &self,
+        initialization_segment: &InitializationSegment,
+    ) -> Result<Vec<(u32, MediaTime)>> {
+        let moov_box = &initialization_segment.moov_box;
+        let mut durations = Vec::with_capacity(self.moof_box.traf_boxes.len());
+        for traf in &self.moof_box.traf_boxes {
+            let track_id = traf.tfhd_box.track_id;
+            let trak = track_assert_some!(
+                moov_box
+                    .trak_boxes
+                    .iter()
+                    .find(|t| t.tkhd_box.track_id() == track_id),
+                ErrorKind::InvalidInput,
+                "Unknown track: track_id={}",
+                track_id
+            );
+            let default_sample_duration = moov_box
+                .mvex_box
+                .trex_boxes
+                .iter()
+                .find(|t| t.track_id() == track_id)
+                .map(|t| t.default_sample_duration);
+            let timing = track!(traf.timing_with_default(default_sample_duration))?;
+            let timescale = trak.mdia_box.mdhd_box.timescale;
+            durations.push((track_id, MediaTime::new(timing.duration, timescale)));
+        }
+        Ok(durations)
+    }
+
+    /// Returns the sync samples (keyframes) of the track fragment of `track_id` in this segment.
+    ///
+    /// The byte offsets are relative to the beginning of the segment (i.e., the `moof` box), so
+    /// callers can build their own index (e.g., for thumbnails or trick play) without reparsing the bitstream.
+    /// Samples without flags are regarded as sync samples.
+    pub fn sync_samples(&self, track_id: u32) -> Result<Vec<SyncSample>> {
+        let mut sync_samples = Vec::new();
+        let data_ranges = track!(self.trun_data_ranges())?;
+        for (traf, ranges) in self.moof_box.traf_boxes.iter().zip(data_ranges) {
+            let tfhd = &traf.tfhd_box;
+            let mut decode_time = traf.tfdt_box.base_media_decode_time;
+            let mut sample_index = 0;
+            for (trun, range) in traf.trun_boxes.iter().zip(ranges) {
+                let mut offset = range.start;
+                for (j, sample) in trun.samples.iter().enumerate() {
+                    let size = sample.size.or(tfhd.default_sample_size).unwrap_or(0);
+                    let duration = track_assert_some!(
+                        sample.duration.or(tfhd.default_sample_duration),
+                        ErrorKind::InvalidInput,
+                        "No sample duration: track_id={}",
+                        tfhd.track_id
+                    );
+                    let flags = sample
+                        .flags
+                        .as_ref()
+                        .or(if j == 0 {
+                            trun.first_sample_flags.as_ref()
+                        } else {
+                            None
+                        })
+                        .or(tfhd.default_sample_flags.as_ref());
+                    let is_sync = !matches!(flags, Some(f) if f.sample_is_non_sync_sample);
+                    if tfhd.track_id == track_id && is_sync {
+                        sync_samples.push(SyncSample {
+                            sample_index,
+                            offset,
+                            size,
+                            decode_time,
+                            presentation_time: decode_time as i64
+                                + i64::from(sample.composition_time_offset.unwrap_or(0)),
+                        });
+                    }
+                    sample_index += 1;
+                    offset += u64::from(size);
+                    decode_time += u64::from(duration);
+                }
+            }
+        }
+        Ok(sync_samples)
+    }
+
+    /// Returns an iterator over the samples of all track fragments in this segment
+    /// (in the order of the track fragments and then of the `trun` boxes).
+    ///
+    /// The defaults of the `tfhd` box and the data offsets of the `trun` boxes are resolved,
+    /// so each item has the timestamps and the payload of a sample.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mse_fmp4::fmp4::{MediaSegmentBuilder, Sample, TrackFragmentBox};
+    ///
+    /// let mut traf = TrackFragmentBox::new(false);
+    /// traf.tfhd_box.default_sample_duration = Some(1024);
+    /// let mut builder = MediaSegmentBuilder::new(1);
+    /// let audio = builder.add_track_fragment(traf).unwrap();
+    /// let samples = vec![Sample { size: Some(2), ..Sample::default() }; 2];
+    /// builder.add_run(audio, None, samples, vec![0, 1, 2, 3]).unwrap();
+    /// let segment = builder.finish().unwrap();
+    ///
+    /// let samples = segment.samples().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    /// assert_eq!(samples[1].decode_time, 1024);
+    /// assert_eq!(samples[1].data, [2, 3]);
+    /// ```
+    pub fn samples(&self) -> Result<SegmentSamples<'_>> {
+        Ok(SegmentSamples {
+            segment: self,
+            data_ranges: track!(self.trun_data_ranges())?,
+            payload_ranges: track!(self.mdat_payload_ranges())?,
+            traf_index: 0,
+            trun_index: 0,
+            sample_index: 0,
+            trun_sample_index: 0,
+            offset: 0,
+            decode_time: 0,
+        })
+    }
+
+    /// Splits this segment into the media segments each of which has only one of the track fragments
+    /// (see also `InitializationSegment::into_track_segments`).
+    ///
+    /// The segments are returned in the order of the track fragments.
+    /// The samples of each track fragment are moved into its own `mdat` box and the data offsets are adjusted accordingly.
+    pub fn into_track_segments(self) -> Result<Vec<Self>> {
+        let sequence_number = self.moof_box.mfhd_box.sequence_number;
+        let mut segments = Vec::with_capacity(self.moof_box.traf_boxes.len());
+        for track in track!(self.into_track_data())? {
+            segments.push(track!(Self::from_track_data(sequence_number, vec![track]))?);
+        }
+        Ok(segments)
+    }
+
+    /// Drops the track fragments of the tracks other than `track_ids` (and their samples) from this segment.
+    ///
+    /// The samples of the remaining track fragments are packed into a single `mdat` box
+    /// and the data offsets are adjusted accordingly.
+    pub fn retain_tracks(&mut self, track_ids: &[u32]) -> Result<()> {
+        let sequence_number = self.moof_box.mfhd_box.sequence_number;
+        let mut tracks = track!(std::mem::take(self).into_track_data())?;
+        tracks.retain(|t| track_ids.contains(&t.traf.tfhd_box.track_id));
+        *self = track!(Self::from_track_data(sequence_number, tracks))?;
+        Ok(())
+    }
+
+    /// Writes the `tfdt` boxes of this segment in version 1 (64-bit decode times)
+    /// regardless of their values (some packagers and players expect this).
+    ///
+    /// As the size of the `moof` box may change, the data offsets are adjusted accordingly.
+    pub fn force_tfdt_version_1(&mut self) -> Result<()> {
+        let old_moof_size = track!(self.moof_box.box_size())?;
+        for traf in &mut self.moof_box.traf_boxes {
+            traf.tfdt_box.force_version_1 = true;
+        }
+        track!(self.shift_data_offsets(old_moof_size))
+    }
+
+    /// Adjusts the data offsets of the `trun` boxes to the new size of the `moof` box
+    /// whose size was `old_moof_size`.
+    pub(crate) fn shift_data_offsets(&mut self, old_moof_size: u32) -> Result<()> {
+        let delta = i64::from(track!(self.moof_box.box_size())?) - i64::from(old_moof_size);
+        for traf in &mut self.moof_box.traf_boxes {
+            for trun in &mut traf.trun_boxes {
+                if let Some(offset) = trun.data_offset.as_mut() {
+                    let new_offset = i64::from(*offset) + delta;
+                    track_assert!(
+                        new_offset >= i64::from(i32::MIN) && new_offset <= i64::from(i32::MAX),
+                        ErrorKind::InvalidInput
+                    );
+                    *offset = new_offset as i32;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `traf` to this segment and stores its sample data in a new `mdat` box following the existing ones.
+    ///
+    /// The samples of the `trun` boxes of `traf` must be laid out in order in `data`.
+    /// The data offsets of the new track fragment are set, and those of the existing ones are
+    /// adjusted to the new size of the `moof` box, so any number of track fragments
+    /// (e.g., subtitles or secondary audio) can be added one after another.
+    pub fn push_track_fragment(&mut self, traf: TrackFragmentBox, data: Vec<u8>) -> Result<()> {
+        let mut builder = track!(MediaSegmentBuilder::from_segment(std::mem::take(self)))?;
+        builder.start_mdat();
+        track!(builder.add_track_fragment_with_data(traf, data))?;
+        *self = track!(builder.finish())?;
+        Ok(())
+    }
+
+    /// Rearranges the sample data of this segment into `mdat` boxes as specified by `layout`.
+    ///
+    /// Except for `MdatLayout::PerTrack`, the relative order of the `trun` boxes in the byte stream
+    /// is kept (e.g., interleaved chunks stay interleaved).
+    /// The data offsets are adjusted accordingly and made relative to the `moof` box.
+    pub fn set_mdat_layout(&mut self, layout: MdatLayout) -> Result<()> {
+        let data_ranges = track!(self.trun_data_ranges())?;
+        let payload_ranges = track!(self.mdat_payload_ranges())?;
+        let mut runs = Vec::new();
+        for (traf_index, ranges) in data_ranges.into_iter().enumerate() {
+            for (trun_index, range) in ranges.into_iter().enumerate() {
+                runs.push((traf_index, trun_index, range));
+            }
+        }
+        if layout != MdatLayout::PerTrack {
+            runs.sort_by_key(|r| r.2.start);
+        }
+
+        // `(traf_index, trun_index, mdat_index, position)`
+        let mut positions = Vec::with_capacity(runs.len());
+        let mut mdat_boxes: Vec<MediaDataBox> = Vec::new();
+        for (i, (traf_index, trun_index, range)) in runs.iter().enumerate() {
+            let starts_new_mdat = match layout {
+                MdatLayout::PerTrack => i == 0 || runs[i - 1].0 != *traf_index,
+                MdatLayout::Single => i == 0,
+                MdatLayout::PerRun => true,
+            };
+            if starts_new_mdat {
+                mdat_boxes.push(MediaDataBox { data: Vec::new() });
+            }
+            let track_id = self.moof_box.traf_boxes[*traf_index].tfhd_box.track_id;
+            let data = track!(self.mdat_data(&payload_ranges, range.clone(), track_id))?;
+            let mdat_index = mdat_boxes.len() - 1;
+            let mdat = &mut mdat_boxes[mdat_index];
+            positions.push((*traf_index, *trun_index, mdat_index, mdat.data.len()));
+            mdat.data.extend_from_slice(data);
+        }
+
+        let builder = MediaSegmentBuilder::from_parts(
+            self.moof_box.mfhd_box.sequence_number,
+            std::mem::take(&mut self.moof_box.traf_boxes),
+            mdat_boxes,
+            positions,
+        );
+        *self = track!(builder.finish())?;
+        Ok(())
+    }
+
+    /// Detaches the sample data of each track fragment from the `mdat` boxes.
+    fn into_track_data(self) -> Result<Vec<TrackData>> {
+        let data_ranges = track!(self.trun_data_ranges())?;
+        let payload_ranges = track!(self.mdat_payload_ranges())?;
+        let mut detached = Vec::with_capacity(self.moof_box.traf_boxes.len());
+        for (traf, ranges) in self.moof_box.traf_boxes.iter().zip(data_ranges) {
+            let mut data = Vec::new();
+            for range in ranges {
+                let track_id = traf.tfhd_box.track_id;
+                data.extend_from_slice(track!(self.mdat_data(&payload_ranges, range, track_id))?);
+            }
+            detached.push(data);
+        }
+        Ok(self
+            .moof_box
+            .traf_boxes
+            .into_iter()
+            .zip(detached)
+            .map(|(traf, data)| TrackData { traf, data })
+            .collect())
+    }
+
+    /// Returns the segment-relative byte ranges of the payloads of the `mdat` boxes.
+    pub(crate) fn mdat_payload_ranges(&self) -> Result<Vec<Range<u64>>> {
+        let mut payload_ranges = Vec::with_capacity(self.mdat_boxes.len());
+        let mut position = u64::from(track!(self.moof_box.box_size())?);
+        for mdat in &self.mdat_boxes {
+            let header_size = if mdat.needs_large_size() { 16 } else { 8 };
+            payload_ranges.push(position + header_size..position + mdat.large_box_size());
+            position += mdat.large_box_size();
+        }
+        Ok(payload_ranges)
+    }
+
+    /// Returns the bytes of the `mdat` boxes in the segment-relative byte range `range`.
+    fn mdat_data(
+        &self,
+        payload_ranges: &[Range<u64>],
+        range: Range<u64>,
+        track_id: u32,
+    ) -> Result<&[u8]> {
+        let (i, payload) = track_assert_some!(
+            payload_ranges
+                .iter()
+                .enumerate()
+                .find(|(_, p)| p.start <= range.start && range.end <= p.end),
+            ErrorKind::InvalidInput,
+            "Samples out of `mdat` boxes: track_id={}, range={:?}",
+            track_id,
+            range
+        );
+        let start = (range.start - payload.start) as usize;
+        let end = (range.end - payload.start) as usize;
+        Ok(&self.mdat_boxes[i].data[start..end])
+    }
+
+    /// Makes a segment that has the given track fragments and a single `mdat` box containing their sample data.
+    fn from_track_data(sequence_number: u32, tracks: Vec<TrackData>) -> Result<Self> {
+        let mut builder = MediaSegmentBuilder::new(sequence_number);
+        builder.start_mdat();
+        for track in tracks {
+            track!(builder.add_track_fragment_with_data(track.traf, track.data))?;
+        }
+        track!(builder.finish())
+    }
+
+    /// Returns the segment-relative byte ranges of the samples of each `trun` box (grouped by track fragments).
+    pub(crate) fn trun_data_ranges(&self) -> Result<Vec<Vec<Range<u64>>>> {
+        let mut data_ranges = Vec::with_capacity(self.moof_box.traf_boxes.len());
+        let mut data_end = 0;
+        for traf in &self.moof_box.traf_boxes {
+            let tfhd = &traf.tfhd_box;
+            let base_data_offset = match tfhd.base_data_offset {
+                Some(offset) => Some(offset),
+                None if tfhd.default_base_is_moof => Some(0),
+                None => None,
+            };
+            let mut ranges = Vec::with_capacity(traf.trun_boxes.len());
+            for (i, trun) in traf.trun_boxes.iter().enumerate() {
+                let start = match (trun.data_offset, base_data_offset) {
+                    (Some(data_offset), base) => {
+                        let offset = base.unwrap_or(data_end) as i64 + i64::from(data_offset);
+                        track!(try_cast(offset, "Sample data offset"))?
+                    }
+                    (None, Some(base)) if i == 0 => base,
+                    (None, _) => data_end,
+                };
+                let mut end = start;
+                for sample in &trun.samples {
+                    let size = track_assert_some!(
+                        sample.size.or(tfhd.default_sample_size),
+                        ErrorKind::InvalidInput,
+                        "No sample size: track_id={}",
+                        tfhd.track_id
+                    );
+                    end += u64::from(size);
+                }
+                ranges.push(start..end);
+                data_end = end;
+            }
+            data_ranges.push(ranges);
+        }
+        Ok(data_ranges)
+    }
+
+    /// Returns the byte size, duration and bitrate of this segment and of each of its track fragments.
+    ///
+    /// The timescales of the tracks are taken from `initialization_segment`.
+    /// This is useful for ABR packagers to record accurate `BANDWIDTH` values.
+    pub fn size_report(
+        &self,
+        initialization_segment: &InitializationSegment,
+    ) -> Result<SegmentSizeReport> {
+        let mut tracks = Vec::with_capacity(self.moof_box.traf_boxes.len());
+        for traf in &self.moof_box.traf_boxes {
+            let track_id = traf.tfhd_box.track_id;
+            let trak = track_assert_some!(
+                initialization_segment
+                    .moov_box
+                    .trak_boxes
+                    .iter()
+                    .find(|t| t.tkhd_box.track_id() == track_id),
+                ErrorKind::InvalidInput,
+                "Unknown track: track_id={}",
+                track_id
+            );
+            let timescale = trak.mdia_box.mdhd_box.timescale;
+            track_assert_ne!(timescale, 0, ErrorKind::InvalidInput);
+
+            let timing = track!(traf.timing())?;
+            let mut size = 0;
+            for sample in traf.trun_boxes.iter().flat_map(|t| t.samples.iter()) {
+                let sample_size = track_assert_some!(
+                    sample.size.or(traf.tfhd_box.default_sample_size),
+                    ErrorKind::InvalidInput,
+                    "No sample size: track_id={}",
+                    track_id
+                );
+                size += u64::from(sample_size);
+            }
+            let duration = MediaTime::new(timing.duration, timescale).to_duration();
+            tracks.push(TrackSizeReport {
+                track_id,
+                size,
+                duration,
+                bitrate: bitrate(size, duration),
+            });
+        }
+
+        let size = track!(self.total_size())?;
+        let duration = tracks.iter().map(|t| t.duration).max().unwrap_or_default();
+        Ok(SegmentSizeReport {
+            size,
+            duration,
+            bitrate: bitrate(size, duration),
+            tracks,
+        })
+    }
+
+    /// Returns a compact tree of the boxes in this segment (type, size and number of children) for debugging.
+    ///
+    /// The payloads of `mdat` boxes are not copied.
+    /// See also [`isobmff::summarize`](../isobmff/fn.summarize.html).
+    pub fn summary(&self) -> Result<String> {
+        let moof_size = track!(self.moof_box.box_size())?;
+        let mut moof = Vec::with_capacity(moof_size as usize);
+        write_box!(moof, self.moof_box);
+        track_assert_eq!(moof.len(), moof_size as usize, ErrorKind::Other);
+
+        let mut summary = track!(isobmff::summarize(&moof))?;
+        for mdat in &self.mdat_boxes {
+            let size = mdat.large_box_size();
+            isobmff::summary_line(&mut summary, 0, MediaDataBox::BOX_TYPE, size, None);
+        }
+        Ok(summary)
+    }
+
+    /// Writes this segment to `writer`, using the 64-bit `largesize` field for `mdat` boxes larger than 4 GiB.
+    ///
+    /// MSE byte stream parsers do not accept such boxes, so `WriteTo::write_to` (and `write_vectored_to`)
+    /// fail with `ErrorKind::SegmentTooLarge` instead.
+    /// This method is intended for files (e.g., self-initializing files written by `SegmentWriter`).
+    pub fn write_large_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(
+            !self.mdat_boxes.is_empty() || self.is_gap(),
+            ErrorKind::InvalidInput
+        );
+        write_box!(writer, self.moof_box);
+        for mdat in &self.mdat_boxes {
+            track!(mdat.write_large_box(&mut writer))?;
+        }
+        Ok(())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        let mut size = u64::from(track!(self.moof_box.box_size())?);
+        for mdat in &self.mdat_boxes {
+            size += mdat.large_box_size();
+        }
+        Ok(size)
+    }
+}
 impl WriteTo for MediaSegment {
     fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
-        track_assert!(!self.mdat_boxes.is_empty(), ErrorKind::InvalidInput);
+        track_assert!(
+            !self.mdat_boxes.is_empty() || self.is_gap(),
+            ErrorKind::InvalidInput
+        );
         write_box!(writer, self.moof_box);
         write_boxes!(writer, &self.mdat_boxes);
         Ok(())
     }
 }
 
+/// Arrangement of the sample data of a media segment into `mdat` boxes.
+///
+/// Players and CDNs differ in their preferences, and the layout also determines
+/// the byte ranges that address the samples of each track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MdatLayout {
+    /// One `mdat` box for each track fragment.
+    #[default]
+    PerTrack,
+
+    /// A single `mdat` box containing the samples of all track fragments.
+    Single,
+
+    /// One `mdat` box for each `trun` box (i.e., each chunk of samples).
+    PerRun,
+}
+
+/// 5.10.3.3 Event Message Box (ISO/IEC 23009-1).
+///
+/// The box is written in version 1 (i.e., `presentation_time` is an absolute time in `timescale`).
+/// It is placed before the `moof` box of a media segment (see `SplicedSegment`).
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventMessageBox {
+    pub scheme_id_uri: String,
+    pub value: String,
+    pub timescale: u32,
+    pub presentation_time: u64,
+    pub event_duration: u32,
+    pub id: u32,
+    pub message_data: Vec<u8>,
+}
+impl Mp4Box for EventMessageBox {
+    const BOX_TYPE: [u8; 4] = fourcc::EMSG;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(1)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        let size = 4 + 8 + 4 + 4 + self.scheme_id_uri.len() + 1 + self.value.len() + 1;
+        track!(try_cast(
+            size + self.message_data.len(),
+            "Event message size"
+        ))
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(
+            !self.scheme_id_uri.contains('\0') && !self.value.contains('\0'),
+            ErrorKind::InvalidInput
+        );
+        write_u32!(writer, self.timescale);
+        write_u64!(writer, self.presentation_time);
+        write_u32!(writer, self.event_duration);
+        write_u32!(writer, self.id);
+        write_all!(writer, self.scheme_id_uri.as_bytes());
+        write_u8!(writer, 0);
+        write_all!(writer, self.value.as_bytes());
+        write_u8!(writer, 0);
+        write_all!(writer, &self.message_data);
+        Ok(())
+    }
+}
+
+/// 8.16.2 Segment Type Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct SegmentTypeBox {
+    pub major_brand: [u8; 4],
+    pub minor_version: u32,
+    pub compatible_brands: Vec<[u8; 4]>,
+}
+impl Default for SegmentTypeBox {
+    /// Returns a `styp` box for indexed media segments (`msdh` and `msix` brands).
+    fn default() -> Self {
+        SegmentTypeBox {
+            major_brand: fourcc::MSDH,
+            minor_version: 0,
+            compatible_brands: vec![fourcc::MSDH, fourcc::MSIX],
+        }
+    }
+}
+impl SegmentTypeBox {
+    /// Reads the first `styp` box in the given bytes (not recursive).
+    ///
+    /// `Ok(None)` is returned if there is no such box.
+    pub fn read_from(bytes: &[u8]) -> Result<Option<Self>> {
+        match track!(isobmff::find_box(bytes, Self::BOX_TYPE))? {
+            None => Ok(None),
+            Some(b) => track!(Self::from_raw_box(&b)).map(Some),
+        }
+    }
+
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let mut reader = b.payload;
+        let mut major_brand = [0; 4];
+        track_io!(reader.read_exact(&mut major_brand))?;
+        let minor_version = track_io!(reader.read_u32::<BigEndian>())?;
+        track_assert_eq!(reader.len() % 4, 0, ErrorKind::InvalidInput);
+        let compatible_brands = reader.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+        Ok(SegmentTypeBox {
+            major_brand,
+            minor_version,
+            compatible_brands,
+        })
+    }
+}
+impl Mp4Box for SegmentTypeBox {
+    const BOX_TYPE: [u8; 4] = fourcc::STYP;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(4 + 4 + 4 * self.compatible_brands.len() as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_all!(writer, &self.major_brand);
+        write_u32!(writer, self.minor_version);
+        for brand in &self.compatible_brands {
+            write_all!(writer, brand);
+        }
+        Ok(())
+    }
+}
+
+/// 8.16.3 Segment Index Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct SegmentIndexBox {
+    pub reference_id: u32,
+    pub timescale: u32,
+    pub earliest_presentation_time: u64,
+    pub first_offset: u64,
+    pub references: Vec<SegmentReference>,
+}
+impl SegmentIndexBox {
+    /// Reads the first `sidx` box in the given bytes (not recursive).
+    ///
+    /// `Ok(None)` is returned if there is no such box.
+    /// Use [`isobmff::Boxes`](../isobmff/struct.Boxes.html) and `from_raw_box` to read the rest of
+    /// the boxes of hierarchical or daisy-chained indexes.
+    pub fn read_from(bytes: &[u8]) -> Result<Option<Self>> {
+        match track!(isobmff::find_box(bytes, Self::BOX_TYPE))? {
+            None => Ok(None),
+            Some(b) => track!(Self::from_raw_box(&b)).map(Some),
+        }
+    }
+
+    /// Reads a `sidx` box (version 0 or 1).
+    pub fn from_raw_box(b: &RawBox) -> Result<Self> {
+        track_assert_eq!(b.box_type, Self::BOX_TYPE, ErrorKind::InvalidInput);
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let reference_id = track_io!(reader.read_u32::<BigEndian>())?;
+        let timescale = track_io!(reader.read_u32::<BigEndian>())?;
+        let (earliest_presentation_time, first_offset) = if full_box.version == 0 {
+            (
+                u64::from(track_io!(reader.read_u32::<BigEndian>())?),
+                u64::from(track_io!(reader.read_u32::<BigEndian>())?),
+            )
+        } else {
+            (
+                track_io!(reader.read_u64::<BigEndian>())?,
+                track_io!(reader.read_u64::<BigEndian>())?,
+            )
+        };
+        let _reserved = track_io!(reader.read_u16::<BigEndian>())?;
+        let reference_count = track_io!(reader.read_u16::<BigEndian>())?;
+        let mut references = Vec::with_capacity(usize::from(reference_count));
+        for _ in 0..reference_count {
+            let n = track_io!(reader.read_u32::<BigEndian>())?;
+            let subsegment_duration = track_io!(reader.read_u32::<BigEndian>())?;
+            let m = track_io!(reader.read_u32::<BigEndian>())?;
+            references.push(SegmentReference {
+                reference_type: (n >> 31) == 1,
+                referenced_size: n & 0x7FFF_FFFF,
+                subsegment_duration,
+                starts_with_sap: (m >> 31) == 1,
+                sap_type: ((m >> 28) & 0b111) as u8,
+                sap_delta_time: m & 0x0FFF_FFFF,
+            });
+        }
+        Ok(SegmentIndexBox {
+            reference_id,
+            timescale,
+            earliest_presentation_time,
+            first_offset,
+            references,
+        })
+    }
+}
+impl Mp4Box for SegmentIndexBox {
+    const BOX_TYPE: [u8; 4] = fourcc::SIDX;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(1)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(4 + 4 + 8 + 8 + 2 + 2 + 12 * self.references.len() as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(self.references.len() <= 0xFFFF, ErrorKind::InvalidInput);
+        write_u32!(writer, self.reference_id);
+        write_u32!(writer, self.timescale);
+        write_u64!(writer, self.earliest_presentation_time);
+        write_u64!(writer, self.first_offset);
+        write_u16!(writer, 0); // reserved
+        write_u16!(writer, self.references.len() as u16);
+        for r in &self.references {
+            track_assert!(r.referenced_size < (1 << 31), ErrorKind::InvalidInput; r.referenced_size);
+            track_assert!(r.sap_type < 8, ErrorKind::InvalidInput; r.sap_type);
+            track_assert!(r.sap_delta_time < (1 << 28), ErrorKind::InvalidInput; r.sap_delta_time);
+            write_u32!(
+                writer,
+                ((r.reference_type as u32) << 31) | r.referenced_size
+            );
+            write_u32!(writer, r.subsegment_duration);
+            write_u32!(
+                writer,
+                ((r.starts_with_sap as u32) << 31)
+                    | (u32::from(r.sap_type) << 28)
+                    | r.sap_delta_time
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A reference entry of `SegmentIndexBox`.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SegmentReference {
+    /// `true` if the reference points to another `sidx` box (otherwise, a media content).
+    pub reference_type: bool,
+    pub referenced_size: u32, // u31
+    pub subsegment_duration: u32,
+    pub starts_with_sap: bool,
+    pub sap_type: u8,        // u3
+    pub sap_delta_time: u32, // u28
+}
+
+/// 8.8.9 Movie Fragment Random Access Box (ISO/IEC 14496-12).
+///
+/// The trailing `mfro` box is generated automatically when the box is written.
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone)]
+pub struct MovieFragmentRandomAccessBox {
+    pub tfra_boxes: Vec<TrackFragmentRandomAccessBox>,
+}
+impl Mp4Box for MovieFragmentRandomAccessBox {
+    const BOX_TYPE: [u8; 4] = fourcc::MFRA;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 0;
+        size += boxes_size!(self.tfra_boxes);
+        size += 16; // mfro
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_boxes!(writer, &self.tfra_boxes);
+
+        // mfro
+        write_u32!(writer, 16);
+        write_all!(writer, &fourcc::MFRO);
+        write_u32!(writer, 0); // version and flags
+        write_u32!(writer, track!(self.box_size())?);
+        Ok(())
+    }
+}
+
+/// 8.8.10 Track Fragment Random Access Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct TrackFragmentRandomAccessBox {
+    pub track_id: u32,
+    pub entries: Vec<RandomAccessEntry>,
+}
+impl Mp4Box for TrackFragmentRandomAccessBox {
+    const BOX_TYPE: [u8; 4] = fourcc::TFRA;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(1)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(4 + 4 + 4 + (8 + 8 + 4 * 3) * self.entries.len() as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u32!(writer, self.track_id);
+        // length_size_of_traf_num, length_size_of_trun_num and length_size_of_sample_num (4 bytes each)
+        write_u32!(writer, 0b11_1111);
+        write_u32!(writer, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_u64!(writer, entry.time);
+            write_u64!(writer, entry.moof_offset);
+            write_u32!(writer, entry.traf_number);
+            write_u32!(writer, entry.trun_number);
+            write_u32!(writer, entry.sample_number);
+        }
+        Ok(())
+    }
+}
+
+/// An entry of `TrackFragmentRandomAccessBox`.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RandomAccessEntry {
+    /// Presentation time of the sync sample (in the timescale of the track).
+    pub time: u64,
+
+    /// Offset of the `moof` box that contains the sync sample from the beginning of the file.
+    pub moof_offset: u64,
+
+    /// Position (1-origin) of the `traf` box in the `moof` box.
+    pub traf_number: u32,
+
+    /// Position (1-origin) of the `trun` box in the `traf` box.
+    pub trun_number: u32,
+
+    /// Position (1-origin) of the sample in the `trun` box.
+    pub sample_number: u32,
+}
+
 /// 8.1.1 Media Data Box (ISO/IEC 14496-12).
 #[allow(missing_docs)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MediaDataBox {
     pub data: Vec<u8>,
 }
-impl Mp4Box for MediaDataBox {
-    const BOX_TYPE: [u8; 4] = *b"mdat";
+impl MediaDataBox {
+    /// Returns `true` if the size of this box cannot be represented with the 32-bit `size` field.
+    pub fn needs_large_size(&self) -> bool {
+        needs_large_size(self.data.len() as u64)
+    }
+
+    /// Returns the size of this box, including the 64-bit `largesize` field if `needs_large_size()` is `true`.
+    pub fn large_box_size(&self) -> u64 {
+        Self::large_box_size_for(self.data.len() as u64)
+    }
+
+    /// Returns the size of an `mdat` box whose payload is `payload_size` bytes,
+    /// including the 64-bit `largesize` field if the 32-bit `size` field cannot represent it.
+    pub fn large_box_size_for(payload_size: u64) -> u64 {
+        if needs_large_size(payload_size) {
+            payload_size.saturating_add(16)
+        } else {
+            payload_size + 8
+        }
+    }
+
+    /// Writes the header (8 or 16 bytes) of an `mdat` box whose payload is `payload_size` bytes.
+    ///
+    /// The payload can then be streamed to `writer` without being held in memory.
+    pub fn write_large_box_header<W: Write>(mut writer: W, payload_size: u64) -> Result<()> {
+        let size = Self::large_box_size_for(payload_size);
+        if needs_large_size(payload_size) {
+            write_u32!(writer, 1);
+            write_all!(writer, &Self::BOX_TYPE);
+            write_u64!(writer, size);
+        } else {
+            write_u32!(writer, size as u32);
+            write_all!(writer, &Self::BOX_TYPE);
+        }
+        Ok(())
+    }
+
+    /// Writes this box to the given writer, using the 64-bit `largesize` field if `needs_large_size()` is `true`.
+    ///
+    /// Note that `Mp4Box::write_box` fails with `ErrorKind::SegmentTooLarge` in that case.
+    pub fn write_large_box<W: Write>(&self, mut writer: W) -> Result<()> {
+        track!(Self::write_large_box_header(
+            &mut writer,
+            self.data.len() as u64
+        ))?;
+        write_all!(writer, &self.data);
+        Ok(())
+    }
+}
+impl Mp4Box for MediaDataBox {
+    const BOX_TYPE: [u8; 4] = fourcc::MDAT;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        track_assert!(
+            !self.needs_large_size(),
+            ErrorKind::SegmentTooLarge,
+            "mdat payload is too large to be written without `largesize`: {} bytes",
+            self.data.len()
+        );
+        Ok(self.data.len() as u32)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_all!(writer, &self.data);
+        Ok(())
+    }
+}
+
+fn needs_large_size(mdat_payload_size: u64) -> bool {
+    mdat_payload_size > u64::from(u32::MAX) - 8
+}
+
+/// 8.8.4 Movie Fragment Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default)]
+pub struct MovieFragmentBox {
+    pub mfhd_box: MovieFragmentHeaderBox,
+    pub traf_boxes: Vec<TrackFragmentBox>,
+}
+impl MovieFragmentBox {
+    pub(crate) fn from_raw_box(b: &RawBox, per_sample_iv_size: Option<u8>) -> Result<Self> {
+        let mut mfhd_box = None;
+        let mut traf_boxes = Vec::new();
+        for child in b.children() {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::MFHD => {
+                    let mut reader = track!(child.full_box())?.payload;
+                    let sequence_number = track_io!(reader.read_u32::<BigEndian>())?;
+                    mfhd_box = Some(MovieFragmentHeaderBox { sequence_number });
+                }
+                fourcc::TRAF => traf_boxes.push(track!(TrackFragmentBox::from_raw_box(
+                    &child,
+                    per_sample_iv_size
+                ))?),
+                _ => {}
+            }
+        }
+        Ok(MovieFragmentBox {
+            mfhd_box: track_assert_some!(mfhd_box, ErrorKind::InvalidInput, "No `mfhd` box"),
+            traf_boxes,
+        })
+    }
+}
+impl Mp4Box for MovieFragmentBox {
+    const BOX_TYPE: [u8; 4] = fourcc::MOOF;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 0;
+        size += box_size!(self.mfhd_box);
+        size += boxes_size!(self.traf_boxes);
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(!self.traf_boxes.is_empty(), ErrorKind::InvalidInput);
+        write_box!(writer, self.mfhd_box);
+        write_boxes!(writer, &self.traf_boxes);
+        Ok(())
+    }
+}
+
+/// A track fragment and its sample data detached from the `mdat` boxes
+/// (the samples of the `trun` boxes are laid out in order in `data`).
+#[derive(Debug)]
+struct TrackData {
+    traf: TrackFragmentBox,
+    data: Vec<u8>,
+}
+
+/// 8.8.5 Movie Fragment Header Box (ISO/IEC 14496-12).
+#[derive(Debug, Clone)]
+pub struct MovieFragmentHeaderBox {
+    /// The number associated with this fragment.
+    pub sequence_number: u32,
+}
+impl Mp4Box for MovieFragmentHeaderBox {
+    const BOX_TYPE: [u8; 4] = fourcc::MFHD;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(4)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_u32!(writer, self.sequence_number);
+        Ok(())
+    }
+}
+impl Default for MovieFragmentHeaderBox {
+    /// Return the default value of `MovieFragmentHeaderBox`.
+    ///
+    /// This is equivalent to `MovieFragmentHeaderBox { sequence_number: 1 }`.
+    fn default() -> Self {
+        MovieFragmentHeaderBox { sequence_number: 1 }
+    }
+}
+
+/// 8.8.6 Track Fragment Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct TrackFragmentBox {
+    pub tfhd_box: TrackFragmentHeaderBox,
+    pub tfdt_box: TrackFragmentBaseMediaDecodeTimeBox,
+
+    /// Track run boxes (this replaces the single `trun_box` field of version 0.2).
+    pub trun_boxes: Vec<TrackRunBox>,
+    pub sbgp_boxes: Vec<SampleToGroupBox>,
+    pub sgpd_boxes: Vec<SampleGroupDescriptionBox>,
+    pub senc_box: Option<SampleEncryptionBox>,
+}
+impl TrackFragmentBox {
+    /// Makes a new `TrackFragmentBox` instance.
+    pub fn new(is_video: bool) -> Self {
+        let track_id = if is_video {
+            VIDEO_TRACK_ID
+        } else {
+            AUDIO_TRACK_ID
+        };
+        TrackFragmentBox {
+            tfhd_box: TrackFragmentHeaderBox::new(track_id),
+            tfdt_box: TrackFragmentBaseMediaDecodeTimeBox::default(),
+            trun_boxes: Vec::new(),
+            sbgp_boxes: Vec::new(),
+            sgpd_boxes: Vec::new(),
+            senc_box: None,
+        }
+    }
+
+    /// Makes a new `TrackFragmentBox` instance of the text track made by `TrackBox::new_text`.
+    pub fn new_text() -> Self {
+        let mut traf = Self::new(true);
+        traf.tfhd_box.track_id = TEXT_TRACK_ID;
+        traf
+    }
+
+    /// Makes a new `TrackFragmentBox` instance that has no samples and represents
+    /// an empty period (e.g., signal loss) of `duration` starting at `base_media_decode_time`.
+    ///
+    /// The `duration_is_empty` flag of the `tfhd` box is set and no `trun` boxes are included,
+    /// so the decode times of the following fragments stay continuous.
+    pub fn new_gap(is_video: bool, base_media_decode_time: u64, duration: u32) -> Self {
+        let mut traf = Self::new(is_video);
+        traf.tfhd_box.duration_is_empty = true;
+        traf.tfhd_box.default_sample_duration = Some(duration);
+        traf.tfdt_box.base_media_decode_time = base_media_decode_time;
+        traf
+    }
+
+    /// Returns `true` if this track fragment has no samples.
+    pub fn is_empty(&self) -> bool {
+        self.trun_boxes.iter().all(|t| t.samples.is_empty())
+    }
+
+    /// Returns the duration of this track fragment in the timescale of the track (i.e., the sum of the sample durations).
+    ///
+    /// Samples without durations must be covered by the `default_sample_duration` of the `tfhd` box.
+    /// Use `MediaSegment::duration_per_track` to fall back on the defaults of the `trex` boxes.
+    /// For a fragment representing an empty period (see `new_gap`), the default sample duration is returned.
+    pub fn duration(&self) -> Result<u64> {
+        track!(self.timing_with_default(None)).map(|t| t.duration)
+    }
+
+    /// Reads the first `traf` box in the given bytes (e.g., the payload of a `moof` box).
+    ///
+    /// Besides the `tfhd`, `tfdt` and `trun` boxes, sample groups (`sbgp` and `sgpd`) and
+    /// sample encryption information (`senc`) are read, so that encrypted or grouped fragments
+    /// can be inspected and written again. Other child boxes are discarded.
+    pub fn read_from(bytes: &[u8]) -> Result<Self> {
+        let traf = track!(isobmff::find_box(bytes, Self::BOX_TYPE))?;
+        let traf = track_assert_some!(traf, ErrorKind::InvalidInput, "No `traf` box");
+        track!(Self::from_raw_box(&traf, None))
+    }
+
+    /// Reads the first `traf` box like `read_from`, using `per_sample_iv_size` as the size of the
+    /// initialization vectors in the `senc` box if no `seig` sample group covers the samples.
+    pub fn read_with_iv_size(bytes: &[u8], per_sample_iv_size: u8) -> Result<Self> {
+        let traf = track!(isobmff::find_box(bytes, Self::BOX_TYPE))?;
+        let traf = track_assert_some!(traf, ErrorKind::InvalidInput, "No `traf` box");
+        track!(Self::from_raw_box(&traf, Some(per_sample_iv_size)))
+    }
+
+    pub(crate) fn from_raw_box(b: &RawBox, per_sample_iv_size: Option<u8>) -> Result<Self> {
+        let mut tfhd_box = None;
+        let mut tfdt_box = None;
+        let mut trun_boxes = Vec::new();
+        let mut sbgp_boxes = Vec::new();
+        let mut sgpd_boxes = Vec::new();
+        let mut senc = None;
+        for child in b.children() {
+            let child = track!(child)?;
+            match child.box_type {
+                fourcc::TFHD => {
+                    tfhd_box = Some(track!(TrackFragmentHeaderBox::from_raw_box(&child))?)
+                }
+                fourcc::TFDT => {
+                    tfdt_box = Some(track!(TrackFragmentBaseMediaDecodeTimeBox::from_raw_box(
+                        &child
+                    ))?)
+                }
+                fourcc::TRUN => trun_boxes.push(track!(TrackRunBox::from_raw_box(&child))?),
+                fourcc::SBGP => sbgp_boxes.push(track!(SampleToGroupBox::from_raw_box(&child))?),
+                fourcc::SGPD => {
+                    sgpd_boxes.push(track!(SampleGroupDescriptionBox::from_raw_box(&child))?)
+                }
+                fourcc::SENC => senc = Some(child),
+                _ => {}
+            }
+        }
+        let senc_box = match senc {
+            None => None,
+            Some(senc) => {
+                let sample_count = trun_boxes.iter().map(|t| t.samples.len()).sum();
+                let iv_sizes = track!(seig_iv_sizes(&sbgp_boxes, &sgpd_boxes, sample_count))?;
+                Some(track!(SampleEncryptionBox::from_raw_box(
+                    &senc,
+                    sample_count,
+                    iv_sizes.as_deref(),
+                    per_sample_iv_size
+                ))?)
+            }
+        };
+        Ok(TrackFragmentBox {
+            tfhd_box: track_assert_some!(tfhd_box, ErrorKind::InvalidInput, "No `tfhd` box"),
+            tfdt_box: tfdt_box.unwrap_or_default(),
+            trun_boxes,
+            sbgp_boxes,
+            sgpd_boxes,
+            senc_box,
+        })
+    }
+}
+impl TrackFragmentBox {
+    /// Returns `false` if the first sample of this track fragment is known to be a non-sync sample.
+    ///
+    /// If no flags are specified for the sample, the defaults in `trex` are unknown here and `true` is returned.
+    pub(crate) fn starts_with_sync_sample(&self) -> bool {
+        let first_trun = match self.trun_boxes.first() {
+            None => return false,
+            Some(trun) => trun,
+        };
+        let flags = first_trun
+            .first_sample_flags
+            .as_ref()
+            .or_else(|| first_trun.samples.first().and_then(|s| s.flags.as_ref()))
+            .or(self.tfhd_box.default_sample_flags.as_ref());
+        !matches!(flags, Some(f) if f.sample_is_non_sync_sample)
+    }
+
+    pub(crate) fn timing(&self) -> Result<TrackTiming> {
+        track!(self.timing_with_default(None))
+    }
+
+    /// Same as `timing` except that `default_sample_duration` (e.g., that of the `trex` box) is used
+    /// for samples whose durations are specified neither in the `trun` box nor in the `tfhd` box.
+    pub(crate) fn timing_with_default(
+        &self,
+        default_sample_duration: Option<u32>,
+    ) -> Result<TrackTiming> {
+        let default_sample_duration = self
+            .tfhd_box
+            .default_sample_duration
+            .or(default_sample_duration);
+        let base_media_decode_time = self.tfdt_box.base_media_decode_time;
+        let mut decode_time = base_media_decode_time;
+        let mut earliest_presentation_time = None;
+        for sample in self.trun_boxes.iter().flat_map(|t| t.samples.iter()) {
+            let presentation_time =
+                decode_time as i64 + i64::from(sample.composition_time_offset.unwrap_or(0));
+            earliest_presentation_time = Some(match earliest_presentation_time {
+                Some(t) if t <= presentation_time => t,
+                _ => presentation_time,
+            });
+
+            let duration = sample.duration.or(default_sample_duration);
+            let duration = track_assert_some!(
+                duration,
+                ErrorKind::InvalidInput,
+                "No sample duration: track_id={}",
+                self.tfhd_box.track_id
+            );
+            decode_time = track_assert_some!(
+                decode_time.checked_add(u64::from(duration)),
+                ErrorKind::InvalidInput
+            );
+        }
+        if self.tfhd_box.duration_is_empty && self.is_empty() {
+            let duration = default_sample_duration.unwrap_or(0);
+            decode_time = track_assert_some!(
+                decode_time.checked_add(u64::from(duration)),
+                ErrorKind::InvalidInput
+            );
+        }
+        Ok(TrackTiming {
+            track_id: self.tfhd_box.track_id,
+            base_media_decode_time,
+            earliest_presentation_time: earliest_presentation_time
+                .unwrap_or(base_media_decode_time as i64),
+            duration: decode_time - base_media_decode_time,
+        })
+    }
+}
+impl Mp4Box for TrackFragmentBox {
+    const BOX_TYPE: [u8; 4] = fourcc::TRAF;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 0;
+        size += box_size!(self.tfhd_box);
+        size += box_size!(self.tfdt_box);
+        size += boxes_size!(self.trun_boxes);
+        size += boxes_size!(self.sbgp_boxes);
+        size += boxes_size!(self.sgpd_boxes);
+        size += optional_box_size!(self.senc_box);
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_box!(writer, self.tfhd_box);
+        write_box!(writer, self.tfdt_box);
+        write_boxes!(writer, &self.trun_boxes);
+        write_boxes!(writer, &self.sbgp_boxes);
+        write_boxes!(writer, &self.sgpd_boxes);
+        if let Some(ref x) = self.senc_box {
+            write_box!(writer, x);
+        }
+        Ok(())
+    }
+}
+
+/// 8.9.2 Sample to Group Box (ISO/IEC 14496-12).
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct SampleToGroupBox {
+    pub grouping_type: [u8; 4],
+    pub entries: Vec<SampleToGroupEntry>,
+}
+impl SampleToGroupBox {
+    /// Reads a `sbgp` box (the `grouping_type_parameter` of version 1 is discarded).
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let mut grouping_type = [0; 4];
+        track_io!(reader.read_exact(&mut grouping_type))?;
+        if full_box.version == 1 {
+            let _grouping_type_parameter = track_io!(reader.read_u32::<BigEndian>())?;
+        }
+        let entry_count = track_io!(reader.read_u32::<BigEndian>())?;
+        track_assert!(
+            u64::from(entry_count) * 8 <= reader.len() as u64,
+            ErrorKind::InvalidInput;
+            entry_count
+        );
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            entries.push(SampleToGroupEntry {
+                sample_count: track_io!(reader.read_u32::<BigEndian>())?,
+                group_description_index: track_io!(reader.read_u32::<BigEndian>())?,
+            });
+        }
+        Ok(SampleToGroupBox {
+            grouping_type,
+            entries,
+        })
+    }
+}
+impl Mp4Box for SampleToGroupBox {
+    const BOX_TYPE: [u8; 4] = fourcc::SBGP;
 
+    fn box_version(&self) -> Option<u8> {
+        Some(0)
+    }
     fn box_payload_size(&self) -> Result<u32> {
-        Ok(self.data.len() as u32)
+        Ok(4 + 4 + 8 * self.entries.len() as u32)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        write_all!(writer, &self.data);
+        write_all!(writer, &self.grouping_type);
+        write_u32!(writer, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_u32!(writer, entry.sample_count);
+            write_u32!(writer, entry.group_description_index);
+        }
         Ok(())
     }
 }
 
-/// 8.8.4 Movie Fragment Box (ISO/IEC 14496-12).
+/// An entry of `SampleToGroupBox`.
 #[allow(missing_docs)]
-#[derive(Debug, Default)]
-pub struct MovieFragmentBox {
-    pub mfhd_box: MovieFragmentHeaderBox,
-    pub traf_boxes: Vec<TrackFragmentBox>,
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SampleToGroupEntry {
+    pub sample_count: u32,
+
+    /// Index (1-origin) of the group description, or `0` if the samples are not members of any group.
+    ///
+    /// Indices of the descriptions in the `sgpd` box of the same track fragment start at `0x10001`.
+    pub group_description_index: u32,
 }
-impl Mp4Box for MovieFragmentBox {
-    const BOX_TYPE: [u8; 4] = *b"moof";
 
+/// 8.9.3 Sample Group Description Box (ISO/IEC 14496-12).
+///
+/// The box is written in version 1. If all of the entries have the same size,
+/// it is used as `default_length`, otherwise the size is written before each entry.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct SampleGroupDescriptionBox {
+    pub grouping_type: [u8; 4],
+
+    /// Serialized sample group entries.
+    pub entries: Vec<Vec<u8>>,
+}
+impl SampleGroupDescriptionBox {
+    /// Makes a `roll` (or `prol` if `roll_distance` is positive) sample group description
+    /// that has a single `AudioRollRecoveryEntry`.
+    ///
+    /// A `roll_distance` of `-1` tells players that the preceding sample is needed to decode a sample
+    /// without artifacts (e.g., AAC).
+    pub fn roll(roll_distance: i16) -> Self {
+        SampleGroupDescriptionBox {
+            grouping_type: if roll_distance > 0 {
+                fourcc::PROL
+            } else {
+                fourcc::ROLL
+            },
+            entries: vec![roll_distance.to_be_bytes().to_vec()],
+        }
+    }
+
+    /// Reads a `sgpd` box.
+    ///
+    /// As the entry sizes of version 0 boxes depend on the grouping type,
+    /// the payload is split evenly among the entries.
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let mut grouping_type = [0; 4];
+        track_io!(reader.read_exact(&mut grouping_type))?;
+        let default_length = if full_box.version == 1 {
+            track_io!(reader.read_u32::<BigEndian>())?
+        } else {
+            0
+        };
+        if full_box.version >= 2 {
+            let _default_sample_description_index = track_io!(reader.read_u32::<BigEndian>())?;
+        }
+        let entry_count = track_io!(reader.read_u32::<BigEndian>())? as usize;
+        let fixed_entry_size = if full_box.version == 0 {
+            track_assert!(
+                entry_count != 0 && reader.len() / entry_count * entry_count == reader.len(),
+                ErrorKind::Unsupported,
+                "Unknown entry size: grouping_type={:?}",
+                String::from_utf8_lossy(&grouping_type)
+            );
+            Some(reader.len() / entry_count)
+        } else if default_length != 0 {
+            Some(default_length as usize)
+        } else {
+            None
+        };
+        let mut entries = Vec::new();
+        for _ in 0..entry_count {
+            let size = match fixed_entry_size {
+                Some(size) => size,
+                None => track_io!(reader.read_u32::<BigEndian>())? as usize,
+            };
+            track_assert!(size <= reader.len(), ErrorKind::InvalidInput; size, reader.len());
+            entries.push(reader[..size].to_vec());
+            reader = &reader[size..];
+        }
+        Ok(SampleGroupDescriptionBox {
+            grouping_type,
+            entries,
+        })
+    }
+
+    fn default_length(&self) -> u32 {
+        match self.entries.first() {
+            Some(first) if self.entries.iter().all(|e| e.len() == first.len()) => {
+                first.len() as u32
+            }
+            _ => 0,
+        }
+    }
+}
+impl Mp4Box for SampleGroupDescriptionBox {
+    const BOX_TYPE: [u8; 4] = fourcc::SGPD;
+
+    fn box_version(&self) -> Option<u8> {
+        Some(1)
+    }
     fn box_payload_size(&self) -> Result<u32> {
-        let mut size = 0;
-        size += box_size!(self.mfhd_box);
-        size += boxes_size!(self.traf_boxes);
+        let mut size = 4 + 4 + 4;
+        for entry in &self.entries {
+            if self.default_length() == 0 {
+                size += 4;
+            }
+            size += entry.len() as u32;
+        }
         Ok(size)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        track_assert!(!self.traf_boxes.is_empty(), ErrorKind::InvalidInput);
-        write_box!(writer, self.mfhd_box);
-        write_boxes!(writer, &self.traf_boxes);
+        let default_length = self.default_length();
+        write_all!(writer, &self.grouping_type);
+        write_u32!(writer, default_length);
+        write_u32!(writer, self.entries.len() as u32);
+        for entry in &self.entries {
+            if default_length == 0 {
+                write_u32!(writer, entry.len() as u32);
+            }
+            write_all!(writer, entry);
+        }
         Ok(())
     }
 }
 
-/// 8.8.5 Movie Fragment Header Box (ISO/IEC 14496-12).
-#[derive(Debug)]
-pub struct MovieFragmentHeaderBox {
-    /// The number associated with this fragment.
-    pub sequence_number: u32,
+/// 7.2 Sample Encryption Box (ISO/IEC 23001-7).
+///
+/// The size of the initialization vectors is not recorded in the box itself (it is specified by
+/// the `tenc` box or a sample group). The reader takes the sizes from the `seig` sample group
+/// of the track fragment if it covers all of the samples, or else the size given by the caller
+/// (see `MediaSegment::read_with_iv_size`). Without either, a single size (one of 0, 8 and 16 bytes)
+/// is inferred from the payload, and the box is rejected if more than one size fits it.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampleEncryptionBox {
+    /// If `true`, each entry has subsample information (i.e., the `UseSubSampleEncryption` flag).
+    pub use_subsamples: bool,
+    pub entries: Vec<SampleEncryptionEntry>,
 }
-impl Mp4Box for MovieFragmentHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"mfhd";
+impl SampleEncryptionBox {
+    fn from_raw_box(
+        b: &RawBox,
+        max_sample_count: usize,
+        iv_sizes: Option<&[usize]>,
+        per_sample_iv_size: Option<u8>,
+    ) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let use_subsamples = full_box.flags & 0x00_0002 != 0;
+        let mut reader = full_box.payload;
+        let sample_count = track_io!(reader.read_u32::<BigEndian>())? as usize;
+
+        // Entries may be empty (no subsamples and a constant IV), so the count is also bounded by the `trun` boxes
+        track_assert!(
+            sample_count <= max_sample_count,
+            ErrorKind::InvalidInput,
+            "Too many `senc` entries: sample_count={}, samples={}",
+            sample_count,
+            max_sample_count
+        );
+        if use_subsamples {
+            track_assert!(
+                sample_count <= reader.len() / 2,
+                ErrorKind::InvalidInput,
+                "Too many `senc` entries: sample_count={}, payload={} bytes",
+                sample_count,
+                reader.len()
+            );
+        }
+
+        let entries = if let Some(iv_sizes) = iv_sizes.filter(|s| s.len() == sample_count) {
+            track!(Self::read_entries(
+                reader,
+                sample_count,
+                |i| iv_sizes[i],
+                use_subsamples
+            ))?
+        } else if let Some(iv_size) = per_sample_iv_size {
+            track!(Self::read_entries(
+                reader,
+                sample_count,
+                |_| usize::from(iv_size),
+                use_subsamples
+            ))?
+        } else {
+            let mut candidates = [0, 8, 16].iter().filter_map(|&iv_size| {
+                Self::read_entries(reader, sample_count, |_| iv_size, use_subsamples).ok()
+            });
+            let entries = track_assert_some!(
+                candidates.next(),
+                ErrorKind::InvalidInput,
+                "Malformed `senc` box: sample_count={}",
+                sample_count
+            );
+            track_assert!(
+                candidates.all(|c| c == entries),
+                ErrorKind::InvalidInput,
+                "Ambiguous IV size of the `senc` box: sample_count={}",
+                sample_count
+            );
+            entries
+        };
+        Ok(SampleEncryptionBox {
+            use_subsamples,
+            entries,
+        })
+    }
+
+    fn read_entries<F: Fn(usize) -> usize>(
+        mut reader: &[u8],
+        sample_count: usize,
+        iv_size: F,
+        use_subsamples: bool,
+    ) -> Result<Vec<SampleEncryptionEntry>> {
+        let mut entries = Vec::new();
+        for i in 0..sample_count {
+            let iv_size = iv_size(i);
+            track_assert!(iv_size <= reader.len(), ErrorKind::InvalidInput);
+            let initialization_vector = reader[..iv_size].to_vec();
+            reader = &reader[iv_size..];
+            let mut subsamples = Vec::new();
+            if use_subsamples {
+                let subsample_count = track_io!(reader.read_u16::<BigEndian>())?;
+                for _ in 0..subsample_count {
+                    subsamples.push(Subsample {
+                        clear_bytes: track_io!(reader.read_u16::<BigEndian>())?,
+                        protected_bytes: track_io!(reader.read_u32::<BigEndian>())?,
+                    });
+                }
+            }
+            entries.push(SampleEncryptionEntry {
+                initialization_vector,
+                subsamples,
+            });
+        }
+        track_assert!(reader.is_empty(), ErrorKind::InvalidInput);
+        Ok(entries)
+    }
+}
+impl Mp4Box for SampleEncryptionBox {
+    const BOX_TYPE: [u8; 4] = fourcc::SENC;
 
     fn box_version(&self) -> Option<u8> {
         Some(0)
     }
+    fn box_flags(&self) -> Option<u32> {
+        Some(if self.use_subsamples { 0x00_0002 } else { 0 })
+    }
     fn box_payload_size(&self) -> Result<u32> {
-        Ok(4)
+        let mut size = 4;
+        for entry in &self.entries {
+            size += entry.initialization_vector.len() as u32;
+            if self.use_subsamples {
+                size += 2 + 6 * entry.subsamples.len() as u32;
+            }
+        }
+        Ok(size)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        write_u32!(writer, self.sequence_number);
+        write_u32!(writer, self.entries.len() as u32);
+        for entry in &self.entries {
+            write_all!(writer, &entry.initialization_vector);
+            if self.use_subsamples {
+                track_assert!(
+                    entry.subsamples.len() <= usize::from(u16::MAX),
+                    ErrorKind::InvalidInput
+                );
+                write_u16!(writer, entry.subsamples.len() as u16);
+                for subsample in &entry.subsamples {
+                    write_u16!(writer, subsample.clear_bytes);
+                    write_u32!(writer, subsample.protected_bytes);
+                }
+            }
+        }
         Ok(())
     }
 }
-impl Default for MovieFragmentHeaderBox {
-    /// Return the default value of `MovieFragmentHeaderBox`.
-    ///
-    /// This is equivalent to `MovieFragmentHeaderBox { sequence_number: 1 }`.
-    fn default() -> Self {
-        MovieFragmentHeaderBox { sequence_number: 1 }
-    }
+
+/// An entry of `SampleEncryptionBox`.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampleEncryptionEntry {
+    /// Per-sample initialization vector (empty if a constant IV is used).
+    pub initialization_vector: Vec<u8>,
+
+    /// Subsamples of the sample (used only if `SampleEncryptionBox::use_subsamples` is `true`).
+    pub subsamples: Vec<Subsample>,
 }
 
-/// 8.8.6 Track Fragment Box (ISO/IEC 14496-12).
+/// Ranges of clear and protected bytes in a sample.
 #[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subsample {
+    pub clear_bytes: u16,
+    pub protected_bytes: u32,
+}
+
+/// Sync sample (keyframe) of a track fragment in a media segment.
+///
+/// See [`MediaSegment::sync_samples`](struct.MediaSegment.html#method.sync_samples).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncSample {
+    /// Index of the sample in the track fragment (starting from zero).
+    pub sample_index: u32,
+
+    /// Byte offset of the sample data from the beginning of the media segment.
+    pub offset: u64,
+
+    /// Size of the sample data in bytes.
+    pub size: u32,
+
+    /// Decode time of the sample in the timescale of the track.
+    pub decode_time: u64,
+
+    /// Presentation time of the sample in the timescale of the track.
+    pub presentation_time: i64,
+}
+
+/// A sample in a media segment.
+///
+/// See [`MediaSegment::samples`](struct.MediaSegment.html#method.samples).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentSample<'a> {
+    /// Identifier of the track.
+    pub track_id: u32,
+
+    /// Index of the sample in the track fragment (starting from zero).
+    pub sample_index: u32,
+
+    /// Decode time of the sample in the timescale of the track.
+    pub decode_time: u64,
+
+    /// Presentation time of the sample in the timescale of the track.
+    pub presentation_time: i64,
+
+    /// Duration of the sample in the timescale of the track.
+    pub duration: u32,
+
+    /// `true` if the sample is a sync sample (samples without flags are regarded as sync samples).
+    pub is_sync: bool,
+
+    /// Byte offset of the sample data from the beginning of the media segment.
+    pub offset: u64,
+
+    /// Sample data.
+    pub data: &'a [u8],
+}
+
+/// An iterator over the samples in a media segment.
+///
+/// This is created by [`MediaSegment::samples`](struct.MediaSegment.html#method.samples).
 #[derive(Debug)]
-pub struct TrackFragmentBox {
-    pub tfhd_box: TrackFragmentHeaderBox,
-    pub tfdt_box: TrackFragmentBaseMediaDecodeTimeBox,
-    pub trun_box: TrackRunBox,
+pub struct SegmentSamples<'a> {
+    segment: &'a MediaSegment,
+    data_ranges: Vec<Vec<Range<u64>>>,
+    payload_ranges: Vec<Range<u64>>,
+    traf_index: usize,
+    trun_index: usize,
+    sample_index: u32,
+    trun_sample_index: usize,
+    offset: u64,
+    decode_time: u64,
 }
-impl TrackFragmentBox {
-    /// Makes a new `TrackFragmentBox` instance.
-    pub fn new(is_video: bool) -> Self {
-        let track_id = if is_video {
-            VIDEO_TRACK_ID
-        } else {
-            AUDIO_TRACK_ID
-        };
-        TrackFragmentBox {
-            tfhd_box: TrackFragmentHeaderBox::new(track_id),
-            tfdt_box: TrackFragmentBaseMediaDecodeTimeBox,
-            trun_box: TrackRunBox::default(),
+impl<'a> SegmentSamples<'a> {
+    fn next_sample(&mut self) -> Result<Option<SegmentSample<'a>>> {
+        let segment = self.segment;
+        loop {
+            let traf = match segment.moof_box.traf_boxes.get(self.traf_index) {
+                None => return Ok(None),
+                Some(traf) => traf,
+            };
+            if self.trun_index == traf.trun_boxes.len() {
+                self.traf_index += 1;
+                self.trun_index = 0;
+                self.sample_index = 0;
+                continue;
+            }
+            let trun = &traf.trun_boxes[self.trun_index];
+            if self.trun_sample_index == trun.samples.len() {
+                self.trun_index += 1;
+                self.trun_sample_index = 0;
+                continue;
+            }
+            if self.trun_sample_index == 0 {
+                self.offset = self.data_ranges[self.traf_index][self.trun_index].start;
+                if self.trun_index == 0 {
+                    self.decode_time = traf.tfdt_box.base_media_decode_time;
+                }
+            }
+
+            let tfhd = &traf.tfhd_box;
+            let sample = &trun.samples[self.trun_sample_index];
+            let size = sample.size.or(tfhd.default_sample_size).unwrap_or(0);
+            let duration = track_assert_some!(
+                sample.duration.or(tfhd.default_sample_duration),
+                ErrorKind::InvalidInput,
+                "No sample duration: track_id={}",
+                tfhd.track_id
+            );
+            let flags = sample
+                .flags
+                .as_ref()
+                .or(if self.trun_sample_index == 0 {
+                    trun.first_sample_flags.as_ref()
+                } else {
+                    None
+                })
+                .or(tfhd.default_sample_flags.as_ref());
+            let range = self.offset..self.offset + u64::from(size);
+            let data = track!(segment.mdat_data(&self.payload_ranges, range, tfhd.track_id))?;
+            let item = SegmentSample {
+                track_id: tfhd.track_id,
+                sample_index: self.sample_index,
+                decode_time: self.decode_time,
+                presentation_time: self.decode_time as i64
+                    + i64::from(sample.composition_time_offset.unwrap_or(0)),
+                duration,
+                is_sync: !matches!(flags, Some(f) if f.sample_is_non_sync_sample),
+                offset: self.offset,
+                data,
+            };
+            self.sample_index += 1;
+            self.trun_sample_index += 1;
+            self.offset += u64::from(size);
+            self.decode_time += u64::from(duration);
+            return Ok(Some(item));
         }
     }
 }
-impl Mp4Box for TrackFragmentBox {
-    const BOX_TYPE: [u8; 4] = *b"traf";
+impl<'a> Iterator for SegmentSamples<'a> {
+    type Item = Result<SegmentSample<'a>>;
 
-    fn box_payload_size(&self) -> Result<u32> {
-        let mut size = 0;
-        size += box_size!(self.tfhd_box);
-        size += box_size!(self.tfdt_box);
-        size += box_size!(self.trun_box);
-        Ok(size)
-    }
-    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        write_box!(writer, self.tfhd_box);
-        write_box!(writer, self.tfdt_box);
-        write_box!(writer, self.trun_box);
-        Ok(())
+    fn next(&mut self) -> Option<Self::Item> {
+        match track!(self.next_sample()) {
+            Err(e) => {
+                // Stops the iteration after an error
+                self.traf_index = self.segment.moof_box.traf_boxes.len();
+                Some(Err(e))
+            }
+            Ok(sample) => sample.map(Ok),
+        }
     }
 }
 
+/// Presentation timing of a track fragment in a media segment.
+///
+/// See [`MediaSegment::track_timings`](struct.MediaSegment.html#method.track_timings).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackTiming {
+    /// Track identifier.
+    pub track_id: u32,
+
+    /// Decode time of the first sample (i.e., the value of the `tfdt` box).
+    pub base_media_decode_time: u64,
+
+    /// The smallest presentation time among the samples.
+    pub earliest_presentation_time: i64,
+
+    /// Sum of the sample durations.
+    pub duration: u64,
+}
+
+/// Byte size, duration and bitrate of a media segment.
+///
+/// See [`MediaSegment::size_report`](struct.MediaSegment.html#method.size_report).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentSizeReport {
+    /// Total size of the `moof` and `mdat` boxes in bytes.
+    pub size: u64,
+
+    /// The longest duration among the track fragments.
+    pub duration: Duration,
+
+    /// Average bitrate (bits per second) computed from `size` and `duration` (`0` if `duration` is zero).
+    pub bitrate: u64,
+
+    /// Reports of the track fragments.
+    pub tracks: Vec<TrackSizeReport>,
+}
+
+/// Byte size, duration and bitrate of a track fragment in a media segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackSizeReport {
+    /// Track identifier.
+    pub track_id: u32,
+
+    /// Total size of the samples in bytes.
+    pub size: u64,
+
+    /// Sum of the sample durations.
+    pub duration: Duration,
+
+    /// Average bitrate (bits per second) of the samples (`0` if `duration` is zero).
+    pub bitrate: u64,
+}
+
+fn bitrate(size: u64, duration: Duration) -> u64 {
+    (u128::from(size) * 8 * 1_000_000_000)
+        .checked_div(duration.as_nanos())
+        .unwrap_or(0) as u64
+}
+
 /// 8.8.7 Track Fragment Header Box (ISO/IEC 14496-12).
 #[allow(missing_docs)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TrackFragmentHeaderBox {
     track_id: u32,
     pub duration_is_empty: bool,
@@ -159,9 +1889,72 @@ impl TrackFragmentHeaderBox {
             default_sample_flags: None,
         }
     }
+
+    /// Reads the first `tfhd` box in the given bytes (e.g., an isolated box taken from a packet capture).
+    ///
+    /// The optional fields are read according to the flags of the box.
+    pub fn read_from(bytes: &[u8]) -> Result<Self> {
+        let tfhd = track!(isobmff::find_box(bytes, Self::BOX_TYPE))?;
+        let tfhd = track_assert_some!(tfhd, ErrorKind::InvalidInput, "No `tfhd` box");
+        track!(Self::from_raw_box(&tfhd))
+    }
+
+    pub(crate) fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let flags = full_box.flags;
+        let mut reader = full_box.payload;
+        let track_id = track_io!(reader.read_u32::<BigEndian>())?;
+        let base_data_offset = if flags & 0x00_0001 != 0 {
+            Some(track_io!(reader.read_u64::<BigEndian>())?)
+        } else {
+            None
+        };
+        let mut read_optional_u32 = |flag| -> Result<Option<u32>> {
+            if flags & flag != 0 {
+                Ok(Some(track_io!(reader.read_u32::<BigEndian>())?))
+            } else {
+                Ok(None)
+            }
+        };
+        let sample_description_index = track!(read_optional_u32(0x00_0002))?;
+        let default_sample_duration = track!(read_optional_u32(0x00_0008))?;
+        let default_sample_size = track!(read_optional_u32(0x00_0010))?;
+        let default_sample_flags = track!(read_optional_u32(0x00_0020))?.map(SampleFlags::from_u32);
+        Ok(TrackFragmentHeaderBox {
+            track_id,
+            duration_is_empty: flags & 0x01_0000 != 0,
+            default_base_is_moof: flags & 0x02_0000 != 0,
+            base_data_offset,
+            sample_description_index,
+            default_sample_duration,
+            default_sample_size,
+            default_sample_flags,
+        })
+    }
+
+    /// Returns the identifier of the track.
+    pub fn track_id(&self) -> u32 {
+        self.track_id
+    }
+
+    /// Returns the size of a `tfhd` box that has the given box flags (see `Mp4Box::box_flags`).
+    ///
+    /// The size is computed without serializing the box.
+    pub fn box_size_for(box_flags: u32) -> u32 {
+        let mut size = 12 + 4; // full box header and `track_id`
+        if box_flags & 0x00_0001 != 0 {
+            size += 8;
+        }
+        size += [0x00_0002, 0x00_0008, 0x00_0010, 0x00_0020]
+            .iter()
+            .filter(|&&flag| box_flags & flag != 0)
+            .count() as u32
+            * 4;
+        size
+    }
 }
 impl Mp4Box for TrackFragmentHeaderBox {
-    const BOX_TYPE: [u8; 4] = *b"tfhd";
+    const BOX_TYPE: [u8; 4] = fourcc::TFHD;
 
     fn box_flags(&self) -> Option<u32> {
         let flags = self.base_data_offset.is_some() as u32
@@ -174,8 +1967,8 @@ impl Mp4Box for TrackFragmentHeaderBox {
         Some(flags)
     }
     fn box_payload_size(&self) -> Result<u32> {
-        let size = track!(ByteCounter::calculate(|w| self.write_box_payload(w)))?;
-        Ok(size as u32)
+        let flags = self.box_flags().expect("Never fails");
+        Ok(Self::box_size_for(flags) - 12)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
         write_u32!(writer, self.track_id);
@@ -199,33 +1992,163 @@ impl Mp4Box for TrackFragmentHeaderBox {
 }
 
 /// 8.8.12 Track fragment decode time (ISO/IEC 14496-12).
-#[derive(Debug)]
-pub struct TrackFragmentBaseMediaDecodeTimeBox;
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default)]
+pub struct TrackFragmentBaseMediaDecodeTimeBox {
+    pub base_media_decode_time: u64,
+
+    /// If `true`, the box is written in version 1 (64-bit decode time) even if the time fits in 32 bits.
+    ///
+    /// Otherwise, version 1 is used only when needed.
+    pub force_version_1: bool,
+}
+impl TrackFragmentBaseMediaDecodeTimeBox {
+    fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let mut reader = full_box.payload;
+        let base_media_decode_time = if full_box.version == 1 {
+            track_io!(reader.read_u64::<BigEndian>())?
+        } else {
+            u64::from(track_io!(reader.read_u32::<BigEndian>())?)
+        };
+        Ok(TrackFragmentBaseMediaDecodeTimeBox {
+            base_media_decode_time,
+            force_version_1: full_box.version == 1,
+        })
+    }
+
+    fn is_version_1(&self) -> bool {
+        self.force_version_1 || self.base_media_decode_time > u64::from(u32::MAX)
+    }
+}
 impl Mp4Box for TrackFragmentBaseMediaDecodeTimeBox {
-    const BOX_TYPE: [u8; 4] = *b"tfdt";
+    const BOX_TYPE: [u8; 4] = fourcc::TFDT;
 
     fn box_version(&self) -> Option<u8> {
-        Some(0)
+        if self.is_version_1() {
+            Some(1)
+        } else {
+            Some(0)
+        }
     }
     fn box_payload_size(&self) -> Result<u32> {
-        Ok(4)
+        if self.is_version_1() {
+            Ok(8)
+        } else {
+            Ok(4)
+        }
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
-        write_u32!(writer, 0); // base_media_decode_time
+        if self.is_version_1() {
+            write_u64!(writer, self.base_media_decode_time);
+        } else {
+            write_u32!(writer, self.base_media_decode_time as u32);
+        }
         Ok(())
     }
 }
 
 /// 8.8.8 Track Fragment Run Box (ISO/IEC 14496-12).
 #[allow(missing_docs)]
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct TrackRunBox {
     pub data_offset: Option<i32>,
     pub first_sample_flags: Option<SampleFlags>,
     pub samples: Vec<Sample>,
 }
+impl TrackRunBox {
+    /// Returns the size of a `trun` box that has `sample_count` samples and the given box flags (see `Mp4Box::box_flags`).
+    ///
+    /// The size is computed without serializing the box, so the `data_offset` fields of
+    /// a `moof` box can be filled in a single pass (e.g., for each chunk in low-latency streaming).
+    pub fn box_size_for(sample_count: usize, box_flags: u32) -> Result<u32> {
+        let mut size = 12 + 4; // full box header and `sample_count`
+        if box_flags & 0x00_0001 != 0 {
+            size += 4;
+        }
+        if box_flags & 0x00_0004 != 0 {
+            size += 4;
+        }
+        let samples_size = sample_count
+            .checked_mul(sample_field_size(box_flags))
+            .and_then(|n| n.checked_add(size));
+        let samples_size = track_assert_some!(
+            samples_size,
+            ErrorKind::SegmentTooLarge;
+            sample_count
+        );
+        track!(try_cast(samples_size, "`trun` box size"))
+    }
+
+    /// Reads the first `trun` box in the given bytes (e.g., an isolated box taken from a packet capture).
+    ///
+    /// The optional fields are read according to the flags of the box, and
+    /// the composition time offsets of version 0 boxes are reinterpreted as signed values.
+    pub fn read_from(bytes: &[u8]) -> Result<Self> {
+        let trun = track!(isobmff::find_box(bytes, Self::BOX_TYPE))?;
+        let trun = track_assert_some!(trun, ErrorKind::InvalidInput, "No `trun` box");
+        track!(Self::from_raw_box(&trun))
+    }
+
+    /// Reads a `trun` box.
+    ///
+    /// Composition time offsets of version 0 boxes are reinterpreted as signed values.
+    pub(crate) fn from_raw_box(b: &RawBox) -> Result<Self> {
+        let full_box = track!(b.full_box())?;
+        let flags = full_box.flags;
+        let mut reader = full_box.payload;
+        let sample_count = track_io!(reader.read_u32::<BigEndian>())?;
+        let data_offset = if flags & 0x00_0001 != 0 {
+            Some(track_io!(reader.read_i32::<BigEndian>())?)
+        } else {
+            None
+        };
+        let first_sample_flags = if flags & 0x00_0004 != 0 {
+            Some(SampleFlags::from_u32(track_io!(
+                reader.read_u32::<BigEndian>()
+            )?))
+        } else {
+            None
+        };
+
+        let samples_size = track_assert_some!(
+            (sample_count as usize).checked_mul(sample_field_size(flags)),
+            ErrorKind::InvalidInput;
+            sample_count
+        );
+        track_assert!(
+            samples_size <= reader.len(),
+            ErrorKind::InvalidInput;
+            sample_count, reader.len()
+        );
+        let mut samples = Vec::with_capacity(sample_count as usize);
+        for _ in 0..sample_count {
+            let mut sample = Sample::default();
+            if flags & 0x00_0100 != 0 {
+                sample.duration = Some(track_io!(reader.read_u32::<BigEndian>())?);
+            }
+            if flags & 0x00_0200 != 0 {
+                sample.size = Some(track_io!(reader.read_u32::<BigEndian>())?);
+            }
+            if flags & 0x00_0400 != 0 {
+                sample.flags = Some(SampleFlags::from_u32(track_io!(
+                    reader.read_u32::<BigEndian>()
+                )?));
+            }
+            if flags & 0x00_0800 != 0 {
+                sample.composition_time_offset = Some(track_io!(reader.read_i32::<BigEndian>())?);
+            }
+            samples.push(sample);
+        }
+        Ok(TrackRunBox {
+            data_offset,
+            first_sample_flags,
+            samples,
+        })
+    }
+}
 impl Mp4Box for TrackRunBox {
-    const BOX_TYPE: [u8; 4] = *b"trun";
+    const BOX_TYPE: [u8; 4] = fourcc::TRUN;
 
     fn box_version(&self) -> Option<u8> {
         Some(1)
@@ -242,8 +2165,9 @@ impl Mp4Box for TrackRunBox {
         Some(flags)
     }
     fn box_payload_size(&self) -> Result<u32> {
-        let size = track!(ByteCounter::calculate(|w| self.write_box_payload(w)))?;
-        Ok(size as u32)
+        let flags = self.box_flags().expect("Never fails");
+        let size = track!(Self::box_size_for(self.samples.len(), flags))?;
+        Ok(size - 12)
     }
     fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
         write_u32!(writer, self.samples.len() as u32);
@@ -282,6 +2206,15 @@ impl Mp4Box for TrackRunBox {
     }
 }
 
+/// Returns the number of bytes that each sample occupies in a `trun` box that has the given box flags.
+fn sample_field_size(box_flags: u32) -> usize {
+    [0x00_0100, 0x00_0200, 0x00_0400, 0x00_0800]
+        .iter()
+        .filter(|&&flag| box_flags & flag != 0)
+        .count()
+        * 4
+}
+
 /// 8.8.8.2 A sample (ISO/IEC 14496-12).
 #[allow(missing_docs)]
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
@@ -292,7 +2225,7 @@ pub struct Sample {
     pub composition_time_offset: Option<i32>,
 }
 impl Sample {
-    fn to_box_flags(&self) -> u32 {
+    pub(crate) fn to_box_flags(&self) -> u32 {
         (self.duration.is_some() as u32 * 0x00_0100)
             | (self.size.is_some() as u32 * 0x00_0200)
             | (self.flags.is_some() as u32 * 0x00_0400)
@@ -313,7 +2246,7 @@ pub struct SampleFlags {
     pub sample_degradation_priority: u16,
 }
 impl SampleFlags {
-    fn to_u32(&self) -> u32 {
+    pub(crate) fn to_u32(&self) -> u32 {
         (u32::from(self.is_leading) << 26)
             | (u32::from(self.sample_depends_on) << 24)
             | (u32::from(self.sample_is_depdended_on) << 22)
@@ -322,4 +2255,79 @@ impl SampleFlags {
             | ((self.sample_is_non_sync_sample as u32) << 16)
             | u32::from(self.sample_degradation_priority)
     }
+
+    pub(crate) fn from_u32(n: u32) -> Self {
+        SampleFlags {
+            is_leading: ((n >> 26) & 0b11) as u8,
+            sample_depends_on: ((n >> 24) & 0b11) as u8,
+            sample_is_depdended_on: ((n >> 22) & 0b11) as u8,
+            sample_has_redundancy: ((n >> 20) & 0b11) as u8,
+            sample_padding_value: ((n >> 17) & 0b111) as u8,
+            sample_is_non_sync_sample: (n >> 16) & 1 != 0,
+            sample_degradation_priority: n as u16,
+        }
+    }
+}
+
+/// 5.3 WebVTT Cue Box (ISO/IEC 14496-30).
+///
+/// A sample of a WebVTT track consists of the cue boxes that are active during the sample
+/// (or a `WebVttEmptyCueBox` if there are none).
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WebVttCueBox {
+    /// Cue identifier (the `iden` box).
+    pub cue_id: Option<String>,
+
+    /// Cue settings (the `sttg` box, e.g., `"line:0 align:start"`).
+    pub settings: Option<String>,
+
+    /// Cue text (the `payl` box).
+    pub payload: String,
+}
+impl Mp4Box for WebVttCueBox {
+    const BOX_TYPE: [u8; 4] = fourcc::VTTC;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        let mut size = 8 + self.payload.len() as u32;
+        if let Some(ref x) = self.cue_id {
+            size += 8 + x.len() as u32;
+        }
+        if let Some(ref x) = self.settings {
+            size += 8 + x.len() as u32;
+        }
+        Ok(size)
+    }
+    fn write_box_payload<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut write_string_box = |box_type: &[u8; 4], s: &str| -> Result<()> {
+            write_u32!(writer, 8 + s.len() as u32);
+            write_all!(writer, box_type);
+            write_all!(writer, s.as_bytes());
+            Ok(())
+        };
+        if let Some(ref x) = self.cue_id {
+            track!(write_string_box(&fourcc::IDEN, x))?;
+        }
+        if let Some(ref x) = self.settings {
+            track!(write_string_box(&fourcc::STTG, x))?;
+        }
+        track!(write_string_box(&fourcc::PAYL, &self.payload))?;
+        Ok(())
+    }
+}
+
+/// 5.4 WebVTT Empty Cue Box (ISO/IEC 14496-30).
+///
+/// This is the content of a WebVTT sample during which no cue is active.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WebVttEmptyCueBox;
+impl Mp4Box for WebVttEmptyCueBox {
+    const BOX_TYPE: [u8; 4] = fourcc::VTTE;
+
+    fn box_payload_size(&self) -> Result<u32> {
+        Ok(0)
+    }
+    fn write_box_payload<W: Write>(&self, _writer: W) -> Result<()> {
+        Ok(())
+    }
 }
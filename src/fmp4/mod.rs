@@ -1,22 +1,64 @@
 //! Fragmented MP4 (ISO BMFF) related constituent elements.
-pub use self::common::Mp4Box;
+pub use self::builder::MediaSegmentBuilder;
+pub use self::common::{DynMp4Box, Mp4Box};
+pub use self::demux::{demux_to_adts, demux_to_annexb};
+pub use self::descriptor::{
+    DecoderConfigDescriptor, DecoderSpecificInfo, Descriptor, EsDescriptor, SlConfigDescriptor,
+};
+pub use self::duration::{patch_duration, patch_duration_in};
+pub use self::encryption::{
+    apply_key_rotation, CencSampleEncryptionInformationGroupEntry, ClearLead, KeyPeriod,
+    KeyProvider, ProtectionSystemSpecificHeaderBox,
+};
 pub use self::initialization::{
-    AacSampleEntry, AvcConfigurationBox, AvcSampleEntry, ChunkOffsetBox, DataEntryUrlBox,
-    DataInformationBox, DataReferenceBox, EditBox, EditListBox, FileTypeBox, HandlerReferenceBox,
-    InitializationSegment, MediaBox, MediaHeaderBox, MediaInformationBox, MovieBox,
-    MovieExtendsBox, MovieExtendsHeaderBox, MovieHeaderBox, Mpeg4EsDescriptorBox,
-    SampleDescriptionBox, SampleEntry, SampleSizeBox, SampleTableBox, SampleToChunkBox,
-    SoundMediaHeaderBox, TimeToSampleBox, TrackBox, TrackExtendsBox, TrackHeaderBox,
-    VideoMediaHeaderBox,
+    AacSampleEntry, Av1ConfigurationBox, Av1SampleEntry, AvcConfigurationBox, AvcSampleEntry,
+    ChunkOffsetBox, ContentLightLevelBox, CustomSampleEntry, DataEntryUrlBox, DataInformationBox,
+    DataReferenceBox, DolbyVisionConfigurationBox, DolbyVisionExtendedConfigurationBox,
+    DoviDecoderConfigurationRecord, EditBox, EditListBox, FileTypeBox, FreeSpaceBox,
+    HandlerReferenceBox, HdrBoxes, InitializationSegment, ItemExtent, ItemInfoBox, ItemInfoEntry,
+    ItemLocation, ItemLocationBox, KindBox, MasteringDisplayColourVolumeBox, MediaBox,
+    MediaHeaderBox, MediaInformationBox, MetaBox, MovieBox, MovieExtendsBox, MovieExtendsHeaderBox,
+    MovieHeaderBox, Mpeg4EsDescriptorBox, NullMediaHeaderBox, SampleDescriptionBox, SampleEntry,
+    SampleSizeBox, SampleTableBox, SampleToChunkBox, SamplingRateBox, SoundMediaHeaderBox,
+    TimeToSampleBox, TrackBox, TrackExtendsBox, TrackHeaderBox, UserDataBox, VideoMediaHeaderBox,
+    Vp9SampleEntry, VpCodecConfigurationBox, WebVttConfigurationBox, WebVttSampleEntry,
 };
 pub use self::media::{
-    MediaDataBox, MediaSegment, MovieFragmentBox, MovieFragmentHeaderBox, Sample, SampleFlags,
-    TrackFragmentBaseMediaDecodeTimeBox, TrackFragmentBox, TrackFragmentHeaderBox, TrackRunBox,
+    EventMessageBox, MdatLayout, MediaDataBox, MediaSegment, MovieFragmentBox,
+    MovieFragmentHeaderBox, MovieFragmentRandomAccessBox, RandomAccessEntry, Sample,
+    SampleEncryptionBox, SampleEncryptionEntry, SampleFlags, SampleGroupDescriptionBox,
+    SampleToGroupBox, SampleToGroupEntry, SegmentIndexBox, SegmentReference, SegmentSample,
+    SegmentSamples, SegmentSizeReport, SegmentTypeBox, Subsample, SyncSample,
+    TrackFragmentBaseMediaDecodeTimeBox, TrackFragmentBox, TrackFragmentHeaderBox,
+    TrackFragmentRandomAccessBox, TrackRunBox, TrackSizeReport, TrackTiming, WebVttCueBox,
+    WebVttEmptyCueBox,
 };
+pub use self::mse::{apply_mse_fixes, mse_compatibility, MseFix};
+pub use self::restamp::{rescale_track, restamp_media_segment, TrackRestamp};
+pub use self::sample_table::SampleTable;
+pub use self::splice::{splice, SplicedSegment};
+pub use self::split::{split_into_segments, SplitSegments};
+pub use self::subset::retain_tracks;
+pub use self::validate::{validate_segments, SegmentIssue};
+pub use self::writer::{write_self_initializing_file, SegmentWriter};
 
-const VIDEO_TRACK_ID: u32 = 1;
+pub(crate) const VIDEO_TRACK_ID: u32 = 1;
 const AUDIO_TRACK_ID: u32 = 2;
+const TEXT_TRACK_ID: u32 = 3;
 
+mod builder;
 mod common;
+mod demux;
+mod descriptor;
+mod duration;
+mod encryption;
 mod initialization;
 mod media;
+mod mse;
+mod restamp;
+mod sample_table;
+mod splice;
+mod split;
+mod subset;
+mod validate;
+mod writer;
@@ -0,0 +1,235 @@
+use crate::fmp4::{Mp4Box, TrackExtendsBox};
+use crate::fourcc::{self, BoxType};
+use crate::isobmff::{Boxes, RawBox};
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ByteOrder};
+use std::io::Write;
+
+/// Brands of which at least one should be listed in the `ftyp` box of an initialization segment.
+const KNOWN_BRANDS: &[[u8; 4]] = &[
+    *b"isom", *b"iso2", *b"iso3", *b"iso4", *b"iso5", *b"iso6", *b"iso8", *b"iso9", *b"mp41",
+    *b"mp42", *b"avc1", *b"dash", *b"cmfc", *b"cmf2",
+];
+
+/// Top-level boxes that must not appear in an initialization segment.
+const FORBIDDEN_BOXES: &[[u8; 4]] = &[fourcc::MOOF, fourcc::MDAT, fourcc::MFRA];
+
+/// Automatically applicable fix of an initialization segment (see `mse_compatibility`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MseFix {
+    /// The `ftyp` box is missing or does not precede the `moov` box.
+    AddFileType,
+
+    /// The `ftyp` box lists none of the well-known brands (e.g., `isom` and `iso6`),
+    /// so `isom` is added to the compatible brands.
+    AddCompatibleBrand,
+
+    /// A top-level box that is not allowed in initialization segments (e.g., `mdat` and `moof`) is removed.
+    RemoveBox(BoxType),
+
+    /// The track has no `trex` box (`mvex` box is added if needed).
+    AddTrackExtends {
+        /// Identifier of the track.
+        track_id: u32,
+    },
+}
+
+/// Checks the given initialization segment against the [ISO BMFF Byte Stream Format] of MSE.
+///
+/// The following conditions are checked:
+///
+/// - The segment starts with a `ftyp` box that lists a well-known brand,
+/// - The segment contains no `mdat`, `moof` or `mfra` box, and
+/// - Each track has a `trex` box in the `mvex` box.
+///
+/// An empty list is returned if the segment is compatible, and otherwise the fixes that
+/// `apply_mse_fixes` would apply are returned.
+/// Errors are returned for problems that cannot be fixed automatically (e.g., the lack of the `moov` box).
+///
+/// [ISO BMFF Byte Stream Format]: https://w3c.github.io/media-source/isobmff-byte-stream-format.html
+pub fn mse_compatibility(bytes: &[u8]) -> Result<Vec<MseFix>> {
+    let layout = track!(Layout::read(bytes))?;
+    let mut fixes = Vec::new();
+    match layout.ftyp {
+        Some(ref ftyp) if ftyp.offset < layout.moov.offset => {
+            if !track!(has_known_brand(ftyp))? {
+                fixes.push(MseFix::AddCompatibleBrand);
+            }
+        }
+        _ => fixes.push(MseFix::AddFileType),
+    }
+    for b in &layout.forbidden {
+        fixes.push(MseFix::RemoveBox(BoxType(b.box_type)));
+    }
+    for track_id in track!(tracks_without_trex(&layout.moov))? {
+        fixes.push(MseFix::AddTrackExtends { track_id });
+    }
+    Ok(fixes)
+}
+
+/// Applies the fixes reported by `mse_compatibility` to the given initialization segment, and returns the fixed segment.
+///
+/// The boxes that need no fixes are copied as is.
+pub fn apply_mse_fixes(bytes: &[u8]) -> Result<Vec<u8>> {
+    let layout = track!(Layout::read(bytes))?;
+    let mut fixed = Vec::with_capacity(bytes.len() + 64);
+
+    let ftyp = layout.ftyp.as_ref();
+    let mut brands = Vec::new();
+    if let Some(ftyp) = ftyp {
+        track_assert!(ftyp.payload.len() >= 8, ErrorKind::InvalidInput);
+        brands.extend(
+            ftyp.payload[8..]
+                .chunks_exact(4)
+                .map(|b| [b[0], b[1], b[2], b[3]]),
+        );
+    }
+    let has_known_brand = match ftyp {
+        Some(ftyp) => track!(has_known_brand(ftyp))?,
+        None => false,
+    };
+    if !has_known_brand {
+        brands.push(fourcc::ISOM);
+    }
+    let (major_brand, minor_version) = match ftyp {
+        Some(ftyp) => (&ftyp.payload[..4], &ftyp.payload[4..8]),
+        None => (&fourcc::ISOM[..], &[0, 0, 2, 0][..]),
+    };
+    write_u32!(fixed, 8 + 8 + 4 * brands.len() as u32);
+    write_all!(fixed, &fourcc::FTYP);
+    write_all!(fixed, major_brand);
+    write_all!(fixed, minor_version);
+    for brand in &brands {
+        write_all!(fixed, brand);
+    }
+
+    let missing = track!(tracks_without_trex(&layout.moov))?;
+    if missing.is_empty() {
+        write_all!(fixed, box_bytes(bytes, &layout.moov));
+    } else {
+        let mut payload = Vec::with_capacity(layout.moov.payload.len() + 32 * missing.len());
+        let mut has_mvex = false;
+        for child in Boxes::new(layout.moov.payload) {
+            let child = track!(child)?;
+            if child.box_type != fourcc::MVEX {
+                write_all!(payload, box_bytes(layout.moov.payload, &child));
+                continue;
+            }
+            has_mvex = true;
+            let mut mvex = child.payload.to_owned();
+            for &track_id in &missing {
+                write_box!(mvex, TrackExtendsBox::for_track(track_id));
+            }
+            write_u32!(payload, 8 + mvex.len() as u32);
+            write_all!(payload, &fourcc::MVEX);
+            write_all!(payload, &mvex);
+        }
+        if !has_mvex {
+            let mut mvex = Vec::new();
+            for &track_id in &missing {
+                write_box!(mvex, TrackExtendsBox::for_track(track_id));
+            }
+            write_u32!(payload, 8 + mvex.len() as u32);
+            write_all!(payload, &fourcc::MVEX);
+            write_all!(payload, &mvex);
+        }
+        write_u32!(fixed, 8 + payload.len() as u32);
+        write_all!(fixed, &fourcc::MOOV);
+        write_all!(fixed, &payload);
+    }
+
+    for b in &layout.others {
+        write_all!(fixed, box_bytes(bytes, b));
+    }
+    Ok(fixed)
+}
+
+struct Layout<'a> {
+    ftyp: Option<RawBox<'a>>,
+    moov: RawBox<'a>,
+    forbidden: Vec<RawBox<'a>>,
+    others: Vec<RawBox<'a>>,
+}
+impl<'a> Layout<'a> {
+    fn read(bytes: &'a [u8]) -> Result<Self> {
+        let mut ftyp = None;
+        let mut moov = None;
+        let mut forbidden = Vec::new();
+        let mut others = Vec::new();
+        for b in Boxes::new(bytes) {
+            let b = track!(b)?;
+            if b.box_type == fourcc::FTYP && ftyp.is_none() {
+                ftyp = Some(b);
+            } else if b.box_type == fourcc::MOOV {
+                track_assert!(
+                    moov.is_none(),
+                    ErrorKind::InvalidInput,
+                    "Multiple `moov` boxes"
+                );
+                moov = Some(b);
+            } else if FORBIDDEN_BOXES.contains(&b.box_type) {
+                forbidden.push(b);
+            } else {
+                others.push(b);
+            }
+        }
+        let moov = track_assert_some!(moov, ErrorKind::InvalidInput, "No `moov` box");
+        Ok(Layout {
+            ftyp,
+            moov,
+            forbidden,
+            others,
+        })
+    }
+}
+
+fn box_bytes<'a>(bytes: &'a [u8], b: &RawBox) -> &'a [u8] {
+    &bytes[b.offset..b.offset + b.size()]
+}
+
+fn has_known_brand(ftyp: &RawBox) -> Result<bool> {
+    track_assert!(ftyp.payload.len() >= 8, ErrorKind::InvalidInput);
+    let major_brand = &ftyp.payload[..4];
+    let compatible_brands = ftyp.payload[8..].chunks_exact(4);
+    Ok(std::iter::once(major_brand)
+        .chain(compatible_brands)
+        .any(|b| KNOWN_BRANDS.iter().any(|k| &k[..] == b)))
+}
+
+fn tracks_without_trex(moov: &RawBox) -> Result<Vec<u32>> {
+    let mut track_ids = Vec::new();
+    let mut trex_track_ids = Vec::new();
+    for child in moov.children() {
+        let child = track!(child)?;
+        if child.box_type == fourcc::TRAK {
+            for b in child.children() {
+                let b = track!(b)?;
+                if b.box_type == fourcc::TKHD {
+                    track_ids.push(track!(tkhd_track_id(&b))?);
+                }
+            }
+        } else if child.box_type == fourcc::MVEX {
+            for b in child.children() {
+                let b = track!(b)?;
+                if b.box_type == fourcc::TREX {
+                    let payload = track!(b.full_box())?.payload;
+                    track_assert!(payload.len() >= 4, ErrorKind::InvalidInput);
+                    trex_track_ids.push(BigEndian::read_u32(payload));
+                }
+            }
+        }
+    }
+    track_ids.retain(|id| !trex_track_ids.contains(id));
+    Ok(track_ids)
+}
+
+pub(crate) fn tkhd_track_id(tkhd: &RawBox) -> Result<u32> {
+    let full_box = track!(tkhd.full_box())?;
+    // `track_id` follows `creation_time` and `modification_time` (64-bit fields in version 1)
+    let offset = if full_box.version == 1 { 16 } else { 8 };
+    track_assert!(
+        full_box.payload.len() >= offset + 4,
+        ErrorKind::InvalidInput
+    );
+    Ok(BigEndian::read_u32(&full_box.payload[offset..]))
+}
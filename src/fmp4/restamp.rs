@@ -0,0 +1,190 @@
+use crate::error::try_cast;
+use crate::fmp4::{InitializationSegment, MediaSegment, Mp4Box, TrackFragmentBox};
+use crate::time::{self, Rounding};
+use crate::{ErrorKind, Result};
+
+/// Rewriting of the timestamps of a track, used by `restamp_media_segment`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackRestamp {
+    /// Identifier of the track to be rewritten.
+    pub track_id: u32,
+
+    /// New decode time (in the new timescale) of the first sample of the track fragment.
+    ///
+    /// If `None`, the current decode time is kept (and converted to the new timescale).
+    pub base_media_decode_time: Option<u64>,
+
+    /// Current and new timescales (i.e., `(from, to)`) of the track.
+    ///
+    /// If `None`, the sample durations and composition time offsets are kept as they are.
+    pub timescale: Option<(u32, u32)>,
+}
+
+/// Rewrites the sequence number and the timestamps of `segment` without touching its `mdat` boxes.
+///
+/// This is useful for stitching segments made from different sources (e.g., an ad insertion)
+/// into a single continuous stream.
+///
+/// The `tfdt` box of each track fragment listed in `tracks` is replaced,
+/// and if a timescale conversion is specified, the sample durations and composition time offsets
+/// are converted, too (the rounding errors do not accumulate across samples or segments).
+/// Note that the `timescale` of the `mdhd` box in the initialization segment must be updated separately.
+///
+/// As the size of the `moof` box may change, the `data_offset` fields of the `trun` boxes are adjusted accordingly.
+pub fn restamp_media_segment(
+    segment: &mut MediaSegment,
+    sequence_number: u32,
+    tracks: &[TrackRestamp],
+) -> Result<()> {
+    let old_moof_size = track!(segment.moof_box.box_size())?;
+    segment.moof_box.mfhd_box.sequence_number = sequence_number;
+    for restamp in tracks {
+        for traf in &mut segment.moof_box.traf_boxes {
+            if traf.tfhd_box.track_id() == restamp.track_id {
+                track!(restamp_traf(traf, restamp))?;
+            }
+        }
+    }
+
+    track!(segment.shift_data_offsets(old_moof_size))
+}
+
+/// Converts the timescale of the track identified by `track_id` into `timescale`
+/// (e.g., to splice content made by encoders that use different clock rates into one `SourceBuffer`).
+///
+/// The `mdhd`, `elst` and `trex` boxes of the track in `initialization_segment` and
+/// the track fragments of the track in `segments` are rewritten (see also `restamp_media_segment`).
+/// The sequence numbers of the segments are kept.
+///
+/// The times are converted as absolute values (rounded to the nearest tick),
+/// so the error of each decode or presentation time is at most half a tick of the new timescale.
+pub fn rescale_track(
+    initialization_segment: &mut InitializationSegment,
+    segments: &mut [MediaSegment],
+    track_id: u32,
+    timescale: u32,
+) -> Result<()> {
+    track_assert_ne!(timescale, 0, ErrorKind::InvalidInput);
+    let trak = initialization_segment
+        .moov_box
+        .trak_boxes
+        .iter_mut()
+        .find(|t| t.tkhd_box.track_id() == track_id);
+    let trak = track_assert_some!(trak, ErrorKind::InvalidInput, "Unknown track: {}", track_id);
+    let from = trak.mdia_box.mdhd_box.timescale;
+    track_assert_ne!(from, 0, ErrorKind::InvalidInput);
+    let scale = |t: u32| -> Result<u32> {
+        let t = time::rescale(u64::from(t), from, timescale, Rounding::Nearest);
+        track!(try_cast(t, "Scaled time"))
+    };
+
+    let mdhd = &mut trak.mdia_box.mdhd_box;
+    mdhd.timescale = timescale;
+    mdhd.duration = track!(scale(mdhd.duration))?;
+    let elst = &mut trak.edts_box.elst_box;
+    if elst.media_time > 0 {
+        // `-1` (an empty edit) is kept as it is
+        let media_time = time::rescale(elst.media_time as u64, from, timescale, Rounding::Nearest);
+        elst.media_time = track!(try_cast(media_time, "Media time"))?;
+    }
+    for trex in &mut initialization_segment.moov_box.mvex_box.trex_boxes {
+        if trex.track_id() == track_id {
+            trex.default_sample_duration = track!(scale(trex.default_sample_duration))?;
+        }
+    }
+
+    let restamp = TrackRestamp {
+        track_id,
+        base_media_decode_time: None,
+        timescale: Some((from, timescale)),
+    };
+    for segment in segments {
+        let sequence_number = segment.moof_box.mfhd_box.sequence_number;
+        track!(restamp_media_segment(
+            segment,
+            sequence_number,
+            std::slice::from_ref(&restamp)
+        ))?;
+    }
+    Ok(())
+}
+
+fn restamp_traf(traf: &mut TrackFragmentBox, restamp: &TrackRestamp) -> Result<()> {
+    let old_base = traf.tfdt_box.base_media_decode_time;
+    let (from, to) = match restamp.timescale {
+        None => {
+            if let Some(base) = restamp.base_media_decode_time {
+                traf.tfdt_box.base_media_decode_time = base;
+            }
+            return Ok(());
+        }
+        Some(x) => x,
+    };
+    track_assert_ne!(from, 0, ErrorKind::InvalidInput);
+    track_assert_ne!(to, 0, ErrorKind::InvalidInput);
+
+    // The times are converted as absolute values, so that the rounding errors do not accumulate
+    let scale = |t: i128| -> i128 {
+        (t * i128::from(to) + i128::from(from) / 2).div_euclid(i128::from(from))
+    };
+    let scaled_old_base = scale(i128::from(old_base));
+    let new_base = restamp
+        .base_media_decode_time
+        .map_or(scaled_old_base, i128::from);
+    track_assert!(
+        new_base >= 0 && new_base <= i128::from(u64::MAX),
+        ErrorKind::InvalidInput
+    );
+
+    let old_default_duration = traf.tfhd_box.default_sample_duration;
+    let new_default_duration = match old_default_duration {
+        None => None,
+        Some(d) => Some(track!(to_u32(scale(i128::from(d))))?),
+    };
+    let mut decode_time = i128::from(old_base);
+    for trun in &mut traf.trun_boxes {
+        let mut uses_default_duration = true;
+        let mut durations = Vec::with_capacity(trun.samples.len());
+        for sample in &mut trun.samples {
+            let duration = track_assert_some!(
+                sample.duration.or(old_default_duration),
+                ErrorKind::InvalidInput,
+                "No sample duration: track_id={}",
+                restamp.track_id
+            );
+            let next_decode_time = decode_time + i128::from(duration);
+            let new_duration = track!(to_u32(scale(next_decode_time) - scale(decode_time)))?;
+            if let Some(offset) = sample.composition_time_offset {
+                let presentation_time = decode_time + i128::from(offset);
+                let new_offset = scale(presentation_time) - scale(decode_time);
+                track_assert!(
+                    new_offset >= i128::from(i32::MIN) && new_offset <= i128::from(i32::MAX),
+                    ErrorKind::InvalidInput
+                );
+                sample.composition_time_offset = Some(new_offset as i32);
+            }
+            uses_default_duration &=
+                sample.duration.is_none() && Some(new_duration) == new_default_duration;
+            durations.push(new_duration);
+            decode_time = next_decode_time;
+        }
+        if !uses_default_duration {
+            // All samples in a `trun` box must have the same set of fields
+            for (sample, duration) in trun.samples.iter_mut().zip(durations) {
+                sample.duration = Some(duration);
+            }
+        }
+    }
+    traf.tfhd_box.default_sample_duration = new_default_duration;
+    traf.tfdt_box.base_media_decode_time = new_base as u64;
+    Ok(())
+}
+
+fn to_u32(n: i128) -> Result<u32> {
+    track_assert!(
+        n >= 0 && n <= i128::from(u32::MAX),
+        ErrorKind::InvalidInput;
+        n
+    );
+    Ok(n as u32)
+}
@@ -0,0 +1,227 @@
+use crate::fmp4::{Sample, SampleFlags, TrackRunBox};
+use crate::fourcc;
+use crate::{ErrorKind, Result};
+use std::io::Write;
+
+/// Columnar storage of the samples of a track fragment run.
+///
+/// Unlike `Vec<Sample>`, each field is stored in its own vector and only the fields listed in
+/// the presence bitmask (the sample fields of the `trun` box flags, e.g., `SampleTable::SIZE`) are stored.
+/// This saves memory for fragments with hundreds of thousands of samples, and
+/// the `trun` box can be written without checking the fields of each sample (see `write_trun_box`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampleTable {
+    fields: u32,
+    len: usize,
+    durations: Vec<u32>,
+    sizes: Vec<u32>,
+    flags: Vec<u32>,
+    composition_time_offsets: Vec<i32>,
+}
+impl SampleTable {
+    /// Presence flag of the `duration` field.
+    pub const DURATION: u32 = 0x00_0100;
+
+    /// Presence flag of the `size` field.
+    pub const SIZE: u32 = 0x00_0200;
+
+    /// Presence flag of the `flags` field.
+    pub const FLAGS: u32 = 0x00_0400;
+
+    /// Presence flag of the `composition_time_offset` field.
+    pub const COMPOSITION_TIME_OFFSET: u32 = 0x00_0800;
+
+    const ALL_FIELDS: u32 =
+        Self::DURATION | Self::SIZE | Self::FLAGS | Self::COMPOSITION_TIME_OFFSET;
+
+    /// Makes an empty table whose samples have the given fields.
+    ///
+    /// Bits other than the presence flags are ignored.
+    pub fn new(fields: u32) -> Self {
+        Self::with_capacity(fields, 0)
+    }
+
+    /// Makes an empty table that can hold `capacity` samples without reallocation.
+    pub fn with_capacity(fields: u32, capacity: usize) -> Self {
+        let column = |flag: u32| {
+            if fields & flag != 0 {
+                capacity
+            } else {
+                0
+            }
+        };
+        SampleTable {
+            fields: fields & Self::ALL_FIELDS,
+            len: 0,
+            durations: Vec::with_capacity(column(Self::DURATION)),
+            sizes: Vec::with_capacity(column(Self::SIZE)),
+            flags: Vec::with_capacity(column(Self::FLAGS)),
+            composition_time_offsets: Vec::with_capacity(column(Self::COMPOSITION_TIME_OFFSET)),
+        }
+    }
+
+    /// Makes a table from `samples`.
+    ///
+    /// The fields of the first sample determine those of the table, so all samples must have the same fields.
+    pub fn from_samples(samples: &[Sample]) -> Result<Self> {
+        let fields = samples.first().map_or(0, Sample::to_box_flags);
+        let mut table = Self::with_capacity(fields, samples.len());
+        for sample in samples {
+            track!(table.push(sample))?;
+        }
+        Ok(table)
+    }
+
+    /// Returns the presence bitmask of the fields.
+    pub fn fields(&self) -> u32 {
+        self.fields
+    }
+
+    /// Returns the number of samples.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the table has no samples.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends a sample.
+    ///
+    /// The sample must have exactly the fields of this table.
+    pub fn push(&mut self, sample: &Sample) -> Result<()> {
+        track_assert_eq!(sample.to_box_flags(), self.fields, ErrorKind::InvalidInput);
+        if let Some(x) = sample.duration {
+            self.durations.push(x);
+        }
+        if let Some(x) = sample.size {
+            self.sizes.push(x);
+        }
+        if let Some(x) = sample.flags {
+            self.flags.push(x.to_u32());
+        }
+        if let Some(x) = sample.composition_time_offset {
+            self.composition_time_offsets.push(x);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Returns the `i`-th sample.
+    pub fn get(&self, i: usize) -> Option<Sample> {
+        if i >= self.len {
+            return None;
+        }
+        Some(Sample {
+            duration: self.durations.get(i).cloned(),
+            size: self.sizes.get(i).cloned(),
+            flags: self.flags.get(i).map(|&x| SampleFlags::from_u32(x)),
+            composition_time_offset: self.composition_time_offsets.get(i).cloned(),
+        })
+    }
+
+    /// Returns an iterator over the samples.
+    pub fn iter(&self) -> impl Iterator<Item = Sample> + '_ {
+        (0..self.len).map(move |i| self.get(i).expect("Never fails"))
+    }
+
+    /// Returns the sample durations (empty if the samples have no durations).
+    pub fn durations(&self) -> &[u32] {
+        &self.durations
+    }
+
+    /// Returns the sample sizes (empty if the samples have no sizes).
+    pub fn sizes(&self) -> &[u32] {
+        &self.sizes
+    }
+
+    /// Returns the composition time offsets (empty if the samples have no offsets).
+    pub fn composition_time_offsets(&self) -> &[i32] {
+        &self.composition_time_offsets
+    }
+
+    /// Returns the total size of the samples in bytes.
+    pub fn data_size(&self) -> u64 {
+        self.sizes.iter().map(|&x| u64::from(x)).sum()
+    }
+
+    /// Removes the first `n` samples from this table and returns them as a new table.
+    pub fn split_to(&mut self, n: usize) -> Self {
+        let n = n.min(self.len);
+        let front = SampleTable {
+            fields: self.fields,
+            len: n,
+            durations: take_front(&mut self.durations, n),
+            sizes: take_front(&mut self.sizes, n),
+            flags: take_front(&mut self.flags, n),
+            composition_time_offsets: take_front(&mut self.composition_time_offsets, n),
+        };
+        self.len -= n;
+        front
+    }
+
+    /// Converts this table into samples (e.g., to build a `TrackRunBox`).
+    pub fn into_samples(self) -> Vec<Sample> {
+        self.iter().collect()
+    }
+
+    /// Returns the size of the `trun` box written by `write_trun_box`.
+    pub fn trun_box_size(
+        &self,
+        has_data_offset: bool,
+        has_first_sample_flags: bool,
+    ) -> Result<u32> {
+        let box_flags = self.trun_box_flags(has_data_offset, has_first_sample_flags);
+        track!(TrackRunBox::box_size_for(self.len, box_flags))
+    }
+
+    /// Writes a `trun` box that has the samples of this table.
+    ///
+    /// The output is the same as that of a `TrackRunBox` that has the same samples.
+    pub fn write_trun_box<W: Write>(
+        &self,
+        mut writer: W,
+        data_offset: Option<i32>,
+        first_sample_flags: Option<SampleFlags>,
+    ) -> Result<()> {
+        let box_flags = self.trun_box_flags(data_offset.is_some(), first_sample_flags.is_some());
+        let size = track!(self.trun_box_size(data_offset.is_some(), first_sample_flags.is_some()))?;
+        write_u32!(writer, size);
+        write_all!(writer, &fourcc::TRUN);
+        write_u32!(writer, (1 << 24) | box_flags);
+        write_u32!(writer, self.len as u32);
+        if let Some(x) = data_offset {
+            write_i32!(writer, x);
+        }
+        if let Some(x) = first_sample_flags {
+            write_u32!(writer, x.to_u32());
+        }
+        for i in 0..self.len {
+            if let Some(&x) = self.durations.get(i) {
+                write_u32!(writer, x);
+            }
+            if let Some(&x) = self.sizes.get(i) {
+                write_u32!(writer, x);
+            }
+            if let Some(&x) = self.flags.get(i) {
+                write_u32!(writer, x);
+            }
+            if let Some(&x) = self.composition_time_offsets.get(i) {
+                write_i32!(writer, x);
+            }
+        }
+        Ok(())
+    }
+
+    fn trun_box_flags(&self, has_data_offset: bool, has_first_sample_flags: bool) -> u32 {
+        let fields = if self.is_empty() { 0 } else { self.fields };
+        has_data_offset as u32 | (has_first_sample_flags as u32 * 0x00_0004) | fields
+    }
+}
+
+/// Removes the first `n` elements of `column` (which is empty if the field is absent).
+fn take_front<T>(column: &mut Vec<T>, n: usize) -> Vec<T> {
+    let end = n.min(column.len());
+    column.drain(..end).collect()
+}
@@ -0,0 +1,186 @@
+use crate::error::try_cast;
+use crate::fmp4::{
+    restamp_media_segment, EventMessageBox, InitializationSegment, MediaSegment, Mp4Box,
+    TrackRestamp,
+};
+use crate::io::WriteTo;
+use crate::time::{MediaTime, Rounding};
+use crate::{ErrorKind, Result};
+use std::io::Write;
+
+/// Media segment produced by `splice`.
+#[derive(Debug, Clone)]
+pub struct SplicedSegment {
+    /// Event messages to be written before the segment (the splice markers, if requested).
+    pub emsg_boxes: Vec<EventMessageBox>,
+
+    /// Media segment.
+    pub segment: MediaSegment,
+}
+impl WriteTo for SplicedSegment {
+    fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_boxes!(writer, &self.emsg_boxes);
+        track!(self.segment.write_to(writer))
+    }
+}
+
+/// Inserts the `ad` segments into the `main` segments at `at` (e.g., for server-side ad insertion).
+///
+/// The ad is inserted before the first main segment whose decode time of the first track is
+/// at or after `at`, so `at` should be aligned to a segment boundary of the main content.
+/// The ad segments are restamped to start where the preceding main segments end, and the following
+/// main segments are delayed by the duration of the ad in each track.
+/// The sequence numbers of all the segments are renumbered from that of the first main segment.
+///
+/// The two initialization segments must be compatible (see `InitializationSegment::is_compatible_with`),
+/// since the spliced segments are played with `main_initialization_segment`.
+///
+/// If `marker_scheme` is given, `emsg` boxes of the scheme are added to the first ad segment
+/// (value `"out"`, with the duration of the ad) and to the first main segment after the ad (value `"in"`).
+/// The times of the markers are in the timescale of the first track.
+pub fn splice(
+    main_initialization_segment: &InitializationSegment,
+    main: &[MediaSegment],
+    ad_initialization_segment: &InitializationSegment,
+    ad: &[MediaSegment],
+    at: MediaTime,
+    marker_scheme: Option<&str>,
+) -> Result<Vec<SplicedSegment>> {
+    track_assert!(
+        track!(main_initialization_segment.is_compatible_with(ad_initialization_segment))?,
+        ErrorKind::Unsupported,
+        "The ad is not compatible with the main content"
+    );
+    let tracks = main_initialization_segment
+        .moov_box
+        .trak_boxes
+        .iter()
+        .map(|t| (t.tkhd_box.track_id(), t.mdia_box.mdhd_box.timescale))
+        .collect::<Vec<_>>();
+    let (reference_track_id, reference_timescale) =
+        *track_assert_some!(tracks.first(), ErrorKind::InvalidInput, "No tracks");
+    let reference_at = at.rescale(reference_timescale, Rounding::Nearest).value;
+
+    let split = main
+        .iter()
+        .position(|s| {
+            s.moof_box.traf_boxes.iter().any(|t| {
+                t.tfhd_box.track_id() == reference_track_id
+                    && t.tfdt_box.base_media_decode_time >= reference_at
+            })
+        })
+        .unwrap_or(main.len());
+
+    let mut splice_times = Vec::with_capacity(tracks.len());
+    let mut ad_starts = Vec::with_capacity(tracks.len());
+    let mut ad_durations = Vec::with_capacity(tracks.len());
+    for &(track_id, timescale) in &tracks {
+        let mut splice_time = at.rescale(timescale, Rounding::Nearest).value;
+        for segment in &main[..split] {
+            for timing in track!(segment.track_timings())? {
+                if timing.track_id == track_id {
+                    splice_time = timing.base_media_decode_time + timing.duration;
+                }
+            }
+        }
+        splice_times.push(splice_time);
+
+        let mut range: Option<(u64, u64)> = None;
+        for segment in ad {
+            for timing in track!(segment.track_timings())? {
+                if timing.track_id == track_id {
+                    let end = timing.base_media_decode_time + timing.duration;
+                    let start = range.map_or(timing.base_media_decode_time, |r| r.0);
+                    range = Some((start, end));
+                }
+            }
+        }
+        let (start, end) = range.unwrap_or((0, 0));
+        track_assert!(start <= end, ErrorKind::InvalidInput; track_id, start, end);
+        ad_starts.push(start);
+        ad_durations.push(end - start);
+    }
+
+    let mut sequence_number = main
+        .first()
+        .or_else(|| ad.first())
+        .map_or(1, |s| s.moof_box.mfhd_box.sequence_number);
+    let mut spliced = Vec::with_capacity(main.len() + ad.len());
+    let mut push =
+        |segment: &MediaSegment, decode_time: &dyn Fn(usize, u64) -> Result<u64>| -> Result<()> {
+            let mut restamps = Vec::with_capacity(segment.moof_box.traf_boxes.len());
+            for traf in &segment.moof_box.traf_boxes {
+                let track_id = traf.tfhd_box.track_id();
+                let i = track_assert_some!(
+                    tracks.iter().position(|t| t.0 == track_id),
+                    ErrorKind::InvalidInput,
+                    "Unknown track: {}",
+                    track_id
+                );
+                restamps.push(TrackRestamp {
+                    track_id,
+                    base_media_decode_time: Some(track!(decode_time(
+                        i,
+                        traf.tfdt_box.base_media_decode_time
+                    ))?),
+                    timescale: None,
+                });
+            }
+            let mut segment = segment.clone();
+            track!(restamp_media_segment(
+                &mut segment,
+                sequence_number,
+                &restamps
+            ))?;
+            sequence_number = track_assert_some!(
+                sequence_number.checked_add(1),
+                ErrorKind::InvalidInput,
+                "Too many segments"
+            );
+            spliced.push(SplicedSegment {
+                emsg_boxes: Vec::new(),
+                segment,
+            });
+            Ok(())
+        };
+
+    for segment in &main[..split] {
+        track!(push(segment, &|_, t| Ok(t)))?;
+    }
+    for segment in ad {
+        track!(push(segment, &|i, t| {
+            let offset = track_assert_some!(t.checked_sub(ad_starts[i]), ErrorKind::InvalidInput);
+            Ok(splice_times[i] + offset)
+        }))?;
+    }
+    for segment in &main[split..] {
+        track!(push(segment, &|i, t| Ok(t + ad_durations[i])))?;
+    }
+
+    if let Some(scheme) = marker_scheme {
+        if !ad.is_empty() {
+            let out = EventMessageBox {
+                scheme_id_uri: scheme.to_owned(),
+                value: "out".to_owned(),
+                timescale: reference_timescale,
+                presentation_time: splice_times[0],
+                event_duration: track!(try_cast(ad_durations[0], "Ad duration"))?,
+                id: 0,
+                message_data: Vec::new(),
+            };
+            spliced[split].emsg_boxes.push(out);
+            if let Some(resumed) = spliced.get_mut(split + ad.len()) {
+                resumed.emsg_boxes.push(EventMessageBox {
+                    scheme_id_uri: scheme.to_owned(),
+                    value: "in".to_owned(),
+                    timescale: reference_timescale,
+                    presentation_time: splice_times[0] + ad_durations[0],
+                    event_duration: 0,
+                    id: 1,
+                    message_data: Vec::new(),
+                });
+            }
+        }
+    }
+    Ok(spliced)
+}
@@ -0,0 +1,473 @@
+use crate::error::try_cast;
+use crate::fmp4::{
+    Mp4Box, SampleFlags, SegmentIndexBox, SegmentReference, SegmentTypeBox,
+    TrackFragmentBaseMediaDecodeTimeBox, TrackFragmentBox, TrackFragmentHeaderBox, TrackRunBox,
+};
+use crate::fourcc;
+use crate::isobmff::{self, Boxes, RawBox};
+use crate::time::{MediaTime, Rounding};
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A fragmented MP4 file split into an initialization segment and media segments.
+#[derive(Debug)]
+pub struct SplitSegments {
+    /// `ftyp` and `moov` boxes of the original file.
+    pub initialization_segment: Vec<u8>,
+
+    /// Media segments (each of which starts with `styp` and `sidx` boxes).
+    pub media_segments: Vec<Vec<u8>>,
+}
+
+/// Re-groups the fragments (moof/mdat pairs) of a fragmented MP4 file into segments of about `target_duration`.
+///
+/// Segments are cut only at fragment boundaries: a new segment is started once the accumulated duration of
+/// the reference track (the first video track, or the first track if there is no video track)
+/// reaches `target_duration` and the next fragment begins with a sync sample.
+///
+/// While regrouping, the sequence numbers of the fragments are renumbered from 1 and
+/// the `tfdt` of each track fragment is rewritten so that the decode times of the track are contiguous
+/// from its first fragment.
+/// Text tracks (`text`, `subt` and `sbtl` handlers, e.g., `wvtt` and `tx3g` samples) are sparse,
+/// so the gaps between their fragments are kept: their `tfdt` is shifted by the same amount as
+/// that of the reference track to stay aligned with it.
+/// Existing `styp`, `sidx`, `ssix`, `mfra` and free space boxes are dropped and a `sidx` box is generated for each segment.
+pub fn split_into_segments(file: &[u8], target_duration: Duration) -> Result<SplitSegments> {
+    let mut initialization_segment = Vec::new();
+    let mut tracks = Vec::new();
+    let mut fragments = Vec::new();
+    let mut current: Option<FragmentBuilder> = None;
+    let mut pending = Vec::new();
+    for b in Boxes::new(file) {
+        let b = track!(b)?;
+        let bytes = &file[b.offset..b.offset + b.size()];
+        match b.box_type {
+            fourcc::FTYP => initialization_segment.extend_from_slice(bytes),
+            fourcc::META if current.is_none() => initialization_segment.extend_from_slice(bytes),
+            fourcc::MOOV => {
+                initialization_segment.extend_from_slice(bytes);
+                tracks = track!(read_tracks(&b))?;
+            }
+            fourcc::STYP
+            | fourcc::SIDX
+            | fourcc::SSIX
+            | fourcc::MFRA
+            | fourcc::FREE
+            | fourcc::SKIP => {}
+            fourcc::MOOF => {
+                if let Some(builder) = current.take() {
+                    fragments.push(track!(builder.finish(&tracks))?);
+                }
+                let moof_offset = pending.len();
+                let mut bytes_with_pending = std::mem::take(&mut pending);
+                bytes_with_pending.extend_from_slice(bytes);
+                current = Some(FragmentBuilder {
+                    bytes: bytes_with_pending,
+                    moof_offset,
+                    has_mdat: false,
+                });
+            }
+            fourcc::MDAT => {
+                let builder = track_assert_some!(
+                    current.as_mut(),
+                    ErrorKind::InvalidInput,
+                    "`mdat` box without a preceding `moof` box"
+                );
+                builder.bytes.extend_from_slice(bytes);
+                builder.has_mdat = true;
+            }
+            _ => match current {
+                Some(ref mut builder) if !builder.has_mdat => {
+                    builder.bytes.extend_from_slice(bytes);
+                }
+                _ => {
+                    if let Some(builder) = current.take() {
+                        fragments.push(track!(builder.finish(&tracks))?);
+                    }
+                    pending.extend_from_slice(bytes);
+                }
+            },
+        }
+    }
+    if let Some(builder) = current.take() {
+        fragments.push(track!(builder.finish(&tracks))?);
+    }
+    track_assert!(!tracks.is_empty(), ErrorKind::InvalidInput, "No `moov` box");
+
+    let reference = tracks
+        .iter()
+        .find(|t| t.is_video)
+        .unwrap_or_else(|| &tracks[0]);
+    let target =
+        MediaTime::from_duration(target_duration, reference.timescale, Rounding::Down).value;
+
+    let mut media_segments = Vec::new();
+    let mut group = Vec::new();
+    let mut group_duration = 0;
+    let mut next_decode_times = HashMap::new();
+    let mut reference_shift = 0i128; // in the timescale of the reference track
+    for (i, mut fragment) in fragments.into_iter().enumerate() {
+        let (duration, starts_with_sync) = match fragment.traf(reference.track_id) {
+            Some(traf) => (traf.duration, traf.starts_with_sync),
+            None => (0, true),
+        };
+        if !group.is_empty() && group_duration >= target && starts_with_sync {
+            media_segments.push(track!(make_segment(&group, reference))?);
+            group.clear();
+            group_duration = 0;
+        }
+
+        let sequence_number = track!(try_cast(i + 1, "Sequence number"))?;
+        BigEndian::write_u32(&mut fragment.bytes[fragment.mfhd_offset..], sequence_number);
+        if let Some(traf) = fragment.traf(reference.track_id) {
+            let decode_time = next_decode_times
+                .get(&traf.track_id)
+                .map_or(traf.base_media_decode_time, |&t| t);
+            reference_shift = i128::from(decode_time) - i128::from(traf.base_media_decode_time);
+        }
+        for traf in &mut fragment.trafs {
+            let track = tracks
+                .iter()
+                .find(|t| t.track_id == traf.track_id)
+                .expect("Never fails");
+            let decode_time = if track.is_sparse && track.track_id != reference.track_id {
+                let shift =
+                    reference_shift * i128::from(track.timescale) / i128::from(reference.timescale);
+                let decode_time = i128::from(traf.base_media_decode_time) + shift;
+                track_assert!(
+                    0 <= decode_time && decode_time <= i128::from(u64::MAX),
+                    ErrorKind::InvalidInput,
+                    "Decode time out of range: track_id={}, decode_time={}",
+                    traf.track_id,
+                    decode_time
+                );
+                decode_time as u64
+            } else {
+                *next_decode_times
+                    .entry(traf.track_id)
+                    .or_insert(traf.base_media_decode_time)
+            };
+            let tfdt = &mut fragment.bytes[traf.tfdt_offset..];
+            if tfdt[0] == 1 {
+                BigEndian::write_u64(&mut tfdt[4..], decode_time);
+            } else {
+                track_assert!(
+                    decode_time <= u64::from(u32::MAX),
+                    ErrorKind::Unsupported,
+                    "Too large decode time for a version 0 `tfdt` box: {}",
+                    decode_time
+                );
+                BigEndian::write_u32(&mut tfdt[4..], decode_time as u32);
+            }
+            traf.earliest_presentation_time +=
+                i128::from(decode_time) - i128::from(traf.base_media_decode_time);
+            traf.base_media_decode_time = decode_time;
+            let next_decode_time = track_assert_some!(
+                decode_time.checked_add(traf.duration),
+                ErrorKind::InvalidInput,
+                "Decode time overflow: track_id={}",
+                traf.track_id
+            );
+            next_decode_times.insert(traf.track_id, next_decode_time);
+        }
+        group_duration += duration;
+        group.push(fragment);
+    }
+    if !group.is_empty() {
+        media_segments.push(track!(make_segment(&group, reference))?);
+    }
+
+    Ok(SplitSegments {
+        initialization_segment,
+        media_segments,
+    })
+}
+
+fn make_segment(fragments: &[Fragment], reference: &TrackInfo) -> Result<Vec<u8>> {
+    let mut references = Vec::with_capacity(fragments.len());
+    for fragment in fragments {
+        let (duration, starts_with_sap) = match fragment.traf(reference.track_id) {
+            Some(traf) => (traf.duration, traf.starts_with_sync),
+            None => (0, false),
+        };
+        track_assert!(duration <= u64::from(u32::MAX), ErrorKind::Unsupported; duration);
+        track_assert!(fragment.bytes.len() < (1 << 31), ErrorKind::Unsupported; fragment.bytes.len());
+        references.push(SegmentReference {
+            reference_type: false,
+            referenced_size: fragment.bytes.len() as u32,
+            subsegment_duration: duration as u32,
+            starts_with_sap,
+            sap_type: if starts_with_sap { 1 } else { 0 },
+            sap_delta_time: 0,
+        });
+    }
+    let earliest_presentation_time = fragments
+        .iter()
+        .filter_map(|f| f.traf(reference.track_id))
+        .map(|t| t.earliest_presentation_time)
+        .next()
+        .unwrap_or(0);
+    track_assert!(
+        0 <= earliest_presentation_time && earliest_presentation_time <= i128::from(u64::MAX),
+        ErrorKind::Unsupported,
+        "Earliest presentation time out of range: {}",
+        earliest_presentation_time
+    );
+    let sidx_box = SegmentIndexBox {
+        reference_id: reference.track_id,
+        timescale: reference.timescale,
+        earliest_presentation_time: earliest_presentation_time as u64,
+        first_offset: 0,
+        references,
+    };
+
+    let mut segment = Vec::new();
+    write_box!(segment, SegmentTypeBox::default());
+    write_box!(segment, sidx_box);
+    for fragment in fragments {
+        segment.extend_from_slice(&fragment.bytes);
+    }
+    Ok(segment)
+}
+
+#[derive(Debug)]
+struct TrackInfo {
+    track_id: u32,
+    timescale: u32,
+    is_video: bool,
+
+    // `true` for text tracks, whose fragments may have gaps between them
+    is_sparse: bool,
+
+    default_sample_duration: u32,
+    default_sample_flags: u32,
+}
+
+fn read_tracks(moov: &RawBox) -> Result<Vec<TrackInfo>> {
+    let mut tracks = Vec::new();
+    let mut trex_defaults = HashMap::new();
+    for b in moov.children() {
+        let b = track!(b)?;
+        match b.box_type {
+            fourcc::TRAK => tracks.push(track!(read_track(&b))?),
+            fourcc::MVEX => {
+                for trex in b.children() {
+                    let trex = track!(trex)?;
+                    if trex.box_type == fourcc::TREX {
+                        let mut reader = track!(trex.full_box())?.payload;
+                        let track_id = track_io!(reader.read_u32::<BigEndian>())?;
+                        track!(isobmff::skip_bytes(&mut reader, 4))?; // default_sample_description_index
+                        let duration = track_io!(reader.read_u32::<BigEndian>())?;
+                        track!(isobmff::skip_bytes(&mut reader, 4))?; // default_sample_size
+                        let flags = track_io!(reader.read_u32::<BigEndian>())?;
+                        trex_defaults.insert(track_id, (duration, flags));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for t in &mut tracks {
+        if let Some(&(duration, flags)) = trex_defaults.get(&t.track_id) {
+            t.default_sample_duration = duration;
+            t.default_sample_flags = flags;
+        }
+    }
+    Ok(tracks)
+}
+
+fn read_track(trak: &RawBox) -> Result<TrackInfo> {
+    let mut track_id = None;
+    let mut timescale = None;
+    let mut is_video = false;
+    let mut is_sparse = false;
+    for b in trak.children() {
+        let b = track!(b)?;
+        match b.box_type {
+            fourcc::TKHD => {
+                let full_box = track!(b.full_box())?;
+                let mut reader = full_box.payload;
+                let times_size = if full_box.version == 1 { 16 } else { 8 };
+                track!(isobmff::skip_bytes(&mut reader, times_size))?;
+                track_id = Some(track_io!(reader.read_u32::<BigEndian>())?);
+            }
+            fourcc::MDIA => {
+                for b in b.children() {
+                    let b = track!(b)?;
+                    match b.box_type {
+                        fourcc::MDHD => {
+                            let full_box = track!(b.full_box())?;
+                            let mut reader = full_box.payload;
+                            let times_size = if full_box.version == 1 { 16 } else { 8 };
+                            track!(isobmff::skip_bytes(&mut reader, times_size))?;
+                            timescale = Some(track_io!(reader.read_u32::<BigEndian>())?);
+                        }
+                        fourcc::HDLR => {
+                            let mut reader = track!(b.full_box())?.payload;
+                            track!(isobmff::skip_bytes(&mut reader, 4))?; // pre_defined
+                            is_video = reader.starts_with(&fourcc::VIDE);
+                            is_sparse = [fourcc::TEXT, fourcc::SUBT, fourcc::SBTL]
+                                .iter()
+                                .any(|t| reader.starts_with(t));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(TrackInfo {
+        track_id: track_assert_some!(track_id, ErrorKind::InvalidInput, "No `tkhd` box"),
+        timescale: track_assert_some!(timescale, ErrorKind::InvalidInput, "No `mdhd` box"),
+        is_video,
+        is_sparse,
+        default_sample_duration: 0,
+        default_sample_flags: 0,
+    })
+}
+
+#[derive(Debug)]
+struct FragmentBuilder {
+    bytes: Vec<u8>,
+    moof_offset: usize,
+    has_mdat: bool,
+}
+impl FragmentBuilder {
+    fn finish(self, tracks: &[TrackInfo]) -> Result<Fragment> {
+        let moof = track!(Boxes::new(&self.bytes[self.moof_offset..])
+            .next()
+            .expect("Never fails"))?;
+        let moof_payload_offset = self.moof_offset + moof.header_size;
+        let mut mfhd_offset = None;
+        let mut trafs = Vec::new();
+        for b in moof.children() {
+            let b = track!(b)?;
+            match b.box_type {
+                fourcc::MFHD => {
+                    // full box header (4 bytes) precedes `sequence_number`
+                    mfhd_offset = Some(moof_payload_offset + b.offset + b.header_size + 4);
+                }
+                fourcc::TRAF => {
+                    let traf_payload_offset = moof_payload_offset + b.offset + b.header_size;
+                    trafs.push(track!(TrafInfo::read(&b, traf_payload_offset, tracks))?);
+                }
+                _ => {}
+            }
+        }
+        track_assert!(
+            self.has_mdat || trafs.iter().all(|t| t.is_gap),
+            ErrorKind::InvalidInput,
+            "`moof` box without a following `mdat` box"
+        );
+        Ok(Fragment {
+            mfhd_offset: track_assert_some!(mfhd_offset, ErrorKind::InvalidInput, "No `mfhd` box"),
+            trafs,
+            bytes: self.bytes,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Fragment {
+    bytes: Vec<u8>,
+    mfhd_offset: usize,
+    trafs: Vec<TrafInfo>,
+}
+impl Fragment {
+    fn traf(&self, track_id: u32) -> Option<&TrafInfo> {
+        self.trafs.iter().find(|t| t.track_id == track_id)
+    }
+}
+
+#[derive(Debug)]
+struct TrafInfo {
+    track_id: u32,
+    tfdt_offset: usize,
+    base_media_decode_time: u64,
+
+    // The smallest presentation time among the samples (composition offsets may be negative)
+    earliest_presentation_time: i128,
+
+    duration: u64,
+    starts_with_sync: bool,
+
+    // `true` if the fragment has no samples and represents an empty period (`duration_is_empty`)
+    is_gap: bool,
+}
+impl TrafInfo {
+    fn read(traf: &RawBox, payload_offset: usize, tracks: &[TrackInfo]) -> Result<Self> {
+        let mut tfhd_box = None;
+        let mut tfdt = None;
+        let mut trun_boxes = Vec::new();
+        for b in traf.children() {
+            let b = track!(b)?;
+            match b.box_type {
+                fourcc::TFHD => tfhd_box = Some(track!(TrackFragmentHeaderBox::from_raw_box(&b))?),
+                fourcc::TFDT => {
+                    let full_box = track!(b.full_box())?;
+                    let mut reader = full_box.payload;
+                    let base_media_decode_time = if full_box.version == 1 {
+                        track_io!(reader.read_u64::<BigEndian>())?
+                    } else {
+                        u64::from(track_io!(reader.read_u32::<BigEndian>())?)
+                    };
+                    tfdt = Some((
+                        payload_offset + b.offset + b.header_size,
+                        base_media_decode_time,
+                    ));
+                }
+                fourcc::TRUN => trun_boxes.push(track!(TrackRunBox::from_raw_box(&b))?),
+                _ => {}
+            }
+        }
+        let tfhd_box = track_assert_some!(tfhd_box, ErrorKind::InvalidInput, "No `tfhd` box");
+        let (tfdt_offset, base_media_decode_time) =
+            track_assert_some!(tfdt, ErrorKind::Unsupported, "No `tfdt` box");
+
+        let track_id = tfhd_box.track_id();
+        let track = tracks.iter().find(|t| t.track_id == track_id);
+        let track = track_assert_some!(
+            track,
+            ErrorKind::InvalidInput,
+            "Unknown track: {}",
+            track_id
+        );
+
+        let is_gap =
+            tfhd_box.duration_is_empty && trun_boxes.iter().all(|trun| trun.samples.is_empty());
+        let first_sample_flags = trun_boxes.first().and_then(|trun| {
+            trun.first_sample_flags
+                .or_else(|| trun.samples.first().and_then(|s| s.flags))
+        });
+        let first_sample_flags = first_sample_flags
+            .or(tfhd_box.default_sample_flags)
+            .unwrap_or_else(|| SampleFlags::from_u32(track.default_sample_flags));
+
+        // Sample groups and encryption information do not affect the timing, so they are not read here
+        let traf_box = TrackFragmentBox {
+            tfhd_box,
+            tfdt_box: TrackFragmentBaseMediaDecodeTimeBox {
+                base_media_decode_time,
+                force_version_1: false,
+            },
+            trun_boxes,
+            sbgp_boxes: Vec::new(),
+            sgpd_boxes: Vec::new(),
+            senc_box: None,
+        };
+        let timing = track!(traf_box.timing_with_default(Some(track.default_sample_duration)))?;
+
+        Ok(TrafInfo {
+            track_id,
+            tfdt_offset,
+            base_media_decode_time,
+            earliest_presentation_time: i128::from(timing.earliest_presentation_time),
+            duration: timing.duration,
+            starts_with_sync: is_gap || !first_sample_flags.sample_is_non_sync_sample,
+            is_gap,
+        })
+    }
+}
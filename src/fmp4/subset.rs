@@ -0,0 +1,156 @@
+use crate::fmp4::mse::tkhd_track_id;
+use crate::fmp4::{MediaDataBox, MediaSegment, MovieFragmentBox};
+use crate::fourcc;
+use crate::isobmff::{Boxes, RawBox};
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use std::io::Write;
+
+/// Removes the tracks other than `track_ids` from a fragmented MP4 file
+/// (e.g., to strip audio descriptions or extra languages).
+///
+/// The `trak` and `trex` boxes of the removed tracks are dropped from the `moov` box and
+/// the `next_track_id` of the `mvhd` box is rewritten to follow the remaining tracks.
+/// In each fragment, the `traf` boxes of the removed tracks and their samples are dropped,
+/// and the samples of the remaining tracks are packed into a single `mdat` box.
+///
+/// The `sidx`, `ssix` and `mfra` boxes are dropped since the byte offsets they refer to are changed,
+/// and only the `mfhd` and `traf` boxes of each `moof` box are kept. The other boxes are copied as is.
+pub fn retain_tracks(file: &[u8], track_ids: &[u32]) -> Result<Vec<u8>> {
+    let mut retained = Vec::with_capacity(file.len());
+    let mut fragment: Option<MediaSegment> = None;
+    let mut has_moov = false;
+    for b in Boxes::new(file) {
+        let b = track!(b)?;
+        if b.box_type == fourcc::MDAT {
+            let segment = track_assert_some!(
+                fragment.as_mut(),
+                ErrorKind::InvalidInput,
+                "`mdat` box without a preceding `moof` box"
+            );
+            segment.mdat_boxes.push(MediaDataBox {
+                data: b.payload.to_owned(),
+            });
+            continue;
+        }
+        if let Some(segment) = fragment.take() {
+            track!(write_fragment(&mut retained, segment, track_ids))?;
+        }
+        match b.box_type {
+            fourcc::MOOV => {
+                track!(write_moov(&mut retained, &b, track_ids))?;
+                has_moov = true;
+            }
+            fourcc::MOOF => {
+                let mut moof_box = track!(MovieFragmentBox::from_raw_box(&b, None))?;
+                for traf in &mut moof_box.traf_boxes {
+                    // Makes the base data offsets relative to the `moof` box
+                    if let Some(offset) = traf.tfhd_box.base_data_offset.as_mut() {
+                        *offset = track_assert_some!(
+                            offset.checked_sub(b.offset as u64),
+                            ErrorKind::InvalidInput
+                        );
+                    }
+                }
+                fragment = Some(MediaSegment {
+                    moof_box,
+                    mdat_boxes: Vec::new(),
+                });
+            }
+            fourcc::SIDX | fourcc::SSIX | fourcc::MFRA => {}
+            _ => {
+                write_all!(retained, &file[b.offset..b.offset + b.size()]);
+            }
+        }
+    }
+    if let Some(segment) = fragment.take() {
+        track!(write_fragment(&mut retained, segment, track_ids))?;
+    }
+    track_assert!(has_moov, ErrorKind::InvalidInput, "No `moov` box");
+    Ok(retained)
+}
+
+fn write_fragment(
+    retained: &mut Vec<u8>,
+    mut segment: MediaSegment,
+    track_ids: &[u32],
+) -> Result<()> {
+    track!(segment.retain_tracks(track_ids))?;
+    if segment.moof_box.traf_boxes.is_empty() {
+        // None of the retained tracks has a fragment here
+        return Ok(());
+    }
+    track!(segment.write_large_to(retained))
+}
+
+fn write_moov(retained: &mut Vec<u8>, moov: &RawBox, track_ids: &[u32]) -> Result<()> {
+    let mut payload = Vec::with_capacity(moov.payload.len());
+    let mut mvhd = None;
+    let mut max_track_id = 0;
+    for child in moov.children() {
+        let child = track!(child)?;
+        let bytes = &moov.payload[child.offset..child.offset + child.size()];
+        match child.box_type {
+            fourcc::MVHD => {
+                mvhd = Some(payload.len());
+                write_all!(payload, bytes);
+            }
+            fourcc::TRAK => {
+                let track_id = track!(trak_track_id(&child))?;
+                if track_ids.contains(&track_id) {
+                    max_track_id = max_track_id.max(track_id);
+                    write_all!(payload, bytes);
+                }
+            }
+            fourcc::MVEX => {
+                let mut mvex = Vec::with_capacity(child.payload.len());
+                for b in child.children() {
+                    let b = track!(b)?;
+                    if b.box_type == fourcc::TREX {
+                        let mut reader = track!(b.full_box())?.payload;
+                        let track_id = track_io!(reader.read_u32::<BigEndian>())?;
+                        if !track_ids.contains(&track_id) {
+                            continue;
+                        }
+                    }
+                    write_all!(mvex, &child.payload[b.offset..b.offset + b.size()]);
+                }
+                write_u32!(payload, 8 + mvex.len() as u32);
+                write_all!(payload, &fourcc::MVEX);
+                write_all!(payload, &mvex);
+            }
+            _ => {
+                write_all!(payload, bytes);
+            }
+        }
+    }
+    track_assert_ne!(
+        max_track_id,
+        0,
+        ErrorKind::InvalidInput,
+        "No tracks retained"
+    );
+
+    // `next_track_id` is the last field of the `mvhd` box (`0xFFFF_FFFF` means it is not in use)
+    let mvhd = track_assert_some!(mvhd, ErrorKind::InvalidInput, "No `mvhd` box");
+    let mvhd_end = mvhd + BigEndian::read_u32(&payload[mvhd..]) as usize;
+    let next_track_id = &mut payload[mvhd_end - 4..mvhd_end];
+    if BigEndian::read_u32(next_track_id) != 0xFFFF_FFFF {
+        BigEndian::write_u32(next_track_id, max_track_id.saturating_add(1));
+    }
+
+    write_u32!(retained, 8 + payload.len() as u32);
+    write_all!(retained, &fourcc::MOOV);
+    write_all!(retained, &payload);
+    Ok(())
+}
+
+fn trak_track_id(trak: &RawBox) -> Result<u32> {
+    for b in trak.children() {
+        let b = track!(b)?;
+        if b.box_type == fourcc::TKHD {
+            return track!(tkhd_track_id(&b));
+        }
+    }
+    track_panic!(ErrorKind::InvalidInput, "No `tkhd` box")
+}
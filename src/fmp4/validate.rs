@@ -0,0 +1,157 @@
+use crate::fmp4::{mse_compatibility, InitializationSegment, MediaSegment, MseFix};
+use crate::fourcc;
+use crate::io::WriteTo;
+use crate::Result;
+
+/// Problem found by `validate_segments`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentIssue {
+    /// The initialization segment does not conform to the ISO BMFF Byte Stream Format of MSE
+    /// (see `mse_compatibility`).
+    Initialization(MseFix),
+
+    /// The segment has a track fragment of a track that is not in the initialization segment.
+    UnknownTrack {
+        /// Sequence number of the segment.
+        sequence_number: u32,
+
+        /// Identifier of the track.
+        track_id: u32,
+    },
+
+    /// The sequence number does not increase from that of the preceding segment.
+    SequenceNumberNotIncreasing {
+        /// Sequence number of the segment.
+        sequence_number: u32,
+
+        /// Sequence number of the preceding segment.
+        previous: u32,
+    },
+
+    /// The samples of the segment cannot be resolved
+    /// (e.g., a sample lacks its duration or size, or its data is out of the `mdat` boxes).
+    InvalidSamples {
+        /// Sequence number of the segment.
+        sequence_number: u32,
+    },
+
+    /// The decode time of the track fragment differs from the end of the preceding fragment of the track
+    /// (i.e., there is a gap or an overlap).
+    Discontinuity {
+        /// Sequence number of the segment.
+        sequence_number: u32,
+
+        /// Identifier of the track.
+        track_id: u32,
+
+        /// End of the preceding fragment of the track.
+        expected: u64,
+
+        /// Decode time of the track fragment.
+        actual: u64,
+    },
+
+    /// The first sample of the video track fragment is not a sync sample,
+    /// so the segment cannot be decoded independently.
+    NoLeadingSyncSample {
+        /// Sequence number of the segment.
+        sequence_number: u32,
+
+        /// Identifier of the track.
+        track_id: u32,
+    },
+}
+
+/// Checks `media_segments` as a continuous stream that follows `initialization_segment`.
+///
+/// The following conditions are checked:
+///
+/// - The initialization segment is compatible with MSE (see `mse_compatibility`),
+/// - Each track fragment belongs to a track of the initialization segment,
+/// - The sequence numbers increase,
+/// - The samples have durations and sizes, and their data is in the `mdat` boxes,
+/// - Each track fragment starts where the preceding one of the track ends, and
+/// - Each video track fragment starts with a sync sample.
+///
+/// An empty list is returned if no problems are found.
+/// Track fragments without samples (see `MediaSegment::new_gap`) are exempted from the last two checks.
+pub fn validate_segments(
+    initialization_segment: &InitializationSegment,
+    media_segments: &[MediaSegment],
+) -> Result<Vec<SegmentIssue>> {
+    let bytes = track!(initialization_segment.to_bytes())?;
+    let mut issues = track!(mse_compatibility(&bytes))?
+        .into_iter()
+        .map(SegmentIssue::Initialization)
+        .collect::<Vec<_>>();
+
+    let tracks = initialization_segment
+        .moov_box
+        .trak_boxes
+        .iter()
+        .map(|t| (t.tkhd_box.track_id(), t.mdia_box.hdlr_box.handler_type()))
+        .collect::<Vec<_>>();
+    let mut track_ends: Vec<Option<u64>> = vec![None; tracks.len()];
+    let mut previous: Option<u32> = None;
+    for segment in media_segments {
+        let sequence_number = segment.moof_box.mfhd_box.sequence_number;
+        if let Some(previous) = previous {
+            if sequence_number <= previous {
+                issues.push(SegmentIssue::SequenceNumberNotIncreasing {
+                    sequence_number,
+                    previous,
+                });
+            }
+        }
+        previous = Some(sequence_number);
+
+        let has_valid_samples = match segment.samples() {
+            Err(_) => false,
+            Ok(mut samples) => samples.all(|s| s.is_ok()),
+        };
+        if !has_valid_samples {
+            issues.push(SegmentIssue::InvalidSamples { sequence_number });
+        }
+
+        for traf in &segment.moof_box.traf_boxes {
+            let track_id = traf.tfhd_box.track_id();
+            let i = match tracks.iter().position(|t| t.0 == track_id) {
+                None => {
+                    issues.push(SegmentIssue::UnknownTrack {
+                        sequence_number,
+                        track_id,
+                    });
+                    continue;
+                }
+                Some(i) => i,
+            };
+            let timing = match traf.timing() {
+                Err(_) => continue, // reported as `InvalidSamples`
+                Ok(timing) => timing,
+            };
+            if traf.is_empty() {
+                // The duration of a gap is not known from its samples
+                track_ends[i] = None;
+                continue;
+            }
+            if let Some(expected) = track_ends[i] {
+                if expected != timing.base_media_decode_time {
+                    issues.push(SegmentIssue::Discontinuity {
+                        sequence_number,
+                        track_id,
+                        expected,
+                        actual: timing.base_media_decode_time,
+                    });
+                }
+            }
+            track_ends[i] = Some(timing.base_media_decode_time + timing.duration);
+            if tracks[i].1 == fourcc::VIDE && !traf.starts_with_sync_sample() {
+                issues.push(SegmentIssue::NoLeadingSyncSample {
+                    sequence_number,
+                    track_id,
+                });
+            }
+        }
+    }
+    Ok(issues)
+}
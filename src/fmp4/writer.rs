@@ -0,0 +1,194 @@
+use crate::fmp4::{
+    InitializationSegment, MediaSegment, MovieFragmentRandomAccessBox, Mp4Box, RandomAccessEntry,
+    SegmentIndexBox, SegmentReference, TrackFragmentRandomAccessBox,
+};
+use crate::io::{ByteCounter, WriteTo};
+use crate::{ErrorKind, Result};
+use std::cmp;
+use std::io::Write;
+
+/// Writes a self-initializing file that consists of `initialization_segment`,
+/// all of `media_segments` and a closing `mfra` box.
+///
+/// The `mfra` box has an entry for each track fragment starting with a sync sample,
+/// and the offsets of the entries are computed while writing.
+/// This is useful for archiving (e.g., DVR recordings) while keeping the file fragmented.
+pub fn write_self_initializing_file<W: Write>(
+    writer: W,
+    initialization_segment: &InitializationSegment,
+    media_segments: &[MediaSegment],
+) -> Result<()> {
+    let mut writer = SegmentWriter::new(writer);
+    track!(writer.write_initialization_segment(initialization_segment))?;
+    for segment in media_segments {
+        track!(writer.write_media_segment(segment))?;
+    }
+    track!(writer.write_mfra_box())?;
+    Ok(())
+}
+
+/// Writer of segments that keeps track of the absolute byte offsets of the written `moof` boxes.
+///
+/// The recorded offsets are used to make `sidx` and `mfra` boxes for the written media segments,
+/// so that users do not need to count the bytes by themselves.
+#[derive(Debug)]
+pub struct SegmentWriter<W> {
+    writer: ByteCounter<W>,
+    fragments: Vec<FragmentRecord>,
+}
+impl<W: Write> SegmentWriter<W> {
+    /// Makes a new `SegmentWriter` instance.
+    pub fn new(writer: W) -> Self {
+        SegmentWriter {
+            writer: ByteCounter::new(writer),
+            fragments: Vec::new(),
+        }
+    }
+
+    /// Writes an initialization segment.
+    pub fn write_initialization_segment(&mut self, segment: &InitializationSegment) -> Result<()> {
+        track!(segment.write_to(&mut self.writer))
+    }
+
+    /// Writes a media segment and records its offset.
+    ///
+    /// `mdat` boxes larger than 4 GiB are written with the `largesize` field (see `MediaSegment::write_large_to`).
+    pub fn write_media_segment(&mut self, segment: &MediaSegment) -> Result<()> {
+        let offset = self.writer.count();
+        let timings = track!(segment.track_timings())?;
+        let mut tracks = Vec::with_capacity(timings.len());
+        for (traf, timing) in segment.moof_box.traf_boxes.iter().zip(timings) {
+            let composition_time_offset = traf
+                .trun_boxes
+                .first()
+                .and_then(|t| t.samples.first())
+                .and_then(|s| s.composition_time_offset)
+                .unwrap_or(0);
+            let first_presentation_time =
+                timing.base_media_decode_time as i64 + i64::from(composition_time_offset);
+            tracks.push(TrackFragmentRecord {
+                track_id: timing.track_id,
+                starts_with_sync_sample: traf.starts_with_sync_sample(),
+                first_presentation_time: cmp::max(0, first_presentation_time) as u64,
+                earliest_presentation_time: cmp::max(0, timing.earliest_presentation_time) as u64,
+                duration: timing.duration,
+            });
+        }
+
+        track!(segment.write_large_to(&mut self.writer))?;
+        self.fragments.push(FragmentRecord {
+            offset,
+            size: self.writer.count() - offset,
+            tracks,
+        });
+        Ok(())
+    }
+
+    /// Writes the `mfra` box made by `mfra_box()`.
+    pub fn write_mfra_box(&mut self) -> Result<()> {
+        let mfra_box = self.mfra_box();
+        write_box!(self.writer, mfra_box);
+        Ok(())
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn position(&self) -> u64 {
+        self.writer.count()
+    }
+
+    /// Returns the absolute byte offsets of the written `moof` boxes.
+    pub fn moof_offsets(&self) -> Vec<u64> {
+        self.fragments.iter().map(|f| f.offset).collect()
+    }
+
+    /// Makes a `mfra` box that has an entry for each written track fragment starting with a sync sample.
+    pub fn mfra_box(&self) -> MovieFragmentRandomAccessBox {
+        let mut mfra_box = MovieFragmentRandomAccessBox::default();
+        for fragment in &self.fragments {
+            for (i, track) in fragment.tracks.iter().enumerate() {
+                if !track.starts_with_sync_sample {
+                    continue;
+                }
+                let entry = RandomAccessEntry {
+                    time: track.first_presentation_time,
+                    moof_offset: fragment.offset,
+                    traf_number: i as u32 + 1,
+                    trun_number: 1,
+                    sample_number: 1,
+                };
+
+                let tfra_boxes = &mut mfra_box.tfra_boxes;
+                if let Some(tfra) = tfra_boxes.iter_mut().find(|t| t.track_id == track.track_id) {
+                    tfra.entries.push(entry);
+                } else {
+                    tfra_boxes.push(TrackFragmentRandomAccessBox {
+                        track_id: track.track_id,
+                        entries: vec![entry],
+                    });
+                }
+            }
+        }
+        mfra_box
+    }
+
+    /// Makes a `sidx` box that references each written media segment.
+    ///
+    /// `reference_id` is the identifier of the track whose timing is used, and
+    /// `timescale` must be the timescale of the track.
+    /// The `first_offset` of the box is zero, that is, the box is supposed to be placed
+    /// immediately before the first written media segment.
+    pub fn sidx_box(&self, reference_id: u32, timescale: u32) -> Result<SegmentIndexBox> {
+        let mut earliest_presentation_time = None;
+        let mut references = Vec::with_capacity(self.fragments.len());
+        for fragment in &self.fragments {
+            let track = fragment.tracks.iter().find(|t| t.track_id == reference_id);
+            let track = track_assert_some!(track, ErrorKind::InvalidInput; reference_id);
+            if earliest_presentation_time.is_none() {
+                earliest_presentation_time = Some(track.earliest_presentation_time);
+            }
+            track_assert!(fragment.size < (1 << 31), ErrorKind::SegmentTooLarge; fragment.size);
+            track_assert!(track.duration <= u64::from(u32::MAX), ErrorKind::Unsupported; track.duration);
+            references.push(SegmentReference {
+                reference_type: false,
+                referenced_size: fragment.size as u32,
+                subsegment_duration: track.duration as u32,
+                starts_with_sap: track.starts_with_sync_sample,
+                sap_type: if track.starts_with_sync_sample { 1 } else { 0 },
+                sap_delta_time: 0,
+            });
+        }
+        Ok(SegmentIndexBox {
+            reference_id,
+            timescale,
+            earliest_presentation_time: earliest_presentation_time.unwrap_or(0),
+            first_offset: 0,
+            references,
+        })
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn inner_ref(&self) -> &W {
+        self.writer.inner_ref()
+    }
+
+    /// Takes ownership of the writer and returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}
+
+#[derive(Debug)]
+struct FragmentRecord {
+    offset: u64,
+    size: u64,
+    tracks: Vec<TrackFragmentRecord>,
+}
+
+#[derive(Debug)]
+struct TrackFragmentRecord {
+    track_id: u32,
+    starts_with_sync_sample: bool,
+    first_presentation_time: u64,
+    earliest_presentation_time: u64,
+    duration: u64,
+}
@@ -0,0 +1,274 @@
+//! Four-character codes (e.g., box types, sample entry types and brands).
+//!
+//! The constants are plain `[u8; 4]` values, so that they can be compared with (and matched against)
+//! `RawBox::box_type` and `Mp4Box::BOX_TYPE` directly.
+//! `BoxType` is a wrapper of a code for parsing and displaying it.
+//!
+//! # Examples
+//!
+//! ```
+//! use mse_fmp4::fourcc::{self, BoxType};
+//!
+//! let box_type: BoxType = "moof".parse().unwrap();
+//! assert_eq!(box_type, BoxType(fourcc::MOOF));
+//! assert_eq!(box_type.to_string(), "moof");
+//! ```
+use crate::{Error, ErrorKind, Result};
+use std::fmt;
+use std::str::FromStr;
+
+// Box types
+/// `ftyp` (File Type Box).
+pub const FTYP: [u8; 4] = *b"ftyp";
+/// `styp` (Segment Type Box).
+pub const STYP: [u8; 4] = *b"styp";
+/// `moov` (Movie Box).
+pub const MOOV: [u8; 4] = *b"moov";
+/// `mvhd` (Movie Header Box).
+pub const MVHD: [u8; 4] = *b"mvhd";
+/// `trak` (Track Box).
+pub const TRAK: [u8; 4] = *b"trak";
+/// `tkhd` (Track Header Box).
+pub const TKHD: [u8; 4] = *b"tkhd";
+/// `edts` (Edit Box).
+pub const EDTS: [u8; 4] = *b"edts";
+/// `elst` (Edit List Box).
+pub const ELST: [u8; 4] = *b"elst";
+/// `mdia` (Media Box).
+pub const MDIA: [u8; 4] = *b"mdia";
+/// `mdhd` (Media Header Box).
+pub const MDHD: [u8; 4] = *b"mdhd";
+/// `hdlr` (Handler Reference Box).
+pub const HDLR: [u8; 4] = *b"hdlr";
+/// `minf` (Media Information Box).
+pub const MINF: [u8; 4] = *b"minf";
+/// `vmhd` (Video Media Header Box).
+pub const VMHD: [u8; 4] = *b"vmhd";
+/// `smhd` (Sound Media Header Box).
+pub const SMHD: [u8; 4] = *b"smhd";
+/// `nmhd` (Null Media Header Box).
+pub const NMHD: [u8; 4] = *b"nmhd";
+/// `dinf` (Data Information Box).
+pub const DINF: [u8; 4] = *b"dinf";
+/// `dref` (Data Reference Box).
+pub const DREF: [u8; 4] = *b"dref";
+/// `url ` (Data Entry Url Box).
+pub const URL: [u8; 4] = *b"url ";
+/// `stbl` (Sample Table Box).
+pub const STBL: [u8; 4] = *b"stbl";
+/// `stsd` (Sample Description Box).
+pub const STSD: [u8; 4] = *b"stsd";
+/// `stts` (Decoding Time to Sample Box).
+pub const STTS: [u8; 4] = *b"stts";
+/// `stsc` (Sample To Chunk Box).
+pub const STSC: [u8; 4] = *b"stsc";
+/// `stsz` (Sample Size Box).
+pub const STSZ: [u8; 4] = *b"stsz";
+/// `stco` (Chunk Offset Box).
+pub const STCO: [u8; 4] = *b"stco";
+/// `mvex` (Movie Extends Box).
+pub const MVEX: [u8; 4] = *b"mvex";
+/// `mehd` (Movie Extends Header Box).
+pub const MEHD: [u8; 4] = *b"mehd";
+/// `trex` (Track Extends Box).
+pub const TREX: [u8; 4] = *b"trex";
+/// `udta` (User Data Box).
+pub const UDTA: [u8; 4] = *b"udta";
+/// `kind` (Track Kind Box).
+pub const KIND: [u8; 4] = *b"kind";
+/// `meta` (Meta Box).
+pub const META: [u8; 4] = *b"meta";
+/// `iinf` (Item Information Box).
+pub const IINF: [u8; 4] = *b"iinf";
+/// `infe` (Item Information Entry).
+pub const INFE: [u8; 4] = *b"infe";
+/// `iloc` (Item Location Box).
+pub const ILOC: [u8; 4] = *b"iloc";
+/// `pssh` (Protection System Specific Header Box).
+pub const PSSH: [u8; 4] = *b"pssh";
+/// `free` (Free Space Box).
+pub const FREE: [u8; 4] = *b"free";
+/// `skip` (Free Space Box (`skip`)).
+pub const SKIP: [u8; 4] = *b"skip";
+/// `moof` (Movie Fragment Box).
+pub const MOOF: [u8; 4] = *b"moof";
+/// `mfhd` (Movie Fragment Header Box).
+pub const MFHD: [u8; 4] = *b"mfhd";
+/// `traf` (Track Fragment Box).
+pub const TRAF: [u8; 4] = *b"traf";
+/// `tfhd` (Track Fragment Header Box).
+pub const TFHD: [u8; 4] = *b"tfhd";
+/// `tfdt` (Track Fragment Decode Time Box).
+pub const TFDT: [u8; 4] = *b"tfdt";
+/// `trun` (Track Fragment Run Box).
+pub const TRUN: [u8; 4] = *b"trun";
+/// `sbgp` (Sample To Group Box).
+pub const SBGP: [u8; 4] = *b"sbgp";
+/// `sgpd` (Sample Group Description Box).
+pub const SGPD: [u8; 4] = *b"sgpd";
+/// `senc` (Sample Encryption Box).
+pub const SENC: [u8; 4] = *b"senc";
+/// `mdat` (Media Data Box).
+pub const MDAT: [u8; 4] = *b"mdat";
+/// `sidx` (Segment Index Box).
+pub const SIDX: [u8; 4] = *b"sidx";
+/// `ssix` (Subsegment Index Box).
+pub const SSIX: [u8; 4] = *b"ssix";
+/// `mfra` (Movie Fragment Random Access Box).
+pub const MFRA: [u8; 4] = *b"mfra";
+/// `tfra` (Track Fragment Random Access Box).
+pub const TFRA: [u8; 4] = *b"tfra";
+/// `mfro` (Movie Fragment Random Access Offset Box).
+pub const MFRO: [u8; 4] = *b"mfro";
+/// `emsg` (Event Message Box).
+pub const EMSG: [u8; 4] = *b"emsg";
+/// `avcC` (AVC Configuration Box).
+pub const AVCC: [u8; 4] = *b"avcC";
+/// `vpcC` (VP Codec Configuration Box).
+pub const VPCC: [u8; 4] = *b"vpcC";
+/// `av1C` (AV1 Codec Configuration Box).
+pub const AV1C: [u8; 4] = *b"av1C";
+/// `esds` (Elementary Stream Descriptor Box).
+pub const ESDS: [u8; 4] = *b"esds";
+/// `srat` (Sampling Rate Box).
+pub const SRAT: [u8; 4] = *b"srat";
+/// `dvcC` (Dolby Vision Configuration Box).
+pub const DVCC: [u8; 4] = *b"dvcC";
+/// `dvvC` (Dolby Vision Configuration Box (profiles 8 to 10)).
+pub const DVVC: [u8; 4] = *b"dvvC";
+/// `mdcv` (Mastering Display Colour Volume Box).
+pub const MDCV: [u8; 4] = *b"mdcv";
+/// `clli` (Content Light Level Box).
+pub const CLLI: [u8; 4] = *b"clli";
+/// `vttC` (WebVTT Configuration Box).
+pub const VTTC_CONFIG: [u8; 4] = *b"vttC";
+/// `vttc` (WebVTT Cue Box).
+pub const VTTC: [u8; 4] = *b"vttc";
+/// `vtte` (WebVTT Empty Cue Box).
+pub const VTTE: [u8; 4] = *b"vtte";
+/// `iden` (Cue ID Box (WebVTT)).
+pub const IDEN: [u8; 4] = *b"iden";
+/// `sttg` (Cue Settings Box (WebVTT)).
+pub const STTG: [u8; 4] = *b"sttg";
+/// `payl` (Cue Payload Box (WebVTT)).
+pub const PAYL: [u8; 4] = *b"payl";
+
+// Sample entry types
+/// `avc1` (AVC (H.264) video).
+pub const AVC1: [u8; 4] = *b"avc1";
+/// `avc3` (AVC (H.264) video with in-band parameter sets).
+pub const AVC3: [u8; 4] = *b"avc3";
+/// `hvc1` (HEVC (H.265) video).
+pub const HVC1: [u8; 4] = *b"hvc1";
+/// `hev1` (HEVC (H.265) video with in-band parameter sets).
+pub const HEV1: [u8; 4] = *b"hev1";
+/// `vp09` (VP9 video).
+pub const VP09: [u8; 4] = *b"vp09";
+/// `av01` (AV1 video).
+pub const AV01: [u8; 4] = *b"av01";
+/// `mp4a` (MPEG-4 audio (e.g., AAC)).
+pub const MP4A: [u8; 4] = *b"mp4a";
+/// `wvtt` (WebVTT text).
+pub const WVTT: [u8; 4] = *b"wvtt";
+/// `encv` (Encrypted video).
+pub const ENCV: [u8; 4] = *b"encv";
+/// `enca` (Encrypted audio).
+pub const ENCA: [u8; 4] = *b"enca";
+
+// Handler types
+/// `vide` (Video track).
+pub const VIDE: [u8; 4] = *b"vide";
+/// `soun` (Audio track).
+pub const SOUN: [u8; 4] = *b"soun";
+/// `text` (Text track).
+pub const TEXT: [u8; 4] = *b"text";
+/// `subt` (Subtitle track).
+pub const SUBT: [u8; 4] = *b"subt";
+/// `sbtl` (Subtitle track (QuickTime)).
+pub const SBTL: [u8; 4] = *b"sbtl";
+/// `pict` (Image items (e.g., HEIF)).
+pub const PICT: [u8; 4] = *b"pict";
+/// `mdir` (Apple metadata (`meta` box)).
+pub const MDIR: [u8; 4] = *b"mdir";
+
+// Brands
+/// `isom` (ISO base media file format).
+pub const ISOM: [u8; 4] = *b"isom";
+/// `iso5` (ISO base media file format with the features of ISO/IEC 14496-12 4th edition).
+pub const ISO5: [u8; 4] = *b"iso5";
+/// `iso6` (ISO base media file format with the features of ISO/IEC 14496-12 5th edition).
+pub const ISO6: [u8; 4] = *b"iso6";
+/// `mp41` (MP4 version 1).
+pub const MP41: [u8; 4] = *b"mp41";
+/// `dash` (DASH media segment).
+pub const DASH: [u8; 4] = *b"dash";
+/// `msdh` (Media segment (DASH)).
+pub const MSDH: [u8; 4] = *b"msdh";
+/// `msix` (Indexed media segment (DASH)).
+pub const MSIX: [u8; 4] = *b"msix";
+
+// Sample grouping types
+/// `roll` (Roll recovery).
+pub const ROLL: [u8; 4] = *b"roll";
+/// `prol` (Pre-roll).
+pub const PROL: [u8; 4] = *b"prol";
+/// `seig` (CENC sample encryption information).
+pub const SEIG: [u8; 4] = *b"seig";
+
+// Item types
+/// `mime` (MIME item).
+pub const MIME: [u8; 4] = *b"mime";
+
+/// Four-character code (e.g., a box type).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BoxType(pub [u8; 4]);
+impl BoxType {
+    /// Returns the code as bytes.
+    pub fn as_bytes(&self) -> &[u8; 4] {
+        &self.0
+    }
+}
+impl From<[u8; 4]> for BoxType {
+    fn from(f: [u8; 4]) -> Self {
+        BoxType(f)
+    }
+}
+impl From<BoxType> for [u8; 4] {
+    fn from(f: BoxType) -> Self {
+        f.0
+    }
+}
+impl FromStr for BoxType {
+    type Err = Error;
+
+    /// Parses a code that consists of exactly four ASCII characters (e.g., `"url "`).
+    fn from_str(s: &str) -> Result<Self> {
+        track_assert!(
+            s.len() == 4 && s.is_ascii(),
+            ErrorKind::InvalidInput,
+            "Not a four-character code: {:?}",
+            s
+        );
+        let mut code = [0; 4];
+        code.copy_from_slice(s.as_bytes());
+        Ok(BoxType(code))
+    }
+}
+impl fmt::Display for BoxType {
+    /// Formats the code as text (non-printable bytes are escaped, e.g., `\x00`).
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &b in &self.0 {
+            if b.is_ascii_graphic() || b == b' ' {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "\\x{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Debug for BoxType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BoxType({:?})", self.to_string())
+    }
+}
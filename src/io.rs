@@ -1,40 +1,122 @@
 //! I/O related constituent elements.
-use crate::Result;
+use crate::{ErrorKind, Result};
 use byteorder::ReadBytesExt;
-use std::io::{sink, Read, Result as IoResult, Sink, Write};
+use std::io::{sink, IoSlice, Read, Result as IoResult, Sink, Write};
+
+pub use self::digest::{write_with_digest, Digest, DigestWriter, Md5, Sha256};
+pub use self::pool::{BufferPool, PooledBuffer};
+pub use self::sink::{NumberedFileSink, RingBufferSink, SegmentSink};
+
+mod digest;
+mod pool;
+mod sink;
 
 /// A trait for objects which can be written to byte-oriented sinks.
 pub trait WriteTo {
     /// Writes this object to the given byte-oriented sink.
     fn write_to<W: Write>(&self, writer: W) -> Result<()>;
+
+    /// Writes this object to a new `Vec` and returns it.
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        track!(self.write_to(&mut buf))?;
+        Ok(buf)
+    }
+}
+impl<T: WriteTo + ?Sized> WriteTo for &T {
+    fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        track!((**self).write_to(writer))
+    }
 }
 
+/// Writes all of `bufs` to `writer` using `Write::write_vectored`.
+///
+/// This reduces the number of system calls when `writer` is, for example, a socket
+/// and the data is scattered across several buffers.
+pub fn write_all_vectored<W: Write>(mut writer: W, bufs: &[&[u8]]) -> Result<()> {
+    let mut bufs = bufs
+        .iter()
+        .filter(|b| !b.is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut start = 0;
+    while start < bufs.len() {
+        let slices = bufs[start..]
+            .iter()
+            .map(|b| IoSlice::new(b))
+            .collect::<Vec<_>>();
+        let mut written = track_io!(writer.write_vectored(&slices))?;
+        track_assert_ne!(
+            written,
+            0,
+            ErrorKind::Other,
+            "Failed to write whole buffers"
+        );
+        while start < bufs.len() && written >= bufs[start].len() {
+            written -= bufs[start].len();
+            start += 1;
+        }
+        if written > 0 {
+            bufs[start] = &bufs[start][written..];
+        }
+    }
+    Ok(())
+}
+
+/// A writer that counts the bytes written to the inner writer.
 #[derive(Debug)]
-pub(crate) struct ByteCounter<T> {
+pub struct ByteCounter<T> {
     inner: T,
     count: u64,
 }
 impl<T> ByteCounter<T> {
+    /// Makes a new `ByteCounter` instance.
     pub fn new(inner: T) -> Self {
         ByteCounter { inner, count: 0 }
     }
 
+    /// Returns the number of bytes written so far.
     pub fn count(&self) -> u64 {
         self.count
     }
+
+    /// Returns a reference to the inner writer.
+    pub fn inner_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Takes ownership of the counter and returns the inner writer.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
 }
 impl ByteCounter<Sink> {
+    /// Makes a new `ByteCounter` instance that discards the written bytes.
     pub fn with_sink() -> Self {
         Self::new(sink())
     }
 
+    /// Returns the number of bytes written by `f`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mse_fmp4::io::ByteCounter;
+    /// use std::io::Write;
+    ///
+    /// let size = ByteCounter::calculate(|w| {
+    ///     w.write_all(b"foo").unwrap();
+    ///     Ok(())
+    /// });
+    /// assert_eq!(size.ok(), Some(3));
+    /// ```
     pub fn calculate<F>(f: F) -> Result<u64>
     where
         F: FnOnce(&mut Self) -> Result<()>,
     {
         let mut writer = ByteCounter::with_sink();
         track!(f(&mut writer))?;
-        Ok(writer.count() as u64)
+        Ok(writer.count())
     }
 }
 impl<T: Write> Write for ByteCounter<T> {
@@ -49,24 +131,31 @@ impl<T: Write> Write for ByteCounter<T> {
     }
 }
 
+/// Bit reader for AVC RBSP (Raw Byte Sequence Payload).
+///
+/// The emulation prevention bytes contained in the underlying stream are removed transparently.
 #[derive(Debug)]
-pub(crate) struct AvcBitReader<R> {
+pub struct AvcBitReader<R> {
     stream: R,
     byte: u8,
     bit_offset: usize,
+    zero_bytes: usize,
 }
 impl<R: Read> AvcBitReader<R> {
+    /// Makes a new `AvcBitReader` instance.
     pub fn new(stream: R) -> Self {
         AvcBitReader {
             stream,
             byte: 0,
             bit_offset: 8,
+            zero_bytes: 0,
         }
     }
 
+    /// Reads a bit.
     pub fn read_bit(&mut self) -> Result<u8> {
         if self.bit_offset == 8 {
-            self.byte = track_io!(self.stream.read_u8())?;
+            self.byte = track!(self.read_rbsp_byte())?;
             self.bit_offset = 0;
         }
         let bit = (self.byte >> (7 - self.bit_offset)) & 0b1;
@@ -74,14 +163,88 @@ impl<R: Read> AvcBitReader<R> {
         Ok(bit)
     }
 
+    /// Reads a boolean flag (i.e., a bit).
+    pub fn read_flag(&mut self) -> Result<bool> {
+        Ok(track!(self.read_bit())? == 1)
+    }
+
+    /// Reads `n` bits as an unsigned integer (i.e., `u(n)`).
+    ///
+    /// `n` must be less than or equal to `64`.
+    pub fn read_bits(&mut self, n: usize) -> Result<u64> {
+        track_assert!(n <= 64, ErrorKind::InvalidInput; n);
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | u64::from(track!(self.read_bit())?);
+        }
+        Ok(value)
+    }
+
+    /// Reads an unsigned 8-bit integer (i.e., `u(8)`).
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(track!(self.read_bits(8))? as u8)
+    }
+
+    /// Reads an unsigned Exp-Golomb-coded integer (i.e., `ue(v)`).
     pub fn read_ue(&mut self) -> Result<u64> {
         track!(self.read_exp_golomb_code())
     }
 
+    /// Reads a signed Exp-Golomb-coded integer (i.e., `se(v)`).
+    pub fn read_se(&mut self) -> Result<i64> {
+        let n = track!(self.read_exp_golomb_code())?;
+        if n % 2 == 1 {
+            Ok((n / 2 + 1) as i64)
+        } else {
+            Ok(-((n / 2) as i64))
+        }
+    }
+
+    /// Returns `true` if the reader is positioned at a byte boundary, otherwise `false`.
+    pub fn is_byte_aligned(&self) -> bool {
+        self.bit_offset == 8
+    }
+
+    /// Skips the remaining bits in the current byte.
+    ///
+    /// If any of the skipped bits is non-zero, an `ErrorKind::InvalidInput` error is returned.
+    pub fn align_to_byte(&mut self) -> Result<()> {
+        while !self.is_byte_aligned() {
+            track_assert_eq!(track!(self.read_bit())?, 0, ErrorKind::InvalidInput);
+        }
+        Ok(())
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn inner_ref(&self) -> &R {
+        &self.stream
+    }
+
+    /// Takes ownership of the reader and returns the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.stream
+    }
+
+    fn read_rbsp_byte(&mut self) -> Result<u8> {
+        let mut byte = track_io!(self.stream.read_u8())?;
+        if self.zero_bytes >= 2 && byte == 0x03 {
+            // emulation_prevention_three_byte
+            byte = track_io!(self.stream.read_u8())?;
+            self.zero_bytes = 0;
+        }
+        if byte == 0 {
+            self.zero_bytes += 1;
+        } else {
+            self.zero_bytes = 0;
+        }
+        Ok(byte)
+    }
+
     fn read_exp_golomb_code(&mut self) -> Result<u64> {
         let mut leading_zeros = 0;
         while 0 == track!(self.read_bit())? {
             leading_zeros += 1;
+            track_assert!(leading_zeros < 64, ErrorKind::InvalidInput);
         }
         let mut n = 0;
         for _ in 0..leading_zeros {
@@ -0,0 +1,427 @@
+use crate::io::WriteTo;
+use crate::Result;
+use std::io::{Result as IoResult, Write};
+
+/// A trait for incremental message digest algorithms.
+pub trait Digest {
+    /// Digest value (e.g., `[u8; 32]` for SHA-256).
+    type Output;
+
+    /// Feeds `bytes` to the algorithm.
+    fn update(&mut self, bytes: &[u8]);
+
+    /// Consumes the instance and returns the digest of the bytes fed so far.
+    fn finish(self) -> Self::Output;
+}
+
+/// A writer that computes the digest and counts the bytes while writing them to the inner writer.
+///
+/// # Examples
+///
+/// ```
+/// use mse_fmp4::io::{DigestWriter, Sha256};
+/// use std::io::Write;
+///
+/// let mut writer = DigestWriter::new(Vec::new(), Sha256::new());
+/// writer.write_all(b"abc").unwrap();
+/// let (bytes, digest, count) = writer.finish();
+/// assert_eq!(bytes, b"abc");
+/// assert_eq!(count, 3);
+/// assert_eq!(&digest[..4], &[0xba, 0x78, 0x16, 0xbf]);
+/// ```
+#[derive(Debug)]
+pub struct DigestWriter<W, D> {
+    inner: W,
+    digest: D,
+    count: u64,
+}
+impl<W: Write, D: Digest> DigestWriter<W, D> {
+    /// Makes a new `DigestWriter` instance.
+    pub fn new(inner: W, digest: D) -> Self {
+        DigestWriter {
+            inner,
+            digest,
+            count: 0,
+        }
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Consumes the writer and returns the inner writer, the digest and the number of written bytes.
+    pub fn finish(self) -> (W, D::Output, u64) {
+        (self.inner, self.digest.finish(), self.count)
+    }
+}
+impl<W: Write, D: Digest> Write for DigestWriter<W, D> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let size = self.inner.write(buf)?;
+        self.digest.update(&buf[..size]);
+        self.count += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes `x` to `writer` and returns the digest of the written bytes together with the byte count.
+pub fn write_with_digest<T, W, D>(x: &T, writer: W, digest: D) -> Result<(D::Output, u64)>
+where
+    T: WriteTo,
+    W: Write,
+    D: Digest,
+{
+    let mut writer = DigestWriter::new(writer, digest);
+    track!(x.write_to(&mut writer))?;
+    let (_, digest, count) = writer.finish();
+    Ok((digest, count))
+}
+
+/// Buffers input into 64-byte blocks (both SHA-256 and MD5 use this block size).
+#[derive(Debug, Clone)]
+struct BlockBuffer {
+    block: [u8; 64],
+    len: usize,
+    total: u64,
+}
+impl BlockBuffer {
+    fn new() -> Self {
+        BlockBuffer {
+            block: [0; 64],
+            len: 0,
+            total: 0,
+        }
+    }
+
+    fn update<F: FnMut(&[u8; 64])>(&mut self, mut bytes: &[u8], mut compress: F) {
+        self.total = self.total.wrapping_add(bytes.len() as u64);
+        while !bytes.is_empty() {
+            let n = std::cmp::min(64 - self.len, bytes.len());
+            self.block[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+            if self.len == 64 {
+                compress(&self.block);
+                self.len = 0;
+            }
+        }
+    }
+
+    /// Appends the padding (`0x80`, zeroes and the message length in bits).
+    fn pad<F: FnMut(&[u8; 64])>(&mut self, length_bytes: [u8; 8], mut compress: F) {
+        let total = self.total;
+        self.update(&[0x80], &mut compress);
+        while self.len != 56 {
+            self.update(&[0], &mut compress);
+        }
+        self.update(&length_bytes, &mut compress);
+        self.total = total;
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a_2f98,
+    0x7137_4491,
+    0xb5c0_fbcf,
+    0xe9b5_dba5,
+    0x3956_c25b,
+    0x59f1_11f1,
+    0x923f_82a4,
+    0xab1c_5ed5,
+    0xd807_aa98,
+    0x1283_5b01,
+    0x2431_85be,
+    0x550c_7dc3,
+    0x72be_5d74,
+    0x80de_b1fe,
+    0x9bdc_06a7,
+    0xc19b_f174,
+    0xe49b_69c1,
+    0xefbe_4786,
+    0x0fc1_9dc6,
+    0x240c_a1cc,
+    0x2de9_2c6f,
+    0x4a74_84aa,
+    0x5cb0_a9dc,
+    0x76f9_88da,
+    0x983e_5152,
+    0xa831_c66d,
+    0xb003_27c8,
+    0xbf59_7fc7,
+    0xc6e0_0bf3,
+    0xd5a7_9147,
+    0x06ca_6351,
+    0x1429_2967,
+    0x27b7_0a85,
+    0x2e1b_2138,
+    0x4d2c_6dfc,
+    0x5338_0d13,
+    0x650a_7354,
+    0x766a_0abb,
+    0x81c2_c92e,
+    0x9272_2c85,
+    0xa2bf_e8a1,
+    0xa81a_664b,
+    0xc24b_8b70,
+    0xc76c_51a3,
+    0xd192_e819,
+    0xd699_0624,
+    0xf40e_3585,
+    0x106a_a070,
+    0x19a4_c116,
+    0x1e37_6c08,
+    0x2748_774c,
+    0x34b0_bcb5,
+    0x391c_0cb3,
+    0x4ed8_aa4a,
+    0x5b9c_ca4f,
+    0x682e_6ff3,
+    0x748f_82ee,
+    0x78a5_636f,
+    0x84c8_7814,
+    0x8cc7_0208,
+    0x90be_fffa,
+    0xa450_6ceb,
+    0xbef9_a3f7,
+    0xc671_78f2,
+];
+
+/// SHA-256 (FIPS 180-4).
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: BlockBuffer,
+}
+impl Sha256 {
+    /// Makes a new `Sha256` instance.
+    pub fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09_e667,
+                0xbb67_ae85,
+                0x3c6e_f372,
+                0xa54f_f53a,
+                0x510e_527f,
+                0x9b05_688c,
+                0x1f83_d9ab,
+                0x5be0_cd19,
+            ],
+            buffer: BlockBuffer::new(),
+        }
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, x) in state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *s = s.wrapping_add(*x);
+        }
+    }
+}
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Digest for Sha256 {
+    type Output = [u8; 32];
+
+    fn update(&mut self, bytes: &[u8]) {
+        let state = &mut self.state;
+        self.buffer
+            .update(bytes, |block| Self::compress(state, block));
+    }
+
+    fn finish(mut self) -> Self::Output {
+        let length_bytes = self.buffer.total.wrapping_mul(8).to_be_bytes();
+        let state = &mut self.state;
+        self.buffer
+            .pad(length_bytes, |block| Self::compress(state, block));
+
+        let mut digest = [0; 32];
+        for (chunk, s) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&s.to_be_bytes());
+        }
+        digest
+    }
+}
+
+const MD5_S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+const MD5_K: [u32; 64] = [
+    0xd76a_a478,
+    0xe8c7_b756,
+    0x2420_70db,
+    0xc1bd_ceee,
+    0xf57c_0faf,
+    0x4787_c62a,
+    0xa830_4613,
+    0xfd46_9501,
+    0x6980_98d8,
+    0x8b44_f7af,
+    0xffff_5bb1,
+    0x895c_d7be,
+    0x6b90_1122,
+    0xfd98_7193,
+    0xa679_438e,
+    0x49b4_0821,
+    0xf61e_2562,
+    0xc040_b340,
+    0x265e_5a51,
+    0xe9b6_c7aa,
+    0xd62f_105d,
+    0x0244_1453,
+    0xd8a1_e681,
+    0xe7d3_fbc8,
+    0x21e1_cde6,
+    0xc337_07d6,
+    0xf4d5_0d87,
+    0x455a_14ed,
+    0xa9e3_e905,
+    0xfcef_a3f8,
+    0x676f_02d9,
+    0x8d2a_4c8a,
+    0xfffa_3942,
+    0x8771_f681,
+    0x6d9d_6122,
+    0xfde5_380c,
+    0xa4be_ea44,
+    0x4bde_cfa9,
+    0xf6bb_4b60,
+    0xbebf_bc70,
+    0x289b_7ec6,
+    0xeaa1_27fa,
+    0xd4ef_3085,
+    0x0488_1d05,
+    0xd9d4_d039,
+    0xe6db_99e5,
+    0x1fa2_7cf8,
+    0xc4ac_5665,
+    0xf429_2244,
+    0x432a_ff97,
+    0xab94_23a7,
+    0xfc93_a039,
+    0x655b_59c3,
+    0x8f0c_cc92,
+    0xffef_f47d,
+    0x8584_5dd1,
+    0x6fa8_7e4f,
+    0xfe2c_e6e0,
+    0xa301_4314,
+    0x4e08_11a1,
+    0xf753_7e82,
+    0xbd3a_f235,
+    0x2ad7_d2bb,
+    0xeb86_d391,
+];
+
+/// MD5 (RFC 1321).
+///
+/// MD5 is not collision resistant, so it should only be used for integrity checks
+/// required by existing formats.
+#[derive(Debug, Clone)]
+pub struct Md5 {
+    state: [u32; 4],
+    buffer: BlockBuffer,
+}
+impl Md5 {
+    /// Makes a new `Md5` instance.
+    pub fn new() -> Self {
+        Md5 {
+            state: [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476],
+            buffer: BlockBuffer::new(),
+        }
+    }
+
+    fn compress(state: &mut [u32; 4], block: &[u8; 64]) {
+        let mut m = [0u32; 16];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            m[i] = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = *state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_S[i]));
+        }
+        for (s, x) in state.iter_mut().zip([a, b, c, d].iter()) {
+            *s = s.wrapping_add(*x);
+        }
+    }
+}
+impl Default for Md5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Digest for Md5 {
+    type Output = [u8; 16];
+
+    fn update(&mut self, bytes: &[u8]) {
+        let state = &mut self.state;
+        self.buffer
+            .update(bytes, |block| Self::compress(state, block));
+    }
+
+    fn finish(mut self) -> Self::Output {
+        let length_bytes = self.buffer.total.wrapping_mul(8).to_le_bytes();
+        let state = &mut self.state;
+        self.buffer
+            .pad(length_bytes, |block| Self::compress(state, block));
+
+        let mut digest = [0; 16];
+        for (chunk, s) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&s.to_le_bytes());
+        }
+        digest
+    }
+}
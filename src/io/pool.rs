@@ -0,0 +1,132 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Pool of byte buffers reused across media segments.
+///
+/// Servers that produce many segments per second allocate (and free) large buffers for the sample data
+/// and the serialized segments over and over again. A pool keeps the released buffers and hands them out again,
+/// so their allocations are reused (see `StreamConverter::set_buffer_pool`).
+///
+/// Cloned pools share the same buffers, so a pool can be shared among converters and threads.
+///
+/// # Examples
+///
+/// ```
+/// use mse_fmp4::io::BufferPool;
+///
+/// let pool = BufferPool::new(4);
+/// let mut buf = pool.get(1024);
+/// buf.extend_from_slice(b"foo");
+/// drop(buf); // returned to the pool
+/// assert_eq!(pool.len(), 1);
+///
+/// let buf = pool.get(16);
+/// assert!(buf.is_empty());
+/// assert!(buf.capacity() >= 1024);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+}
+impl BufferPool {
+    /// Default value of the maximum number of idle buffers kept by a pool.
+    pub const DEFAULT_MAX_BUFFERS: usize = 16;
+
+    /// Makes a new pool that keeps at most `max_buffers` idle buffers.
+    ///
+    /// The buffers released while the pool is full are freed.
+    pub fn new(max_buffers: usize) -> Self {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+            max_buffers,
+        }
+    }
+
+    /// Takes an empty buffer that can hold at least `capacity` bytes without reallocation.
+    ///
+    /// The buffer is returned to the pool when it is dropped (unless it is detached by `PooledBuffer::into_inner`).
+    pub fn get(&self, capacity: usize) -> PooledBuffer {
+        PooledBuffer {
+            buf: self.take(capacity),
+            pool: self.clone(),
+        }
+    }
+
+    /// Takes an empty buffer like `get`, but the buffer is not returned to the pool automatically.
+    ///
+    /// It can be returned explicitly by `put`.
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let buf = self.lock().pop();
+        let mut buf = buf.unwrap_or_default();
+        buf.clear();
+        buf.reserve(capacity);
+        buf
+    }
+
+    /// Returns `buf` to the pool.
+    pub fn put(&self, buf: Vec<u8>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+        let mut buffers = self.lock();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        }
+    }
+
+    /// Returns the number of idle buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns `true` if the pool has no idle buffers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        // The buffers are plain bytes, so they remain usable even if a thread panicked while holding the lock
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_BUFFERS)
+    }
+}
+
+/// Buffer taken from a `BufferPool`, which is returned to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: BufferPool,
+}
+impl PooledBuffer {
+    /// Detaches the buffer from the pool.
+    pub fn into_inner(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+}
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf));
+    }
+}
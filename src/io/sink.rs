@@ -0,0 +1,181 @@
+use crate::{ErrorKind, Result};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A trait for destinations of serialized segments.
+///
+/// `StreamConverter::write_to_sink` delivers its output through this trait,
+/// so that callers do not need to wire up file naming or buffering logic by themselves.
+pub trait SegmentSink {
+    /// Called with a serialized initialization segment.
+    ///
+    /// This may be called again if the initialization segment changes.
+    fn on_init(&mut self, bytes: &[u8]) -> Result<()>;
+
+    /// Called with a serialized media segment and its sequence number (i.e., that of the `mfhd` box).
+    fn on_media(&mut self, sequence_number: u32, bytes: &[u8]) -> Result<()>;
+}
+impl<T: SegmentSink + ?Sized> SegmentSink for &mut T {
+    fn on_init(&mut self, bytes: &[u8]) -> Result<()> {
+        track!((**self).on_init(bytes))
+    }
+
+    fn on_media(&mut self, sequence_number: u32, bytes: &[u8]) -> Result<()> {
+        track!((**self).on_media(sequence_number, bytes))
+    }
+}
+impl<T: SegmentSink + ?Sized> SegmentSink for Box<T> {
+    fn on_init(&mut self, bytes: &[u8]) -> Result<()> {
+        track!((**self).on_init(bytes))
+    }
+
+    fn on_media(&mut self, sequence_number: u32, bytes: &[u8]) -> Result<()> {
+        track!((**self).on_media(sequence_number, bytes))
+    }
+}
+
+/// A sink that writes each segment to its own file in a directory.
+///
+/// The initialization segment is written to `init.mp4` and the media segments are written to
+/// `segment_00001.m4s`, `segment_00002.m4s` and so on (numbered by their sequence numbers).
+/// Each file is first written under a temporary name and then renamed,
+/// so that readers (e.g., HTTP servers) never observe partially written segments.
+#[derive(Debug, Clone)]
+pub struct NumberedFileSink {
+    directory: PathBuf,
+    init_file_name: String,
+    media_file_prefix: String,
+}
+impl NumberedFileSink {
+    /// Makes a new `NumberedFileSink` instance that writes files to `directory`.
+    ///
+    /// The directory must exist.
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        NumberedFileSink {
+            directory: directory.as_ref().to_path_buf(),
+            init_file_name: "init.mp4".to_owned(),
+            media_file_prefix: "segment_".to_owned(),
+        }
+    }
+
+    /// Sets the file name of the initialization segment (`"init.mp4"` by default).
+    pub fn set_init_file_name(&mut self, name: &str) {
+        self.init_file_name = name.to_owned();
+    }
+
+    /// Sets the prefix of the file names of the media segments (`"segment_"` by default).
+    pub fn set_media_file_prefix(&mut self, prefix: &str) {
+        self.media_file_prefix = prefix.to_owned();
+    }
+
+    /// Returns the path of the initialization segment.
+    pub fn init_path(&self) -> PathBuf {
+        self.directory.join(&self.init_file_name)
+    }
+
+    /// Returns the path of the media segment that has `sequence_number`.
+    pub fn media_path(&self, sequence_number: u32) -> PathBuf {
+        self.directory.join(format!(
+            "{}{:05}.m4s",
+            self.media_file_prefix, sequence_number
+        ))
+    }
+
+    fn write_file(path: &Path, bytes: &[u8]) -> Result<()> {
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+        track_io!(fs::write(&temporary_path, bytes))?;
+        track_io!(fs::rename(&temporary_path, path))?;
+        Ok(())
+    }
+}
+impl SegmentSink for NumberedFileSink {
+    fn on_init(&mut self, bytes: &[u8]) -> Result<()> {
+        track!(Self::write_file(&self.init_path(), bytes))
+    }
+
+    fn on_media(&mut self, sequence_number: u32, bytes: &[u8]) -> Result<()> {
+        track!(Self::write_file(&self.media_path(sequence_number), bytes))
+    }
+}
+
+/// A sink that keeps the latest initialization segment and (at most) `capacity` latest media segments in memory.
+///
+/// This is useful for serving a live stream (e.g., a sliding window of an HLS playlist)
+/// without touching the file system.
+///
+/// # Examples
+///
+/// ```
+/// use mse_fmp4::io::{RingBufferSink, SegmentSink};
+///
+/// let mut sink = RingBufferSink::new(2).unwrap();
+/// for i in 1..=3 {
+///     sink.on_media(i, &[i as u8]).unwrap();
+/// }
+/// assert_eq!(sink.media_segment(1), None);
+/// assert_eq!(sink.media_segment(3), Some(&[3][..]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RingBufferSink {
+    capacity: usize,
+    initialization_segment: Option<Vec<u8>>,
+    media_segments: VecDeque<(u32, Vec<u8>)>,
+}
+impl RingBufferSink {
+    /// Makes a new `RingBufferSink` instance.
+    ///
+    /// `capacity` must be greater than zero.
+    pub fn new(capacity: usize) -> Result<Self> {
+        track_assert_ne!(capacity, 0, ErrorKind::InvalidInput);
+        Ok(RingBufferSink {
+            capacity,
+            initialization_segment: None,
+            media_segments: VecDeque::with_capacity(capacity),
+        })
+    }
+
+    /// Returns the latest initialization segment.
+    pub fn initialization_segment(&self) -> Option<&[u8]> {
+        self.initialization_segment.as_ref().map(|b| &b[..])
+    }
+
+    /// Returns the media segment that has `sequence_number` if it is still held.
+    pub fn media_segment(&self, sequence_number: u32) -> Option<&[u8]> {
+        self.media_segments
+            .iter()
+            .find(|s| s.0 == sequence_number)
+            .map(|s| &s.1[..])
+    }
+
+    /// Returns the held media segments (and their sequence numbers) from the oldest to the latest.
+    pub fn media_segments(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.media_segments.iter().map(|s| (s.0, &s.1[..]))
+    }
+
+    /// Returns the number of the held media segments.
+    pub fn len(&self) -> usize {
+        self.media_segments.len()
+    }
+
+    /// Returns `true` if no media segments are held.
+    pub fn is_empty(&self) -> bool {
+        self.media_segments.is_empty()
+    }
+}
+impl SegmentSink for RingBufferSink {
+    fn on_init(&mut self, bytes: &[u8]) -> Result<()> {
+        self.initialization_segment = Some(bytes.to_owned());
+        Ok(())
+    }
+
+    fn on_media(&mut self, sequence_number: u32, bytes: &[u8]) -> Result<()> {
+        if self.media_segments.len() == self.capacity {
+            self.media_segments.pop_front();
+        }
+        self.media_segments
+            .push_back((sequence_number, bytes.to_owned()));
+        Ok(())
+    }
+}
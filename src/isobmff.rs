@@ -0,0 +1,604 @@
+//! Low-level ISO BMFF (ISO/IEC 14496-12) reader.
+//!
+//! This module only splits byte sequences into boxes.
+//! The interpretation of the payloads is left to the callers
+//! (e.g., [`InitializationSegment::read_from`](../fmp4/struct.InitializationSegment.html#method.read_from)).
+use crate::fourcc;
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::Read;
+
+/// A box borrowed from a byte sequence.
+#[derive(Debug, Clone)]
+pub struct RawBox<'a> {
+    /// Box type.
+    pub box_type: [u8; 4],
+
+    /// Offset of the box from the start of the byte sequence given to `Boxes::new`.
+    pub offset: usize,
+
+    /// Size of the box header (8 or 16).
+    pub header_size: usize,
+
+    /// Payload of the box.
+    pub payload: &'a [u8],
+}
+impl<'a> RawBox<'a> {
+    /// Returns the size of the box (including the header).
+    pub fn size(&self) -> usize {
+        self.header_size + self.payload.len()
+    }
+
+    /// Returns an iterator over the child boxes contained in the payload.
+    pub fn children(&self) -> Boxes<'a> {
+        Boxes::new(self.payload)
+    }
+
+    /// Interprets the payload of the box as that of a full box.
+    pub fn full_box(&self) -> Result<FullBox<'a>> {
+        let mut payload = self.payload;
+        let header = track_io!(payload.read_u32::<BigEndian>())?;
+        Ok(FullBox {
+            version: (header >> 24) as u8,
+            flags: header & 0x00FF_FFFF,
+            payload,
+        })
+    }
+}
+
+/// Version, flags and remaining payload of a full box.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct FullBox<'a> {
+    pub version: u8,
+    pub flags: u32, // u24
+    pub payload: &'a [u8],
+}
+
+/// An iterator over the boxes contained in a byte sequence.
+#[derive(Debug)]
+pub struct Boxes<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+impl<'a> Boxes<'a> {
+    /// Makes a new `Boxes` instance.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Boxes { bytes, offset: 0 }
+    }
+
+    fn read_box(&mut self) -> Result<RawBox<'a>> {
+        let mut reader = self.bytes;
+        let size = track_io!(reader.read_u32::<BigEndian>())?;
+        let mut box_type = [0; 4];
+        track_io!(reader.read_exact(&mut box_type))?;
+
+        let (size, header_size) = match size {
+            0 => (self.bytes.len() as u64, 8), // extends to the end of the sequence
+            1 => (track_io!(reader.read_u64::<BigEndian>())?, 16),
+            _ => (u64::from(size), 8),
+        };
+        track_assert!(
+            size >= header_size as u64,
+            ErrorKind::InvalidInput,
+            "Too small box: type={:?}, size={}",
+            String::from_utf8_lossy(&box_type),
+            size
+        );
+        track_assert!(
+            size <= self.bytes.len() as u64,
+            ErrorKind::InvalidInput,
+            "Truncated box: type={:?}, size={}, remaining={}",
+            String::from_utf8_lossy(&box_type),
+            size,
+            self.bytes.len()
+        );
+
+        let size = size as usize;
+        let raw = RawBox {
+            box_type,
+            offset: self.offset,
+            header_size,
+            payload: &self.bytes[header_size..size],
+        };
+        self.bytes = &self.bytes[size..];
+        self.offset += size;
+        Ok(raw)
+    }
+}
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Result<RawBox<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            None
+        } else {
+            let result = track!(self.read_box());
+            if result.is_err() {
+                self.bytes = &[];
+            }
+            Some(result)
+        }
+    }
+}
+
+/// Returns the first box of the given type in `bytes` (not recursive).
+pub fn find_box<'a>(bytes: &'a [u8], box_type: [u8; 4]) -> Result<Option<RawBox<'a>>> {
+    for b in Boxes::new(bytes) {
+        let b = track!(b)?;
+        if b.box_type == box_type {
+            return Ok(Some(b));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns all boxes reached by following `path` from the top-level boxes in `bytes`.
+///
+/// Each element of `path` is the box type at the corresponding depth (e.g., `moov/trak/mdia`),
+/// and the offsets of the returned boxes are relative to the start of `bytes`.
+/// Besides plain container boxes, the children of the boxes whose payloads start with fixed fields
+/// (i.e., `meta`, `stsd`, `dref` and the sample entries of the supported codecs) are searched, too.
+///
+/// # Examples
+///
+/// ```
+/// use mse_fmp4::fourcc::{MDIA, MINF, MOOV, STBL, STSD, TRAK};
+/// use mse_fmp4::isobmff::find_boxes;
+///
+/// # fn main() -> mse_fmp4::Result<()> {
+/// let bytes = include_bytes!("../tests/golden/init.mp4");
+/// let stsd_boxes = find_boxes(bytes, &[&MOOV, &TRAK, &MDIA, &MINF, &STBL, &STSD])?;
+/// assert_eq!(stsd_boxes.len(), 2); // A video track and an audio track
+/// for stsd in stsd_boxes {
+///     println!("stsd: offset={}, size={}", stsd.offset, stsd.size());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn find_boxes<'a>(bytes: &'a [u8], path: &[&[u8; 4]]) -> Result<Vec<RawBox<'a>>> {
+    let mut found = Vec::new();
+    track!(collect_boxes(Boxes::new(bytes), path, &mut found))?;
+    Ok(found)
+}
+
+fn collect_boxes<'a>(
+    boxes: Boxes<'a>,
+    path: &[&[u8; 4]],
+    found: &mut Vec<RawBox<'a>>,
+) -> Result<()> {
+    let (box_type, rest) = match path.split_first() {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+    for b in boxes {
+        let b = track!(b)?;
+        if b.box_type != **box_type {
+            continue;
+        }
+        if rest.is_empty() {
+            found.push(b);
+        } else {
+            let children = track!(child_boxes(&b))?;
+            track!(collect_boxes(children, rest, found))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns an iterator over the children of `b`, skipping the fixed fields preceding them.
+///
+/// The offsets of the children are relative to the byte sequence that `b` was read from.
+fn child_boxes<'a>(b: &RawBox<'a>) -> Result<Boxes<'a>> {
+    let skip = match b.box_type {
+        fourcc::META => 4,
+        fourcc::STSD | fourcc::DREF => 8,
+        fourcc::AVC1
+        | fourcc::AVC3
+        | fourcc::HVC1
+        | fourcc::HEV1
+        | fourcc::AV01
+        | fourcc::VP09
+        | fourcc::ENCV => 78,
+        fourcc::MP4A | fourcc::ENCA => 28,
+        fourcc::WVTT => 8,
+        _ => 0,
+    };
+    let mut payload = b.payload;
+    track!(skip_bytes(&mut payload, skip))?;
+    Ok(Boxes {
+        bytes: payload,
+        offset: b.offset + b.header_size + skip,
+    })
+}
+
+/// Resource limits used by [`parse_untrusted`](fn.parse_untrusted.html).
+#[derive(Debug, Clone)]
+pub struct ParseLimits {
+    /// Maximum size of the input in bytes.
+    pub max_input_size: usize,
+
+    /// Maximum nesting depth of boxes (top-level boxes are at depth 1).
+    pub max_depth: usize,
+
+    /// Maximum number of boxes (including nested ones) in the input.
+    pub max_boxes: usize,
+}
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_input_size: 256 * 1024 * 1024,
+            max_depth: 16,
+            max_boxes: 100_000,
+        }
+    }
+}
+
+/// Splits untrusted bytes into top-level boxes while enforcing `limits`.
+///
+/// The payloads of well-known container boxes (e.g., `moov`, `trak` and `moof`) are
+/// checked recursively, so any truncated or oversized box in the tree is reported as
+/// an `ErrorKind::InvalidInput` error instead of being discovered later.
+/// Exceeding one of the limits results in an `ErrorKind::Unsupported` error.
+///
+/// It is recommended to call this function before handing untrusted input to other readers
+/// (e.g., [`InitializationSegment::read_from`](../fmp4/struct.InitializationSegment.html#method.read_from)).
+pub fn parse_untrusted<'a>(bytes: &'a [u8], limits: &ParseLimits) -> Result<Vec<RawBox<'a>>> {
+    track_assert!(
+        bytes.len() <= limits.max_input_size,
+        ErrorKind::Unsupported,
+        "Too large input: size={}, limit={}",
+        bytes.len(),
+        limits.max_input_size
+    );
+    let mut box_count = 0;
+    let mut boxes = Vec::new();
+    for b in Boxes::new(bytes) {
+        let b = track!(b)?;
+        track!(check_box_tree(&b, 1, limits, &mut box_count))?;
+        boxes.push(b);
+    }
+    Ok(boxes)
+}
+
+fn check_box_tree(
+    b: &RawBox,
+    depth: usize,
+    limits: &ParseLimits,
+    box_count: &mut usize,
+) -> Result<()> {
+    track_assert!(
+        depth <= limits.max_depth,
+        ErrorKind::Unsupported,
+        "Too deeply nested box: type={:?}, limit={}",
+        String::from_utf8_lossy(&b.box_type),
+        limits.max_depth
+    );
+    *box_count += 1;
+    track_assert!(
+        *box_count <= limits.max_boxes,
+        ErrorKind::Unsupported,
+        "Too many boxes: limit={}",
+        limits.max_boxes
+    );
+    if is_container(b.box_type) {
+        for child in b.children() {
+            let child = track!(child)?;
+            track!(check_box_tree(&child, depth + 1, limits, box_count))?;
+        }
+    }
+    Ok(())
+}
+
+/// Handling of the payloads of `mdat` boxes by [`BoxReader`](struct.BoxReader.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MdatPayloads {
+    /// The payloads are loaded into memory like those of the other boxes.
+    #[default]
+    Load,
+
+    /// The payloads are skipped, and only their offsets and sizes are reported.
+    Skip,
+}
+
+/// A top-level box read by [`BoxReader`](struct.BoxReader.html).
+#[derive(Debug, Clone)]
+pub struct OwnedBox {
+    /// Box type.
+    pub box_type: [u8; 4],
+
+    /// Offset of the box from the start of the stream.
+    pub offset: u64,
+
+    /// Size of the box header (8 or 16).
+    pub header_size: usize,
+
+    /// Size of the payload in bytes.
+    pub payload_size: u64,
+
+    /// Payload of the box (`None` if it was skipped or passed to a callback).
+    pub payload: Option<Vec<u8>>,
+}
+impl OwnedBox {
+    /// Returns the size of the box (including the header).
+    pub fn size(&self) -> u64 {
+        self.header_size as u64 + self.payload_size
+    }
+
+    /// Returns the box as a `RawBox` (whose `offset` is zero) if the payload has been loaded.
+    pub fn as_raw_box(&self) -> Option<RawBox<'_>> {
+        self.payload.as_ref().map(|payload| RawBox {
+            box_type: self.box_type,
+            offset: 0,
+            header_size: self.header_size,
+            payload,
+        })
+    }
+}
+
+/// Reader of the top-level boxes of a byte stream.
+///
+/// Unlike `Boxes`, the input does not need to be in memory, and the payloads of `mdat` boxes
+/// can be skipped or streamed into a callback (see `set_mdat_payloads` and `read_box_with`),
+/// so that large files can be parsed with a small amount of memory.
+/// The payloads of the other boxes (e.g., `moov` and `moof`) are loaded into memory.
+///
+/// # Examples
+///
+/// ```
+/// use mse_fmp4::isobmff::{BoxReader, MdatPayloads};
+///
+/// # fn main() -> mse_fmp4::Result<()> {
+/// let bytes = [0, 0, 0, 12, b'm', b'd', b'a', b't', 1, 2, 3, 4];
+/// let mut reader = BoxReader::new(&bytes[..]);
+/// reader.set_mdat_payloads(MdatPayloads::Skip);
+///
+/// let mdat = reader.read_box()?.unwrap();
+/// assert_eq!(mdat.payload_size, 4);
+/// assert!(mdat.payload.is_none());
+/// assert!(reader.read_box()?.is_none());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct BoxReader<R> {
+    reader: R,
+    offset: u64,
+    mdat_payloads: MdatPayloads,
+    max_payload_size: u64,
+}
+impl<R: Read> BoxReader<R> {
+    /// Makes a new `BoxReader` instance.
+    pub fn new(reader: R) -> Self {
+        BoxReader {
+            reader,
+            offset: 0,
+            mdat_payloads: MdatPayloads::default(),
+            max_payload_size: 256 * 1024 * 1024,
+        }
+    }
+
+    /// Sets how the payloads of `mdat` boxes are handled by `read_box` (`MdatPayloads::Load` by default).
+    pub fn set_mdat_payloads(&mut self, mdat_payloads: MdatPayloads) {
+        self.mdat_payloads = mdat_payloads;
+    }
+
+    /// Sets the maximum size of the payloads loaded into memory (256 MiB by default).
+    ///
+    /// Reading a larger box results in an `ErrorKind::Unsupported` error.
+    pub fn set_max_payload_size(&mut self, size: u64) {
+        self.max_payload_size = size;
+    }
+
+    /// Returns the offset of the next box from the start of the stream.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Reads the next box.
+    ///
+    /// `Ok(None)` is returned at the end of the stream.
+    pub fn read_box(&mut self) -> Result<Option<OwnedBox>> {
+        track!(self.read_box_inner(None))
+    }
+
+    /// Reads the next box, passing the payload of an `mdat` box to `on_mdat_data` in chunks instead of loading it.
+    ///
+    /// `Ok(None)` is returned at the end of the stream.
+    pub fn read_box_with<F>(&mut self, mut on_mdat_data: F) -> Result<Option<OwnedBox>>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        track!(self.read_box_inner(Some(&mut on_mdat_data)))
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_box_inner(&mut self, on_mdat_data: Option<MdatCallback>) -> Result<Option<OwnedBox>> {
+        let mut header = [0; 8];
+        let n = track!(read_fully(&mut self.reader, &mut header))?;
+        if n == 0 {
+            return Ok(None);
+        }
+        track_assert_eq!(n, 8, ErrorKind::InvalidInput, "Truncated box header");
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let box_type = [header[4], header[5], header[6], header[7]];
+        let (size, header_size) = match size {
+            0 => (None, 8), // extends to the end of the stream
+            1 => (Some(track_io!(self.reader.read_u64::<BigEndian>())?), 16),
+            _ => (Some(u64::from(size)), 8),
+        };
+        if let Some(size) = size {
+            track_assert!(
+                size >= header_size as u64,
+                ErrorKind::InvalidInput,
+                "Too small box: type={:?}, size={}",
+                String::from_utf8_lossy(&box_type),
+                size
+            );
+        }
+        let payload_size = size.map(|size| size - header_size as u64);
+
+        let streams_payload = box_type == fourcc::MDAT
+            && (on_mdat_data.is_some() || self.mdat_payloads == MdatPayloads::Skip);
+        let (payload_size, payload) = if streams_payload {
+            let mut on_mdat_data = on_mdat_data;
+            let mut limited = (&mut self.reader).take(payload_size.unwrap_or(u64::MAX));
+            let mut buf = vec![0; 64 * 1024];
+            let mut read_size = 0;
+            loop {
+                let n = track_io!(limited.read(&mut buf))?;
+                if n == 0 {
+                    break;
+                }
+                if let Some(f) = on_mdat_data.as_mut() {
+                    track!(f(&buf[..n]))?;
+                }
+                read_size += n as u64;
+            }
+            (read_size, None)
+        } else {
+            let max = self.max_payload_size;
+            track_assert!(
+                payload_size.is_none_or(|size| size <= max),
+                ErrorKind::Unsupported,
+                "Too large box: type={:?}, payload_size={:?}",
+                String::from_utf8_lossy(&box_type),
+                payload_size
+            );
+            let mut payload = Vec::new();
+            let limit = payload_size.unwrap_or(max.saturating_add(1));
+            track_io!((&mut self.reader).take(limit).read_to_end(&mut payload))?;
+            track_assert!(
+                payload.len() as u64 <= max,
+                ErrorKind::Unsupported,
+                "Too large box: type={:?}",
+                String::from_utf8_lossy(&box_type)
+            );
+            (payload.len() as u64, Some(payload))
+        };
+        if let Some(expected) = size.map(|size| size - header_size as u64) {
+            track_assert_eq!(
+                payload_size,
+                expected,
+                ErrorKind::InvalidInput,
+                "Truncated box: type={:?}",
+                String::from_utf8_lossy(&box_type)
+            );
+        }
+
+        let b = OwnedBox {
+            box_type,
+            offset: self.offset,
+            header_size,
+            payload_size,
+            payload,
+        };
+        self.offset += b.size();
+        Ok(Some(b))
+    }
+}
+
+type MdatCallback<'a> = &'a mut dyn FnMut(&[u8]) -> Result<()>;
+
+/// Reads bytes until `buf` is filled or the end of the stream is reached, and returns the number of bytes read.
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return track_io!(Err::<usize, _>(e)),
+        }
+    }
+    Ok(n)
+}
+
+/// Returns a compact tree of the boxes in `bytes` for debugging.
+///
+/// Each line shows the type and size of a box, followed by the number of children for
+/// well-known container boxes (the same ones as `parse_untrusted` descends into).
+/// Children are indented by two spaces per level.
+///
+/// Boxes nested more deeply than `ParseLimits::default().max_depth` result in an
+/// `ErrorKind::Unsupported` error.
+pub fn summarize(bytes: &[u8]) -> Result<String> {
+    let mut summary = String::new();
+    let limits = ParseLimits::default();
+    track!(summarize_boxes(bytes, 0, &limits, &mut summary))?;
+    Ok(summary)
+}
+
+fn summarize_boxes(
+    bytes: &[u8],
+    depth: usize,
+    limits: &ParseLimits,
+    summary: &mut String,
+) -> Result<()> {
+    for b in Boxes::new(bytes) {
+        let b = track!(b)?;
+        track_assert!(
+            depth < limits.max_depth,
+            ErrorKind::Unsupported,
+            "Too deeply nested box: type={:?}, limit={}",
+            String::from_utf8_lossy(&b.box_type),
+            limits.max_depth
+        );
+        if is_container(b.box_type) {
+            let children = b.children().count();
+            summary_line(summary, depth, b.box_type, b.size() as u64, Some(children));
+            track!(summarize_boxes(b.payload, depth + 1, limits, summary))?;
+        } else {
+            summary_line(summary, depth, b.box_type, b.size() as u64, None);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn summary_line(
+    summary: &mut String,
+    depth: usize,
+    box_type: [u8; 4],
+    size: u64,
+    children: Option<usize>,
+) {
+    summary.push_str(&format!(
+        "{:indent$}{} size={}",
+        "",
+        String::from_utf8_lossy(&box_type),
+        size,
+        indent = depth * 2
+    ));
+    if let Some(children) = children {
+        summary.push_str(&format!(" children={}", children));
+    }
+    summary.push('\n');
+}
+
+fn is_container(box_type: [u8; 4]) -> bool {
+    matches!(
+        box_type,
+        fourcc::MOOV
+            | fourcc::TRAK
+            | fourcc::EDTS
+            | fourcc::MDIA
+            | fourcc::MINF
+            | fourcc::DINF
+            | fourcc::STBL
+            | fourcc::MVEX
+            | fourcc::MOOF
+            | fourcc::TRAF
+            | fourcc::MFRA
+            | fourcc::UDTA
+    )
+}
+
+pub(crate) fn skip_bytes(reader: &mut &[u8], n: usize) -> Result<()> {
+    track_assert!(n <= reader.len(), ErrorKind::InvalidInput; n, reader.len());
+    *reader = &reader[n..];
+    Ok(())
+}
@@ -101,9 +101,12 @@ macro_rules! optional_box_size {
 }
 macro_rules! boxes_size {
     ($b:expr) => {{
-        let mut size = 0;
+        let mut size: u32 = 0;
         for b in $b.iter() {
-            size += box_size!(b);
+            size = track_assert_some!(
+                size.checked_add(box_size!(b)),
+                crate::ErrorKind::SegmentTooLarge
+            );
         }
         size
     }};
@@ -112,10 +115,17 @@ macro_rules! boxes_size {
 pub use error::{Error, ErrorKind};
 
 pub mod aac;
+pub mod av1;
 pub mod avc;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod fmp4;
+pub mod fourcc;
 pub mod io;
+pub mod isobmff;
 pub mod mpeg2_ts;
+pub mod time;
+pub mod vp9;
 
 mod error;
 
@@ -0,0 +1,196 @@
+use crate::aac::{AacProfile, ChannelConfiguration, SamplingFrequency};
+use crate::avc::AvcDecoderConfigurationRecord;
+use crate::fmp4::{FileTypeBox, SyncSample, VIDEO_TRACK_ID};
+use crate::io::WriteTo;
+use crate::mpeg2_ts::convert::read_streams;
+use crate::mpeg2_ts::segment::{
+    apply_media_segment_options, make_media_segment, make_stream_initialization_segment,
+    SegmentPosition,
+};
+use crate::mpeg2_ts::{AvAlignment, CompositionOffsets, ConvertOptions, SkipToPmt};
+use crate::{ErrorKind, Result};
+use mpeg2ts;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Cache of serialized initialization segments keyed by the codec configurations of the converted streams.
+///
+/// A server converting many TS segments of the same channel can use `InitSegmentCache::convert`
+/// instead of `ts_bytes_to_fmp4`, so that the initialization segment is built and serialized
+/// only when the codec configurations (or the options affecting it) change.
+///
+/// As in `StreamConverter`, the cached segments have no durations (e.g., the `mehd` box is omitted),
+/// since the segments are shared among the conversions.
+/// When the number of the segments exceeds the capacity, the least recently used one is evicted.
+#[derive(Debug)]
+pub struct InitSegmentCache {
+    segments: HashMap<InitSegmentKey, (CachedInitSegment, u64)>,
+    capacity: usize,
+    uses: u64,
+}
+
+impl InitSegmentCache {
+    /// The capacity of the caches made by `new`.
+    pub const DEFAULT_CAPACITY: usize = 16;
+
+    /// Makes a new empty `InitSegmentCache` instance that holds up to `DEFAULT_CAPACITY` segments.
+    pub fn new() -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+
+    /// Makes a new empty `InitSegmentCache` instance that holds up to `capacity` segments.
+    ///
+    /// If `capacity` is zero, nothing is cached.
+    pub fn with_capacity(capacity: usize) -> Self {
+        InitSegmentCache {
+            segments: HashMap::new(),
+            capacity,
+            uses: 0,
+        }
+    }
+
+    /// Converts the given MPEG-2 TS bytes like `ts_bytes_to_fmp4`, reusing a cached initialization segment if possible.
+    ///
+    /// `AvAlignment::EditList` is not supported, because the resulting edit lists vary between conversions.
+    /// For the same reason, `options.composition_offsets` is ignored and the composition offsets of
+    /// the video samples are always shifted as in `CompositionOffsets::Negative`.
+    pub fn convert(&mut self, ts: &[u8], options: &ConvertOptions) -> Result<CachedFmp4Bytes> {
+        track_assert_ne!(
+            options.av_alignment,
+            AvAlignment::EditList,
+            ErrorKind::Unsupported,
+            "The edit lists would vary between conversions"
+        );
+        let options = &ConvertOptions {
+            composition_offsets: CompositionOffsets::Negative,
+            ..options.clone()
+        };
+        let reader = mpeg2ts::ts::TsPacketReader::new(SkipToPmt::new(ts));
+        let (avc_stream, aac_stream) = track!(read_streams(reader, options))?;
+        let key = InitSegmentKey {
+            video: avc_stream.configuration.clone(),
+            audio_profile: aac_stream.adts_header.profile,
+            audio_frequency: aac_stream.adts_header.sampling_frequency,
+            audio_channel_configuration: aac_stream.adts_header.channel_configuration,
+            audio_timescale: aac_stream.timescale,
+            video_handler_name: options.video_handler_name.clone(),
+            audio_handler_name: options.audio_handler_name.clone(),
+            encoder_identification: options.encoder_identification.clone(),
+            file_type: options.file_type.clone(),
+        };
+        self.uses += 1;
+        let initialization_segment = if let Some((segment, last_used)) = self.segments.get_mut(&key)
+        {
+            *last_used = self.uses;
+            segment.clone()
+        } else {
+            let segment = track!(make_stream_initialization_segment(
+                &avc_stream.configuration,
+                avc_stream.width,
+                avc_stream.height,
+                &aac_stream.adts_header,
+                aac_stream.timescale,
+                options
+            ))?;
+            let cached = CachedInitSegment {
+                bytes: track!(segment.to_bytes())?.into(),
+                mime_type: segment.mime_type(),
+                codecs: segment.codecs(),
+            };
+            if self.segments.len() >= self.capacity {
+                let least_recently_used = self
+                    .segments
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(key) = least_recently_used {
+                    self.segments.remove(&key);
+                }
+            }
+            if self.capacity > 0 {
+                self.segments.insert(key, (cached.clone(), self.uses));
+            }
+            cached
+        };
+
+        let mut media_segment = track!(make_media_segment(
+            avc_stream,
+            aac_stream,
+            options,
+            &SegmentPosition::default()
+        ))?;
+        track!(apply_media_segment_options(&mut media_segment, options))?;
+        Ok(CachedFmp4Bytes {
+            initialization_segment: initialization_segment.bytes,
+            media_segment: track!(media_segment.to_bytes())?,
+            mime_type: initialization_segment.mime_type,
+            codecs: initialization_segment.codecs,
+            keyframes: track!(media_segment.sync_samples(VIDEO_TRACK_ID))?,
+        })
+    }
+
+    /// Returns the maximum number of the cached initialization segments.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of the cached initialization segments.
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Returns `true` if no initialization segments are cached.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Removes all of the cached initialization segments.
+    pub fn clear(&mut self) {
+        self.segments.clear();
+    }
+}
+
+impl Default for InitSegmentCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fragmented MP4 segments serialized by `InitSegmentCache::convert`.
+#[derive(Debug, Clone)]
+pub struct CachedFmp4Bytes {
+    /// Serialized initialization segment (shared among the conversions with the same codec configurations).
+    pub initialization_segment: Arc<[u8]>,
+
+    /// Serialized media segment.
+    pub media_segment: Vec<u8>,
+
+    /// MIME type to be passed to `MediaSource.addSourceBuffer()`.
+    pub mime_type: String,
+
+    /// Codec strings of the tracks.
+    pub codecs: Vec<String>,
+
+    /// Sync samples (keyframes) of the video track in the media segment (see `Fmp4Bytes::keyframes`).
+    pub keyframes: Vec<SyncSample>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct InitSegmentKey {
+    video: AvcDecoderConfigurationRecord,
+    audio_profile: AacProfile,
+    audio_frequency: SamplingFrequency,
+    audio_channel_configuration: ChannelConfiguration,
+    audio_timescale: u32,
+    video_handler_name: Option<String>,
+    audio_handler_name: Option<String>,
+    encoder_identification: Option<String>,
+    file_type: Option<FileTypeBox>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedInitSegment {
+    bytes: Arc<[u8]>,
+    mime_type: String,
+    codecs: Vec<String>,
+}
@@ -0,0 +1,444 @@
+use crate::avc::LevelCheck;
+use crate::error::try_cast;
+use crate::fmp4::{
+    InitializationSegment, MediaSegment, MediaSegmentBuilder, MovieExtendsHeaderBox, Sample,
+    SyncSample, TrackExtendsBox, VIDEO_TRACK_ID,
+};
+use crate::io::WriteTo;
+use crate::mpeg2_ts::demux::{
+    append_avc_sample, read_aac_stream, read_avc_aac_stream, read_parameter_sets,
+};
+use crate::mpeg2_ts::segment::{
+    add_audio_roll_group, apply_initialization_segment_options, apply_media_segment_options,
+    av_gaps, drop_leading_samples, make_audio_track, make_audio_traf, make_initialization_segment,
+    make_media_segment, make_video_track, make_video_traf, sample_data_size, AacStream, AvcStream,
+    SegmentPosition, VIDEO_SYNC_SAMPLE_FLAGS,
+};
+use crate::mpeg2_ts::{
+    AvAlignment, CompositionOffsets, ConvertOptions, SegmentationPolicy, SkipToPmt, TrackLayout,
+    VideoSampleFlags,
+};
+use crate::time::{self, MediaTime, Rounding, MPEG2_TS_TIMESCALE};
+use crate::{ErrorKind, Result};
+use mpeg2ts;
+use mpeg2ts::ts::ReadTsPacket;
+use std::time::Duration;
+
+/// Reads TS packets from `reader`, and converts them into fragmented MP4 segments.
+///
+/// `reader` must start at a PAT and the PMT it announces, as `TsPacketReader` rejects the packets of the PIDs
+/// it has not seen in a PMT. Wrap an input that may start mid-stream (e.g., a recording of a live broadcast)
+/// in `SkipToPmt`, as `ts_bytes_to_fmp4` does.
+pub fn to_fmp4<R: ReadTsPacket>(reader: R) -> Result<(InitializationSegment, MediaSegment)> {
+    track!(to_fmp4_with_options(reader, &ConvertOptions::default()))
+}
+
+/// Reads TS packets from `reader`, and converts them into fragmented MP4 segments
+/// in the manner specified by `options`.
+///
+/// An input that may start mid-stream must be read through `SkipToPmt` (see `to_fmp4`).
+pub fn to_fmp4_with_options<R: ReadTsPacket>(
+    reader: R,
+    options: &ConvertOptions,
+) -> Result<(InitializationSegment, MediaSegment)> {
+    let (avc_stream, aac_stream) = track!(read_streams(reader, options))?;
+    let initialization_segment = track!(make_initialization_segment(
+        &avc_stream,
+        &aac_stream,
+        options
+    ))?;
+    let mut media_segment = track!(make_media_segment(
+        avc_stream,
+        aac_stream,
+        options,
+        &SegmentPosition::default()
+    ))?;
+    track!(apply_media_segment_options(&mut media_segment, options))?;
+    Ok((initialization_segment, media_segment))
+}
+
+/// Reads TS packets from `reader`, and converts them into pairs of initialization and media segments
+/// laid out as specified by `ConvertOptions::track_layout`.
+///
+/// A single pair is returned if the layout is `TrackLayout::Multiplexed`,
+/// and a pair for each track (video first) if it is `TrackLayout::Demultiplexed`.
+///
+/// An input that may start mid-stream must be read through `SkipToPmt` (see `to_fmp4`).
+pub fn to_fmp4_tracks<R: ReadTsPacket>(
+    reader: R,
+    options: &ConvertOptions,
+) -> Result<Vec<(InitializationSegment, MediaSegment)>> {
+    let (initialization_segment, media_segment) = track!(to_fmp4_with_options(reader, options))?;
+    match options.track_layout {
+        TrackLayout::Multiplexed => Ok(vec![(initialization_segment, media_segment)]),
+        TrackLayout::Demultiplexed => {
+            let initialization_segments = track!(initialization_segment.into_track_segments())?;
+            let media_segments = track!(media_segment.into_track_segments())?;
+            track_assert_eq!(
+                initialization_segments.len(),
+                media_segments.len(),
+                ErrorKind::Other
+            );
+            Ok(initialization_segments
+                .into_iter()
+                .zip(media_segments)
+                .collect())
+        }
+    }
+}
+
+/// Reads TS packets from `reader`, and converts them into an initialization segment and
+/// media segments of about `target_duration` each.
+///
+/// A long input converted into a single media segment (as by `to_fmp4_with_options`) stalls browsers,
+/// so this function splits the samples before the video samples where `options.segmentation` with
+/// `target_duration` decides to cut (i.e., at the video keyframes by default, as `StreamConverter` does).
+/// The audio track is cut at the first frame presented at or after the first video sample of the next segment
+/// (the empty edits inserted by `AvAlignment::EditList` are taken into account).
+///
+/// The sequence numbers start from 1, and the decode times of each track continue from one segment to the next
+/// (the samples and their decode times are the same as those of the single segment made by `to_fmp4_with_options`).
+///
+/// An input that may start mid-stream must be read through `SkipToPmt` (see `to_fmp4`).
+pub fn to_fmp4_segments<R: ReadTsPacket>(
+    reader: R,
+    target_duration: Duration,
+    options: &ConvertOptions,
+) -> Result<(InitializationSegment, Vec<MediaSegment>)> {
+    let (mut avc_stream, mut aac_stream) = track!(read_streams(reader, options))?;
+    let initialization_segment = track!(make_initialization_segment(
+        &avc_stream,
+        &aac_stream,
+        options
+    ))?;
+
+    // `(first video sample, first audio frame, video decode time, audio decode time)` of each segment
+    let policy = SegmentationPolicy {
+        target_duration,
+        ..options.segmentation.clone()
+    };
+    let (video_gap, audio_gap) = av_gaps(&avc_stream, &aac_stream, options);
+    let audio_clock = aac_stream.clock();
+    let mut cuts = vec![(0, 0, 0, 0)];
+    let mut video_time = 0;
+    let mut segment_start = 0;
+    let mut data_size = 0;
+    let mut audio_frame = 0;
+    for (i, sample) in avc_stream.samples.iter().enumerate() {
+        // The audio frames presented before this video sample belong to the current segment
+        let audio_end = time::rescale(
+            (video_time + video_gap).saturating_sub(audio_gap),
+            MPEG2_TS_TIMESCALE,
+            aac_stream.timescale,
+            Rounding::Up,
+        );
+        while audio_frame < aac_stream.samples.len()
+            && audio_clock.time(audio_frame as u64) < audio_end
+        {
+            data_size += sample_data_size(&aac_stream.samples[audio_frame..][..1]);
+            audio_frame += 1;
+        }
+
+        let duration = MediaTime::from_90khz(video_time - segment_start).to_duration();
+        if i > 0 && policy.should_cut(duration, avc_stream.sync_samples[i], data_size) {
+            cuts.push((
+                i,
+                audio_frame,
+                video_time,
+                audio_clock.time(audio_frame as u64),
+            ));
+            segment_start = video_time;
+            data_size = 0;
+        }
+        data_size += sample_data_size(&avc_stream.samples[i..][..1]);
+        video_time += u64::from(track_assert_some!(sample.duration, ErrorKind::InvalidInput));
+    }
+
+    // The streams are split from the end, so that each byte of the sample data is moved only once
+    let mut media_segments = Vec::with_capacity(cuts.len());
+    for (i, &(video_start, audio_start, video_decode_time, audio_decode_time)) in
+        cuts.iter().enumerate().rev()
+    {
+        let avc_part = avc_stream.split_off(video_start);
+        let aac_part = aac_stream.split_off(audio_start);
+        let position = SegmentPosition {
+            sequence_number: track!(try_cast(i + 1, "Sequence number"))?,
+            video_decode_time,
+            audio_decode_time,
+            starts_with_sync: avc_part.sync_samples.first() == Some(&true),
+        };
+        let mut segment = track!(make_media_segment(avc_part, aac_part, options, &position))?;
+        track!(apply_media_segment_options(&mut segment, options))?;
+        media_segments.push(segment);
+    }
+    media_segments.reverse();
+    Ok((initialization_segment, media_segments))
+}
+
+/// Reads TS packets from `reader`, and converts only the AAC stream of them into fragmented MP4 segments
+/// (e.g., for audio-only HLS renditions and live radio services).
+///
+/// The video PES packets are skipped without being parsed, so the input does not need to have a video stream.
+/// The timescale of the audio track is always the sampling rate (`ConvertOptions::audio_timescale` is ignored),
+/// so every sample lasts exactly 1024 ticks and the `trun` box carries no per-sample durations.
+/// The base media decode time of the fragment is derived from the PTS of the first frame,
+/// so the media segments made from consecutive TS segments can be appended to the same `SourceBuffer`.
+///
+/// The options only related to video are ignored.
+///
+/// An input that may start mid-stream must be read through `SkipToPmt` (see `to_fmp4`).
+pub fn to_fmp4_audio_only<R: ReadTsPacket>(
+    reader: R,
+    options: &ConvertOptions,
+) -> Result<(InitializationSegment, MediaSegment)> {
+    let aac_stream = track!(read_aac_stream(reader, options))?;
+    let duration = track!(aac_stream.duration())?;
+
+    let mut initialization_segment = InitializationSegment::default();
+    initialization_segment.moov_box.mvhd_box.timescale = aac_stream.timescale;
+    initialization_segment.moov_box.mvhd_box.duration = duration;
+    initialization_segment.moov_box.mvex_box.mehd_box = Some(MovieExtendsHeaderBox {
+        fragment_duration: duration,
+    });
+    let track = track!(make_audio_track(&aac_stream, options))?;
+    initialization_segment.moov_box.trak_boxes.push(track);
+    initialization_segment
+        .moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new(false));
+    apply_initialization_segment_options(&mut initialization_segment, options);
+
+    let position = SegmentPosition {
+        audio_decode_time: aac_stream.start_timestamp.map_or(0, |t| {
+            time::rescale(
+                t,
+                MPEG2_TS_TIMESCALE,
+                aac_stream.timescale,
+                Rounding::Nearest,
+            )
+        }),
+        ..SegmentPosition::default()
+    };
+    let mut builder = MediaSegmentBuilder::new(position.sequence_number);
+    let mut traf = make_audio_traf(aac_stream.default_sample_duration(), &position);
+    if options.audio_roll_groups {
+        add_audio_roll_group(&mut traf, aac_stream.samples.len());
+    }
+    let audio = track!(builder.add_track_fragment(traf))?;
+    track!(builder.add_run(audio, None, aac_stream.samples, aac_stream.data))?;
+    let mut media_segment = track!(builder.finish())?;
+    track!(apply_media_segment_options(&mut media_segment, options))?;
+    Ok((initialization_segment, media_segment))
+}
+
+/// Makes a video-only initialization segment and a media segment that has only the given IDR access unit
+/// (e.g., for thumbnails and fast-start previews, or to warm up the decoder of an MSE player).
+///
+/// `access_unit` is an H.264 byte stream (Annex B) that must contain the SPS, PPS and an IDR picture.
+/// The sample lasts `duration` in the 90 kHz timescale of the track.
+/// Of `options`, only those related to the video track (e.g., `parameter_sets`) are taken into account.
+pub fn access_unit_to_fmp4(
+    access_unit: &[u8],
+    duration: Duration,
+    options: &ConvertOptions,
+) -> Result<(InitializationSegment, MediaSegment)> {
+    let (mut configuration, sps_summary) = track_assert_some!(
+        track!(read_parameter_sets(access_unit))?,
+        ErrorKind::InvalidInput,
+        "No SPS or PPS"
+    );
+    let check = || LevelCheck::new(&sps_summary, None, None);
+    if let Some(level_idc) = track!(options.avc_level.level_to_raise(check))? {
+        track!(configuration.raise_level(level_idc))?;
+    }
+    let mut data = Vec::with_capacity(access_unit.len());
+    let (sample_size, is_sync_sample) = track!(append_avc_sample(
+        &mut data,
+        access_unit,
+        &configuration,
+        options
+    ))?;
+    track_assert!(
+        is_sync_sample,
+        ErrorKind::InvalidInput,
+        "Not an IDR access unit"
+    );
+    let duration = track!(try_cast(
+        MediaTime::from_duration(duration, MPEG2_TS_TIMESCALE, Rounding::Nearest).value,
+        "Sample duration"
+    ))?;
+    let mut avc_stream = AvcStream {
+        configuration,
+        width: sps_summary.width(),
+        height: sps_summary.height(),
+        sps_summary,
+        samples: vec![Sample {
+            duration: Some(duration),
+            size: Some(sample_size),
+            flags: None,
+            composition_time_offset: Some(0),
+        }],
+        sync_samples: vec![true],
+        presentation_timestamps: vec![0],
+        data,
+    };
+
+    let mut initialization_segment = InitializationSegment::default();
+    initialization_segment.moov_box.mvhd_box.timescale = MPEG2_TS_TIMESCALE;
+    initialization_segment.moov_box.mvhd_box.duration = duration;
+    initialization_segment.moov_box.mvex_box.mehd_box = Some(MovieExtendsHeaderBox {
+        fragment_duration: duration,
+    });
+    let track = track!(make_video_track(&avc_stream, options))?;
+    initialization_segment.moov_box.trak_boxes.push(track);
+    initialization_segment
+        .moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new(true));
+    apply_initialization_segment_options(&mut initialization_segment, options);
+
+    let per_sample_flags = options.video_sample_flags == VideoSampleFlags::PerSample;
+    if per_sample_flags {
+        avc_stream.set_per_sample_flags();
+    }
+    let mut builder = MediaSegmentBuilder::new(1);
+    let video = track!(builder.add_track_fragment(make_video_traf(&SegmentPosition::default())))?;
+    let first_sample_flags = if per_sample_flags {
+        None
+    } else {
+        Some(VIDEO_SYNC_SAMPLE_FLAGS)
+    };
+    track!(builder.add_run(
+        video,
+        first_sample_flags,
+        avc_stream.samples,
+        avc_stream.data
+    ))?;
+    let mut media_segment = track!(builder.finish())?;
+    track!(apply_media_segment_options(&mut media_segment, options))?;
+    Ok((initialization_segment, media_segment))
+}
+
+/// Reads the video and audio streams from `reader` and adjusts them as specified by `options`.
+pub(crate) fn read_streams<R: ReadTsPacket>(
+    reader: R,
+    options: &ConvertOptions,
+) -> Result<(AvcStream, AacStream)> {
+    let (mut avc_stream, mut aac_stream) = track!(read_avc_aac_stream(reader, options))?;
+    if options.av_alignment == AvAlignment::DropSamples {
+        drop_leading_samples(&mut avc_stream, &mut aac_stream);
+    }
+    aac_stream.assign_sample_durations(0);
+    if options.composition_offsets == CompositionOffsets::Negative {
+        let shift = avc_stream.start_time();
+        for sample in &mut avc_stream.samples {
+            if let Some(offset) = sample.composition_time_offset.as_mut() {
+                *offset -= shift;
+            }
+        }
+    }
+    if let Some(level_idc) = track!(options
+        .avc_level
+        .level_to_raise(|| avc_stream.check_level()))?
+    {
+        track!(avc_stream.raise_level(level_idc))?;
+    }
+    Ok((avc_stream, aac_stream))
+}
+
+/// Fragmented MP4 segments serialized into bytes.
+#[derive(Debug, Clone)]
+pub struct Fmp4Bytes {
+    /// Serialized initialization segment.
+    pub initialization_segment: Vec<u8>,
+
+    /// Serialized media segment.
+    pub media_segment: Vec<u8>,
+
+    /// MIME type to be passed to `MediaSource.addSourceBuffer()`
+    /// (e.g., `video/mp4; codecs="avc1.640029, mp4a.40.2"`).
+    pub mime_type: String,
+
+    /// Codec strings of the tracks (e.g., `["avc1.640029", "mp4a.40.2"]`).
+    pub codecs: Vec<String>,
+
+    /// Sync samples (keyframes) of the video track in the media segment.
+    ///
+    /// The offsets are relative to the beginning of `media_segment` and
+    /// the times are in the 90 kHz timescale of the video track.
+    pub keyframes: Vec<SyncSample>,
+}
+
+/// Converts the given MPEG-2 TS bytes into serialized fragmented MP4 segments.
+///
+/// This function neither depends on files nor standard I/O,
+/// so it can also be used in `wasm32-unknown-unknown` environments (e.g., a browser worker feeding MSE).
+/// The packets preceding the first PAT and PMT are discarded (see `SkipToPmt`).
+pub fn ts_bytes_to_fmp4(ts: &[u8], options: &ConvertOptions) -> Result<Fmp4Bytes> {
+    let reader = mpeg2ts::ts::TsPacketReader::new(SkipToPmt::new(ts));
+    let (initialization_segment, media_segment) = track!(to_fmp4_with_options(reader, options))?;
+
+    Ok(Fmp4Bytes {
+        initialization_segment: track!(initialization_segment.to_bytes())?,
+        media_segment: track!(media_segment.to_bytes())?,
+        mime_type: initialization_segment.mime_type(),
+        codecs: initialization_segment.codecs(),
+        keyframes: track!(media_segment.sync_samples(VIDEO_TRACK_ID))?,
+    })
+}
+
+/// Serialized segment with the metadata needed to hand it to MSE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentBytes {
+    /// Serialized segment.
+    pub data: Vec<u8>,
+
+    /// MIME type to be passed to `MediaSource.addSourceBuffer()`
+    /// (e.g., `video/mp4; codecs="avc1.640029, mp4a.40.2"`).
+    pub mime_type: String,
+
+    /// Duration of the segment (the total duration of the media segments for an initialization segment).
+    pub duration: Duration,
+
+    /// Sequence number of the segment (`0` for an initialization segment).
+    pub sequence_number: u32,
+}
+
+/// Reads TS packets from `reader`, and converts them into serialized fragmented MP4 segments
+/// (an initialization segment and the media segments of about `target_duration` that follow it).
+///
+/// This is a convenience for servers that just hand the segments to MSE.
+/// The segments are the same as those of `to_fmp4_segments`.
+///
+/// An input that may start mid-stream must be read through `SkipToPmt` (see `to_fmp4`).
+pub fn to_fmp4_bytes<R: ReadTsPacket>(
+    reader: R,
+    target_duration: Duration,
+    options: &ConvertOptions,
+) -> Result<(SegmentBytes, Vec<SegmentBytes>)> {
+    let (initialization_segment, media_segments) =
+        track!(to_fmp4_segments(reader, target_duration, options))?;
+    let mime_type = initialization_segment.mime_type();
+
+    let mut total_duration = Duration::default();
+    let mut media_segment_bytes = Vec::with_capacity(media_segments.len());
+    for segment in &media_segments {
+        let duration = track!(segment.size_report(&initialization_segment))?.duration;
+        total_duration += duration;
+        media_segment_bytes.push(SegmentBytes {
+            data: track!(segment.to_bytes())?,
+            mime_type: mime_type.clone(),
+            duration,
+            sequence_number: segment.moof_box.mfhd_box.sequence_number,
+        });
+    }
+    let initialization_segment_bytes = SegmentBytes {
+        data: track!(initialization_segment.to_bytes())?,
+        mime_type,
+        duration: total_duration,
+        sequence_number: 0,
+    };
+    Ok((initialization_segment_bytes, media_segment_bytes))
+}
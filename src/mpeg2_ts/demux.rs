@@ -0,0 +1,846 @@
+use crate::aac::{AdtsFrames, AdtsHeader};
+use crate::avc::{
+    self, AvcDecoderConfigurationRecord, ByteStreamFormatNalUnits, NalUnit, NalUnitType,
+    PpsSummary, SeiMessage, SpsSummary,
+};
+use crate::error::try_cast;
+use crate::fmp4::Sample;
+use crate::mpeg2_ts::segment::{audio_timescale, AacStream, AvcStream};
+use crate::mpeg2_ts::stream::timestamp_diff;
+use crate::mpeg2_ts::{
+    AvcLevelPolicy, ConvertOptions, MissingDts, OnUnsupported, ParameterSetPlacement, SeiFilter,
+    UnsupportedStream,
+};
+use crate::{Error, ErrorKind, Result};
+use mpeg2ts;
+use mpeg2ts::es::{StreamId, StreamType};
+use mpeg2ts::pes::{PesPacket, PesPacketReader, ReadPesPacket};
+use mpeg2ts::time::{ClockReference, Timestamp};
+use mpeg2ts::ts::{Pid, ReadTsPacket, TsPacket, TsPayload};
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Read, Result as IoResult};
+
+/// Extends 33-bit MPEG-2 TS timestamps (which wrap around about every 26.5 hours)
+/// into monotonically increasing 64-bit values.
+///
+/// A timestamp is regarded as having wrapped around when it goes backwards by more than half of the 33-bit range
+/// from the previous one. Smaller backward jumps (e.g., caused by the reordering of B-frames) are preserved,
+/// so any number of wrap-arounds in long-running live sessions is handled.
+#[derive(Debug, Default, Clone)]
+pub struct TimestampExtender {
+    last: Option<u64>,
+    offset: u64,
+}
+
+impl TimestampExtender {
+    pub(crate) const WRAP: u64 = 1 << 33;
+
+    /// Makes a new `TimestampExtender` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the 64-bit extension of `timestamp`.
+    pub fn extend(&mut self, timestamp: Timestamp) -> u64 {
+        self.extend_u64(timestamp.as_u64())
+    }
+
+    pub(crate) fn extend_u64(&mut self, timestamp: u64) -> u64 {
+        let timestamp = timestamp % Self::WRAP;
+        let mut offset = self.offset;
+        if let Some(last) = self.last {
+            if timestamp < last && last - timestamp > Self::WRAP / 2 {
+                offset += Self::WRAP;
+                self.offset = offset;
+                self.last = Some(timestamp);
+            } else if timestamp > last && timestamp - last > Self::WRAP / 2 {
+                // A late (reordered) timestamp from before the last wrap-around.
+                offset = offset.saturating_sub(Self::WRAP);
+            } else {
+                self.last = Some(timestamp);
+            }
+        } else {
+            self.last = Some(timestamp);
+        }
+        offset + timestamp
+    }
+}
+
+/// A set of `TimestampExtender`s, one for each elementary stream.
+///
+/// Each stream is extended independently, so the reordering or gaps of one stream do not
+/// affect the wrap-around detection of the others.
+/// The extender of a newly seen stream starts in the epoch of the last extended timestamp,
+/// so that the extended values of all streams stay comparable across wrap-arounds.
+#[derive(Debug, Default)]
+pub(crate) struct StreamTimestampExtenders {
+    extenders: HashMap<StreamId, TimestampExtender>,
+    last: Option<u64>,
+}
+
+impl StreamTimestampExtenders {
+    pub(crate) fn extend(&mut self, stream_id: StreamId, timestamp: Timestamp) -> u64 {
+        let last = self.last;
+        let extender = self.extenders.entry(stream_id).or_insert_with(|| {
+            let mut extender = TimestampExtender::new();
+            if let Some(last) = last {
+                extender.last = Some(last % TimestampExtender::WRAP);
+                extender.offset = last - last % TimestampExtender::WRAP;
+            }
+            extender
+        });
+        let extended = extender.extend(timestamp);
+        self.last = Some(extended);
+        extended
+    }
+}
+
+/// Returns `true` if `pes` belongs to a video or audio stream (i.e., not to a private or metadata stream).
+pub(crate) fn is_audio_or_video(pes: &PesPacket<Vec<u8>>) -> bool {
+    pes.header.stream_id.is_video() || pes.header.stream_id.is_audio()
+}
+
+pub(crate) fn read_avc_aac_stream<R: ReadTsPacket>(
+    ts_reader: R,
+    options: &ConvertOptions,
+) -> Result<(AvcStream, AacStream)> {
+    let mut avc_stream: Option<AvcStream> = None;
+    let mut aac_stream: Option<AacStream> = None;
+    let mut avc_timestamps = Vec::new();
+    let mut avc_timestamp_offset = 0;
+    let mut has_dts = false;
+    let mut timestamp_extenders = StreamTimestampExtenders::default();
+
+    let mut reader = TypedPesReader::new(ts_reader, options);
+    while let Some((pes, _)) = track!(reader.read_pes_packet())? {
+        if !is_audio_or_video(&pes) {
+            continue;
+        }
+        if pes.header.stream_id.is_video() {
+            let pts = track_assert_some!(pes.header.pts, ErrorKind::InvalidInput);
+            let dts = pes.header.dts.unwrap_or(pts);
+            has_dts |= pes.header.dts.is_some();
+
+            if avc_stream.is_none() {
+                // Skips the leading samples that cannot be decoded
+                // (e.g., the input starts in the middle of a stream)
+                let (configuration, sps_summary) = match track!(read_parameter_sets(&pes.data))? {
+                    None => continue,
+                    Some(x) => x,
+                };
+                avc_stream = Some(AvcStream {
+                    configuration,
+                    width: sps_summary.width(),
+                    height: sps_summary.height(),
+                    sps_summary,
+                    samples: Vec::new(),
+                    sync_samples: Vec::new(),
+                    presentation_timestamps: Vec::new(),
+                    data: Vec::new(),
+                });
+            }
+
+            let i = avc_timestamps.len();
+            let timestamp = timestamp_extenders.extend(pes.header.stream_id, pts);
+            if i == 0 {
+                avc_timestamp_offset = timestamp;
+            }
+            avc_timestamps.push((timestamp as i64 - avc_timestamp_offset as i64, i));
+
+            let avc_stream = avc_stream.as_mut().expect("Never fails");
+            let (sample_size, is_sync_sample) = track!(append_avc_sample(
+                &mut avc_stream.data,
+                &pes.data,
+                &avc_stream.configuration,
+                options
+            ))?;
+            let sample_composition_time_offset = track!(try_cast(
+                timestamp_diff(pts, dts),
+                "Composition time offset"
+            ))?;
+            avc_stream.samples.push(Sample {
+                duration: None, // dummy
+                size: Some(sample_size),
+                flags: None,
+                composition_time_offset: Some(sample_composition_time_offset),
+            });
+            avc_stream.sync_samples.push(is_sync_sample);
+            avc_stream.presentation_timestamps.push(timestamp);
+        } else {
+            track_assert!(pes.header.stream_id.is_audio(), ErrorKind::InvalidInput);
+            if aac_stream.is_none() {
+                let adts_header = track!(AdtsHeader::read_from(&pes.data[..]))?;
+                aac_stream = Some(AacStream {
+                    timescale: audio_timescale(&adts_header, options),
+                    adts_header,
+                    start_timestamp: pes
+                        .header
+                        .pts
+                        .map(|pts| timestamp_extenders.extend(pes.header.stream_id, pts)),
+                    samples: Vec::new(),
+                    data: Vec::new(),
+                });
+            }
+
+            let aac_stream = aac_stream.as_mut().expect("Never fails");
+            track!(append_aac_frames(
+                &mut aac_stream.samples,
+                &mut aac_stream.data,
+                &pes.data
+            ))?;
+        }
+    }
+
+    let mut avc_stream = track_assert_some!(avc_stream, ErrorKind::InvalidInput);
+    let aac_stream = track_assert_some!(aac_stream, ErrorKind::InvalidInput);
+
+    if options.missing_dts == MissingDts::Reconstruct && !has_dts {
+        let offsets = track!(reconstruct_composition_offsets(
+            &avc_stream.presentation_timestamps
+        ))?;
+        for (sample, offset) in avc_stream.samples.iter_mut().zip(offsets) {
+            sample.composition_time_offset = Some(offset);
+        }
+    }
+
+    avc_timestamps.sort();
+    for (&(curr, _), &(next, i)) in avc_timestamps.iter().zip(avc_timestamps.iter().skip(1)) {
+        let duration = next - curr;
+        avc_stream.samples[i].duration = Some(track!(try_cast(duration, "Sample duration"))?);
+    }
+    if !avc_stream.samples.is_empty() {
+        avc_stream.samples[0].duration = Some(cmp::max(0, avc_stream.start_time()) as u32);
+    }
+
+    Ok((avc_stream, aac_stream))
+}
+
+/// Returns the composition offsets of the samples whose PTS values (in decode order) are `timestamps`,
+/// deriving their DTS values as described in `MissingDts::Reconstruct`.
+fn reconstruct_composition_offsets(timestamps: &[u64]) -> Result<Vec<i32>> {
+    let mut sorted = timestamps.to_vec();
+    sorted.sort_unstable();
+    let delay = timestamps
+        .iter()
+        .zip(sorted.iter())
+        .map(|(&pts, &t)| t as i64 - pts as i64)
+        .fold(0, cmp::max);
+    timestamps
+        .iter()
+        .zip(sorted.iter())
+        .map(|(&pts, &t)| {
+            let offset = pts as i64 - (t as i64 - delay);
+            track!(try_cast(offset, "Composition time offset"))
+        })
+        .collect()
+}
+
+/// Reads the AAC stream from `ts_reader` (see `to_fmp4_audio_only`).
+pub(crate) fn read_aac_stream<R: ReadTsPacket>(
+    ts_reader: R,
+    options: &ConvertOptions,
+) -> Result<AacStream> {
+    let mut aac_stream: Option<AacStream> = None;
+    let mut timestamp_extender = TimestampExtender::new();
+
+    let mut reader = TypedPesReader::new(ts_reader, options);
+    while let Some((pes, _)) = track!(reader.read_pes_packet())? {
+        if !pes.header.stream_id.is_audio() {
+            continue;
+        }
+        if aac_stream.is_none() {
+            let adts_header = track!(AdtsHeader::read_from(&pes.data[..]))?;
+            aac_stream = Some(AacStream {
+                timescale: adts_header.sampling_frequency.as_u32(),
+                adts_header,
+                start_timestamp: pes.header.pts.map(|pts| timestamp_extender.extend(pts)),
+                samples: Vec::new(),
+                data: Vec::new(),
+            });
+        }
+
+        let aac_stream = aac_stream.as_mut().expect("Never fails");
+        track!(append_aac_frames(
+            &mut aac_stream.samples,
+            &mut aac_stream.data,
+            &pes.data
+        ))?;
+    }
+    Ok(track_assert_some!(aac_stream, ErrorKind::InvalidInput))
+}
+
+/// Reads the SPS and PPS in `data` (Annex B byte stream), if both exist.
+pub(crate) fn read_parameter_sets(
+    data: &[u8],
+) -> Result<Option<(AvcDecoderConfigurationRecord, SpsSummary)>> {
+    let mut sps = None;
+    let mut pps = None;
+    for nal_unit in track!(ByteStreamFormatNalUnits::new(data))? {
+        let nal_unit_type = track!(NalUnit::read_from(nal_unit))?.nal_unit_type;
+        match nal_unit_type {
+            NalUnitType::SequenceParameterSet => {
+                let summary = track!(SpsSummary::read_from(&nal_unit[1..]))?;
+                sps = Some((nal_unit.to_owned(), summary));
+            }
+            NalUnitType::PictureParameterSet => {
+                let summary = track!(PpsSummary::read_from(&nal_unit[1..]))?;
+                pps = Some((nal_unit.to_owned(), summary));
+            }
+            _ => {}
+        }
+    }
+
+    let ((sps, sps_summary), (pps, pps_summary)) = match (sps, pps) {
+        (Some(sps), Some(pps)) => (sps, pps),
+        _ => return Ok(None),
+    };
+    track!(pps_summary.check_pairing(&sps_summary))?;
+    let configuration = AvcDecoderConfigurationRecord {
+        profile_idc: sps_summary.profile_idc,
+        constraint_set_flag: sps_summary.constraint_set_flag,
+        level_idc: sps_summary.level_idc,
+        sequence_parameter_set: sps,
+        picture_parameter_set: pps,
+    };
+    Ok(Some((configuration, sps_summary)))
+}
+
+/// Appends the NAL units in `pes_data` to `data` in the length-prefixed format.
+///
+/// The SPS and PPS NAL units are removed or inserted according to `options.parameter_sets`,
+/// and the SEI messages are filtered according to `options.sei_filter`.
+///
+/// Returns the size of the appended sample and whether it is a sync (IDR) sample.
+pub(crate) fn append_avc_sample(
+    data: &mut Vec<u8>,
+    pes_data: &[u8],
+    configuration: &AvcDecoderConfigurationRecord,
+    options: &ConvertOptions,
+) -> Result<(u32, bool)> {
+    let placement = options.parameter_sets;
+    let mut nal_units = Vec::new();
+    for nal_unit in track!(ByteStreamFormatNalUnits::new(pes_data))? {
+        let nal_unit_type = track!(NalUnit::read_from(nal_unit))?.nal_unit_type;
+        nal_units.push((nal_unit_type, nal_unit));
+    }
+    let is_parameter_set = |t: NalUnitType| {
+        t == NalUnitType::SequenceParameterSet || t == NalUnitType::PictureParameterSet
+    };
+    let is_sync_sample = nal_units
+        .iter()
+        .any(|&(t, _)| t == NalUnitType::CodedSliceOfAnIdrPicture);
+    let has_sps = nal_units
+        .iter()
+        .any(|&(t, _)| t == NalUnitType::SequenceParameterSet);
+    let has_pps = nal_units
+        .iter()
+        .any(|&(t, _)| t == NalUnitType::PictureParameterSet);
+    let in_band = placement == ParameterSetPlacement::InBand && is_sync_sample;
+    let mut insert_sps = in_band && !has_sps;
+    let mut insert_pps = in_band && !has_pps;
+    // The in-band SPS are raised to the level of the `avcC` box if `avc_level` has raised it
+    let raised_level_idc = match options.avc_level {
+        AvcLevelPolicy::Keep => None,
+        _ => Some(configuration.indicated_level_idc()),
+    };
+
+    let mut output: Vec<Cow<[u8]>> = Vec::with_capacity(nal_units.len() + 2);
+    for (nal_unit_type, nal_unit) in nal_units {
+        if placement == ParameterSetPlacement::OutOfBand && is_parameter_set(nal_unit_type) {
+            continue;
+        }
+        if let SeiFilter::KeepOnly(payload_types) = &options.sei_filter {
+            if nal_unit_type == NalUnitType::SupplementalEnhancementInformation {
+                let messages = track!(SeiMessage::read_all(nal_unit))?;
+                let kept = messages
+                    .iter()
+                    .filter(|m| payload_types.contains(&m.payload_type))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if kept.is_empty() {
+                    continue;
+                }
+                if kept.len() < messages.len() {
+                    output.push(Cow::Owned(SeiMessage::to_nal_unit(&kept)));
+                    continue;
+                }
+            }
+        }
+        // The missing parameter sets follow the access unit delimiter (if any),
+        // and a PPS always follows the SPS
+        if insert_sps && nal_unit_type != NalUnitType::AccessUnitDelimiter {
+            output.push(Cow::Borrowed(&configuration.sequence_parameter_set));
+            insert_sps = false;
+        }
+        if insert_pps && !has_sps && nal_unit_type != NalUnitType::AccessUnitDelimiter {
+            output.push(Cow::Borrowed(&configuration.picture_parameter_set));
+            insert_pps = false;
+        }
+        match raised_level_idc {
+            Some(level_idc) if nal_unit_type == NalUnitType::SequenceParameterSet => {
+                let mut sps = nal_unit.to_owned();
+                if track!(avc::raise_sps_level(&mut sps, level_idc))? {
+                    output.push(Cow::Owned(sps));
+                } else {
+                    output.push(Cow::Borrowed(nal_unit));
+                }
+            }
+            _ => output.push(Cow::Borrowed(nal_unit)),
+        }
+        if insert_pps && nal_unit_type == NalUnitType::SequenceParameterSet {
+            output.push(Cow::Borrowed(&configuration.picture_parameter_set));
+            insert_pps = false;
+        }
+    }
+    let sample_size = track!(append_length_prefixed_nal_units(data, &output))?;
+    Ok((sample_size, is_sync_sample))
+}
+
+/// Appends `nal_units` to `data`, each prefixed by its 4-byte length, and returns the number of the appended bytes.
+///
+/// The whole sample is reserved at once, so `data` is reallocated at most once however many NAL units there are
+/// (a 4K picture may consist of dozens of slices).
+fn append_length_prefixed_nal_units<T: AsRef<[u8]>>(
+    data: &mut Vec<u8>,
+    nal_units: &[T],
+) -> Result<u32> {
+    let mut sample_size = 0u32;
+    for nal_unit in nal_units {
+        let size: u32 = track!(try_cast(nal_unit.as_ref().len(), "NAL unit size"))?;
+        sample_size = track_assert_some!(
+            sample_size.checked_add(4).and_then(|n| n.checked_add(size)),
+            ErrorKind::InvalidInput,
+            "Too large sample"
+        );
+    }
+    data.reserve(sample_size as usize);
+    for nal_unit in nal_units {
+        let nal_unit = nal_unit.as_ref();
+        data.extend_from_slice(&(nal_unit.len() as u32).to_be_bytes());
+        data.extend_from_slice(nal_unit);
+    }
+    Ok(sample_size)
+}
+
+/// Returns the SEI messages of the given payload types in `pes_data` (Annex B byte stream).
+pub(crate) fn extract_sei_messages(
+    pes_data: &[u8],
+    payload_types: &[u32],
+) -> Result<Vec<SeiMessage>> {
+    let mut messages = Vec::new();
+    for nal_unit in track!(ByteStreamFormatNalUnits::new(pes_data))? {
+        let nal_unit_type = track!(NalUnit::read_from(nal_unit))?.nal_unit_type;
+        if nal_unit_type == NalUnitType::SupplementalEnhancementInformation {
+            let sei = track!(SeiMessage::read_all(nal_unit))?;
+            messages.extend(
+                sei.into_iter()
+                    .filter(|m| payload_types.contains(&m.payload_type)),
+            );
+        }
+    }
+    Ok(messages)
+}
+
+/// Appends the raw data blocks of the ADTS frames in `pes_data` to `samples` and `data`.
+///
+/// Returns the number of the appended frames.
+fn append_aac_frames(
+    samples: &mut Vec<Sample>,
+    data: &mut Vec<u8>,
+    pes_data: &[u8],
+) -> Result<usize> {
+    let mut count = 0;
+    for frame in AdtsFrames::new(pes_data) {
+        let frame = track!(frame)?;
+        samples.push(Sample {
+            duration: None,
+            size: Some(frame.payload.len() as u32),
+            flags: None,
+            composition_time_offset: None,
+        });
+        data.extend_from_slice(frame.payload);
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Maximum number of `PesStart`s remembered for the PES packets of a stream that have not been read yet.
+const MAX_PES_STARTS: usize = 64;
+
+/// Start of a PES packet seen by `StreamMapper`.
+#[derive(Debug)]
+struct PesStart {
+    pts: Option<u64>,
+    pcr: Option<ClockReference>,
+}
+
+/// PES packet reader that also resolves the stream types of the packets.
+#[derive(Debug)]
+pub(crate) struct TypedPesReader<R> {
+    inner: PesPacketReader<StreamMapper<R>>,
+    pcr: Option<ClockReference>,
+    on_unsupported: OnUnsupported,
+    unsupported_streams: HashSet<UnsupportedStream>,
+}
+
+impl<R: ReadTsPacket> TypedPesReader<R> {
+    pub(crate) fn new(inner: R, options: &ConvertOptions) -> Self {
+        TypedPesReader {
+            inner: PesPacketReader::new(StreamMapper::new(inner)),
+            pcr: None,
+            on_unsupported: options.on_unsupported.clone(),
+            unsupported_streams: HashSet::new(),
+        }
+    }
+
+    /// Returns the PCR preceding the start of the last PES packet returned by `read_pes_packet`.
+    pub(crate) fn pcr(&self) -> Option<ClockReference> {
+        self.pcr
+    }
+
+    /// Reads the next PES packet, handling the video and audio streams of unsupported codecs
+    /// according to `ConvertOptions::on_unsupported`.
+    pub(crate) fn read_pes_packet(&mut self) -> Result<Option<(PesPacket<Vec<u8>>, StreamType)>> {
+        loop {
+            let (pes, stream_type) = match track!(self.read_typed_pes_packet())? {
+                None => return Ok(None),
+                Some(x) => x,
+            };
+            let supported = match stream_type {
+                StreamType::H264 => pes.header.stream_id.is_video(),
+                StreamType::AdtsAac => pes.header.stream_id.is_audio(),
+                _ => !is_audio_or_video(&pes),
+            };
+            if supported {
+                return Ok(Some((pes, stream_type)));
+            }
+
+            let stream = UnsupportedStream {
+                pid: self.inner.ts_packet_reader().pid(pes.header.stream_id),
+                stream_id: pes.header.stream_id,
+                stream_type,
+            };
+            match self.on_unsupported {
+                OnUnsupported::Skip => {}
+                OnUnsupported::Fail => track_panic!(ErrorKind::Unsupported, "{:?}", stream),
+                OnUnsupported::Callback(ref callback) => {
+                    if self.unsupported_streams.insert(stream) {
+                        callback(&stream);
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_typed_pes_packet(&mut self) -> Result<Option<(PesPacket<Vec<u8>>, StreamType)>> {
+        let pes = match track!(self.inner.read_pes_packet().map_err(Error::from))? {
+            None => return Ok(None),
+            Some(pes) => pes,
+        };
+        self.pcr = self.inner.ts_packet_reader_mut().take_pes_start_pcr(&pes);
+        let stream_type = self
+            .inner
+            .ts_packet_reader()
+            .stream_type_of(pes.header.stream_id);
+        let stream_type = track_assert_some!(
+            stream_type,
+            ErrorKind::InvalidInput,
+            "Unknown stream: {:?}",
+            pes.header.stream_id
+        );
+        Ok(Some((pes, stream_type)))
+    }
+}
+
+/// Reader that discards the TS packets preceding the first PAT and PMT.
+///
+/// `TsPacketReader` fails on the packets of PIDs that have not been announced by a PMT,
+/// so inputs that start in the middle of a stream (e.g., a live stream joined at an arbitrary point)
+/// should be wrapped by this reader. The PAT and the PMT of its first program are kept,
+/// and all packets following that PMT are passed through as they are.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate mpeg2ts;
+/// # extern crate mse_fmp4;
+/// use mpeg2ts::ts::TsPacketReader;
+/// use mse_fmp4::mpeg2_ts::{self, SkipToPmt};
+///
+/// # fn main() {
+/// let file = std::fs::File::open("joined-mid-stream.ts").unwrap();
+/// let reader = TsPacketReader::new(SkipToPmt::new(file));
+/// let (initialization_segment, media_segment) = mpeg2_ts::to_fmp4(reader).unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SkipToPmt<R> {
+    inner: R,
+    pmt_pid: Option<u16>,
+    passing: bool,
+    packet: [u8; TS_PACKET_SIZE],
+    packet_offset: usize,
+    packet_end: usize,
+}
+
+impl<R: Read> SkipToPmt<R> {
+    /// Makes a new `SkipToPmt` instance.
+    pub fn new(inner: R) -> Self {
+        SkipToPmt {
+            inner,
+            pmt_pid: None,
+            passing: false,
+            packet: [0; TS_PACKET_SIZE],
+            packet_offset: 0,
+            packet_end: 0,
+        }
+    }
+
+    /// Returns `true` if the PMT has been found and the packets are passed through.
+    pub fn pmt_found(&self) -> bool {
+        self.passing
+    }
+
+    /// Takes ownership of the reader and returns the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads the next packet into `self.packet` and decides whether it is kept.
+    ///
+    /// Returns `false` at the end of the stream.
+    fn read_packet(&mut self) -> IoResult<bool> {
+        let mut size = 0;
+        while size < TS_PACKET_SIZE {
+            match self.inner.read(&mut self.packet[size..]) {
+                Ok(0) if size == 0 => return Ok(false),
+                Ok(0) => {
+                    // The truncated packet is left to `TsPacketReader`
+                    self.passing = true;
+                    break;
+                }
+                Ok(n) => size += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.packet_offset = 0;
+        self.packet_end = size;
+        if self.passing || self.packet[0] != TS_SYNC_BYTE {
+            // Packets out of sync are also left to `TsPacketReader` (which reports the error)
+            self.passing = true;
+            return Ok(true);
+        }
+
+        let pid = (u16::from(self.packet[1] & 0x1F) << 8) | u16::from(self.packet[2]);
+        let payload_unit_start = self.packet[1] & 0x40 != 0;
+        let keep = match self.pmt_pid {
+            _ if pid == 0 => {
+                if payload_unit_start && self.pmt_pid.is_none() {
+                    self.pmt_pid = pat_first_pmt_pid(&self.packet);
+                }
+                self.pmt_pid.is_some()
+            }
+            Some(pmt_pid) if pid == pmt_pid && payload_unit_start => {
+                self.passing = true;
+                true
+            }
+            _ => false,
+        };
+        if !keep {
+            self.packet_end = 0;
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for SkipToPmt<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            if self.packet_offset < self.packet_end {
+                let n = cmp::min(buf.len(), self.packet_end - self.packet_offset);
+                buf[..n].copy_from_slice(&self.packet[self.packet_offset..][..n]);
+                self.packet_offset += n;
+                return Ok(n);
+            }
+            if self.passing {
+                return self.inner.read(buf);
+            }
+            if !self.read_packet()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+/// Returns the PMT PID of the first program in the PAT section that starts in `packet`.
+fn pat_first_pmt_pid(packet: &[u8; TS_PACKET_SIZE]) -> Option<u16> {
+    let adaptation_field_control = (packet[3] >> 4) & 0b11;
+    let mut offset = 4;
+    if adaptation_field_control & 0b10 != 0 {
+        offset += 1 + usize::from(packet[4]);
+    }
+    let pointer_field = usize::from(*packet.get(offset)?);
+    let section = packet.get(offset + 1 + pointer_field..)?;
+    if section.len() < 8 || section[0] != 0x00 {
+        return None;
+    }
+    let section_length = (usize::from(section[1] & 0x0F) << 8) | usize::from(section[2]);
+    let programs_end = cmp::min(3 + section_length.checked_sub(4)?, section.len());
+    section
+        .get(8..programs_end)?
+        .chunks_exact(4)
+        .find(|p| p[0] != 0 || p[1] != 0) // program number 0 is the network PID
+        .map(|p| (u16::from(p[2] & 0x1F) << 8) | u16::from(p[3]))
+}
+
+/// TS packet reader that keeps track of the mapping between PIDs, stream types and stream identifiers.
+///
+/// The mapping is built from the PMT and the PES headers contained in the packets read through this reader,
+/// so applications can probe a stream for the available elementary streams before configuring the conversion.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate mpeg2ts;
+/// # extern crate mse_fmp4;
+/// use mpeg2ts::ts::{ReadTsPacket, TsPacketReader};
+/// use mse_fmp4::mpeg2_ts::StreamMapper;
+///
+/// # fn main() {
+/// let file = std::fs::File::open("foo.ts").unwrap();
+/// let mut reader = StreamMapper::new(TsPacketReader::new(file));
+/// while !reader.pmt_received() && reader.read_ts_packet().unwrap().is_some() {}
+/// for (pid, stream_type) in reader.streams() {
+///     println!("{:?}: {:?}", pid, stream_type);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct StreamMapper<R> {
+    inner: R,
+    pid_to_stream_type: HashMap<Pid, StreamType>,
+    stream_id_to_pid: HashMap<StreamId, Pid>,
+    pmt_received: bool,
+    last_pcr: Option<ClockReference>,
+    pes_starts: HashMap<StreamId, VecDeque<PesStart>>,
+}
+
+impl<R> StreamMapper<R> {
+    /// Makes a new `StreamMapper` instance.
+    pub fn new(inner: R) -> Self {
+        StreamMapper {
+            inner,
+            pid_to_stream_type: HashMap::new(),
+            stream_id_to_pid: HashMap::new(),
+            pmt_received: false,
+            last_pcr: None,
+            pes_starts: HashMap::new(),
+        }
+    }
+
+    /// Returns `true` if a PMT has been read, otherwise `false`.
+    pub fn pmt_received(&self) -> bool {
+        self.pmt_received
+    }
+
+    /// Returns the known elementary streams (sorted by PID).
+    pub fn streams(&self) -> Vec<(Pid, StreamType)> {
+        let mut streams = self
+            .pid_to_stream_type
+            .iter()
+            .map(|(&pid, &stream_type)| (pid, stream_type))
+            .collect::<Vec<_>>();
+        streams.sort_by_key(|&(pid, _)| pid.as_u16());
+        streams
+    }
+
+    /// Returns the stream type of the elementary stream carried by the packets of `pid`.
+    pub fn stream_type(&self, pid: Pid) -> Option<StreamType> {
+        self.pid_to_stream_type.get(&pid).cloned()
+    }
+
+    /// Returns the PID of the packets that carry the PES packets of `stream_id`.
+    pub fn pid(&self, stream_id: StreamId) -> Option<Pid> {
+        self.stream_id_to_pid.get(&stream_id).cloned()
+    }
+
+    /// Returns the stream type of the PES packets of `stream_id`.
+    pub fn stream_type_of(&self, stream_id: StreamId) -> Option<StreamType> {
+        self.pid(stream_id).and_then(|pid| self.stream_type(pid))
+    }
+
+    /// Returns the last PCR read through this reader.
+    pub fn last_pcr(&self) -> Option<ClockReference> {
+        self.last_pcr
+    }
+
+    /// Takes the PCR that preceded the TS packet starting `pes`.
+    fn take_pes_start_pcr(&mut self, pes: &PesPacket<Vec<u8>>) -> Option<ClockReference> {
+        let queue = self.pes_starts.get_mut(&pes.header.stream_id)?;
+        let pts = pes.header.pts.map(|pts| pts.as_u64());
+        if pts.is_some() {
+            // Entries of the packets dropped by the PES reader are skipped
+            while queue.len() > 1 && queue.front().map(|x| x.pts) != Some(pts) {
+                queue.pop_front();
+            }
+        }
+        queue.pop_front().and_then(|x| x.pcr)
+    }
+
+    /// Returns a reference to the inner reader.
+    pub fn inner_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner reader.
+    pub fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Takes ownership of the mapper and returns the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: ReadTsPacket> ReadTsPacket for StreamMapper<R> {
+    fn read_ts_packet(&mut self) -> mpeg2ts::Result<Option<TsPacket>> {
+        if let Some(packet) = track!(self.inner.read_ts_packet())? {
+            if let Some(pcr) = packet.adaptation_field.as_ref().and_then(|a| a.pcr) {
+                self.last_pcr = Some(pcr);
+            }
+            match packet.payload {
+                Some(TsPayload::Pmt(ref pmt)) => {
+                    self.pmt_received = true;
+                    for es_info in &pmt.table {
+                        self.pid_to_stream_type
+                            .insert(es_info.elementary_pid, es_info.stream_type);
+                    }
+                }
+                Some(TsPayload::Pes(ref pes)) => {
+                    self.stream_id_to_pid
+                        .insert(pes.header.stream_id, packet.header.pid);
+
+                    let queue = self.pes_starts.entry(pes.header.stream_id).or_default();
+                    if queue.len() == MAX_PES_STARTS {
+                        queue.pop_front();
+                    }
+                    queue.push_back(PesStart {
+                        pts: pes.header.pts.map(|pts| pts.as_u64()),
+                        pcr: self.last_pcr,
+                    });
+                }
+                _ => {}
+            }
+            Ok(Some(packet))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! MPEG-2 TS related constituent elements.
+pub use self::cache::{CachedFmp4Bytes, InitSegmentCache};
+pub use self::convert::{
+    access_unit_to_fmp4, to_fmp4, to_fmp4_audio_only, to_fmp4_bytes, to_fmp4_segments,
+    to_fmp4_tracks, to_fmp4_with_options, ts_bytes_to_fmp4, Fmp4Bytes, SegmentBytes,
+};
+pub use self::demux::{SkipToPmt, StreamMapper, TimestampExtender};
+pub use self::options::{
+    AudioBuffering, AvAlignment, AvcLevelPolicy, CompositionOffsets, ConvertOptions, MissingDts,
+    OnUnsupported, ParameterSetPlacement, Profile, SegmentationPolicy, SeiFilter, TrackLayout,
+    UnsupportedStream, VideoSampleFlags,
+};
+pub use self::probe::{probe, ProbeResult, ProgramInfo, StreamInfo};
+pub use self::stream::{
+    DataPacket, DtsTimestampPolicy, PcrTimestampPolicy, PesTimestamps, PtsTimestampPolicy,
+    SampleSeiMessages, SegmentSampleTimestamps, StreamConverter, SubtitleCue, SubtitleDecoder,
+    TimestampPolicy, WallclockTimestampPolicy,
+};
+
+mod cache;
+mod convert;
+mod demux;
+mod options;
+mod probe;
+mod segment;
+mod stream;
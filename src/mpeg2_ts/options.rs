@@ -0,0 +1,525 @@
+use crate::avc::LevelCheck;
+use crate::fmp4::{FileTypeBox, MdatLayout};
+use crate::fourcc;
+use crate::Result;
+use mpeg2ts;
+use mpeg2ts::es::{StreamId, StreamType};
+use mpeg2ts::ts::Pid;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Options for the conversion from MPEG-2 TS to fragmented MP4.
+///
+/// Regardless of the options, the conversion is deterministic:
+/// the same input and options always produce byte-identical segments
+/// (boxes are emitted in a fixed order, creation/modification times are zero and
+/// handler names are taken from the options), so the results can be cached by their content hash.
+/// The output for a TS fixture is locked by the golden-file tests in `tests/mpeg2_ts.rs`.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Handling of AVC streams whose declared level is too low for
+    /// the resolution, frame rate and bitrate of the stream.
+    ///
+    /// `StreamConverter` and `access_unit_to_fmp4` check only the resolution,
+    /// because the frame rate and bitrate are not known when the initialization segment is made.
+    ///
+    /// See also [`LevelCheck`](../avc/struct.LevelCheck.html).
+    pub avc_level: AvcLevelPolicy,
+
+    /// If `Some(duration)`, the video and audio samples are interleaved in a single `mdat` box
+    /// in chunks of at most `duration` (each chunk is described by its own `trun` box).
+    ///
+    /// If `None`, one `mdat` box is emitted for each track.
+    pub interleave_chunk_duration: Option<Duration>,
+
+    /// Arrangement of the sample data of the media segments into `mdat` boxes.
+    ///
+    /// If `None`, one `mdat` box is emitted for each track, or a single one if `interleave_chunk_duration` is set.
+    /// Note that `MdatLayout::PerTrack` undoes the interleaving of the chunks.
+    pub mdat_layout: Option<MdatLayout>,
+
+    /// How the sync and non-sync video samples are flagged in the `trun` boxes.
+    pub video_sample_flags: VideoSampleFlags,
+
+    /// Strategy to align the start times of the video and audio tracks.
+    ///
+    /// `StreamConverter` ignores this option and always places the samples
+    /// according to their timestamps.
+    pub av_alignment: AvAlignment,
+
+    /// Strategy to handle the composition offset of the first video sample
+    /// (i.e., the difference between its PTS and DTS when the stream has B-frames).
+    pub composition_offsets: CompositionOffsets,
+
+    /// Handling of video PES packets that have a PTS but no DTS.
+    ///
+    /// `StreamConverter` uses the PTS as the DTS (or whatever its `TimestampPolicy` returns), and
+    /// fails with `ErrorKind::Unsupported` if this is `MissingDts::Reconstruct`, which needs the whole stream.
+    pub missing_dts: MissingDts,
+
+    /// Placement of the SPS and PPS NAL units in the video samples.
+    pub parameter_sets: ParameterSetPlacement,
+
+    /// SEI messages that are kept in the video samples.
+    pub sei_filter: SeiFilter,
+
+    /// Payload types of the SEI messages that `StreamConverter` copies out of the video samples
+    /// (e.g., `SeiMessage::PIC_TIMING`), so that they can be taken by `StreamConverter::take_sei_messages`.
+    ///
+    /// The messages are copied before `sei_filter` is applied.
+    pub extracted_sei_payload_types: Vec<u32>,
+
+    /// If `true`, `StreamConverter` records the presentation timestamps of the samples packaged into each media segment,
+    /// so that they can be taken by `StreamConverter::take_sample_timestamps`
+    /// (e.g., to align external subtitle or metadata tracks to the segments).
+    pub record_sample_timestamps: bool,
+
+    /// Policy used by `StreamConverter` to decide where media segments are cut.
+    pub segmentation: SegmentationPolicy,
+
+    /// Handling of the video and audio streams whose codecs are not supported
+    /// (i.e., other than H.264 and ADTS AAC).
+    pub on_unsupported: OnUnsupported,
+
+    /// If `true`, `StreamConverter` keeps the PES packets of the elementary streams that are neither video nor audio
+    /// (e.g., KLV metadata, teletext and DVB subtitles), so that they can be taken by
+    /// `StreamConverter::take_data_packets`.
+    ///
+    /// Regardless of this option, such packets are not converted into the segments.
+    pub keep_data_packets: bool,
+
+    /// Maximum drift of the audio track against the PES timestamps allowed by `StreamConverter`.
+    ///
+    /// Audio samples are packaged with exact durations of 1024 samples, so the audio timeline gradually
+    /// drifts from the 90 kHz clock of the video if the actual sampling rate of the source is slightly off.
+    /// If the drift measured at the end of a media segment exceeds this value, the duration of the last
+    /// audio sample in the segment is adjusted (by at most half a frame) to reduce the drift.
+    ///
+    /// If `None`, no adjustment is made (see also `StreamConverter::audio_drift`).
+    pub max_audio_drift: Option<Duration>,
+
+    /// How `StreamConverter` buffers the AAC frames until they are packaged into a media segment.
+    ///
+    /// The other conversion functions ignore this option.
+    pub audio_buffering: AudioBuffering,
+
+    /// Timescale of the audio track (the sampling rate if `None`).
+    ///
+    /// If the duration of an AAC frame (1024 samples) is not an integer in this timescale
+    /// (e.g., 44.1 kHz audio in a 90 kHz timescale), each sample has its own duration
+    /// derived from the exact start times of the frames, so the rounding errors do not accumulate.
+    pub audio_timescale: Option<u32>,
+
+    /// If `true`, a `roll` sample group (`roll_distance = -1`) that covers all of the samples
+    /// is added to each audio track fragment, so that players can start decoding at the fragment boundary
+    /// without priming artifacts.
+    ///
+    /// This is off by default because some players do not expect sample groups in the track fragments.
+    pub audio_roll_groups: bool,
+
+    /// Name of the handler of the video track (`"Video Handler"` if `None`).
+    pub video_handler_name: Option<String>,
+
+    /// Name of the handler of the audio track (`"Sound Handler"` if `None`).
+    pub audio_handler_name: Option<String>,
+
+    /// Encoder identification string (e.g., `"my-packager 1.2.0"`) written to the initialization segment.
+    ///
+    /// See also `MovieBox::set_encoder_identification`.
+    pub encoder_identification: Option<String>,
+
+    /// `ftyp` box of the initialization segments (`FileTypeBox::default()` if `None`).
+    pub file_type: Option<FileTypeBox>,
+
+    /// If `true`, the `tfdt` boxes are always written in version 1 (see `MediaSegment::force_tfdt_version_1`).
+    pub tfdt_version_1: bool,
+
+    /// Whether the tracks are packaged together or separately (see `to_fmp4_tracks`).
+    ///
+    /// The other conversion functions and `StreamConverter` ignore this option and
+    /// always produce multiplexed segments.
+    pub track_layout: TrackLayout,
+}
+
+/// Preset of the compatibility options for a family of players or a delivery format.
+///
+/// Each preset configures the brands, the `tfdt` box version, the edit list policy and the `mdat` layout
+/// (the other options are left as they are).
+///
+/// # Examples
+///
+/// ```
+/// use mse_fmp4::mpeg2_ts::{CompositionOffsets, ConvertOptions, Profile};
+///
+/// let options = ConvertOptions::from(Profile::DashLive);
+/// assert_eq!(options.composition_offsets, CompositionOffsets::Negative);
+/// assert!(options.tfdt_version_1);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Chrome and the other Chromium-based browsers (MSE).
+    ///
+    /// Edit lists are avoided by negative composition offsets (`iso6` brand).
+    Chrome,
+
+    /// Safari (MSE and `ManagedMediaSource`).
+    ///
+    /// The start times are aligned by edit lists, and the samples are stored in a single `mdat` box.
+    Safari,
+
+    /// fMP4 segments of HLS (see the HLS authoring specification for Apple devices).
+    ///
+    /// The start times are aligned by edit lists, and the `tfdt` boxes are written in version 1.
+    HlsFmp4,
+
+    /// Live profile of MPEG-DASH (ISO/IEC 23009-1).
+    ///
+    /// Edit lists are avoided by negative composition offsets,
+    /// and the `tfdt` boxes are written in version 1 (for long-running decode times).
+    DashLive,
+}
+
+impl Profile {
+    /// Applies this preset to `options`.
+    pub fn apply(self, options: &mut ConvertOptions) {
+        let (major_brand, compatible_brands) = match self {
+            Profile::Chrome => (fourcc::ISO6, vec![fourcc::ISO6, fourcc::ISOM, fourcc::MP41]),
+            Profile::Safari | Profile::HlsFmp4 => {
+                (fourcc::ISO5, vec![fourcc::ISO5, fourcc::ISO6, fourcc::MP41])
+            }
+            Profile::DashLive => (fourcc::ISO6, vec![fourcc::ISO6, fourcc::DASH, fourcc::MSDH]),
+        };
+        options.file_type = Some(FileTypeBox {
+            major_brand,
+            minor_version: 0,
+            compatible_brands,
+        });
+        options.tfdt_version_1 = matches!(self, Profile::HlsFmp4 | Profile::DashLive);
+        match self {
+            Profile::Chrome | Profile::DashLive => {
+                options.composition_offsets = CompositionOffsets::Negative;
+                options.av_alignment = AvAlignment::None;
+            }
+            Profile::Safari | Profile::HlsFmp4 => {
+                options.composition_offsets = CompositionOffsets::EditList;
+                options.av_alignment = AvAlignment::EditList;
+            }
+        }
+        options.mdat_layout = Some(match self {
+            Profile::Safari => MdatLayout::Single,
+            _ => MdatLayout::PerTrack,
+        });
+    }
+}
+
+impl From<Profile> for ConvertOptions {
+    fn from(profile: Profile) -> Self {
+        let mut options = ConvertOptions::default();
+        profile.apply(&mut options);
+        options
+    }
+}
+
+/// Flagging of the video samples in the track fragments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoSampleFlags {
+    /// The first sample of each `trun` box is flagged by its `first_sample_flags`
+    /// and the other samples use the non-sync `default_sample_flags` of the `tfhd` box.
+    ///
+    /// This is compact, but IDR pictures in the middle of a fragment are flagged as non-sync samples.
+    #[default]
+    FirstSample,
+
+    /// Every sample has its own flags derived from the NAL units of the sample (i.e., whether it is an IDR picture).
+    ///
+    /// Some validators require this when a fragment contains multiple IDR pictures.
+    PerSample,
+}
+
+/// Packaging of the tracks into segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrackLayout {
+    /// All tracks are packaged into one pair of an initialization segment and a media segment.
+    #[default]
+    Multiplexed,
+
+    /// Each track is packaged into its own pair of an initialization segment and a media segment,
+    /// so that it can be fed to its own `SourceBuffer`.
+    Demultiplexed,
+}
+
+/// Strategy to align the start times of the video and audio tracks.
+///
+/// In TS streams the first video and audio samples often have different presentation timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AvAlignment {
+    /// Both tracks start at time zero regardless of their timestamps.
+    #[default]
+    None,
+
+    /// An empty edit is inserted into the edit list of the track that starts later.
+    EditList,
+
+    /// Leading samples of the track that starts earlier are dropped.
+    ///
+    /// Audio frames that end before the start of the video are dropped.
+    /// Video samples are dropped up to the first sync sample presented at or after the start of the audio
+    /// (if there is no such sample, nothing is dropped).
+    DropSamples,
+}
+
+/// Policy to decide where `StreamConverter` cuts media segments.
+///
+/// The default policy cuts a segment at the first video sync sample after two seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentationPolicy {
+    /// Target duration of the media segments.
+    pub target_duration: Duration,
+
+    /// Segments are cut up to this amount of time before `target_duration` is reached.
+    ///
+    /// This avoids extending a segment by a whole GOP when a sync sample arrives slightly earlier than the target.
+    pub tolerance: Duration,
+
+    /// Minimum duration of the media segments (except the last one).
+    ///
+    /// Only `max_data_size` can make shorter segments.
+    pub min_duration: Duration,
+
+    /// Maximum duration of the media segments.
+    ///
+    /// When the limit is reached, a segment is emitted even if the next video sample is not a sync sample.
+    /// The audio frames that follow the last video sample are limited likewise (e.g., when the video stream stalls).
+    pub max_duration: Option<Duration>,
+
+    /// Maximum size in bytes of the sample data buffered for a media segment.
+    ///
+    /// When the limit is reached, a segment is emitted even if the next video sample is not a sync sample.
+    /// This protects the converter from inputs with very long GOPs or missing keyframes.
+    ///
+    /// This limit and `max_duration` also apply to the samples buffered until the initialization segment
+    /// can be made; exceeding them results in an `ErrorKind::SegmentTooLarge` error.
+    pub max_data_size: Option<usize>,
+
+    /// If `true`, segments are cut only before video sync samples (IDR pictures), except for the above limits.
+    ///
+    /// If `false`, segments are cut exactly at the first video sample after the target duration
+    /// (the following segment does not start with a sync sample, so it cannot be decoded independently).
+    pub align_to_idr: bool,
+}
+
+impl SegmentationPolicy {
+    /// Returns `true` if the segment being built should be cut before the next video sample.
+    ///
+    /// `duration` is the duration of the buffered video samples, `next_is_sync` indicates whether the next sample
+    /// is a sync sample, and `data_size` is the total size of the buffered sample data in bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mse_fmp4::mpeg2_ts::SegmentationPolicy;
+    /// use std::time::Duration;
+    ///
+    /// let policy = SegmentationPolicy {
+    ///     tolerance: Duration::from_millis(100),
+    ///     ..SegmentationPolicy::default()
+    /// };
+    /// assert!(policy.should_cut(Duration::from_millis(1950), true, 0));
+    /// assert!(!policy.should_cut(Duration::from_millis(1950), false, 0));
+    /// assert!(!policy.should_cut(Duration::from_millis(1800), true, 0));
+    /// ```
+    pub fn should_cut(&self, duration: Duration, next_is_sync: bool, data_size: usize) -> bool {
+        if matches!(self.max_data_size, Some(max) if data_size >= max) {
+            return true;
+        }
+        if duration < self.min_duration {
+            return false;
+        }
+        if matches!(self.max_duration, Some(max) if duration >= max) {
+            return true;
+        }
+        (next_is_sync || !self.align_to_idr) && duration + self.tolerance >= self.target_duration
+    }
+}
+
+impl Default for SegmentationPolicy {
+    fn default() -> Self {
+        SegmentationPolicy {
+            target_duration: Duration::from_secs(2),
+            tolerance: Duration::from_secs(0),
+            min_duration: Duration::from_secs(0),
+            max_duration: None,
+            max_data_size: None,
+            align_to_idr: true,
+        }
+    }
+}
+
+/// Strategy to handle the composition offset of the first video sample.
+///
+/// Some players accept only one of these strategies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompositionOffsets {
+    /// The composition offsets are kept as they are (non-negative),
+    /// and the `media_time` of the edit list skips the offset of the first sample.
+    #[default]
+    EditList,
+
+    /// The offset of the first sample is subtracted from all of the composition offsets,
+    /// so that the first sample is presented at time zero without an edit list shift.
+    ///
+    /// The resulting offsets may be negative (they are written in a version 1 `trun` box).
+    Negative,
+}
+
+/// Handling of video streams whose PES packets have no DTS.
+///
+/// A DTS may be omitted only if it equals the PTS, but some encoders omit it on streams with B-frames, too.
+/// Using the PTS as the DTS for such streams yields wrong composition offsets
+/// (every offset becomes zero while the samples are not in presentation order).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingDts {
+    /// The PTS is used as the DTS (as the specification requires).
+    #[default]
+    UsePts,
+
+    /// If none of the video PES packets has a DTS, the DTS values are reconstructed from the PTS values.
+    ///
+    /// The PTS values sorted in ascending order are assigned to the samples in decode order,
+    /// and then shifted back by the largest reordering delay of the stream so that no sample is decoded
+    /// after it is presented (as an encoder does for the GOP structure of the stream).
+    Reconstruct,
+}
+
+/// Handling of the video and audio streams whose codecs are not supported (see `ConvertOptions::on_unsupported`).
+#[derive(Clone, Default)]
+pub enum OnUnsupported {
+    /// The PES packets of the stream are discarded.
+    Skip,
+
+    /// The conversion fails with `ErrorKind::Unsupported`.
+    #[default]
+    Fail,
+
+    /// The callback is called once for each unsupported stream, and then its PES packets are discarded.
+    Callback(Arc<dyn Fn(&UnsupportedStream) + Send + Sync>),
+}
+
+impl fmt::Debug for OnUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OnUnsupported::Skip => write!(f, "Skip"),
+            OnUnsupported::Fail => write!(f, "Fail"),
+            OnUnsupported::Callback(_) => write!(f, "Callback(_)"),
+        }
+    }
+}
+
+/// Handling of AVC streams whose declared level is too low (see `ConvertOptions::avc_level`).
+#[derive(Clone, Default)]
+pub enum AvcLevelPolicy {
+    /// The declared level is kept as it is (the level is not checked).
+    #[default]
+    Keep,
+
+    /// The declared level is raised to the required one in the `avcC` box and in the in-band SPS NAL units.
+    Raise,
+
+    /// The callback is called with the result of the check if the declared level is too low,
+    /// and the level is raised (as `Raise` does) if it returns `true`.
+    Callback(Arc<dyn Fn(&LevelCheck) -> bool + Send + Sync>),
+}
+
+impl AvcLevelPolicy {
+    /// Checks the level by `check` as specified by this policy, and returns the level to raise the stream to (if any).
+    pub(crate) fn level_to_raise<F>(&self, check: F) -> Result<Option<u8>>
+    where
+        F: FnOnce() -> Result<LevelCheck>,
+    {
+        if let AvcLevelPolicy::Keep = self {
+            return Ok(None);
+        }
+        let level = track!(check())?;
+        if level.is_sufficient() {
+            return Ok(None);
+        }
+        let raise = match self {
+            AvcLevelPolicy::Callback(callback) => callback(&level),
+            _ => true,
+        };
+        Ok(Some(level.required_level_idc).filter(|_| raise))
+    }
+}
+
+impl fmt::Debug for AvcLevelPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AvcLevelPolicy::Keep => write!(f, "Keep"),
+            AvcLevelPolicy::Raise => write!(f, "Raise"),
+            AvcLevelPolicy::Callback(_) => write!(f, "Callback(_)"),
+        }
+    }
+}
+
+/// Video or audio stream skipped because its codec is not supported (see `OnUnsupported::Callback`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnsupportedStream {
+    /// PID of the stream (`None` if it is not known yet).
+    pub pid: Option<Pid>,
+
+    /// Stream identifier in the PES headers.
+    pub stream_id: StreamId,
+
+    /// Stream type declared in the PMT.
+    pub stream_type: StreamType,
+}
+
+/// Buffering of the AAC frames in `StreamConverter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioBuffering {
+    /// The raw data blocks of the frames are copied out of the PES packets as soon as they are read.
+    #[default]
+    Copy,
+
+    /// The PES payloads are kept as they are, and the raw data blocks are copied
+    /// only once, into the `mdat` data of the media segment.
+    ///
+    /// This saves a copy of every frame at the cost of holding the ADTS headers until
+    /// the frames are packaged.
+    PesPayloads,
+}
+
+/// Filtering of the SEI (supplemental enhancement information) messages in the video samples.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SeiFilter {
+    /// All SEI messages are kept as they are.
+    #[default]
+    KeepAll,
+
+    /// Only the SEI messages of the listed payload types (e.g., `SeiMessage::MASTERING_DISPLAY_COLOUR_VOLUME`)
+    /// are kept, and SEI NAL units left without messages are removed.
+    ///
+    /// An empty list removes all SEI messages.
+    KeepOnly(Vec<u32>),
+}
+
+/// Placement of the SPS (sequence parameter set) and PPS (picture parameter set) NAL units.
+///
+/// The parameter sets are always stored in the `avcC` box of the sample entry.
+/// This enum specifies whether they are also stored in the samples (i.e., in the `mdat` boxes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParameterSetPlacement {
+    /// The parameter sets contained in the PES packets are kept as they are.
+    #[default]
+    AsIs,
+
+    /// The parameter sets are also stored in every sync sample (needed by some TVs).
+    ///
+    /// Those of the `avcC` box are inserted into the sync samples that lack them
+    /// (only the missing one is inserted if a sample has either the SPS or the PPS).
+    InBand,
+
+    /// The parameter sets are stored only in the `avcC` box (they are removed from the samples).
+    OutOfBand,
+}
@@ -0,0 +1,215 @@
+use crate::aac::AdtsHeader;
+use crate::mpeg2_ts::demux::read_parameter_sets;
+use crate::mpeg2_ts::{StreamMapper, TimestampExtender};
+use crate::{Error, Result};
+use mpeg2ts;
+use mpeg2ts::es::StreamType;
+use mpeg2ts::pes::{PesPacketReader, ReadPesPacket};
+use mpeg2ts::time::ClockReference;
+use mpeg2ts::ts::{Pid, ReadTsPacket, TsPacket, TsPayload};
+use std::collections::HashMap;
+
+/// Result of `probe`.
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    /// Programs listed in the PAT.
+    pub programs: Vec<ProgramInfo>,
+
+    /// Elementary streams described by the PMTs (sorted by PID).
+    pub streams: Vec<StreamInfo>,
+
+    /// Number of the TS packets that have been read.
+    pub packets: u64,
+
+    /// Estimated bitrate (bits per second) of the whole transport stream.
+    ///
+    /// This is calculated from the PCRs, and is `None` if fewer than two PCRs were found.
+    pub bitrate: Option<u64>,
+}
+
+/// Program listed in a PAT.
+#[derive(Debug, Clone)]
+pub struct ProgramInfo {
+    /// Program number.
+    pub program_num: u16,
+
+    /// PID of the PMT of the program.
+    pub pmt_pid: Pid,
+}
+
+/// Elementary stream described by a PMT.
+#[derive(Debug, Clone)]
+pub struct StreamInfo {
+    /// Number of the program to which the stream belongs.
+    pub program_num: u16,
+
+    /// PID of the packets that carry the stream.
+    pub pid: Pid,
+
+    /// Stream type.
+    pub stream_type: StreamType,
+
+    /// Codec string (e.g., `avc1.640029` or `mp4a.40.2`).
+    ///
+    /// This is `None` if the stream is not supported or no decodable PES packet has been found.
+    pub codec: Option<String>,
+
+    /// Resolution (width and height in pixels) of the video stream.
+    pub resolution: Option<(usize, usize)>,
+
+    /// Header of the first ADTS frame of the audio stream.
+    pub audio: Option<AdtsHeader>,
+}
+
+/// Reads at most `max_packets` TS packets from `reader`, and describes the contained programs and streams.
+///
+/// This is much cheaper than a full conversion and can be used, for example, to let users select streams
+/// or to validate inputs in advance.
+///
+/// An input that may start mid-stream must be read through `SkipToPmt` (see `to_fmp4`).
+pub fn probe<R: ReadTsPacket>(reader: R, max_packets: u64) -> Result<ProbeResult> {
+    let mut reader = PesPacketReader::new(StreamMapper::new(ProbeReader::new(reader, max_packets)));
+    let mut resolutions = HashMap::new();
+    let mut codecs = HashMap::new();
+    let mut audios = HashMap::new();
+    while let Some(pes) = track!(reader.read_pes_packet().map_err(Error::from))? {
+        let mapper = reader.ts_packet_reader();
+        let (pid, stream_type) = match mapper
+            .pid(pes.header.stream_id)
+            .and_then(|pid| mapper.stream_type(pid).map(|t| (pid, t)))
+        {
+            None => continue,
+            Some(x) => x,
+        };
+        if codecs.contains_key(&pid) {
+            continue;
+        }
+        match stream_type {
+            StreamType::H264 => {
+                if let Ok(Some((configuration, sps_summary))) = read_parameter_sets(&pes.data) {
+                    resolutions.insert(pid, (sps_summary.width(), sps_summary.height()));
+                    codecs.insert(pid, configuration.codec_string());
+                }
+            }
+            StreamType::AdtsAac => {
+                if let Ok(header) = AdtsHeader::read_from(&pes.data[..]) {
+                    codecs.insert(pid, header.profile.codec_string());
+                    audios.insert(pid, header);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let probe_reader = reader.ts_packet_reader().inner_ref();
+    let mut streams = probe_reader
+        .streams
+        .iter()
+        .map(|&(program_num, pid, stream_type)| StreamInfo {
+            program_num,
+            pid,
+            stream_type,
+            codec: codecs.get(&pid).cloned(),
+            resolution: resolutions.get(&pid).cloned(),
+            audio: audios.get(&pid).cloned(),
+        })
+        .collect::<Vec<_>>();
+    streams.sort_by_key(|s| s.pid.as_u16());
+    Ok(ProbeResult {
+        programs: probe_reader.programs.clone(),
+        streams,
+        packets: probe_reader.packets,
+        bitrate: probe_reader.bitrate(),
+    })
+}
+
+/// TS packet reader used by `probe`.
+#[derive(Debug)]
+struct ProbeReader<R> {
+    inner: R,
+    max_packets: u64,
+    packets: u64,
+    programs: Vec<ProgramInfo>,
+    streams: Vec<(u16, Pid, StreamType)>,
+    pcr_extender: TimestampExtender,
+    first_pcr: Option<(u64, u64)>,
+    last_pcr: Option<(u64, u64)>,
+}
+
+impl<R> ProbeReader<R> {
+    fn new(inner: R, max_packets: u64) -> Self {
+        ProbeReader {
+            inner,
+            max_packets,
+            packets: 0,
+            programs: Vec::new(),
+            streams: Vec::new(),
+            pcr_extender: TimestampExtender::new(),
+            first_pcr: None,
+            last_pcr: None,
+        }
+    }
+
+    fn bitrate(&self) -> Option<u64> {
+        let (first_pcr, first_packet) = self.first_pcr?;
+        let (last_pcr, last_packet) = self.last_pcr?;
+        if last_pcr <= first_pcr {
+            return None;
+        }
+        let bits = u128::from(last_packet - first_packet) * TsPacket::SIZE as u128 * 8;
+        let bitrate =
+            bits * u128::from(ClockReference::RESOLUTION) / u128::from(last_pcr - first_pcr);
+        Some(bitrate as u64)
+    }
+}
+
+impl<R: ReadTsPacket> ReadTsPacket for ProbeReader<R> {
+    fn read_ts_packet(&mut self) -> mpeg2ts::Result<Option<TsPacket>> {
+        if self.packets >= self.max_packets {
+            return Ok(None);
+        }
+        let packet = match track!(self.inner.read_ts_packet())? {
+            None => return Ok(None),
+            Some(packet) => packet,
+        };
+        self.packets += 1;
+        if let Some(pcr) = packet.adaptation_field.as_ref().and_then(|a| a.pcr) {
+            // The 27 MHz PCR wraps around along with its 33-bit 90 kHz base
+            let base = self.pcr_extender.extend_u64(pcr.as_u64() / 300);
+            let pcr = (base * 300 + pcr.as_u64() % 300, self.packets);
+            if self.first_pcr.is_none() {
+                self.first_pcr = Some(pcr);
+            }
+            self.last_pcr = Some(pcr);
+        }
+        match packet.payload {
+            Some(TsPayload::Pat(ref pat)) => {
+                for pa in &pat.table {
+                    if !self
+                        .programs
+                        .iter()
+                        .any(|p| p.program_num == pa.program_num)
+                    {
+                        self.programs.push(ProgramInfo {
+                            program_num: pa.program_num,
+                            pmt_pid: pa.program_map_pid,
+                        });
+                    }
+                }
+            }
+            Some(TsPayload::Pmt(ref pmt)) => {
+                for es_info in &pmt.table {
+                    if !self.streams.iter().any(|s| s.1 == es_info.elementary_pid) {
+                        self.streams.push((
+                            pmt.program_num,
+                            es_info.elementary_pid,
+                            es_info.stream_type,
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(Some(packet))
+    }
+}
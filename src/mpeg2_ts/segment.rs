@@ -0,0 +1,739 @@
+use crate::aac::{self, AdtsHeader};
+use crate::avc::{
+    self, AvcDecoderConfigurationRecord, LevelCheck, NalUnit, NalUnitType, SpsSummary,
+};
+use crate::error::try_cast;
+use crate::fmp4::{
+    AacSampleEntry, AvcConfigurationBox, AvcSampleEntry, HdrBoxes, InitializationSegment,
+    MediaSegment, MediaSegmentBuilder, MovieExtendsHeaderBox, Mpeg4EsDescriptorBox, Sample,
+    SampleEntry, SampleFlags, SampleGroupDescriptionBox, SampleToGroupBox, SampleToGroupEntry,
+    SamplingRateBox, TrackBox, TrackExtendsBox, TrackFragmentBox,
+};
+use crate::fourcc;
+use crate::mpeg2_ts::{AvAlignment, ConvertOptions, VideoSampleFlags};
+use crate::time::{self, MediaTime, Rounding, MPEG2_TS_TIMESCALE};
+use crate::{ErrorKind, Result};
+use std::cmp;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Drops the leading samples of the track that starts earlier (see `AvAlignment::DropSamples`).
+pub(crate) fn drop_leading_samples(avc_stream: &mut AvcStream, aac_stream: &mut AacStream) {
+    let (video_start, audio_start) =
+        match (avc_stream.start_timestamp(), aac_stream.start_timestamp) {
+            (Some(v), Some(a)) => (v, a),
+            _ => return,
+        };
+    if audio_start < video_start {
+        let sampling_rate = aac_stream.adts_header.sampling_frequency.as_u32();
+        let gap = time::rescale(
+            video_start - audio_start,
+            MPEG2_TS_TIMESCALE,
+            sampling_rate,
+            Rounding::Down,
+        );
+        let n = cmp::min(
+            (gap / aac::SAMPLES_IN_FRAME as u64) as usize,
+            aac_stream.samples.len(),
+        );
+        let size = sample_data_size(&aac_stream.samples[..n]);
+        aac_stream.samples.drain(..n);
+        aac_stream.data.drain(..size);
+        let dropped =
+            MediaTime::from_samples(n as u64 * aac::SAMPLES_IN_FRAME as u64, sampling_rate);
+        aac_stream.start_timestamp = Some(audio_start + dropped.to_90khz(Rounding::Down));
+    } else if video_start < audio_start {
+        let first = (0..avc_stream.samples.len()).find(|&i| {
+            avc_stream.sync_samples[i] && avc_stream.presentation_timestamps[i] >= audio_start
+        });
+        if let Some(n) = first {
+            let size = sample_data_size(&avc_stream.samples[..n]);
+            avc_stream.samples.drain(..n);
+            avc_stream.sync_samples.drain(..n);
+            avc_stream.presentation_timestamps.drain(..n);
+            avc_stream.data.drain(..size);
+        }
+    }
+}
+
+pub(crate) fn sample_data_size(samples: &[Sample]) -> usize {
+    samples.iter().map(|s| s.size.unwrap_or(0) as usize).sum()
+}
+
+/// Makes an initialization segment that has no durations (i.e., one independent of the samples).
+pub(crate) fn make_stream_initialization_segment(
+    configuration: &AvcDecoderConfigurationRecord,
+    width: usize,
+    height: usize,
+    adts_header: &AdtsHeader,
+    audio_timescale: u32,
+    options: &ConvertOptions,
+) -> Result<InitializationSegment> {
+    let sps_summary = track!(SpsSummary::read_from(
+        &configuration.sequence_parameter_set[1..]
+    ))?;
+    let avc_stream = AvcStream {
+        configuration: configuration.clone(),
+        sps_summary,
+        width,
+        height,
+        samples: Vec::new(),
+        sync_samples: Vec::new(),
+        presentation_timestamps: Vec::new(),
+        data: Vec::new(),
+    };
+    let aac_stream = AacStream {
+        adts_header: adts_header.clone(),
+        timescale: audio_timescale,
+        start_timestamp: None,
+        samples: Vec::new(),
+        data: Vec::new(),
+    };
+    let options = ConvertOptions {
+        av_alignment: AvAlignment::None,
+        ..options.clone()
+    };
+    let mut segment = track!(make_initialization_segment(
+        &avc_stream,
+        &aac_stream,
+        &options
+    ))?;
+    segment.moov_box.mvex_box.mehd_box = None;
+    Ok(segment)
+}
+
+pub(crate) fn make_initialization_segment(
+    avc_stream: &AvcStream,
+    aac_stream: &AacStream,
+    options: &ConvertOptions,
+) -> Result<InitializationSegment> {
+    let video_duration = track!(avc_stream.duration())?;
+    let audio_duration = track!(aac_stream.duration())?;
+
+    let mut segment = InitializationSegment::default();
+    if video_duration < audio_duration {
+        segment.moov_box.mvhd_box.timescale = aac::SAMPLES_IN_FRAME as u32;
+        segment.moov_box.mvhd_box.duration = audio_duration;
+        segment.moov_box.mvex_box.mehd_box = Some(MovieExtendsHeaderBox {
+            fragment_duration: audio_duration,
+        });
+    } else {
+        segment.moov_box.mvhd_box.timescale = MPEG2_TS_TIMESCALE;
+        segment.moov_box.mvhd_box.duration = video_duration;
+        segment.moov_box.mvex_box.mehd_box = Some(MovieExtendsHeaderBox {
+            fragment_duration: video_duration,
+        });
+    }
+
+    // video track
+    let mut track = track!(make_video_track(avc_stream, options))?;
+    let (video_gap, audio_gap) = av_gaps(avc_stream, aac_stream, options);
+    let movie_timescale = segment.moov_box.mvhd_box.timescale;
+    track.edts_box.elst_box.empty_duration = track!(try_cast(
+        time::rescale(
+            video_gap,
+            MPEG2_TS_TIMESCALE,
+            movie_timescale,
+            Rounding::Down
+        ),
+        "Empty edit duration"
+    ))?;
+    segment.moov_box.trak_boxes.push(track);
+    segment
+        .moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new(true));
+
+    // audio track
+    let mut track = track!(make_audio_track(aac_stream, options))?;
+    track.edts_box.elst_box.empty_duration = track!(try_cast(
+        time::rescale(
+            audio_gap,
+            MPEG2_TS_TIMESCALE,
+            movie_timescale,
+            Rounding::Down
+        ),
+        "Empty edit duration"
+    ))?;
+    segment.moov_box.trak_boxes.push(track);
+    segment
+        .moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new(false));
+
+    apply_initialization_segment_options(&mut segment, options);
+    Ok(segment)
+}
+
+/// Returns the durations of the empty edits of the video and audio tracks in 90 kHz ticks
+/// (non-zero only for `AvAlignment::EditList`).
+pub(crate) fn av_gaps(
+    avc_stream: &AvcStream,
+    aac_stream: &AacStream,
+    options: &ConvertOptions,
+) -> (u64, u64) {
+    if options.av_alignment != AvAlignment::EditList {
+        return (0, 0);
+    }
+    match (avc_stream.start_timestamp(), aac_stream.start_timestamp) {
+        (Some(v), Some(a)) if v > a => (v - a, 0),
+        (Some(v), Some(a)) => (0, a - v),
+        _ => (0, 0),
+    }
+}
+
+/// Makes the `trak` box of the video track (without any empty edits).
+pub(crate) fn make_video_track(
+    avc_stream: &AvcStream,
+    options: &ConvertOptions,
+) -> Result<TrackBox> {
+    let duration = track!(avc_stream.duration())?;
+    let mut track = TrackBox::new(true);
+    track.tkhd_box.width = (avc_stream.width as u32) << 16;
+    track.tkhd_box.height = (avc_stream.height as u32) << 16;
+    track.tkhd_box.duration = duration;
+    track.edts_box.elst_box.media_time = avc_stream.start_time();
+    track.mdia_box.mdhd_box.timescale = MPEG2_TS_TIMESCALE;
+    track.mdia_box.mdhd_box.duration = duration;
+    if let Some(name) = &options.video_handler_name {
+        track!(track.mdia_box.hdlr_box.set_name(name))?;
+    }
+
+    let avc_sample_entry = AvcSampleEntry {
+        width: track!(try_cast(avc_stream.width, "Picture width"))?,
+        height: track!(try_cast(avc_stream.height, "Picture height"))?,
+        avcc_box: AvcConfigurationBox {
+            configuration: avc_stream.configuration.clone(),
+        },
+        hdr_boxes: HdrBoxes::default(),
+    };
+    track
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries
+        .push(SampleEntry::Avc(avc_sample_entry));
+    Ok(track)
+}
+
+/// Makes the `trak` box of the audio track (without any edits).
+pub(crate) fn make_audio_track(
+    aac_stream: &AacStream,
+    options: &ConvertOptions,
+) -> Result<TrackBox> {
+    let duration = track!(aac_stream.duration())?;
+    let mut track = TrackBox::new(false);
+    track.tkhd_box.duration = duration;
+    track.mdia_box.mdhd_box.timescale = aac_stream.timescale;
+    track.mdia_box.mdhd_box.duration = duration;
+    if let Some(name) = &options.audio_handler_name {
+        track!(track.mdia_box.hdlr_box.set_name(name))?;
+    }
+
+    let sampling_rate = aac_stream.adts_header.sampling_frequency.as_u32();
+    let aac_sample_entry = AacSampleEntry {
+        esds_box: Mpeg4EsDescriptorBox {
+            profile: aac_stream.adts_header.profile,
+            frequency: aac_stream.adts_header.sampling_frequency,
+            channel_configuration: aac_stream.adts_header.channel_configuration,
+            es_id: 0,
+            stream_priority: 0,
+        },
+        srat_box: if sampling_rate > 0xFFFF {
+            Some(SamplingRateBox { sampling_rate })
+        } else {
+            None
+        },
+    };
+    track
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries
+        .push(SampleEntry::Aac(aac_sample_entry));
+    Ok(track)
+}
+
+/// Position of a media segment in the converted stream.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SegmentPosition {
+    pub(crate) sequence_number: u32,
+    pub(crate) video_decode_time: u64,
+    pub(crate) audio_decode_time: u64,
+
+    /// If `false`, the first video sample is not flagged as a sync sample.
+    pub(crate) starts_with_sync: bool,
+}
+
+impl Default for SegmentPosition {
+    /// Returns the position of a segment that holds the whole stream.
+    fn default() -> Self {
+        SegmentPosition {
+            sequence_number: 1,
+            video_decode_time: 0,
+            audio_decode_time: 0,
+            starts_with_sync: true,
+        }
+    }
+}
+
+pub(crate) fn make_media_segment(
+    mut avc_stream: AvcStream,
+    aac_stream: AacStream,
+    options: &ConvertOptions,
+    position: &SegmentPosition,
+) -> Result<MediaSegment> {
+    let per_sample_flags = options.video_sample_flags == VideoSampleFlags::PerSample;
+    if per_sample_flags {
+        avc_stream.set_per_sample_flags();
+    }
+    if let Some(chunk_duration) = options.interleave_chunk_duration {
+        return track!(make_interleaved_media_segment(
+            avc_stream,
+            aac_stream,
+            chunk_duration,
+            per_sample_flags,
+            options.audio_roll_groups,
+            position
+        ));
+    }
+
+    // The samples of each track are stored in their own `mdat` box
+    let mut builder = MediaSegmentBuilder::new(position.sequence_number);
+    let video = track!(builder.add_track_fragment(make_video_traf(position)))?;
+    let first_sample_flags = if per_sample_flags || !position.starts_with_sync {
+        None
+    } else {
+        Some(VIDEO_SYNC_SAMPLE_FLAGS)
+    };
+    track!(builder.add_run(
+        video,
+        first_sample_flags,
+        avc_stream.samples,
+        avc_stream.data
+    ))?;
+
+    let mut traf = make_audio_traf(aac_stream.default_sample_duration(), position);
+    if options.audio_roll_groups {
+        add_audio_roll_group(&mut traf, aac_stream.samples.len());
+    }
+    let audio = track!(builder.add_track_fragment(traf))?;
+    builder.start_mdat();
+    track!(builder.add_run(audio, None, aac_stream.samples, aac_stream.data))?;
+    track!(builder.finish())
+}
+
+pub(crate) fn apply_media_segment_options(
+    segment: &mut MediaSegment,
+    options: &ConvertOptions,
+) -> Result<()> {
+    if options.tfdt_version_1 {
+        track!(segment.force_tfdt_version_1())?;
+    }
+    if let Some(layout) = options.mdat_layout {
+        track!(segment.set_mdat_layout(layout))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn apply_initialization_segment_options(
+    segment: &mut InitializationSegment,
+    options: &ConvertOptions,
+) {
+    if let Some(file_type) = &options.file_type {
+        segment.ftyp_box = file_type.clone();
+    }
+    if let Some(identification) = &options.encoder_identification {
+        segment.moov_box.set_encoder_identification(identification);
+    }
+}
+
+/// Makes a media segment that has a single `mdat` box in which
+/// the video and audio samples are interleaved in chunks of (at most) `chunk_duration`.
+fn make_interleaved_media_segment(
+    avc_stream: AvcStream,
+    aac_stream: AacStream,
+    chunk_duration: Duration,
+    per_sample_flags: bool,
+    audio_roll_groups: bool,
+    position: &SegmentPosition,
+) -> Result<MediaSegment> {
+    let video_timescale = MPEG2_TS_TIMESCALE;
+    let audio_timescale = aac_stream.timescale;
+    let audio_default_sample_duration = aac_stream.default_sample_duration();
+    let video_chunks = Chunk::split(&avc_stream.samples, video_timescale, chunk_duration, 0);
+    let audio_chunks = Chunk::split(
+        &aac_stream.samples,
+        audio_timescale,
+        chunk_duration,
+        audio_default_sample_duration.unwrap_or(0),
+    );
+
+    // Orders the chunks by their start times (`(is_video, chunk)`)
+    let mut chunks = Vec::with_capacity(video_chunks.len() + audio_chunks.len());
+    let mut video_chunks = video_chunks.into_iter().peekable();
+    let mut audio_chunks = audio_chunks.into_iter().peekable();
+    loop {
+        let is_video = match (video_chunks.peek(), audio_chunks.peek()) {
+            (None, None) => break,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (Some(v), Some(a)) => {
+                u128::from(v.start_time) * u128::from(audio_timescale)
+                    <= u128::from(a.start_time) * u128::from(video_timescale)
+            }
+        };
+        if is_video {
+            chunks.push((true, video_chunks.next().expect("Never fails")));
+        } else {
+            chunks.push((false, audio_chunks.next().expect("Never fails")));
+        }
+    }
+
+    let mut builder = MediaSegmentBuilder::new(position.sequence_number);
+    let video = track!(builder.add_track_fragment(make_video_traf(position)))?;
+    let mut traf = make_audio_traf(audio_default_sample_duration, position);
+    if audio_roll_groups {
+        add_audio_roll_group(&mut traf, aac_stream.samples.len());
+    }
+    let audio = track!(builder.add_track_fragment(traf))?;
+    let mut is_first_video_chunk = true;
+    for (is_video, chunk) in &chunks {
+        let (traf_index, samples, stream_data) = if *is_video {
+            (video, &avc_stream.samples, &avc_stream.data)
+        } else {
+            (audio, &aac_stream.samples, &aac_stream.data)
+        };
+        let first_sample_flags = if *is_video
+            && is_first_video_chunk
+            && !per_sample_flags
+            && position.starts_with_sync
+        {
+            Some(VIDEO_SYNC_SAMPLE_FLAGS)
+        } else {
+            None
+        };
+        if *is_video {
+            is_first_video_chunk = false;
+        }
+        track!(builder.add_run(
+            traf_index,
+            first_sample_flags,
+            samples[chunk.samples.clone()].to_vec(),
+            stream_data[chunk.data.clone()].to_vec()
+        ))?;
+    }
+    track!(builder.finish())
+}
+
+pub(crate) fn make_video_traf(position: &SegmentPosition) -> TrackFragmentBox {
+    let mut traf = TrackFragmentBox::new(true);
+    traf.tfhd_box.default_sample_flags = Some(VIDEO_NON_SYNC_SAMPLE_FLAGS);
+    traf.tfdt_box.base_media_decode_time = position.video_decode_time;
+    traf
+}
+
+/// Makes an audio `traf` box.
+///
+/// If `default_sample_duration` is `None`, each sample needs to have its own duration.
+pub(crate) fn make_audio_traf(
+    default_sample_duration: Option<u32>,
+    position: &SegmentPosition,
+) -> TrackFragmentBox {
+    let mut traf = TrackFragmentBox::new(false);
+    traf.tfhd_box.default_sample_duration = default_sample_duration;
+    traf.tfdt_box.base_media_decode_time = position.audio_decode_time;
+    traf
+}
+
+/// Returns the timescale of the audio track (see `ConvertOptions::audio_timescale`).
+pub(crate) fn audio_timescale(adts_header: &AdtsHeader, options: &ConvertOptions) -> u32 {
+    options
+        .audio_timescale
+        .unwrap_or_else(|| adts_header.sampling_frequency.as_u32())
+}
+
+/// Exact clock of AAC frames in an arbitrary timescale.
+///
+/// The duration of a frame (`1024 * timescale / sampling_rate`) may not be an integer.
+/// Instead of rounding each duration, which accumulates errors, the start time of each frame is
+/// computed from the frame count as a rational number, so the error never exceeds one tick.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameClock {
+    timescale: u32,
+    sampling_rate: u32,
+}
+
+impl FrameClock {
+    pub(crate) fn new(timescale: u32, adts_header: &AdtsHeader) -> Self {
+        FrameClock {
+            timescale,
+            sampling_rate: adts_header.sampling_frequency.as_u32(),
+        }
+    }
+
+    /// Returns the start time of the `frames`-th frame (i.e., the total duration of the preceding frames).
+    pub(crate) fn time(self, frames: u64) -> u64 {
+        let samples =
+            MediaTime::from_samples(frames * aac::SAMPLES_IN_FRAME as u64, self.sampling_rate);
+        samples.rescale(self.timescale, Rounding::Down).value
+    }
+
+    /// Returns the duration of the `frame`-th frame.
+    pub(crate) fn duration(self, frame: u64) -> u32 {
+        (self.time(frame + 1) - self.time(frame)) as u32
+    }
+
+    /// Returns `true` if all frames have the same (integer) duration.
+    pub(crate) fn is_exact(self) -> bool {
+        self.time(1) * u64::from(self.sampling_rate)
+            == aac::SAMPLES_IN_FRAME as u64 * u64::from(self.timescale)
+    }
+}
+
+/// Adds the `roll` sample group (`roll_distance = -1`) that covers all of the `sample_count` samples
+/// of the AAC track fragment, so that players can start decoding at the fragment boundary without artifacts.
+pub(crate) fn add_audio_roll_group(traf: &mut TrackFragmentBox, sample_count: usize) {
+    if sample_count == 0 {
+        return;
+    }
+    traf.sbgp_boxes.push(SampleToGroupBox {
+        grouping_type: fourcc::ROLL,
+        entries: vec![SampleToGroupEntry {
+            sample_count: sample_count as u32,
+            group_description_index: 0x1_0001, // the first entry of the `sgpd` box in this fragment
+        }],
+    });
+    traf.sgpd_boxes.push(SampleGroupDescriptionBox::roll(-1));
+}
+
+pub(crate) const VIDEO_SYNC_SAMPLE_FLAGS: SampleFlags = SampleFlags {
+    is_leading: 0,
+    sample_depends_on: 2,
+    sample_is_depdended_on: 0,
+    sample_has_redundancy: 0,
+    sample_padding_value: 0,
+    sample_is_non_sync_sample: false,
+    sample_degradation_priority: 0,
+};
+
+const VIDEO_NON_SYNC_SAMPLE_FLAGS: SampleFlags = SampleFlags {
+    is_leading: 0,
+    sample_depends_on: 1,
+    sample_is_depdended_on: 0,
+    sample_has_redundancy: 0,
+    sample_padding_value: 0,
+    sample_is_non_sync_sample: true,
+    sample_degradation_priority: 0,
+};
+
+/// A run of consecutive samples of a track.
+#[derive(Debug)]
+struct Chunk {
+    start_time: u64,
+    samples: Range<usize>,
+    data: Range<usize>,
+}
+
+impl Chunk {
+    /// Splits `samples` into chunks of (at most) `duration`.
+    ///
+    /// `default_sample_duration` is used for samples that do not have their own durations.
+    fn split(
+        samples: &[Sample],
+        timescale: u32,
+        duration: Duration,
+        default_sample_duration: u32,
+    ) -> Vec<Chunk> {
+        let max_duration = MediaTime::from_duration(duration, timescale, Rounding::Down).value;
+
+        let mut chunks = Vec::new();
+        let mut chunk_start = (0, 0, 0); // (time, sample index, data position)
+        let (mut time, mut position) = (0, 0);
+        for (i, sample) in samples.iter().enumerate() {
+            if i != chunk_start.1 && time - chunk_start.0 >= max_duration {
+                chunks.push(Chunk {
+                    start_time: chunk_start.0,
+                    samples: chunk_start.1..i,
+                    data: chunk_start.2..position,
+                });
+                chunk_start = (time, i, position);
+            }
+            time += u64::from(sample.duration.unwrap_or(default_sample_duration));
+            position += sample.size.unwrap_or(0) as usize;
+        }
+        if chunk_start.1 < samples.len() {
+            chunks.push(Chunk {
+                start_time: chunk_start.0,
+                samples: chunk_start.1..samples.len(),
+                data: chunk_start.2..position,
+            });
+        }
+        chunks
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AvcStream {
+    pub(crate) configuration: AvcDecoderConfigurationRecord,
+    pub(crate) sps_summary: SpsSummary,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) samples: Vec<Sample>,
+    pub(crate) sync_samples: Vec<bool>,
+    pub(crate) presentation_timestamps: Vec<u64>,
+    pub(crate) data: Vec<u8>,
+}
+
+impl AvcStream {
+    /// Sets the flags of each sample according to whether it is a sync sample.
+    pub(crate) fn set_per_sample_flags(&mut self) {
+        for (sample, &is_sync) in self.samples.iter_mut().zip(&self.sync_samples) {
+            sample.flags = Some(if is_sync {
+                VIDEO_SYNC_SAMPLE_FLAGS
+            } else {
+                VIDEO_NON_SYNC_SAMPLE_FLAGS
+            });
+        }
+    }
+
+    fn duration(&self) -> Result<u32> {
+        let mut duration: u32 = 0;
+        for sample in &self.samples {
+            let sample_duration = track_assert_some!(sample.duration, ErrorKind::InvalidInput);
+            duration = track_assert_some!(
+                duration.checked_add(sample_duration),
+                ErrorKind::InvalidInput
+            );
+        }
+        Ok(duration)
+    }
+    pub(crate) fn check_level(&self) -> Result<LevelCheck> {
+        // The frame interval is derived from the presentation timestamps rather than the sample durations,
+        // because the first sample may have no duration (see `start_time`)
+        let timestamps = &self.presentation_timestamps;
+        let span = match (timestamps.iter().min(), timestamps.iter().max()) {
+            (Some(min), Some(max)) => max - min,
+            _ => 0,
+        };
+        let (frame_rate, bitrate) = if span == 0 {
+            (None, None)
+        } else {
+            let frame_interval =
+                span as f64 / f64::from(MPEG2_TS_TIMESCALE) / (timestamps.len() - 1) as f64;
+            let seconds = frame_interval * timestamps.len() as f64;
+            let bitrate = (self.data.len() as f64 * 8.0 / seconds) as u64;
+            (Some(1.0 / frame_interval), Some(bitrate))
+        };
+        track!(LevelCheck::new(&self.sps_summary, frame_rate, bitrate))
+    }
+
+    /// Raises the level of the `avcC` box and the in-band SPS NAL units to `level_idc`.
+    pub(crate) fn raise_level(&mut self, level_idc: u8) -> Result<()> {
+        track!(self.configuration.raise_level(level_idc))?;
+        self.sps_summary.constraint_set_flag = self.configuration.constraint_set_flag;
+        self.sps_summary.level_idc = self.configuration.level_idc;
+
+        let mut data = &mut self.data[..];
+        while !data.is_empty() {
+            track_assert!(data.len() >= 4, ErrorKind::InvalidInput);
+            let (size, rest) = data.split_at_mut(4);
+            let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+            track_assert!(size <= rest.len(), ErrorKind::InvalidInput; size, rest.len());
+            let (nal_unit, rest) = rest.split_at_mut(size);
+            let nal_unit_type = track!(NalUnit::read_from(&nal_unit[..]))?.nal_unit_type;
+            if nal_unit_type == NalUnitType::SequenceParameterSet {
+                track!(avc::raise_sps_level(nal_unit, level_idc))?;
+            }
+            data = rest;
+        }
+        Ok(())
+    }
+    fn start_timestamp(&self) -> Option<u64> {
+        self.presentation_timestamps.iter().min().cloned()
+    }
+
+    /// Splits the samples at `at`, and returns the latter part as a new stream.
+    pub(crate) fn split_off(&mut self, at: usize) -> Self {
+        let size = sample_data_size(&self.samples[..at]);
+        AvcStream {
+            configuration: self.configuration.clone(),
+            sps_summary: self.sps_summary.clone(),
+            width: self.width,
+            height: self.height,
+            samples: self.samples.split_off(at),
+            sync_samples: self.sync_samples.split_off(at),
+            presentation_timestamps: self.presentation_timestamps.split_off(at),
+            data: self.data.split_off(size),
+        }
+    }
+    pub(crate) fn start_time(&self) -> i32 {
+        self.samples
+            .first()
+            .and_then(|s| s.composition_time_offset)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AacStream {
+    pub(crate) adts_header: AdtsHeader,
+    pub(crate) timescale: u32,
+    pub(crate) start_timestamp: Option<u64>,
+    pub(crate) samples: Vec<Sample>,
+    pub(crate) data: Vec<u8>,
+}
+
+impl AacStream {
+    /// Splits the frames at `at`, and returns the latter part as a new stream.
+    pub(crate) fn split_off(&mut self, at: usize) -> Self {
+        let size = sample_data_size(&self.samples[..at]);
+        AacStream {
+            adts_header: self.adts_header.clone(),
+            timescale: self.timescale,
+            start_timestamp: None,
+            samples: self.samples.split_off(at),
+            data: self.data.split_off(size),
+        }
+    }
+
+    pub(crate) fn clock(&self) -> FrameClock {
+        FrameClock::new(self.timescale, &self.adts_header)
+    }
+
+    pub(crate) fn duration(&self) -> Result<u32> {
+        let duration = self.clock().time(self.samples.len() as u64);
+        track_assert!(
+            duration <= u64::from(u32::MAX),
+            ErrorKind::InvalidInput;
+            duration
+        );
+        Ok(duration as u32)
+    }
+
+    /// Returns the duration of the frames if it is an integer in the timescale of the track.
+    pub(crate) fn default_sample_duration(&self) -> Option<u32> {
+        let clock = self.clock();
+        if clock.is_exact() {
+            Some(clock.duration(0))
+        } else {
+            None
+        }
+    }
+
+    /// Sets the duration of each sample if the durations of the frames are not integers
+    /// (`first_frame` is the index of the first sample in the whole track).
+    pub(crate) fn assign_sample_durations(&mut self, first_frame: u64) {
+        let clock = self.clock();
+        if clock.is_exact() {
+            return;
+        }
+        for (i, sample) in self.samples.iter_mut().enumerate() {
+            sample.duration = Some(clock.duration(first_frame + i as u64));
+        }
+    }
+}
@@ -0,0 +1,1325 @@
+use crate::aac::{AdtsFrames, AdtsHeader};
+use crate::avc::{AvcDecoderConfigurationRecord, LevelCheck, SeiMessage, SpsSummary};
+use crate::error::try_cast;
+use crate::fmp4::{
+    InitializationSegment, MediaDataBox, MediaSegment, Mp4Box, Sample, SampleEntry, SampleTable,
+    TrackBox, TrackExtendsBox, TrackFragmentBox, TrackRunBox, WebVttCueBox, WebVttEmptyCueBox,
+    WebVttSampleEntry, VIDEO_TRACK_ID,
+};
+use crate::io::{BufferPool, PooledBuffer, SegmentSink, WriteTo};
+use crate::mpeg2_ts::demux::{
+    append_avc_sample, extract_sei_messages, is_audio_or_video, read_parameter_sets,
+    StreamTimestampExtenders, TypedPesReader,
+};
+use crate::mpeg2_ts::segment::{
+    apply_media_segment_options, audio_timescale, make_media_segment,
+    make_stream_initialization_segment, sample_data_size, AacStream, AvcStream, FrameClock,
+    SegmentPosition,
+};
+use crate::mpeg2_ts::{
+    AudioBuffering, CompositionOffsets, ConvertOptions, MissingDts, TimestampExtender,
+};
+use crate::time::{self, MediaTime, Rounding, MPEG2_TS_TIMESCALE};
+use crate::{ErrorKind, Result};
+use mpeg2ts;
+use mpeg2ts::es::{StreamId, StreamType};
+use mpeg2ts::pes::PesPacket;
+use mpeg2ts::time::Timestamp;
+use mpeg2ts::ts::ReadTsPacket;
+use std::cmp;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// PES packet of an elementary stream that is neither video nor audio (e.g., KLV metadata or teletext).
+///
+/// See `ConvertOptions::keep_data_packets`.
+#[derive(Debug, Clone)]
+pub struct DataPacket {
+    /// Stream identifier in the PES header (e.g., `private_stream_1`).
+    pub stream_id: StreamId,
+
+    /// Stream type declared in the PMT.
+    pub stream_type: StreamType,
+
+    /// 90 kHz presentation timestamp extended to 64 bits (see `TimestampExtender`).
+    ///
+    /// It can be converted into the time in the segments by subtracting `StreamConverter::presentation_origin`.
+    pub pts: Option<u64>,
+
+    /// Payload of the PES packet.
+    pub data: Vec<u8>,
+}
+
+/// SEI messages of a video sample copied out by `StreamConverter`.
+///
+/// See `ConvertOptions::extracted_sei_payload_types`.
+#[derive(Debug, Clone)]
+pub struct SampleSeiMessages {
+    /// 90 kHz presentation timestamp of the sample extended to 64 bits (see `TimestampExtender`).
+    ///
+    /// It can be converted into the time in the segments by subtracting `StreamConverter::presentation_origin`.
+    pub pts: u64,
+
+    /// SEI messages of the extracted payload types in the order of appearance.
+    pub messages: Vec<SeiMessage>,
+}
+
+/// Presentation timestamps of the samples packaged into a media segment by `StreamConverter`.
+///
+/// See `ConvertOptions::record_sample_timestamps`.
+/// The timestamps are 90 kHz and extended to 64 bits (see `TimestampExtender`), and
+/// can be converted into the times in the segments by subtracting `StreamConverter::presentation_origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentSampleTimestamps {
+    /// Sequence number of the media segment.
+    pub sequence_number: u32,
+
+    /// Timestamps of the video samples in decode order.
+    pub video: Vec<u64>,
+
+    /// Timestamps of the audio samples.
+    pub audio: Vec<u64>,
+}
+
+/// Timestamps of a PES packet handed to `TimestampPolicy`.
+///
+/// All values are in the 90 kHz clock and extended to 64 bits (see `TimestampExtender`).
+#[derive(Debug, Clone)]
+pub struct PesTimestamps {
+    /// Stream identifier in the PES header.
+    pub stream_id: StreamId,
+
+    /// Presentation timestamp in the PES header.
+    pub pts: Option<u64>,
+
+    /// Decode timestamp in the PES header.
+    pub dts: Option<u64>,
+
+    /// Base (i.e., 90 kHz part) of the last PCR read before the TS packet that starts the PES packet.
+    pub pcr: Option<u64>,
+
+    /// Time when the PES packet was read.
+    ///
+    /// The clock is read only if `TimestampPolicy::needs_wallclock` returns `true`
+    /// (`Instant::now` is not available on some targets such as `wasm32-unknown-unknown`).
+    pub received_at: Option<Instant>,
+}
+
+/// Source of the timestamps of the samples used by `StreamConverter`.
+///
+/// Streams produced by broken encoders may have missing, jittery or non-monotonic PES timestamps.
+/// By implementing this trait, the timestamps can be derived from another clock
+/// or the durations of the samples can be injected by the application.
+///
+/// The timestamps of `DataPacket` and `SubtitleCue` are always the PTS of the PES packets.
+pub trait TimestampPolicy: std::fmt::Debug {
+    /// Returns the decode and presentation timestamps (in this order) of the video access unit in a PES packet.
+    fn video_timestamps(&mut self, pes: &PesTimestamps) -> Result<(u64, u64)>;
+
+    /// Returns the presentation timestamp of the first audio frame in a PES packet.
+    ///
+    /// If `None` is returned, the frames are assumed to follow the previous ones.
+    fn audio_timestamp(&mut self, pes: &PesTimestamps) -> Result<Option<u64>>;
+
+    /// Returns `true` if this policy uses `PesTimestamps::received_at`.
+    ///
+    /// The default implementation returns `false`.
+    fn needs_wallclock(&self) -> bool {
+        false
+    }
+}
+
+/// `TimestampPolicy` that uses the PTS and DTS in the PES headers as they are (the default).
+#[derive(Debug, Default, Clone)]
+pub struct PtsTimestampPolicy;
+impl TimestampPolicy for PtsTimestampPolicy {
+    fn video_timestamps(&mut self, pes: &PesTimestamps) -> Result<(u64, u64)> {
+        let pts = track_assert_some!(pes.pts, ErrorKind::InvalidInput);
+        Ok((pes.dts.unwrap_or(pts), pts))
+    }
+
+    fn audio_timestamp(&mut self, pes: &PesTimestamps) -> Result<Option<u64>> {
+        Ok(pes.pts)
+    }
+}
+
+/// `TimestampPolicy` that uses the DTS of the video PES packets as their PTS, too.
+///
+/// This is suitable for streams without B-frames whose video PTS are broken.
+/// The timestamps of the audio PES packets (which have no DTS) are used as they are.
+#[derive(Debug, Default, Clone)]
+pub struct DtsTimestampPolicy;
+impl TimestampPolicy for DtsTimestampPolicy {
+    fn video_timestamps(&mut self, pes: &PesTimestamps) -> Result<(u64, u64)> {
+        let dts = track_assert_some!(pes.dts.or(pes.pts), ErrorKind::InvalidInput);
+        Ok((dts, dts))
+    }
+
+    fn audio_timestamp(&mut self, pes: &PesTimestamps) -> Result<Option<u64>> {
+        Ok(pes.dts.or(pes.pts))
+    }
+}
+
+/// `TimestampPolicy` that uses the PCR at the start of each PES packet as its timestamp.
+///
+/// The composition offsets of the video samples (i.e., PTS minus DTS) are kept.
+/// Note that the buffering delays of the decoder are not taken into account,
+/// so the audio and video tracks may be slightly out of sync.
+#[derive(Debug, Default, Clone)]
+pub struct PcrTimestampPolicy;
+impl TimestampPolicy for PcrTimestampPolicy {
+    fn video_timestamps(&mut self, pes: &PesTimestamps) -> Result<(u64, u64)> {
+        let pcr = track_assert_some!(pes.pcr, ErrorKind::InvalidInput, "No PCR");
+        Ok((pcr, pcr + composition_offset(pes)))
+    }
+
+    fn audio_timestamp(&mut self, pes: &PesTimestamps) -> Result<Option<u64>> {
+        let pcr = track_assert_some!(pes.pcr, ErrorKind::InvalidInput, "No PCR");
+        Ok(Some(pcr))
+    }
+}
+
+/// `TimestampPolicy` that uses the time elapsed since the first PES packet was read as the timestamps.
+///
+/// This is intended for live inputs whose timestamps are unusable.
+/// The composition offsets of the video samples (i.e., PTS minus DTS) are kept.
+#[derive(Debug, Default, Clone)]
+pub struct WallclockTimestampPolicy {
+    start: Option<Instant>,
+}
+
+impl WallclockTimestampPolicy {
+    /// Makes a new `WallclockTimestampPolicy` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn elapsed(&mut self, pes: &PesTimestamps) -> Result<u64> {
+        let received_at =
+            track_assert_some!(pes.received_at, ErrorKind::InvalidInput, "No wallclock");
+        let start = *self.start.get_or_insert(received_at);
+        let elapsed = received_at.saturating_duration_since(start);
+        Ok(MediaTime::from_duration(elapsed, MPEG2_TS_TIMESCALE, Rounding::Down).value)
+    }
+}
+
+impl TimestampPolicy for WallclockTimestampPolicy {
+    fn video_timestamps(&mut self, pes: &PesTimestamps) -> Result<(u64, u64)> {
+        let dts = track!(self.elapsed(pes))?;
+        Ok((dts, dts + composition_offset(pes)))
+    }
+
+    fn audio_timestamp(&mut self, pes: &PesTimestamps) -> Result<Option<u64>> {
+        Ok(Some(track!(self.elapsed(pes))?))
+    }
+
+    fn needs_wallclock(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the PTS minus the DTS of `pes` (or zero if either of them is missing).
+fn composition_offset(pes: &PesTimestamps) -> u64 {
+    match (pes.pts, pes.dts) {
+        (Some(pts), Some(dts)) => pts.saturating_sub(dts),
+        _ => 0,
+    }
+}
+
+/// Returns `a - b` regarding the timestamps as values on the 33-bit wrapping clock.
+pub(crate) fn timestamp_diff(a: Timestamp, b: Timestamp) -> i64 {
+    let wrap = TimestampExtender::WRAP;
+    let diff = (a.as_u64() % wrap + wrap - b.as_u64() % wrap) % wrap;
+    if diff > wrap / 2 {
+        diff as i64 - wrap as i64
+    } else {
+        diff as i64
+    }
+}
+
+/// Converts MPEG-2 TS packets into fragmented MP4 segments incrementally.
+///
+/// Unlike `to_fmp4`, which reads the whole stream before making a single media segment,
+/// this converter emits a media segment each time enough samples have been read
+/// (see `ConvertOptions::segmentation`), so it can be used for live streams.
+///
+/// Video samples preceding the first PES packet that contains both SPS and PPS,
+/// or the first sync sample, are discarded, as are audio frames presented before the first video sample.
+/// The `tfdt` of the video track is the decode time relative to the first video sample,
+/// and that of the audio track is relative to the presentation time of the first video sample.
+#[derive(Debug)]
+pub struct StreamConverter<R> {
+    reader: TypedPesReader<R>,
+    options: ConvertOptions,
+    timestamp_extenders: StreamTimestampExtenders,
+    pcr_extender: TimestampExtender,
+    timestamp_policy: Box<dyn TimestampPolicy>,
+    video: Option<StreamVideoTrack>,
+    audio: Option<StreamAudioTrack>,
+    initialization_segment: Option<InitializationSegment>,
+    origin: Option<u64>,
+    composition_shift: u64,
+    sequence_number: u32,
+    ready_segment: Option<MediaSegment>,
+    data_packets: Vec<DataPacket>,
+    sei_messages: Vec<SampleSeiMessages>,
+    sample_timestamps: Vec<SegmentSampleTimestamps>,
+    buffer_pool: BufferPool,
+    text: Option<StreamTextTrack>,
+    eos: bool,
+}
+
+impl<R: ReadTsPacket> StreamConverter<R> {
+    /// Makes a new `StreamConverter` instance.
+    ///
+    /// Wrap the byte source of `reader` in `SkipToPmt` if the input may start mid-stream.
+    pub fn new(reader: R, options: ConvertOptions) -> Self {
+        StreamConverter {
+            reader: TypedPesReader::new(reader, &options),
+            options,
+            timestamp_extenders: StreamTimestampExtenders::default(),
+            pcr_extender: TimestampExtender::new(),
+            timestamp_policy: Box::new(PtsTimestampPolicy),
+            video: None,
+            audio: None,
+            initialization_segment: None,
+            origin: None,
+            composition_shift: 0,
+            sequence_number: 0,
+            ready_segment: None,
+            data_packets: Vec::new(),
+            sei_messages: Vec::new(),
+            sample_timestamps: Vec::new(),
+            buffer_pool: BufferPool::new(0),
+            text: None,
+            eos: false,
+        }
+    }
+
+    /// Sets the decoder of the subtitle streams (e.g., DVB subtitles or teletext).
+    ///
+    /// The PES packets of the data streams are handed to `decoder`, and the returned cues are
+    /// packaged into a WebVTT track (the third track of the segments).
+    /// This method must be called before the initialization segment is made.
+    pub fn set_subtitle_decoder(&mut self, decoder: Box<dyn SubtitleDecoder>) -> Result<()> {
+        track_assert!(
+            self.initialization_segment.is_none(),
+            ErrorKind::Other,
+            "The initialization segment has already been made"
+        );
+        self.text = Some(StreamTextTrack::new(decoder));
+        Ok(())
+    }
+
+    /// Sets the source of the timestamps of the video and audio samples (`PtsTimestampPolicy` by default).
+    ///
+    /// This method must be called before the initialization segment is made.
+    pub fn set_timestamp_policy(&mut self, policy: Box<dyn TimestampPolicy>) -> Result<()> {
+        track_assert!(
+            self.initialization_segment.is_none(),
+            ErrorKind::Other,
+            "The initialization segment has already been made"
+        );
+        self.timestamp_policy = policy;
+        Ok(())
+    }
+
+    /// Sets the pool from which the buffers of the sample data and the serialized media segments are taken.
+    ///
+    /// By default, every buffer is newly allocated.
+    /// The sample data of a media segment returns to the pool when the segment is serialized by
+    /// `next_media_segment_bytes` or `write_to_sink` (the segments returned by `next_media_segment` are
+    /// owned by the caller, who can return their `mdat` data to the pool by `BufferPool::put`).
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.buffer_pool = pool;
+    }
+
+    /// Takes the PES packets of the data streams read so far (see `ConvertOptions::keep_data_packets`).
+    pub fn take_data_packets(&mut self) -> Vec<DataPacket> {
+        std::mem::take(&mut self.data_packets)
+    }
+
+    /// Takes the SEI messages of the video samples read so far
+    /// (see `ConvertOptions::extracted_sei_payload_types`).
+    ///
+    /// The messages can be reattached to the converted samples by `avc::insert_sei_messages`.
+    pub fn take_sei_messages(&mut self) -> Vec<SampleSeiMessages> {
+        std::mem::take(&mut self.sei_messages)
+    }
+
+    /// Takes the presentation timestamps of the samples in the media segments made so far
+    /// (see `ConvertOptions::record_sample_timestamps`).
+    pub fn take_sample_timestamps(&mut self) -> Vec<SegmentSampleTimestamps> {
+        std::mem::take(&mut self.sample_timestamps)
+    }
+
+    /// Returns the drift of the audio track measured at the end of the last media segment
+    /// (after the adjustment made by `ConvertOptions::max_audio_drift`, if any).
+    ///
+    /// The value is the difference between the decode time derived from the PES timestamps and
+    /// that of the packaged audio samples, in the timescale of the audio track (i.e., the sampling rate).
+    /// A positive value means that the packaged audio lags behind the timestamps.
+    ///
+    /// `None` is returned if no audio sample has been packaged yet.
+    pub fn audio_drift(&self) -> Option<i64> {
+        self.audio.as_ref().and_then(|a| a.drift)
+    }
+
+    /// Returns the (90 kHz and 64-bit extended) presentation timestamp of the first video sample,
+    /// which corresponds to time zero in the segments.
+    ///
+    /// `None` is returned if the first video sample has not been read yet.
+    pub fn presentation_origin(&self) -> Option<u64> {
+        self.origin.map(|origin| origin + self.composition_shift)
+    }
+
+    /// Returns the initialization segment.
+    ///
+    /// If the codec configurations of both tracks (or the first video sample) are not known yet,
+    /// TS packets are read until they are found.
+    pub fn initialization_segment(&mut self) -> Result<&InitializationSegment> {
+        while self.initialization_segment.is_none() {
+            track_assert!(
+                !track!(self.read_pes())?,
+                ErrorKind::InvalidInput,
+                "No video and audio configurations found"
+            );
+            if let (Some(video), Some(audio), Some(_)) = (&self.video, &self.audio, self.origin) {
+                let mut segment = track!(video.make_initialization_segment(audio, &self.options))?;
+                if self.options.composition_offsets == CompositionOffsets::EditList {
+                    segment.moov_box.trak_boxes[0].edts_box.elst_box.media_time =
+                        track!(try_cast(self.composition_shift, "Composition shift"))?;
+                }
+                if self.text.is_some() {
+                    track!(add_text_track(&mut segment))?;
+                }
+                self.initialization_segment = Some(segment);
+            }
+        }
+        Ok(self.initialization_segment.as_ref().expect("Never fails"))
+    }
+
+    /// Returns the next media segment.
+    ///
+    /// `Ok(None)` is returned when the end of the stream has been reached and all samples have been emitted.
+    pub fn next_media_segment(&mut self) -> Result<Option<MediaSegment>> {
+        track!(self.initialization_segment())?;
+        loop {
+            if let Some(segment) = self.ready_segment.take() {
+                return Ok(Some(segment));
+            }
+            if self.eos {
+                return track!(self.flush());
+            }
+            self.eos = track!(self.read_pes())?;
+        }
+    }
+
+    /// Converts the rest of the stream and delivers the initialization segment and the media segments to `sink`.
+    ///
+    /// This method returns when the end of the stream has been reached.
+    pub fn write_to_sink<S: SegmentSink>(&mut self, mut sink: S) -> Result<()> {
+        let bytes = track!(track!(self.initialization_segment())?.to_bytes())?;
+        track!(sink.on_init(&bytes))?;
+        while let Some(segment) = track!(self.next_media_segment())? {
+            let sequence_number = segment.moof_box.mfhd_box.sequence_number;
+            let bytes = track!(self.serialize(segment))?;
+            track!(sink.on_media(sequence_number, &bytes))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next media segment serialized into a buffer taken from the buffer pool
+    /// (see `set_buffer_pool`).
+    ///
+    /// `Ok(None)` is returned when the end of the stream has been reached and all samples have been emitted.
+    pub fn next_media_segment_bytes(&mut self) -> Result<Option<PooledBuffer>> {
+        match track!(self.next_media_segment())? {
+            None => Ok(None),
+            Some(segment) => track!(self.serialize(segment)).map(Some),
+        }
+    }
+
+    /// Serializes `segment` into a pooled buffer and returns its sample data to the pool.
+    fn serialize(&self, segment: MediaSegment) -> Result<PooledBuffer> {
+        let mut size = track!(segment.moof_box.box_size())? as usize;
+        for mdat in &segment.mdat_boxes {
+            size += 16 + mdat.data.len();
+        }
+        let mut bytes = self.buffer_pool.get(size);
+        track!(segment.write_to(&mut *bytes))?;
+        for mdat in segment.mdat_boxes {
+            self.buffer_pool.put(mdat.data);
+        }
+        Ok(bytes)
+    }
+
+    /// Reads a PES packet. Returns `true` if the end of the stream has been reached.
+    fn read_pes(&mut self) -> Result<bool> {
+        track_assert_ne!(
+            self.options.missing_dts,
+            MissingDts::Reconstruct,
+            ErrorKind::Unsupported,
+            "The DTS values cannot be reconstructed before the whole stream is read"
+        );
+        let (pes, stream_type) = match track!(self.reader.read_pes_packet())? {
+            None => return Ok(true),
+            Some(x) => x,
+        };
+        if !is_audio_or_video(&pes) {
+            let packet = DataPacket {
+                stream_id: pes.header.stream_id,
+                stream_type,
+                pts: pes
+                    .header
+                    .pts
+                    .map(|pts| self.timestamp_extenders.extend(pes.header.stream_id, pts)),
+                data: pes.data,
+            };
+            if let Some(text) = self.text.as_mut() {
+                let cues = track!(text.decoder.decode(&packet))?;
+                text.cues.extend(cues);
+            }
+            if self.options.keep_data_packets {
+                self.data_packets.push(packet);
+            }
+            return Ok(false);
+        }
+        if pes.header.stream_id.is_video() {
+            let timestamps = self.pes_timestamps(&pes);
+            let (dts, pts) = track!(self.timestamp_policy.video_timestamps(&timestamps))?;
+
+            if self.video.is_none() {
+                if let Some((mut configuration, sps_summary)) =
+                    track!(read_parameter_sets(&pes.data))?
+                {
+                    let check = || LevelCheck::new(&sps_summary, None, None);
+                    if let Some(level_idc) = track!(self.options.avc_level.level_to_raise(check))? {
+                        track!(configuration.raise_level(level_idc))?;
+                    }
+                    self.video = Some(StreamVideoTrack::new(configuration, sps_summary));
+                } else {
+                    return Ok(false);
+                }
+            }
+            let video = self.video.as_mut().expect("Never fails");
+            let sample = track!(video.make_sample(&pes.data, dts, pts, &self.options))?;
+            if self.origin.is_none() && !sample.is_sync {
+                video.data.truncate(video.data.len() - sample.size as usize);
+                return Ok(false);
+            }
+            if !self.options.extracted_sei_payload_types.is_empty() {
+                let messages = track!(extract_sei_messages(
+                    &pes.data,
+                    &self.options.extracted_sei_payload_types
+                ))?;
+                if !messages.is_empty() {
+                    self.sei_messages.push(SampleSeiMessages { pts, messages });
+                }
+            }
+            if self.origin.is_none() {
+                self.origin = Some(dts);
+                self.composition_shift = pts.saturating_sub(dts);
+            }
+            if self.initialization_segment.is_some() && track!(self.should_cut(&sample))? {
+                let segment = track!(self.make_media_segment(Some(&sample)))?;
+                self.ready_segment = Some(segment);
+            }
+            self.video
+                .as_mut()
+                .expect("Never fails")
+                .samples
+                .push(sample);
+            if self.initialization_segment.is_none() {
+                track!(self.check_limits_before_initialization())?;
+            }
+        } else {
+            track_assert!(pes.header.stream_id.is_audio(), ErrorKind::InvalidInput);
+            if self.audio.is_none() {
+                let adts_header = track!(AdtsHeader::read_from(&pes.data[..]))?;
+                let timescale = audio_timescale(&adts_header, &self.options);
+                self.audio = Some(StreamAudioTrack::new(
+                    adts_header,
+                    timescale,
+                    self.options.audio_buffering,
+                ));
+            }
+            let timestamps = self.pes_timestamps(&pes);
+            let pts = track!(self.timestamp_policy.audio_timestamp(&timestamps))?;
+            let audio = self.audio.as_mut().expect("Never fails");
+            track!(audio.push_frames(pes.data, pts))?;
+            if self.initialization_segment.is_none() {
+                track!(self.check_limits_before_initialization())?;
+            } else if self.ready_segment.is_none() && self.audio_exceeds_limits() {
+                // The video stream is stalled (or its samples are too large), so the segment is cut here
+                let segment = track!(self.make_media_segment(None))?;
+                self.ready_segment = Some(segment);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Fails if the samples buffered until the initialization segment can be made reach
+    /// `max_data_size` or `max_duration` of the segmentation policy
+    /// (e.g., the video stream has no sync sample, or the audio stream is missing).
+    fn check_limits_before_initialization(&self) -> Result<()> {
+        let policy = &self.options.segmentation;
+        let (video_size, video_ticks) = self.video.as_ref().map_or((0, 0), |v| {
+            let ticks = match (v.samples.first(), v.samples.last()) {
+                (Some(first), Some(last)) => last.dts.saturating_sub(first.dts),
+                _ => 0,
+            };
+            (v.data.len(), ticks)
+        });
+        let (audio_size, audio_ticks) = self.audio.as_ref().map_or((0, 0), |a| {
+            let start = a.presentation_timestamps.first().cloned();
+            (a.payloads.size(), a.buffered_ticks(start))
+        });
+        let data_size = video_size + audio_size;
+        let duration = MediaTime::from_90khz(cmp::max(video_ticks, audio_ticks)).to_duration();
+        track_assert!(
+            !matches!(policy.max_data_size, Some(max) if data_size >= max),
+            ErrorKind::SegmentTooLarge,
+            "No initialization segment could be made within the data size limit: data_size={}",
+            data_size
+        );
+        track_assert!(
+            !matches!(policy.max_duration, Some(max) if duration >= max),
+            ErrorKind::SegmentTooLarge,
+            "No initialization segment could be made within the duration limit: duration={:?}",
+            duration
+        );
+        Ok(())
+    }
+
+    /// Returns `true` if the segment being built should be cut after the last audio frame.
+    ///
+    /// This is the case when the buffered sample data reaches `max_data_size`, or
+    /// the audio frames following the last video sample reach `max_duration`.
+    fn audio_exceeds_limits(&self) -> bool {
+        let policy = &self.options.segmentation;
+        let video = self.video.as_ref().expect("Never fails");
+        let audio = self.audio.as_ref().expect("Never fails");
+        let data_size = video.data.len() + audio.payloads.size();
+        if matches!(policy.max_data_size, Some(max) if data_size >= max) {
+            return true;
+        }
+        let start = video
+            .samples
+            .last()
+            .map(|s| s.pts)
+            .or_else(|| audio.presentation_timestamps.first().cloned());
+        let duration = MediaTime::from_90khz(audio.buffered_ticks(start)).to_duration();
+        matches!(policy.max_duration, Some(max) if duration >= max)
+    }
+
+    fn pes_timestamps(&mut self, pes: &PesPacket<Vec<u8>>) -> PesTimestamps {
+        let (pts, dts) = match (pes.header.pts, pes.header.dts) {
+            (Some(pts), Some(raw_dts)) => {
+                let dts = self
+                    .timestamp_extenders
+                    .extend(pes.header.stream_id, raw_dts);
+                let pts = (dts as i64 + timestamp_diff(pts, raw_dts)) as u64;
+                (Some(pts), Some(dts))
+            }
+            (Some(pts), None) => (
+                Some(self.timestamp_extenders.extend(pes.header.stream_id, pts)),
+                None,
+            ),
+            (None, _) => (None, None),
+        };
+        let pcr = self
+            .reader
+            .pcr()
+            .map(|pcr| self.pcr_extender.extend_u64(pcr.as_u64() / 300));
+        PesTimestamps {
+            stream_id: pes.header.stream_id,
+            pts,
+            dts,
+            pcr,
+            received_at: if self.timestamp_policy.needs_wallclock() {
+                Some(Instant::now())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Returns `true` if the buffered samples should be emitted before `next`.
+    fn should_cut(&self, next: &StreamVideoSample) -> Result<bool> {
+        let video = self.video.as_ref().expect("Never fails");
+        let first = match video.samples.first() {
+            None => return Ok(false),
+            Some(first) => first,
+        };
+        let ticks = next.dts.saturating_sub(first.dts);
+        let duration = MediaTime::from_90khz(ticks).to_duration();
+        let audio_size = self.audio.as_ref().map_or(0, |a| a.payloads.size());
+        let data_size = video.data.len() - next.size as usize + audio_size;
+        Ok(self
+            .options
+            .segmentation
+            .should_cut(duration, next.is_sync, data_size))
+    }
+
+    fn flush(&mut self) -> Result<Option<MediaSegment>> {
+        let has_samples = matches!(&self.video, Some(v) if !v.samples.is_empty())
+            || matches!(&self.audio, Some(a) if !a.samples.is_empty());
+        if has_samples {
+            Ok(Some(track!(self.make_media_segment(None))?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Makes a media segment from the buffered samples that precede `next`
+    /// (or from all of the buffered samples if `next` is `None`).
+    fn make_media_segment(&mut self, next: Option<&StreamVideoSample>) -> Result<MediaSegment> {
+        let origin = self.origin.expect("Never fails");
+        let video = self.video.as_mut().expect("Never fails");
+        let audio = self.audio.as_mut().expect("Never fails");
+        let starts_with_sync = matches!(video.samples.first(), Some(s) if s.is_sync);
+
+        let offset_shift = match self.options.composition_offsets {
+            CompositionOffsets::EditList => 0,
+            CompositionOffsets::Negative => {
+                track!(try_cast(self.composition_shift, "Composition shift"))?
+            }
+        };
+        let pool = &self.buffer_pool;
+        let (video_decode_time, avc_stream) =
+            track!(video.take_samples(next, origin, offset_shift, pool))?;
+        let end_pts = next.map(|s| s.pts);
+        let presentation_origin = origin + self.composition_shift;
+        let (audio_decode_time, aac_stream, audio_timestamps) = audio.take_samples(
+            end_pts,
+            presentation_origin,
+            self.options.max_audio_drift,
+            pool,
+        );
+        let video_timestamps = if self.options.record_sample_timestamps {
+            avc_stream.presentation_timestamps.clone()
+        } else {
+            Vec::new()
+        };
+
+        self.sequence_number += 1;
+        let position = SegmentPosition {
+            sequence_number: self.sequence_number,
+            video_decode_time,
+            audio_decode_time,
+            starts_with_sync,
+        };
+        let mut segment = track!(make_media_segment(
+            avc_stream,
+            aac_stream,
+            &self.options,
+            &position
+        ))?;
+        if self.options.record_sample_timestamps {
+            self.sample_timestamps.push(SegmentSampleTimestamps {
+                sequence_number: self.sequence_number,
+                video: video_timestamps,
+                audio: audio_timestamps,
+            });
+        }
+        if let Some(text) = self.text.as_mut() {
+            let end = end_pts.map(|pts| pts.saturating_sub(presentation_origin));
+            if let Some((traf, mdat)) = track!(text.take_samples(end, presentation_origin))? {
+                // The text samples are stored in their own `mdat` box following the others
+                track!(segment.push_track_fragment(traf, mdat.data))?;
+            }
+        }
+        track!(apply_media_segment_options(&mut segment, &self.options))?;
+        Ok(segment)
+    }
+}
+
+/// Decoder of subtitle PES packets (e.g., DVB subtitles or teletext) into cues.
+///
+/// Decoding these formats is out of the scope of this crate.
+/// Applications can provide their own decoders via `StreamConverter::set_subtitle_decoder`,
+/// and the returned cues are packaged into a WebVTT track.
+pub trait SubtitleDecoder: std::fmt::Debug {
+    /// Decodes `packet` and returns the cues contained in it.
+    ///
+    /// The packets of all data streams are passed, so those of the streams the decoder
+    /// does not handle should be ignored (i.e., `Ok(Vec::new())` should be returned).
+    fn decode(&mut self, packet: &DataPacket) -> Result<Vec<SubtitleCue>>;
+}
+
+/// Subtitle cue returned by `SubtitleDecoder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubtitleCue {
+    /// Start time as a 90 kHz presentation timestamp extended to 64 bits (see `DataPacket::pts`).
+    pub start: u64,
+
+    /// End time (exclusive) in the same clock as `start`.
+    pub end: u64,
+
+    /// Cue identifier.
+    pub id: Option<String>,
+
+    /// WebVTT cue settings (e.g., `"line:0 align:start"`).
+    pub settings: Option<String>,
+
+    /// Cue text.
+    pub text: String,
+}
+
+/// Adds a WebVTT track (in the 90 kHz timescale) to `segment`.
+fn add_text_track(segment: &mut InitializationSegment) -> Result<()> {
+    let video_track = segment
+        .moov_box
+        .trak_boxes
+        .iter()
+        .find(|t| t.tkhd_box.track_id() == VIDEO_TRACK_ID);
+    let video_track = track_assert_some!(video_track, ErrorKind::Other, "No video track");
+    let mut track = TrackBox::new_text();
+    track.tkhd_box.duration = video_track.tkhd_box.duration;
+    track.mdia_box.mdhd_box.timescale = MPEG2_TS_TIMESCALE;
+    track.mdia_box.mdhd_box.duration = video_track.mdia_box.mdhd_box.duration;
+    track
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries
+        .push(SampleEntry::WebVtt(WebVttSampleEntry::default()));
+    segment.moov_box.trak_boxes.push(track);
+    segment
+        .moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new_text());
+    Ok(())
+}
+
+#[derive(Debug)]
+struct StreamTextTrack {
+    decoder: Box<dyn SubtitleDecoder>,
+    cues: Vec<SubtitleCue>,
+    next_time: u64,
+}
+
+impl StreamTextTrack {
+    fn new(decoder: Box<dyn SubtitleDecoder>) -> Self {
+        StreamTextTrack {
+            decoder,
+            cues: Vec::new(),
+            next_time: 0,
+        }
+    }
+
+    /// Makes the samples that cover the period from the end of the previous segment to `end`
+    /// (or to the end of the last cue if `end` is `None`).
+    ///
+    /// The times are relative to `origin`. Cues continuing beyond `end` are kept for the next segment.
+    fn take_samples(
+        &mut self,
+        end: Option<u64>,
+        origin: u64,
+    ) -> Result<Option<(TrackFragmentBox, MediaDataBox)>> {
+        let start = self.next_time;
+        let cues = self
+            .cues
+            .iter()
+            .map(|c| {
+                (
+                    c.start.saturating_sub(origin),
+                    c.end.saturating_sub(origin),
+                    c,
+                )
+            })
+            .collect::<Vec<_>>();
+        let end = end.unwrap_or_else(|| cues.iter().map(|c| c.1).max().unwrap_or(start));
+        if end <= start {
+            return Ok(None);
+        }
+
+        // Each sample spans a period in which the set of the active cues does not change
+        let mut times = vec![start, end];
+        for &(cue_start, cue_end, _) in &cues {
+            times.extend(
+                [cue_start, cue_end]
+                    .iter()
+                    .filter(|&&t| start < t && t < end),
+            );
+        }
+        times.sort_unstable();
+        times.dedup();
+
+        let mut trun = TrackRunBox {
+            data_offset: None, // set by `MediaSegment::push_track_fragment`
+            first_sample_flags: None,
+            samples: Vec::new(),
+        };
+        let mut data = Vec::new();
+        for period in times.windows(2) {
+            let prev_data_len = data.len();
+            let mut is_empty = true;
+            for &(cue_start, cue_end, cue) in &cues {
+                if cue_start <= period[0] && period[0] < cue_end {
+                    let cue_box = WebVttCueBox {
+                        cue_id: cue.id.clone(),
+                        settings: cue.settings.clone(),
+                        payload: cue.text.clone(),
+                    };
+                    write_box!(data, cue_box);
+                    is_empty = false;
+                }
+            }
+            if is_empty {
+                write_box!(data, WebVttEmptyCueBox);
+            }
+            let duration = period[1] - period[0];
+            trun.samples.push(Sample {
+                duration: Some(track!(try_cast(duration, "Sample duration"))?),
+                size: Some(track!(try_cast(data.len() - prev_data_len, "Sample size"))?),
+                flags: None,
+                composition_time_offset: None,
+            });
+        }
+
+        self.cues.retain(|c| c.end.saturating_sub(origin) > end);
+        self.next_time = end;
+
+        let mut traf = TrackFragmentBox::new_text();
+        traf.tfdt_box.base_media_decode_time = start;
+        traf.trun_boxes.push(trun);
+        Ok(Some((traf, MediaDataBox { data })))
+    }
+}
+
+#[derive(Debug)]
+struct StreamVideoSample {
+    dts: u64,
+    pts: u64,
+    size: u32,
+    is_sync: bool,
+}
+
+#[derive(Debug)]
+struct StreamVideoTrack {
+    configuration: AvcDecoderConfigurationRecord,
+    sps_summary: SpsSummary,
+    width: usize,
+    height: usize,
+    samples: Vec<StreamVideoSample>,
+    data: Vec<u8>,
+    last_duration: u32,
+    next_decode_time: u64,
+}
+
+impl StreamVideoTrack {
+    fn new(configuration: AvcDecoderConfigurationRecord, mut sps_summary: SpsSummary) -> Self {
+        // The level of `configuration` may have been raised (see `AvcLevelPolicy`)
+        sps_summary.constraint_set_flag = configuration.constraint_set_flag;
+        sps_summary.level_idc = configuration.level_idc;
+        StreamVideoTrack {
+            configuration,
+            width: sps_summary.width(),
+            height: sps_summary.height(),
+            sps_summary,
+            samples: Vec::new(),
+            data: Vec::new(),
+            last_duration: 0,
+            next_decode_time: 0,
+        }
+    }
+
+    fn make_sample(
+        &mut self,
+        pes_data: &[u8],
+        dts: u64,
+        pts: u64,
+        options: &ConvertOptions,
+    ) -> Result<StreamVideoSample> {
+        let (size, is_sync) = track!(append_avc_sample(
+            &mut self.data,
+            pes_data,
+            &self.configuration,
+            options
+        ))?;
+        Ok(StreamVideoSample {
+            dts,
+            pts,
+            size,
+            is_sync,
+        })
+    }
+
+    fn make_initialization_segment(
+        &self,
+        audio: &StreamAudioTrack,
+        options: &ConvertOptions,
+    ) -> Result<InitializationSegment> {
+        track!(make_stream_initialization_segment(
+            &self.configuration,
+            self.width,
+            self.height,
+            &audio.adts_header,
+            audio.timescale,
+            options
+        ))
+    }
+
+    /// Takes the buffered samples that precede `next` and returns them with the decode time of the first one
+    /// (or that following the last taken sample if no sample is buffered).
+    ///
+    /// `offset_shift` is subtracted from the composition offsets of the samples.
+    fn take_samples(
+        &mut self,
+        next: Option<&StreamVideoSample>,
+        origin: u64,
+        offset_shift: i32,
+        pool: &BufferPool,
+    ) -> Result<(u64, AvcStream)> {
+        let decode_time = match self.samples.first() {
+            None => self.next_decode_time,
+            Some(first) => track_assert_some!(
+                first.dts.checked_sub(origin),
+                ErrorKind::InvalidInput,
+                "DTS before the first video sample: dts={}, origin={}",
+                first.dts,
+                origin
+            ),
+        };
+        let samples = std::mem::take(&mut self.samples);
+        let mut mp4_samples = Vec::with_capacity(samples.len());
+        let mut end_time = decode_time;
+        for (i, sample) in samples.iter().enumerate() {
+            let next_dts = samples.get(i + 1).or(next).map(|s| s.dts);
+            let duration = match next_dts {
+                Some(next_dts) => {
+                    let duration = track_assert_some!(
+                        next_dts.checked_sub(sample.dts),
+                        ErrorKind::InvalidInput,
+                        "DTS went backwards: dts={}, next_dts={}",
+                        sample.dts,
+                        next_dts
+                    );
+                    track!(try_cast(duration, "Sample duration"))?
+                }
+                None => self.last_duration,
+            };
+            self.last_duration = duration;
+            end_time += u64::from(duration);
+            let offset = sample.pts as i64 - sample.dts as i64 - i64::from(offset_shift);
+            mp4_samples.push(Sample {
+                duration: Some(duration),
+                size: Some(sample.size),
+                flags: None,
+                composition_time_offset: Some(track!(try_cast(offset, "Composition time offset"))?),
+            });
+        }
+        self.next_decode_time = end_time;
+        let data_size = sample_data_size(&mp4_samples);
+        let mut data = pool.take(data_size);
+        data.extend(self.data.drain(..data_size));
+        let avc_stream = AvcStream {
+            configuration: self.configuration.clone(),
+            sps_summary: self.sps_summary.clone(),
+            width: self.width,
+            height: self.height,
+            sync_samples: samples.iter().map(|s| s.is_sync).collect(),
+            presentation_timestamps: samples.iter().map(|s| s.pts).collect(),
+            samples: mp4_samples,
+            data,
+        };
+        Ok((decode_time, avc_stream))
+    }
+}
+
+#[derive(Debug)]
+struct StreamAudioTrack {
+    adts_header: AdtsHeader,
+    timescale: u32,
+    samples: SampleTable, // only the sizes are stored (the durations are derived from the clock)
+    presentation_timestamps: Vec<u64>,
+    payloads: AacPayloads,
+    pts_anchor: Option<(u64, u64)>, // (the last PTS in the PES headers, the number of frames since then)
+    base_decode_time: Option<i64>, // negative if the first segment was shortened to reduce the drift
+    packaged_frames: u64,
+    drift: Option<i64>,
+}
+
+impl StreamAudioTrack {
+    fn new(adts_header: AdtsHeader, timescale: u32, buffering: AudioBuffering) -> Self {
+        StreamAudioTrack {
+            adts_header,
+            timescale,
+            samples: SampleTable::new(SampleTable::SIZE),
+            presentation_timestamps: Vec::new(),
+            payloads: AacPayloads::new(buffering),
+            pts_anchor: None,
+            base_decode_time: None,
+            packaged_frames: 0,
+            drift: None,
+        }
+    }
+
+    /// Returns the clock of the frames in the timescale of the track.
+    fn clock(&self) -> FrameClock {
+        FrameClock::new(self.timescale, &self.adts_header)
+    }
+
+    /// Returns the clock of the frames in the 90 kHz timescale of the PES timestamps.
+    fn pts_clock(&self) -> FrameClock {
+        FrameClock::new(MPEG2_TS_TIMESCALE, &self.adts_header)
+    }
+
+    /// Returns the (90 kHz) presentation timestamp following the last buffered frame.
+    fn next_pts(&self) -> Option<u64> {
+        self.pts_anchor
+            .map(|(pts, frames)| pts + self.pts_clock().time(frames))
+    }
+
+    /// Returns the duration (in 90 kHz ticks) of the buffered frames presented at or after `start`.
+    fn buffered_ticks(&self, start: Option<u64>) -> u64 {
+        match (start, self.next_pts()) {
+            (Some(start), Some(end)) if !self.presentation_timestamps.is_empty() => {
+                end.saturating_sub(start)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Appends the frames in `pes_data`.
+    ///
+    /// If `pts` is `None`, the frames are assumed to follow the last buffered frame.
+    fn push_frames(&mut self, pes_data: Vec<u8>, pts: Option<u64>) -> Result<()> {
+        let (anchor_pts, offset) = match pts {
+            Some(pts) => (pts, 0),
+            None => track_assert_some!(self.pts_anchor, ErrorKind::InvalidInput),
+        };
+        let mut blocks = Vec::new();
+        for frame in AdtsFrames::new(&pes_data) {
+            let frame = track!(frame)?;
+            track!(self.samples.push(&Sample {
+                duration: None,
+                size: Some(frame.payload.len() as u32),
+                flags: None,
+                composition_time_offset: None,
+            }))?;
+            let start = frame.payload.as_ptr() as usize - pes_data.as_ptr() as usize;
+            blocks.push(start..start + frame.payload.len());
+        }
+        let n = blocks.len() as u64;
+        self.payloads.push(pes_data, blocks);
+        let clock = self.pts_clock();
+        for i in offset..offset + n {
+            self.presentation_timestamps
+                .push(anchor_pts + clock.time(i));
+        }
+        self.pts_anchor = Some((anchor_pts, offset + n));
+        Ok(())
+    }
+
+    /// Takes the buffered frames presented before `end_pts` and returns them with the decode time of the first one
+    /// and their presentation timestamps.
+    ///
+    /// Frames presented before `origin` are discarded.
+    /// If the drift against the timestamps exceeds `max_drift`, the duration of the last frame is adjusted.
+    fn take_samples(
+        &mut self,
+        end_pts: Option<u64>,
+        origin: u64,
+        max_drift: Option<Duration>,
+        pool: &BufferPool,
+    ) -> (u64, AacStream, Vec<u64>) {
+        let skip = self
+            .presentation_timestamps
+            .iter()
+            .take_while(|&&pts| pts < origin)
+            .count();
+        let end = match end_pts {
+            Some(end_pts) => self
+                .presentation_timestamps
+                .iter()
+                .take_while(|&&pts| pts < end_pts)
+                .count(),
+            None => self.samples.len(),
+        };
+        let end = cmp::max(skip, end);
+        let sizes = self.samples.sizes();
+        let size = sizes[skip..end].iter().map(|&x| x as usize).sum::<usize>();
+        let mut data = pool.take(size);
+        self.payloads.take(&sizes[..end], skip, &mut data);
+
+        let timescale = self.timescale;
+        let clock = self.clock();
+        let base_decode_time = match (
+            self.base_decode_time,
+            self.presentation_timestamps.get(skip),
+        ) {
+            (Some(t), _) => t,
+            (None, Some(&pts)) => {
+                time::rescale(pts - origin, MPEG2_TS_TIMESCALE, timescale, Rounding::Down) as i64
+            }
+            (None, None) => 0,
+        };
+        // An adjustment is at most half a frame, so this is never negative
+        let decode_time = cmp::max(
+            0,
+            base_decode_time + clock.time(self.packaged_frames) as i64,
+        ) as u64;
+        let mut samples = self
+            .samples
+            .split_to(end)
+            .iter()
+            .skip(skip)
+            .collect::<Vec<_>>();
+        if end > skip {
+            let n = (end - skip) as u64;
+            let first_frame = self.packaged_frames;
+            let mut end_time = base_decode_time + clock.time(first_frame + n) as i64;
+            if !clock.is_exact() {
+                for (i, sample) in samples.iter_mut().enumerate() {
+                    sample.duration = Some(clock.duration(first_frame + i as u64));
+                }
+            }
+
+            // Measures the drift at the end of the segment
+            let end_pts = self
+                .presentation_timestamps
+                .get(end)
+                .cloned()
+                .or_else(|| self.next_pts())
+                .expect("Never fails");
+            let expected_end_time = time::rescale(
+                end_pts.saturating_sub(origin),
+                MPEG2_TS_TIMESCALE,
+                timescale,
+                Rounding::Down,
+            );
+            let mut drift = expected_end_time as i64 - end_time;
+
+            let max_drift = max_drift
+                .map(|d| MediaTime::from_duration(d, timescale, Rounding::Down).value as i64);
+            if matches!(max_drift, Some(max) if drift.abs() > max) {
+                let last_frame = first_frame + n - 1;
+                let last_duration = clock.duration(last_frame);
+                let max_adjustment = i64::from(last_duration) / 2;
+                let adjustment = cmp::max(-max_adjustment, cmp::min(max_adjustment, drift));
+                for (i, sample) in samples.iter_mut().enumerate() {
+                    sample.duration = Some(clock.duration(first_frame + i as u64));
+                }
+                let last = samples.last_mut().expect("Never fails");
+                last.duration = Some((i64::from(last_duration) + adjustment) as u32);
+                end_time += adjustment;
+                drift -= adjustment;
+            }
+            self.drift = Some(drift);
+            self.base_decode_time = Some(end_time - clock.time(first_frame + n) as i64);
+            self.packaged_frames = first_frame + n;
+        }
+
+        let timestamps = self
+            .presentation_timestamps
+            .drain(..end)
+            .skip(skip)
+            .collect();
+        let aac_stream = AacStream {
+            adts_header: self.adts_header.clone(),
+            timescale: self.timescale,
+            start_timestamp: None,
+            samples,
+            data,
+        };
+        (decode_time, aac_stream, timestamps)
+    }
+}
+
+/// Raw data blocks of the AAC frames buffered by `StreamAudioTrack`.
+#[derive(Debug)]
+enum AacPayloads {
+    Copied(Vec<u8>),
+    Referenced {
+        pes_payloads: VecDeque<Vec<u8>>,
+        first_pes_payload: u64, // the sequence number of the front of `pes_payloads`
+        blocks: VecDeque<(u64, Range<usize>)>, // (the sequence number of the PES payload, the range in it)
+        size: usize,
+    },
+}
+
+impl AacPayloads {
+    fn new(buffering: AudioBuffering) -> Self {
+        match buffering {
+            AudioBuffering::Copy => AacPayloads::Copied(Vec::new()),
+            AudioBuffering::PesPayloads => AacPayloads::Referenced {
+                pes_payloads: VecDeque::new(),
+                first_pes_payload: 0,
+                blocks: VecDeque::new(),
+                size: 0,
+            },
+        }
+    }
+
+    /// Returns the total size of the buffered raw data blocks.
+    fn size(&self) -> usize {
+        match self {
+            AacPayloads::Copied(data) => data.len(),
+            AacPayloads::Referenced { size, .. } => *size,
+        }
+    }
+
+    /// Appends the raw data blocks at `blocks` in `pes_data`.
+    fn push(&mut self, pes_data: Vec<u8>, blocks: Vec<Range<usize>>) {
+        match self {
+            AacPayloads::Copied(data) => {
+                for block in blocks {
+                    data.extend_from_slice(&pes_data[block]);
+                }
+            }
+            AacPayloads::Referenced {
+                pes_payloads,
+                first_pes_payload,
+                blocks: buffered,
+                size,
+            } => {
+                if blocks.is_empty() {
+                    return;
+                }
+                let seqno = *first_pes_payload + pes_payloads.len() as u64;
+                for block in blocks {
+                    *size += block.len();
+                    buffered.push_back((seqno, block));
+                }
+                pes_payloads.push_back(pes_data);
+            }
+        }
+    }
+
+    /// Removes the first `sizes.len()` raw data blocks and appends them to `data`, except the first `skip` ones.
+    fn take(&mut self, sizes: &[u32], skip: usize, data: &mut Vec<u8>) {
+        match self {
+            AacPayloads::Copied(buffered) => {
+                let skip_size = sizes[..skip].iter().map(|&x| x as usize).sum::<usize>();
+                let size = sizes[skip..].iter().map(|&x| x as usize).sum::<usize>();
+                data.extend(buffered.drain(..skip_size + size).skip(skip_size));
+            }
+            AacPayloads::Referenced {
+                pes_payloads,
+                first_pes_payload,
+                blocks,
+                size,
+            } => {
+                for (i, (seqno, block)) in blocks.drain(..sizes.len()).enumerate() {
+                    *size -= block.len();
+                    if i >= skip {
+                        let pes_data = &pes_payloads[(seqno - *first_pes_payload) as usize];
+                        data.extend_from_slice(&pes_data[block]);
+                    }
+                }
+                let next = blocks
+                    .front()
+                    .map_or(*first_pes_payload + pes_payloads.len() as u64, |b| b.0);
+                let consumed = (next - *first_pes_payload) as usize;
+                pes_payloads.drain(..consumed);
+                *first_pes_payload = next;
+            }
+        }
+    }
+}
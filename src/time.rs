@@ -0,0 +1,142 @@
+//! Conversions between timestamp units (e.g., 90 kHz clock, sampling rates and `Duration`).
+//!
+//! # Examples
+//!
+//! ```
+//! use mse_fmp4::time::{MediaTime, Rounding};
+//! use std::time::Duration;
+//!
+//! let time = MediaTime::from_90khz(180_000);
+//! assert_eq!(time.to_duration(), Duration::from_secs(2));
+//! assert_eq!(time.rescale(44_100, Rounding::Down), MediaTime::new(88_200, 44_100));
+//!
+//! let frame = MediaTime::new(1024, 44_100);
+//! assert_eq!(frame.rescale(90_000, Rounding::Down).value, 2089);
+//! assert_eq!(frame.rescale(90_000, Rounding::Up).value, 2090);
+//! ```
+use std::time::Duration;
+
+/// Timescale of the timestamps in MPEG-2 TS streams (i.e., PTS and DTS).
+pub const MPEG2_TS_TIMESCALE: u32 = 90_000;
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// Rounding mode used when a time is converted into a coarser timescale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Rounding {
+    /// Rounds toward zero.
+    #[default]
+    Down,
+
+    /// Rounds away from zero.
+    Up,
+
+    /// Rounds to the nearest value (halfway values are rounded up).
+    Nearest,
+}
+
+/// Time (or duration) expressed as a number of ticks of a timescale (ticks per second).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MediaTime {
+    /// Number of ticks.
+    pub value: u64,
+
+    /// Ticks per second.
+    pub timescale: u32,
+}
+impl MediaTime {
+    /// Makes a new `MediaTime` instance.
+    pub fn new(value: u64, timescale: u32) -> Self {
+        MediaTime { value, timescale }
+    }
+
+    /// Makes a new `MediaTime` instance in the 90 kHz timescale of MPEG-2 TS.
+    pub fn from_90khz(value: u64) -> Self {
+        Self::new(value, MPEG2_TS_TIMESCALE)
+    }
+
+    /// Makes a new `MediaTime` instance that represents `samples` audio samples at `sampling_rate`.
+    pub fn from_samples(samples: u64, sampling_rate: u32) -> Self {
+        Self::new(samples, sampling_rate)
+    }
+
+    /// Converts `duration` into the given timescale.
+    pub fn from_duration(duration: Duration, timescale: u32, rounding: Rounding) -> Self {
+        let nanos = duration.as_nanos();
+        let value = div_round(
+            nanos * u128::from(timescale),
+            u128::from(NANOS_PER_SEC),
+            rounding,
+        );
+        Self::new(saturate(value), timescale)
+    }
+
+    /// Converts this time into a `Duration` (rounded down to nanoseconds).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timescale is zero.
+    pub fn to_duration(self) -> Duration {
+        let nanos = div_round(
+            u128::from(self.value) * u128::from(NANOS_PER_SEC),
+            u128::from(self.timescale),
+            Rounding::Down,
+        );
+        let secs = nanos / u128::from(NANOS_PER_SEC);
+        let subsec_nanos = (nanos % u128::from(NANOS_PER_SEC)) as u32;
+        Duration::new(saturate(secs), subsec_nanos)
+    }
+
+    /// Converts this time into the given timescale.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timescale of this time is zero.
+    pub fn rescale(self, timescale: u32, rounding: Rounding) -> Self {
+        Self::new(
+            rescale(self.value, self.timescale, timescale, rounding),
+            timescale,
+        )
+    }
+
+    /// Converts this time into the 90 kHz timescale of MPEG-2 TS.
+    pub fn to_90khz(self, rounding: Rounding) -> u64 {
+        self.rescale(MPEG2_TS_TIMESCALE, rounding).value
+    }
+}
+
+/// Converts `value` in the `from` timescale into the `to` timescale.
+///
+/// The result saturates at `u64::MAX`.
+///
+/// # Panics
+///
+/// Panics if `from` is zero.
+pub fn rescale(value: u64, from: u32, to: u32, rounding: Rounding) -> u64 {
+    if from == to {
+        return value;
+    }
+    let value = div_round(
+        u128::from(value) * u128::from(to),
+        u128::from(from),
+        rounding,
+    );
+    saturate(value)
+}
+
+fn div_round(n: u128, d: u128, rounding: Rounding) -> u128 {
+    let (quotient, remainder) = (n / d, n % d);
+    match rounding {
+        Rounding::Down => quotient,
+        Rounding::Up => quotient + u128::from(remainder > 0),
+        Rounding::Nearest => quotient + u128::from(remainder >= d - d / 2),
+    }
+}
+
+fn saturate(n: u128) -> u64 {
+    if n > u128::from(u64::MAX) {
+        u64::MAX
+    } else {
+        n as u64
+    }
+}
@@ -0,0 +1,76 @@
+//! VP9 related constituent elements.
+use crate::{ErrorKind, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::{Read, Write};
+
+/// VP codec configuration record.
+///
+/// See [VP Codec ISO Media File Format Binding][vp-codec-iso] for the details of each field.
+///
+/// [vp-codec-iso]: https://www.webmproject.org/vp9/mp4/
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VpCodecConfigurationRecord {
+    pub profile: u8,
+    pub level: u8,
+    pub bit_depth: u8,          // u4
+    pub chroma_subsampling: u8, // u3
+    pub video_full_range_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+}
+impl VpCodecConfigurationRecord {
+    /// Returns the codec string (e.g., `"vp09.00.41.08"`) used in MIME types.
+    pub fn codec_string(&self) -> String {
+        format!(
+            "vp09.{:02}.{:02}.{:02}",
+            self.profile, self.level, self.bit_depth
+        )
+    }
+
+    /// Reads a `VpCodecConfigurationRecord` from the given reader.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let profile = track_io!(reader.read_u8())?;
+        let level = track_io!(reader.read_u8())?;
+        let b = track_io!(reader.read_u8())?;
+        let colour_primaries = track_io!(reader.read_u8())?;
+        let transfer_characteristics = track_io!(reader.read_u8())?;
+        let matrix_coefficients = track_io!(reader.read_u8())?;
+        let codec_initialization_data_size = track_io!(reader.read_u16::<BigEndian>())?;
+        track_assert_eq!(codec_initialization_data_size, 0, ErrorKind::InvalidInput);
+        Ok(VpCodecConfigurationRecord {
+            profile,
+            level,
+            bit_depth: b >> 4,
+            chroma_subsampling: (b >> 1) & 0b111,
+            video_full_range_flag: (b & 1) != 0,
+            colour_primaries,
+            transfer_characteristics,
+            matrix_coefficients,
+        })
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        track_assert!(self.profile <= 3, ErrorKind::InvalidInput; self.profile);
+        track_assert!(
+            [8, 10, 12].contains(&self.bit_depth),
+            ErrorKind::InvalidInput; self.bit_depth
+        );
+        track_assert!(self.chroma_subsampling <= 3, ErrorKind::InvalidInput; self.chroma_subsampling);
+
+        write_u8!(writer, self.profile);
+        write_u8!(writer, self.level);
+        write_u8!(
+            writer,
+            (self.bit_depth << 4)
+                | (self.chroma_subsampling << 1)
+                | self.video_full_range_flag as u8
+        );
+        write_u8!(writer, self.colour_primaries);
+        write_u8!(writer, self.transfer_characteristics);
+        write_u8!(writer, self.matrix_coefficients);
+        write_u16!(writer, 0); // codec_initialization_data_size (must be 0 for VP9)
+        Ok(())
+    }
+}
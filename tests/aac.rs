@@ -0,0 +1,96 @@
+extern crate mse_fmp4;
+
+use mse_fmp4::aac::{
+    write_adts_frame, AacProfile, AdtsFrames, AdtsHeader, AudioSpecificConfig,
+    ChannelConfiguration, SamplingFrequency,
+};
+
+#[test]
+fn adts_buffer_fullness_is_read_from_both_bytes() {
+    // AAC-LC, 48 kHz, stereo, `frame_len=0x123`
+    let header = |buffer_fullness: u16| {
+        let n = (0x123 << 5) | (buffer_fullness >> 6);
+        [
+            0xFF,
+            0xF1,
+            0x4C,
+            0x80,
+            (n >> 8) as u8,
+            n as u8,
+            ((buffer_fullness & 0b11_1111) as u8) << 2,
+        ]
+    };
+    for &buffer_fullness in &[0, 0x040, 0x555, 0x7FF] {
+        let read = AdtsHeader::read_from(&header(buffer_fullness)[..]).unwrap();
+        assert_eq!(read.frame_len, 0x123);
+        assert_eq!(read.buffer_fullness, buffer_fullness);
+    }
+}
+
+fn header_bytes(header: &AdtsHeader) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    header.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn adts_header_round_trips() {
+    let headers = [
+        AdtsHeader {
+            profile: AacProfile::Lc,
+            sampling_frequency: SamplingFrequency::Hz48000,
+            private: false,
+            channel_configuration: ChannelConfiguration::TwoChannels,
+            frame_len: 7,
+            buffer_fullness: 0x7FF,
+        },
+        AdtsHeader {
+            profile: AacProfile::Ltp,
+            sampling_frequency: SamplingFrequency::Hz7350,
+            private: true,
+            channel_configuration: ChannelConfiguration::EightChannels,
+            frame_len: 0x1FFF,
+            buffer_fullness: 0x555,
+        },
+    ];
+    for header in &headers {
+        let bytes = header_bytes(header);
+        assert_eq!(bytes.len(), 7);
+
+        let read = AdtsHeader::read_from(&bytes[..]).unwrap();
+        assert_eq!(read.profile, header.profile);
+        assert_eq!(read.sampling_frequency, header.sampling_frequency);
+        assert_eq!(read.private, header.private);
+        assert_eq!(read.channel_configuration, header.channel_configuration);
+        assert_eq!(read.frame_len, header.frame_len);
+        assert_eq!(read.buffer_fullness, header.buffer_fullness);
+        assert_eq!(header_bytes(&read), bytes);
+    }
+}
+
+#[test]
+fn adts_frames_round_trip_through_audio_specific_config() {
+    let config = AudioSpecificConfig {
+        profile: AacProfile::Main,
+        sampling_frequency: SamplingFrequency::Hz44100,
+        channel_configuration: ChannelConfiguration::OneChannel,
+    };
+    let mut asc = Vec::new();
+    config.write_to(&mut asc).unwrap();
+    assert_eq!(AudioSpecificConfig::read_from(&asc[..]).unwrap(), config);
+
+    let mut bytes = Vec::new();
+    write_adts_frame(&mut bytes, &config, &[1, 2, 3]).unwrap();
+    write_adts_frame(&mut bytes, &config, &[]).unwrap();
+    let frames = AdtsFrames::new(&bytes)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].payload, [1, 2, 3]);
+    assert!(frames[1].payload.is_empty());
+    for frame in &frames {
+        assert_eq!(AudioSpecificConfig::from(&frame.header), config);
+    }
+
+    assert!(config.to_adts_header(0x2000).is_err());
+}
@@ -0,0 +1,187 @@
+extern crate mse_fmp4;
+
+use mse_fmp4::av1::{
+    temporal_unit_to_sample_data, Av1CodecConfigurationRecord, Obu, ObuHeader, ObuType, Obus,
+};
+use mse_fmp4::fmp4::{Av1ConfigurationBox, Mp4Box};
+
+/// Writes `(value, bit width)` pairs MSB first, and pads the last byte with zeroes.
+fn bits(fields: &[(u64, usize)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut offset = 0;
+    for &(value, width) in fields {
+        for i in (0..width).rev() {
+            if offset % 8 == 0 {
+                bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *bytes.last_mut().unwrap() |= bit << (7 - offset % 8);
+            offset += 1;
+        }
+    }
+    bytes
+}
+
+// Main profile, level 4.0, Main tier, 10-bit 4:2:0, 1920x1080
+fn sequence_header_payload() -> Vec<u8> {
+    bits(&[
+        (0, 3),     // seq_profile
+        (0, 1),     // still_picture
+        (0, 1),     // reduced_still_picture_header
+        (0, 1),     // timing_info_present_flag
+        (0, 1),     // initial_display_delay_present_flag
+        (0, 5),     // operating_points_cnt_minus_1
+        (0, 12),    // operating_point_idc[0]
+        (8, 5),     // seq_level_idx[0]
+        (0, 1),     // seq_tier[0]
+        (10, 4),    // frame_width_bits_minus_1
+        (10, 4),    // frame_height_bits_minus_1
+        (1919, 11), // max_frame_width_minus_1
+        (1079, 11), // max_frame_height_minus_1
+        (0, 1),     // frame_id_numbers_present_flag
+        (0, 3),     // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+        (0, 4), // enable_interintra_compound, enable_masked_compound, enable_warped_motion, enable_dual_filter
+        (1, 1), // enable_order_hint
+        (0, 2), // enable_jnt_comp, enable_ref_frame_mvs
+        (1, 1), // seq_choose_screen_content_tools
+        (1, 1), // seq_choose_integer_mv
+        (6, 3), // order_hint_bits_minus_1
+        (0b011, 3), // enable_superres, enable_cdef, enable_restoration
+        (1, 1), // high_bitdepth
+        (0, 1), // mono_chrome
+        (0, 1), // color_description_present_flag
+        (0, 1), // color_range
+        (0, 2), // chroma_sample_position
+        (1, 1), // film_grain_params_present (not read) and trailing bits
+    ])
+}
+
+fn obu_bytes(obu_type: ObuType, extension: Option<(u8, u8)>, payload: &[u8]) -> Vec<u8> {
+    let obu = Obu {
+        header: ObuHeader {
+            obu_type,
+            has_size_field: true,
+            extension,
+        },
+        payload,
+    };
+    let mut bytes = Vec::new();
+    obu.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn obu_sizes_round_trip_as_leb128() {
+    for &size in &[0, 1, 127, 128, 16_383, 16_384, 2_097_151, 2_097_152] {
+        let payload = vec![0xAB; size];
+        let bytes = obu_bytes(ObuType::Padding, None, &payload);
+        let leb128_len = bytes.len() - 1 - size;
+        let expected_len = match size {
+            0..=127 => 1,
+            128..=16_383 => 2,
+            16_384..=2_097_151 => 3,
+            _ => 4,
+        };
+        assert_eq!(leb128_len, expected_len, "size={}", size);
+
+        let obus = Obus::new(&bytes).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(obus.len(), 1);
+        assert_eq!(obus[0].payload.len(), size);
+    }
+}
+
+#[test]
+fn leb128_values_are_limited_to_eight_bytes() {
+    // `5` encoded (non-minimally) in eight bytes
+    let mut bytes = vec![0b0111_1010, 0x85];
+    bytes.extend_from_slice(&[0x80; 6]);
+    bytes.push(0x00);
+    bytes.extend_from_slice(&[1, 2, 3, 4, 5]);
+    let obus = Obus::new(&bytes).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(obus[0].header.obu_type, ObuType::Padding);
+    assert_eq!(obus[0].payload, [1, 2, 3, 4, 5]);
+
+    // ... and in nine bytes
+    let mut bytes = vec![0b0111_1010, 0x85];
+    bytes.extend_from_slice(&[0x80; 7]);
+    bytes.push(0x00);
+    bytes.extend_from_slice(&[1, 2, 3, 4, 5]);
+    let mut obus = Obus::new(&bytes);
+    assert!(obus.next().unwrap().is_err());
+    assert!(obus.next().is_none());
+}
+
+#[test]
+fn obus_with_extension_headers_and_without_size_fields_are_read() {
+    let mut temporal_unit = vec![0b0001_0010, 0]; // temporal delimiter
+    temporal_unit.extend(obu_bytes(ObuType::Frame, Some((2, 1)), &[1, 2, 3]));
+    temporal_unit.extend_from_slice(&[0b0010_1000, 9, 9]); // metadata OBU without the size field
+
+    let obus = Obus::new(&temporal_unit)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(obus.len(), 3);
+    assert_eq!(obus[1].header.obu_type, ObuType::Frame);
+    assert_eq!(obus[1].header.extension, Some((2, 1)));
+    assert!(obus[1].header.has_size_field);
+    assert_eq!(obus[1].payload, [1, 2, 3]);
+    assert_eq!(obus[2].header.obu_type, ObuType::Metadata);
+    assert_eq!(obus[2].header.extension, None);
+    assert!(!obus[2].header.has_size_field);
+    assert_eq!(obus[2].payload, [9, 9]);
+
+    // The temporal delimiter is dropped and the size field is added to the last OBU
+    let mut expected = obu_bytes(ObuType::Frame, Some((2, 1)), &[1, 2, 3]);
+    expected.extend_from_slice(&[0b0010_1010, 2, 9, 9]);
+    assert_eq!(
+        temporal_unit_to_sample_data(&temporal_unit).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn sequence_header_is_converted_to_av1c() {
+    let obu = obu_bytes(ObuType::SequenceHeader, None, &sequence_header_payload());
+    let record = Av1CodecConfigurationRecord::from_sequence_header_obu(&obu).unwrap();
+    assert_eq!(record.seq_profile, 0);
+    assert_eq!(record.seq_level_idx_0, 8);
+    assert!(!record.seq_tier_0);
+    assert_eq!(record.bit_depth(), 10);
+    assert!(!record.monochrome);
+    assert!(record.chroma_subsampling_x && record.chroma_subsampling_y);
+    assert_eq!(record.config_obus, obu);
+    assert_eq!(record.codec_string(), "av01.0.08M.10");
+
+    let av1c_box = Av1ConfigurationBox {
+        configuration: record.clone(),
+    };
+    let mut bytes = Vec::new();
+    av1c_box.write_box(&mut bytes).unwrap();
+    assert_eq!(&bytes[4..8], b"av1C");
+    assert_eq!(bytes[8..12], [0x81, 0x08, 0x4C, 0x00]);
+    let read = Av1CodecConfigurationRecord::read_from(&bytes[8..]).unwrap();
+    assert_eq!(read, record);
+}
+
+#[test]
+fn truncated_av1_input_is_rejected() {
+    let payload = sequence_header_payload();
+    let obu = obu_bytes(ObuType::SequenceHeader, None, &payload);
+    for len in 0..obu.len() {
+        assert!(Av1CodecConfigurationRecord::from_sequence_header_obu(&obu[..len]).is_err());
+    }
+
+    // Without the size field, the payload is truncated instead
+    let mut obu = vec![0b0000_1000];
+    obu.extend_from_slice(&payload);
+    for len in 0..obu.len() - 1 {
+        assert!(Av1CodecConfigurationRecord::from_sequence_header_obu(&obu[..len]).is_err());
+    }
+
+    // Extension header without its second byte
+    assert!(Obus::new(&[0b0011_0100]).next().unwrap().is_err());
+
+    for len in 0..4 {
+        assert!(Av1CodecConfigurationRecord::read_from(&[0x81, 0x08, 0x4C, 0x00][..len]).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+extern crate mse_fmp4;
+
+use mse_fmp4::avc::{
+    self, AvcDecoderConfigurationRecord, ByteStreamFormatNalUnits, LevelCheck, NalUnit,
+    NalUnitType, PpsSummary, SeiMessage, SpsSummary,
+};
+use mse_fmp4::io::WriteTo;
+use mse_fmp4::mpeg2_ts::{ConvertOptions, SeiFilter, VideoSampleFlags};
+
+// Baseline profile SPS (1920x1080) that contains emulation prevention bytes
+// (`0x000003`) before the picture size fields.
+const SPS_WITH_EMULATION_PREVENTION: &[u8] = &[
+    0x67, 0x42, 0xe0, 0x28, 0xd0, 0x00, 0x00, 0x03, 0x02, 0x00, 0x00, 0x03, 0x03, 0xa0, 0x1e, 0x00,
+    0x89, 0xf9, 0x50,
+];
+
+#[test]
+fn sps_with_emulation_prevention_bytes() {
+    let nal_unit = NalUnit::read_from(SPS_WITH_EMULATION_PREVENTION).unwrap();
+    assert_eq!(nal_unit.nal_unit_type, NalUnitType::SequenceParameterSet);
+
+    let sps = SpsSummary::read_from(&SPS_WITH_EMULATION_PREVENTION[1..]).unwrap();
+    assert_eq!(sps.profile_idc, 66);
+    assert_eq!(sps.constraint_set_flag, 0xe0);
+    assert_eq!(sps.level_idc, 40);
+    assert_eq!(sps.width(), 1920);
+    assert_eq!(sps.height(), 1080);
+}
+
+#[test]
+fn idr_access_unit_to_fmp4() {
+    let mut access_unit = vec![0, 0, 0, 1];
+    access_unit.extend_from_slice(SPS_WITH_EMULATION_PREVENTION);
+    access_unit.extend_from_slice(&[0, 0, 0, 1, 0x68, 0xce, 0x38, 0x80]); // PPS
+    access_unit.extend_from_slice(&[0, 0, 0, 1, 0x65, 0x88, 0x84, 0x00]); // IDR slice
+
+    let (init, media) = mse_fmp4::mpeg2_ts::access_unit_to_fmp4(
+        &access_unit,
+        std::time::Duration::from_millis(40),
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(init.resolution(), Some((1920, 1080)));
+    let samples = media
+        .samples()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(samples.len(), 1);
+    assert!(samples[0].is_sync);
+    assert_eq!(samples[0].duration, 3600);
+    assert!(init.to_bytes().is_ok());
+    assert!(media.to_bytes().is_ok());
+
+    let options = ConvertOptions {
+        video_sample_flags: VideoSampleFlags::PerSample,
+        ..Default::default()
+    };
+    let (_, media) = mse_fmp4::mpeg2_ts::access_unit_to_fmp4(
+        &access_unit,
+        std::time::Duration::from_millis(40),
+        &options,
+    )
+    .unwrap();
+    let trun = &media.moof_box.traf_boxes[0].trun_boxes[0];
+    assert_eq!(trun.first_sample_flags, None);
+    assert!(!trun.samples[0].flags.unwrap().sample_is_non_sync_sample);
+    assert!(media.samples().unwrap().next().unwrap().unwrap().is_sync);
+}
+
+#[test]
+fn sei_messages_are_filtered_and_reattached() {
+    let pic_timing = SeiMessage {
+        payload_type: SeiMessage::PIC_TIMING,
+        payload: vec![0x00, 0x00, 0x01, 0x80], // needs emulation prevention
+    };
+    let unregistered = SeiMessage {
+        payload_type: SeiMessage::USER_DATA_UNREGISTERED,
+        payload: vec![0xab; 300],
+    };
+    let sei = SeiMessage::to_nal_unit(&[pic_timing.clone(), unregistered.clone()]);
+    assert!(sei.windows(4).any(|w| w == [0x00, 0x00, 0x03, 0x01]));
+    assert_eq!(
+        SeiMessage::read_all(&sei).unwrap(),
+        [pic_timing.clone(), unregistered]
+    );
+
+    let mut access_unit = vec![0, 0, 0, 1];
+    access_unit.extend_from_slice(SPS_WITH_EMULATION_PREVENTION);
+    access_unit.extend_from_slice(&[0, 0, 0, 1, 0x68, 0xce, 0x38, 0x80]); // PPS
+    access_unit.extend_from_slice(&[0, 0, 0, 1]);
+    access_unit.extend_from_slice(&sei);
+    access_unit.extend_from_slice(&[0, 0, 0, 1, 0x65, 0x88, 0x84, 0x00]); // IDR slice
+
+    let convert = |sei_filter| {
+        let options = ConvertOptions {
+            sei_filter,
+            ..Default::default()
+        };
+        let (_, media) = mse_fmp4::mpeg2_ts::access_unit_to_fmp4(
+            &access_unit,
+            std::time::Duration::from_millis(40),
+            &options,
+        )
+        .unwrap();
+        let sample = media.samples().unwrap().next().unwrap().unwrap();
+        sample.data.to_owned()
+    };
+    let sample = convert(SeiFilter::KeepOnly(vec![SeiMessage::PIC_TIMING]));
+    assert_eq!(
+        avc::read_sei_messages(&sample).unwrap(),
+        std::slice::from_ref(&pic_timing)
+    );
+
+    let sample = convert(SeiFilter::KeepOnly(Vec::new()));
+    assert_eq!(avc::read_sei_messages(&sample).unwrap(), []);
+    let sample = avc::insert_sei_messages(&sample, std::slice::from_ref(&pic_timing)).unwrap();
+    assert_eq!(avc::read_sei_messages(&sample).unwrap(), [pic_timing]);
+    assert_eq!(
+        sample[sample.len() - 8..],
+        [0, 0, 0, 4, 0x65, 0x88, 0x84, 0x00]
+    );
+}
+
+// Straightforward splitter used as the reference of `ByteStreamFormatNalUnits`.
+fn split_nal_units_naively(mut bytes: &[u8]) -> Vec<&[u8]> {
+    let mut nal_units = Vec::new();
+    bytes = if bytes.starts_with(&[0, 0, 1]) {
+        &bytes[3..]
+    } else {
+        &bytes[4..]
+    };
+    while !bytes.is_empty() {
+        let mut end = (bytes.len(), bytes.len());
+        for i in 0..bytes.len() {
+            if bytes[i..].starts_with(&[0, 0, 0, 1]) {
+                end = (i, i + 4);
+                break;
+            } else if bytes[i..].starts_with(&[0, 0, 1]) {
+                end = (i, i + 3);
+                break;
+            }
+        }
+        nal_units.push(&bytes[..end.0]);
+        bytes = &bytes[end.1..];
+    }
+    nal_units
+}
+
+#[test]
+fn start_codes_are_found_at_any_position() {
+    // Pseudo-random bytes rich in zeros and ones, so that start codes of both lengths
+    // (and runs of zeros) appear at every alignment
+    let mut state = 12345u32;
+    let mut bytes = vec![0, 0, 0, 1];
+    for _ in 0..100_000 {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        bytes.push([0, 0, 0, 1, 2, 0x65, 0xff][(state >> 16) as usize % 7]);
+    }
+    for len in (4..bytes.len()).step_by(997).chain(Some(bytes.len())) {
+        let bytes = &bytes[..len];
+        let expected = split_nal_units_naively(bytes);
+        let actual = ByteStreamFormatNalUnits::new(bytes)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn pps_pairing_checks_the_sps_id() {
+    let sps = SpsSummary::read_from(&SPS_WITH_EMULATION_PREVENTION[1..]).unwrap();
+    let mut pps = PpsSummary::read_from(&[0xce, 0x3c, 0x80][..]).unwrap();
+    assert_eq!(pps.seq_parameter_set_id, sps.seq_parameter_set_id);
+    pps.check_pairing(&sps).unwrap();
+
+    pps.seq_parameter_set_id = sps.seq_parameter_set_id + 1;
+    let e = pps.check_pairing(&sps).unwrap_err();
+    assert_eq!(*e.kind(), mse_fmp4::ErrorKind::InvalidInput);
+
+    let mut other_sps = sps.clone();
+    other_sps.seq_parameter_set_id = pps.seq_parameter_set_id;
+    pps.check_pairing(&other_sps).unwrap();
+
+    // CABAC is rejected in the baseline profile even if the ids match
+    pps.entropy_coding_mode_flag = true;
+    let e = pps.check_pairing(&other_sps).unwrap_err();
+    assert_eq!(*e.kind(), mse_fmp4::ErrorKind::InvalidInput);
+}
+
+/// Returns an SPS (without the NAL unit header) of progressive `width_in_mbs` x `height_in_mbs` pictures.
+fn sps_bytes(
+    constraint_set_flag: u8,
+    level_idc: u8,
+    width_in_mbs: u64,
+    height_in_mbs: u64,
+) -> Vec<u8> {
+    let mut bits = Vec::new();
+    let ue = |bits: &mut Vec<bool>, v: u64| {
+        let n = 64 - (v + 1).leading_zeros();
+        bits.extend((1..n).map(|_| false));
+        bits.extend((0..n).rev().map(|i| (v + 1) >> i & 1 == 1));
+    };
+    ue(&mut bits, 0); // seq_parameter_set_id
+    ue(&mut bits, 0); // log2_max_frame_num_minus4
+    ue(&mut bits, 2); // pic_order_cnt_type
+    ue(&mut bits, 1); // num_ref_frames
+    bits.push(false); // gaps_in_frame_num_value_allowed_flag
+    ue(&mut bits, width_in_mbs - 1);
+    ue(&mut bits, height_in_mbs - 1);
+    bits.extend(&[true, true, false, false, true]); // frame_mbs_only_flag ... rbsp_stop_one_bit
+
+    let mut rbsp = vec![66, constraint_set_flag, level_idc];
+    rbsp.extend(
+        bits.chunks(8)
+            .map(|byte| (0..8).fold(0, |n, i| (n << 1) | u8::from(byte.get(i) == Some(&true)))),
+    );
+    let mut sps = Vec::new();
+    for b in rbsp {
+        if sps.ends_with(&[0, 0]) && b <= 3 {
+            sps.push(3);
+        }
+        sps.push(b);
+    }
+    sps
+}
+
+fn level_check(
+    level_idc: u8,
+    (width_in_mbs, height_in_mbs): (u64, u64),
+    frame_rate: f64,
+    bitrate: u64,
+) -> mse_fmp4::Result<LevelCheck> {
+    let sps = SpsSummary::read_from(&sps_bytes(0, level_idc, width_in_mbs, height_in_mbs)[..])?;
+    assert_eq!(sps.width() as u64, width_in_mbs * 16);
+    assert_eq!(sps.height() as u64, height_in_mbs * 16);
+    LevelCheck::new(&sps, Some(frame_rate), Some(bitrate))
+}
+
+#[test]
+fn level_check_follows_table_a_1() {
+    const QCIF: (u64, u64) = (11, 9);
+    const CIF: (u64, u64) = (22, 18);
+    const HD_720: (u64, u64) = (80, 45);
+    const HD_1080: (u64, u64) = (120, 68);
+    const UHD: (u64, u64) = (240, 135);
+    // (frame size, frames per second, bits per second, required level_idc)
+    let cases = [
+        (QCIF, 15.0, 64_000, 10),
+        (QCIF, 15.0, 64_001, 9), // 1b
+        (QCIF, 15.0, 128_001, 11),
+        (QCIF, 15.1, 0, 11), // MaxMBPS of level 1 is 1485
+        (CIF, 7.5, 0, 11),
+        (CIF, 15.0, 0, 12),
+        (CIF, 30.0, 768_000, 13),
+        (CIF, 30.0, 768_001, 20),
+        ((45, 36), 25.0, 0, 30),
+        (HD_720, 30.0, 0, 31),
+        (HD_720, 60.0, 0, 32),
+        (HD_1080, 30.0, 20_000_000, 40),
+        (HD_1080, 30.0, 20_000_001, 41),
+        (HD_1080, 60.0, 0, 42),
+        (HD_1080, 60.0, 50_000_001, 50),
+        (UHD, 30.0, 0, 51),
+        (UHD, 60.0, 0, 52),
+        ((256, 144), 60.0, 0, 60),
+        ((256, 144), 120.0, 0, 61),
+        ((256, 144), 240.0, 480_000_001, 62),
+        // The height exceeds `Sqrt(MaxFS * 8)` of the levels below 2.2
+        ((1, 99), 1.0, 0, 22),
+    ];
+    for &(size, frame_rate, bitrate, expected) in &cases {
+        let check = level_check(10, size, frame_rate, bitrate).unwrap();
+        assert_eq!(
+            check.required_level_idc,
+            expected,
+            "{:?}",
+            (size, frame_rate, bitrate)
+        );
+    }
+
+    let e = level_check(10, (256, 144), 1000.0, 0).unwrap_err();
+    assert_eq!(*e.kind(), mse_fmp4::ErrorKind::Unsupported);
+}
+
+#[test]
+fn level_1b_is_recognized_in_either_form() {
+    let check = |constraint_set_flag, level_idc| {
+        let sps = sps_bytes(constraint_set_flag, level_idc, 11, 9);
+        let sps = SpsSummary::read_from(&sps[..]).unwrap();
+        LevelCheck::new(&sps, Some(15.0), Some(100_000)).unwrap()
+    };
+
+    // `level_idc=11` with `constraint_set3_flag` is level 1b in the baseline profile
+    let level_1b = check(0x10, 11);
+    assert_eq!(level_1b.declared_level_idc, 9);
+    assert_eq!(level_1b.required_level_idc, 9);
+    assert!(level_1b.is_sufficient());
+
+    let level_1_1 = check(0, 11);
+    assert_eq!(level_1_1.declared_level_idc, 11);
+    assert!(level_1_1.is_sufficient());
+
+    let level_1 = check(0, 10);
+    assert_eq!(level_1.declared_level_idc, 10);
+    assert!(!level_1.is_sufficient());
+
+    let check = LevelCheck {
+        declared_level_idc: 9,
+        required_level_idc: 11,
+    };
+    assert!(!check.is_sufficient());
+}
+
+#[test]
+fn raised_levels_are_written_to_the_record_and_sps() {
+    let mut sps = vec![0x67];
+    sps.extend(sps_bytes(0xe0, 10, 11, 9));
+    let mut record = AvcDecoderConfigurationRecord {
+        profile_idc: 66,
+        constraint_set_flag: 0xe0,
+        level_idc: 10,
+        sequence_parameter_set: sps,
+        picture_parameter_set: vec![0x68, 0xce, 0x3c, 0x80],
+    };
+
+    // Level 1b is signalled by `constraint_set3_flag` in the baseline profile
+    assert!(record.raise_level(9).unwrap());
+    assert_eq!(record.codec_string(), "avc1.42f00b");
+    assert_eq!(record.sequence_parameter_set[1..4], [66, 0xf0, 11]);
+    assert!(!record.raise_level(9).unwrap());
+    assert!(!record.raise_level(10).unwrap());
+
+    // ... and the flag is cleared at the higher levels
+    assert!(record.raise_level(11).unwrap());
+    assert_eq!(record.codec_string(), "avc1.42e00b");
+    assert_eq!(record.sequence_parameter_set[1..4], [66, 0xe0, 11]);
+
+    assert!(record.raise_level(40).unwrap());
+    assert_eq!(record.codec_string(), "avc1.42e028");
+    let summary = SpsSummary::read_from(&record.sequence_parameter_set[1..]).unwrap();
+    assert_eq!(summary.constraint_set_flag, 0xe0);
+    assert_eq!(summary.level_idc, 40);
+    assert_eq!(summary.width(), 176);
+}
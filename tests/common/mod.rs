@@ -0,0 +1,380 @@
+// Helpers shared by the integration tests.
+//
+// Each test crate uses only some of them.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Asserts that `actual` is identical to the file `tests/golden/{name}`.
+///
+/// Set `UPDATE_GOLDEN=1` to regenerate the files under `tests/golden/`
+/// (only when the change of the output format is intended).
+pub fn assert_golden(name: &str, actual: &[u8]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+    }
+    let expected = fs::read(&path).unwrap();
+    assert!(
+        expected == actual,
+        "{} differs from the golden file (expected {} bytes, actual {} bytes)",
+        name,
+        expected.len(),
+        actual.len()
+    );
+}
+
+pub const PMT_PID: u16 = 0x1000;
+pub const VIDEO_PID: u16 = 0x100;
+pub const AUDIO_PID: u16 = 0x101;
+
+pub const VIDEO_STREAM_ID: u8 = 0xE0;
+pub const AUDIO_STREAM_ID: u8 = 0xC0;
+
+pub const STREAM_TYPE_H264: u8 = 0x1B;
+pub const STREAM_TYPE_ADTS_AAC: u8 = 0x0F;
+
+/// Duration of a video frame (30 fps) in 90 kHz ticks.
+pub const FRAME_DURATION: u64 = 3000;
+
+/// Duration of an AAC frame (1024 samples at 48 kHz) in 90 kHz ticks.
+pub const AAC_FRAME_DURATION: u64 = 1920;
+
+/// PTS of the first sample of the streams made by `av_stream`.
+pub const START_PTS: u64 = 900_000;
+
+// Baseline profile SPS (1920x1080) that contains emulation prevention bytes.
+pub const SPS: &[u8] = &[
+    0x67, 0x42, 0xe0, 0x28, 0xd0, 0x00, 0x00, 0x03, 0x02, 0x00, 0x00, 0x03, 0x03, 0xa0, 0x1e, 0x00,
+    0x89, 0xf9, 0x50,
+];
+pub const PPS: &[u8] = &[0x68, 0xce, 0x38, 0x80];
+
+/// Returns an Annex B access unit that has a slice of `slice_size` bytes
+/// (preceded by the SPS and PPS if `with_parameter_sets` is `true`).
+pub fn access_unit(is_idr: bool, with_parameter_sets: bool, slice_size: usize) -> Vec<u8> {
+    let mut access_unit = Vec::new();
+    if with_parameter_sets {
+        access_unit.extend_from_slice(&[0, 0, 0, 1]);
+        access_unit.extend_from_slice(SPS);
+        access_unit.extend_from_slice(&[0, 0, 0, 1]);
+        access_unit.extend_from_slice(PPS);
+    }
+    let (nal_unit_header, fill) = if is_idr { (0x65, 0x88) } else { (0x41, 0x9a) };
+    access_unit.extend_from_slice(&[0, 0, 0, 1, nal_unit_header]);
+    access_unit.resize(access_unit.len() + slice_size - 1, fill);
+    access_unit
+}
+
+/// Returns an ADTS frame (AAC LC, 48 kHz, stereo) that has `payload_size` bytes of raw data.
+pub fn adts_frame(payload_size: usize) -> Vec<u8> {
+    let frame_len = 7 + payload_size;
+    let sampling_frequency_index = 3; // 48 kHz
+    let channel_configuration = 2;
+    let mut frame = vec![
+        0xFF,
+        0xF1,
+        (1 << 6) | (sampling_frequency_index << 2) | (channel_configuration >> 2),
+        ((channel_configuration & 0b11) << 6) | ((frame_len >> 11) & 0b11) as u8,
+        (frame_len >> 3) as u8,
+        (((frame_len & 0b111) << 5) as u8) | 0b1_1111,
+        0xFC,
+    ];
+    frame.resize(frame_len, 0x21);
+    frame
+}
+
+// CRC-32/MPEG-2 used by PSI sections.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in bytes {
+        crc ^= u32::from(b) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn write_timestamp(buf: &mut Vec<u8>, prefix: u8, timestamp: u64) {
+    let t = timestamp & ((1 << 33) - 1);
+    buf.push((prefix << 4) | (((t >> 30) as u8 & 0b111) << 1) | 1);
+    buf.push((t >> 22) as u8);
+    buf.push((((t >> 15) as u8 & 0x7F) << 1) | 1);
+    buf.push((t >> 7) as u8);
+    buf.push(((t as u8 & 0x7F) << 1) | 1);
+}
+
+/// Builder of MPEG-2 TS byte sequences.
+///
+/// Timestamps are wrapped around at 33 bits (and PCRs at 33 bits of 90 kHz base).
+#[derive(Debug, Default)]
+pub struct TsBuilder {
+    bytes: Vec<u8>,
+    continuity_counters: HashMap<u16, u8>,
+}
+impl TsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes a PAT announcing `PMT_PID` and a PMT with `VIDEO_PID` (H.264) and `AUDIO_PID` (ADTS AAC).
+    pub fn psi(&mut self) -> &mut Self {
+        self.pat().pmt(&[
+            (VIDEO_PID, STREAM_TYPE_H264),
+            (AUDIO_PID, STREAM_TYPE_ADTS_AAC),
+        ])
+    }
+
+    /// Writes a PAT with a single program whose PMT is `PMT_PID`.
+    pub fn pat(&mut self) -> &mut Self {
+        let mut section = vec![0x00, 0xB0, 0x00, 0x00, 0x01, 0xC1, 0x00, 0x00];
+        section.extend_from_slice(&[0x00, 0x01]);
+        section.extend_from_slice(&[0xE0 | (PMT_PID >> 8) as u8, PMT_PID as u8]);
+        self.section(0, section)
+    }
+
+    /// Writes a PMT with the given `(pid, stream_type)` pairs (the first PID carries the PCR).
+    pub fn pmt(&mut self, streams: &[(u16, u8)]) -> &mut Self {
+        let pcr_pid = streams.first().map_or(0x1FFF, |s| s.0);
+        let mut section = vec![0x02, 0xB0, 0x00, 0x00, 0x01, 0xC1, 0x00, 0x00];
+        section.extend_from_slice(&[0xE0 | (pcr_pid >> 8) as u8, pcr_pid as u8, 0xF0, 0x00]);
+        for &(pid, stream_type) in streams {
+            section.extend_from_slice(&[stream_type, 0xE0 | (pid >> 8) as u8, pid as u8, 0xF0, 0]);
+        }
+        self.section(PMT_PID, section)
+    }
+
+    fn section(&mut self, pid: u16, mut section: Vec<u8>) -> &mut Self {
+        let section_len = section.len() - 3 + 4;
+        section[1] |= (section_len >> 8) as u8;
+        section[2] = section_len as u8;
+        let crc = crc32(&section);
+        section.extend_from_slice(&crc.to_be_bytes());
+
+        let mut payload = vec![0]; // pointer_field
+        payload.extend_from_slice(&section);
+        payload.resize(184, 0xFF);
+        self.packet(pid, true, None, &payload);
+        self
+    }
+
+    /// Writes a video PES packet (with a PCR 100 milliseconds before the DTS).
+    pub fn video(&mut self, pts: u64, dts: Option<u64>, access_unit: &[u8]) -> &mut Self {
+        let pcr = (dts.unwrap_or(pts) + (1 << 33) - 9000) * 300;
+        self.pes(VIDEO_PID, VIDEO_STREAM_ID, pts, dts, access_unit, Some(pcr))
+    }
+
+    /// Writes an audio PES packet.
+    pub fn audio(&mut self, pts: u64, frames: &[u8]) -> &mut Self {
+        self.pes(AUDIO_PID, AUDIO_STREAM_ID, pts, None, frames, None)
+    }
+
+    /// Writes a PES packet split into TS packets.
+    ///
+    /// The `PES_packet_length` is zero for video streams, as is common in TS.
+    pub fn pes(
+        &mut self,
+        pid: u16,
+        stream_id: u8,
+        pts: u64,
+        dts: Option<u64>,
+        data: &[u8],
+        pcr: Option<u64>,
+    ) -> &mut Self {
+        let mut header = Vec::new();
+        match dts {
+            None => {
+                header.extend_from_slice(&[0x80, 0x80, 5]);
+                write_timestamp(&mut header, 0b0010, pts);
+            }
+            Some(dts) => {
+                header.extend_from_slice(&[0x80, 0xC0, 10]);
+                write_timestamp(&mut header, 0b0011, pts);
+                write_timestamp(&mut header, 0b0001, dts);
+            }
+        }
+        let pes_packet_len = header.len() + data.len();
+        let pes_packet_len = if (0xE0..=0xEF).contains(&stream_id) || pes_packet_len > 0xFFFF {
+            0
+        } else {
+            pes_packet_len
+        };
+
+        let mut packet = vec![0, 0, 1, stream_id];
+        packet.extend_from_slice(&(pes_packet_len as u16).to_be_bytes());
+        packet.extend_from_slice(&header);
+        packet.extend_from_slice(data);
+
+        let mut rest = &packet[..];
+        let mut pcr = pcr;
+        let mut first = true;
+        while !rest.is_empty() {
+            let capacity = if pcr.is_some() { 184 - 8 } else { 184 };
+            let n = std::cmp::min(capacity, rest.len());
+            self.packet(pid, first, pcr.take(), &rest[..n]);
+            rest = &rest[n..];
+            first = false;
+        }
+        self
+    }
+
+    /// Writes a TS packet (the adaptation field is added for the PCR and for stuffing).
+    pub fn packet(&mut self, pid: u16, pusi: bool, pcr: Option<u64>, payload: &[u8]) -> &mut Self {
+        assert!(payload.len() <= 184);
+        let counter = self.continuity_counters.entry(pid).or_insert(0);
+        let continuity_counter = *counter;
+        *counter = (*counter + 1) & 0x0F;
+
+        let mut adaptation_field = Vec::new();
+        if pcr.is_some() || payload.len() < 184 {
+            let mut flags = 0;
+            let mut fields = Vec::new();
+            if let Some(pcr) = pcr {
+                flags |= 0x10;
+                let base = (pcr / 300) & ((1 << 33) - 1);
+                let extension = pcr % 300;
+                fields.extend_from_slice(&((base >> 1) as u32).to_be_bytes());
+                fields.push((((base & 1) as u8) << 7) | 0x7E | ((extension >> 8) as u8 & 1));
+                fields.push(extension as u8);
+            }
+            let len = 184 - 1 - payload.len();
+            adaptation_field.push(len as u8);
+            if len > 0 {
+                adaptation_field.push(flags);
+                adaptation_field.extend_from_slice(&fields);
+                adaptation_field.resize(1 + len, 0xFF);
+            }
+        }
+
+        let adaptation_field_control = if adaptation_field.is_empty() { 1 } else { 3 };
+        self.bytes.push(0x47);
+        self.bytes
+            .push((if pusi { 0x40 } else { 0 }) | ((pid >> 8) as u8 & 0x1F));
+        self.bytes.push(pid as u8);
+        self.bytes
+            .push((adaptation_field_control << 4) | continuity_counter);
+        self.bytes.extend_from_slice(&adaptation_field);
+        self.bytes.extend_from_slice(payload);
+        assert_eq!(self.bytes.len() % 188, 0);
+        self
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Options of `av_stream`.
+#[derive(Debug, Clone)]
+pub struct AvStream {
+    /// Number of video frames.
+    pub frames: u64,
+
+    /// Every `gop`-th frame is an IDR picture (`0` means that only the first one is).
+    pub gop: u64,
+
+    /// If `false`, no frame is an IDR picture.
+    pub has_idr: bool,
+
+    /// Number of AAC frames per PES packet (`0` means that the stream has no audio PES packets).
+    pub aac_frames_per_pes: u64,
+
+    /// PTS of the first video sample (wrapped around at 33 bits when written).
+    pub start_pts: u64,
+
+    /// Difference between the PTS of the first audio sample and `start_pts` in 90 kHz ticks.
+    pub audio_start_offset: i64,
+
+    /// If `true`, the frames of each GOP are coded in the IBBP order
+    /// (`start_pts` is then the DTS of the first frame, whose PTS is one frame later).
+    pub b_frames: bool,
+
+    /// If `true`, the video PES packets have no DTS (even if it differs from the PTS).
+    pub omit_dts: bool,
+}
+impl Default for AvStream {
+    fn default() -> Self {
+        AvStream {
+            frames: 150,
+            gop: 30,
+            has_idr: true,
+            aac_frames_per_pes: 2,
+            start_pts: START_PTS,
+            audio_start_offset: 0,
+            b_frames: false,
+            omit_dts: false,
+        }
+    }
+}
+impl AvStream {
+    /// Builds the stream (30 fps H.264 without B-frames and 48 kHz AAC interleaved by time).
+    pub fn build(&self) -> Vec<u8> {
+        let mut ts = TsBuilder::new();
+        ts.psi();
+        let mut audio_pts = (self.start_pts as i64 + self.audio_start_offset) as u64;
+        for i in 0..self.frames {
+            let dts = self.start_pts + i * FRAME_DURATION;
+            let pts = if self.b_frames {
+                let gop = if self.gop == 0 { self.frames } else { self.gop };
+                let gop_start = i - i % gop;
+                let gop_len = gop.min(self.frames - gop_start);
+                let display = ibbp_display_index(i % gop, gop_len);
+                self.start_pts + (gop_start + display + 1) * FRAME_DURATION
+            } else {
+                dts
+            };
+            while self.aac_frames_per_pes > 0 && audio_pts <= dts {
+                let mut frames = Vec::new();
+                for _ in 0..self.aac_frames_per_pes {
+                    frames.extend_from_slice(&adts_frame(64));
+                }
+                ts.audio(audio_pts, &frames);
+                audio_pts += AAC_FRAME_DURATION * self.aac_frames_per_pes;
+            }
+            let is_idr = self.has_idr && (i == 0 || (self.gop != 0 && i % self.gop == 0));
+            // The parameter sets of the first frame are needed to make the initialization segment
+            let access_unit = if is_idr {
+                access_unit(true, true, 1000)
+            } else {
+                access_unit(false, i == 0, 300)
+            };
+            ts.video(pts, Some(dts).filter(|_| !self.omit_dts), &access_unit);
+        }
+        ts.into_bytes()
+    }
+}
+
+/// Returns the display index of the `i`-th frame (in decode order) of a GOP of `len` frames coded as I, P, B, B, P, B, B, ...
+///
+/// The frames at the end of the GOP that do not fill a P, B, B group are coded as P frames.
+pub fn ibbp_display_index(i: u64, len: u64) -> u64 {
+    if i == 0 {
+        return 0;
+    }
+    let group = (i - 1) / 3;
+    if group * 3 + 3 >= len {
+        return i;
+    }
+    match (i - 1) % 3 {
+        0 => group * 3 + 3, // P
+        _ => i - 1,         // B
+    }
+}
+
+/// Returns the audio/video stream used as the fixture of the TS conversion tests (5 seconds, one IDR per second).
+pub fn av_stream() -> Vec<u8> {
+    AvStream::default().build()
+}
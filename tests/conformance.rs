@@ -0,0 +1,683 @@
+extern crate mse_fmp4;
+
+mod common;
+
+use common::assert_golden;
+use mse_fmp4::fmp4::{
+    apply_key_rotation, patch_duration, patch_duration_in, restamp_media_segment, splice,
+    split_into_segments, validate_segments, CencSampleEncryptionInformationGroupEntry, ClearLead,
+    FileTypeBox, InitializationSegment, KeyPeriod, MdatLayout, MediaSegment, Mp4Box,
+    ProtectionSystemSpecificHeaderBox, Sample, SampleEncryptionBox, SampleEncryptionEntry,
+    SampleEntry, SegmentIndexBox, SegmentIssue, Subsample, TrackBox, TrackExtendsBox,
+    TrackFragmentBox, TrackRestamp, TrackRunBox, WebVttSampleEntry,
+};
+use mse_fmp4::io::WriteTo;
+use mse_fmp4::isobmff::Boxes;
+use mse_fmp4::time::MediaTime;
+use std::io::Cursor;
+use std::time::Duration;
+
+// The same fixtures as `tests/golden.rs` (a video track and an audio track).
+const INIT: &[u8] = include_bytes!("golden/init.mp4");
+const MEDIA: &[u8] = include_bytes!("golden/media.m4s");
+
+fn init() -> InitializationSegment {
+    InitializationSegment::read_from(INIT).unwrap()
+}
+
+fn media() -> MediaSegment {
+    MediaSegment::read_from(MEDIA).unwrap()
+}
+
+// Returns the `n`-th segment of a continuous stream made by repeating the golden media segment.
+fn nth_media(n: u32) -> MediaSegment {
+    let mut segment = media();
+    let restamps = segment
+        .track_timings()
+        .unwrap()
+        .into_iter()
+        .map(|t| TrackRestamp {
+            track_id: t.track_id,
+            base_media_decode_time: Some(t.duration * u64::from(n)),
+            timescale: None,
+        })
+        .collect::<Vec<_>>();
+    restamp_media_segment(&mut segment, n + 1, &restamps).unwrap();
+    segment
+}
+
+#[test]
+fn golden_segments_conform() {
+    assert_eq!(validate_segments(&init(), &[media()]).unwrap(), []);
+    assert_eq!(media().to_bytes().unwrap(), MEDIA);
+}
+
+#[test]
+fn continuous_segments_conform() {
+    let segments = (0..3).map(nth_media).collect::<Vec<_>>();
+    assert_eq!(validate_segments(&init(), &segments).unwrap(), []);
+}
+
+#[test]
+fn mdat_layouts_conform() {
+    for (layout, name) in [
+        (MdatLayout::Single, "media_single_mdat.m4s"),
+        (MdatLayout::PerRun, "media_mdat_per_run.m4s"),
+    ] {
+        let mut segment = media();
+        segment.set_mdat_layout(layout).unwrap();
+        assert_eq!(validate_segments(&init(), &[segment.clone()]).unwrap(), []);
+        assert_golden(name, &segment.to_bytes().unwrap());
+    }
+}
+
+#[test]
+fn pushed_track_fragments_keep_sample_data() {
+    let sample_data = |segment: &MediaSegment| {
+        segment
+            .samples()
+            .unwrap()
+            .map(|s| s.unwrap())
+            .map(|s| (s.track_id, s.data.to_vec()))
+            .collect::<Vec<_>>()
+    };
+    for &layout in &[MdatLayout::Single, MdatLayout::PerRun, MdatLayout::PerTrack] {
+        let mut segment = media();
+        segment.set_mdat_layout(layout).unwrap();
+        let mut expected = sample_data(&segment);
+
+        add_text_fragment(&mut segment, 0, 1000);
+        let reread = MediaSegment::read_from(&segment.to_bytes().unwrap()).unwrap();
+        expected.push((3, b"\0\0\0\x08vtte".to_vec()));
+        assert_eq!(sample_data(&reread), expected);
+    }
+}
+
+#[test]
+fn tfdt_version_1_conforms() {
+    let mut segment = media();
+    segment.force_tfdt_version_1().unwrap();
+    assert_eq!(validate_segments(&init(), &[segment.clone()]).unwrap(), []);
+
+    let bytes = segment.to_bytes().unwrap();
+    assert_eq!(
+        bytes.len(),
+        MEDIA.len() + 4 * segment.moof_box.traf_boxes.len()
+    );
+    let reread = MediaSegment::read_from(&bytes).unwrap();
+    assert!(reread
+        .moof_box
+        .traf_boxes
+        .iter()
+        .all(|t| t.tfdt_box.force_version_1));
+    let original = media();
+    let samples = original.samples().unwrap().map(|s| s.unwrap().data);
+    assert!(samples.eq(reread.samples().unwrap().map(|s| s.unwrap().data)));
+}
+
+#[test]
+fn durations_per_track_use_trex_defaults() {
+    let init = init();
+    let segment = media();
+    let timings = segment.track_timings().unwrap();
+    let durations = segment.duration_per_track(&init).unwrap();
+    assert_eq!(durations.len(), timings.len());
+    // The tracks of the golden files are in the same order as their track fragments
+    for (i, (track_id, duration)) in durations.iter().enumerate() {
+        let timescale = init.moov_box.trak_boxes[i].mdia_box.mdhd_box.timescale;
+        assert_eq!(*track_id, timings[i].track_id);
+        assert_eq!(duration.value, timings[i].duration);
+        assert_eq!(duration.timescale, timescale);
+        let traf = &segment.moof_box.traf_boxes[i];
+        assert_eq!(traf.duration().unwrap(), timings[i].duration);
+    }
+
+    // The audio samples have no durations of their own
+    let mut init = init;
+    let mut segment = segment;
+    let traf = &mut segment.moof_box.traf_boxes[1];
+    let default_sample_duration = traf.tfhd_box.default_sample_duration.take().unwrap();
+    assert!(traf.duration().is_err());
+    for trex in &mut init.moov_box.mvex_box.trex_boxes {
+        trex.default_sample_duration = default_sample_duration;
+    }
+    assert_eq!(
+        segment.duration_per_track(&init).unwrap()[1].1.value,
+        timings[1].duration
+    );
+}
+
+#[test]
+fn file_type_brands_are_kept() {
+    let mut init = init();
+    init.ftyp_box = FileTypeBox {
+        major_brand: *b"iso6",
+        minor_version: 0,
+        compatible_brands: vec![*b"iso6", *b"dash"],
+    };
+    assert_eq!(validate_segments(&init, &[media()]).unwrap(), []);
+    let reread = InitializationSegment::read_from(&init.to_bytes().unwrap()).unwrap();
+    assert_eq!(reread.ftyp_box, init.ftyp_box);
+}
+
+#[test]
+fn clear_lead_is_unprotected() {
+    let mut segment = media();
+    let track_id = segment.moof_box.traf_boxes[0].tfhd_box.track_id();
+    let iv_entry = SampleEncryptionEntry {
+        initialization_vector: vec![7; 8],
+        subsamples: Vec::new(),
+    };
+    // An encryptor has encrypted all but the first (clear) sample
+    let old_moof_size = segment.moof_box.box_size().unwrap();
+    segment.moof_box.traf_boxes[0].senc_box = Some(SampleEncryptionBox {
+        use_subsamples: false,
+        entries: vec![iv_entry.clone()],
+    });
+    let delta = (segment.moof_box.box_size().unwrap() - old_moof_size) as i32;
+    for traf in &mut segment.moof_box.traf_boxes {
+        for trun in &mut traf.trun_boxes {
+            *trun.data_offset.as_mut().unwrap() += delta;
+        }
+    }
+    let mut key = CencSampleEncryptionInformationGroupEntry::unprotected();
+    key.is_protected = true;
+    key.per_sample_iv_size = 8;
+    key.key_id = [1; 16];
+    let key_clone = key.clone();
+    let provider = move |_, _| {
+        Ok(KeyPeriod {
+            range: 0..u64::MAX,
+            entry: key_clone.clone(),
+        })
+    };
+    let mut provider = ClearLead::new(&init(), Duration::from_millis(20), provider);
+    apply_key_rotation(&mut segment, &[track_id], &mut provider).unwrap();
+    assert_eq!(validate_segments(&init(), &[segment.clone()]).unwrap(), []);
+
+    let traf = &segment.moof_box.traf_boxes[0];
+    let sgpd = traf.sgpd_boxes.iter().find(|b| &b.grouping_type == b"seig");
+    let entries = sgpd
+        .unwrap()
+        .entries
+        .iter()
+        .map(|e| CencSampleEncryptionInformationGroupEntry::read_from(e).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        [
+            CencSampleEncryptionInformationGroupEntry::unprotected(),
+            key
+        ]
+    );
+    let sbgp = traf.sbgp_boxes.iter().find(|b| &b.grouping_type == b"seig");
+    let counts = sbgp.unwrap().entries.iter().map(|e| e.sample_count);
+    assert!(counts.eq([1, 1].iter().cloned()));
+    assert_eq!(
+        traf.senc_box.as_ref().unwrap().entries,
+        [SampleEncryptionEntry::default(), iv_entry]
+    );
+
+    let reread = MediaSegment::read_from(&segment.to_bytes().unwrap()).unwrap();
+    assert_eq!(reread.moof_box.traf_boxes[0].senc_box, traf.senc_box);
+    let original = media();
+    let samples = original.samples().unwrap().map(|s| s.unwrap().data);
+    assert!(samples.eq(reread.samples().unwrap().map(|s| s.unwrap().data)));
+}
+
+// Returns a video track fragment that has `sample_count` samples and the given sample encryption information.
+fn encrypted_traf(sample_count: usize, senc_box: SampleEncryptionBox) -> TrackFragmentBox {
+    let mut traf = TrackFragmentBox::new(true);
+    traf.trun_boxes.push(TrackRunBox {
+        data_offset: None,
+        first_sample_flags: None,
+        samples: vec![
+            Sample {
+                size: Some(16),
+                ..Sample::default()
+            };
+            sample_count
+        ],
+    });
+    traf.senc_box = Some(senc_box);
+    traf
+}
+
+fn traf_bytes(traf: &TrackFragmentBox) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    traf.write_box(&mut bytes).unwrap();
+    bytes
+}
+
+fn senc_entry(iv: &[u8], subsamples: &[(u16, u32)]) -> SampleEncryptionEntry {
+    SampleEncryptionEntry {
+        initialization_vector: iv.to_vec(),
+        subsamples: subsamples
+            .iter()
+            .map(|&(clear_bytes, protected_bytes)| Subsample {
+                clear_bytes,
+                protected_bytes,
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn sample_encryption_boxes_round_trip() {
+    for &iv_size in &[0, 8, 16] {
+        for &use_subsamples in &[false, true] {
+            let iv = (0..iv_size).collect::<Vec<u8>>();
+            let subsamples: &[(u16, u32)] = if use_subsamples {
+                &[(5, 11), (2, 0)]
+            } else {
+                &[]
+            };
+            let senc_box = SampleEncryptionBox {
+                use_subsamples,
+                entries: vec![
+                    senc_entry(&iv, subsamples),
+                    senc_entry(&iv, &subsamples[..subsamples.len() / 2]),
+                ],
+            };
+            let traf = encrypted_traf(2, senc_box.clone());
+            let bytes = traf_bytes(&traf);
+            let reread = TrackFragmentBox::read_with_iv_size(&bytes, iv_size).unwrap();
+            assert_eq!(reread.senc_box.as_ref(), Some(&senc_box), "{}", iv_size);
+            assert_eq!(traf_bytes(&reread), bytes);
+
+            // The size can be inferred unless the subsamples make it ambiguous (not the case here)
+            let reread = TrackFragmentBox::read_from(&bytes).unwrap();
+            assert_eq!(reread.senc_box, Some(senc_box));
+        }
+    }
+}
+
+#[test]
+fn ambiguous_iv_sizes_need_to_be_given() {
+    // Three samples with 8-byte IVs and no subsamples, which can also be read as
+    // samples with empty IVs (the first one has four subsamples)
+    let senc_box = SampleEncryptionBox {
+        use_subsamples: true,
+        entries: vec![
+            senc_entry(&[0, 4, 1, 1, 1, 1, 1, 1], &[]),
+            senc_entry(&[2; 8], &[]),
+            senc_entry(&[3, 3, 3, 3, 3, 3, 0, 0], &[]),
+        ],
+    };
+    let bytes = traf_bytes(&encrypted_traf(3, senc_box.clone()));
+    assert!(TrackFragmentBox::read_from(&bytes).is_err());
+    let reread = TrackFragmentBox::read_with_iv_size(&bytes, 8).unwrap();
+    assert_eq!(reread.senc_box, Some(senc_box));
+    let reread = TrackFragmentBox::read_with_iv_size(&bytes, 0).unwrap();
+    assert_eq!(reread.senc_box.unwrap().entries[0].subsamples.len(), 4);
+}
+
+#[test]
+fn malformed_sample_encryption_boxes_are_rejected() {
+    for &use_subsamples in &[false, true] {
+        let senc_box = SampleEncryptionBox {
+            use_subsamples,
+            entries: Vec::new(),
+        };
+        let mut bytes = traf_bytes(&encrypted_traf(2, senc_box));
+
+        // A 16-byte `senc` box claiming 0xFFFFFFFF samples
+        let senc_offset = bytes.windows(4).position(|w| w == b"senc").unwrap() - 4;
+        assert_eq!(&bytes[senc_offset..][..4], [0, 0, 0, 16]);
+        bytes[senc_offset + 12..][..4].copy_from_slice(&[0xFF; 4]);
+        assert!(TrackFragmentBox::read_from(&bytes).is_err());
+        assert!(TrackFragmentBox::read_with_iv_size(&bytes, 0).is_err());
+
+        // More entries than samples
+        bytes[senc_offset + 12..][..4].copy_from_slice(&[0, 0, 0, 3]);
+        assert!(TrackFragmentBox::read_with_iv_size(&bytes, 0).is_err());
+    }
+}
+
+#[test]
+fn pssh_boxes_are_kept() {
+    let key_ids = [[0x11; 16], [0x22; 16]];
+    let mut init = init();
+    init.moov_box.pssh_boxes = vec![
+        ProtectionSystemSpecificHeaderBox::common(&key_ids),
+        ProtectionSystemSpecificHeaderBox::widevine(&key_ids[..1], b"movie"),
+        ProtectionSystemSpecificHeaderBox::playready(&[], vec![1, 2, 3]),
+    ];
+    assert_eq!(validate_segments(&init, &[media()]).unwrap(), []);
+
+    let bytes = init.to_bytes().unwrap();
+    let reread = InitializationSegment::read_from(&bytes).unwrap();
+    assert_eq!(reread.moov_box.pssh_boxes, init.moov_box.pssh_boxes);
+    let mut widevine_data = vec![0x12, 0x10];
+    widevine_data.extend_from_slice(&key_ids[0]);
+    widevine_data.extend_from_slice(b"\x22\x05movie");
+    assert_eq!(reread.moov_box.pssh_boxes[1].data, widevine_data);
+    for track in reread.into_track_segments().unwrap() {
+        assert_eq!(track.moov_box.pssh_boxes, init.moov_box.pssh_boxes);
+    }
+}
+
+#[test]
+fn live_durations_are_patched() {
+    let mut init = init();
+    init.mark_live();
+    assert_eq!(validate_segments(&init, &[media()]).unwrap(), []);
+    let mut bytes = init.to_bytes().unwrap();
+    let live = InitializationSegment::read_from(&bytes).unwrap();
+    assert_eq!(live.moov_box.mvhd_box.duration, 0);
+    assert!(live.moov_box.mvex_box.mehd_box.is_none());
+
+    let duration = Duration::from_secs(3600);
+    patch_duration(&mut bytes, duration).unwrap();
+    assert_eq!(bytes.len(), init.to_bytes().unwrap().len());
+    let archived = InitializationSegment::read_from(&bytes).unwrap();
+    let movie_timescale = archived.moov_box.mvhd_box.timescale;
+    assert_eq!(
+        u64::from(archived.moov_box.mvhd_box.duration),
+        3600 * u64::from(movie_timescale)
+    );
+    for trak in &archived.moov_box.trak_boxes {
+        assert_eq!(
+            u64::from(trak.tkhd_box.duration),
+            3600 * u64::from(movie_timescale)
+        );
+        let mdhd = &trak.mdia_box.mdhd_box;
+        assert_eq!(u64::from(mdhd.duration), 3600 * u64::from(mdhd.timescale));
+    }
+
+    // The same segment at the head of a file
+    let mut file = init.to_bytes().unwrap();
+    file.extend_from_slice(MEDIA);
+    patch_duration_in(Cursor::new(&mut file[..]), duration).unwrap();
+    assert_eq!(&file[..bytes.len()], &bytes[..]);
+    assert_eq!(&file[bytes.len()..], MEDIA);
+}
+
+#[test]
+fn rotated_keys_are_signaled() {
+    let mut segment = media();
+    let track_id = segment.moof_box.traf_boxes[0].tfhd_box.track_id();
+    let duration = segment.track_timings().unwrap()[0].duration;
+    let period = duration / 2;
+    let mut provider = |_, decode_time: u64| {
+        let start = decode_time / period * period;
+        let mut entry = CencSampleEncryptionInformationGroupEntry::unprotected();
+        entry.is_protected = true;
+        entry.per_sample_iv_size = 8;
+        entry.key_id = [(start / period) as u8; 16];
+        Ok(KeyPeriod {
+            range: start..start + period,
+            entry,
+        })
+    };
+    apply_key_rotation(&mut segment, &[track_id], &mut provider).unwrap();
+    assert_eq!(validate_segments(&init(), &[segment.clone()]).unwrap(), []);
+
+    let reread = MediaSegment::read_from(&segment.to_bytes().unwrap()).unwrap();
+    let traf = &reread.moof_box.traf_boxes[0];
+    let sgpd = traf.sgpd_boxes.iter().find(|b| &b.grouping_type == b"seig");
+    let key_ids = sgpd
+        .unwrap()
+        .entries
+        .iter()
+        .map(|e| {
+            CencSampleEncryptionInformationGroupEntry::read_from(e)
+                .unwrap()
+                .key_id[0]
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(key_ids, [0, 1]);
+    let sbgp = traf.sbgp_boxes.iter().find(|b| &b.grouping_type == b"seig");
+    let sbgp = sbgp.unwrap();
+    assert_eq!(sbgp.entries.len(), 2);
+    let sample_count = traf
+        .trun_boxes
+        .iter()
+        .map(|t| t.samples.len())
+        .sum::<usize>();
+    let grouped = sbgp
+        .entries
+        .iter()
+        .map(|e| e.sample_count as usize)
+        .sum::<usize>();
+    assert_eq!(grouped, sample_count);
+
+    let original = media();
+    let samples = original.samples().unwrap().map(|s| s.unwrap().data);
+    assert!(samples.eq(reread.samples().unwrap().map(|s| s.unwrap().data)));
+}
+
+// Adds a WebVTT fragment that has an empty cue (`vtte`) of `duration` at `decode_time` to `segment`.
+fn add_text_fragment(segment: &mut MediaSegment, decode_time: u64, duration: u32) {
+    let mut traf = TrackFragmentBox::new_text();
+    traf.tfdt_box.base_media_decode_time = decode_time;
+    traf.trun_boxes.push(TrackRunBox {
+        data_offset: None,
+        first_sample_flags: None,
+        samples: vec![Sample {
+            duration: Some(duration),
+            size: Some(8),
+            flags: None,
+            composition_time_offset: None,
+        }],
+    });
+    segment
+        .push_track_fragment(traf, b"\0\0\0\x08vtte".to_vec())
+        .unwrap();
+}
+
+#[test]
+fn text_track_gaps_are_kept_by_split() {
+    let mut init = init();
+    let timescale = init.moov_box.trak_boxes[0].mdia_box.mdhd_box.timescale;
+    let mut track = TrackBox::new_text();
+    track.mdia_box.mdhd_box.timescale = timescale;
+    track
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries
+        .push(SampleEntry::WebVtt(WebVttSampleEntry::default()));
+    init.moov_box.trak_boxes.push(track);
+    init.moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new_text());
+
+    // The audio and video tracks skip a segment and the cues are sparse
+    let duration = media().track_timings().unwrap()[0].duration;
+    let mut first = nth_media(0);
+    add_text_fragment(&mut first, 0, 100);
+    let mut second = nth_media(2);
+    add_text_fragment(&mut second, 3 * duration, 100);
+    assert_eq!(validate_segments(&init, &[first.clone()]).unwrap(), []);
+    let samples = first.samples().unwrap().map(|s| s.unwrap().data.to_vec());
+    let original = media();
+    let expected = original
+        .samples()
+        .unwrap()
+        .map(|s| s.unwrap().data.to_vec());
+    assert!(samples.eq(expected.chain(Some(b"\0\0\0\x08vtte".to_vec()))));
+
+    let mut file = init.to_bytes().unwrap();
+    file.extend_from_slice(&first.to_bytes().unwrap());
+    file.extend_from_slice(&second.to_bytes().unwrap());
+    let split = split_into_segments(&file, Duration::from_secs(0)).unwrap();
+    assert_eq!(split.media_segments.len(), 2);
+    let segments = split
+        .media_segments
+        .iter()
+        .map(|s| MediaSegment::read_from(s).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        validate_segments(&init, &segments).unwrap(),
+        [SegmentIssue::Discontinuity {
+            sequence_number: 2,
+            track_id: 3,
+            expected: 100,
+            actual: 2 * duration
+        }]
+    );
+
+    // The text track is shifted as the video track closes the gap
+    let decode_times = segments[1]
+        .track_timings()
+        .unwrap()
+        .into_iter()
+        .map(|t| t.base_media_decode_time)
+        .collect::<Vec<_>>();
+    assert_eq!(decode_times[0], duration);
+    assert_eq!(decode_times[2], 2 * duration);
+}
+
+#[test]
+fn gap_segments_keep_decode_times_continuous() {
+    // The second segment of each track is lost (e.g., because of a signal loss)
+    let timings = media().track_timings().unwrap();
+    let trafs = timings
+        .iter()
+        .map(|t| TrackFragmentBox::new_gap(t.track_id == 1, t.duration, t.duration as u32))
+        .collect();
+    let gap = MediaSegment::new_gap(2, trafs);
+    assert!(gap.is_gap());
+
+    let bytes = gap.to_bytes().unwrap();
+    let types = Boxes::new(&bytes)
+        .map(|b| b.unwrap().box_type)
+        .collect::<Vec<_>>();
+    assert_eq!(types, [*b"moof"]);
+    let read = MediaSegment::read_from(&bytes).unwrap();
+    assert!(read.mdat_boxes.is_empty());
+    for (traf, timing) in read.moof_box.traf_boxes.iter().zip(&timings) {
+        assert!(traf.tfhd_box.duration_is_empty);
+        assert!(traf.trun_boxes.is_empty());
+        assert_eq!(traf.tfdt_box.base_media_decode_time, timing.duration);
+        assert_eq!(traf.duration().unwrap(), timing.duration);
+    }
+
+    let segments = [nth_media(0), gap, nth_media(2)];
+    assert_eq!(validate_segments(&init(), &segments).unwrap(), []);
+
+    // The gap is counted in the decode times of the following segment
+    let mut file = init().to_bytes().unwrap();
+    for segment in &segments {
+        file.extend_from_slice(&segment.to_bytes().unwrap());
+    }
+    let split = split_into_segments(&file, Duration::from_secs(0)).unwrap();
+    assert_eq!(split.media_segments.len(), 3);
+    for (i, bytes) in split.media_segments.iter().enumerate() {
+        let segment = MediaSegment::read_from(bytes).unwrap();
+        assert_eq!(segment.is_gap(), i == 1);
+        for (actual, expected) in segment.track_timings().unwrap().iter().zip(&timings) {
+            assert_eq!(actual.base_media_decode_time, i as u64 * expected.duration);
+        }
+    }
+}
+
+#[test]
+fn split_segments_index_earliest_presentation_times() {
+    // The video samples are presented 1000 ticks after they are decoded (e.g., because of B-frames)
+    let with_offsets = |n| {
+        let mut segment = nth_media(n);
+        for sample in &mut segment.moof_box.traf_boxes[0].trun_boxes[0].samples {
+            sample.composition_time_offset = Some(1000);
+        }
+        segment
+    };
+    let mut file = init().to_bytes().unwrap();
+    file.extend_from_slice(&with_offsets(0).to_bytes().unwrap());
+    file.extend_from_slice(&with_offsets(1).to_bytes().unwrap());
+    let split = split_into_segments(&file, Duration::from_secs(0)).unwrap();
+    assert_eq!(split.media_segments.len(), 2);
+
+    let duration = media().track_timings().unwrap()[0].duration;
+    for (i, segment) in split.media_segments.iter().enumerate() {
+        let sidx = SegmentIndexBox::read_from(segment).unwrap().unwrap();
+        let timing = &MediaSegment::read_from(segment)
+            .unwrap()
+            .track_timings()
+            .unwrap()[0];
+        assert_eq!(timing.base_media_decode_time, i as u64 * duration);
+        assert_eq!(
+            sidx.earliest_presentation_time,
+            timing.earliest_presentation_time as u64
+        );
+        assert_eq!(sidx.earliest_presentation_time, i as u64 * duration + 1000);
+    }
+}
+
+#[test]
+fn spliced_segments_conform() {
+    let main = (0..2).map(nth_media).collect::<Vec<_>>();
+    let ad = vec![media()];
+    let timescale = init().moov_box.trak_boxes[0].mdia_box.mdhd_box.timescale;
+    let at = main[1].track_timings().unwrap()[0].base_media_decode_time;
+    let spliced = splice(
+        &init(),
+        &main,
+        &init(),
+        &ad,
+        MediaTime::new(at, timescale),
+        None,
+    )
+    .unwrap();
+    let segments = spliced.into_iter().map(|s| s.segment).collect::<Vec<_>>();
+    assert_eq!(validate_segments(&init(), &segments).unwrap(), []);
+}
+
+#[test]
+fn broken_segments_are_reported() {
+    // Discontinuity and non-increasing sequence number
+    let segments = vec![nth_media(0), nth_media(2), nth_media(1)];
+    let issues = validate_segments(&init(), &segments).unwrap();
+    assert!(issues.contains(&SegmentIssue::SequenceNumberNotIncreasing {
+        sequence_number: 2,
+        previous: 3
+    }));
+    assert!(issues.iter().any(|i| matches!(
+        i,
+        SegmentIssue::Discontinuity {
+            sequence_number: 3,
+            track_id: 1,
+            ..
+        }
+    )));
+
+    // Sample data out of the `mdat` boxes
+    let mut segment = media();
+    segment.moof_box.traf_boxes[0].trun_boxes[0].data_offset = Some(1 << 20);
+    let issues = validate_segments(&init(), &[segment]).unwrap();
+    assert_eq!(
+        issues,
+        [SegmentIssue::InvalidSamples { sequence_number: 1 }]
+    );
+
+    // Video fragment without a leading sync sample
+    let mut segment = media();
+    let trun = &mut segment.moof_box.traf_boxes[0].trun_boxes[0];
+    trun.first_sample_flags
+        .as_mut()
+        .unwrap()
+        .sample_is_non_sync_sample = true;
+    let issues = validate_segments(&init(), &[segment]).unwrap();
+    assert_eq!(
+        issues,
+        [SegmentIssue::NoLeadingSyncSample {
+            sequence_number: 1,
+            track_id: 1
+        }]
+    );
+
+    // Track missing from the initialization segment
+    let mut init = init();
+    init.retain_tracks(&[1]);
+    let issues = validate_segments(&init, &[media()]).unwrap();
+    assert_eq!(
+        issues,
+        [SegmentIssue::UnknownTrack {
+            sequence_number: 1,
+            track_id: 2
+        }]
+    );
+}
@@ -0,0 +1,126 @@
+extern crate mse_fmp4;
+
+use mse_fmp4::fmp4::{
+    DecoderConfigDescriptor, DecoderSpecificInfo, Descriptor, EsDescriptor, SlConfigDescriptor,
+};
+
+fn es_descriptor(dec_specific_info: Vec<u8>) -> EsDescriptor {
+    EsDescriptor {
+        es_id: 0x1234,
+        stream_priority: 7,
+        dec_config_descr: DecoderConfigDescriptor {
+            object_type_indication: 0x40,
+            stream_type: 0x05,
+            up_stream: false,
+            buffer_size_db: 0x01_8000,
+            max_bitrate: 192_000,
+            avg_bitrate: 128_000,
+            dec_specific_info: Some(DecoderSpecificInfo {
+                data: dec_specific_info,
+            }),
+        },
+        sl_config_descr: SlConfigDescriptor::mp4(),
+    }
+}
+
+fn to_bytes<T: Descriptor>(x: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    x.write_descriptor(&mut bytes).unwrap();
+    assert_eq!(bytes.len() as u32, x.descriptor_size().unwrap());
+    bytes
+}
+
+#[test]
+fn es_descriptor_round_trips() {
+    // The size field of the decoder specific info takes one, two and three bytes
+    for &len in &[2, 200, 20_000] {
+        let descriptor = es_descriptor(vec![0x11; len]);
+        let bytes = to_bytes(&descriptor);
+
+        let read = EsDescriptor::read_from(&bytes).unwrap();
+        assert_eq!(read.es_id, 0x1234);
+        assert_eq!(read.stream_priority, 7);
+        let config = &read.dec_config_descr;
+        assert_eq!(config.object_type_indication, 0x40);
+        assert_eq!(config.stream_type, 0x05);
+        assert_eq!(config.buffer_size_db, 0x01_8000);
+        assert_eq!(config.max_bitrate, 192_000);
+        assert_eq!(config.avg_bitrate, 128_000);
+        assert_eq!(config.dec_specific_info.as_ref().unwrap().data.len(), len);
+        assert_eq!(read.sl_config_descr.predefined, 2);
+        assert_eq!(to_bytes(&read), bytes);
+    }
+}
+
+/// Returns a descriptor whose size field is always four bytes long (as some muxers write it).
+fn padded_descriptor(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let size = payload.len() as u32;
+    let mut bytes = vec![tag];
+    for i in (0..4).rev() {
+        let more = if i == 0 { 0 } else { 0x80 };
+        bytes.push(more | ((size >> (i * 7)) & 0x7F) as u8);
+    }
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+#[test]
+fn foreign_es_descriptor_is_read() {
+    let asc = [0x12, 0x10];
+    let mut config = vec![0x40, (0x05 << 2) | 1, 0x01, 0x80, 0x00];
+    config.extend_from_slice(&192_000u32.to_be_bytes());
+    config.extend_from_slice(&128_000u32.to_be_bytes());
+    config.extend(padded_descriptor(0x05, &asc));
+    config.extend(padded_descriptor(0x14, &[0xEE; 3])); // unknown (profile level indication index)
+
+    // stream_dependence_flag, URL_flag and OCRstreamFlag are set
+    let mut es = vec![
+        0x12,
+        0x34,
+        0xE0 | 7,
+        0x00,
+        0x01,
+        3,
+        b'a',
+        b'b',
+        b'c',
+        0x00,
+        0x02,
+    ];
+    es.extend(padded_descriptor(0x0E, &[0xDD; 4])); // unknown (IPI descriptor pointer)
+    es.extend(padded_descriptor(0x04, &config));
+    es.extend(padded_descriptor(0x06, &[0x02]));
+    let bytes = padded_descriptor(0x03, &es);
+
+    let read = EsDescriptor::read_from(&bytes).unwrap();
+    assert_eq!(read.es_id, 0x1234);
+    assert_eq!(read.stream_priority, 7);
+    let info = read.dec_config_descr.dec_specific_info.as_ref().unwrap();
+    assert_eq!(info.data, asc);
+
+    // The flags are dropped and the size fields are written in the minimal number of bytes
+    assert_eq!(to_bytes(&read), to_bytes(&es_descriptor(asc.to_vec())));
+}
+
+#[test]
+fn malformed_es_descriptors_are_rejected() {
+    let bytes = to_bytes(&es_descriptor(vec![0x12, 0x10]));
+    for len in 0..bytes.len() {
+        assert!(EsDescriptor::read_from(&bytes[..len]).is_err());
+    }
+
+    // Unexpected tag
+    let mut wrong_tag = bytes.clone();
+    wrong_tag[0] = 0x04;
+    assert!(EsDescriptor::read_from(&wrong_tag).is_err());
+
+    // Size field longer than four bytes
+    let mut long_size = vec![0x03, 0x80, 0x80, 0x80, 0x80, 0x00];
+    long_size.extend_from_slice(&bytes[2..]);
+    assert!(EsDescriptor::read_from(&long_size).is_err());
+
+    // No decoder config descriptor
+    let mut es = vec![0x00, 0x01, 0x00];
+    es.extend(padded_descriptor(0x06, &[0x02]));
+    assert!(EsDescriptor::read_from(&padded_descriptor(0x03, &es)).is_err());
+}
@@ -0,0 +1,177 @@
+extern crate mse_fmp4;
+
+use mse_fmp4::io::{write_with_digest, Digest, DigestWriter, Md5, Sha256};
+use std::io::Write;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    let mut digest = Sha256::new();
+    digest.update(bytes);
+    hex(&digest.finish())
+}
+
+fn md5(bytes: &[u8]) -> String {
+    let mut digest = Md5::new();
+    digest.update(bytes);
+    hex(&digest.finish())
+}
+
+// Inputs of `n` bytes around the block and padding boundaries (the padding needs another block from 56 bytes).
+fn boundary_input(n: usize) -> Vec<u8> {
+    (0..n).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn sha256_matches_nist_vectors() {
+    // NIST example values for FIPS 180-4 (including the long message of one million "a")
+    let vectors: &[(&[u8], &str)] = &[
+        (
+            b"",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            b"abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+        (
+            b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+            "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+        ),
+        (
+            &[b'a'; 1_000_000],
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+        ),
+    ];
+    for &(input, expected) in vectors {
+        assert_eq!(sha256(input), expected, "input length {}", input.len());
+    }
+}
+
+#[test]
+fn md5_matches_rfc1321_vectors() {
+    // RFC 1321, appendix A.5
+    let vectors: &[(&[u8], &str)] = &[
+        (b"", "d41d8cd98f00b204e9800998ecf8427e"),
+        (b"a", "0cc175b9c0f1b6a831c399e269772661"),
+        (b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+        (b"message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+        (
+            b"abcdefghijklmnopqrstuvwxyz",
+            "c3fcd3d76192e4007dfb496cca67e13b",
+        ),
+        (
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+            "d174ab98d277d9f5a5611c2c9f419d9f",
+        ),
+        (
+            b"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+            "57edf4a22be3c955ac49da2e2107b67a",
+        ),
+    ];
+    for &(input, expected) in vectors {
+        assert_eq!(md5(input), expected, "input length {}", input.len());
+    }
+}
+
+#[test]
+fn digests_handle_block_boundaries() {
+    let vectors = [
+        (
+            55,
+            "463eb28e72f82e0a96c0a4cc53690c571281131f672aa229e0d45ae59b598b59",
+            "6912ee65fff2d9f9ce2508cddf8bcda0",
+        ),
+        (
+            56,
+            "da2ae4d6b36748f2a318f23e7ab1dfdf45acdc9d049bd80e59de82a60895f562",
+            "51fdd1acda72405dfdfa03fcb85896d7",
+        ),
+        (
+            57,
+            "2fe741af801cc238602ac0ec6a7b0c3a8a87c7fc7d7f02a3fe03d1c12eac4d8f",
+            "5320ef4c17ef34a0cf2db763338d25eb",
+        ),
+        (
+            63,
+            "29af2686fd53374a36b0846694cc342177e428d1647515f078784d69cdb9e488",
+            "48a6295221902e8e0938f773a7185e72",
+        ),
+        (
+            64,
+            "fdeab9acf3710362bd2658cdc9a29e8f9c757fcf9811603a8c447cd1d9151108",
+            "b2d3f56bc197fd985d5965079b5e7148",
+        ),
+        (
+            65,
+            "4bfd2c8b6f1eec7a2afeb48b934ee4b2694182027e6d0fc075074f2fabb31781",
+            "8bd7053801c768420faf816fadba971c",
+        ),
+        (
+            119,
+            "da18797ed7c3a777f0847f429724a2d8cd5138e6ed2895c3fa1a6d39d18f7ec6",
+            "1c772251899a7ff007400b888d6b2042",
+        ),
+        (
+            120,
+            "f52b23db1fbb6ded89ef42a23ce0c8922c45f25c50b568a93bf1c075420bbb7c",
+            "b7ba1efc6022e9ed272f00b8831e26e6",
+        ),
+        (
+            128,
+            "471fb943aa23c511f6f72f8d1652d9c880cfa392ad80503120547703e56a2be5",
+            "37eff01866ba3f538421b30b7cbefcac",
+        ),
+        (
+            1000,
+            "4e4c294b331f7a2099a379bec34b9f9fc03dc46ab465d998f4d683da53487e6d",
+            "a24f1e3ef66950e1327f210e3997ba2c",
+        ),
+    ];
+    for &(n, expected_sha256, expected_md5) in &vectors {
+        let input = boundary_input(n);
+        assert_eq!(sha256(&input), expected_sha256, "input length {}", n);
+        assert_eq!(md5(&input), expected_md5, "input length {}", n);
+    }
+}
+
+#[test]
+fn incremental_updates_match_single_update() {
+    let input = boundary_input(1000);
+    for &chunk_size in &[1, 7, 55, 56, 63, 64, 65, 129] {
+        let mut sha256_digest = Sha256::new();
+        let mut md5_digest = Md5::new();
+        for chunk in input.chunks(chunk_size) {
+            sha256_digest.update(chunk);
+            md5_digest.update(chunk);
+        }
+        assert_eq!(hex(&sha256_digest.finish()), sha256(&input));
+        assert_eq!(hex(&md5_digest.finish()), md5(&input));
+    }
+}
+
+#[test]
+fn digest_writer_digests_written_bytes() {
+    let input = boundary_input(1000);
+    let mut writer = DigestWriter::new(Vec::new(), Sha256::new());
+    for chunk in input.chunks(100) {
+        writer.write_all(chunk).unwrap();
+    }
+    assert_eq!(writer.count(), 1000);
+    let (bytes, digest, count) = writer.finish();
+    assert_eq!(bytes, input);
+    assert_eq!(count, 1000);
+    assert_eq!(hex(&digest), sha256(&input));
+
+    let init = include_bytes!("golden/init.mp4");
+    let segment = mse_fmp4::fmp4::InitializationSegment::read_from(&init[..]).unwrap();
+    let (digest, count) = write_with_digest(&segment, Vec::new(), Md5::new()).unwrap();
+    assert_eq!(count, init.len() as u64);
+    assert_eq!(hex(&digest), md5(init));
+}
@@ -0,0 +1,123 @@
+#![cfg(feature = "ffi")]
+extern crate mse_fmp4;
+
+mod common;
+
+use mse_fmp4::ffi::*;
+use mse_fmp4::mpeg2_ts::{self, ConvertOptions};
+use std::ffi::CStr;
+use std::ptr;
+
+const HEADER: &str = include_str!("../include/mse_fmp4.h");
+
+fn empty_segment() -> MseFmp4Segment {
+    MseFmp4Segment {
+        kind: MseFmp4SegmentKind::Media,
+        data: ptr::null(),
+        len: 0,
+    }
+}
+
+#[test]
+fn converter_round_trip() {
+    let ts = common::av_stream();
+    let expected = mpeg2_ts::ts_bytes_to_fmp4(&ts, &ConvertOptions::default()).unwrap();
+
+    unsafe {
+        let c = mse_fmp4_converter_new();
+        assert!(!c.is_null());
+        assert!(mse_fmp4_converter_mime_type(c).is_null());
+        for chunk in ts.chunks(1000) {
+            assert_eq!(
+                mse_fmp4_converter_push(c, chunk.as_ptr(), chunk.len()),
+                MSE_FMP4_OK
+            );
+        }
+        assert_eq!(mse_fmp4_converter_finish(c), MSE_FMP4_OK);
+
+        let mime_type = CStr::from_ptr(mse_fmp4_converter_mime_type(c));
+        assert_eq!(mime_type.to_str().unwrap(), expected.mime_type);
+
+        let mut segment = empty_segment();
+        assert_eq!(mse_fmp4_converter_pull(c, &mut segment), 1);
+        assert_eq!(segment.kind, MseFmp4SegmentKind::Initialization);
+        let data = std::slice::from_raw_parts(segment.data, segment.len);
+        assert_eq!(data, &expected.initialization_segment[..]);
+
+        assert_eq!(mse_fmp4_converter_pull(c, &mut segment), 1);
+        assert_eq!(segment.kind, MseFmp4SegmentKind::Media);
+        let data = std::slice::from_raw_parts(segment.data, segment.len);
+        assert_eq!(data, &expected.media_segment[..]);
+
+        assert_eq!(mse_fmp4_converter_pull(c, &mut segment), 0);
+        mse_fmp4_converter_free(c);
+    }
+}
+
+#[test]
+fn invalid_arguments_and_inputs_are_reported() {
+    unsafe {
+        mse_fmp4_converter_free(ptr::null_mut());
+        assert_eq!(
+            mse_fmp4_converter_push(ptr::null_mut(), ptr::null(), 0),
+            MSE_FMP4_ERROR_INVALID_ARGUMENT
+        );
+
+        let c = mse_fmp4_converter_new();
+        assert_eq!(
+            mse_fmp4_converter_push(c, ptr::null(), 1),
+            MSE_FMP4_ERROR_INVALID_ARGUMENT
+        );
+        let mut segment = empty_segment();
+        assert_eq!(
+            mse_fmp4_converter_pull(c, &mut segment),
+            MSE_FMP4_ERROR_INVALID_ARGUMENT
+        );
+
+        let garbage = [0u8; 188];
+        assert_eq!(
+            mse_fmp4_converter_push(c, garbage.as_ptr(), garbage.len()),
+            MSE_FMP4_OK
+        );
+        assert_eq!(mse_fmp4_converter_finish(c), MSE_FMP4_ERROR_INVALID_INPUT);
+        assert!(mse_fmp4_converter_mime_type(c).is_null());
+        mse_fmp4_converter_free(c);
+    }
+}
+
+#[test]
+fn header_declares_every_function_and_error_code() {
+    for name in &[
+        "mse_fmp4_converter_new(",
+        "mse_fmp4_converter_free(",
+        "mse_fmp4_converter_push(",
+        "mse_fmp4_converter_finish(",
+        "mse_fmp4_converter_pull(",
+        "mse_fmp4_converter_mime_type(",
+    ] {
+        assert!(HEADER.contains(name), "{} is not declared", name);
+    }
+    for &(name, code) in &[
+        ("MSE_FMP4_OK", MSE_FMP4_OK),
+        (
+            "MSE_FMP4_ERROR_INVALID_ARGUMENT",
+            MSE_FMP4_ERROR_INVALID_ARGUMENT,
+        ),
+        ("MSE_FMP4_ERROR_INVALID_INPUT", MSE_FMP4_ERROR_INVALID_INPUT),
+        ("MSE_FMP4_ERROR_UNSUPPORTED", MSE_FMP4_ERROR_UNSUPPORTED),
+        ("MSE_FMP4_ERROR_OTHER", MSE_FMP4_ERROR_OTHER),
+        (
+            "MSE_FMP4_ERROR_SEGMENT_TOO_LARGE",
+            MSE_FMP4_ERROR_SEGMENT_TOO_LARGE,
+        ),
+        ("MSE_FMP4_ERROR_PANIC", MSE_FMP4_ERROR_PANIC),
+    ] {
+        let value = if code < 0 {
+            format!("(-{})", -code)
+        } else {
+            code.to_string()
+        };
+        let define = format!("#define {} {}\n", name, value);
+        assert!(HEADER.contains(&define), "{:?} is not declared", define);
+    }
+}
@@ -0,0 +1,36 @@
+extern crate mse_fmp4;
+
+use mse_fmp4::fourcc::{self, BoxType};
+use mse_fmp4::ErrorKind;
+
+#[test]
+fn box_types_round_trip_through_text() {
+    for s in &["moov", "url ", "avc1", "(c)x"] {
+        let box_type = s.parse::<BoxType>().unwrap();
+        assert_eq!(box_type.as_bytes(), s.as_bytes());
+        assert_eq!(box_type.to_string(), *s);
+    }
+    assert_eq!("mdat".parse::<BoxType>().unwrap(), BoxType(fourcc::MDAT));
+}
+
+#[test]
+fn box_types_must_be_four_ascii_characters() {
+    // "©day" is four characters but five bytes, and "éab" is three characters but four bytes
+    for s in &["", "moo", "moovs", "\u{a9}day", "\u{e9}ab", "\u{1F600}"] {
+        let e = s.parse::<BoxType>().unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput, "{:?}", s);
+    }
+}
+
+#[test]
+fn non_printable_box_type_bytes_are_escaped() {
+    // QuickTime uses 0xA9 (the copyright sign in Mac OS Roman) for metadata keys (e.g., `©day`)
+    let box_type = BoxType([0xa9, b'd', b'a', b'y']);
+    assert_eq!(box_type.to_string(), "\\xa9day");
+    assert_eq!(format!("{:?}", box_type), r#"BoxType("\\xa9day")"#);
+    assert_eq!(BoxType([0, 0, 0, 0x7f]).to_string(), "\\x00\\x00\\x00\\x7f");
+    assert_eq!(BoxType(*b"tab\t").to_string(), "tab\\x09");
+
+    // The escaped form is not parsed back
+    assert!(box_type.to_string().parse::<BoxType>().is_err());
+}
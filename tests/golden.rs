@@ -0,0 +1,1199 @@
+extern crate mse_fmp4;
+
+mod common;
+
+use common::assert_golden;
+use mse_fmp4::aac::{AacProfile, AdtsFrames, ChannelConfiguration, SamplingFrequency};
+use mse_fmp4::avc::AvcDecoderConfigurationRecord;
+use mse_fmp4::fmp4::{
+    apply_mse_fixes, demux_to_adts, mse_compatibility, rescale_track, restamp_media_segment,
+    retain_tracks, splice, write_self_initializing_file, AacSampleEntry, AvcConfigurationBox,
+    AvcSampleEntry, HdrBoxes, InitializationSegment, ItemExtent, ItemInfoBox, ItemInfoEntry,
+    ItemLocation, ItemLocationBox, MdatLayout, MediaDataBox, MediaSegment, MetaBox, Mp4Box,
+    Mpeg4EsDescriptorBox, MseFix, Sample, SampleEntry, SampleFlags, SampleTable, SamplingRateBox,
+    SegmentIndexBox, SegmentReference, SegmentTypeBox, TrackBox, TrackExtendsBox, TrackFragmentBox,
+    TrackFragmentHeaderBox, TrackRestamp, TrackRunBox,
+};
+use mse_fmp4::io::WriteTo;
+use mse_fmp4::isobmff::Boxes;
+use mse_fmp4::time::MediaTime;
+use mse_fmp4::ErrorKind;
+
+fn initialization_segment() -> InitializationSegment {
+    let mut segment = InitializationSegment::default();
+    segment.moov_box.mvhd_box.timescale = 90000;
+    segment.moov_box.mvhd_box.duration = 6006;
+
+    let mut track = TrackBox::new(true);
+    track.tkhd_box.width = 1920 << 16;
+    track.tkhd_box.height = 1080 << 16;
+    track.tkhd_box.duration = 6006;
+    track.mdia_box.mdhd_box.timescale = 90000;
+    track.mdia_box.mdhd_box.duration = 6006;
+    track
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries
+        .push(SampleEntry::Avc(AvcSampleEntry {
+            width: 1920,
+            height: 1080,
+            avcc_box: AvcConfigurationBox {
+                configuration: AvcDecoderConfigurationRecord {
+                    profile_idc: 66,
+                    constraint_set_flag: 0xe0,
+                    level_idc: 40,
+                    sequence_parameter_set: vec![
+                        0x67, 0x42, 0xe0, 0x28, 0xd0, 0x00, 0x00, 0x03, 0x02, 0x00, 0x00, 0x03,
+                        0x03, 0xa0, 0x1e, 0x00, 0x89, 0xf9, 0x50,
+                    ],
+                    picture_parameter_set: vec![0x68, 0xce, 0x3c, 0x80],
+                },
+            },
+            hdr_boxes: HdrBoxes::default(),
+        }));
+    segment.moov_box.trak_boxes.push(track);
+    segment
+        .moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new(true));
+
+    let mut track = TrackBox::new(false);
+    track.tkhd_box.duration = 3072;
+    track.mdia_box.mdhd_box.timescale = 48000;
+    track.mdia_box.mdhd_box.duration = 3072;
+    track
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries
+        .push(SampleEntry::Aac(AacSampleEntry {
+            esds_box: Mpeg4EsDescriptorBox {
+                profile: AacProfile::Lc,
+                frequency: SamplingFrequency::Hz48000,
+                channel_configuration: ChannelConfiguration::TwoChannels,
+                es_id: 0,
+                stream_priority: 0,
+            },
+            srat_box: None,
+        }));
+    segment.moov_box.trak_boxes.push(track);
+    segment
+        .moov_box
+        .mvex_box
+        .trex_boxes
+        .push(TrackExtendsBox::new(false));
+    segment
+}
+
+fn media_segment() -> MediaSegment {
+    let mut segment = MediaSegment::default();
+
+    let mut traf = TrackFragmentBox::new(true);
+    traf.tfhd_box.default_sample_flags = Some(SampleFlags {
+        is_leading: 0,
+        sample_depends_on: 1,
+        sample_is_depdended_on: 0,
+        sample_has_redundancy: 0,
+        sample_padding_value: 0,
+        sample_is_non_sync_sample: true,
+        sample_degradation_priority: 0,
+    });
+    traf.trun_boxes.push(TrackRunBox {
+        data_offset: Some(0),
+        first_sample_flags: Some(SampleFlags {
+            is_leading: 0,
+            sample_depends_on: 2,
+            sample_is_depdended_on: 0,
+            sample_has_redundancy: 0,
+            sample_padding_value: 0,
+            sample_is_non_sync_sample: false,
+            sample_degradation_priority: 0,
+        }),
+        samples: (0..2)
+            .map(|_| Sample {
+                duration: Some(3003),
+                size: Some(4),
+                flags: None,
+                composition_time_offset: Some(0),
+            })
+            .collect(),
+    });
+    segment.moof_box.traf_boxes.push(traf);
+
+    let mut traf = TrackFragmentBox::new(false);
+    traf.tfhd_box.default_sample_duration = Some(1024);
+    traf.trun_boxes.push(TrackRunBox {
+        data_offset: Some(0),
+        first_sample_flags: None,
+        samples: (0..3)
+            .map(|_| Sample {
+                duration: None,
+                size: Some(2),
+                flags: None,
+                composition_time_offset: None,
+            })
+            .collect(),
+    });
+    segment.moof_box.traf_boxes.push(traf);
+
+    let moof_size = segment.moof_box.box_size().unwrap() as i32;
+    segment.moof_box.traf_boxes[0].trun_boxes[0].data_offset = Some(moof_size + 8);
+    segment.moof_box.traf_boxes[1].trun_boxes[0].data_offset = Some(moof_size + 8 + 8 + 8);
+    segment.mdat_boxes.push(MediaDataBox {
+        data: vec![0, 0, 0, 0, 1, 1, 1, 1],
+    });
+    segment.mdat_boxes.push(MediaDataBox {
+        data: vec![2, 2, 3, 3, 4, 4],
+    });
+    segment
+}
+
+fn to_bytes<T: WriteTo>(x: &T) -> Vec<u8> {
+    x.to_bytes().unwrap()
+}
+
+#[test]
+fn initialization_segment_matches_golden_file() {
+    assert_golden("init.mp4", &to_bytes(&initialization_segment()));
+}
+
+#[test]
+fn media_segment_matches_golden_file() {
+    assert_golden("media.m4s", &to_bytes(&media_segment()));
+}
+
+// Accepts at most 5 bytes per call to exercise partial vectored writes.
+struct ChoppyWriter(Vec<u8>);
+impl std::io::Write for ChoppyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = std::cmp::min(buf.len(), 5);
+        self.0.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn vectored_write_matches_golden_file() {
+    let mut writer = ChoppyWriter(Vec::new());
+    media_segment().write_vectored_to(&mut writer).unwrap();
+    assert_golden("media.m4s", &writer.0);
+}
+
+fn be_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |n, &b| (n << 8) | u64::from(b))
+}
+
+#[test]
+fn self_initializing_file_has_valid_mfra() {
+    let init = to_bytes(&initialization_segment());
+    let mut file = Vec::new();
+    write_self_initializing_file(
+        &mut file,
+        &initialization_segment(),
+        &[media_segment(), media_segment()],
+    )
+    .unwrap();
+
+    let boxes = Boxes::new(&file).map(|b| b.unwrap()).collect::<Vec<_>>();
+    let types = boxes.iter().map(|b| &b.box_type).collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        [b"ftyp", b"moov", b"moof", b"mdat", b"mdat", b"moof", b"mdat", b"mdat", b"mfra"]
+    );
+
+    // The last 4 bytes (`mfro.size`) are the size of the `mfra` box.
+    let mfra = &boxes[8];
+    let mfro_size = be_uint(&file[file.len() - 4..]) as u32;
+    assert_eq!(mfro_size as usize, mfra.size());
+
+    // The first `tfra` has the entries of the video track pointing to the `moof` boxes.
+    let tfra = Boxes::new(mfra.payload).next().unwrap().unwrap();
+    assert_eq!(&tfra.box_type, b"tfra");
+    let payload = tfra.full_box().unwrap().payload;
+    assert_eq!(be_uint(&payload[8..12]), 2);
+    let first_moof_offset = be_uint(&payload[20..28]);
+    assert_eq!(first_moof_offset as usize, init.len());
+    assert_eq!(boxes[2].offset, init.len());
+}
+
+#[test]
+fn size_report_matches_serialized_segment() {
+    let segment = media_segment();
+    let report = segment.size_report(&initialization_segment()).unwrap();
+    assert_eq!(report.size as usize, to_bytes(&segment).len());
+    assert_eq!(report.duration, std::time::Duration::from_nanos(66_733_333));
+    assert_eq!(report.tracks[0].size, 8);
+    assert_eq!(report.tracks[1].size, 6);
+    assert_eq!(report.tracks[0].bitrate, 8 * 8 * 90000 / 6006);
+}
+
+#[test]
+fn segment_index_round_trips() {
+    let reference = SegmentReference {
+        reference_type: false,
+        referenced_size: 1234,
+        subsegment_duration: 180_000,
+        starts_with_sap: true,
+        sap_type: 1,
+        sap_delta_time: 0,
+    };
+    let sidx = SegmentIndexBox {
+        reference_id: 1,
+        timescale: 90000,
+        earliest_presentation_time: 1 << 33,
+        first_offset: 0,
+        references: vec![reference.clone(), reference],
+    };
+    let mut bytes = Vec::new();
+    SegmentTypeBox::default().write_box(&mut bytes).unwrap();
+    sidx.write_box(&mut bytes).unwrap();
+
+    let styp = SegmentTypeBox::read_from(&bytes).unwrap().unwrap();
+    assert_eq!(styp.compatible_brands, [*b"msdh", *b"msix"]);
+    let read = SegmentIndexBox::read_from(&bytes).unwrap().unwrap();
+    assert_eq!(
+        read.earliest_presentation_time,
+        sidx.earliest_presentation_time
+    );
+    assert_eq!(read.references, sidx.references);
+}
+
+#[test]
+fn sync_samples_point_into_serialized_segment() {
+    let segment = media_segment();
+    let bytes = to_bytes(&segment);
+
+    let keyframes = segment.sync_samples(1).unwrap();
+    assert_eq!(keyframes.len(), 1);
+    assert_eq!(keyframes[0].sample_index, 0);
+    assert_eq!(keyframes[0].presentation_time, 0);
+    let start = keyframes[0].offset as usize;
+    assert_eq!(
+        &bytes[start..start + keyframes[0].size as usize],
+        [0, 0, 0, 0]
+    );
+
+    let audio = segment.sync_samples(2).unwrap();
+    assert_eq!(audio.len(), 3);
+    assert_eq!(audio[2].decode_time, 2048);
+    let start = audio[2].offset as usize;
+    assert_eq!(&bytes[start..start + audio[2].size as usize], [4, 4]);
+}
+
+#[test]
+fn mse_fixes_are_applied() {
+    assert_eq!(
+        mse_compatibility(&to_bytes(&initialization_segment())).unwrap(),
+        []
+    );
+
+    let mut init = initialization_segment();
+    init.moov_box.mvex_box.trex_boxes.pop();
+    let mut bytes = to_bytes(&init);
+    MediaDataBox { data: vec![0; 4] }
+        .write_box(&mut bytes)
+        .unwrap();
+
+    let fixes = mse_compatibility(&bytes).unwrap();
+    assert_eq!(
+        fixes,
+        [
+            MseFix::RemoveBox(mse_fmp4::fourcc::BoxType(*b"mdat")),
+            MseFix::AddTrackExtends { track_id: 2 },
+        ]
+    );
+    let fixed = apply_mse_fixes(&bytes).unwrap();
+    assert_eq!(mse_compatibility(&fixed).unwrap(), []);
+    assert_eq!(fixed, to_bytes(&initialization_segment()));
+}
+
+#[test]
+fn segments_are_demultiplexed_per_track() {
+    let inits = initialization_segment().into_track_segments().unwrap();
+    assert_eq!(inits.len(), 2);
+    assert_eq!(inits[1].mime_type(), r#"audio/mp4; codecs="mp4a.40.2""#);
+
+    let segments = media_segment().into_track_segments().unwrap();
+    assert_eq!(segments.len(), 2);
+    for (segment, (track_id, data)) in segments.iter().zip(vec![
+        (1, vec![0, 0, 0, 0, 1, 1, 1, 1]),
+        (2, vec![2, 2, 3, 3, 4, 4]),
+    ]) {
+        assert_eq!(segment.moof_box.traf_boxes.len(), 1);
+        assert_eq!(segment.mdat_boxes.len(), 1);
+        assert_eq!(segment.mdat_boxes[0].data, data);
+
+        let bytes = to_bytes(segment);
+        let first = &segment.sync_samples(track_id).unwrap()[0];
+        let start = first.offset as usize;
+        assert_eq!(
+            &bytes[start..start + first.size as usize],
+            &data[..first.size as usize]
+        );
+    }
+}
+
+#[test]
+fn retained_tracks_keep_their_samples() {
+    let mut file = to_bytes(&initialization_segment());
+    file.extend_from_slice(&to_bytes(&media_segment()));
+
+    let retained = retain_tracks(&file, &[2]).unwrap();
+    let init = InitializationSegment::read_from(&retained).unwrap();
+    assert_eq!(init.codecs(), ["mp4a.40.2"]);
+
+    let moof = mse_fmp4::isobmff::find_box(&retained, mse_fmp4::fourcc::MOOF)
+        .unwrap()
+        .unwrap();
+    let traf = TrackFragmentBox::read_from(moof.payload).unwrap();
+    assert_eq!(traf.trun_boxes[0].samples.len(), 3);
+    let start = moof.offset + traf.trun_boxes[0].data_offset.unwrap() as usize;
+    assert_eq!(&retained[start..start + 6], [2, 2, 3, 3, 4, 4]);
+}
+
+#[test]
+fn demuxed_adts_frames_have_sample_payloads() {
+    let adts = demux_to_adts(&initialization_segment(), &media_segment(), 2).unwrap();
+    let payloads = AdtsFrames::new(&adts)
+        .map(|frame| frame.unwrap().payload.to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(payloads, [[2, 2], [3, 3], [4, 4]]);
+}
+
+#[test]
+fn rescaled_track_keeps_its_timing() {
+    let mut init = initialization_segment();
+    let mut segments = vec![media_segment()];
+    rescale_track(&mut init, &mut segments, 1, 30000).unwrap();
+    assert_eq!(
+        init.moov_box.trak_boxes[0].mdia_box.mdhd_box.timescale,
+        30000
+    );
+    assert_eq!(init.moov_box.trak_boxes[0].mdia_box.mdhd_box.duration, 2002);
+
+    let samples = segments[0]
+        .samples()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(samples[1].decode_time, 1001);
+    assert_eq!(samples[1].data, [1, 1, 1, 1]);
+    assert_eq!(samples[4].decode_time, 2048); // the audio track is untouched
+}
+
+#[test]
+fn rescaled_track_keeps_decode_times_across_segments() {
+    let mut init = initialization_segment();
+    init.moov_box.trak_boxes[0].edts_box.elst_box.media_time = 3003;
+    let mut second = media_segment();
+    let restamps = [(1, 6006), (2, 3072)].map(|(track_id, t)| TrackRestamp {
+        track_id,
+        base_media_decode_time: Some(t),
+        timescale: None,
+    });
+    restamp_media_segment(&mut second, 2, &restamps).unwrap();
+    let mut segments = vec![media_segment(), second];
+
+    rescale_track(&mut init, &mut segments, 1, 1000).unwrap();
+    assert_eq!(init.moov_box.trak_boxes[0].edts_box.elst_box.media_time, 33);
+
+    // The decode times are rounded as absolute values (6006 / 90 = 66.7 and 9009 / 90 = 100.1)
+    let mut decode_times = Vec::new();
+    for segment in &segments {
+        let segment = MediaSegment::read_from(&to_bytes(segment)).unwrap();
+        let samples = segment
+            .samples()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let video = samples.iter().filter(|s| s.track_id == 1);
+        for (sample, data) in video.zip(&[[0; 4], [1; 4]]) {
+            assert_eq!(sample.data, data);
+            decode_times.push(sample.decode_time);
+        }
+        assert_eq!(samples[2].data, [2, 2]);
+    }
+    assert_eq!(decode_times, [0, 33, 67, 100]);
+
+    // Large media times are scaled without overflowing, and rejected if they do not fit in the `elst` box
+    let mut init = initialization_segment();
+    init.moov_box.trak_boxes[0].edts_box.elst_box.media_time = i32::MAX;
+    rescale_track(&mut init, &mut [], 1, 1000).unwrap();
+    assert_eq!(
+        init.moov_box.trak_boxes[0].edts_box.elst_box.media_time,
+        23_860_929
+    );
+    assert!(rescale_track(&mut init, &mut [], 1, 90000 * 1000).is_err());
+}
+
+#[test]
+fn spliced_ad_shifts_following_segments() {
+    let init = initialization_segment();
+    let mut second = media_segment();
+    let restamps = [(1, 6006), (2, 3072)].map(|(track_id, t)| TrackRestamp {
+        track_id,
+        base_media_decode_time: Some(t),
+        timescale: None,
+    });
+    restamp_media_segment(&mut second, 2, &restamps).unwrap();
+    let main = vec![media_segment(), second];
+
+    let timescale = init.moov_box.trak_boxes[0].mdia_box.mdhd_box.timescale;
+    let at = MediaTime::new(6006, timescale);
+    let spliced = splice(
+        &init,
+        &main,
+        &init,
+        &[media_segment()],
+        at,
+        Some("urn:example:ad"),
+    )
+    .unwrap();
+    assert_eq!(spliced.len(), 3);
+    for (i, s) in spliced.iter().enumerate() {
+        assert_eq!(s.segment.moof_box.mfhd_box.sequence_number, i as u32 + 1);
+    }
+    let decode_times = |i: usize| -> Vec<u64> {
+        spliced[i]
+            .segment
+            .track_timings()
+            .unwrap()
+            .iter()
+            .map(|t| t.base_media_decode_time)
+            .collect()
+    };
+    assert_eq!(decode_times(1), [6006, 3072]);
+    assert_eq!(decode_times(2), [12012, 6144]);
+
+    assert_eq!(spliced[1].emsg_boxes[0].value, "out");
+    assert_eq!(spliced[1].emsg_boxes[0].event_duration, 6006);
+    assert_eq!(spliced[2].emsg_boxes[0].value, "in");
+    assert_eq!(spliced[2].emsg_boxes[0].presentation_time, 12012);
+
+    let bytes = to_bytes(&spliced[1]);
+    let types = Boxes::new(&bytes)
+        .map(|b| b.unwrap().box_type)
+        .collect::<Vec<_>>();
+    assert_eq!(types[..2], [*b"emsg", *b"moof"]);
+}
+
+#[test]
+fn mdat_layout_keeps_sample_data() {
+    let payloads = |segment: &MediaSegment| -> Vec<Vec<u8>> {
+        segment
+            .samples()
+            .unwrap()
+            .map(|s| s.unwrap().data.to_vec())
+            .collect()
+    };
+    let expected = payloads(&media_segment());
+    for (layout, mdat_count) in [
+        (MdatLayout::Single, 1),
+        (MdatLayout::PerRun, 2),
+        (MdatLayout::PerTrack, 2),
+    ] {
+        let mut segment = media_segment();
+        segment.set_mdat_layout(layout).unwrap();
+        assert_eq!(segment.mdat_boxes.len(), mdat_count);
+        assert_eq!(payloads(&segment), expected);
+
+        let bytes = to_bytes(&segment);
+        for sample in segment.samples().unwrap() {
+            let sample = sample.unwrap();
+            let start = sample.offset as usize;
+            assert_eq!(&bytes[start..start + sample.data.len()], sample.data);
+        }
+    }
+}
+
+#[test]
+fn large_mdat_boxes_use_the_64_bit_size_field() {
+    let mdat_header = |payload_size: u64| {
+        let mut header = Vec::new();
+        MediaDataBox::write_large_box_header(&mut header, payload_size).unwrap();
+        header
+    };
+    let largest = u64::from(u32::MAX) - 8;
+    assert_eq!(
+        MediaDataBox::large_box_size_for(largest),
+        u64::from(u32::MAX)
+    );
+    assert_eq!(
+        mdat_header(largest),
+        [0xff, 0xff, 0xff, 0xff, b'm', b'd', b'a', b't']
+    );
+
+    // `size` is 1 and the `largesize` includes the 16-byte header
+    let payload_size = 5 << 30;
+    assert_eq!(MediaDataBox::large_box_size_for(largest + 1), largest + 17);
+    assert_eq!(
+        MediaDataBox::large_box_size_for(payload_size),
+        payload_size + 16
+    );
+    let header = mdat_header(payload_size);
+    assert_eq!(header[..8], [0, 0, 0, 1, b'm', b'd', b'a', b't']);
+    assert_eq!(be_uint(&header[8..]), payload_size + 16);
+
+    // Segments with ordinary `mdat` boxes are written as by `WriteTo`
+    let segment = media_segment();
+    assert!(!segment.mdat_boxes[0].needs_large_size());
+    let mut bytes = Vec::new();
+    segment.write_large_to(&mut bytes).unwrap();
+    assert_eq!(bytes, to_bytes(&segment));
+    let mut bytes = Vec::new();
+    segment.mdat_boxes[0].write_large_box(&mut bytes).unwrap();
+    assert_eq!(bytes, box_bytes(&segment.mdat_boxes[0]));
+}
+
+struct HugeBox;
+impl Mp4Box for HugeBox {
+    const BOX_TYPE: [u8; 4] = *b"huge";
+
+    fn box_payload_size(&self) -> mse_fmp4::Result<u32> {
+        Ok(u32::MAX - 4)
+    }
+    fn write_box_payload<W: std::io::Write>(&self, _writer: W) -> mse_fmp4::Result<()> {
+        unreachable!()
+    }
+}
+
+#[test]
+fn box_size_overflows_are_reported() {
+    let e = HugeBox.box_size().unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::SegmentTooLarge);
+    let e = HugeBox.write_box(Vec::new()).unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::SegmentTooLarge);
+
+    let flags = 0x00_0301; // `data_offset`, `sample_duration` and `sample_size`
+    assert!(TrackRunBox::box_size_for(1000, flags).is_ok());
+    let e = TrackRunBox::box_size_for(usize::MAX / 4, flags).unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::SegmentTooLarge);
+}
+
+#[test]
+fn samples_resolve_data_offsets_of_multiple_runs() {
+    let flags = |sample_is_non_sync_sample| SampleFlags {
+        is_leading: 0,
+        sample_depends_on: 0,
+        sample_is_depdended_on: 0,
+        sample_has_redundancy: 0,
+        sample_padding_value: 0,
+        sample_is_non_sync_sample,
+        sample_degradation_priority: 0,
+    };
+    let sample = |size, composition_time_offset| Sample {
+        duration: None,
+        size: Some(size),
+        flags: None,
+        composition_time_offset,
+    };
+
+    // The video track fragment addresses its data from an explicit base data offset
+    // (the second run continues where the first one ends)
+    let mut video = TrackFragmentBox::new(true);
+    video.tfdt_box.base_media_decode_time = 100;
+    video.tfhd_box.default_base_is_moof = false;
+    video.tfhd_box.base_data_offset = Some(0);
+    video.tfhd_box.default_sample_duration = Some(10);
+    video.tfhd_box.default_sample_flags = Some(flags(true));
+    video.trun_boxes.push(TrackRunBox {
+        data_offset: Some(2),
+        first_sample_flags: Some(flags(false)),
+        samples: vec![sample(3, Some(20)), sample(3, Some(0))],
+    });
+    video.trun_boxes.push(TrackRunBox {
+        data_offset: None,
+        first_sample_flags: None,
+        samples: vec![sample(2, Some(-10))],
+    });
+
+    // The runs of the audio track fragment are stored in the reverse order
+    let mut audio = TrackFragmentBox::new(false);
+    audio.tfdt_box.base_media_decode_time = 50;
+    audio.tfhd_box.default_sample_duration = Some(1024);
+    for _ in 0..2 {
+        audio.trun_boxes.push(TrackRunBox {
+            data_offset: Some(0),
+            first_sample_flags: None,
+            samples: vec![sample(2, None)],
+        });
+    }
+
+    let mut segment = MediaSegment::default();
+    segment.moof_box.traf_boxes = vec![video, audio];
+    segment.mdat_boxes.push(MediaDataBox {
+        data: vec![0xff, 0xff, 1, 1, 1, 2, 2, 2, 3, 3],
+    });
+    segment.mdat_boxes.push(MediaDataBox {
+        data: vec![4, 4, 5, 5],
+    });
+    let moof_size = u64::from(segment.moof_box.box_size().unwrap());
+    let first_mdat = moof_size + 8;
+    let second_mdat = first_mdat + 10 + 8;
+    let trafs = &mut segment.moof_box.traf_boxes;
+    trafs[0].tfhd_box.base_data_offset = Some(first_mdat);
+    trafs[1].trun_boxes[0].data_offset = Some(second_mdat as i32 + 2);
+    trafs[1].trun_boxes[1].data_offset = Some(second_mdat as i32);
+
+    let summarize = |segment: &MediaSegment| {
+        segment
+            .samples()
+            .unwrap()
+            .map(|s| {
+                let s = s.unwrap();
+                let times = (s.decode_time, s.presentation_time);
+                (
+                    s.track_id,
+                    s.sample_index,
+                    times,
+                    s.is_sync,
+                    s.offset,
+                    s.data.to_vec(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let expected = vec![
+        (1, 0, (100, 120), true, first_mdat + 2, vec![1, 1, 1]),
+        (1, 1, (110, 110), false, first_mdat + 5, vec![2, 2, 2]),
+        (1, 2, (120, 110), false, first_mdat + 8, vec![3, 3]),
+        (2, 0, (50, 50), true, second_mdat + 2, vec![5, 5]),
+        (2, 1, (1074, 1074), true, second_mdat, vec![4, 4]),
+    ];
+    assert_eq!(summarize(&segment), expected);
+    let keyframes = segment.sync_samples(1).unwrap();
+    assert_eq!(keyframes.len(), 1);
+    assert_eq!(keyframes[0].offset, first_mdat + 2);
+
+    // A `base_data_offset` is relative to the start of the file, which may precede the `moof` box
+    let mut styp = Vec::new();
+    SegmentTypeBox::default().write_box(&mut styp).unwrap();
+    let mut shifted = segment.clone();
+    shifted.moof_box.traf_boxes[0].tfhd_box.base_data_offset = Some(first_mdat + styp.len() as u64);
+    let mut bytes = styp;
+    bytes.extend_from_slice(&to_bytes(&shifted));
+    let read = MediaSegment::read_from(&bytes).unwrap();
+    assert_eq!(
+        read.moof_box.traf_boxes[0].tfhd_box.base_data_offset,
+        Some(first_mdat)
+    );
+    assert_eq!(summarize(&read), expected);
+}
+
+#[test]
+fn sample_table_writes_same_trun_box() {
+    let trun = &media_segment().moof_box.traf_boxes[0].trun_boxes[0];
+    let table = SampleTable::from_samples(&trun.samples).unwrap();
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.data_size(), 8);
+
+    let mut bytes = Vec::new();
+    table
+        .write_trun_box(&mut bytes, trun.data_offset, trun.first_sample_flags)
+        .unwrap();
+    let mut expected = Vec::new();
+    trun.write_box(&mut expected).unwrap();
+    assert_eq!(bytes, expected);
+    assert_eq!(
+        table.trun_box_size(true, true).unwrap() as usize,
+        bytes.len()
+    );
+    assert_eq!(table.into_samples(), trun.samples);
+}
+
+#[test]
+fn isolated_trun_and_tfhd_boxes_are_parsed() {
+    let traf = &media_segment().moof_box.traf_boxes[1];
+    let mut tfhd = Vec::new();
+    traf.tfhd_box.write_box(&mut tfhd).unwrap();
+    let parsed = TrackFragmentHeaderBox::read_from(&tfhd).unwrap();
+    assert_eq!(parsed.track_id(), 2);
+    assert_eq!(parsed.default_sample_duration, Some(1024));
+    assert!(parsed.default_base_is_moof);
+
+    let mut trun = Vec::new();
+    traf.trun_boxes[0].write_box(&mut trun).unwrap();
+    let parsed = TrackRunBox::read_from(&trun).unwrap();
+    assert_eq!(parsed.data_offset, traf.trun_boxes[0].data_offset);
+    assert_eq!(parsed.samples, traf.trun_boxes[0].samples);
+
+    assert!(TrackRunBox::read_from(&tfhd).is_err());
+}
+
+fn box_bytes<T: Mp4Box>(x: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    x.write_box(&mut bytes).unwrap();
+    bytes
+}
+
+fn meta_box() -> MetaBox {
+    let mut meta = MetaBox::new(mse_fmp4::fourcc::MDIR);
+    meta.iinf_box = Some(ItemInfoBox {
+        entries: vec![
+            ItemInfoEntry {
+                item_id: 1,
+                item_protection_index: 0,
+                item_type: mse_fmp4::fourcc::MIME,
+                item_name: "cover".to_owned(),
+                content_type: Some("image/jpeg".to_owned()),
+            },
+            ItemInfoEntry {
+                item_id: 0x1_0000,
+                item_protection_index: 1,
+                item_type: *b"Exif",
+                item_name: "exif".to_owned(),
+                content_type: None,
+            },
+        ],
+    });
+    meta.iloc_box = Some(ItemLocationBox {
+        items: vec![
+            ItemLocation {
+                item_id: 1,
+                construction_method: 0,
+                data_reference_index: 0,
+                base_offset: 100,
+                extents: vec![ItemExtent {
+                    offset: 0,
+                    length: 1000,
+                }],
+            },
+            ItemLocation {
+                item_id: 0x1_0000,
+                construction_method: 1,
+                data_reference_index: 0,
+                base_offset: 0,
+                extents: vec![
+                    ItemExtent {
+                        offset: 0,
+                        length: 10,
+                    },
+                    ItemExtent {
+                        offset: 1 << 32,
+                        length: 20,
+                    },
+                ],
+            },
+        ],
+    });
+    meta
+}
+
+// Returns `init` serialized with `meta` appended to its `moov` box.
+fn with_meta_bytes(init: &InitializationSegment, meta: &[u8]) -> Vec<u8> {
+    let mut bytes = to_bytes(init);
+    let moov = mse_fmp4::isobmff::find_box(&bytes, mse_fmp4::fourcc::MOOV)
+        .unwrap()
+        .unwrap();
+    let (offset, size) = (moov.offset, moov.size());
+    assert_eq!(offset + size, bytes.len());
+    bytes.extend_from_slice(meta);
+    let new_size = (size + meta.len()) as u32;
+    bytes[offset..offset + 4].copy_from_slice(&new_size.to_be_bytes());
+    bytes
+}
+
+#[test]
+fn meta_box_round_trips() {
+    let mut init = initialization_segment();
+    init.moov_box.meta_box = Some(meta_box());
+    let bytes = to_bytes(&init);
+
+    let read = InitializationSegment::read_from(&bytes).unwrap();
+    assert_eq!(to_bytes(&read), bytes);
+    let meta = read.moov_box.meta_box.unwrap();
+    assert_eq!(meta.handler_type(), mse_fmp4::fourcc::MDIR);
+    assert!(!meta.has_image_items());
+
+    let entries = &meta.iinf_box.unwrap().entries;
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].item_id, 1);
+    assert_eq!(entries[0].item_name, "cover");
+    assert_eq!(entries[0].content_type.as_ref().unwrap(), "image/jpeg");
+    assert_eq!(entries[1].item_id, 0x1_0000);
+    assert_eq!(entries[1].item_protection_index, 1);
+    assert_eq!(&entries[1].item_type, b"Exif");
+    assert!(entries[1].content_type.is_none());
+
+    let items = &meta.iloc_box.unwrap().items;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].base_offset, 100);
+    assert_eq!(items[0].extents[0].length, 1000);
+    assert_eq!(items[1].item_id, 0x1_0000);
+    assert_eq!(items[1].construction_method, 1);
+    assert_eq!(items[1].extents.len(), 2);
+    assert_eq!(items[1].extents[1].offset, 1 << 32);
+    assert_eq!(items[1].extents[1].length, 20);
+}
+
+#[test]
+fn quicktime_style_meta_box_is_read() {
+    let init = initialization_segment();
+    let standard = box_bytes(&meta_box());
+
+    // Drops the full box header (version and flags)
+    let mut quicktime = standard[..4].to_vec();
+    quicktime.extend_from_slice(b"meta");
+    quicktime.extend_from_slice(&standard[12..]);
+    let size = quicktime.len() as u32;
+    quicktime[..4].copy_from_slice(&size.to_be_bytes());
+
+    let read = InitializationSegment::read_from(&with_meta_bytes(&init, &quicktime)).unwrap();
+    let meta = read.moov_box.meta_box.unwrap();
+    assert_eq!(meta.handler_type(), mse_fmp4::fourcc::MDIR);
+    assert_eq!(meta.iinf_box.as_ref().unwrap().entries.len(), 2);
+    assert_eq!(box_bytes(&meta), standard);
+}
+
+#[test]
+fn unparseable_meta_box_is_ignored() {
+    let init = initialization_segment();
+    let metas: &[&[u8]] = &[
+        // No `hdlr` box
+        &[0, 0, 0, 12, b'm', b'e', b't', b'a', 0, 0, 0, 0],
+        // Truncated full box header
+        &[0, 0, 0, 10, b'm', b'e', b't', b'a', 0, 0],
+    ];
+    for meta in metas {
+        let read = InitializationSegment::read_from(&with_meta_bytes(&init, meta)).unwrap();
+        assert!(read.moov_box.meta_box.is_none());
+        assert_eq!(to_bytes(&read), to_bytes(&init));
+    }
+}
+
+#[test]
+fn track_kinds_round_trip() {
+    let mut init = initialization_segment();
+    let audio = &mut init.moov_box.trak_boxes[1];
+    audio.tkhd_box.alternate_group = 1;
+    audio.add_kind("urn:mpeg:dash:role:2011", "commentary");
+    audio.add_kind("about:html-kind", "alternative");
+    let bytes = to_bytes(&init);
+    use mse_fmp4::fourcc::{KIND, MOOV, TRAK, UDTA};
+    let path = [&MOOV, &TRAK, &UDTA, &KIND];
+    assert_eq!(
+        mse_fmp4::isobmff::find_boxes(&bytes, &path).unwrap().len(),
+        2
+    );
+
+    let read = InitializationSegment::read_from(&bytes).unwrap();
+    assert_eq!(to_bytes(&read), bytes);
+    assert!(read.moov_box.trak_boxes[0].udta_box.is_none());
+    let audio = &read.moov_box.trak_boxes[1];
+    assert_eq!(audio.tkhd_box.alternate_group, 1);
+    let kinds = &audio.udta_box.as_ref().unwrap().kind_boxes;
+    let kinds = kinds
+        .iter()
+        .map(|k| (k.scheme_uri.as_str(), k.value.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            ("urn:mpeg:dash:role:2011", "commentary"),
+            ("about:html-kind", "alternative")
+        ]
+    );
+}
+
+#[test]
+fn boxes_are_found_in_sample_entries() {
+    use mse_fmp4::fourcc::{AVC1, AVCC, ESDS, MDIA, MINF, MOOV, MP4A, STBL, STSD, TRAK};
+    use mse_fmp4::isobmff::find_boxes;
+    let bytes = include_bytes!("golden/init.mp4");
+    let stsd = [&MOOV, &TRAK, &MDIA, &MINF, &STBL, &STSD];
+
+    let stsd_boxes = find_boxes(bytes, &stsd).unwrap();
+    assert_eq!(stsd_boxes.len(), 2);
+    for b in &stsd_boxes {
+        assert_eq!(&bytes[b.offset + 4..][..4], b"stsd");
+    }
+
+    let avcc_boxes = find_boxes(bytes, &[&stsd[..], &[&AVC1, &AVCC]].concat()).unwrap();
+    assert_eq!(avcc_boxes.len(), 1);
+    let avcc = &avcc_boxes[0];
+    let entry = &initialization_segment().moov_box.trak_boxes[0]
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries[0];
+    let mut expected = Vec::new();
+    match entry {
+        SampleEntry::Avc(avc) => avc.avcc_box.write_box(&mut expected).unwrap(),
+        _ => panic!("{:?}", entry),
+    }
+    assert_eq!(&bytes[avcc.offset..][..avcc.size()], &expected[..]);
+    assert!(stsd_boxes[0].offset < avcc.offset && avcc.offset < stsd_boxes[1].offset);
+
+    let esds_boxes = find_boxes(bytes, &[&stsd[..], &[&MP4A, &ESDS]].concat()).unwrap();
+    assert_eq!(esds_boxes.len(), 1);
+    assert!(stsd_boxes[1].offset < esds_boxes[0].offset);
+}
+
+fn with_audio_rate(frequency: SamplingFrequency) -> InitializationSegment {
+    let mut init = initialization_segment();
+    let sampling_rate = frequency.as_u32();
+    let entries = &mut init.moov_box.trak_boxes[1]
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries;
+    match entries[0] {
+        SampleEntry::Aac(ref mut x) => {
+            x.esds_box.frequency = frequency;
+            if sampling_rate > 0xFFFF {
+                x.srat_box = Some(SamplingRateBox { sampling_rate });
+            }
+        }
+        ref x => panic!("{:?}", x),
+    }
+    init
+}
+
+#[test]
+fn high_sampling_rates_use_version_1_audio_sample_entries() {
+    use mse_fmp4::fourcc::{MDIA, MINF, MOOV, MP4A, SRAT, STBL, STSD, TRAK};
+    use mse_fmp4::isobmff::find_boxes;
+    let stsd = [&MOOV, &TRAK, &MDIA, &MINF, &STBL, &STSD];
+    let mp4a = [&stsd[..], &[&MP4A]].concat();
+
+    let cases = [
+        (SamplingFrequency::Hz48000, 0, 48_000),
+        (SamplingFrequency::Hz88200, 1, 44_100),
+        (SamplingFrequency::Hz96000, 1, 48_000),
+    ];
+    for &(frequency, version, samplerate) in &cases {
+        let init = with_audio_rate(frequency);
+        let bytes = to_bytes(&init);
+
+        let audio_stsd = &find_boxes(&bytes, &stsd).unwrap()[1];
+        assert_eq!(bytes[audio_stsd.offset + 8], version);
+        let entry = &find_boxes(&bytes, &mp4a).unwrap()[0];
+        assert_eq!(be_uint(&entry.payload[8..10]), u64::from(version));
+        assert_eq!(be_uint(&entry.payload[24..28]), samplerate << 16);
+        let srat_boxes = find_boxes(&bytes, &[&mp4a[..], &[&SRAT]].concat()).unwrap();
+        assert_eq!(srat_boxes.len(), version as usize);
+
+        let read = InitializationSegment::read_from(&bytes).unwrap();
+        assert_eq!(read.audio_sample_rate(), Some(frequency.as_u32()));
+        assert_eq!(to_bytes(&read), bytes);
+    }
+}
+
+#[test]
+fn version_0_audio_sample_entries_need_a_16_bit_rate() {
+    let mut init = with_audio_rate(SamplingFrequency::Hz96000);
+    match init.moov_box.trak_boxes[1]
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .sample_entries[0]
+    {
+        SampleEntry::Aac(ref mut x) => x.srat_box = None,
+        ref x => panic!("{:?}", x),
+    }
+    let mut bytes = Vec::new();
+    assert!(init.write_to(&mut bytes).is_err());
+}
+
+#[test]
+fn avc_and_aac_initialization_segment_round_trips() {
+    let bytes = to_bytes(&initialization_segment());
+    assert_eq!(&bytes[..], &include_bytes!("golden/init.mp4")[..]);
+
+    let read = InitializationSegment::read_from(&bytes).unwrap();
+    assert_eq!(read.codecs(), ["avc1.42e028", "mp4a.40.2"]);
+    assert_eq!(to_bytes(&read), bytes);
+}
+
+#[test]
+fn av1_and_vp9_initialization_segment_round_trips() {
+    use mse_fmp4::av1::Av1CodecConfigurationRecord;
+    use mse_fmp4::fmp4::{
+        Av1ConfigurationBox, Av1SampleEntry, ContentLightLevelBox, MasteringDisplayColourVolumeBox,
+        Vp9SampleEntry, VpCodecConfigurationBox,
+    };
+    use mse_fmp4::vp9::VpCodecConfigurationRecord;
+
+    let entries = vec![
+        SampleEntry::Av1(Av1SampleEntry {
+            width: 3840,
+            height: 2160,
+            av1c_box: Av1ConfigurationBox {
+                configuration: Av1CodecConfigurationRecord {
+                    seq_profile: 0,
+                    seq_level_idx_0: 12,
+                    seq_tier_0: true,
+                    high_bitdepth: true,
+                    twelve_bit: false,
+                    monochrome: false,
+                    chroma_subsampling_x: true,
+                    chroma_subsampling_y: true,
+                    chroma_sample_position: 1,
+                    initial_presentation_delay_minus_one: Some(3),
+                    config_obus: vec![0x0A, 0x03, 0x00, 0x00, 0x00],
+                },
+            },
+            hdr_boxes: HdrBoxes {
+                mdcv_box: Some(MasteringDisplayColourVolumeBox {
+                    display_primaries: [(8500, 39850), (6550, 2300), (35400, 14600)],
+                    white_point: (15635, 16450),
+                    max_display_mastering_luminance: 10_000_000,
+                    min_display_mastering_luminance: 50,
+                }),
+                clli_box: Some(ContentLightLevelBox {
+                    max_content_light_level: 1000,
+                    max_pic_average_light_level: 400,
+                }),
+                ..HdrBoxes::default()
+            },
+        }),
+        SampleEntry::Vp9(Vp9SampleEntry {
+            width: 1280,
+            height: 720,
+            vpcc_box: VpCodecConfigurationBox {
+                configuration: VpCodecConfigurationRecord {
+                    profile: 2,
+                    level: 31,
+                    bit_depth: 10,
+                    chroma_subsampling: 1,
+                    video_full_range_flag: false,
+                    colour_primaries: 9,
+                    transfer_characteristics: 16,
+                    matrix_coefficients: 9,
+                },
+            },
+            hdr_boxes: HdrBoxes::default(),
+        }),
+    ];
+    let codecs = ["av01.0.12H.10", "vp09.02.31.10"];
+    for (entry, codec) in entries.into_iter().zip(&codecs) {
+        let mut init = InitializationSegment::default();
+        init.moov_box.mvhd_box.timescale = 1000;
+        let mut track = TrackBox::new(true);
+        track.mdia_box.mdhd_box.timescale = 90000;
+        track
+            .mdia_box
+            .minf_box
+            .stbl_box
+            .stsd_box
+            .sample_entries
+            .push(entry);
+        init.moov_box.trak_boxes.push(track);
+        init.moov_box
+            .mvex_box
+            .trex_boxes
+            .push(TrackExtendsBox::new(true));
+
+        let bytes = to_bytes(&init);
+        let read = InitializationSegment::read_from(&bytes).unwrap();
+        assert_eq!(read.codecs(), [*codec]);
+        assert_eq!(to_bytes(&read), bytes);
+    }
+}
+
+fn make_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = (8 + payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(box_type);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Rebuilds the first box on `path` (and its ancestors) by replacing its payload with `f(payload)`.
+///
+/// Each element of `path` is a box type and the number of bytes that precede the child boxes in its payload.
+fn edit_box(
+    bytes: &[u8],
+    path: &[(&[u8; 4], usize)],
+    f: &mut dyn FnMut(&[u8]) -> Vec<u8>,
+) -> Vec<u8> {
+    let mut edited = Vec::new();
+    let mut found = false;
+    for b in Boxes::new(bytes) {
+        let b = b.unwrap();
+        if found || b.box_type != *path[0].0 {
+            edited.extend_from_slice(&bytes[b.offset..][..b.size()]);
+            continue;
+        }
+        found = true;
+        let payload = if path.len() == 1 {
+            f(b.payload)
+        } else {
+            let (fields, children) = b.payload.split_at(path[0].1);
+            let mut payload = fields.to_vec();
+            payload.extend(edit_box(children, &path[1..], f));
+            payload
+        };
+        edited.extend(make_box(&b.box_type, &payload));
+    }
+    assert!(found, "{:?}", path[0].0);
+    edited
+}
+
+#[test]
+fn foreign_initialization_segment_is_normalized() {
+    use mse_fmp4::fourcc::{AVC1, MDIA, MINF, MOOV, MVEX, MVHD, STBL, STSD, TRAK};
+    let canonical = to_bytes(&initialization_segment());
+
+    // The sample entry has boxes that are not modeled (bitrate and pixel aspect ratio)
+    let avc1 = [
+        (&MOOV, 0),
+        (&TRAK, 0),
+        (&MDIA, 0),
+        (&MINF, 0),
+        (&STBL, 0),
+        (&STSD, 8),
+        (&AVC1, 78),
+    ];
+    let mut foreign = edit_box(&canonical, &avc1, &mut |payload| {
+        let mut payload = payload.to_vec();
+        payload.extend(make_box(
+            b"btrt",
+            &[0, 1, 0, 0, 0, 0x10, 0, 0, 0, 0x08, 0, 0],
+        ));
+        payload.extend(make_box(b"pasp", &[0, 0, 0, 1, 0, 0, 0, 1]));
+        payload
+    });
+
+    // `mvex` precedes the version 1 `mvhd` box, and `moov` ends with an unknown box
+    foreign = edit_box(&foreign, &[(&MOOV, 0)], &mut |payload| {
+        let children = Boxes::new(payload).map(|b| b.unwrap()).collect::<Vec<_>>();
+        let child = |box_type: [u8; 4]| children.iter().filter(move |b| b.box_type == box_type);
+        let mvhd = child(MVHD).next().unwrap().payload;
+        let mut mvhd_v1 = vec![1, 0, 0, 0];
+        mvhd_v1.extend_from_slice(&0xD000_0000u64.to_be_bytes()); // creation_time
+        mvhd_v1.extend_from_slice(&0xD000_0001u64.to_be_bytes()); // modification_time
+        mvhd_v1.extend_from_slice(&mvhd[12..16]); // timescale
+        mvhd_v1.extend_from_slice(&be_uint(&mvhd[16..20]).to_be_bytes()); // duration
+        mvhd_v1.extend_from_slice(&mvhd[20..]);
+
+        let mut payload = make_box(&MVEX, child(MVEX).next().unwrap().payload);
+        payload.extend(make_box(&MVHD, &mvhd_v1));
+        for trak in child(TRAK) {
+            payload.extend(make_box(&TRAK, trak.payload));
+        }
+        payload.extend(make_box(b"xtra", b"unknown"));
+        payload
+    });
+
+    // A `free` box between `ftyp` and `moov`
+    let ftyp_size = be_uint(&foreign[..4]) as usize;
+    let free = make_box(b"free", &[0; 16]);
+    foreign.splice(ftyp_size..ftyp_size, free);
+    assert_ne!(foreign, canonical);
+
+    let read = InitializationSegment::read_from(&foreign).unwrap();
+    assert_eq!(read.moov_box.mvhd_box.timescale, 90000);
+    assert_eq!(read.moov_box.mvhd_box.duration, 6006);
+    assert_eq!(read.codecs(), ["avc1.42e028", "mp4a.40.2"]);
+    assert_eq!(to_bytes(&read), canonical);
+}
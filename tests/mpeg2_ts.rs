@@ -0,0 +1,984 @@
+extern crate mpeg2ts;
+extern crate mse_fmp4;
+
+mod common;
+
+use common::{
+    access_unit, adts_frame, assert_golden, AvStream, TsBuilder, AAC_FRAME_DURATION,
+    FRAME_DURATION, START_PTS,
+};
+use mpeg2ts::es::{StreamId, StreamType};
+use mpeg2ts::time::Timestamp;
+use mpeg2ts::ts::{Pid, TsPacketReader};
+use mse_fmp4::avc::LevelCheck;
+use mse_fmp4::fmp4::{InitializationSegment, MediaSegment};
+use mse_fmp4::io::WriteTo;
+use mse_fmp4::mpeg2_ts::{
+    self, AvAlignment, AvcLevelPolicy, CompositionOffsets, ConvertOptions, InitSegmentCache,
+    MissingDts, OnUnsupported, ParameterSetPlacement, SkipToPmt, StreamConverter,
+    TimestampExtender, UnsupportedStream, VideoSampleFlags,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WRAP: u64 = 1 << 33;
+
+// The fixture is made by `common::av_stream` (regenerated together with the golden outputs).
+fn fixture() -> Vec<u8> {
+    let ts = common::av_stream();
+    assert_golden("av.ts", &ts);
+    ts
+}
+
+#[test]
+fn ts_conversion_matches_golden_files() {
+    let ts = fixture();
+    let (init, media) = mpeg2_ts::to_fmp4(TsPacketReader::new(&ts[..])).unwrap();
+    assert_golden("ts_init.mp4", &init.to_bytes().unwrap());
+    assert_golden("ts_media.m4s", &media.to_bytes().unwrap());
+    let samples = media.samples().unwrap().map(|s| s.unwrap());
+    let video_samples = samples.filter(|s| s.track_id == 1).count();
+    assert_eq!(video_samples, AvStream::default().frames as usize);
+
+    let bytes = mpeg2_ts::ts_bytes_to_fmp4(&ts, &ConvertOptions::default()).unwrap();
+    assert_golden("ts_init.mp4", &bytes.initialization_segment);
+    assert_golden("ts_media.m4s", &bytes.media_segment);
+    assert_eq!(
+        bytes.mime_type,
+        r#"video/mp4; codecs="avc1.42e028, mp4a.40.2""#
+    );
+}
+
+// Each conversion uses `HashMap`s with different random seeds,
+// so this also catches output that depends on their iteration order.
+#[test]
+fn ts_conversion_is_deterministic() {
+    let ts = fixture();
+    let options = ConvertOptions::default();
+    let first = mpeg2_ts::ts_bytes_to_fmp4(&ts, &options).unwrap();
+    let second = mpeg2_ts::ts_bytes_to_fmp4(&ts, &options).unwrap();
+    assert_eq!(first.initialization_segment, second.initialization_segment);
+    assert_eq!(first.media_segment, second.media_segment);
+}
+
+fn convert(ts: &[u8], options: &ConvertOptions) -> (InitializationSegment, MediaSegment) {
+    mpeg2_ts::to_fmp4_with_options(TsPacketReader::new(ts), options).unwrap()
+}
+
+fn sample_count(media: &MediaSegment, track_id: u32) -> usize {
+    let samples = media.samples().unwrap().map(|s| s.unwrap());
+    samples.filter(|s| s.track_id == track_id).count()
+}
+
+fn empty_edit_durations(init: &InitializationSegment) -> Vec<u32> {
+    let traks = &init.moov_box.trak_boxes;
+    traks
+        .iter()
+        .map(|t| t.edts_box.elst_box.empty_duration)
+        .collect()
+}
+
+#[test]
+fn av_alignment_edit_list_delays_the_later_track() {
+    let options = ConvertOptions {
+        av_alignment: AvAlignment::EditList,
+        ..ConvertOptions::default()
+    };
+
+    // Audio starts 100 milliseconds earlier, so the video track is delayed
+    let ts = AvStream {
+        audio_start_offset: -9000,
+        ..AvStream::default()
+    }
+    .build();
+    let (init, _) = convert(&ts, &options);
+    let movie_timescale = u64::from(init.moov_box.mvhd_box.timescale);
+    let gap = (9000 * movie_timescale / 90_000) as u32;
+    assert_eq!(empty_edit_durations(&init), [gap, 0]);
+
+    // Audio starts 100 milliseconds later, so the audio track is delayed
+    let ts = AvStream {
+        audio_start_offset: 9000,
+        ..AvStream::default()
+    }
+    .build();
+    let (init, _) = convert(&ts, &options);
+    assert_eq!(empty_edit_durations(&init), [0, gap]);
+
+    // Without the option, no empty edit is inserted
+    let (init, _) = convert(&ts, &ConvertOptions::default());
+    assert_eq!(empty_edit_durations(&init), [0, 0]);
+}
+
+#[test]
+fn av_alignment_drop_samples_drops_the_leading_samples() {
+    let options = ConvertOptions {
+        av_alignment: AvAlignment::DropSamples,
+        ..ConvertOptions::default()
+    };
+
+    // Audio starts five frames earlier, so those frames are dropped
+    let ts = AvStream {
+        audio_start_offset: -5 * AAC_FRAME_DURATION as i64,
+        ..AvStream::default()
+    }
+    .build();
+    let (_, original) = convert(&ts, &ConvertOptions::default());
+    let (_, aligned) = convert(&ts, &options);
+    assert_eq!(sample_count(&aligned, 1), sample_count(&original, 1));
+    assert_eq!(sample_count(&aligned, 2), sample_count(&original, 2) - 5);
+
+    // Audio starts 1.5 seconds later, so the video samples before the next IDR picture (at 2 seconds) are dropped
+    let ts = AvStream {
+        audio_start_offset: 45 * FRAME_DURATION as i64,
+        ..AvStream::default()
+    }
+    .build();
+    let (_, original) = convert(&ts, &ConvertOptions::default());
+    let (_, aligned) = convert(&ts, &options);
+    assert_eq!(sample_count(&aligned, 1), sample_count(&original, 1) - 60);
+    assert_eq!(sample_count(&aligned, 2), sample_count(&original, 2));
+    let first = aligned.samples().unwrap().next().unwrap().unwrap();
+    assert_eq!(first.track_id, 1);
+    assert!(first.is_sync);
+}
+
+#[test]
+fn timestamp_extender_handles_wrap_arounds() {
+    let ts = |n: u64| Timestamp::new(n % WRAP).unwrap();
+    let mut extender = TimestampExtender::new();
+    assert_eq!(extender.extend(ts(WRAP - 3000)), WRAP - 3000);
+    assert_eq!(extender.extend(ts(WRAP + 3000)), WRAP + 3000);
+
+    // A reordered timestamp from before the wrap-around
+    assert_eq!(extender.extend(ts(WRAP - 1000)), WRAP - 1000);
+    assert_eq!(extender.extend(ts(WRAP + 6000)), WRAP + 6000);
+
+    // Small backward jumps (e.g., B-frames) are preserved
+    assert_eq!(extender.extend(ts(WRAP + 4500)), WRAP + 4500);
+
+    // Any number of wrap-arounds is handled
+    for i in 2..5 {
+        let t = i * WRAP - 3000;
+        for &t in &[t - WRAP / 3 * 2, t - WRAP / 3, t, t + 6000] {
+            assert_eq!(extender.extend(ts(t)), t);
+        }
+    }
+}
+
+// The segments are relative to the first samples, so a stream whose timestamps wrap around
+// is converted into the same bytes as the one whose timestamps do not.
+#[test]
+fn ts_conversion_handles_timestamp_wrap_arounds() {
+    let cases = [
+        // The wrap-around occurs in the middle of the stream
+        (WRAP - 2 * 90_000, 0),
+        // The wrap-around occurs between the first audio and video samples
+        (WRAP + 1000, -2 * AAC_FRAME_DURATION as i64),
+    ];
+    for &(start_pts, audio_start_offset) in &cases {
+        let expected = AvStream {
+            start_pts: START_PTS,
+            audio_start_offset,
+            ..AvStream::default()
+        }
+        .build();
+        let wrapped = AvStream {
+            start_pts,
+            audio_start_offset,
+            ..AvStream::default()
+        }
+        .build();
+        for &av_alignment in &[AvAlignment::None, AvAlignment::EditList] {
+            let options = ConvertOptions {
+                av_alignment,
+                ..ConvertOptions::default()
+            };
+            let expected = mpeg2_ts::ts_bytes_to_fmp4(&expected, &options).unwrap();
+            let actual = mpeg2_ts::ts_bytes_to_fmp4(&wrapped, &options).unwrap();
+            assert_eq!(
+                actual.initialization_segment,
+                expected.initialization_segment
+            );
+            assert_eq!(actual.media_segment, expected.media_segment);
+        }
+
+        let expected = stream_converter_output(&expected);
+        let actual = stream_converter_output(&wrapped);
+        assert!(expected.len() > 2);
+        assert_eq!(actual, expected);
+    }
+}
+
+fn stream_converter_output(ts: &[u8]) -> Vec<Vec<u8>> {
+    let mut converter = StreamConverter::new(TsPacketReader::new(ts), ConvertOptions::default());
+    let mut segments = vec![converter
+        .initialization_segment()
+        .unwrap()
+        .to_bytes()
+        .unwrap()];
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        segments.push(segment.to_bytes().unwrap());
+    }
+    segments
+}
+
+// Returns `(track_id, decode_time, duration, data)` of the samples of `segments`.
+fn segment_samples(segments: &[MediaSegment]) -> Vec<(u32, u64, u32, Vec<u8>)> {
+    let mut samples = Vec::new();
+    for segment in segments {
+        for s in segment.samples().unwrap() {
+            let s = s.unwrap();
+            samples.push((s.track_id, s.decode_time, s.duration, s.data.to_vec()));
+        }
+    }
+    samples.sort_by_key(|s| (s.0, s.1));
+    samples
+}
+
+#[test]
+fn ts_segments_are_continuous_and_start_with_sync_samples() {
+    let options = ConvertOptions {
+        av_alignment: AvAlignment::EditList,
+        ..ConvertOptions::default()
+    };
+    for &audio_start_offset in &[0, 9000, -9000] {
+        let ts = AvStream {
+            audio_start_offset,
+            ..AvStream::default()
+        }
+        .build();
+        let (_, segments) = mpeg2_ts::to_fmp4_segments(
+            TsPacketReader::new(&ts[..]),
+            Duration::from_secs(1),
+            &options,
+        )
+        .unwrap();
+        let (_, single) = convert(&ts, &options);
+        assert_eq!(segment_samples(&segments), segment_samples(&[single]));
+
+        // Cut at the IDR pictures (one per second), except the first one at one second
+        // (the first video sample has no duration, so the first GOP is a frame short of a second)
+        assert_eq!(segments.len(), 4);
+        let (video_gap, audio_gap) = if audio_start_offset < 0 {
+            (-audio_start_offset as u64, 0)
+        } else {
+            (0, audio_start_offset as u64)
+        };
+        let mut end_times = [0, 0];
+        for (i, segment) in segments.iter().enumerate() {
+            assert_eq!(segment.moof_box.mfhd_box.sequence_number, i as u32 + 1);
+            let first = segment.samples().unwrap().next().unwrap().unwrap();
+            assert_eq!(first.track_id, 1);
+            assert!(first.is_sync);
+
+            let trafs = &segment.moof_box.traf_boxes;
+            for (end_time, traf) in end_times.iter_mut().zip(trafs) {
+                assert_eq!(traf.tfdt_box.base_media_decode_time, *end_time);
+                *end_time += traf.duration().unwrap();
+            }
+
+            // The audio track is cut at the frame presented first after the video cut
+            let video_time = trafs[0].tfdt_box.base_media_decode_time + video_gap;
+            let audio_time = trafs[1].tfdt_box.base_media_decode_time * 90_000 / 48_000 + audio_gap;
+            if i > 0 {
+                assert!(video_time <= audio_time, "{} > {}", video_time, audio_time);
+                assert!(audio_time < video_time + AAC_FRAME_DURATION);
+            }
+        }
+    }
+}
+
+#[test]
+fn ts_segments_are_serialized_with_their_metadata() {
+    let ts = fixture();
+    let options = ConvertOptions::default();
+    let target_duration = Duration::from_secs(1);
+    let (init, media) =
+        mpeg2_ts::to_fmp4_bytes(TsPacketReader::new(&ts[..]), target_duration, &options).unwrap();
+    let (expected_init, expected_media) =
+        mpeg2_ts::to_fmp4_segments(TsPacketReader::new(&ts[..]), target_duration, &options)
+            .unwrap();
+
+    let mime_type = r#"video/mp4; codecs="avc1.42e028, mp4a.40.2""#;
+    assert_eq!(init.data, expected_init.to_bytes().unwrap());
+    assert_eq!(init.mime_type, mime_type);
+    assert_eq!(init.sequence_number, 0);
+
+    assert_eq!(media.len(), 4);
+    assert_eq!(media.len(), expected_media.len());
+    let mut total_duration = Duration::default();
+    for (i, (actual, expected)) in media.iter().zip(&expected_media).enumerate() {
+        assert_eq!(actual.data, expected.to_bytes().unwrap());
+        assert_eq!(actual.mime_type, mime_type);
+        assert_eq!(actual.sequence_number, i as u32 + 1);
+        let report = expected.size_report(&expected_init).unwrap();
+        assert_eq!(actual.duration, report.duration);
+        assert!(actual.duration >= target_duration - Duration::from_millis(100));
+        total_duration += actual.duration;
+    }
+
+    // The duration of the initialization segment is that of the whole presentation
+    // (the audio track ends a little after the last video sample)
+    assert_eq!(init.duration, total_duration);
+    assert!(total_duration >= Duration::from_secs(5));
+    assert!(total_duration < Duration::from_secs(5) + Duration::from_millis(50));
+}
+
+// Returns a stream joined in the middle of the first GOP: it starts with the continuation of a video PES packet,
+// and the PAT and PMT (which are repeated at each IDR picture) follow some PES packets.
+fn mid_stream() -> Vec<u8> {
+    let mut ts = TsBuilder::new();
+    let mut audio_pts = START_PTS;
+    let mut join_at = 0;
+    for i in 0..150 {
+        let pts = START_PTS + i * FRAME_DURATION;
+        if i % 30 == 0 {
+            ts.psi();
+        }
+        while audio_pts <= pts {
+            ts.audio(audio_pts, &adts_frame(64));
+            audio_pts += AAC_FRAME_DURATION;
+        }
+        if i == 10 {
+            join_at = ts.bytes().len() + 188;
+        }
+        let is_idr = i % 30 == 0;
+        ts.video(pts, Some(pts), &access_unit(is_idr, is_idr, 1000));
+    }
+    ts.bytes()[join_at..].to_vec()
+}
+
+#[test]
+fn ts_conversion_of_stream_joined_mid_stream() {
+    let ts = mid_stream();
+    assert!(mpeg2_ts::to_fmp4(TsPacketReader::new(&ts[..])).is_err());
+
+    // The samples up to the first IDR picture following the PMT are discarded
+    let reader = TsPacketReader::new(SkipToPmt::new(&ts[..]));
+    let (_, media) = mpeg2_ts::to_fmp4(reader).unwrap();
+    assert_eq!(sample_count(&media, 1), 120);
+    let first = media.samples().unwrap().next().unwrap().unwrap();
+    assert!(first.is_sync);
+    assert!(sample_count(&media, 2) > 0);
+
+    let bytes = mpeg2_ts::ts_bytes_to_fmp4(&ts, &ConvertOptions::default()).unwrap();
+    assert_eq!(bytes.media_segment, media.to_bytes().unwrap());
+
+    let reader = TsPacketReader::new(SkipToPmt::new(&ts[..]));
+    let mut converter = StreamConverter::new(reader, ConvertOptions::default());
+    let mut video_samples = 0;
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        video_samples += sample_count(&segment, 1);
+    }
+    assert_eq!(video_samples, 120);
+}
+
+#[test]
+fn ts_conversion_of_stream_starting_mid_pes_before_pmt() {
+    // The input starts inside an audio PES packet and the first PAT/PMT is written
+    // between the TS packets of a video PES packet
+    let mut ts = TsBuilder::new();
+    let mut audio_pts = START_PTS;
+    let mut join_at = 0;
+    let mut psi_at = 0;
+    for i in 0..90 {
+        let pts = START_PTS + i * FRAME_DURATION;
+        while audio_pts <= pts {
+            if i >= 5 && join_at == 0 {
+                join_at = ts.bytes().len() + 188;
+            }
+            let mut frames = adts_frame(64);
+            frames.extend_from_slice(&adts_frame(64));
+            frames.extend_from_slice(&adts_frame(64));
+            ts.audio(audio_pts, &frames);
+            audio_pts += 3 * AAC_FRAME_DURATION;
+        }
+        if i == 10 {
+            psi_at = ts.bytes().len() + 2 * 188;
+        }
+        let is_idr = i % 30 == 0;
+        ts.video(pts, Some(pts), &access_unit(is_idr, is_idr, 1000));
+    }
+    assert_ne!(join_at, 0);
+    let mut data = ts.bytes()[join_at..psi_at].to_vec();
+    data.extend_from_slice(TsBuilder::new().psi().bytes());
+    data.extend_from_slice(&ts.bytes()[psi_at..]);
+
+    // The remainder of the interrupted video PES packet and the GOP it belongs to are discarded
+    let reader = TsPacketReader::new(SkipToPmt::new(&data[..]));
+    let (_, media) = mpeg2_ts::to_fmp4(reader).unwrap();
+    assert_eq!(sample_count(&media, 1), 60);
+    let first = media.samples().unwrap().next().unwrap().unwrap();
+    assert!(first.is_sync);
+    assert!(sample_count(&media, 2) > 0);
+
+    let reader = TsPacketReader::new(SkipToPmt::new(&data[..]));
+    let mut converter = StreamConverter::new(reader, ConvertOptions::default());
+    let mut video_samples = 0;
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        video_samples += sample_count(&segment, 1);
+    }
+    assert_eq!(video_samples, 60);
+}
+
+#[test]
+fn skip_to_pmt_passes_complete_streams_through() {
+    let ts = common::av_stream();
+    let mut reader = SkipToPmt::new(&ts[..]);
+    let mut bytes = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut bytes).unwrap();
+    assert!(reader.pmt_found());
+    assert_eq!(bytes, ts);
+}
+
+#[test]
+fn probe_describes_the_fixture() {
+    let ts = fixture();
+    let result = mpeg2_ts::probe(TsPacketReader::new(&ts[..]), u64::MAX).unwrap();
+    assert_eq!(result.packets, (ts.len() / 188) as u64);
+    assert_eq!(result.programs.len(), 1);
+    assert_eq!(result.programs[0].pmt_pid.as_u16(), common::PMT_PID);
+
+    assert_eq!(result.streams.len(), 2);
+    let video = &result.streams[0];
+    assert_eq!(video.pid.as_u16(), common::VIDEO_PID);
+    assert_eq!(video.codec.as_ref().unwrap(), "avc1.42e028");
+    assert_eq!(video.resolution, Some((1920, 1080)));
+    assert!(video.audio.is_none());
+    let audio = &result.streams[1];
+    assert_eq!(audio.pid.as_u16(), common::AUDIO_PID);
+    assert_eq!(audio.codec.as_ref().unwrap(), "mp4a.40.2");
+    assert!(audio.resolution.is_none());
+    assert!(audio.audio.is_some());
+
+    // The codec strings are the same as those of the converted initialization segment
+    let (init, _) = mpeg2_ts::to_fmp4(TsPacketReader::new(&ts[..])).unwrap();
+    let codecs = result.streams.iter().map(|s| s.codec.clone().unwrap());
+    assert_eq!(codecs.collect::<Vec<_>>(), init.codecs());
+
+    // The fixture is five seconds long
+    let bitrate = result.bitrate.unwrap();
+    let expected = ts.len() as u64 * 8 / 5;
+    assert!(
+        bitrate > expected * 95 / 100 && bitrate < expected * 105 / 100,
+        "{}",
+        bitrate
+    );
+
+    // Only the first packets are read
+    let result = mpeg2_ts::probe(TsPacketReader::new(&ts[..]), 2).unwrap();
+    assert_eq!(result.packets, 2);
+    assert_eq!(result.streams.len(), 2);
+    assert!(result.streams.iter().all(|s| s.codec.is_none()));
+    assert!(result.bitrate.is_none());
+}
+
+#[test]
+fn probe_bitrate_handles_pcr_wrap_arounds() {
+    let probe = |start_pts| {
+        let ts = AvStream {
+            start_pts,
+            ..AvStream::default()
+        }
+        .build();
+        let result = mpeg2_ts::probe(TsPacketReader::new(&ts[..]), u64::MAX).unwrap();
+        result.bitrate.unwrap()
+    };
+    assert_eq!(probe(WRAP - 2 * 90_000), probe(START_PTS));
+}
+
+#[test]
+fn audio_roll_groups_are_optional() {
+    use mse_fmp4::fourcc::{MOOF, SBGP, SGPD, TRAF};
+    use mse_fmp4::isobmff::find_boxes;
+
+    let ts = fixture();
+    let (_, media) = convert(&ts, &ConvertOptions::default());
+    let bytes = media.to_bytes().unwrap();
+    assert!(find_boxes(&bytes, &[&MOOF, &TRAF, &SBGP])
+        .unwrap()
+        .is_empty());
+    assert!(find_boxes(&bytes, &[&MOOF, &TRAF, &SGPD])
+        .unwrap()
+        .is_empty());
+
+    let options = ConvertOptions {
+        audio_roll_groups: true,
+        ..ConvertOptions::default()
+    };
+    let (_, media) = convert(&ts, &options);
+    let audio_samples = sample_count(&media, 2) as u32;
+    let bytes = media.to_bytes().unwrap();
+
+    // Only the audio track fragment has the group
+    let trafs = find_boxes(&bytes, &[&MOOF, &TRAF]).unwrap();
+    assert!(find_boxes(trafs[0].payload, &[&SBGP]).unwrap().is_empty());
+    let sbgp = find_boxes(trafs[1].payload, &[&SBGP]).unwrap();
+    let sgpd = find_boxes(trafs[1].payload, &[&SGPD]).unwrap();
+    assert_eq!(sbgp.len(), 1);
+    assert_eq!(sgpd.len(), 1);
+
+    // Version 0; grouping type; one entry that covers all of the samples and refers to the first local description
+    let mut expected = vec![0, 0, 0, 0];
+    expected.extend_from_slice(b"roll");
+    expected.extend_from_slice(&1u32.to_be_bytes());
+    expected.extend_from_slice(&audio_samples.to_be_bytes());
+    expected.extend_from_slice(&0x1_0001u32.to_be_bytes());
+    assert_eq!(sbgp[0].payload, &expected[..]);
+
+    // Version 1; grouping type; default length of 2 bytes; one entry of `roll_distance = -1`
+    let mut expected = vec![1, 0, 0, 0];
+    expected.extend_from_slice(b"roll");
+    expected.extend_from_slice(&2u32.to_be_bytes());
+    expected.extend_from_slice(&1u32.to_be_bytes());
+    expected.extend_from_slice(&(-1i16).to_be_bytes());
+    assert_eq!(sgpd[0].payload, &expected[..]);
+}
+
+#[test]
+fn init_segment_cache_reuses_segments() {
+    let ts = AvStream::default().build();
+    let options = ConvertOptions::default();
+    let mut cache = InitSegmentCache::new();
+    let first = cache.convert(&ts, &options).unwrap();
+    let second = cache.convert(&ts, &options).unwrap();
+    assert!(Arc::ptr_eq(
+        &first.initialization_segment,
+        &second.initialization_segment
+    ));
+    assert_eq!(first.media_segment, second.media_segment);
+
+    // A stream with B-frames starts with a non-zero composition offset,
+    // which is removed from the media segment instead of being skipped by the edit list
+    let ts = AvStream {
+        b_frames: true,
+        ..AvStream::default()
+    }
+    .build();
+    let third = cache.convert(&ts, &options).unwrap();
+    assert!(Arc::ptr_eq(
+        &first.initialization_segment,
+        &third.initialization_segment
+    ));
+    assert_eq!(cache.len(), 1);
+    let media = MediaSegment::read_from(&third.media_segment).unwrap();
+    assert_eq!(video_trun(&media).1[..4], [0, 6000, -3000, -3000]);
+
+    // The least recently used segment is evicted
+    let named = |name: &str| ConvertOptions {
+        video_handler_name: Some(name.to_owned()),
+        ..ConvertOptions::default()
+    };
+    let mut cache = InitSegmentCache::with_capacity(2);
+    let mut convert = |name| {
+        cache
+            .convert(&ts, &named(name))
+            .unwrap()
+            .initialization_segment
+    };
+    let a = convert("a");
+    let b = convert("b");
+    assert!(Arc::ptr_eq(&a, &convert("a")));
+    let c = convert("c");
+    assert!(Arc::ptr_eq(&a, &convert("a")));
+    assert!(Arc::ptr_eq(&c, &convert("c")));
+    assert!(!Arc::ptr_eq(&b, &convert("b")));
+    assert_eq!(cache.len(), 2);
+
+    // The packets preceding the first PAT and PMT are discarded as by `ts_bytes_to_fmp4`
+    let ts = mid_stream();
+    let options = ConvertOptions {
+        video_sample_flags: VideoSampleFlags::PerSample,
+        ..ConvertOptions::default()
+    };
+    let expected = mpeg2_ts::ts_bytes_to_fmp4(&ts, &options).unwrap();
+    let cached = InitSegmentCache::new().convert(&ts, &options).unwrap();
+    assert_eq!(cached.media_segment, expected.media_segment);
+    assert_eq!(cached.keyframes, expected.keyframes);
+    assert_eq!(cached.keyframes.len(), 4);
+}
+
+// Returns the version and the composition offsets of the first `trun` box of the video track.
+fn video_trun(media: &MediaSegment) -> (u8, Vec<i32>) {
+    use mse_fmp4::fourcc::{MOOF, TRAF, TRUN};
+    let bytes = media.to_bytes().unwrap();
+    let truns = mse_fmp4::isobmff::find_boxes(&bytes, &[&MOOF, &TRAF, &TRUN]).unwrap();
+    let offsets = media.moof_box.traf_boxes[0].trun_boxes[0]
+        .samples
+        .iter()
+        .map(|s| s.composition_time_offset.unwrap())
+        .collect();
+    (truns[0].payload[0], offsets)
+}
+
+#[test]
+fn composition_offsets_are_shifted_by_edit_list_or_made_negative() {
+    let ts = AvStream {
+        b_frames: true,
+        ..AvStream::default()
+    }
+    .build();
+    let frame = FRAME_DURATION as i32;
+    // I, P, B, B, P, B, B, ... (the PTS of the I frame is one frame later than its DTS)
+    let expected_offsets = [frame, 3 * frame, 0, 0, 3 * frame, 0, 0];
+
+    let options = ConvertOptions {
+        composition_offsets: CompositionOffsets::EditList,
+        ..ConvertOptions::default()
+    };
+    let (init, media) = convert(&ts, &options);
+    assert_eq!(
+        init.moov_box.trak_boxes[0].edts_box.elst_box.media_time,
+        frame
+    );
+    // The `trun` boxes are always written in version 1, so the offsets are signed in both cases
+    let (version, offsets) = video_trun(&media);
+    assert_eq!(version, 1);
+    assert_eq!(offsets[..7], expected_offsets);
+    assert!(offsets.iter().all(|&o| o >= 0));
+
+    let options = ConvertOptions {
+        composition_offsets: CompositionOffsets::Negative,
+        ..ConvertOptions::default()
+    };
+    let (init, media) = convert(&ts, &options);
+    assert_eq!(init.moov_box.trak_boxes[0].edts_box.elst_box.media_time, 0);
+    let (version, negative_offsets) = video_trun(&media);
+    assert_eq!(version, 1);
+    let shifted = offsets.iter().map(|o| o - frame).collect::<Vec<_>>();
+    assert_eq!(negative_offsets, shifted);
+    assert_eq!(negative_offsets[2], -frame);
+
+    // `StreamConverter` produces the same offsets and edit lists
+    for &(composition_offsets, media_time, first_offsets) in &[
+        (CompositionOffsets::EditList, frame, &offsets[..7]),
+        (CompositionOffsets::Negative, 0, &shifted[..7]),
+    ] {
+        let options = ConvertOptions {
+            composition_offsets,
+            ..ConvertOptions::default()
+        };
+        let mut converter = StreamConverter::new(TsPacketReader::new(&ts[..]), options);
+        let init = converter.initialization_segment().unwrap();
+        assert_eq!(
+            init.moov_box.trak_boxes[0].edts_box.elst_box.media_time,
+            media_time
+        );
+        let segment = converter.next_media_segment().unwrap().unwrap();
+        let (version, actual_offsets) = video_trun(&segment);
+        assert_eq!(version, 1);
+        assert_eq!(&actual_offsets[..7], first_offsets);
+    }
+}
+
+// Returns the types of the length-prefixed NAL units of each video sample.
+#[test]
+fn missing_dts_can_be_reconstructed() {
+    let stream = AvStream {
+        b_frames: true,
+        ..AvStream::default()
+    };
+    let (_, expected) = convert(&stream.build(), &ConvertOptions::default());
+    let (_, expected_offsets) = video_trun(&expected);
+    assert_eq!(expected_offsets[..7], [3000, 9000, 0, 0, 9000, 0, 0]);
+
+    let ts = AvStream {
+        omit_dts: true,
+        ..stream
+    }
+    .build();
+    let (_, media) = convert(&ts, &ConvertOptions::default());
+    assert!(video_trun(&media).1.iter().all(|&offset| offset == 0));
+
+    // The PTS values are in the IBBP order, from which the DTS values are derived
+    let options = ConvertOptions {
+        missing_dts: MissingDts::Reconstruct,
+        ..ConvertOptions::default()
+    };
+    let (_, media) = convert(&ts, &options);
+    assert_eq!(video_trun(&media).1, expected_offsets);
+    let durations = |media: &MediaSegment| {
+        media.moof_box.traf_boxes[0].trun_boxes[0]
+            .samples
+            .iter()
+            .map(|s| s.duration)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(durations(&media), durations(&expected));
+
+    // `StreamConverter` cannot reconstruct them without reading the whole stream
+    let mut converter = StreamConverter::new(TsPacketReader::new(&ts[..]), options);
+    let e = converter.initialization_segment().err().unwrap();
+    assert_eq!(*e.kind(), mse_fmp4::ErrorKind::Unsupported);
+}
+
+fn video_nal_unit_types(media: &MediaSegment) -> Vec<Vec<u8>> {
+    let samples = media.samples().unwrap().map(|s| s.unwrap());
+    samples
+        .filter(|s| s.track_id == 1)
+        .map(|s| {
+            let mut types = Vec::new();
+            let mut data = s.data;
+            while !data.is_empty() {
+                let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                types.push(data[4] & 0x1F);
+                data = &data[4 + size..];
+            }
+            types
+        })
+        .collect()
+}
+
+#[test]
+fn parameter_set_placement() {
+    const AUD: &[u8] = &[0x09, 0xF0];
+    let annex_b = |nal_units: &[&[u8]], slice: Vec<u8>| {
+        let mut access_unit = Vec::new();
+        for nal_unit in nal_units {
+            access_unit.extend_from_slice(&[0, 0, 0, 1]);
+            access_unit.extend_from_slice(nal_unit);
+        }
+        access_unit.extend_from_slice(&slice);
+        access_unit
+    };
+    let access_units = [
+        access_unit(true, true, 100),
+        access_unit(false, false, 100),
+        access_unit(true, false, 100),
+        annex_b(&[common::SPS], access_unit(true, false, 100)),
+        annex_b(&[common::PPS], access_unit(true, false, 100)),
+        access_unit(false, false, 100),
+        annex_b(&[AUD], access_unit(true, false, 100)),
+    ];
+    let mut ts = TsBuilder::new();
+    ts.psi();
+    for (i, access_unit) in access_units.iter().enumerate() {
+        let pts = START_PTS + i as u64 * FRAME_DURATION;
+        ts.audio(pts, &adts_frame(64));
+        ts.video(pts, Some(pts), access_unit);
+    }
+
+    let cases: [(ParameterSetPlacement, &[&[u8]]); 3] = [
+        (
+            ParameterSetPlacement::AsIs,
+            &[&[7, 8, 5], &[1], &[5], &[7, 5], &[8, 5], &[1], &[9, 5]],
+        ),
+        (
+            ParameterSetPlacement::InBand,
+            &[
+                &[7, 8, 5],
+                &[1],
+                &[7, 8, 5],
+                &[7, 8, 5],
+                &[7, 8, 5],
+                &[1],
+                &[9, 7, 8, 5],
+            ],
+        ),
+        (
+            ParameterSetPlacement::OutOfBand,
+            &[&[5], &[1], &[5], &[5], &[5], &[1], &[9, 5]],
+        ),
+    ];
+    for &(parameter_sets, expected) in &cases {
+        let options = ConvertOptions {
+            parameter_sets,
+            ..ConvertOptions::default()
+        };
+        let (init, media) = convert(ts.bytes(), &options);
+        assert_eq!(init.codecs()[0], "avc1.42e028");
+        assert_eq!(
+            video_nal_unit_types(&media),
+            expected,
+            "{:?}",
+            parameter_sets
+        );
+
+        let mut converter = StreamConverter::new(TsPacketReader::new(ts.bytes()), options);
+        let segment = converter.next_media_segment().unwrap().unwrap();
+        assert!(converter.next_media_segment().unwrap().is_none());
+        assert_eq!(
+            video_nal_unit_types(&segment),
+            expected,
+            "{:?}",
+            parameter_sets
+        );
+    }
+
+    // The inserted parameter sets are those of the `avcC` box
+    let options = ConvertOptions {
+        parameter_sets: ParameterSetPlacement::InBand,
+        ..ConvertOptions::default()
+    };
+    let (_, media) = convert(ts.bytes(), &options);
+    let sample = media.samples().unwrap().nth(3).unwrap().unwrap();
+    let mut expected = Vec::new();
+    for nal_unit in &[common::SPS, common::PPS] {
+        expected.extend_from_slice(&(nal_unit.len() as u32).to_be_bytes());
+        expected.extend_from_slice(nal_unit);
+    }
+    assert_eq!(&sample.data[..expected.len()], &expected[..]);
+}
+
+// Returns the `constraint_set_flag` and `level_idc` of the SPS NAL units in the video samples.
+fn in_band_sps_levels(media: &MediaSegment) -> Vec<(u8, u8)> {
+    let samples = media.samples().unwrap().map(|s| s.unwrap());
+    let mut levels = Vec::new();
+    for sample in samples.filter(|s| s.track_id == 1) {
+        let mut data = sample.data;
+        while !data.is_empty() {
+            let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            if data[4] & 0x1F == 7 {
+                levels.push((data[6], data[7]));
+            }
+            data = &data[4 + size..];
+        }
+    }
+    levels
+}
+
+#[test]
+fn too_low_avc_levels_are_raised() {
+    // The 1080p fixture needs level 4, but declares level 1b
+    let declared = [0x67, 0x42, 0xf0, 0x0b];
+    let mut ts = fixture();
+    for i in 0..ts.len() - 4 {
+        if ts[i..i + 4] == common::SPS[..4] {
+            ts[i..i + 4].copy_from_slice(&declared);
+        }
+    }
+    let idr_count = 5;
+
+    let (init, media) = convert(&ts, &ConvertOptions::default());
+    assert_eq!(init.codecs()[0], "avc1.42f00b");
+    assert_eq!(in_band_sps_levels(&media), vec![(0xf0, 11); idr_count]);
+
+    let options = ConvertOptions {
+        avc_level: AvcLevelPolicy::Raise,
+        ..ConvertOptions::default()
+    };
+    let (init, media) = convert(&ts, &options);
+    assert_eq!(init.codecs()[0], "avc1.42e028");
+    assert_eq!(in_band_sps_levels(&media), vec![(0xe0, 40); idr_count]);
+
+    let mut converter = StreamConverter::new(TsPacketReader::new(&ts[..]), options);
+    let codecs = converter.initialization_segment().unwrap().codecs();
+    assert_eq!(codecs[0], "avc1.42e028");
+    let mut levels = Vec::new();
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        levels.extend(in_band_sps_levels(&segment));
+    }
+    assert_eq!(levels, vec![(0xe0, 40); idr_count]);
+
+    // The callback can only report the insufficient level
+    let checks = Arc::new(Mutex::new(Vec::new()));
+    let reported = checks.clone();
+    let options = ConvertOptions {
+        avc_level: AvcLevelPolicy::Callback(Arc::new(move |check: &LevelCheck| {
+            reported.lock().unwrap().push(*check);
+            false
+        })),
+        ..ConvertOptions::default()
+    };
+    let (init, media) = convert(&ts, &options);
+    assert_eq!(init.codecs()[0], "avc1.42f00b");
+    assert_eq!(in_band_sps_levels(&media), vec![(0xf0, 11); idr_count]);
+    assert_eq!(
+        *checks.lock().unwrap(),
+        [LevelCheck {
+            declared_level_idc: 9,
+            required_level_idc: 40
+        }]
+    );
+}
+
+const MP3_PID: u16 = 0x103;
+const HEVC_PID: u16 = 0x104;
+
+// Returns a stream that also has an MPEG-1 audio stream and an HEVC stream (with or without their PES packets).
+fn stream_with_unsupported_codecs(with_packets: bool) -> Vec<u8> {
+    let mut ts = TsBuilder::new();
+    ts.pat().pmt(&[
+        (common::VIDEO_PID, common::STREAM_TYPE_H264),
+        (common::AUDIO_PID, common::STREAM_TYPE_ADTS_AAC),
+        (MP3_PID, StreamType::Mpeg1Audio as u8),
+        (HEVC_PID, StreamType::H265 as u8),
+    ]);
+    let mut audio_pts = START_PTS;
+    for i in 0..60 {
+        let pts = START_PTS + i * FRAME_DURATION;
+        while audio_pts <= pts {
+            ts.audio(audio_pts, &adts_frame(64));
+            audio_pts += AAC_FRAME_DURATION;
+        }
+        let is_idr = i % 30 == 0;
+        ts.video(pts, Some(pts), &access_unit(is_idr, is_idr, 1000));
+        if with_packets {
+            ts.pes(MP3_PID, 0xC1, pts, None, &[0xff; 100], None);
+            ts.pes(HEVC_PID, 0xE1, pts, Some(pts), &[0; 300], None);
+        }
+    }
+    ts.bytes().to_vec()
+}
+
+#[test]
+fn unsupported_streams_are_handled_as_specified() {
+    let ts = stream_with_unsupported_codecs(true);
+    let (_, expected) = convert(
+        &stream_with_unsupported_codecs(false),
+        &ConvertOptions::default(),
+    );
+
+    // Fail (default)
+    let e = mpeg2_ts::to_fmp4(TsPacketReader::new(&ts[..])).unwrap_err();
+    assert_eq!(*e.kind(), mse_fmp4::ErrorKind::Unsupported);
+    let mut converter =
+        StreamConverter::new(TsPacketReader::new(&ts[..]), ConvertOptions::default());
+    let e = converter.initialization_segment().err().unwrap();
+    assert_eq!(*e.kind(), mse_fmp4::ErrorKind::Unsupported);
+
+    // Skip
+    let options = ConvertOptions {
+        on_unsupported: OnUnsupported::Skip,
+        ..ConvertOptions::default()
+    };
+    let (_, media) = convert(&ts, &options);
+    assert_eq!(media.to_bytes().unwrap(), expected.to_bytes().unwrap());
+    let audio = mpeg2_ts::to_fmp4_audio_only(TsPacketReader::new(&ts[..]), &options).unwrap();
+    assert_eq!(sample_count(&audio.1, 2), sample_count(&expected, 2));
+
+    // Callback (called once for each stream, although each of them has 60 PES packets)
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let options = ConvertOptions {
+        on_unsupported: OnUnsupported::Callback({
+            let reported = reported.clone();
+            Arc::new(move |stream: &UnsupportedStream| reported.lock().unwrap().push(*stream))
+        }),
+        ..ConvertOptions::default()
+    };
+    let expected_streams = vec![
+        UnsupportedStream {
+            pid: Some(Pid::new(MP3_PID).unwrap()),
+            stream_id: StreamId::new(0xC1),
+            stream_type: StreamType::Mpeg1Audio,
+        },
+        UnsupportedStream {
+            pid: Some(Pid::new(HEVC_PID).unwrap()),
+            stream_id: StreamId::new(0xE1),
+            stream_type: StreamType::H265,
+        },
+    ];
+    let (_, media) = convert(&ts, &options);
+    assert_eq!(media.to_bytes().unwrap(), expected.to_bytes().unwrap());
+    assert_eq!(*reported.lock().unwrap(), expected_streams);
+
+    reported.lock().unwrap().clear();
+    let mut converter = StreamConverter::new(TsPacketReader::new(&ts[..]), options);
+    let mut video_samples = 0;
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        video_samples += sample_count(&segment, 1);
+    }
+    assert_eq!(video_samples, 60);
+    assert_eq!(*reported.lock().unwrap(), expected_streams);
+}
@@ -0,0 +1,137 @@
+extern crate mse_fmp4;
+
+use mse_fmp4::fmp4::{split_into_segments, InitializationSegment};
+use mse_fmp4::isobmff::{self, BoxReader, MdatPayloads, ParseLimits};
+use mse_fmp4::ErrorKind;
+use std::time::Duration;
+
+const INIT: &[u8] = include_bytes!("golden/init.mp4");
+const MEDIA: &[u8] = include_bytes!("golden/media.m4s");
+
+// Feeds the input to every reader; none of them may panic (errors are fine).
+fn parse_all(bytes: &[u8]) {
+    if isobmff::parse_untrusted(bytes, &ParseLimits::default()).is_ok() {
+        let _ = InitializationSegment::read_from(bytes);
+        let _ = split_into_segments(bytes, Duration::from_secs(1));
+    }
+    let mut reader = BoxReader::new(bytes);
+    while let Ok(Some(_)) = reader.read_box_with(|_| Ok(())) {}
+}
+
+// A tiny xorshift generator so that the test is reproducible without extra dependencies.
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[test]
+fn valid_input_is_accepted() {
+    let file = [INIT, MEDIA].concat();
+    let boxes = isobmff::parse_untrusted(&file, &ParseLimits::default()).unwrap();
+    let types = boxes.iter().map(|b| &b.box_type).collect::<Vec<_>>();
+    assert_eq!(types, [b"ftyp", b"moov", b"moof", b"mdat", b"mdat"]);
+}
+
+#[test]
+fn mdat_payloads_can_be_skipped_or_streamed() {
+    let file = [INIT, MEDIA].concat();
+    let expected = isobmff::parse_untrusted(&file, &ParseLimits::default()).unwrap();
+
+    let mut reader = BoxReader::new(&file[..]);
+    reader.set_mdat_payloads(MdatPayloads::Skip);
+    for b in &expected {
+        let actual = reader.read_box().unwrap().unwrap();
+        assert_eq!(actual.box_type, b.box_type);
+        assert_eq!(actual.offset, b.offset as u64);
+        assert_eq!(actual.size(), b.size() as u64);
+        assert_eq!(actual.payload.is_none(), b.box_type == *b"mdat");
+    }
+    assert!(reader.read_box().unwrap().is_none());
+
+    let mut mdat_data = Vec::new();
+    let mut reader = BoxReader::new(&file[..]);
+    while let Some(b) = reader
+        .read_box_with(|data| {
+            mdat_data.extend_from_slice(data);
+            Ok(())
+        })
+        .unwrap()
+    {
+        assert_eq!(b.payload.is_none(), b.box_type == *b"mdat");
+    }
+    let expected_data = expected
+        .iter()
+        .filter(|b| b.box_type == *b"mdat")
+        .flat_map(|b| b.payload.iter().cloned())
+        .collect::<Vec<_>>();
+    assert_eq!(mdat_data, expected_data);
+}
+
+#[test]
+fn truncated_input_does_not_panic() {
+    let file = [INIT, MEDIA].concat();
+    for i in 0..file.len() {
+        parse_all(&file[..i]);
+    }
+}
+
+#[test]
+fn mutated_input_does_not_panic() {
+    let file = [INIT, MEDIA].concat();
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    for _ in 0..2000 {
+        let mut bytes = file.clone();
+        for _ in 0..1 + rng.next() % 8 {
+            let i = (rng.next() % bytes.len() as u64) as usize;
+            bytes[i] = rng.next() as u8;
+        }
+        parse_all(&bytes);
+    }
+}
+
+#[test]
+fn limits_are_enforced() {
+    let limits = ParseLimits {
+        max_input_size: INIT.len() - 1,
+        ..ParseLimits::default()
+    };
+    let e = isobmff::parse_untrusted(INIT, &limits).unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Unsupported);
+
+    let limits = ParseLimits {
+        max_depth: 3,
+        ..ParseLimits::default()
+    };
+    let e = isobmff::parse_untrusted(INIT, &limits).unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Unsupported);
+
+    let limits = ParseLimits {
+        max_boxes: 3,
+        ..ParseLimits::default()
+    };
+    let e = isobmff::parse_untrusted(INIT, &limits).unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn summarize_rejects_deeply_nested_boxes() {
+    let mut bytes = b"\0\0\0\x08free".to_vec();
+    for _ in 0..ParseLimits::default().max_depth {
+        let size = bytes.len() as u32 + 8;
+        bytes = [&size.to_be_bytes()[..], b"moov", &bytes].concat();
+    }
+    let e = isobmff::summarize(&bytes).unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Unsupported);
+    let e = isobmff::parse_untrusted(&bytes, &ParseLimits::default()).unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Unsupported);
+
+    // One level less is accepted by both functions.
+    let bytes = &bytes[8..];
+    assert!(isobmff::summarize(bytes).is_ok());
+    assert!(isobmff::parse_untrusted(bytes, &ParseLimits::default()).is_ok());
+}
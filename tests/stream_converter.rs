@@ -0,0 +1,700 @@
+extern crate mpeg2ts;
+extern crate mse_fmp4;
+
+mod common;
+
+use common::{
+    access_unit, adts_frame, AvStream, TsBuilder, AAC_FRAME_DURATION, FRAME_DURATION, START_PTS,
+};
+use mpeg2ts::es::StreamId;
+use mpeg2ts::ts::TsPacketReader;
+use mse_fmp4::fmp4::{MediaSegment, SegmentSample};
+use mse_fmp4::io::{BufferPool, WriteTo};
+use mse_fmp4::isobmff::Boxes;
+use mse_fmp4::mpeg2_ts::{
+    self, ConvertOptions, DataPacket, DtsTimestampPolicy, PcrTimestampPolicy, PesTimestamps,
+    PtsTimestampPolicy, SegmentationPolicy, StreamConverter, SubtitleCue, SubtitleDecoder,
+    TimestampPolicy, WallclockTimestampPolicy,
+};
+use mse_fmp4::ErrorKind;
+use std::time::{Duration, Instant};
+
+const VIDEO_TRACK_ID: u32 = 1;
+const AUDIO_TRACK_ID: u32 = 2;
+
+fn convert(ts: &[u8], options: ConvertOptions) -> Vec<MediaSegment> {
+    let mut converter = StreamConverter::new(TsPacketReader::new(ts), options);
+    let mut segments = Vec::new();
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        segments.push(segment);
+    }
+    assert!(converter.next_media_segment().unwrap().is_none());
+    segments
+}
+
+fn with_segmentation(segmentation: SegmentationPolicy) -> ConvertOptions {
+    ConvertOptions {
+        segmentation,
+        ..ConvertOptions::default()
+    }
+}
+
+fn track_samples(segment: &MediaSegment, track_id: u32) -> Vec<SegmentSample<'_>> {
+    let samples = segment.samples().unwrap().map(|s| s.unwrap());
+    samples.filter(|s| s.track_id == track_id).collect()
+}
+
+fn video_sample_counts(segments: &[MediaSegment]) -> Vec<usize> {
+    segments
+        .iter()
+        .map(|s| track_samples(s, VIDEO_TRACK_ID).len())
+        .collect()
+}
+
+// Asserts that each segment of the track starts where the previous one ends.
+fn assert_continuous_decode_times(segments: &[MediaSegment], track_id: u32) {
+    let mut next_decode_time = None;
+    for (i, segment) in segments.iter().enumerate() {
+        let traf = &segment.moof_box.traf_boxes[track_id as usize - 1];
+        let base_decode_time = traf.tfdt_box.base_media_decode_time;
+        if let Some(expected) = next_decode_time {
+            assert_eq!(
+                base_decode_time, expected,
+                "track {}, segment {}",
+                track_id, i
+            );
+        }
+        let duration = track_samples(segment, track_id)
+            .iter()
+            .map(|s| u64::from(s.duration))
+            .sum::<u64>();
+        next_decode_time = Some(base_decode_time + duration);
+    }
+}
+
+#[test]
+fn segments_are_cut_at_sync_samples_after_target_duration() {
+    let segments = convert(&common::av_stream(), ConvertOptions::default());
+    assert_eq!(video_sample_counts(&segments), [60, 60, 30]);
+    for (i, segment) in segments.iter().enumerate() {
+        assert_eq!(segment.moof_box.mfhd_box.sequence_number, i as u32 + 1);
+        assert!(track_samples(segment, VIDEO_TRACK_ID)[0].is_sync);
+    }
+
+    let options = with_segmentation(SegmentationPolicy {
+        target_duration: Duration::from_millis(1500),
+        ..SegmentationPolicy::default()
+    });
+    let segments = convert(&common::av_stream(), options);
+    assert_eq!(video_sample_counts(&segments), [60, 60, 30]);
+}
+
+#[test]
+fn decode_times_are_continuous() {
+    let segments = convert(&common::av_stream(), ConvertOptions::default());
+    assert_eq!(segments.len(), 3);
+    let first = &segments[0].moof_box.traf_boxes;
+    assert_eq!(first[0].tfdt_box.base_media_decode_time, 0);
+    assert_eq!(first[1].tfdt_box.base_media_decode_time, 0);
+    assert_continuous_decode_times(&segments, VIDEO_TRACK_ID);
+    assert_continuous_decode_times(&segments, AUDIO_TRACK_ID);
+    for segment in &segments {
+        for sample in track_samples(segment, VIDEO_TRACK_ID) {
+            assert_eq!(u64::from(sample.duration), FRAME_DURATION);
+        }
+    }
+}
+
+#[test]
+fn flush_emits_all_remaining_samples() {
+    let ts = common::av_stream();
+    let segments = convert(&ts, ConvertOptions::default());
+    let (_, whole) = mpeg2_ts::to_fmp4(TsPacketReader::new(&ts[..])).unwrap();
+
+    let video_samples = video_sample_counts(&segments).iter().sum::<usize>();
+    assert_eq!(video_samples, AvStream::default().frames as usize);
+    let audio_samples = segments
+        .iter()
+        .map(|s| track_samples(s, AUDIO_TRACK_ID).len())
+        .sum::<usize>();
+    assert_eq!(audio_samples, track_samples(&whole, AUDIO_TRACK_ID).len());
+
+    // The last segment ends with the last video sample (whose duration is that of the previous one)
+    let last = segments.last().unwrap();
+    let last_sample = track_samples(last, VIDEO_TRACK_ID).pop().unwrap();
+    assert_eq!(
+        last_sample.decode_time,
+        (AvStream::default().frames - 1) * FRAME_DURATION
+    );
+    assert_eq!(u64::from(last_sample.duration), FRAME_DURATION);
+}
+
+#[test]
+fn stream_without_sync_samples_is_limited() {
+    let ts = AvStream {
+        has_idr: false,
+        ..AvStream::default()
+    }
+    .build();
+
+    // Without limits, the whole stream is read before the failure
+    let mut converter =
+        StreamConverter::new(TsPacketReader::new(&ts[..]), ConvertOptions::default());
+    let e = converter.initialization_segment().unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+    let limits = [
+        SegmentationPolicy {
+            max_data_size: Some(8 * 1024),
+            ..SegmentationPolicy::default()
+        },
+        SegmentationPolicy {
+            max_duration: Some(Duration::from_secs(1)),
+            ..SegmentationPolicy::default()
+        },
+    ];
+    for segmentation in &limits {
+        let options = with_segmentation(segmentation.clone());
+        let mut converter = StreamConverter::new(TsPacketReader::new(&ts[..]), options);
+        let e = converter.initialization_segment().unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::SegmentTooLarge);
+    }
+}
+
+#[test]
+fn max_data_size_cuts_long_gops() {
+    let ts = AvStream {
+        gop: 0,
+        ..AvStream::default()
+    }
+    .build();
+    let max_data_size = 16 * 1024;
+    let options = with_segmentation(SegmentationPolicy {
+        max_data_size: Some(max_data_size),
+        ..SegmentationPolicy::default()
+    });
+    let segments = convert(&ts, options);
+    assert!(segments.len() > 2);
+    for segment in &segments {
+        let data_size = segment
+            .mdat_boxes
+            .iter()
+            .map(|m| m.data.len())
+            .sum::<usize>();
+        // The segment is cut once the limit is reached, so it can exceed the limit by the last sample
+        assert!(data_size < max_data_size + 1024, "{}", data_size);
+    }
+    assert!(track_samples(&segments[0], VIDEO_TRACK_ID)[0].is_sync);
+    assert!(!track_samples(&segments[1], VIDEO_TRACK_ID)[0].is_sync);
+    assert_eq!(
+        video_sample_counts(&segments).iter().sum::<usize>(),
+        AvStream::default().frames as usize
+    );
+    assert_continuous_decode_times(&segments, VIDEO_TRACK_ID);
+    assert_continuous_decode_times(&segments, AUDIO_TRACK_ID);
+}
+
+#[test]
+fn max_duration_cuts_segments_when_video_stalls() {
+    // One second of audio and video followed by three seconds of audio only
+    let mut ts = TsBuilder::new();
+    ts.psi();
+    let start = common::START_PTS;
+    let mut audio_pts = start;
+    for i in 0..30 {
+        let pts = start + i * FRAME_DURATION;
+        while audio_pts <= pts {
+            ts.audio(audio_pts, &adts_frame(64));
+            audio_pts += AAC_FRAME_DURATION;
+        }
+        ts.video(pts, Some(pts), &access_unit(i == 0, i == 0, 300));
+    }
+    while audio_pts < start + 4 * 90_000 {
+        ts.audio(audio_pts, &adts_frame(64));
+        audio_pts += AAC_FRAME_DURATION;
+    }
+    let audio_frames = ((audio_pts - start) / AAC_FRAME_DURATION) as usize;
+
+    let options = with_segmentation(SegmentationPolicy {
+        max_duration: Some(Duration::from_secs(1)),
+        ..SegmentationPolicy::default()
+    });
+    let segments = convert(ts.bytes(), options);
+    assert!(segments.len() >= 3, "{}", segments.len());
+    assert_eq!(video_sample_counts(&segments).iter().sum::<usize>(), 30);
+    let audio_samples = segments
+        .iter()
+        .map(|s| track_samples(s, AUDIO_TRACK_ID).len())
+        .sum::<usize>();
+    assert_eq!(audio_samples, audio_frames);
+    for segment in &segments[1..] {
+        // 1 second at 48 kHz is 46.875 frames
+        assert!(track_samples(segment, AUDIO_TRACK_ID).len() <= 48);
+    }
+    assert_continuous_decode_times(&segments, VIDEO_TRACK_ID);
+    assert_continuous_decode_times(&segments, AUDIO_TRACK_ID);
+}
+
+#[test]
+fn dts_going_backwards_is_rejected() {
+    let mut ts = TsBuilder::new();
+    ts.psi();
+    let start = common::START_PTS;
+    for i in 0..90 {
+        let mut pts = start + i * FRAME_DURATION;
+        if i >= 45 {
+            pts -= 10 * FRAME_DURATION;
+        }
+        ts.audio(pts, &adts_frame(64));
+        ts.video(pts, Some(pts), &access_unit(i % 30 == 0, i == 0, 300));
+    }
+    let mut converter =
+        StreamConverter::new(TsPacketReader::new(ts.bytes()), ConvertOptions::default());
+    let result = (|| -> mse_fmp4::Result<()> {
+        while converter.next_media_segment()?.is_some() {}
+        Ok(())
+    })();
+    assert_eq!(*result.unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn segmentation_policy_decides_cut_points() {
+    let ts = common::av_stream();
+    let cases = [
+        // A sync sample arrives every second
+        (SegmentationPolicy::default(), vec![60, 60, 30]),
+        (
+            SegmentationPolicy {
+                target_duration: Duration::from_millis(2100),
+                ..SegmentationPolicy::default()
+            },
+            vec![90, 60],
+        ),
+        (
+            SegmentationPolicy {
+                target_duration: Duration::from_millis(2100),
+                tolerance: Duration::from_millis(100),
+                ..SegmentationPolicy::default()
+            },
+            vec![60, 60, 30],
+        ),
+        (
+            SegmentationPolicy {
+                target_duration: Duration::from_secs(0),
+                ..SegmentationPolicy::default()
+            },
+            vec![30, 30, 30, 30, 30],
+        ),
+        (
+            SegmentationPolicy {
+                target_duration: Duration::from_secs(0),
+                min_duration: Duration::from_millis(1500),
+                ..SegmentationPolicy::default()
+            },
+            vec![60, 60, 30],
+        ),
+        (
+            SegmentationPolicy {
+                target_duration: Duration::from_millis(500),
+                align_to_idr: false,
+                ..SegmentationPolicy::default()
+            },
+            vec![15; 10],
+        ),
+        (
+            SegmentationPolicy {
+                target_duration: Duration::from_millis(1400),
+                align_to_idr: false,
+                ..SegmentationPolicy::default()
+            },
+            vec![42, 42, 42, 24],
+        ),
+    ];
+    for (segmentation, expected) in &cases {
+        let segments = convert(&ts, with_segmentation(segmentation.clone()));
+        assert_eq!(
+            &video_sample_counts(&segments),
+            expected,
+            "{:?}",
+            segmentation
+        );
+        assert_continuous_decode_times(&segments, VIDEO_TRACK_ID);
+        assert_continuous_decode_times(&segments, AUDIO_TRACK_ID);
+
+        // Each segment starts with a sync sample only if the cut is aligned to one
+        let mut start = 0;
+        for (segment, count) in segments.iter().zip(expected) {
+            let is_sync = track_samples(segment, VIDEO_TRACK_ID)[0].is_sync;
+            assert_eq!(is_sync, start % 30 == 0);
+            if segmentation.align_to_idr {
+                assert!(is_sync);
+            }
+            start += count;
+        }
+    }
+}
+
+// Returns a 20-second stream whose audio frames span `frame_ticks_x100 / 100` ticks of the PES timestamps
+// (i.e., the actual sampling rate differs from the 48 kHz declared in the ADTS headers unless it is 192,000).
+fn skewed_audio_stream(frame_ticks_x100: u64) -> Vec<u8> {
+    let start = common::START_PTS;
+    let mut ts = TsBuilder::new();
+    ts.psi();
+    let mut audio_frames = 0;
+    for i in 0..600 {
+        let pts = start + i * FRAME_DURATION;
+        loop {
+            let audio_pts = start + audio_frames * frame_ticks_x100 / 100;
+            if audio_pts > pts {
+                break;
+            }
+            ts.audio(audio_pts, &adts_frame(64));
+            audio_frames += 1;
+        }
+        ts.video(pts, Some(pts), &access_unit(i % 30 == 0, i == 0, 300));
+    }
+    ts.into_bytes()
+}
+
+// Returns the audio drift after each media segment (and checks that the audio decode times are continuous).
+fn audio_drifts(ts: &[u8], max_audio_drift: Option<Duration>) -> Vec<i64> {
+    let options = ConvertOptions {
+        max_audio_drift,
+        ..ConvertOptions::default()
+    };
+    let mut converter = StreamConverter::new(TsPacketReader::new(ts), options);
+    let mut segments = Vec::new();
+    let mut drifts = Vec::new();
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        segments.push(segment);
+        drifts.push(converter.audio_drift().unwrap());
+    }
+    assert_continuous_decode_times(&segments, AUDIO_TRACK_ID);
+    drifts
+}
+
+#[test]
+fn audio_drift_is_kept_within_max_audio_drift() {
+    // The audio is sampled 0.2% slower than declared,
+    // so each frame of 1024 samples spans 1923.84 ticks (instead of 1920) of the PES timestamps
+    let ts = skewed_audio_stream(192_384);
+
+    // Without the option, the drift accumulates (about 190 samples per two-second segment)
+    let drifts_without_limit = audio_drifts(&ts, None);
+    assert_eq!(drifts_without_limit.len(), 10);
+    assert!(*drifts_without_limit.last().unwrap() > 1800);
+
+    // With the option, the last sample of a segment is adjusted once the drift exceeds 10 milliseconds (480 samples)
+    let drifts_with_limit = audio_drifts(&ts, Some(Duration::from_millis(10)));
+    assert_eq!(drifts_with_limit[..2], drifts_without_limit[..2]);
+    assert!(
+        drifts_with_limit.iter().all(|d| d.abs() <= 480),
+        "{:?}",
+        drifts_with_limit
+    );
+}
+
+#[test]
+fn negative_audio_drift_is_adjusted_in_the_first_segment() {
+    // The audio is sampled 0.2% faster than declared, so the drift is already negative at the end of the first segment
+    let ts = skewed_audio_stream(191_616);
+    let drifts_without_limit = audio_drifts(&ts, None);
+    assert!(drifts_without_limit[0] < -100);
+    assert!(*drifts_without_limit.last().unwrap() < -1800);
+
+    let drifts_with_limit = audio_drifts(&ts, Some(Duration::from_millis(1)));
+    assert!(drifts_with_limit[0] > drifts_without_limit[0]);
+    assert!(
+        drifts_with_limit.iter().all(|d| d.abs() <= 48),
+        "{:?}",
+        drifts_with_limit
+    );
+
+    // The rounding of the expected end time may yield a drift of -1 on an exact clock
+    let drifts = audio_drifts(&common::av_stream(), Some(Duration::from_secs(0)));
+    assert!(drifts.iter().all(|d| d.abs() <= 1), "{:?}", drifts);
+}
+
+fn pes_timestamps(stream_id: u8, pts: Option<u64>, dts: Option<u64>) -> PesTimestamps {
+    PesTimestamps {
+        stream_id: StreamId::new(stream_id),
+        pts,
+        dts,
+        pcr: Some(1000),
+        received_at: None,
+    }
+}
+
+#[test]
+fn pts_timestamp_policy_uses_pes_timestamps() {
+    let mut policy = PtsTimestampPolicy;
+    assert!(!policy.needs_wallclock());
+    let video = pes_timestamps(0xE0, Some(9000), Some(6000));
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (6000, 9000));
+    let video = pes_timestamps(0xE0, Some(9000), None);
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (9000, 9000));
+    let video = pes_timestamps(0xE0, None, None);
+    assert!(policy.video_timestamps(&video).is_err());
+
+    let audio = pes_timestamps(0xC0, Some(9000), None);
+    assert_eq!(policy.audio_timestamp(&audio).unwrap(), Some(9000));
+    let audio = pes_timestamps(0xC0, None, None);
+    assert_eq!(policy.audio_timestamp(&audio).unwrap(), None);
+}
+
+#[test]
+fn dts_timestamp_policy_uses_dts_as_pts() {
+    let mut policy = DtsTimestampPolicy;
+    assert!(!policy.needs_wallclock());
+    let video = pes_timestamps(0xE0, Some(9000), Some(6000));
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (6000, 6000));
+    let video = pes_timestamps(0xE0, Some(9000), None);
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (9000, 9000));
+    let video = pes_timestamps(0xE0, None, None);
+    assert!(policy.video_timestamps(&video).is_err());
+
+    let audio = pes_timestamps(0xC0, Some(9000), None);
+    assert_eq!(policy.audio_timestamp(&audio).unwrap(), Some(9000));
+}
+
+#[test]
+fn pcr_timestamp_policy_uses_pcr() {
+    let mut policy = PcrTimestampPolicy;
+    assert!(!policy.needs_wallclock());
+    // The composition offset is kept
+    let video = pes_timestamps(0xE0, Some(9000), Some(6000));
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (1000, 4000));
+    let video = pes_timestamps(0xE0, Some(9000), None);
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (1000, 1000));
+
+    let audio = pes_timestamps(0xC0, Some(9000), None);
+    assert_eq!(policy.audio_timestamp(&audio).unwrap(), Some(1000));
+
+    let no_pcr = PesTimestamps {
+        pcr: None,
+        ..video.clone()
+    };
+    assert!(policy.video_timestamps(&no_pcr).is_err());
+    assert!(policy.audio_timestamp(&no_pcr).is_err());
+}
+
+#[test]
+fn wallclock_timestamp_policy_uses_elapsed_time() {
+    let mut policy = WallclockTimestampPolicy::new();
+    assert!(policy.needs_wallclock());
+    let start = Instant::now();
+    let at = |millis| Some(start + Duration::from_millis(millis));
+
+    // The first PES packet is at time zero
+    let video = PesTimestamps {
+        received_at: at(500),
+        ..pes_timestamps(0xE0, Some(9000), Some(6000))
+    };
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (0, 3000));
+    let audio = PesTimestamps {
+        received_at: at(600),
+        ..pes_timestamps(0xC0, Some(1), None)
+    };
+    assert_eq!(policy.audio_timestamp(&audio).unwrap(), Some(9000));
+    let video = PesTimestamps {
+        received_at: at(1500),
+        ..pes_timestamps(0xE0, Some(9000), None)
+    };
+    assert_eq!(policy.video_timestamps(&video).unwrap(), (90_000, 90_000));
+
+    // Packets read before the first one (which cannot happen) are at time zero
+    let audio = PesTimestamps {
+        received_at: at(0),
+        ..pes_timestamps(0xC0, Some(1), None)
+    };
+    assert_eq!(policy.audio_timestamp(&audio).unwrap(), Some(0));
+
+    let no_clock = pes_timestamps(0xC0, Some(1), None);
+    assert!(policy.audio_timestamp(&no_clock).is_err());
+}
+
+#[test]
+fn stream_converter_applies_timestamp_policy() {
+    let ts = common::av_stream();
+    let mut converter =
+        StreamConverter::new(TsPacketReader::new(&ts[..]), ConvertOptions::default());
+    converter
+        .set_timestamp_policy(Box::new(DtsTimestampPolicy))
+        .unwrap();
+    let mut segments = Vec::new();
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        segments.push(segment);
+    }
+    assert_eq!(video_sample_counts(&segments), [60, 60, 30]);
+    assert_continuous_decode_times(&segments, VIDEO_TRACK_ID);
+}
+
+const SUBTITLE_PID: u16 = 0x102;
+const TEXT_TRACK_ID: u32 = 3;
+
+// Reads cues whose payload is the duration (32-bit) followed by the text.
+#[derive(Debug)]
+struct StubSubtitleDecoder;
+impl SubtitleDecoder for StubSubtitleDecoder {
+    fn decode(&mut self, packet: &DataPacket) -> mse_fmp4::Result<Vec<SubtitleCue>> {
+        let start = packet.pts.unwrap();
+        let duration = u32::from_be_bytes([
+            packet.data[0],
+            packet.data[1],
+            packet.data[2],
+            packet.data[3],
+        ]);
+        Ok(vec![SubtitleCue {
+            start,
+            end: start + u64::from(duration),
+            id: None,
+            settings: None,
+            text: String::from_utf8(packet.data[4..].to_vec()).unwrap(),
+        }])
+    }
+}
+
+// Returns the texts of the cues in a WebVTT sample (an empty list for a `vtte` box).
+fn cue_texts(data: &[u8]) -> Vec<String> {
+    let mut texts = Vec::new();
+    for b in Boxes::new(data) {
+        let b = b.unwrap();
+        if b.box_type == *b"vttc" {
+            let payl = b
+                .children()
+                .map(|c| c.unwrap())
+                .find(|c| c.box_type == *b"payl");
+            texts.push(String::from_utf8(payl.unwrap().payload.to_vec()).unwrap());
+        } else {
+            assert_eq!(b.box_type, *b"vtte");
+        }
+    }
+    texts
+}
+
+#[test]
+fn subtitle_cues_are_split_into_webvtt_samples() {
+    // `(frame, duration, text)`: "a" overlaps "b" and spans the first two segments
+    let cues: &[(u64, u32, &str)] = &[(20, 180_000, "a"), (40, 30_000, "b"), (90, 30_000, "c")];
+
+    let mut ts = TsBuilder::new();
+    ts.pat().pmt(&[
+        (common::VIDEO_PID, common::STREAM_TYPE_H264),
+        (common::AUDIO_PID, common::STREAM_TYPE_ADTS_AAC),
+        (SUBTITLE_PID, 0x06),
+    ]);
+    let mut audio_pts = START_PTS;
+    for i in 0..150 {
+        let pts = START_PTS + i * FRAME_DURATION;
+        while audio_pts <= pts {
+            ts.audio(audio_pts, &adts_frame(64));
+            audio_pts += AAC_FRAME_DURATION;
+        }
+        for &(_, duration, text) in cues.iter().filter(|c| c.0 == i) {
+            let mut data = duration.to_be_bytes().to_vec();
+            data.extend_from_slice(text.as_bytes());
+            ts.pes(SUBTITLE_PID, 0xBD, pts, None, &data, None);
+        }
+        let is_idr = i % 30 == 0;
+        ts.video(pts, Some(pts), &access_unit(is_idr, is_idr, 1000));
+    }
+
+    let mut converter =
+        StreamConverter::new(TsPacketReader::new(ts.bytes()), ConvertOptions::default());
+    converter
+        .set_subtitle_decoder(Box::new(StubSubtitleDecoder))
+        .unwrap();
+    let init = converter.initialization_segment().unwrap();
+    let traks = &init.moov_box.trak_boxes;
+    assert_eq!(traks.len(), 3);
+    assert_eq!(
+        traks[2].mdia_box.mdhd_box.duration,
+        traks[0].mdia_box.mdhd_box.duration
+    );
+    let mut segments = Vec::new();
+    while let Some(segment) = converter.next_media_segment().unwrap() {
+        segments.push(segment);
+    }
+    assert_eq!(video_sample_counts(&segments), [60, 60, 30]);
+
+    // Each sample spans a period in which the set of the active cues does not change,
+    // and the periods without cues are filled with `vtte` samples
+    let expected: [&[(u32, &[&str])]; 2] = [
+        &[
+            (60_000, &[]),
+            (60_000, &["a"]),
+            (30_000, &["a", "b"]),
+            (30_000, &["a"]),
+        ],
+        &[
+            (60_000, &["a"]),
+            (30_000, &[]),
+            (30_000, &["c"]),
+            (60_000, &[]),
+        ],
+    ];
+    let mut next_decode_time = 0;
+    for (segment, expected) in segments.iter().zip(&expected) {
+        let samples = track_samples(segment, TEXT_TRACK_ID);
+        assert_eq!(samples[0].decode_time, next_decode_time);
+        let actual = samples
+            .iter()
+            .map(|s| (s.duration, cue_texts(s.data)))
+            .collect::<Vec<_>>();
+        let expected = expected
+            .iter()
+            .map(|&(d, texts)| (d, texts.iter().map(|t| t.to_string()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+        next_decode_time += actual.iter().map(|s| u64::from(s.0)).sum::<u64>();
+    }
+    assert_eq!(next_decode_time, 360_000);
+}
+
+#[test]
+fn buffer_pool_is_reused_across_segments() {
+    let ts = common::av_stream();
+    let expected = convert(&ts, ConvertOptions::default());
+
+    let pool = BufferPool::new(3);
+    let mut converter =
+        StreamConverter::new(TsPacketReader::new(&ts[..]), ConvertOptions::default());
+    converter.set_buffer_pool(pool.clone());
+
+    // The sample data of the video and audio tracks returns to the pool once the segment is serialized
+    let first = converter.next_media_segment_bytes().unwrap().unwrap();
+    assert_eq!(*first, expected[0].to_bytes().unwrap());
+    assert_eq!(pool.len(), 2);
+    drop(first);
+    assert_eq!(pool.len(), 3);
+
+    // The next segment takes all the idle buffers (two for the sample data and one for the serialized bytes)
+    let second = converter.next_media_segment_bytes().unwrap().unwrap();
+    assert_eq!(*second, expected[1].to_bytes().unwrap());
+    assert_eq!(pool.len(), 2);
+
+    // The buffers released while the pool is full are freed
+    pool.put(vec![0; 16]);
+    assert_eq!(pool.len(), 3);
+    drop(second);
+    pool.put(vec![0; 16]);
+    assert_eq!(pool.len(), 3);
+
+    // The segments returned by `next_media_segment` are owned by the caller
+    let third = converter.next_media_segment().unwrap().unwrap();
+    assert_eq!(third.to_bytes().unwrap(), expected[2].to_bytes().unwrap());
+    assert_eq!(pool.len(), 1);
+    for mdat in third.mdat_boxes {
+        pool.put(mdat.data);
+    }
+    assert_eq!(pool.len(), 3);
+    assert!(converter.next_media_segment().unwrap().is_none());
+
+    // Taken and detached buffers are not returned automatically, and empty buffers are not kept
+    let _ = pool.take(0);
+    pool.put(Vec::new());
+    assert_eq!(pool.len(), 2);
+    let detached = pool.get(16).into_inner();
+    assert!(detached.capacity() >= 16);
+    assert_eq!(pool.len(), 1);
+}